@@ -0,0 +1,135 @@
+//! Timers of paid orders awaiting acknowledgment by their stores. Timer starts once
+//! the order is paid and stops once its store moves it on, e.g. to `InProcessing`,
+//! or the order is cancelled. Stores sitting on paid orders beyond SLA are nudged by
+//! `jobs::acknowledgment`. Timers are kept in memory, optionally mirrored into json file.
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use failure::Error as FailureError;
+
+use stq_types::OrderId;
+
+use json_file::JsonFile;
+use models::PendingAcknowledgment;
+
+pub trait AcknowledgmentTimers {
+    /// Starts timer of paid order, time of an earlier start is kept
+    fn start(&self, pending: PendingAcknowledgment) -> Result<(), FailureError>;
+    /// Stops timer of order no longer awaiting acknowledgment
+    fn stop(&self, order_id: OrderId) -> Result<(), FailureError>;
+    /// Records that store was nudged about order
+    fn mark_nudged(&self, order_id: OrderId) -> Result<(), FailureError>;
+    /// Records that moderators were told about order
+    fn mark_escalated(&self, order_id: OrderId) -> Result<(), FailureError>;
+    /// Orders awaiting acknowledgment, paid the earliest first
+    fn pending(&self) -> Result<Vec<PendingAcknowledgment>, FailureError>;
+}
+
+/// Keeps timers in memory, optionally mirroring them into json file
+pub struct AcknowledgmentTimersImpl {
+    pending: Mutex<Vec<PendingAcknowledgment>>,
+    file: Option<JsonFile>,
+}
+
+impl AcknowledgmentTimersImpl {
+    pub fn new(path: Option<PathBuf>) -> Result<Self, FailureError> {
+        let file = path.map(|path| JsonFile::new(path, "acknowledgment timers"));
+        let pending = match file {
+            Some(ref file) => file.read::<Vec<PendingAcknowledgment>>()?,
+            None => None,
+        };
+
+        Ok(Self {
+            pending: Mutex::new(pending.unwrap_or_default()),
+            file,
+        })
+    }
+
+    fn flush(&self, pending: &[PendingAcknowledgment]) -> Result<(), FailureError> {
+        if let Some(ref file) = self.file {
+            file.write(&pending)?;
+        }
+        Ok(())
+    }
+
+    fn update<F: FnOnce(&mut PendingAcknowledgment)>(&self, order_id: OrderId, f: F) -> Result<(), FailureError> {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.iter_mut().find(|pending| pending.order_id == order_id) {
+            Some(item) => f(item),
+            None => return Ok(()),
+        }
+        self.flush(&pending)
+    }
+}
+
+impl AcknowledgmentTimers for AcknowledgmentTimersImpl {
+    fn start(&self, item: PendingAcknowledgment) -> Result<(), FailureError> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.iter().any(|pending| pending.order_id == item.order_id) {
+            return Ok(());
+        }
+        pending.push(item);
+        self.flush(&pending)
+    }
+
+    fn stop(&self, order_id: OrderId) -> Result<(), FailureError> {
+        let mut pending = self.pending.lock().unwrap();
+        let len = pending.len();
+        pending.retain(|pending| pending.order_id != order_id);
+        if pending.len() == len {
+            return Ok(());
+        }
+        self.flush(&pending)
+    }
+
+    fn mark_nudged(&self, order_id: OrderId) -> Result<(), FailureError> {
+        self.update(order_id, |pending| pending.nudged_at = Some(SystemTime::now()))
+    }
+
+    fn mark_escalated(&self, order_id: OrderId) -> Result<(), FailureError> {
+        self.update(order_id, |pending| pending.escalated_at = Some(SystemTime::now()))
+    }
+
+    fn pending(&self) -> Result<Vec<PendingAcknowledgment>, FailureError> {
+        let mut items = self.pending.lock().unwrap().clone();
+        items.sort_by_key(|pending| pending.paid_at);
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use stq_types::{OrderId, OrderSlug, StoreId};
+
+    use super::{AcknowledgmentTimers, AcknowledgmentTimersImpl};
+    use models::PendingAcknowledgment;
+
+    #[test]
+    fn keeps_time_of_payment_until_stopped() {
+        let timers = AcknowledgmentTimersImpl::new(None).unwrap();
+        let order_id = OrderId::new();
+        timers
+            .start(PendingAcknowledgment::new(order_id, OrderSlug(1), StoreId(1)))
+            .unwrap();
+        let paid_at = timers.pending().unwrap()[0].paid_at;
+        timers
+            .start(PendingAcknowledgment::new(order_id, OrderSlug(1), StoreId(1)))
+            .unwrap();
+        timers.mark_nudged(order_id).unwrap();
+
+        let pending = timers.pending().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].paid_at, paid_at);
+        assert!(pending[0].nudged_at.is_some());
+        assert!(pending[0].escalated_at.is_none());
+        let sla = Duration::from_secs(3600);
+        assert!(!pending[0].is_overdue(sla, SystemTime::now()));
+        assert!(pending[0].is_overdue(sla, SystemTime::now() + Duration::from_secs(3601)));
+
+        timers.stop(order_id).unwrap();
+        assert!(timers.pending().unwrap().is_empty());
+    }
+}
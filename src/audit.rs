@@ -0,0 +1,211 @@
+//! Audit of calls to microservices made with superadmin rights. Coordinator
+//! acts as superadmin in many saga steps, so every such call is recorded
+//! along with the saga and request it was made for. If `saga.record_calls` is set,
+//! the scope also records request of the saga and every call of the saga along with
+//! its response in saga store, so that the saga can be replayed.
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use failure::Error as FailureError;
+use failure::Fail;
+use hyper::Method;
+use serde_json;
+
+use stq_types::SagaId;
+
+use models::{AuditEntry, AuditQuery, LegalHold, RecordedCall, RecordedRequest};
+use saga::SagaStore;
+
+pub trait AuditLog {
+    fn record(&self, entry: AuditEntry) -> Result<(), FailureError>;
+    /// Entries matching the query, the latest first
+    fn list(&self, query: AuditQuery) -> Result<Vec<AuditEntry>, FailureError>;
+}
+
+/// Keeps the latest entries in memory, optionally appending every entry to json lines file
+pub struct AuditLogImpl {
+    entries: Mutex<VecDeque<AuditEntry>>,
+    capacity: usize,
+    file: Option<Mutex<File>>,
+}
+
+impl AuditLogImpl {
+    pub fn new(path: Option<PathBuf>, capacity: usize) -> Result<Self, FailureError> {
+        let file = match path {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| e.context(format!("Could not open audit log {}", path.display())))?,
+            )),
+            None => None,
+        };
+
+        Ok(Self {
+            entries: Mutex::new(VecDeque::new()),
+            capacity,
+            file,
+        })
+    }
+}
+
+impl AuditLog for AuditLogImpl {
+    fn record(&self, entry: AuditEntry) -> Result<(), FailureError> {
+        if let Some(ref file) = self.file {
+            let mut line = serde_json::to_vec(&entry)?;
+            line.push(b'\n');
+            file.lock()
+                .unwrap()
+                .write_all(&line)
+                .map_err(|e| e.context("Could not write audit log"))?;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_back(entry);
+        while entries.len() > self.capacity {
+            entries.pop_front();
+        }
+        Ok(())
+    }
+
+    fn list(&self, query: AuditQuery) -> Result<Vec<AuditEntry>, FailureError> {
+        let entries = self.entries.lock().unwrap();
+        let matching = entries
+            .iter()
+            .rev()
+            .filter(|entry| query.saga_id.is_none() || entry.saga_id == query.saga_id)
+            .cloned();
+        Ok(match query.limit {
+            Some(limit) => matching.take(limit).collect(),
+            None => matching.collect(),
+        })
+    }
+}
+
+/// Audit of calls made by microservice clients of a single request or job
+#[derive(Clone)]
+pub struct AuditScope {
+    log: Arc<AuditLog>,
+    reason: String,
+    saga_id: Rc<Cell<Option<SagaId>>>,
+    recording: Option<Rc<Recording>>,
+}
+
+struct Recording {
+    store: Arc<SagaStore>,
+    request: RecordedRequest,
+    /// Whether request is saved along with the saga
+    saved: Cell<bool>,
+}
+
+impl AuditScope {
+    pub fn new(log: Arc<AuditLog>, reason: String) -> Self {
+        Self {
+            log,
+            reason,
+            saga_id: Rc::new(Cell::new(None)),
+            recording: None,
+        }
+    }
+
+    /// Records calls of the saga the scope is bound to in saga store
+    pub fn with_recording(self, store: Arc<SagaStore>, request: RecordedRequest) -> Self {
+        Self {
+            recording: Some(Rc::new(Recording {
+                store,
+                request,
+                saved: Cell::new(false),
+            })),
+            ..self
+        }
+    }
+
+    pub fn records_calls(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Attributes further calls of the scope to the saga
+    pub fn bind_saga(&self, saga_id: SagaId) {
+        self.saga_id.set(Some(saga_id));
+    }
+
+    pub fn record(&self, method: &Method, url: &str) {
+        let entry = AuditEntry {
+            method: method.to_string(),
+            url: url.to_string(),
+            saga_id: self.saga_id.get(),
+            reason: self.reason.clone(),
+            recorded_at: SystemTime::now(),
+            legal_hold: None,
+        };
+        if let Err(e) = self.log.record(entry) {
+            error!("Could not audit superadmin call {} {}: {}", method, url, e);
+        }
+    }
+
+    /// Records legal hold put on store or released by the saga the scope is bound to, failure fails the saga step
+    pub fn record_legal_hold(&self, legal_hold: LegalHold) -> Result<(), FailureError> {
+        let saga_id = self
+            .saga_id
+            .get()
+            .ok_or_else(|| format_err!("Legal hold of store {} can be recorded by saga only", legal_hold.store_id))?;
+        self.log.record(AuditEntry::legal_hold(saga_id, self.reason.clone(), legal_hold))
+    }
+
+    /// Saves call to microservice in saga store, calls made before saga is bound are not recorded
+    pub fn record_call(&self, call: RecordedCall) {
+        let (recording, saga_id) = match (self.recording.as_ref(), self.saga_id.get()) {
+            (Some(recording), Some(saga_id)) => (recording, saga_id),
+            _ => return,
+        };
+        if !recording.saved.get() {
+            match recording.store.record_request(saga_id, recording.request.clone()) {
+                Ok(()) => recording.saved.set(true),
+                Err(e) => debug!("Could not record request of saga {}: {}", saga_id, e),
+            }
+        }
+        if let Err(e) = recording.store.record_call(saga_id, call) {
+            debug!("Could not record call of saga {}: {}", saga_id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use hyper::Method;
+
+    use stq_types::SagaId;
+
+    use super::{AuditLog, AuditLogImpl, AuditScope};
+    use models::AuditQuery;
+
+    #[test]
+    fn keeps_latest_entries_of_saga() {
+        let log = Arc::new(AuditLogImpl::new(None, 2).unwrap());
+        let scope = AuditScope::new(log.clone(), "POST /create_store".to_string());
+        scope.record(&Method::Get, "http://stores/stores/1");
+        let saga_id = SagaId::new();
+        scope.bind_saga(saga_id);
+        scope.record(&Method::Post, "http://billing/merchants/store");
+        scope.record(&Method::Post, "http://orders/roles");
+
+        assert_eq!(log.list(AuditQuery::default()).unwrap().len(), 2);
+        let entries = log
+            .list(AuditQuery {
+                saga_id: Some(saga_id),
+                limit: Some(1),
+            })
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].url, "http://orders/roles");
+    }
+}
@@ -0,0 +1,112 @@
+//! Order states recently received from billing, so that a billing call can be replayed
+//! with `POST /admin/orders/update_state/replay` by its `X-Request-Id` after an incident,
+//! e.g. when orders microservice was down. Events are kept in memory of the replica
+//! that received them, the oldest events are dropped once capacity is reached.
+//! A call reusing `X-Request-Id` of a recent event is rejected, so that order states
+//! of unrelated calls are never merged into one event.
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use failure::Error as FailureError;
+
+use errors::Error;
+use models::{BillingEvent, BillingOrderInfo};
+
+pub trait BillingEventLog {
+    /// Starts event of the call, fails with conflict if a recent event has the same id
+    fn open(&self, event_id: &str) -> Result<(), FailureError>;
+    /// Appends order states to the event opened by the call, streamed calls are recorded batch by batch
+    fn record(&self, event_id: &str, orders: Vec<BillingOrderInfo>);
+    fn get(&self, event_id: &str) -> Option<BillingEvent>;
+}
+
+pub struct BillingEventLogImpl {
+    events: Mutex<VecDeque<BillingEvent>>,
+    capacity: usize,
+}
+
+impl BillingEventLogImpl {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: Mutex::new(VecDeque::new()),
+            capacity,
+        }
+    }
+}
+
+impl BillingEventLogImpl {
+    fn push(&self, events: &mut VecDeque<BillingEvent>, event_id: &str, orders: Vec<BillingOrderInfo>) {
+        events.push_back(BillingEvent {
+            id: event_id.to_string(),
+            received_at: SystemTime::now(),
+            orders,
+        });
+        while events.len() > self.capacity {
+            events.pop_front();
+        }
+    }
+}
+
+impl BillingEventLog for BillingEventLogImpl {
+    fn open(&self, event_id: &str) -> Result<(), FailureError> {
+        let mut events = self.events.lock().unwrap();
+        if events.iter().any(|event| event.id == event_id) {
+            return Err(format_err!("Billing event {} has already been received", event_id)
+                .context(Error::Conflict)
+                .into());
+        }
+        self.push(&mut events, event_id, vec![]);
+        Ok(())
+    }
+
+    fn record(&self, event_id: &str, orders: Vec<BillingOrderInfo>) {
+        let mut events = self.events.lock().unwrap();
+        match events.iter_mut().rev().find(|event| event.id == event_id) {
+            Some(event) => event.orders.extend(orders),
+            // event of a long streamed call may have been dropped in the meantime
+            None => self.push(&mut events, event_id, orders),
+        }
+    }
+
+    fn get(&self, event_id: &str) -> Option<BillingEvent> {
+        self.events.lock().unwrap().iter().rev().find(|event| event.id == event_id).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stq_static_resources::OrderState;
+    use stq_types::{OrderId, StoreId, UserId};
+
+    use super::{BillingEventLog, BillingEventLogImpl};
+    use errors::Error;
+    use models::BillingOrderInfo;
+
+    fn order_info() -> BillingOrderInfo {
+        BillingOrderInfo {
+            order_id: OrderId::new(),
+            customer_id: UserId(1),
+            store_id: StoreId(1),
+            status: OrderState::Paid,
+        }
+    }
+
+    #[test]
+    fn rejects_reused_event_id() {
+        let events = BillingEventLogImpl::new(10);
+        events.open("request-1").unwrap();
+        events.record("request-1", vec![order_info()]);
+        events.record("request-1", vec![order_info()]);
+
+        let e = events.open("request-1").unwrap_err();
+        assert!(e.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(&Error::Conflict) => true,
+            _ => false,
+        }));
+        assert_eq!(events.get("request-1").unwrap().orders.len(), 2);
+
+        events.open("request-2").unwrap();
+        assert!(events.get("request-2").unwrap().orders.is_empty());
+    }
+}
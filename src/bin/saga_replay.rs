@@ -0,0 +1,37 @@
+//! Replays saga recorded in saga log against mock microservices, e.g.
+//! `cargo run --features replay --bin saga_replay -- saga_log.json <saga_id> [<authorization>]`
+extern crate saga_coordinator_lib as lib;
+extern crate serde_json;
+extern crate stq_types;
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use stq_types::SagaId;
+
+fn main() {
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() != 3 && args.len() != 4 {
+        eprintln!("Usage: saga_replay <saga log path> <saga id> [<authorization>]");
+        process::exit(2);
+    }
+    let saga_id = args[2].parse::<SagaId>().unwrap_or_else(|_| {
+        eprintln!("Invalid saga id {}", args[2]);
+        process::exit(2);
+    });
+
+    let config = lib::config::Config::new().expect("Failed to load service configuration. Please check your 'config' folder");
+    lib::secrets::resolve(&config).expect("Failed to resolve secrets referenced in configuration");
+
+    match lib::replay::replay(config, PathBuf::from(&args[1]), saga_id, args.get(3).cloned()) {
+        Ok(report) => println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("Could not serialize replay report")
+        ),
+        Err(e) => {
+            eprintln!("Replay failed: {}", e);
+            process::exit(1);
+        }
+    }
+}
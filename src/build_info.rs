@@ -0,0 +1,14 @@
+//! Build of the service, compiled in by the build script
+use chrono::{DateTime, TimeZone, Utc};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Commit the service is built from, `None` if it was not known at build time
+pub const GIT_COMMIT: Option<&str> = option_env!("GIT_COMMIT");
+/// Unix time of the build
+const BUILD_TIMESTAMP: Option<&str> = option_env!("BUILD_TIMESTAMP");
+
+pub fn built_at() -> Option<DateTime<Utc>> {
+    BUILD_TIMESTAMP
+        .and_then(|timestamp| timestamp.parse::<i64>().ok())
+        .map(|timestamp| Utc.timestamp(timestamp, 0))
+}
@@ -1,11 +1,18 @@
+use std::collections::HashMap;
 use std::env;
+use std::time::Duration;
 
 use config_crate::{Config as RawConfig, ConfigError, Environment, File};
+use failure::Error as FailureError;
 
 use stq_http;
 use stq_logging::GrayLogConfig;
 use stq_routes::service::Service as StqService;
+use stq_static_resources::{CommitterRole, Device, OrderState, Project};
+use stq_types::{CategoryId, StoreId};
 
+use models::{ReceiptFormat, SagaPriority};
+use secrets::Secret;
 use sentry_integration::SentryConfig;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -24,6 +31,85 @@ pub struct Config {
     pub client: Client,
     pub sentry: Option<SentryConfig>,
     pub service: Service,
+    pub saga: Saga,
+    pub limits: Limits,
+    #[serde(default)]
+    pub cors: Cors,
+    #[serde(default)]
+    pub metrics: Metrics,
+    #[serde(default)]
+    pub moderation: Moderation,
+    #[serde(default)]
+    pub features: Features,
+    /// Checkouts are not screened for fraud if not set
+    #[serde(default)]
+    pub fraud_screening: Option<FraudScreening>,
+    /// Receipts of invoices are not requested if not set
+    #[serde(default)]
+    pub receipts: Option<Receipts>,
+    /// Stores are not notified about low stocks if not set
+    #[serde(default)]
+    pub low_stock: Option<LowStock>,
+    /// Stores are not nudged about paid orders they have not acknowledged if not set
+    #[serde(default)]
+    pub order_acknowledgment: Option<OrderAcknowledgment>,
+    #[serde(default)]
+    pub vacations: Vacations,
+    #[serde(default)]
+    pub order_state_permissions: OrderStatePermissions,
+    #[serde(default)]
+    pub returns: Returns,
+    /// Tracking updates of couriers are rejected if not set
+    #[serde(default)]
+    pub delivery_tracking: Option<DeliveryTracking>,
+    /// Uploads of deactivated stores and products are left in storage if not set
+    #[serde(default)]
+    pub files: Option<Files>,
+    #[serde(default)]
+    pub catalog_import: CatalogImport,
+    /// Stores are created without looking for duplicates if not set
+    #[serde(default)]
+    pub duplicate_stores: Option<DuplicateStores>,
+    #[serde(default)]
+    pub audit: Audit,
+    #[serde(default)]
+    pub billing_events: BillingEvents,
+    #[serde(default)]
+    pub secrets: Secrets,
+    /// Every replica runs every background job if not set
+    #[serde(default)]
+    pub leader_election: Option<LeaderElection>,
+    #[serde(default)]
+    pub executor: Executor,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreaker,
+    #[serde(default)]
+    pub dependency_monitor: DependencyMonitor,
+    /// Saga lifecycle events are not sent to Graylog if not set
+    #[serde(default)]
+    pub saga_events: Option<SagaEvents>,
+    /// Statistics of finished sagas are not exported if not set
+    #[serde(default)]
+    pub saga_stats: Option<SagaStatsExport>,
+    #[serde(default)]
+    pub logging: Logging,
+    #[serde(default)]
+    pub scrubbing: Scrubbing,
+    /// Calls to microservices are made as is if not set
+    #[serde(default)]
+    pub cassette: Option<Cassette>,
+    /// Faults are not injected if not set
+    #[serde(default)]
+    pub chaos: Option<Chaos>,
+    /// Calls to microservices are not mirrored if not set
+    #[serde(default)]
+    pub shadow: Option<Shadow>,
+    /// Canaries by saga type, e.g. `create_order`
+    #[serde(default)]
+    pub canary: HashMap<String, Canary>,
+    /// Responses are sent in full if not set
+    #[serde(default)]
+    pub redaction: Option<Redaction>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -31,6 +117,13 @@ pub struct Client {
     pub http_client_buffer_size: usize,
     pub http_client_retries: usize,
     pub http_timeout_ms: u64,
+    /// Currency sent to orders and billing when caller did not set `Currency` header
+    pub default_currency: String,
+    /// Fiat currency sent to orders and billing when caller did not set `FiatCurrency` header
+    pub default_fiat_currency: String,
+    /// `User-Agent` and `X-Calling-Service` sent to microservices, `saga-coordinator/<version> (build <commit>)` if not set
+    #[serde(default)]
+    pub user_agent: Option<String>,
 }
 
 /// Common server settings
@@ -43,6 +136,9 @@ pub struct Server {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Microservice {
     pub url: String,
+    /// Url of the other deployment of the microservice, e.g. the green one, traffic is switched to it with `PUT /admin/endpoints`
+    #[serde(default)]
+    pub secondary_url: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -54,6 +150,17 @@ pub struct Cluster {
 pub struct NotificationUrls {
     pub verify_email: ProjectUrls,
     pub reset_password: ProjectUrls,
+    /// Page for appealing store deactivation, `{store_id}` is replaced with id of the store
+    #[serde(default)]
+    pub store_appeal: Option<String>,
+}
+
+impl NotificationUrls {
+    pub fn store_appeal(&self, store_id: StoreId) -> Option<String> {
+        self.store_appeal
+            .as_ref()
+            .map(|url| url.replace("{store_id}", &store_id.to_string()))
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -63,15 +170,1058 @@ pub struct DevicesUrls {
     pub android: String,
 }
 
+impl DevicesUrls {
+    pub fn for_device(&self, device: Option<Device>) -> String {
+        match device {
+            Some(Device::IOS) => self.ios.clone(),
+            Some(Device::Android) => self.android.clone(),
+            Some(Device::WEB) | None => self.web.clone(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ProjectUrls {
     pub marketplace: DevicesUrls,
     pub wallet: DevicesUrls,
+    /// Overrides of urls by locale, e.g. `ru` or `ru-RU`
+    #[serde(default)]
+    pub locales: HashMap<String, LocaleUrls>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LocaleUrls {
+    pub marketplace: Option<DevicesUrls>,
+    pub wallet: Option<DevicesUrls>,
+}
+
+impl ProjectUrls {
+    /// Url for the device of the project. Url of the exact locale is preferred, then the one
+    /// of its primary language, then the default one.
+    pub fn device_url(&self, project: Project, device: Option<Device>, locale: Option<&str>) -> String {
+        let localized = locale
+            .into_iter()
+            .flat_map(|locale| vec![locale, locale.split('-').next().unwrap_or(locale)])
+            .filter_map(|locale| self.locales.get(&locale.to_lowercase()))
+            .filter_map(|urls| match project {
+                Project::MarketPlace => urls.marketplace.as_ref(),
+                Project::Wallet => urls.wallet.as_ref(),
+            })
+            .next();
+        let default = match project {
+            Project::MarketPlace => &self.marketplace,
+            Project::Wallet => &self.wallet,
+        };
+        localized.unwrap_or(default).for_device(device)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Service {
+    /// Margin subtracted from timeout of the caller for the coordinator to respond in time
     pub processing_timeout_ms: u64,
+    /// Reject request payloads with fields unknown to the coordinator instead of only logging them
+    pub strict_payloads: bool,
+    /// Margin is static `processing_timeout_ms` if not set
+    #[serde(default)]
+    pub adaptive_margin: Option<AdaptiveMargin>,
+}
+
+/// Margin following measured overhead of the coordinator, `processing_timeout_ms` is used until enough requests are measured
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveMargin {
+    /// Quantile of overhead the margin is based on
+    pub quantile: f64,
+    /// Multiplier of the quantile
+    pub headroom: f64,
+    pub min_ms: u64,
+    pub max_ms: u64,
+    /// Number of the latest requests overhead is measured over
+    pub window: usize,
+    pub min_samples: usize,
+}
+
+impl Default for AdaptiveMargin {
+    fn default() -> Self {
+        Self {
+            quantile: 0.99,
+            headroom: 1.5,
+            min_ms: 50,
+            max_ms: 5000,
+            window: 1000,
+            min_samples: 100,
+        }
+    }
+}
+
+/// Size limits of request bodies, in bytes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Limits {
+    /// Account endpoints: registration, email verification and password reset
+    pub account_body_bytes: usize,
+    /// Bulk endpoints: order state updates from billing, repricing and catalog imports
+    pub bulk_body_bytes: usize,
+    pub default_body_bytes: usize,
+    /// Serialized payloads of requests to other microservices
+    pub downstream_payload_bytes: usize,
+}
+
+/// CORS settings for browser based clients, CORS is disabled if no origins are allowed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Cors {
+    /// Origins allowed to call the service, `*` allows any origin
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// Response headers readable by browser based clients besides the basic ones
+    pub exposed_headers: Vec<String>,
+    /// How long preflight response can be cached by browser
+    pub max_age_s: u64,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![],
+            allowed_methods: ["GET", "POST", "PUT", "DELETE"].iter().map(|s| s.to_string()).collect(),
+            allowed_headers: [
+                "Authorization",
+                "Content-Type",
+                "Content-Encoding",
+                "Correlation-Token",
+                "Currency",
+                "FiatCurrency",
+                "Request-Timeout",
+                "Session-Locale",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            exposed_headers: ["X-Request-Id", "ETag"].iter().map(|s| s.to_string()).collect(),
+            max_age_s: 3600,
+        }
+    }
+}
+
+impl Cors {
+    pub fn is_allowed(&self, origin: &str) -> bool {
+        self.allowed_origins.iter().any(|allowed| allowed == "*" || allowed == origin)
+    }
+}
+
+/// Prometheus metrics settings
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metrics {
+    pub labels: MetricsLabels,
+    /// Pushes metrics to statsd agent, for deployments without prometheus
+    pub statsd: Option<StatsD>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatsD {
+    /// Address of statsd agent, e.g. `127.0.0.1:8125`
+    pub address: String,
+    /// Prepended to metric names
+    pub prefix: String,
+    pub interval_s: u64,
+    /// Sends labels as datadog tags instead of parts of metric name
+    pub datadog_tags: bool,
+}
+
+impl Default for StatsD {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:8125".to_string(),
+            prefix: String::new(),
+            interval_s: 10,
+            datadog_tags: false,
+        }
+    }
+}
+
+/// Label failures of saga steps are reported by
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsLabels {
+    /// Saga type and step
+    Step,
+    /// Saga type and downstream service the step calls, keeps number of series small
+    Service,
+}
+
+impl Default for MetricsLabels {
+    fn default() -> Self {
+        MetricsLabels::Step
+    }
+}
+
+/// Moderation SLA tracking settings
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Moderation {
+    /// Json file for moderation queue, the queue is kept only in memory if not set
+    pub queue_path: Option<String>,
+    /// Time moderators have to decide on store or base product
+    pub sla_s: u64,
+    /// Reminds moderators by email about items beyond SLA, at most once per `sla_s`
+    pub remind_moderators: bool,
+    pub check_interval_s: u64,
+    /// Rules approving base products sent to moderation without moderators
+    pub auto_approval: Vec<AutoApprovalRule>,
+}
+
+/// Base product sent to moderation is approved if it matches every criterion set in the rule.
+/// Rules without criteria never match.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoApprovalRule {
+    /// Logged along with approved base product
+    pub name: String,
+    /// Stores trusted by moderators
+    pub store_ids: Vec<StoreId>,
+    pub category_ids: Vec<CategoryId>,
+    /// Edits touching only these fields are minor, base products not edited since last moderation do not match
+    pub minor_edit_fields: Vec<String>,
+}
+
+impl Default for Moderation {
+    fn default() -> Self {
+        Self {
+            queue_path: None,
+            sla_s: 86400,
+            remind_moderators: false,
+            check_interval_s: 600,
+            auto_approval: vec![],
+        }
+    }
+}
+
+/// Optional saga steps, disabled steps are skipped
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Features {
+    /// Computes tax lines of orders in billing before invoice is created
+    pub tax_calculation: bool,
+    /// Validates and normalizes delivery address of checkout in delivery before cart is converted
+    pub address_normalization: bool,
+    /// Purchases shipping label in delivery when store marks order sent without track id
+    pub delivery_labels: bool,
+    /// Notifies customers when products in their carts are removed by moderation or deactivation
+    pub unavailable_cart_products_notifications: bool,
+}
+
+impl Features {
+    /// Names of enabled features
+    pub fn enabled(&self) -> Vec<String> {
+        let features = [
+            ("tax_calculation", self.tax_calculation),
+            ("address_normalization", self.address_normalization),
+            ("delivery_labels", self.delivery_labels),
+            (
+                "unavailable_cart_products_notifications",
+                self.unavailable_cart_products_notifications,
+            ),
+        ];
+        features
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+}
+
+/// External fraud scoring of checkouts before invoice is created
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FraudScreening {
+    /// Url of scoring service, checkouts are posted to `<url>/score`
+    pub url: String,
+    /// Checkouts scored at least that are flagged for review
+    pub flag_score: f64,
+    /// Checkouts scored at least that are blocked
+    pub block_score: f64,
+    /// Lets checkout proceed when scoring service fails
+    #[serde(default)]
+    pub fail_open: bool,
+    /// Json file for admin overrides, overrides are kept only in memory if not set
+    #[serde(default)]
+    pub overrides_path: Option<String>,
+}
+
+/// Order states committers may set with `POST /orders/<slug>/set_state`, by committer role.
+/// Role is derived from the caller: superadmin commits as system, manager of the order's store as seller
+/// and its buyer as customer, other callers may not change the order
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OrderStatePermissions {
+    pub customer: Vec<OrderState>,
+    pub seller: Vec<OrderState>,
+    /// Any state may be set if not set
+    pub system: Option<Vec<OrderState>>,
+}
+
+impl Default for OrderStatePermissions {
+    fn default() -> Self {
+        Self {
+            customer: vec![OrderState::Received, OrderState::Cancelled],
+            seller: vec![OrderState::InProcessing, OrderState::Sent],
+            system: None,
+        }
+    }
+}
+
+impl OrderStatePermissions {
+    pub fn allows(&self, committer_role: CommitterRole, state: OrderState) -> bool {
+        match committer_role {
+            CommitterRole::Customer => self.customer.contains(&state),
+            CommitterRole::Seller => self.seller.contains(&state),
+            CommitterRole::System => self.system.as_ref().map(|states| states.contains(&state)).unwrap_or(true),
+        }
+    }
+}
+
+/// Returns of delivered orders requested by customers
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Returns {
+    /// Time since delivery the customer has to request a return
+    pub window_s: u64,
+}
+
+impl Default for Returns {
+    fn default() -> Self {
+        Self { window_s: 14 * 24 * 3600 }
+    }
+}
+
+/// Webhooks of couriers reporting tracking updates of sent orders
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeliveryTracking {
+    /// Key of HMAC-SHA256 signature of body, sent hex encoded in `X-Tracking-Signature` header
+    pub secret: Secret,
+    /// Tracking statuses meaning the order is delivered, compared case insensitively
+    pub delivered_statuses: Vec<String>,
+    /// Number of the latest handled events remembered to skip redelivered ones
+    pub events_capacity: usize,
+}
+
+impl DeliveryTracking {
+    /// State the order is moved to on tracking status, other statuses are ignored
+    pub fn order_state(&self, status: &str) -> Option<OrderState> {
+        if self
+            .delivered_statuses
+            .iter()
+            .any(|delivered| delivered.eq_ignore_ascii_case(status))
+        {
+            Some(OrderState::Delivered)
+        } else {
+            None
+        }
+    }
+}
+
+/// Static files service keeping store logos and product images
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Files {
+    pub url: String,
+    /// Files are deleted that long after their cleanup is scheduled, activation cancels cleanup meanwhile
+    pub cleanup_delay_s: u64,
+}
+
+/// Receipts generated by billing for created invoices, linked in order created email
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Receipts {
+    pub format: ReceiptFormat,
+}
+
+/// Digest of products running out of stock emailed to stores
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LowStock {
+    /// Local times the digest is sent at
+    pub schedule: Schedule,
+    /// Products with less in stock are reported
+    pub threshold: u32,
+    /// Thresholds of stores overriding the default one
+    #[serde(default)]
+    pub store_thresholds: HashMap<String, u32>,
+    /// Stores that opted out of the digest
+    #[serde(default)]
+    pub opted_out_store_ids: Vec<StoreId>,
+}
+
+impl LowStock {
+    /// Threshold of the store, `None` if the store opted out of the digest
+    pub fn threshold(&self, store_id: StoreId) -> Option<u32> {
+        if self.opted_out_store_ids.contains(&store_id) {
+            return None;
+        }
+        Some(self.store_thresholds.get(&store_id.to_string()).cloned().unwrap_or(self.threshold))
+    }
+}
+
+/// Paid orders are expected to be taken into processing by their stores within SLA
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OrderAcknowledgment {
+    /// Json file for acknowledgment timers, the timers are kept only in memory if not set
+    pub timers_path: Option<String>,
+    /// Time store has to move paid order to `InProcessing`, the store is nudged by email once it is over
+    pub sla_s: u64,
+    /// Time since payment after which moderators are told about the order, they are not told if not set
+    #[serde(default)]
+    pub escalate_after_s: Option<u64>,
+    pub check_interval_s: u64,
+}
+
+/// Stores on vacation, their products are hidden from search until vacation ends
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Vacations {
+    /// Json file for stores on vacation, they are kept only in memory if not set
+    pub path: Option<String>,
+    /// Stores whose vacation is over are brought back this often
+    pub check_interval_s: u64,
+}
+
+impl Default for Vacations {
+    fn default() -> Self {
+        Self {
+            path: None,
+            check_interval_s: 600,
+        }
+    }
+}
+
+/// Catalog import runs in background, so it gets its own limits instead of request ones
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CatalogImport {
+    pub max_rows: usize,
+    /// Time given to import of the whole catalog, products imported so far are rolled back when it expires
+    pub deadline_ms: u64,
+}
+
+impl Default for CatalogImport {
+    fn default() -> Self {
+        Self {
+            max_rows: 1000,
+            deadline_ms: 600_000,
+        }
+    }
+}
+
+/// Audit of calls to microservices made with superadmin rights
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Audit {
+    /// Entries are appended to this file as json lines, they are kept only in memory if not set
+    pub path: Option<String>,
+    /// Number of the latest entries kept in memory for admin endpoint
+    pub capacity: usize,
+}
+
+impl Default for Audit {
+    fn default() -> Self {
+        Self {
+            path: None,
+            capacity: 10_000,
+        }
+    }
+}
+
+/// Order states received from billing, kept for replay
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BillingEvents {
+    /// Number of the latest billing calls kept in memory
+    pub capacity: usize,
+}
+
+impl Default for BillingEvents {
+    fn default() -> Self {
+        Self { capacity: 1000 }
+    }
+}
+
+/// Sources of secrets referenced in config as `vault:<key>` or `file:<path>`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Secrets {
+    pub vault: Option<Vault>,
+    /// Secrets are resolved only at startup if not set
+    pub refresh_interval_s: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Vault {
+    pub url: String,
+    /// Usually `file:<path>` of token mounted by orchestrator, can not reference Vault itself
+    pub token: Secret,
+    /// Path of the secret keys are looked up in, e.g. `secret/data/saga-coordinator`
+    pub path: String,
+}
+
+/// Execution of background sagas by priority classes
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Executor {
+    pub checkout: PriorityClass,
+    pub moderation: PriorityClass,
+    pub housekeeping: PriorityClass,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PriorityClass {
+    /// Share of free slots the class gets when several classes have sagas waiting
+    pub weight: u32,
+    /// Sagas of the class executed at once
+    pub concurrency: usize,
+}
+
+impl Executor {
+    pub fn class(&self, priority: SagaPriority) -> &PriorityClass {
+        match priority {
+            SagaPriority::Checkout => &self.checkout,
+            SagaPriority::Moderation => &self.moderation,
+            SagaPriority::Housekeeping => &self.housekeeping,
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self {
+            checkout: PriorityClass {
+                weight: 6,
+                concurrency: 16,
+            },
+            moderation: PriorityClass { weight: 3, concurrency: 4 },
+            housekeeping: PriorityClass { weight: 1, concurrency: 2 },
+        }
+    }
+}
+
+/// Circuit breakers of notifications and emarsys, steps calling them are skipped while breaker is open
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreaker {
+    /// Failed calls in a row opening the breaker
+    pub failure_threshold: u32,
+    /// Time calls are skipped for before they are let through again
+    pub open_ms: u64,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_ms: 30000,
+        }
+    }
+}
+
+/// Sampling of calls to microservices reported by `/admin/dependencies`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DependencyMonitor {
+    /// Error rate and latency are computed from calls made within the window
+    pub window_s: u64,
+    /// Samples kept per microservice
+    pub max_samples: usize,
+}
+
+impl Default for DependencyMonitor {
+    fn default() -> Self {
+        Self {
+            window_s: 300,
+            max_samples: 1000,
+        }
+    }
+}
+
+/// Log levels, global level can be changed at runtime with `PUT /admin/log_level`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Logging {
+    pub level: String,
+    /// Levels by module path, e.g. `saga_coordinator_lib::services::order = "debug"`
+    pub modules: HashMap<String, String>,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            modules: HashMap::new(),
+        }
+    }
+}
+
+/// Personal data removed from log output and Sentry events
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scrubbing {
+    pub enabled: bool,
+    /// String values of these fields are replaced, both in json and in debug output of models
+    pub fields: Vec<String>,
+    /// Regular expressions replaced wherever they match, emails and international phone numbers by default
+    pub patterns: Vec<ScrubPattern>,
+}
+
+impl Default for Scrubbing {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            fields: ["phone", "birthdate", "address", "postal_code", "route", "street_number", "locality"]
+                .iter()
+                .map(|field| field.to_string())
+                .collect(),
+            patterns: vec![
+                ScrubPattern {
+                    regex: r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}".to_string(),
+                    replacement: "<email>".to_string(),
+                },
+                ScrubPattern {
+                    regex: r"\+\d[\d ()-]{7,}\d".to_string(),
+                    replacement: "<phone>".to_string(),
+                },
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScrubPattern {
+    pub regex: String,
+    pub replacement: String,
+}
+
+/// Graylog input saga lifecycle events are sent to as GELF messages
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SagaEvents {
+    /// Address of GELF UDP input, e.g. `graylog:12201`
+    pub address: String,
+    /// Host messages are sent from, `HOSTNAME` environment variable if not set
+    #[serde(default)]
+    pub host: Option<String>,
+}
+
+/// Periodic export of statistics of finished sagas for analytics
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SagaStatsExport {
+    /// Sagas finished during every interval are aggregated into a row per saga type
+    pub interval_s: u64,
+    pub sink: SagaStatsSink,
+}
+
+/// Where exported statistics are written
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SagaStatsSink {
+    /// Rows are appended to csv file, header is written to a new file
+    Csv { path: String },
+    /// Rows are posted as json array, e.g. to ingestion endpoint of the warehouse
+    Http {
+        url: String,
+        #[serde(default)]
+        auth_token: Option<Secret>,
+    },
+}
+
+/// Cassette of calls to microservices, for development and regression tests only
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cassette {
+    /// Json file with recorded calls
+    pub path: String,
+    pub mode: CassetteMode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CassetteMode {
+    /// Calls are made to microservices, their responses are appended to the cassette
+    Record,
+    /// Calls are answered with responses from the cassette, microservices are not called
+    Playback,
+}
+
+/// Faults injected into calls to microservices, for staging only
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Chaos {
+    /// Faults by microservice, e.g. `users` or `fraud_scoring`
+    pub services: HashMap<String, ChaosFaults>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChaosFaults {
+    /// Delay added to every call
+    pub latency_ms: u64,
+    /// Random delay of up to that is added on top of `latency_ms`
+    pub jitter_ms: u64,
+    /// Share of calls answered with `503 Service Unavailable` instead of calling microservice, from 0 to 1
+    pub error_rate: f64,
+}
+
+/// Fields of successful json responses that are returned to callers, the rest is removed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Redaction {
+    /// Allowed fields by route name, e.g. `create_account = ["id", "email", "is_active"]`.
+    /// Nested fields are separated by dots, e.g. `user.id`, fields of arrays apply to every element.
+    /// Routes that are not listed are not redacted.
+    pub routes: HashMap<String, Vec<String>>,
+}
+
+/// Canary of a saga type, sagas chosen for canary call listed microservices at canary urls.
+/// Takes precedence over blue/green switch of `secondary_url`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Canary {
+    /// Share of sagas of the type in percents, e.g. `5.0`
+    pub percent: f64,
+    /// Canary urls by name of the microservice, e.g. `orders`
+    pub endpoints: HashMap<String, String>,
+}
+
+/// Mirroring of calls to microservices to their new versions, e.g. during migration to billing v2.
+/// Responses of secondary microservices are compared in background and never reach sagas.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Shadow {
+    /// Secondary microservices by name of the primary one, e.g. `billing`
+    pub services: HashMap<String, ShadowTarget>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShadowTarget {
+    /// Url of the secondary microservice, url of the primary one is replaced with it in mirrored calls
+    pub url: String,
+    /// Methods of mirrored calls, only `GET` calls are mirrored if not set.
+    /// Mirrored calls changing data are executed by both microservices.
+    #[serde(default)]
+    pub methods: Option<Vec<String>>,
+    /// Fields that are expected to differ, e.g. `id` or `created_at`, they are skipped at any depth
+    #[serde(default)]
+    pub ignored_fields: Vec<String>,
+}
+
+/// Election of the replica sending low stock digest
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LeaderElection {
+    /// Address of Redis keeping the lease, e.g. `127.0.0.1:6379`
+    pub redis_address: String,
+    pub key: String,
+    /// Lease expires if leader does not renew it for that long
+    pub lease_ms: u64,
+    /// Should be several times less than `lease_ms`
+    pub renew_interval_ms: u64,
+}
+
+impl Default for LeaderElection {
+    fn default() -> Self {
+        Self {
+            redis_address: "127.0.0.1:6379".to_string(),
+            key: "saga-coordinator:leader".to_string(),
+            lease_ms: 15_000,
+            renew_interval_ms: 5_000,
+        }
+    }
+}
+
+/// Check of new stores against existing stores of the same user
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DuplicateStores {
+    pub action: DuplicateStoreAction,
+}
+
+/// What is done to store which looks like a duplicate
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateStoreAction {
+    /// Store is created, duplicates are reported in saga warnings
+    Flag,
+    /// Store is not created
+    Reject,
+}
+
+/// Saga log and orphaned resources reaper settings
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Saga {
+    /// Json file for saga logs, logs are kept only in memory if not set
+    pub log_path: Option<String>,
+    pub reaper_interval_s: u64,
+    /// Runs reaper at fixed times of day instead of every `reaper_interval_s`
+    #[serde(default)]
+    pub reaper_schedule: Option<Schedule>,
+    pub reaper_max_attempts: u32,
+    /// Sagas that are in progress longer than that are considered failed, entities they lock are unlocked
+    pub reaper_stale_after_s: u64,
+    /// Time given to happy path of saga, completed steps are compensated when it expires
+    pub deadline_ms: u64,
+    /// Saga executed in background is resumed by reaper if its claim is not renewed for that long, by any replica if `sharing` is set
+    pub claim_lease_ms: u64,
+    pub heartbeat_interval_ms: u64,
+    #[serde(default)]
+    pub definitions: SagaDefinitions,
+    /// Records requests starting sagas and calls of sagas to microservices, so that sagas can be replayed
+    #[serde(default)]
+    pub record_calls: bool,
+    #[serde(default)]
+    pub lookup_retries: LookupRetries,
+    /// Personal data in persisted saga log is written as is if not set
+    #[serde(default)]
+    pub encryption: Option<SagaLogEncryption>,
+    #[serde(default)]
+    pub retention: SagaRetention,
+    /// Claims of background sagas and entity locks are kept by each replica for itself if not set
+    #[serde(default)]
+    pub sharing: Option<SagaSharing>,
+}
+
+/// Claims of background sagas and entity locks shared by replicas in Redis, so that a replica
+/// resumes background sagas of a replica that stopped and conflicting sagas are refused on any replica
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SagaSharing {
+    /// Address of Redis, usually the one keeping leader lease
+    pub redis_address: String,
+    pub key_prefix: String,
+}
+
+impl Default for SagaSharing {
+    fn default() -> Self {
+        Self {
+            redis_address: "127.0.0.1:6379".to_string(),
+            key_prefix: "saga-coordinator".to_string(),
+        }
+    }
+}
+
+/// Purging of finished sagas, so that saga log does not grow forever
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SagaRetention {
+    pub purge_interval_s: u64,
+    /// Completed and reverted sagas are purged once they have not changed for that long
+    pub finished_after_s: u64,
+    /// Orphaned sagas need manual intervention, they are kept until it happens if not set
+    pub orphaned_after_s: Option<u64>,
+}
+
+impl Default for SagaRetention {
+    fn default() -> Self {
+        Self {
+            purge_interval_s: 3600,
+            finished_after_s: 30 * 24 * 3600,
+            orphaned_after_s: None,
+        }
+    }
+}
+
+/// Encryption of inputs, recorded calls and results of stages in persisted saga log
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SagaLogEncryption {
+    /// Id of the key saga log is encrypted with
+    pub active_key: String,
+    /// Base64 encoded 256 bit AES keys by id, usually `vault:<key>`. Keys that are no longer active
+    /// only decrypt saga log written before rotation, the whole log is encrypted with the active key
+    /// on the next write, after that the old key can be removed.
+    pub keys: HashMap<String, Secret>,
+}
+
+/// Retries of lookups made by sagas, e.g. getting order or store, on transient failures
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LookupRetries {
+    /// Attempts including the first one
+    pub attempts: u32,
+    /// Delay before the second attempt, every next attempt waits that much longer
+    pub backoff_ms: u64,
+}
+
+impl Default for LookupRetries {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff_ms: 100,
+        }
+    }
+}
+
+/// Daily run times of a job
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Schedule {
+    /// Name from tz database, e.g. `Europe/Moscow`
+    pub time_zone: String,
+    /// Local times in `HH:MM` format
+    pub times: Vec<String>,
+}
+
+/// Steps of sagas executed after their main entity is created
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SagaDefinitions {
+    pub create_account: SagaDefinition,
+    pub create_store: SagaDefinition,
+    /// Steps of order sagas are fixed, only their timeouts are configured
+    pub orders: StepTimeouts,
+}
+
+/// Steps account creation saga is able to run
+pub const CREATE_ACCOUNT_STEPS: &[&str] = &[
+    "users_role_set",
+    "store_role_set",
+    "billing_role_set",
+    "delivery_role_set",
+    "billing_create_merchant",
+];
+
+/// Steps store creation saga is able to run
+pub const CREATE_STORE_STEPS: &[&str] = &[
+    "warehouses_role_set",
+    "orders_role_set",
+    "billing_role_set",
+    "delivery_role_set",
+    "billing_create_merchant",
+];
+
+/// Steps of order sagas which are able to time out
+pub const ORDER_STEPS: &[&str] = &[
+    "billing_reserve_gift_cards",
+    "billing_calculate_taxes",
+    "billing_create_invoice",
+    "users_get_user",
+];
+
+impl Default for SagaDefinitions {
+    fn default() -> Self {
+        Self {
+            create_account: SagaDefinition::new(CREATE_ACCOUNT_STEPS),
+            create_store: SagaDefinition::new(CREATE_STORE_STEPS),
+            orders: StepTimeouts::default(),
+        }
+    }
+}
+
+impl SagaDefinitions {
+    /// Fails on steps sagas are not able to run, so that typos are found at startup rather than by the first saga
+    pub fn validate(&self) -> Result<(), FailureError> {
+        self.create_account
+            .validate(CREATE_ACCOUNT_STEPS)
+            .map_err(|e| format_err!("saga.definitions.create_account: {}", e))?;
+        self.create_store
+            .validate(CREATE_STORE_STEPS)
+            .map_err(|e| format_err!("saga.definitions.create_store: {}", e))?;
+        self.orders
+            .validate(ORDER_STEPS)
+            .map_err(|e| format_err!("saga.definitions.orders: {}", e))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SagaDefinition {
+    /// Steps in order of execution, steps missing from the list are not executed
+    pub steps: Vec<String>,
+    /// Groups of steps executed concurrently, group is executed in place of its first step
+    #[serde(default)]
+    pub parallel: Vec<Vec<String>>,
+    /// Steps whose failure does not fail the saga
+    #[serde(default)]
+    pub soft: Vec<String>,
+    /// Timeouts of steps, step taking longer fails even if saga deadline is not reached yet
+    #[serde(default)]
+    pub timeouts_ms: HashMap<String, u64>,
+}
+
+impl SagaDefinition {
+    pub fn new(steps: &[&str]) -> Self {
+        Self {
+            steps: steps.iter().map(|step| step.to_string()).collect(),
+            parallel: vec![],
+            soft: vec![],
+            timeouts_ms: HashMap::new(),
+        }
+    }
+
+    /// Splits steps into groups executed one after another
+    pub fn groups(&self) -> Vec<Vec<String>> {
+        let mut groups: Vec<Vec<String>> = vec![];
+        for step in &self.steps {
+            if groups.iter().any(|group| group.contains(step)) {
+                continue;
+            }
+            let group = self
+                .parallel
+                .iter()
+                .find(|group| group.contains(step))
+                .map(|group| group.iter().filter(|step| self.steps.contains(step)).cloned().collect())
+                .unwrap_or_else(|| vec![step.clone()]);
+            groups.push(group);
+        }
+        groups
+    }
+
+    pub fn is_soft(&self, step: &str) -> bool {
+        self.soft.iter().any(|soft| soft == step)
+    }
+
+    pub fn timeout(&self, step: &str) -> Option<Duration> {
+        self.timeouts_ms.get(step).map(|timeout_ms| Duration::from_millis(*timeout_ms))
+    }
+
+    /// Every step named in the definition must be one of `known` steps
+    pub fn validate(&self, known: &[&str]) -> Result<(), FailureError> {
+        let named = self
+            .steps
+            .iter()
+            .chain(self.parallel.iter().flat_map(|group| group.iter()))
+            .chain(self.soft.iter())
+            .chain(self.timeouts_ms.keys());
+        let mut unknown = named.filter(|step| !known.contains(&step.as_str())).cloned().collect::<Vec<_>>();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        unknown.sort();
+        unknown.dedup();
+        Err(format_err!(
+            "unknown steps {}, known steps are {}",
+            unknown.join(", "),
+            known.join(", ")
+        ))
+    }
+}
+
+/// Timeouts of steps of sagas whose steps are fixed in code
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StepTimeouts {
+    /// Step taking longer fails even if saga deadline is not reached yet, steps without timeout are limited by the deadline only
+    #[serde(default)]
+    pub timeouts_ms: HashMap<String, u64>,
+}
+
+impl StepTimeouts {
+    pub fn timeout(&self, step: &str) -> Option<Duration> {
+        self.timeouts_ms.get(step).map(|timeout_ms| Duration::from_millis(*timeout_ms))
+    }
+
+    pub fn validate(&self, known: &[&str]) -> Result<(), FailureError> {
+        let mut unknown = self
+            .timeouts_ms
+            .keys()
+            .filter(|step| !known.contains(&step.as_str()))
+            .cloned()
+            .collect::<Vec<_>>();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        unknown.sort();
+        Err(format_err!(
+            "unknown steps {}, known steps are {}",
+            unknown.join(", "),
+            known.join(", ")
+        ))
+    }
 }
 
 impl Config {
@@ -82,6 +1232,19 @@ impl Config {
         let mut s = RawConfig::new();
 
         s.set_default("service.processing_timeout_ms", 1000 as i64).unwrap();
+        s.set_default("service.strict_payloads", false).unwrap();
+        s.set_default("client.default_currency", "STQ").unwrap();
+        s.set_default("client.default_fiat_currency", "USD").unwrap();
+        s.set_default("saga.reaper_interval_s", 60 as i64).unwrap();
+        s.set_default("saga.reaper_max_attempts", 5 as i64).unwrap();
+        s.set_default("saga.reaper_stale_after_s", 3600 as i64).unwrap();
+        s.set_default("saga.deadline_ms", 30000 as i64).unwrap();
+        s.set_default("saga.claim_lease_ms", 60000 as i64).unwrap();
+        s.set_default("saga.heartbeat_interval_ms", 15000 as i64).unwrap();
+        s.set_default("limits.account_body_bytes", 16 * 1024 as i64).unwrap();
+        s.set_default("limits.bulk_body_bytes", 10 * 1024 * 1024 as i64).unwrap();
+        s.set_default("limits.default_body_bytes", 1024 * 1024 as i64).unwrap();
+        s.set_default("limits.downstream_payload_bytes", 10 * 1024 * 1024 as i64).unwrap();
 
         s.merge(File::with_name("config/base"))?;
 
@@ -95,6 +1258,19 @@ impl Config {
         s.try_into()
     }
 
+    /// Microservices by their names
+    pub fn microservices_mut(&mut self) -> Vec<(&'static str, &mut Microservice)> {
+        vec![
+            ("users", &mut self.users_microservice),
+            ("stores", &mut self.stores_microservice),
+            ("orders", &mut self.orders_microservice),
+            ("billing", &mut self.billing_microservice),
+            ("warehouses", &mut self.warehouses_microservice),
+            ("notifications", &mut self.notifications_microservice),
+            ("delivery", &mut self.delivery_microservice),
+        ]
+    }
+
     pub fn service_url(&self, service: StqService) -> String {
         match service {
             StqService::Users => self.users_microservice.url.clone(),
@@ -0,0 +1,50 @@
+//! Routes which only start a saga in background respond with `202 Accepted`
+//! instead of `200 OK`, since controller can not set status of successful responses.
+use std::sync::Arc;
+
+use futures::prelude::*;
+use hyper;
+use hyper::server::{Request, Response, Service};
+use hyper::StatusCode;
+
+use stq_router::RouteParser;
+
+use super::routes::{split_version, Route};
+
+pub struct Accepted<S> {
+    route_parser: Arc<RouteParser<Route>>,
+    inner: S,
+}
+
+impl<S> Accepted<S> {
+    pub fn new(route_parser: Arc<RouteParser<Route>>, inner: S) -> Self {
+        Self { route_parser, inner }
+    }
+}
+
+impl<S> Service for Accepted<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+    S::Future: 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let is_async = self
+            .route_parser
+            .test(split_version(req.path()).1)
+            .map(|route| route.is_async())
+            .unwrap_or(false);
+
+        Box::new(self.inner.call(req).map(move |response| {
+            if is_async && response.status() == StatusCode::Ok {
+                response.with_status(StatusCode::Accepted)
+            } else {
+                response
+            }
+        }))
+    }
+}
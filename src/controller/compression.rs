@@ -0,0 +1,148 @@
+//! Responses are compressed with gzip or deflate, whichever the caller prefers in `Accept-Encoding`.
+//! Small bodies are sent as is, compressing them saves less than the encoding costs.
+use std::io::Write;
+
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use hyper;
+use hyper::header::{q, AcceptEncoding, ContentEncoding, ContentLength, Encoding};
+use hyper::server::{Request, Response, Service};
+
+/// Bodies shorter than that are not compressed
+const MIN_COMPRESSED_BYTES: usize = 1024;
+
+pub struct Compressed<S> {
+    inner: S,
+}
+
+impl<S> Compressed<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S> Service for Compressed<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+    S::Future: 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let encoding = req.headers().get::<AcceptEncoding>().and_then(preferred_encoding);
+
+        Box::new(self.inner.call(req).and_then(move |response| {
+            let encoding = match encoding {
+                Some(ref encoding) if response.headers().get::<ContentEncoding>().is_none() => encoding.clone(),
+                _ => return Either::A(future::ok(response)),
+            };
+            let mut headers = response.headers().clone();
+            let status = response.status();
+            Either::B(response.body().concat2().map(move |body| {
+                // compressed or not, response depends on `Accept-Encoding` of the request
+                headers.append_raw("Vary", "Accept-Encoding");
+                if body.len() < MIN_COMPRESSED_BYTES {
+                    return Response::new().with_status(status).with_headers(headers).with_body(body);
+                }
+                match compress(&body, &encoding) {
+                    Ok(compressed) => {
+                        headers.set(ContentLength(compressed.len() as u64));
+                        headers.set(ContentEncoding(vec![encoding]));
+                        Response::new().with_status(status).with_headers(headers).with_body(compressed)
+                    }
+                    Err(e) => {
+                        warn!("Response is sent uncompressed, compressing it failed: {}", e);
+                        Response::new().with_status(status).with_headers(headers).with_body(body)
+                    }
+                }
+            }))
+        }))
+    }
+}
+
+/// Gzip or deflate with the highest quality, gzip wins a tie. `*` stands for gzip
+fn preferred_encoding(accept: &AcceptEncoding) -> Option<Encoding> {
+    let mut encodings = accept
+        .0
+        .iter()
+        .filter(|item| item.quality > q(0))
+        .filter_map(|item| match item.item {
+            Encoding::Gzip => Some((Encoding::Gzip, item.quality, 1)),
+            Encoding::EncodingExt(ref any) if any == "*" => Some((Encoding::Gzip, item.quality, 0)),
+            Encoding::Deflate => Some((Encoding::Deflate, item.quality, 0)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    encodings.sort_by(|a, b| (b.1, b.2).cmp(&(a.1, a.2)));
+    encodings.into_iter().next().map(|(encoding, _, _)| encoding)
+}
+
+fn compress(body: &[u8], encoding: &Encoding) -> Result<Vec<u8>, ::std::io::Error> {
+    match *encoding {
+        Encoding::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+        _ => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::{q, AcceptEncoding, Encoding, QualityItem};
+
+    use super::{compress, preferred_encoding};
+    use controller::decode_body;
+
+    fn accept(items: Vec<(Encoding, u16)>) -> AcceptEncoding {
+        AcceptEncoding(
+            items
+                .into_iter()
+                .map(|(encoding, quality)| QualityItem::new(encoding, q(quality)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn picks_encoding_preferred_by_caller() {
+        assert_eq!(
+            preferred_encoding(&accept(vec![(Encoding::Deflate, 1000), (Encoding::Gzip, 1000)])),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            preferred_encoding(&accept(vec![(Encoding::Deflate, 1000), (Encoding::Gzip, 500)])),
+            Some(Encoding::Deflate)
+        );
+        assert_eq!(
+            preferred_encoding(&accept(vec![(Encoding::EncodingExt("*".to_string()), 1000)])),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            preferred_encoding(&accept(vec![(Encoding::Gzip, 0), (Encoding::Brotli, 1000)])),
+            None
+        );
+        assert_eq!(preferred_encoding(&accept(vec![])), None);
+    }
+
+    #[test]
+    fn compressed_body_decodes_back() {
+        let body = (0..100)
+            .flat_map(|_| br#"{"status":"completed"}"#.iter().cloned())
+            .collect::<Vec<u8>>();
+        for encoding in vec![Encoding::Gzip, Encoding::Deflate] {
+            let compressed = compress(&body, &encoding).unwrap();
+            assert!(compressed.len() < body.len());
+            assert_eq!(decode_body(compressed, &[encoding], body.len()).unwrap(), body);
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Caller of the request and everything derived from its headers. The context
+//! is built once per request, services and microservices get it instead of raw headers.
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use hyper::header::{Authorization, Headers};
+
+use stq_http::request_util::CorrelationToken as CorrelationTokenHeader;
+use stq_http::request_util::RequestTimeout as RequestTimeoutHeader;
+use stq_http::request_util::{Currency as CurrencyHeader, FiatCurrency as FiatCurrencyHeader};
+use stq_types::UserId;
+
+use super::locale;
+use super::request_id::{request_id, REQUEST_ID_HEADER};
+use config::Config;
+use microservice::{is_superadmin, Budget, Initiator};
+
+#[derive(Clone, Debug)]
+pub struct RequestContext {
+    /// Caller of the request, `None` for anonymous requests, superadmin is given with its own `Authorization`
+    pub initiator: Option<Initiator>,
+    /// `Authorization` header of the caller, passed downstream as is
+    pub authorization: Option<String>,
+    pub correlation_token: Option<String>,
+    pub request_id: Option<String>,
+    /// Currency of the caller, falls back to configured default
+    pub currency: String,
+    /// Fiat currency of the caller, falls back to configured default
+    pub fiat_currency: String,
+    pub locale: Option<String>,
+    /// Calls to microservices must be finished by the deadline of the request, shared by every client of the request
+    pub budget: Budget,
+    /// Config the request is served with
+    pub config: Config,
+    /// Urls of microservices switched between deployments the request is served with, by name of the microservice
+    pub endpoints: BTreeMap<String, String>,
+}
+
+impl RequestContext {
+    /// `margin` is subtracted from timeout of the caller, so that the coordinator responds in time
+    pub fn new(config: &Config, headers: &Headers, margin: Duration) -> Self {
+        let authorization = headers.get::<Authorization<String>>().map(|header| header.0.clone());
+        let initiator = if is_superadmin(headers) {
+            Some(Initiator::Superadmin)
+        } else {
+            authorization
+                .as_ref()
+                .and_then(|value| value.parse::<i32>().ok())
+                .map(|id| Initiator::User(UserId(id)))
+        };
+
+        let default_timeout = Duration::from_millis(config.client.http_timeout_ms);
+        let request_timeout = match headers.get::<RequestTimeoutHeader>() {
+            None => default_timeout,
+            Some(header) => header.0.parse::<u64>().map(Duration::from_millis).unwrap_or(default_timeout),
+        }
+        .checked_sub(margin)
+        .unwrap_or(Duration::new(0, 0));
+
+        Self {
+            initiator,
+            authorization,
+            correlation_token: headers.get::<CorrelationTokenHeader>().map(|header| header.0.clone()),
+            request_id: request_id(headers),
+            currency: headers
+                .get::<CurrencyHeader>()
+                .map(|header| header.0.clone())
+                .unwrap_or_else(|| config.client.default_currency.clone()),
+            fiat_currency: headers
+                .get::<FiatCurrencyHeader>()
+                .map(|header| header.0.clone())
+                .unwrap_or_else(|| config.client.default_fiat_currency.clone()),
+            locale: locale(headers),
+            budget: Budget::new(Instant::now() + request_timeout, default_timeout),
+            config: config.clone(),
+            endpoints: BTreeMap::new(),
+        }
+    }
+
+    /// Headers identifying the caller and the request in microservices
+    pub fn default_headers(&self) -> Headers {
+        let mut headers = Headers::new();
+        if let Some(ref authorization) = self.authorization {
+            headers.set(Authorization(authorization.clone()));
+        }
+        if let Some(ref correlation_token) = self.correlation_token {
+            headers.set(CorrelationTokenHeader(correlation_token.clone()));
+        }
+        if let Some(ref request_id) = self.request_id {
+            headers.set_raw(REQUEST_ID_HEADER, request_id.clone());
+        }
+        headers
+    }
+
+    /// Default headers with currencies of the caller
+    pub fn currency_headers(&self) -> Headers {
+        let mut headers = self.default_headers();
+        headers.set(CurrencyHeader(self.currency.clone()));
+        headers.set(FiatCurrencyHeader(self.fiat_currency.clone()));
+        headers
+    }
+
+    /// Default headers with currencies stores microservice keeps prices in
+    pub fn stores_headers(&self) -> Headers {
+        let mut headers = self.default_headers();
+        headers.set(CurrencyHeader("STQ".to_string()));
+        headers.set(FiatCurrencyHeader("USD".to_string()));
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hyper::header::{Authorization, Headers};
+
+    use stq_types::UserId;
+
+    use super::RequestContext;
+    use config::Config;
+    use microservice::Initiator;
+
+    fn initiator(authorization: Option<&str>) -> Option<Initiator> {
+        let mut headers = Headers::new();
+        if let Some(authorization) = authorization {
+            headers.set(Authorization(authorization.to_string()));
+        }
+        RequestContext::new(&Config::new().unwrap(), &headers, Duration::from_millis(0)).initiator
+    }
+
+    #[test]
+    fn derives_caller_from_authorization() {
+        match initiator(Some("1")) {
+            Some(Initiator::Superadmin) => {}
+            other => panic!("superadmin is taken for {:?}", other),
+        }
+        match initiator(Some("42")) {
+            Some(Initiator::User(user_id)) => assert_eq!(user_id, UserId(42)),
+            other => panic!("user is taken for {:?}", other),
+        }
+        assert!(initiator(Some("token")).is_none());
+        assert!(initiator(None).is_none());
+    }
+}
@@ -0,0 +1,79 @@
+//! CORS middleware, lets browser based clients like admin tools call
+//! the service directly. Preflight requests of allowed origins are answered
+//! here and never reach `Controller`. Headers listed in `exposed_headers`,
+//! e.g. `X-Request-Id` and `ETag`, are made readable by scripts of allowed origins.
+use futures::future;
+use futures::prelude::*;
+use hyper;
+use hyper::server::{Request, Response, Service};
+use hyper::{Method, StatusCode};
+
+use config;
+
+pub struct Cors<S> {
+    config: config::Cors,
+    inner: S,
+}
+
+impl<S> Cors<S> {
+    pub fn new(config: config::Cors, inner: S) -> Self {
+        Self { config, inner }
+    }
+
+    fn allowed_origin(&self, req: &Request) -> Option<String> {
+        req.headers()
+            .get_raw("Origin")
+            .and_then(|raw| raw.one())
+            .and_then(|origin| String::from_utf8(origin.to_vec()).ok())
+            .filter(|origin| self.config.is_allowed(origin))
+    }
+
+    fn preflight_response(&self, origin: String) -> Response {
+        let mut response = Response::new().with_status(StatusCode::NoContent);
+        {
+            let headers = response.headers_mut();
+            headers.set_raw("Access-Control-Allow-Origin", origin);
+            headers.set_raw("Access-Control-Allow-Methods", self.config.allowed_methods.join(", "));
+            headers.set_raw("Access-Control-Allow-Headers", self.config.allowed_headers.join(", "));
+            headers.set_raw("Access-Control-Max-Age", self.config.max_age_s.to_string());
+            headers.set_raw("Vary", "Origin");
+        }
+        response
+    }
+}
+
+impl<S> Service for Cors<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+    S::Future: 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let origin = match self.allowed_origin(&req) {
+            Some(origin) => origin,
+            None => return Box::new(self.inner.call(req)),
+        };
+
+        let is_preflight = *req.method() == Method::Options && req.headers().get_raw("Access-Control-Request-Method").is_some();
+        if is_preflight {
+            return Box::new(future::ok(self.preflight_response(origin)));
+        }
+
+        let exposed_headers = self.config.exposed_headers.join(", ");
+        Box::new(self.inner.call(req).map(move |mut response| {
+            {
+                let headers = response.headers_mut();
+                headers.set_raw("Access-Control-Allow-Origin", origin);
+                if !exposed_headers.is_empty() {
+                    headers.set_raw("Access-Control-Expose-Headers", exposed_headers);
+                }
+                headers.set_raw("Vary", "Origin");
+            }
+            response
+        }))
+    }
+}
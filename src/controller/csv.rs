@@ -0,0 +1,109 @@
+//! Parsing of csv request bodies (RFC 4180). The first record is a header,
+//! the rest of records are keyed by its column names.
+use std::collections::HashMap;
+
+use failure::Error as FailureError;
+
+use errors::Error;
+
+/// Records of csv document keyed by column names, blank lines are skipped
+pub fn parse_records(bytes: &[u8]) -> Result<Vec<HashMap<String, String>>, FailureError> {
+    let text = ::std::str::from_utf8(bytes).map_err(|_| format_err!("Csv body is not valid utf-8").context(Error::Parse))?;
+    let mut records = parse_fields(text)?.into_iter();
+
+    let header = match records.next() {
+        Some(header) => header.into_iter().map(|column| column.trim().to_string()).collect::<Vec<_>>(),
+        None => return Ok(vec![]),
+    };
+
+    records
+        .enumerate()
+        .map(|(index, fields)| {
+            if fields.len() != header.len() {
+                return Err(
+                    format_err!("Csv record {} has {} fields, header has {}", index + 1, fields.len(), header.len())
+                        .context(Error::Parse)
+                        .into(),
+                );
+            }
+            Ok(header.iter().cloned().zip(fields).collect())
+        })
+        .collect()
+}
+
+fn parse_fields(text: &str) -> Result<Vec<Vec<String>>, FailureError> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    // field was quoted, so record of a single empty field is not a blank line
+    let mut had_quotes = false;
+    let mut chars = text.trim_left_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                c => field.push(c),
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.is_empty() => {
+                quoted = true;
+                had_quotes = true;
+            }
+            '"' => return Err(format_err!("Unexpected quote in csv field {}", field).context(Error::Parse).into()),
+            ',' => record.push(::std::mem::replace(&mut field, String::new())),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                finish_record(&mut records, &mut record, &mut field, had_quotes);
+                had_quotes = false;
+            }
+            c => field.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(format_err!("Csv body ends inside quoted field").context(Error::Parse).into());
+    }
+    finish_record(&mut records, &mut record, &mut field, had_quotes);
+    Ok(records)
+}
+
+fn finish_record(records: &mut Vec<Vec<String>>, record: &mut Vec<String>, field: &mut String, had_quotes: bool) {
+    if record.is_empty() && field.is_empty() && !had_quotes {
+        return;
+    }
+    record.push(::std::mem::replace(field, String::new()));
+    records.push(::std::mem::replace(record, vec![]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_records;
+
+    #[test]
+    fn parses_quoted_fields() {
+        let body = "name,short_description\r\nMug,\"Big, \"\"ceramic\"\" mug\"\r\n\r\n\"Cup\",\"Two\nlines\"\n";
+        let records = parse_records(body.as_bytes()).unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["name"], "Mug");
+        assert_eq!(records[0]["short_description"], "Big, \"ceramic\" mug");
+        assert_eq!(records[1]["name"], "Cup");
+        assert_eq!(records[1]["short_description"], "Two\nlines");
+    }
+
+    #[test]
+    fn rejects_malformed_records() {
+        assert!(parse_records(b"name,price\nMug\n").is_err());
+        assert!(parse_records(b"name\n\"Mug\n").is_err());
+        assert!(parse_records(b"name\nM\"ug\n").is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! Blue/green switching of microservices having `secondary_url`. Share of traffic sent to
+//! the secondary deployment is set at runtime with `PUT /admin/endpoints`, from none of it
+//! to all of it. Deployment is chosen once per request, so every call of a saga goes to
+//! the same deployment, and the chosen urls are recorded in the saga record. Shares are
+//! kept in memory of the replica, it starts with all traffic sent to primary urls.
+//!
+//! Sagas of types having `[canary]` configured are sent to canary urls of listed
+//! microservices instead, in the configured share of requests starting them. Saga type
+//! is known from the route before the saga starts, so the whole saga goes to canary.
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+
+use rand;
+
+use config::{Canary, Config, Microservice};
+use errors::Error;
+use models::{EndpointState, SagaType};
+
+#[derive(Clone)]
+pub struct EndpointSwitch {
+    /// Primary and secondary urls by name of the microservice
+    services: Arc<Vec<(String, String, String)>>,
+    /// Share of traffic sent to secondary url in percents by name of the microservice
+    shares: Arc<RwLock<HashMap<String, u8>>>,
+    /// Canaries by saga type
+    canaries: Arc<HashMap<String, Canary>>,
+}
+
+impl EndpointSwitch {
+    pub fn new(config: &Config) -> Self {
+        for (saga_type, canary) in &config.canary {
+            if saga_type.parse::<SagaType>().is_err() {
+                error!("Canary of unknown saga type {} is never used", saga_type);
+            }
+            info!(
+                "{}% of {} sagas are sent to canary of {:?}",
+                canary.percent,
+                saga_type,
+                canary.endpoints.keys()
+            );
+        }
+        let canaries = config.canary.clone();
+        let mut config = config.clone();
+        Self::with_microservices(config.microservices_mut(), canaries)
+    }
+
+    fn with_microservices(microservices: Vec<(&'static str, &mut Microservice)>, canaries: HashMap<String, Canary>) -> Self {
+        let services = microservices
+            .into_iter()
+            .filter_map(|(name, microservice)| {
+                microservice
+                    .secondary_url
+                    .clone()
+                    .map(|secondary_url| (name.to_string(), microservice.url.clone(), secondary_url))
+            })
+            .collect();
+        Self {
+            services: Arc::new(services),
+            shares: Arc::new(RwLock::new(HashMap::new())),
+            canaries: Arc::new(canaries),
+        }
+    }
+
+    pub fn states(&self) -> Vec<EndpointState> {
+        let shares = self.shares.read().unwrap();
+        self.services
+            .iter()
+            .map(|&(ref service, ref primary_url, ref secondary_url)| EndpointState {
+                service: service.clone(),
+                primary_url: primary_url.clone(),
+                secondary_url: secondary_url.clone(),
+                secondary_percent: shares.get(service).cloned().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    pub fn set(&self, service: &str, secondary_percent: u8) -> Result<EndpointState, Error> {
+        if secondary_percent > 100 {
+            return Err(Error::Validate(
+                validation_errors!({"secondary_percent": ["range" => "Share must be from 0 to 100"]}),
+            ));
+        }
+        if !self.services.iter().any(|&(ref name, _, _)| name == service) {
+            return Err(Error::Validate(
+                validation_errors!({"service": ["secondary_url" => "Microservice has no secondary url"]}),
+            ));
+        }
+        self.shares.write().unwrap().insert(service.to_string(), secondary_percent);
+        warn!("{}% of traffic to {} is switched to its secondary url", secondary_percent, service);
+        Ok(self
+            .states()
+            .into_iter()
+            .find(|state| state.service == service)
+            .expect("State of switched microservice"))
+    }
+
+    /// Chooses deployments for the request starting saga of `saga_type` and points urls of `config` at them,
+    /// returns urls chosen by name of the microservice
+    pub fn apply(&self, config: &mut Config, saga_type: Option<SagaType>) -> BTreeMap<String, String> {
+        self.switch(config.microservices_mut(), saga_type, rand::random::<f64>)
+    }
+
+    fn switch<R: FnMut() -> f64>(
+        &self,
+        microservices: Vec<(&'static str, &mut Microservice)>,
+        saga_type: Option<SagaType>,
+        mut random: R,
+    ) -> BTreeMap<String, String> {
+        let canary = match saga_type.and_then(|saga_type| self.canaries.get(&saga_type.to_string())) {
+            Some(canary) if random() * 100.0 < canary.percent => Some(canary),
+            _ => None,
+        };
+        let shares = self.shares.read().unwrap();
+        let mut chosen = BTreeMap::new();
+        for (name, microservice) in microservices {
+            if let Some(url) = canary.and_then(|canary| canary.endpoints.get(name)) {
+                microservice.url = url.clone();
+                chosen.insert(name.to_string(), url.clone());
+                continue;
+            }
+            let secondary_url = match microservice.secondary_url {
+                Some(ref secondary_url) => secondary_url.clone(),
+                None => continue,
+            };
+            let share = shares.get(name).cloned().unwrap_or_default();
+            if random() * 100.0 < f64::from(share) {
+                microservice.url = secondary_url;
+            }
+            chosen.insert(name.to_string(), microservice.url.clone());
+        }
+        chosen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::EndpointSwitch;
+    use config::{Canary, Microservice};
+    use models::SagaType;
+
+    fn microservice(url: &str, secondary_url: Option<&str>) -> Microservice {
+        Microservice {
+            url: url.to_string(),
+            secondary_url: secondary_url.map(|url| url.to_string()),
+        }
+    }
+
+    #[test]
+    fn switches_share_of_requests() {
+        let mut users = microservice("http://users", None);
+        let mut billing = microservice("http://billing-blue", Some("http://billing-green"));
+        let switch = EndpointSwitch::with_microservices(vec![("users", &mut users), ("billing", &mut billing)], HashMap::new());
+        assert!(switch.set("users", 100).is_err());
+        assert!(switch.set("billing", 101).is_err());
+        switch.set("billing", 30).unwrap();
+
+        let chosen = switch.switch(vec![("users", &mut users), ("billing", &mut billing)], None, || 0.5);
+        assert_eq!(chosen.len(), 1);
+        assert_eq!(chosen["billing"], "http://billing-blue");
+
+        let chosen = switch.switch(vec![("users", &mut users), ("billing", &mut billing)], None, || 0.2);
+        assert_eq!(chosen["billing"], "http://billing-green");
+        assert_eq!(billing.url, "http://billing-green");
+    }
+
+    #[test]
+    fn sends_share_of_saga_type_to_canary() {
+        let mut orders = microservice("http://orders", None);
+        let mut canaries = HashMap::new();
+        let mut endpoints = HashMap::new();
+        endpoints.insert("orders".to_string(), "http://orders-canary".to_string());
+        canaries.insert("create_order".to_string(), Canary { percent: 5.0, endpoints });
+        let switch = EndpointSwitch::with_microservices(vec![("orders", &mut orders)], canaries);
+
+        let chosen = switch.switch(vec![("orders", &mut orders)], Some(SagaType::CreateOrder), || 0.5);
+        assert!(chosen.is_empty());
+        let chosen = switch.switch(vec![("orders", &mut orders)], Some(SagaType::CreateAccount), || 0.01);
+        assert!(chosen.is_empty());
+        let chosen = switch.switch(vec![("orders", &mut orders)], Some(SagaType::CreateOrder), || 0.01);
+        assert_eq!(chosen["orders"], "http://orders-canary");
+        assert_eq!(orders.url, "http://orders-canary");
+    }
+}
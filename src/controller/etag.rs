@@ -0,0 +1,111 @@
+//! Read endpoints polled by dashboards, like saga status and dependency health, carry
+//! `ETag` of their response. Requests with `If-None-Match` naming the current tag get
+//! `304 Not Modified` without body. The response is still built in full to compute the tag,
+//! only the payload sent to the dashboard is saved.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::sync::Arc;
+
+use futures::future::{self, Either};
+use futures::prelude::*;
+use hyper;
+use hyper::header::{ContentLength, ETag, EntityTag, IfNoneMatch};
+use hyper::server::{Request, Response, Service};
+use hyper::{Method, StatusCode};
+
+use stq_router::RouteParser;
+
+use super::routes::{split_version, Route};
+
+pub struct ETags<S> {
+    route_parser: Arc<RouteParser<Route>>,
+    inner: S,
+}
+
+impl<S> ETags<S> {
+    pub fn new(route_parser: Arc<RouteParser<Route>>, inner: S) -> Self {
+        Self { route_parser, inner }
+    }
+}
+
+impl<S> Service for ETags<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+    S::Future: 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let is_tagged = *req.method() == Method::Get
+            && self
+                .route_parser
+                .test(split_version(req.path()).1)
+                .map(|route| route.is_tagged())
+                .unwrap_or(false);
+        if !is_tagged {
+            return Box::new(self.inner.call(req));
+        }
+
+        let if_none_match = req.headers().get::<IfNoneMatch>().cloned();
+        Box::new(self.inner.call(req).and_then(move |response| {
+            if response.status() != StatusCode::Ok {
+                return Either::A(future::ok(response));
+            }
+            let mut headers = response.headers().clone();
+            Either::B(response.body().concat2().map(move |body| {
+                let etag = entity_tag(&body);
+                if is_not_modified(if_none_match.as_ref(), &etag) {
+                    headers.remove::<ContentLength>();
+                    Response::new()
+                        .with_status(StatusCode::NotModified)
+                        .with_headers(headers)
+                        .with_header(ETag(etag))
+                } else {
+                    Response::new().with_headers(headers).with_header(ETag(etag)).with_body(body)
+                }
+            }))
+        }))
+    }
+}
+
+/// Strong tag of the body. Default hasher is the same in every replica of the build,
+/// so tags do not change when polling goes to another replica.
+fn entity_tag(body: &[u8]) -> EntityTag {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(body);
+    EntityTag::strong(format!("{:016x}", hasher.finish()))
+}
+
+fn is_not_modified(if_none_match: Option<&IfNoneMatch>, etag: &EntityTag) -> bool {
+    match if_none_match {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(ref tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::{EntityTag, IfNoneMatch};
+
+    use super::{entity_tag, is_not_modified};
+
+    #[test]
+    fn matches_tags_weakly() {
+        let etag = entity_tag(b"{\"status\":\"completed\"}");
+        assert_eq!(etag, entity_tag(b"{\"status\":\"completed\"}"));
+        assert_ne!(etag, entity_tag(b"{\"status\":\"in_progress\"}"));
+
+        let weak = EntityTag::weak(etag.tag().to_string());
+        assert!(is_not_modified(Some(&IfNoneMatch::Items(vec![weak])), &etag));
+        assert!(is_not_modified(Some(&IfNoneMatch::Any), &etag));
+        assert!(!is_not_modified(
+            Some(&IfNoneMatch::Items(vec![EntityTag::strong("stale".to_string())])),
+            &etag
+        ));
+        assert!(!is_not_modified(None, &etag));
+    }
+}
@@ -0,0 +1,81 @@
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::prelude::*;
+use hyper::server::Request;
+use hyper::Method;
+
+use stq_http::controller::ControllerFuture;
+use stq_http::request_util::serialize_future;
+
+use super::super::routes::Route;
+use super::super::{parse_body, saga_result, validate};
+use super::{Handler, HandlerContext};
+use models::*;
+use services::account::AccountService;
+
+/// Account creation, email verification and password reset
+pub struct AccountsHandler;
+
+impl Handler for AccountsHandler {
+    fn handle(&self, ctx: HandlerContext, req: Request, route: Route) -> Option<ControllerFuture> {
+        let version = ctx.version;
+        let account_service = ctx.account_service();
+
+        let fut = match (&req.method().clone(), route) {
+            (&Method::Post, Route::CreateAccount) => serialize_future(
+                parse_body::<SagaCreateProfile>(req.body(), &ctx.headers, ctx.body_options)
+                    .map_err(|e| FailureError::from(e.context("Parsing body // POST /create_account in SagaCreateProfile failed!")))
+                    .and_then(validate)
+                    .and_then(move |profile| {
+                        account_service
+                            .create(profile)
+                            .map(move |(_, user)| saga_result(version, user))
+                            .map_err(|(_, e)| FailureError::from(e.context("Error during account creation occurred.")))
+                    }),
+            ),
+            (&Method::Post, Route::VerifyEmail) => serialize_future(
+                parse_body::<VerifyRequest>(req.body(), &ctx.headers, ctx.body_options)
+                    .map_err(|e| FailureError::from(e.context("Parsing body // POST /email_verify in VerifyRequest failed!")))
+                    .and_then(move |profile| {
+                        account_service
+                            .request_email_verification(profile)
+                            .map(|(_, user)| user)
+                            .map_err(|(_, e)| FailureError::from(e.context("Error during email verification occurred.")))
+                    }),
+            ),
+            (&Method::Post, Route::VerifyEmailApply) => serialize_future(
+                parse_body::<EmailVerifyApply>(req.body(), &ctx.headers, ctx.body_options)
+                    .map_err(|e| FailureError::from(e.context("Parsing body // POST /email_verify_apply in EmailVerifyApply failed!")))
+                    .and_then(move |profile| {
+                        account_service
+                            .request_email_verification_apply(profile)
+                            .map(|(_, user)| user)
+                            .map_err(|(_, e)| FailureError::from(e.context("Error during email verification apply occurred.")))
+                    }),
+            ),
+            (&Method::Post, Route::ResetPassword) => serialize_future(
+                parse_body::<ResetRequest>(req.body(), &ctx.headers, ctx.body_options)
+                    .map_err(|e| FailureError::from(e.context("Parsing body // POST /reset_password in ResetRequest failed!")))
+                    .and_then(move |profile| {
+                        account_service
+                            .request_password_reset(profile)
+                            .map(|(_, user)| user)
+                            .map_err(|(_, e)| FailureError::from(e.context("Error during reset password occurred.")))
+                    }),
+            ),
+            (&Method::Post, Route::ResetPasswordApply) => serialize_future(
+                parse_body::<PasswordResetApply>(req.body(), &ctx.headers, ctx.body_options)
+                    .map_err(|e| FailureError::from(e.context("Parsing body // POST /reset_password_apply in PasswordResetApply failed!")))
+                    .and_then(move |profile| {
+                        account_service
+                            .request_password_reset_apply(profile)
+                            .map(|(_, user)| user)
+                            .map_err(|(_, e)| FailureError::from(e.context("Error during reset password apply occurred.")))
+                    }),
+            ),
+            _ => return None,
+        };
+
+        Some(fut)
+    }
+}
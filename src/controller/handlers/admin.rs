@@ -0,0 +1,541 @@
+use std::time::{Duration, SystemTime};
+
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::future;
+use futures::prelude::*;
+use hyper::server::Request;
+use hyper::Method;
+
+use stq_http::controller::ControllerFuture;
+use stq_http::request_util::serialize_future;
+use stq_types::{SagaId, StoreId};
+
+use super::super::query::Query;
+use super::super::routes::Route;
+use super::super::{page_request, parse_body, saga_result, validate};
+use super::{Handler, HandlerContext};
+use audit::AuditLog;
+use build_info;
+use errors::Error;
+use jobs;
+use logging;
+use metrics;
+use microservice;
+use models::*;
+use saga::schema::SCHEMA_VERSION;
+use services::inventory::InventoryService;
+use services::order::OrderService;
+use services::support::SupportService;
+use services::takedown::StoreTakedownService;
+
+/// Jobs, sagas, billing replays, store takedowns, moderation queue, fraud overrides, audit, config, dependencies, readiness, build
+/// and inventory of the coordinator itself. Routes under `/admin` are served only to superadmin, metrics, readiness
+/// and build are served to anyone, as they are polled by infrastructure.
+pub struct AdminHandler;
+
+impl Handler for AdminHandler {
+    fn handle(&self, ctx: HandlerContext, req: Request, route: Route) -> Option<ControllerFuture> {
+        match route {
+            Route::Metrics | Route::Readyz | Route::About => {}
+            _ if !microservice::is_superadmin(req.headers()) => {
+                return Some(Box::new(future::err(
+                    format_err!("Route {} is served only to superadmin", route.name())
+                        .context(Error::Forbidden)
+                        .into(),
+                )));
+            }
+            _ => {}
+        }
+
+        let fut = match (&req.method().clone(), route) {
+            // GET /admin/jobs
+            (&Method::Get, Route::AdminJobs) => serialize_future(
+                jobs::jobs_info(&ctx.request.config)
+                    .map_err(|e| FailureError::from(e.context("Error fetching jobs occurred.")))
+                    .into_future(),
+            ),
+
+            // GET /admin/moderation/overdue?limit=<limit>&offset=<offset>&order=<asc|desc>
+            (&Method::Get, Route::AdminModerationOverdue) => {
+                let sla = Duration::from_secs(ctx.request.config.moderation.sla_s);
+                let moderation_queue = ctx.moderation_queue.clone();
+                serialize_future(
+                    page_request(&Query::parse(req.query()))
+                        .and_then(move |page| {
+                            moderation_queue.pending().map(|pending| {
+                                let now = SystemTime::now();
+                                let overdue = pending.into_iter().filter(|item| item.is_overdue(sla, now)).collect();
+                                page.paginate_by(overdue, |item| item.entered_at)
+                            })
+                        })
+                        .map_err(|e| FailureError::from(e.context("Error fetching items awaiting moderation occurred.")))
+                        .into_future(),
+                )
+            }
+
+            // GET /admin/moderation/deactivations?limit=<limit>&offset=<offset>&order=<asc|desc>
+            (&Method::Get, Route::AdminModerationDeactivations) => {
+                let moderation_queue = ctx.moderation_queue.clone();
+                serialize_future(
+                    page_request(&Query::parse(req.query()))
+                        .and_then(move |page| {
+                            moderation_queue
+                                .deactivations(None)
+                                .map(|deactivations| page.paginate_by(deactivations, |record| record.recorded_at))
+                        })
+                        .map_err(|e| FailureError::from(e.context("Error fetching moderation trail occurred.")))
+                        .into_future(),
+                )
+            }
+
+            // GET /admin/sagas?status=<status>,<status>&saga_type=<saga_type>&limit=<limit>&offset=<offset>&order=<asc|desc>
+            (&Method::Get, Route::AdminSagas) => {
+                let query = Query::parse(req.query());
+                let saga_store = ctx.saga_store.clone();
+                let filters = query
+                    .list::<SagaStatus>("status")
+                    .and_then(|statuses| query.get::<SagaType>("saga_type").map(|saga_type| (statuses, saga_type)));
+                serialize_future(
+                    filters
+                        .and_then(|filters| page_request(&query).map(|page| (filters, page)))
+                        .and_then(move |((statuses, saga_type), page)| {
+                            let statuses = if statuses.is_empty() { SagaStatus::ALL.to_vec() } else { statuses };
+                            saga_store.find_by_status(&statuses).map(|records| {
+                                let records = records
+                                    .into_iter()
+                                    .filter(|record| saga_type.map(|saga_type| record.saga_type == saga_type).unwrap_or(true))
+                                    .collect();
+                                page.paginate_by(records, |record| record.created_at)
+                            })
+                        })
+                        .map_err(|e| FailureError::from(e.context("Error fetching sagas occurred.")))
+                        .into_future(),
+                )
+            }
+
+            // GET /admin/sagas/orphaned?limit=<limit>&offset=<offset>&order=<asc|desc>
+            (&Method::Get, Route::AdminOrphanedSagas) => {
+                let saga_store = ctx.saga_store.clone();
+                serialize_future(
+                    page_request(&Query::parse(req.query()))
+                        .and_then(move |page| {
+                            saga_store
+                                .find_by_status(&[SagaStatus::RevertFailed, SagaStatus::Orphaned])
+                                .map(|records| page.paginate_by(records, |record| record.created_at))
+                        })
+                        .map_err(|e| FailureError::from(e.context("Error fetching orphaned sagas occurred.")))
+                        .into_future(),
+                )
+            }
+
+            // POST /admin/sagas/purge?finished_after_s=<seconds>&orphaned_after_s=<seconds>
+            // Params override `[saga.retention]`
+            (&Method::Post, Route::AdminSagasPurge) => {
+                let query = Query::parse(req.query());
+                let retention = ctx.request.config.saga.retention.clone();
+                let saga_store = ctx.saga_store.clone();
+                serialize_future(
+                    query
+                        .get::<u64>("finished_after_s")
+                        .and_then(|finished_after_s| {
+                            query
+                                .get::<u64>("orphaned_after_s")
+                                .map(|orphaned_after_s| (finished_after_s, orphaned_after_s))
+                        })
+                        .and_then(move |(finished_after_s, orphaned_after_s)| {
+                            let finished_after_s = finished_after_s.unwrap_or(retention.finished_after_s);
+                            let orphaned_after_s = orphaned_after_s.or(retention.orphaned_after_s);
+                            jobs::retention::purge(
+                                &*saga_store,
+                                Duration::from_secs(finished_after_s),
+                                orphaned_after_s.map(Duration::from_secs),
+                                SystemTime::now(),
+                            )
+                        })
+                        .map_err(|e| FailureError::from(e.context("Error purging sagas occurred.")))
+                        .into_future(),
+                )
+            }
+
+            // POST /admin/orders/update_state/replay
+            // Orders already in the received state are skipped, so replaying the same event twice changes nothing
+            (&Method::Post, Route::AdminOrdersUpdateStateReplay) => {
+                let order_service = ctx.order_service();
+                let billing_events = ctx.billing_events.clone();
+                serialize_future(
+                    parse_body::<BillingReplayInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: BillingReplayInput")))
+                        .and_then(move |input| match (input.event_id, input.orders) {
+                            (Some(event_id), None) => billing_events
+                                .get(&event_id)
+                                .map(|event| (Some(event_id.clone()), event.orders))
+                                .ok_or_else(|| {
+                                    format_err!("Billing event {} is not found among recent billing events", event_id)
+                                        .context(Error::NotFound)
+                                        .into()
+                                }),
+                            (None, Some(orders)) => Ok((None, orders)),
+                            _ => Err(Error::Validate(validation_errors!({
+                                "event_id": ["event_id" => "Either event_id or orders must be given"]
+                            }))
+                            .into()),
+                        })
+                        .and_then(move |(event_id, orders)| {
+                            let received = orders.len();
+                            order_service
+                                .replay_update_state_by_billing(BillingOrdersVec(orders))
+                                .map(move |(_, updated)| BillingReplayReport {
+                                    event_id,
+                                    received,
+                                    updated,
+                                })
+                                .map_err(|(_, e)| FailureError::from(e.context("Error replaying orders update by billing occurred.")))
+                        }),
+                )
+            }
+
+            // GET /admin/orders/<order_slug>/debug
+            // Bundle is gathered as far as microservices allow, parts that could not be gathered are listed in `errors`
+            (&Method::Get, Route::AdminOrderDebug(order_slug)) => serialize_future(
+                ctx.support_service()
+                    .order_bundle(order_slug)
+                    .map(|(_, bundle)| bundle)
+                    .map_err(|(_, e)| FailureError::from(e.context("Error gathering order debug bundle occurred."))),
+            ),
+
+            // POST /admin/stores/<store_id>/takedown
+            (&Method::Post, Route::AdminStoreTakedown(store_id)) => {
+                let version = ctx.version;
+                let takedown_service = ctx.takedown_service();
+                serialize_future(
+                    parse_body::<StoreTakedown>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: StoreTakedown")))
+                        .and_then(validate)
+                        .and_then(move |payload| {
+                            takedown_service
+                                .takedown_store(store_id, payload)
+                                .map(move |(_, result)| saga_result(version, result))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error taking down store occurred.")))
+                        }),
+                )
+            }
+
+            // GET /admin/sagas/<saga_id>
+            (&Method::Get, Route::AdminSaga(saga_id)) => serialize_future(
+                ctx.saga_store
+                    .get(saga_id)
+                    .and_then(|record| {
+                        record.ok_or_else(|| {
+                            format_err!("Saga {} is not found in saga store", saga_id)
+                                .context(Error::NotFound)
+                                .into()
+                        })
+                    })
+                    .map_err(|e| FailureError::from(e.context("Error fetching saga occurred.")))
+                    .into_future(),
+            ),
+
+            // GET /admin/sagas/<saga_id>/compensations
+            (&Method::Get, Route::AdminSagaCompensations(saga_id)) => serialize_future(
+                ctx.saga_store
+                    .get(saga_id)
+                    .and_then(|record| {
+                        record.map(|record| record.compensations).ok_or_else(|| {
+                            format_err!("Saga {} is not found in saga store", saga_id)
+                                .context(Error::NotFound)
+                                .into()
+                        })
+                    })
+                    .map_err(|e| FailureError::from(e.context("Error fetching saga compensations occurred.")))
+                    .into_future(),
+            ),
+
+            // GET /admin/fraud/overrides
+            (&Method::Get, Route::AdminFraudOverrides) => serialize_future(
+                ctx.fraud_overrides
+                    .list()
+                    .map_err(|e| FailureError::from(e.context("Error fetching fraud overrides occurred.")))
+                    .into_future(),
+            ),
+
+            // PUT /admin/fraud/overrides/<customer_id>
+            (&Method::Put, Route::AdminFraudOverride(customer_id)) => {
+                let fraud_overrides = ctx.fraud_overrides.clone();
+                serialize_future(
+                    parse_body::<FraudOverrideInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: FraudOverrideInput")))
+                        .and_then(move |input| {
+                            fraud_overrides
+                                .set(customer_id, input)
+                                .map_err(|e| FailureError::from(e.context("Error setting fraud override occurred.")))
+                        }),
+                )
+            }
+
+            // DELETE /admin/fraud/overrides/<customer_id>
+            (&Method::Delete, Route::AdminFraudOverride(customer_id)) => serialize_future(
+                ctx.fraud_overrides
+                    .remove(customer_id)
+                    .and_then(|removed| {
+                        removed.ok_or_else(|| {
+                            format_err!("Fraud override of customer {} is not found", customer_id)
+                                .context(Error::NotFound)
+                                .into()
+                        })
+                    })
+                    .map_err(|e| FailureError::from(e.context("Error removing fraud override occurred.")))
+                    .into_future(),
+            ),
+
+            // GET /admin/inventory/reconcile?store_id=<store_id>&fix=<fix>&tolerance=<tolerance>&limit=<limit>&offset=<offset>&order=<asc|desc>
+            // Discrepancies are sorted by their difference
+            (&Method::Get, Route::AdminInventoryReconcile) => {
+                let inventory_service = ctx.inventory_service();
+                let query = Query::parse(req.query());
+                let input = query.require::<StoreId>("store_id").and_then(|store_id| {
+                    query.flag("fix").and_then(|fix| {
+                        query.get::<u32>("tolerance").map(|tolerance| ReconcileInventory {
+                            store_id,
+                            fix,
+                            tolerance: tolerance.unwrap_or(0),
+                        })
+                    })
+                });
+                let input = input.and_then(|input| page_request(&query).map(|page| (input, page)));
+                serialize_future(input.into_future().and_then(move |(input, page)| {
+                    inventory_service
+                        .reconcile(input)
+                        .map(move |(_, report)| report.paginate(&page))
+                        .map_err(|(_, e)| FailureError::from(e.context("Error during inventory reconciliation occurred.")))
+                }))
+            }
+
+            // GET /admin/audit?saga_id=<saga_id>&limit=<limit>&offset=<offset>&order=<asc|desc>
+            (&Method::Get, Route::AdminAudit) => {
+                let query = Query::parse(req.query());
+                let audit_log = ctx.audit_log.clone();
+                let query = query
+                    .get::<SagaId>("saga_id")
+                    .and_then(|saga_id| page_request(&query).map(|page| (AuditQuery { saga_id, limit: None }, page)));
+                serialize_future(
+                    query
+                        .and_then(move |(query, page)| {
+                            audit_log
+                                .list(query)
+                                .map(|entries| page.paginate_by(entries, |entry| entry.recorded_at))
+                        })
+                        .map_err(|e| FailureError::from(e.context("Error fetching audit log occurred.")))
+                        .into_future(),
+                )
+            }
+
+            // GET /admin/config
+            // Secrets are serialized as references they are loaded from, literal values are redacted
+            (&Method::Get, Route::AdminConfig) => serialize_future(future::ok::<_, FailureError>(ctx.request.config.clone())),
+
+            // GET /admin/dependencies
+            (&Method::Get, Route::AdminDependencies) => serialize_future(future::ok::<_, FailureError>(ctx.monitor.report(&ctx.breakers))),
+
+            // GET /admin/shadow
+            (&Method::Get, Route::AdminShadow) => serialize_future(future::ok::<_, FailureError>(ctx.http_client.reports().get())),
+
+            // GET /admin/endpoints
+            (&Method::Get, Route::AdminEndpoints) => serialize_future(future::ok::<_, FailureError>(ctx.endpoints.states())),
+
+            // PUT /admin/endpoints
+            (&Method::Put, Route::AdminEndpoints) => {
+                let endpoints = ctx.endpoints.clone();
+                serialize_future(
+                    parse_body::<EndpointSwitchInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: EndpointSwitchInput")))
+                        .and_then(move |input| endpoints.set(&input.service, input.secondary_percent).map_err(FailureError::from)),
+                )
+            }
+
+            // GET /admin/log_level
+            (&Method::Get, Route::AdminLogLevel) => serialize_future(future::ok::<_, FailureError>(log_levels(&ctx))),
+
+            // PUT /admin/log_level
+            (&Method::Put, Route::AdminLogLevel) => serialize_future(
+                parse_body::<LogLevelInput>(req.body(), &ctx.headers, ctx.body_options)
+                    .map_err(|e| FailureError::from(e.context("Parsing body failed, target: LogLevelInput")))
+                    .and_then(move |input| {
+                        logging::parse_level(&input.level)
+                            .map_err(|_| Error::Validate(validation_errors!({"level": ["level" => "Unknown log level"]})).into())
+                    })
+                    .map(move |level| {
+                        logging::set_level(level);
+                        log_levels(&ctx)
+                    }),
+            ),
+
+            // GET /metrics
+            (&Method::Get, Route::Metrics) => Box::new(
+                metrics::render(
+                    &*ctx.saga_store,
+                    &*ctx.moderation_queue,
+                    &ctx.executor,
+                    &ctx.breakers,
+                    &ctx.margin,
+                    &ctx.request.config,
+                )
+                .map_err(|e| FailureError::from(e.context("Error rendering metrics occurred.")))
+                .into_future(),
+            ),
+
+            // GET /readyz
+            // Coordinator stays ready with open circuit breakers, degraded dependencies are listed in response
+            (&Method::Get, Route::Readyz) => {
+                let dependencies = ctx.breakers.health();
+                let status = if dependencies.iter().all(|health| health.state == BreakerState::Closed) {
+                    ReadinessStatus::Ready
+                } else {
+                    ReadinessStatus::Degraded
+                };
+                serialize_future(future::ok::<_, FailureError>(Readiness { status, dependencies }))
+            }
+
+            // GET /about
+            (&Method::Get, Route::About) => serialize_future(future::ok::<_, FailureError>(About {
+                version: build_info::VERSION.to_string(),
+                git_commit: build_info::GIT_COMMIT.map(|commit| commit.to_string()),
+                built_at: build_info::built_at(),
+                features: ctx.request.config.features.enabled(),
+                saga_log_schema_version: SCHEMA_VERSION,
+            })),
+
+            _ => return None,
+        };
+
+        Some(fut)
+    }
+}
+
+fn log_levels(ctx: &HandlerContext) -> LogLevels {
+    LogLevels {
+        level: logging::level().to_string().to_lowercase(),
+        modules: ctx.request.config.logging.modules.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    use futures::future;
+    use futures::prelude::*;
+    use hyper;
+    use hyper::header::{Authorization, ContentType};
+    use hyper::server::{Http, Request, Response, Service};
+    use hyper::Method;
+    use tokio_core::reactor::Core;
+
+    use stq_http::client::Client;
+    use stq_http::controller::Controller;
+    use stq_http::errors::ErrorMessageWrapper;
+
+    use config::Config;
+    use controller::ControllerImpl;
+    use errors::Error;
+    use microservice::{CassetteHttpClient, ChaosHttpClient, ShadowHttpClient};
+
+    /// Microservices that know no entities, calls are recorded along with their `Authorization`
+    struct EmptyMicroservices {
+        calls: Rc<RefCell<Vec<(String, Option<String>)>>>,
+    }
+
+    impl Service for EmptyMicroservices {
+        type Request = Request;
+        type Response = Response;
+        type Error = hyper::Error;
+        type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+        fn call(&self, req: Request) -> Self::Future {
+            let authorization = req.headers().get::<Authorization<String>>().map(|header| header.0.clone());
+            self.calls
+                .borrow_mut()
+                .push((format!("{} {}", req.method(), req.path()), authorization));
+            Box::new(future::ok(Response::new().with_header(ContentType::json()).with_body("null")))
+        }
+    }
+
+    /// Status of response to `GET path` made with `authorization`, along with calls made to microservices
+    fn get(path: &str, authorization: &str) -> (u16, Vec<(String, Option<String>)>) {
+        let mut core = Core::new().unwrap();
+        let handle = Arc::new(core.handle());
+        let calls = Rc::new(RefCell::new(vec![]));
+
+        let serve = Http::new()
+            .serve_addr_handle(&"127.0.0.1:0".parse().unwrap(), &*handle, {
+                let calls = calls.clone();
+                move || Ok(EmptyMicroservices { calls: calls.clone() })
+            })
+            .unwrap();
+        let url = format!("http://{}", serve.incoming_ref().local_addr());
+        handle.spawn(
+            serve
+                .for_each({
+                    let handle = handle.clone();
+                    move |conn| {
+                        handle.spawn(conn.map(|_| ()).map_err(|_| ()));
+                        Ok(())
+                    }
+                })
+                .map_err(|_| ()),
+        );
+
+        let mut config = Config::new().unwrap();
+        for (_, microservice) in config.microservices_mut() {
+            microservice.url = url.clone();
+            microservice.secondary_url = None;
+        }
+        config.chaos = None;
+        config.shadow = None;
+        config.saga.record_calls = false;
+
+        let client = Client::new(&config.to_http_config(), &handle);
+        let (http_client, _) = ShadowHttpClient::new(
+            ChaosHttpClient::new(CassetteHttpClient::new(client.handle(), None), &config),
+            &config,
+        );
+        handle.spawn(client.stream().for_each(|_| Ok(())));
+        let controller = ControllerImpl::in_memory(config, http_client, handle.clone()).unwrap();
+
+        let mut req = Request::new(Method::Get, path.parse().unwrap());
+        req.headers_mut().set(Authorization(authorization.to_string()));
+        let status = core
+            .run(controller.call(req).then(|res| {
+                Ok::<_, ()>(match res {
+                    Ok(response) => response.status().as_u16(),
+                    Err(e) => ErrorMessageWrapper::<Error>::from(&e).inner.code,
+                })
+            }))
+            .unwrap();
+        let calls = calls.borrow().clone();
+        (status, calls)
+    }
+
+    #[test]
+    fn gathers_order_debug_bundle_for_superadmin_only() {
+        let path = "/admin/orders/1042/debug";
+
+        // order is looked up with superadmin rights, it is not known to microservices
+        let (status, calls) = get(path, "1");
+        assert_eq!(status, 404);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].1.as_ref().map(String::as_str), Some("1"));
+
+        let (status, calls) = get(path, "42");
+        assert_eq!(status, 403);
+        assert!(calls.is_empty());
+    }
+
+    #[test]
+    fn serves_admin_routes_to_superadmin_only() {
+        assert_eq!(get("/admin/config", "42").0, 403);
+        assert_eq!(get("/about", "42").0, 200);
+    }
+}
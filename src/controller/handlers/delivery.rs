@@ -0,0 +1,112 @@
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use hyper::server::Request;
+use hyper::Method;
+
+use stq_http::controller::ControllerFuture;
+use stq_http::request_util::serialize_future;
+
+use super::super::routes::Route;
+use super::super::signature::{self, TRACKING_SIGNATURE_HEADER};
+use super::super::{deserialize, parse_body, read_body, validate};
+use super::{Handler, HandlerContext};
+use errors::Error;
+use models::*;
+use services::delivery::DeliveryService;
+use services::order::OrderService;
+
+/// Shipping of base products, delivery quotes and tracking updates of couriers
+pub struct DeliveryHandler;
+
+impl Handler for DeliveryHandler {
+    fn handle(&self, ctx: HandlerContext, req: Request, route: Route) -> Option<ControllerFuture> {
+        let fut = match (&req.method().clone(), route) {
+            // POST /base_products/<base_product_id>/upsert-shipping
+            (&Method::Post, Route::BaseProductUpsertShipping(base_product_id)) => {
+                let delivery_service = ctx.delivery_service();
+                serialize_future(
+                    parse_body::<NewShipping>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: NewShipping")))
+                        .and_then(move |payload| {
+                            delivery_service
+                                .upsert_shipping(base_product_id, payload)
+                                .map(|(_, shipping)| shipping)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error update shipping for base product occurred.")))
+                        }),
+                )
+            }
+
+            // POST /delivery/quote
+            (&Method::Post, Route::DeliveryQuote) => {
+                let delivery_service = ctx.delivery_service();
+                serialize_future(
+                    parse_body::<DeliveryQuoteInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: DeliveryQuoteInput")))
+                        .and_then(validate)
+                        .and_then(move |input| {
+                            delivery_service
+                                .quote(input)
+                                .map(|(_, quote)| quote)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during delivery quote occurred.")))
+                        }),
+                )
+            }
+
+            // POST /delivery/tracking_update
+            // Body is verified against its signature before parsing. Events that are already handled
+            // are acknowledged as duplicates, failed events are forgotten so that courier can retry them.
+            (&Method::Post, Route::DeliveryTrackingUpdate) => {
+                let order_service = ctx.order_service();
+                let tracking_events = ctx.tracking_events.clone();
+                let provided_signature = signature::signature(&ctx.headers, TRACKING_SIGNATURE_HEADER);
+                let headers = ctx.headers.clone();
+                let options = ctx.body_options;
+                let secret = match ctx.request.config.delivery_tracking {
+                    Some(ref tracking) => tracking
+                        .secret
+                        .value()
+                        .ok_or_else(|| format_err!("Secret of delivery tracking is not resolved")),
+                    None => Err(format_err!("Delivery tracking is not configured").context(Error::NotFound).into()),
+                };
+                serialize_future(
+                    future::result(secret)
+                        .and_then(move |secret| read_body(req.body(), &headers, options.limit).map(move |bytes| (secret, bytes)))
+                        .and_then(move |(secret, bytes)| {
+                            let verified = provided_signature
+                                .map(|provided| signature::verify(secret.as_bytes(), &bytes, &provided))
+                                .unwrap_or(false);
+                            if !verified {
+                                return Err(format_err!("Signature of tracking update is missing or invalid")
+                                    .context(Error::Forbidden)
+                                    .into());
+                            }
+                            deserialize::<TrackingUpdate>(&bytes, options.strict)
+                                .map_err(|e| FailureError::from(e.context("Parsing body failed, target: TrackingUpdate")))
+                        })
+                        .and_then(move |update| {
+                            let event_id = update.event_id.clone();
+                            if !tracking_events.begin(&event_id) {
+                                return Either::A(future::ok(TrackingUpdateResult {
+                                    event_id,
+                                    outcome: TrackingOutcome::Duplicate,
+                                }));
+                            }
+                            Either::B(order_service.apply_tracking_update(update).then(move |res| match res {
+                                Ok((_, outcome)) => Ok(TrackingUpdateResult { event_id, outcome }),
+                                Err((_, e)) => {
+                                    tracking_events.forget(&event_id);
+                                    Err(FailureError::from(e.context("Error during tracking update occurred.")))
+                                }
+                            }))
+                        }),
+                )
+            }
+
+            _ => return None,
+        };
+
+        Some(fut)
+    }
+}
@@ -0,0 +1,532 @@
+//! Handlers of routes grouped by domain. Every route belongs to a domain
+//! (see `Route::domain`), requests are dispatched to the handler registered
+//! for it, so that each handler builds only services it uses.
+pub mod accounts;
+pub mod admin;
+pub mod delivery;
+pub mod orders;
+pub mod stores;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use hyper::header::Headers;
+use hyper::server::Request;
+use tokio_core::reactor::Handle;
+
+use stq_http::client::HttpClientWithDefaultHeaders;
+use stq_http::controller::ControllerFuture;
+
+use super::context::RequestContext;
+use super::endpoints::EndpointSwitch;
+use super::margin::ProcessingMargin;
+use super::routes::{ApiVersion, Domain, Route};
+use super::BodyOptions;
+use acknowledgment::AcknowledgmentTimers;
+use audit::{AuditLog, AuditScope};
+use billing_events::BillingEventLog;
+use fraud::{FraudOverrides, FraudScreener};
+use microservice::*;
+use moderation::ModerationQueue;
+use saga::{SagaExecutor, SagaStore};
+use services::account::AccountServiceImpl;
+use services::cancellation::OrderCancellationServiceImpl;
+use services::catalog::CatalogServiceImpl;
+use services::category::CategoryChangeServiceImpl;
+use services::delivery::DeliveryServiceImpl;
+use services::dispute::DisputeServiceImpl;
+use services::inventory::InventoryServiceImpl;
+use services::order::OrderServiceImpl;
+use services::order_return::OrderReturnServiceImpl;
+use services::payout::PayoutServiceImpl;
+use services::pricing::PricingServiceImpl;
+use services::store::StoreServiceImpl;
+use services::support::SupportServiceImpl;
+use services::takedown::StoreTakedownServiceImpl;
+use services::vacation::StoreVacationServiceImpl;
+use services::variants::VariantsServiceImpl;
+use services::verification::StoreVerificationServiceImpl;
+use tracking_events::TrackingEventLog;
+use vacations::StoreVacations;
+
+pub trait Handler {
+    /// Serves request to a route of handler domain, `None` if the route is not served with method of the request
+    fn handle(&self, ctx: HandlerContext, req: Request, route: Route) -> Option<ControllerFuture>;
+}
+
+/// Handlers by domain of routes they serve
+pub struct Handlers {
+    handlers: HashMap<Domain, Box<Handler>>,
+}
+
+impl Handlers {
+    pub fn new() -> Self {
+        let mut handlers = Self { handlers: HashMap::new() };
+        handlers.register(Domain::Accounts, accounts::AccountsHandler);
+        handlers.register(Domain::Stores, stores::StoresHandler);
+        handlers.register(Domain::Orders, orders::OrdersHandler);
+        handlers.register(Domain::Delivery, delivery::DeliveryHandler);
+        handlers.register(Domain::Admin, admin::AdminHandler);
+        handlers
+    }
+
+    /// Registers handler of the domain, replacing the previous one
+    pub fn register<H: Handler + 'static>(&mut self, domain: Domain, handler: H) {
+        self.handlers.insert(domain, Box::new(handler));
+    }
+
+    pub fn handle(&self, ctx: HandlerContext, req: Request, route: Route) -> Option<ControllerFuture> {
+        self.handlers
+            .get(&route.domain())
+            .and_then(|handler| handler.handle(ctx, req, route))
+    }
+}
+
+impl Default for Handlers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Everything handlers need to serve the request. Microservices and services
+/// are built on demand, calls to microservices are made on behalf of the caller.
+#[derive(Clone)]
+pub struct HandlerContext {
+    pub request: RequestContext,
+    /// Headers of the request, needed to read its body
+    pub headers: Headers,
+    pub version: ApiVersion,
+    pub body_options: BodyOptions,
+    pub http_client: BaseHttpClient,
+    pub handle: Arc<Handle>,
+    pub saga_store: Arc<SagaStore>,
+    pub moderation_queue: Arc<ModerationQueue>,
+    pub acknowledgment_timers: Arc<AcknowledgmentTimers>,
+    pub vacations: Arc<StoreVacations>,
+    pub fraud_overrides: Arc<FraudOverrides>,
+    pub audit_log: Arc<AuditLog>,
+    pub billing_events: Arc<BillingEventLog>,
+    pub tracking_events: Arc<TrackingEventLog>,
+    /// Audit of superadmin calls made for the request
+    pub audit: AuditScope,
+    /// Executes sagas started in background
+    pub executor: SagaExecutor,
+    pub breakers: CircuitBreakers,
+    pub monitor: DependencyMonitor,
+    pub margin: ProcessingMargin,
+    pub endpoints: EndpointSwitch,
+}
+
+impl HandlerContext {
+    pub fn orders_microservice(&self) -> Arc<OrdersMicroservice> {
+        Arc::new(
+            OrdersMicroserviceImpl::new(
+                HttpClientWithDefaultHeaders::new(
+                    BudgetedHttpClient::new(self.http_client.clone(), self.request.budget.clone()),
+                    self.request.currency_headers(),
+                ),
+                self.request.config.clone(),
+            )
+            .with_audit(self.audit.clone())
+            .with_monitor(self.monitor.clone()),
+        )
+    }
+
+    pub fn stores_microservice(&self) -> Arc<StoresMicroservice> {
+        Arc::new(
+            StoresMicroserviceImpl::new(
+                HttpClientWithDefaultHeaders::new(
+                    BudgetedHttpClient::new(self.http_client.clone(), self.request.budget.clone()),
+                    self.request.stores_headers(),
+                ),
+                self.request.config.clone(),
+            )
+            .with_audit(self.audit.clone())
+            .with_monitor(self.monitor.clone()),
+        )
+    }
+
+    pub fn notifications_microservice(&self) -> Arc<NotificationsMicroservice> {
+        Arc::new(
+            NotificationsMicroserviceImpl::new(
+                HttpClientWithDefaultHeaders::new(
+                    BudgetedHttpClient::new(self.http_client.clone(), self.request.budget.clone()),
+                    self.request.default_headers(),
+                ),
+                self.request.config.clone(),
+            )
+            .with_audit(self.audit.clone())
+            .with_monitor(self.monitor.clone())
+            .with_breakers(self.breakers.clone()),
+        )
+    }
+
+    pub fn users_microservice(&self) -> Arc<UsersMicroservice> {
+        Arc::new(
+            UsersMicroserviceImpl::new(
+                HttpClientWithDefaultHeaders::new(
+                    BudgetedHttpClient::new(self.http_client.clone(), self.request.budget.clone()),
+                    self.request.default_headers(),
+                ),
+                self.request.config.clone(),
+            )
+            .with_audit(self.audit.clone())
+            .with_monitor(self.monitor.clone()),
+        )
+    }
+
+    pub fn billing_microservice(&self) -> Arc<BillingMicroservice> {
+        Arc::new(
+            BillingMicroserviceImpl::new(
+                HttpClientWithDefaultHeaders::new(
+                    BudgetedHttpClient::new(self.http_client.clone(), self.request.budget.clone()),
+                    self.request.currency_headers(),
+                ),
+                self.request.config.clone(),
+            )
+            .with_audit(self.audit.clone())
+            .with_monitor(self.monitor.clone()),
+        )
+    }
+
+    pub fn warehouses_microservice(&self) -> Arc<WarehousesMicroservice> {
+        Arc::new(
+            WarehousesMicroserviceImpl::new(
+                HttpClientWithDefaultHeaders::new(
+                    BudgetedHttpClient::new(self.http_client.clone(), self.request.budget.clone()),
+                    self.request.default_headers(),
+                ),
+                self.request.config.clone(),
+            )
+            .with_audit(self.audit.clone())
+            .with_monitor(self.monitor.clone()),
+        )
+    }
+
+    pub fn delivery_microservice(&self) -> Arc<DeliveryMicroservice> {
+        Arc::new(
+            DeliveryMicroserviceImpl::new(
+                HttpClientWithDefaultHeaders::new(
+                    BudgetedHttpClient::new(self.http_client.clone(), self.request.budget.clone()),
+                    self.request.default_headers(),
+                ),
+                self.request.config.clone(),
+            )
+            .with_audit(self.audit.clone())
+            .with_monitor(self.monitor.clone()),
+        )
+    }
+
+    /// Screening of checkouts, `None` if it is not configured
+    pub fn fraud_screener(&self) -> Option<FraudScreener> {
+        self.request.config.fraud_screening.clone().map(|fraud_config| FraudScreener {
+            scoring: Arc::new(
+                FraudScoringMicroserviceImpl::new(
+                    BudgetedHttpClient::new(self.http_client.clone(), self.request.budget.clone()),
+                    &self.request.config,
+                    fraud_config.url.clone(),
+                )
+                .with_monitor(self.monitor.clone()),
+            ),
+            config: fraud_config,
+            overrides: self.fraud_overrides.clone(),
+        })
+    }
+
+    /// Static files service, `None` if it is not configured
+    pub fn files_microservice(&self) -> Option<Arc<FilesMicroservice>> {
+        self.request.config.files.as_ref().map(|files_config| {
+            Arc::new(
+                FilesMicroserviceImpl::new(
+                    BudgetedHttpClient::new(self.http_client.clone(), self.request.budget.clone()),
+                    &self.request.config,
+                    files_config.url.clone(),
+                )
+                .with_audit(self.audit.clone())
+                .with_monitor(self.monitor.clone()),
+            ) as Arc<FilesMicroservice>
+        })
+    }
+
+    pub fn account_service(&self) -> AccountServiceImpl {
+        let service = AccountServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.stores_microservice(),
+            self.billing_microservice(),
+            self.delivery_microservice(),
+            self.users_microservice(),
+            self.notifications_microservice(),
+            self.request.locale.clone(),
+        )
+        .with_caller(self.request.initiator);
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn store_service(&self) -> StoreServiceImpl {
+        let service = StoreServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.moderation_queue.clone(),
+            self.orders_microservice(),
+            self.stores_microservice(),
+            self.notifications_microservice(),
+            self.billing_microservice(),
+            self.warehouses_microservice(),
+            self.users_microservice(),
+            self.delivery_microservice(),
+        )
+        .with_caller(self.request.initiator);
+        let service = match self.files_microservice() {
+            Some(files_microservice) => service.with_files_microservice(files_microservice),
+            None => service,
+        };
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn order_service(&self) -> OrderServiceImpl {
+        let service = OrderServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.orders_microservice(),
+            self.stores_microservice(),
+            self.notifications_microservice(),
+            self.users_microservice(),
+            self.billing_microservice(),
+            self.warehouses_microservice(),
+            self.delivery_microservice(),
+            self.fraud_screener(),
+        )
+        .with_caller(self.request.initiator);
+        let service = match self.request.config.order_acknowledgment {
+            Some(_) => service.with_acknowledgment_timers(self.acknowledgment_timers.clone()),
+            None => service,
+        };
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn delivery_service(&self) -> DeliveryServiceImpl {
+        let service = DeliveryServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.orders_microservice(),
+            self.delivery_microservice(),
+            self.stores_microservice(),
+        );
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn verification_service(&self) -> StoreVerificationServiceImpl {
+        let service = StoreVerificationServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.stores_microservice(),
+            self.billing_microservice(),
+            self.users_microservice(),
+            self.notifications_microservice(),
+        );
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn takedown_service(&self) -> StoreTakedownServiceImpl {
+        let service = StoreTakedownServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.audit.clone(),
+            self.orders_microservice(),
+            self.stores_microservice(),
+            self.users_microservice(),
+            self.notifications_microservice(),
+        )
+        .with_caller(self.request.initiator);
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn vacation_service(&self) -> StoreVacationServiceImpl {
+        let service = StoreVacationServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.vacations.clone(),
+            self.orders_microservice(),
+            self.stores_microservice(),
+            self.users_microservice(),
+            self.notifications_microservice(),
+        );
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn category_service(&self) -> CategoryChangeServiceImpl {
+        let service = CategoryChangeServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.orders_microservice(),
+            self.stores_microservice(),
+            self.delivery_microservice(),
+            self.users_microservice(),
+            self.notifications_microservice(),
+        );
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn variants_service(&self) -> VariantsServiceImpl {
+        let service = VariantsServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.orders_microservice(),
+            self.stores_microservice(),
+            self.warehouses_microservice(),
+            self.users_microservice(),
+            self.notifications_microservice(),
+        );
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn payout_service(&self) -> PayoutServiceImpl {
+        let service = PayoutServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.stores_microservice(),
+            self.billing_microservice(),
+            self.notifications_microservice(),
+        )
+        .with_caller(self.request.initiator);
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn pricing_service(&self) -> PricingServiceImpl {
+        let service = PricingServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.orders_microservice(),
+            self.stores_microservice(),
+            self.users_microservice(),
+            self.notifications_microservice(),
+        );
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn support_service(&self) -> SupportServiceImpl {
+        SupportServiceImpl::new(
+            self.orders_microservice(),
+            self.billing_microservice(),
+            self.warehouses_microservice(),
+            self.saga_store.clone(),
+        )
+        .with_caller(self.request.initiator)
+    }
+
+    pub fn inventory_service(&self) -> InventoryServiceImpl {
+        InventoryServiceImpl::new(
+            self.request.config.clone(),
+            self.stores_microservice(),
+            self.warehouses_microservice(),
+        )
+    }
+
+    /// Catalog import outlives the request, so it is limited by its own deadline instead of request timeout
+    pub fn catalog_service(&self) -> CatalogServiceImpl {
+        let mut ctx = self.clone();
+        ctx.request.budget = Budget::new(
+            Instant::now() + Duration::from_millis(self.request.config.catalog_import.deadline_ms),
+            Duration::from_millis(self.request.config.client.http_timeout_ms),
+        );
+        let service = CatalogServiceImpl::new(
+            ctx.request.config.clone(),
+            ctx.saga_store.clone(),
+            ctx.stores_microservice(),
+            ctx.delivery_microservice(),
+            ctx.warehouses_microservice(),
+        );
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(ctx.request.budget);
+        service
+    }
+
+    pub fn dispute_service(&self) -> DisputeServiceImpl {
+        let service = DisputeServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.orders_microservice(),
+            self.billing_microservice(),
+            self.warehouses_microservice(),
+            self.stores_microservice(),
+            self.users_microservice(),
+            self.notifications_microservice(),
+        )
+        .with_caller(self.request.initiator);
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn order_return_service(&self) -> OrderReturnServiceImpl {
+        let service = OrderReturnServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.orders_microservice(),
+            self.billing_microservice(),
+            self.warehouses_microservice(),
+            self.stores_microservice(),
+            self.users_microservice(),
+            self.notifications_microservice(),
+        )
+        .with_caller(self.request.initiator);
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+
+    pub fn cancellation_service(&self) -> OrderCancellationServiceImpl {
+        let service = OrderCancellationServiceImpl::new(
+            self.request.config.clone(),
+            self.saga_store.clone(),
+            self.orders_microservice(),
+            self.billing_microservice(),
+            self.warehouses_microservice(),
+            self.stores_microservice(),
+            self.users_microservice(),
+            self.notifications_microservice(),
+        )
+        .with_caller(self.request.initiator);
+        self.audit.bind_saga(service.log.saga_id());
+        service.log.bind_budget(self.request.budget.clone());
+        service.log.bind_endpoints(self.request.endpoints.clone());
+        service
+    }
+}
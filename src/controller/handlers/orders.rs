@@ -0,0 +1,205 @@
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::prelude::*;
+use hyper::server::Request;
+use hyper::Method;
+
+use stq_http::controller::ControllerFuture;
+use stq_http::request_util::serialize_future;
+
+use super::super::json_stream::parse_array_stream;
+use super::super::routes::Route;
+use super::super::{parse_body, saga_result, validate};
+use super::{Handler, HandlerContext};
+use models::*;
+use services::cancellation::OrderCancellationService;
+use services::dispute::DisputeService;
+use services::order::OrderService;
+use services::order_return::OrderReturnService;
+
+/// Checkouts, order states, cancellations, disputes and returns
+pub struct OrdersHandler;
+
+impl Handler for OrdersHandler {
+    fn handle(&self, ctx: HandlerContext, req: Request, route: Route) -> Option<ControllerFuture> {
+        let version = ctx.version;
+
+        let fut = match (&req.method().clone(), route) {
+            (&Method::Post, Route::CreateOrder) => {
+                let order_service = ctx.order_service();
+                serialize_future(
+                    parse_body::<ConvertCart>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: ConvertCart")))
+                        .and_then(validate)
+                        .and_then(move |new_order| {
+                            order_service
+                                .create(new_order)
+                                .map(move |(_, invoice)| saga_result(version, invoice))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during order creation occurred.")))
+                        }),
+                )
+            }
+
+            (&Method::Post, Route::BuyNow) => {
+                let order_service = ctx.order_service();
+                serialize_future(
+                    parse_body::<BuyNow>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body // POST /buy_now in BuyNow failed!")))
+                        .and_then(validate)
+                        .and_then(move |new_buy_now| {
+                            order_service
+                                .create_buy_now(new_buy_now)
+                                .map(move |(_, invoice)| saga_result(version, invoice))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during order creation from buy now data occurred.")))
+                        }),
+                )
+            }
+
+            // Received order states are kept by `X-Request-Id` of the call, so that they can be replayed by admin.
+            // Call reusing id of a recent call is rejected with conflict before anything is applied.
+            // Large batches are processed as they arrive, elements applied before a failure are reported in the response
+            (&Method::Post, Route::OrdersUpdateStateByBilling) => {
+                let order_service = ctx.order_service();
+                let billing_events = ctx.billing_events.clone();
+                let event_id = ctx.request.request_id.clone().unwrap_or_default();
+                let opened = billing_events.open(&event_id);
+                let orders_info = parse_array_stream::<BillingOrderInfo>(req.body(), &ctx.headers, ctx.body_options)
+                    .map_err(|e| FailureError::from(e.context("Parsing body // POST /orders/update_state in BillingOrdersVec failed!")))
+                    .inspect(move |order_info| billing_events.record(&event_id, vec![order_info.clone()]));
+                serialize_future(opened.into_future().and_then(move |_| {
+                    order_service
+                        .update_state_by_billing_stream(Box::new(orders_info))
+                        .map(|(_, report)| report)
+                        .map_err(|(_, e)| FailureError::from(e.context("Error during orders update by external billing occurred.")))
+                }))
+            }
+
+            (&Method::Post, Route::OrdersManualSetState { order_slug }) => {
+                let order_service = ctx.order_service();
+                serialize_future(
+                    parse_body::<SetStateInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(move |e| {
+                            FailureError::from(e.context(format!(
+                                "Parsing body // POST /orders/{}/set_state in SetStateInput failed!",
+                                order_slug
+                            )))
+                        })
+                        .and_then(move |payload| {
+                            order_service
+                                .manual_set_state(order_slug, payload.state, payload.track_id, payload.comment)
+                                .map(|(_, order)| order)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during orders manual update occurred.")))
+                        }),
+                )
+            }
+
+            (&Method::Post, Route::OrdersSetPaymentState { order_id }) => {
+                let order_service = ctx.order_service();
+                serialize_future(
+                    parse_body::<OrderPaymentStateRequest>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(move |e| FailureError::from(e.context("Parsing body failed, target: OrderPaymentStateRequest")))
+                        .and_then(move |payload| {
+                            order_service
+                                .manual_set_payment_state(order_id, payload)
+                                .map(|_| ())
+                                .map_err(|(_, e)| {
+                                    FailureError::from(e.context("Error during orders manual payment state update occurred."))
+                                })
+                        }),
+                )
+            }
+
+            // POST /orders/<order_id>/dispute
+            (&Method::Post, Route::OrderDispute { order_id }) => {
+                let dispute_service = ctx.dispute_service();
+                serialize_future(
+                    parse_body::<DisputeInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: DisputeInput")))
+                        .and_then(validate)
+                        .and_then(move |input| {
+                            dispute_service
+                                .open_dispute(order_id, input)
+                                .map(move |(_, dispute)| saga_result(version, dispute))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during dispute opening occurred.")))
+                        }),
+                )
+            }
+
+            // POST /orders/<order_id>/dispute/resolve
+            (&Method::Post, Route::OrderDisputeResolve { order_id }) => {
+                let dispute_service = ctx.dispute_service();
+                serialize_future(
+                    parse_body::<DisputeResolveInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: DisputeResolveInput")))
+                        .and_then(move |input| {
+                            dispute_service
+                                .resolve_dispute(order_id, input)
+                                .map(move |(_, order)| saga_result(version, order))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during dispute resolution occurred.")))
+                        }),
+                )
+            }
+
+            // POST /orders/<order_slug>/return
+            (&Method::Post, Route::OrderReturn { order_slug }) => {
+                let order_return_service = ctx.order_return_service();
+                serialize_future(
+                    parse_body::<OrderReturnInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: OrderReturnInput")))
+                        .and_then(validate)
+                        .and_then(move |input| {
+                            order_return_service
+                                .request_return(order_slug, input)
+                                .map(move |(_, order_return)| saga_result(version, order_return))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during order return request occurred.")))
+                        }),
+                )
+            }
+
+            // POST /orders/<order_slug>/return/receive
+            (&Method::Post, Route::OrderReturnReceive { order_slug }) => serialize_future(
+                ctx.order_return_service()
+                    .receive_return(order_slug)
+                    .map(move |(_, order_return)| saga_result(version, order_return))
+                    .map_err(|(_, e)| FailureError::from(e.context("Error during order return receipt occurred."))),
+            ),
+
+            // POST /orders/<order_slug>/cancel
+            (&Method::Post, Route::OrderCancelItem { order_slug }) => {
+                let cancellation_service = ctx.cancellation_service();
+                serialize_future(
+                    parse_body::<OrderItemCancelInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: OrderItemCancelInput")))
+                        .and_then(validate)
+                        .and_then(move |input| {
+                            cancellation_service
+                                .cancel_item(order_slug, input)
+                                .map(move |(_, order)| saga_result(version, order))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during order cancellation occurred.")))
+                        }),
+                )
+            }
+
+            // POST /orders/cancel
+            // Orders are taken out of their invoice with a single amendment
+            (&Method::Post, Route::OrderCancelItems) => {
+                let cancellation_service = ctx.cancellation_service();
+                serialize_future(
+                    parse_body::<OrderItemsCancelInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: OrderItemsCancelInput")))
+                        .and_then(validate)
+                        .and_then(move |input| {
+                            cancellation_service
+                                .cancel_items(input)
+                                .map(move |(_, orders)| saga_result(version, orders))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during orders cancellation occurred.")))
+                        }),
+                )
+            }
+
+            _ => return None,
+        };
+
+        Some(fut)
+    }
+}
@@ -0,0 +1,378 @@
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::prelude::*;
+use hyper::server::Request;
+use hyper::Method;
+use serde_json;
+
+use stq_http::controller::ControllerFuture;
+use stq_http::request_util::serialize_future;
+
+use super::super::routes::Route;
+use super::super::{if_match_version, parse_body, parse_catalog, parse_optional_body, saga_result, validate};
+use super::{Handler, HandlerContext};
+use errors::Error;
+use microservice::StoresMicroservice;
+use models::*;
+use services::catalog::CatalogService;
+use services::category::CategoryChangeService;
+use services::payout::PayoutService;
+use services::pricing::PricingService;
+use services::store::StoreService;
+use services::vacation::StoreVacationService;
+use services::variants::VariantsService;
+use services::verification::StoreVerificationService;
+
+/// Stores, their catalogs, base products and products
+pub struct StoresHandler;
+
+impl Handler for StoresHandler {
+    fn handle(&self, ctx: HandlerContext, req: Request, route: Route) -> Option<ControllerFuture> {
+        let version = ctx.version;
+
+        let fut = match (&req.method().clone(), route) {
+            (&Method::Post, Route::CreateStore) => {
+                let store_service = ctx.store_service();
+                serialize_future(
+                    parse_body::<NewStore>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body // POST /create_store in NewStore failed!")))
+                        .and_then(validate)
+                        .and_then(move |store| {
+                            store_service
+                                .create(store)
+                                .map(move |(_, store)| saga_result(version, store))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during store creation occurred.")))
+                        }),
+                )
+            }
+
+            // POST /stores/moderate
+            (&Method::Post, Route::StoreModerate) => {
+                let store_service = ctx.store_service();
+                let if_match = if_match_version(&ctx.headers);
+                serialize_future(
+                    parse_body::<StoreModerate>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: StoreModerate")))
+                        .and_then(move |mut store_moderate| {
+                            store_moderate.version = store_moderate.version.or(if_match);
+                            store_service
+                                .set_store_moderation_status(store_moderate)
+                                .map(|(_, result)| result)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during change store status occurred.")))
+                        }),
+                )
+            }
+
+            // POST /stores/moderation
+            (&Method::Post, Route::StoreModeration(store_id)) => serialize_future(
+                ctx.store_service()
+                    .send_to_moderation(store_id)
+                    .map(|(_, store)| store)
+                    .map_err(|(_, e)| FailureError::from(e.context("Error sending store to moderation occurred."))),
+            ),
+
+            // POST /stores/<store_id>/activate
+            (&Method::Post, Route::StoreActivate(store_id)) => {
+                let store_service = ctx.store_service();
+                serialize_future(
+                    parse_optional_body::<Activation>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: Activation")))
+                        .and_then(move |payload| {
+                            store_service
+                                .activate_store(store_id, payload)
+                                .map(|(_, result)| result)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error activating store occurred.")))
+                        }),
+                )
+            }
+
+            // POST /stores/<store_id>/deactivate
+            (&Method::Post, Route::StoreDeactivate(store_id)) => {
+                let store_service = ctx.store_service();
+                serialize_future(
+                    parse_optional_body::<Deactivation>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: Deactivation")))
+                        .and_then(move |payload| {
+                            store_service
+                                .deactivate_store(store_id, payload)
+                                .map(|(_, result)| result)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error deactivating store occurred.")))
+                        }),
+                )
+            }
+
+            // POST /stores/<store_id>/verify
+            (&Method::Post, Route::StoreVerify(store_id)) => serialize_future(
+                ctx.verification_service()
+                    .verify_store(store_id)
+                    .map(move |(_, store)| saga_result(version, store))
+                    .map_err(|(_, e)| FailureError::from(e.context("Error during store verification occurred."))),
+            ),
+
+            // POST /stores/<store_id>/payouts
+            (&Method::Post, Route::StoreCreatePayout(store_id)) => {
+                let payout_service = ctx.payout_service();
+                serialize_future(
+                    parse_body::<PayoutInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: PayoutInput")))
+                        .and_then(validate)
+                        .and_then(move |input| {
+                            payout_service
+                                .create_payout(store_id, input)
+                                .map(move |(_, payout)| saga_result(version, payout))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during payout creation occurred.")))
+                        }),
+                )
+            }
+
+            // POST /stores/<store_id>/reprice
+            (&Method::Post, Route::StoreReprice(store_id)) => {
+                let pricing_service = ctx.pricing_service();
+                serialize_future(
+                    parse_body::<RepriceInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: RepriceInput")))
+                        .and_then(validate)
+                        .and_then(move |input| {
+                            pricing_service
+                                .reprice(store_id, input)
+                                .map(move |(_, result)| saga_result(version, result))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during products repricing occurred.")))
+                        }),
+                )
+            }
+
+            // POST /stores/<store_id>/vacation
+            (&Method::Post, Route::StoreVacation(store_id)) => {
+                let vacation_service = ctx.vacation_service();
+                serialize_future(
+                    parse_body::<StoreVacationInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: StoreVacationInput")))
+                        .and_then(move |input| {
+                            vacation_service
+                                .start_vacation(store_id, input)
+                                .map(move |(_, result)| saga_result(version, result))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error putting store on vacation occurred.")))
+                        }),
+                )
+            }
+
+            // PUT /stores/<store_id>/draft
+            // Dashboard autosaves drafts every few seconds, so they go straight to stores without setting up sagas
+            (&Method::Put, Route::StoreDraft(store_id)) => {
+                let stores_microservice = ctx.stores_microservice();
+                serialize_future(
+                    parse_body::<serde_json::Value>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: store draft")))
+                        .and_then(move |draft| {
+                            stores_microservice
+                                .update_store_draft(None, store_id, draft)
+                                .map_err(|e| FailureError::from(e.context("Error during store draft update occurred.")))
+                        }),
+                )
+            }
+
+            // POST /stores/<store_id>/catalog/import
+            (&Method::Post, Route::StoreCatalogImport(store_id)) => {
+                let catalog_service = ctx.catalog_service();
+                let executor = ctx.executor.clone();
+                serialize_future(
+                    parse_catalog(
+                        req.body(),
+                        &ctx.headers,
+                        ctx.body_options,
+                        ctx.request.config.catalog_import.max_rows,
+                    )
+                    .map_err(|e| FailureError::from(e.context("Parsing body failed, target: CatalogRow")))
+                    .map(move |rows| {
+                        let saga_id = catalog_service.log.saga_id();
+                        let accepted = CatalogImportAccepted {
+                            saga_id,
+                            rows: rows.len(),
+                            report_url: format!("/catalog/imports/{}", saga_id),
+                        };
+                        // bulk import must not delay checkouts executed in background
+                        executor.spawn(
+                            SagaPriority::Housekeeping,
+                            catalog_service.import(store_id, rows).then(move |res| {
+                                match res {
+                                    Ok(_) => info!("Catalog import {} into store {} finished", saga_id, store_id),
+                                    Err((_, e)) => error!("Catalog import {} into store {} failed: {}", saga_id, store_id, e),
+                                }
+                                Ok::<_, ()>(())
+                            }),
+                        );
+                        accepted
+                    }),
+                )
+            }
+
+            // GET /catalog/imports/<saga_id>
+            (&Method::Get, Route::CatalogImport(saga_id)) => serialize_future(
+                ctx.saga_store
+                    .get(saga_id)
+                    .and_then(|record| {
+                        record.as_ref().and_then(CatalogImportReport::from_record).ok_or_else(|| {
+                            format_err!("Catalog import {} is not found in saga store", saga_id)
+                                .context(Error::NotFound)
+                                .into()
+                        })
+                    })
+                    .map_err(|e| FailureError::from(e.context("Error fetching catalog import occurred.")))
+                    .into_future(),
+            ),
+
+            // POST /base_products/moderate
+            (&Method::Post, Route::BaseProductModerate) => {
+                let store_service = ctx.store_service();
+                let if_match = if_match_version(&ctx.headers);
+                serialize_future(
+                    parse_body::<BaseProductModerate>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: BaseProductModerate")))
+                        .and_then(move |mut base_product_moderate| {
+                            base_product_moderate.version = base_product_moderate.version.or(if_match);
+                            store_service
+                                .set_moderation_status_base_product(base_product_moderate)
+                                .map(|(_, result)| result)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error change base product status occurred.")))
+                        }),
+                )
+            }
+
+            // POST /base_products/moderation
+            (&Method::Post, Route::BaseProductModeration(base_product_id)) => serialize_future(
+                ctx.store_service()
+                    .send_to_moderation_base_product(base_product_id)
+                    .map(|(_, _)| ())
+                    .map_err(|(_, e)| FailureError::from(e.context("Error sending base product to moderation occurred."))),
+            ),
+
+            // POST /base_products/<base_product_id>/change_category
+            (&Method::Post, Route::BaseProductChangeCategory(base_product_id)) => {
+                let category_service = ctx.category_service();
+                let if_match = if_match_version(&ctx.headers);
+                serialize_future(
+                    parse_body::<ChangeCategoryInput>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: ChangeCategoryInput")))
+                        .and_then(move |mut input| {
+                            input.version = input.version.or(if_match);
+                            category_service
+                                .change_category(base_product_id, input)
+                                .map(move |(_, result)| saga_result(version, result))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error changing category of base product occurred.")))
+                        }),
+                )
+            }
+
+            // POST /base_products/<base_product_id>/variants/bulk
+            (&Method::Post, Route::BaseProductVariantsBulk(base_product_id)) => {
+                let variants_service = ctx.variants_service();
+                serialize_future(
+                    parse_body::<VariantsBulkEdit>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: VariantsBulkEdit")))
+                        .and_then(move |input| {
+                            variants_service
+                                .bulk_edit(base_product_id, input)
+                                .map(move |(_, result)| saga_result(version, result))
+                                .map_err(|(_, e)| FailureError::from(e.context("Error during bulk edit of variants occurred.")))
+                        }),
+                )
+            }
+
+            // POST /base_products/<base_product_id>/activate
+            (&Method::Post, Route::BaseProductActivate(base_product_id)) => {
+                let store_service = ctx.store_service();
+                serialize_future(
+                    parse_optional_body::<Activation>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: Activation")))
+                        .and_then(move |payload| {
+                            store_service
+                                .activate_base_product(base_product_id, payload)
+                                .map(|(_, result)| result)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error activating base product occurred.")))
+                        }),
+                )
+            }
+
+            // POST /base_products/<base_product_id>/deactivate
+            (&Method::Post, Route::BaseProductDeactivate(base_product_id)) => {
+                let store_service = ctx.store_service();
+                serialize_future(
+                    parse_optional_body::<Deactivation>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: Deactivation")))
+                        .and_then(move |payload| {
+                            store_service
+                                .deactivate_base_product(base_product_id, payload)
+                                .map(|(_, result)| result)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error deactivating base product occurred.")))
+                        }),
+                )
+            }
+
+            // POST /base_products/<base_product_id>/update
+            (&Method::Post, Route::BaseProductUpdate(base_product_id)) => {
+                let store_service = ctx.store_service();
+                let if_match = if_match_version(&ctx.headers);
+                serialize_future(
+                    parse_body::<UpdateBaseProduct>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: UpdateBaseProduct")))
+                        .and_then(move |mut base_product_update| {
+                            base_product_update.version = base_product_update.version.or(if_match);
+                            store_service
+                                .update_base_product(base_product_id, base_product_update)
+                                .map(|(_, base_product)| base_product)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error updating base product occurred.")))
+                        }),
+                )
+            }
+
+            // POST /base_products/create_with_variants
+            (&Method::Post, Route::BaseProductCreateWithVariants) => {
+                let store_service = ctx.store_service();
+                serialize_future(
+                    parse_body::<NewBaseProductWithVariants>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: NewBaseProductWithVariants")))
+                        .and_then(move |payload| {
+                            store_service
+                                .create_base_product_with_variants(payload)
+                                .map(|(_, base_product)| base_product)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error creating base product with variants occurred.")))
+                        }),
+                )
+            }
+
+            // POST /products/<product_id>/activate
+            (&Method::Post, Route::ProductActivate(product_id)) => {
+                let store_service = ctx.store_service();
+                serialize_future(
+                    parse_optional_body::<Activation>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: Activation")))
+                        .and_then(move |payload| {
+                            store_service
+                                .activate_product(product_id, payload)
+                                .map(|(_, result)| result)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error activating product occurred.")))
+                        }),
+                )
+            }
+
+            // POST /products/<product_id>/deactivate
+            (&Method::Post, Route::ProductDeactivate(product_id)) => {
+                let store_service = ctx.store_service();
+                serialize_future(
+                    parse_optional_body::<Deactivation>(req.body(), &ctx.headers, ctx.body_options)
+                        .map_err(|e| FailureError::from(e.context("Parsing body failed, target: Deactivation")))
+                        .and_then(move |payload| {
+                            store_service
+                                .deactivate_product(product_id, payload)
+                                .map(|(_, result)| result)
+                                .map_err(|(_, e)| FailureError::from(e.context("Error deactivating product occurred.")))
+                        }),
+                )
+            }
+
+            _ => return None,
+        };
+
+        Some(fut)
+    }
+}
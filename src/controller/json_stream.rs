@@ -0,0 +1,330 @@
+//! Incremental parsing of request bodies holding json arrays. Elements are
+//! parsed and handed over as soon as they arrive, so only one element
+//! is kept in memory at a time. Bodies compressed with gzip or deflate are
+//! decompressed chunk by chunk, bodies with several encodings are decoded at once.
+//! Elements handed over before a later element fails to parse are not taken back,
+//! consumers report how many of them were applied.
+use std::io::Write;
+use std::mem;
+
+use failure::Error as FailureError;
+use failure::Fail;
+use flate2::write::{GzDecoder, ZlibDecoder};
+use futures::prelude::*;
+use futures::stream::{self, iter_ok};
+use hyper::header::{ContentEncoding, Encoding, Headers};
+use hyper::Body;
+use serde::de::DeserializeOwned;
+
+use super::{decode_body, deserialize, BodyOptions};
+use errors::Error;
+
+/// Parses body holding json array into stream of its elements. Elements larger than `options.limit` bytes are rejected.
+pub fn parse_array_stream<T: DeserializeOwned + 'static>(
+    body: Body,
+    headers: &Headers,
+    options: BodyOptions,
+) -> Box<Stream<Item = T, Error = FailureError>> {
+    let encodings = headers.get::<ContentEncoding>().map(|header| header.0.clone()).unwrap_or_default();
+    let mut decoder = ChunkDecoder::new(encodings, options.limit);
+    let mut splitter = ArraySplitter::new(options.limit);
+    Box::new(
+        body.map(Some)
+            .map_err(|e| FailureError::from(e.context("Reading request body failed").context(Error::Parse)))
+            .chain(stream::once(Ok(None)))
+            .and_then(move |chunk| match chunk {
+                Some(chunk) => decoder.decode(&chunk).and_then(|bytes| splitter.push(&bytes)),
+                None => {
+                    let elements = decoder.finish().and_then(|bytes| splitter.push(&bytes))?;
+                    splitter.finish().map(|_| elements)
+                }
+            })
+            .map(iter_ok::<_, FailureError>)
+            .flatten()
+            .and_then(move |element| deserialize::<T>(&element, options.strict)),
+    )
+}
+
+/// Undoes content encoding of body as its chunks arrive
+enum ChunkDecoder {
+    Identity,
+    Gzip(GzDecoder<Vec<u8>>),
+    Deflate(ZlibDecoder<Vec<u8>>),
+    /// Several encodings are undone once the whole body of at most `limit` bytes is received
+    Buffered {
+        encodings: Vec<Encoding>,
+        body: Vec<u8>,
+        limit: usize,
+    },
+}
+
+impl ChunkDecoder {
+    fn new(encodings: Vec<Encoding>, limit: usize) -> Self {
+        let encodings = encodings
+            .into_iter()
+            .filter(|encoding| *encoding != Encoding::Identity)
+            .collect::<Vec<_>>();
+        match (encodings.len(), encodings.first()) {
+            (0, _) => ChunkDecoder::Identity,
+            (1, Some(&Encoding::Gzip)) => ChunkDecoder::Gzip(GzDecoder::new(vec![])),
+            (1, Some(&Encoding::Deflate)) => ChunkDecoder::Deflate(ZlibDecoder::new(vec![])),
+            _ => ChunkDecoder::Buffered {
+                encodings,
+                body: vec![],
+                limit,
+            },
+        }
+    }
+
+    /// Returns part of decoded body available after the chunk
+    fn decode(&mut self, chunk: &[u8]) -> Result<Vec<u8>, FailureError> {
+        match self {
+            ChunkDecoder::Identity => Ok(chunk.to_vec()),
+            ChunkDecoder::Gzip(decoder) => {
+                decoder.write_all(chunk).and_then(|_| decoder.flush()).map_err(decompress_error)?;
+                Ok(mem::replace(decoder.get_mut(), vec![]))
+            }
+            ChunkDecoder::Deflate(decoder) => {
+                decoder.write_all(chunk).and_then(|_| decoder.flush()).map_err(decompress_error)?;
+                Ok(mem::replace(decoder.get_mut(), vec![]))
+            }
+            ChunkDecoder::Buffered { body, limit, .. } => {
+                body.extend_from_slice(chunk);
+                if body.len() > *limit {
+                    return Err(format_err!("Request body exceeds limit of {} bytes", limit)
+                        .context(Error::PayloadTooLarge)
+                        .into());
+                }
+                Ok(vec![])
+            }
+        }
+    }
+
+    /// Returns rest of decoded body once the whole body is received
+    fn finish(&mut self) -> Result<Vec<u8>, FailureError> {
+        match self {
+            ChunkDecoder::Identity => Ok(vec![]),
+            ChunkDecoder::Gzip(decoder) => {
+                decoder.try_finish().map_err(decompress_error)?;
+                Ok(mem::replace(decoder.get_mut(), vec![]))
+            }
+            ChunkDecoder::Deflate(decoder) => {
+                decoder.try_finish().map_err(decompress_error)?;
+                Ok(mem::replace(decoder.get_mut(), vec![]))
+            }
+            ChunkDecoder::Buffered { encodings, body, limit } => decode_body(mem::replace(body, vec![]), encodings, *limit),
+        }
+    }
+}
+
+fn decompress_error(e: ::std::io::Error) -> FailureError {
+    FailureError::from(e.context("Decompressing request body failed").context(Error::Parse))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Waiting for opening bracket of array
+    Start,
+    /// Inside array, between elements
+    Array,
+    /// Inside array element
+    Element,
+    /// After closing bracket of array
+    End,
+}
+
+/// Splits json array into raw elements tracking nesting depth and string literals
+struct ArraySplitter {
+    state: State,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    element: Vec<u8>,
+    limit: usize,
+}
+
+impl ArraySplitter {
+    fn new(limit: usize) -> Self {
+        Self {
+            state: State::Start,
+            depth: 0,
+            in_string: false,
+            escaped: false,
+            element: vec![],
+            limit,
+        }
+    }
+
+    /// Consumes next part of body and returns elements completed by it
+    fn push(&mut self, bytes: &[u8]) -> Result<Vec<Vec<u8>>, FailureError> {
+        let mut elements = vec![];
+        for &byte in bytes {
+            match self.state {
+                State::Start => match byte {
+                    b'[' => self.state = State::Array,
+                    byte if is_whitespace(byte) => {}
+                    _ => return Err(parse_error("Request body is not a json array")),
+                },
+                State::Array => match byte {
+                    b']' => self.state = State::End,
+                    b',' => {}
+                    byte if is_whitespace(byte) => {}
+                    _ => {
+                        self.state = State::Element;
+                        self.take(byte, &mut elements)?;
+                    }
+                },
+                State::Element => self.take(byte, &mut elements)?,
+                State::End => {
+                    if !is_whitespace(byte) {
+                        return Err(parse_error("Unexpected data after the end of json array"));
+                    }
+                }
+            }
+        }
+        Ok(elements)
+    }
+
+    /// Checks that the whole array has been received
+    fn finish(&self) -> Result<(), FailureError> {
+        match self.state {
+            State::End => Ok(()),
+            _ => Err(parse_error("Request body ended before the end of json array")),
+        }
+    }
+
+    fn take(&mut self, byte: u8, elements: &mut Vec<Vec<u8>>) -> Result<(), FailureError> {
+        if self.in_string {
+            if self.escaped {
+                self.escaped = false;
+            } else if byte == b'\\' {
+                self.escaped = true;
+            } else if byte == b'"' {
+                self.in_string = false;
+            }
+        } else {
+            match byte {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => self.depth += 1,
+                b'}' | b']' if self.depth > 0 => self.depth -= 1,
+                // end of scalar element or of the whole array
+                b',' | b']' => {
+                    self.complete(elements);
+                    self.state = if byte == b']' { State::End } else { State::Array };
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+
+        self.element.push(byte);
+        if self.element.len() > self.limit {
+            return Err(format_err!("Element of json array exceeds limit of {} bytes", self.limit)
+                .context(Error::PayloadTooLarge)
+                .into());
+        }
+        if self.depth == 0 && !self.in_string && (byte == b'}' || byte == b']') {
+            self.complete(elements);
+            self.state = State::Array;
+        }
+        Ok(())
+    }
+
+    fn complete(&mut self, elements: &mut Vec<Vec<u8>>) {
+        let element = ::std::mem::replace(&mut self.element, vec![]);
+        if !element.iter().all(|&byte| is_whitespace(byte)) {
+            elements.push(element);
+        }
+    }
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    byte == b' ' || byte == b'\n' || byte == b'\r' || byte == b'\t'
+}
+
+fn parse_error(message: &'static str) -> FailureError {
+    format_err!("{}", message).context(Error::Parse).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use futures::prelude::*;
+    use hyper::header::{Encoding, Headers};
+    use hyper::Body;
+    use serde_json::Value;
+
+    use super::{parse_array_stream, ArraySplitter, ChunkDecoder};
+    use controller::BodyOptions;
+
+    fn split(parts: &[&str]) -> Vec<String> {
+        let mut splitter = ArraySplitter::new(1024);
+        let mut elements = vec![];
+        for part in parts {
+            elements.extend(splitter.push(part.as_bytes()).unwrap());
+        }
+        splitter.finish().unwrap();
+        elements.into_iter().map(|element| String::from_utf8(element).unwrap()).collect()
+    }
+
+    #[test]
+    fn splits_elements_across_chunks() {
+        let elements = split(&[r#" [{"id": 1, "tags": ["a", "]"]}, {"#, r#""id": 2, "name": "x\"}"}, 3 ,"s"] "#]);
+        assert_eq!(
+            elements,
+            vec![r#"{"id": 1, "tags": ["a", "]"]}"#, r#"{"id": 2, "name": "x\"}"}"#, "3 ", r#""s""#]
+        );
+    }
+
+    #[test]
+    fn handles_empty_array() {
+        assert!(split(&["[", " ]"]).is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_array() {
+        let mut splitter = ArraySplitter::new(1024);
+        splitter.push(br#"[{"id": 1}, {"id""#).unwrap();
+        assert!(splitter.finish().is_err());
+    }
+
+    #[test]
+    fn rejects_element_exceeding_limit() {
+        let mut splitter = ArraySplitter::new(8);
+        assert!(splitter.push(br#"[{"id": 12345}]"#).is_err());
+    }
+
+    #[test]
+    fn decompresses_body_chunk_by_chunk() {
+        let body = br#"[{"id": 1}, {"id": 2}]"#;
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoder = ChunkDecoder::new(vec![Encoding::Gzip], 1024);
+        let mut decoded = vec![];
+        for chunk in compressed.chunks(4) {
+            decoded.extend(decoder.decode(chunk).unwrap());
+        }
+        decoded.extend(decoder.finish().unwrap());
+        assert_eq!(&decoded[..], &body[..]);
+    }
+
+    #[test]
+    fn hands_over_elements_preceding_malformed_one() {
+        let options = BodyOptions {
+            limit: 1024,
+            strict: false,
+        };
+        let elements = parse_array_stream::<Value>(Body::from(r#"[{"id": 1}, {"id": }]"#), &Headers::new(), options)
+            .then(|res| Ok::<_, ()>(res.is_ok()))
+            .collect()
+            .wait()
+            .unwrap();
+        // the first element is applied by consumer before the second one fails to parse
+        assert_eq!(elements, vec![true, false]);
+    }
+}
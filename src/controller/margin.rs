@@ -0,0 +1,125 @@
+//! Safety margin subtracted from timeout of the caller, so that the coordinator has
+//! time to respond after calls to microservices are finished. Overhead of every
+//! request, i.e. its duration without time spent waiting for microservices, is
+//! measured. In adaptive mode the margin follows a quantile of measured overhead
+//! instead of static `service.processing_timeout_ms`.
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use config;
+use metrics::duration_ms;
+
+/// Overhead quantiles reported in metrics
+pub const REPORTED_QUANTILES: &[f64] = &[0.5, 0.95, 0.99];
+
+#[derive(Clone)]
+pub struct ProcessingMargin {
+    config: config::Service,
+    /// Overhead of the latest requests in milliseconds
+    samples: Arc<Mutex<VecDeque<u64>>>,
+}
+
+/// Measured overhead and the margin derived from it
+#[derive(Clone, Debug, PartialEq)]
+pub struct MarginStats {
+    /// Overhead in milliseconds by quantile, `None` until any request is measured
+    pub overhead_ms: Vec<(f64, Option<u64>)>,
+    pub margin_ms: u64,
+    /// Quantile of overhead the margin follows, `None` for static margin
+    pub adaptive_quantile: Option<f64>,
+}
+
+impl ProcessingMargin {
+    pub fn new(config: config::Service) -> Self {
+        Self {
+            config,
+            samples: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    pub fn record(&self, overhead: Duration) {
+        let window = match self.config.adaptive_margin {
+            Some(ref adaptive) => adaptive.window,
+            None => config::AdaptiveMargin::default().window,
+        };
+        let mut samples = self.samples.lock().unwrap();
+        samples.push_back(duration_ms(overhead));
+        while samples.len() > window {
+            samples.pop_front();
+        }
+    }
+
+    /// Margin for the next request
+    pub fn margin(&self) -> Duration {
+        let samples = self.sorted_samples();
+        Duration::from_millis(margin_ms(&self.config, &samples))
+    }
+
+    pub fn stats(&self) -> MarginStats {
+        let samples = self.sorted_samples();
+        MarginStats {
+            overhead_ms: REPORTED_QUANTILES
+                .iter()
+                .map(|quantile| (*quantile, quantile_of(&samples, *quantile)))
+                .collect(),
+            margin_ms: margin_ms(&self.config, &samples),
+            adaptive_quantile: self.config.adaptive_margin.as_ref().map(|adaptive| adaptive.quantile),
+        }
+    }
+
+    fn sorted_samples(&self) -> Vec<u64> {
+        let mut samples = self.samples.lock().unwrap().iter().cloned().collect::<Vec<_>>();
+        samples.sort();
+        samples
+    }
+}
+
+/// Static margin until enough requests are measured, then quantile of overhead with headroom within bounds
+fn margin_ms(config: &config::Service, sorted_samples: &[u64]) -> u64 {
+    let adaptive = match config.adaptive_margin {
+        Some(ref adaptive) if sorted_samples.len() >= adaptive.min_samples => adaptive,
+        _ => return config.processing_timeout_ms,
+    };
+    let overhead = quantile_of(sorted_samples, adaptive.quantile).unwrap_or(config.processing_timeout_ms);
+    let margin = (overhead as f64 * adaptive.headroom).ceil() as u64;
+    ::std::cmp::min(::std::cmp::max(margin, adaptive.min_ms), adaptive.max_ms)
+}
+
+/// Nearest rank quantile of sorted samples
+fn quantile_of(sorted_samples: &[u64], quantile: f64) -> Option<u64> {
+    if sorted_samples.is_empty() {
+        return None;
+    }
+    let rank = (quantile * sorted_samples.len() as f64).ceil() as usize;
+    Some(sorted_samples[rank.max(1).min(sorted_samples.len()) - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::margin_ms;
+    use config::{AdaptiveMargin, Service};
+
+    #[test]
+    fn adapts_margin_to_measured_overhead() {
+        let mut config = Service {
+            processing_timeout_ms: 1000,
+            strict_payloads: false,
+            adaptive_margin: None,
+        };
+        let samples = (1..=100).collect::<Vec<u64>>();
+        assert_eq!(margin_ms(&config, &samples), 1000);
+
+        config.adaptive_margin = Some(AdaptiveMargin {
+            quantile: 0.99,
+            headroom: 2.0,
+            min_ms: 50,
+            max_ms: 150,
+            window: 1000,
+            min_samples: 100,
+        });
+        assert_eq!(margin_ms(&config, &samples[..10]), 1000);
+        assert_eq!(margin_ms(&config, &samples[..]), 150);
+        assert_eq!(margin_ms(&config, &[1; 100]), 50);
+    }
+}
@@ -0,0 +1,67 @@
+//! Method handling common to all routes: `OPTIONS` lists methods of the route,
+//! `HEAD` is served as `GET` without body and requests with methods the route
+//! does not support are rejected with `405 Method Not Allowed`.
+use std::sync::Arc;
+
+use futures::future;
+use futures::prelude::*;
+use hyper;
+use hyper::header::Allow;
+use hyper::server::{Request, Response, Service};
+use hyper::{Body, Method, StatusCode};
+
+use stq_router::RouteParser;
+
+use super::routes::{split_version, Route};
+
+pub struct Methods<S> {
+    route_parser: Arc<RouteParser<Route>>,
+    inner: S,
+}
+
+impl<S> Methods<S> {
+    pub fn new(route_parser: Arc<RouteParser<Route>>, inner: S) -> Self {
+        Self { route_parser, inner }
+    }
+}
+
+impl<S> Service for Methods<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+    S::Future: 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, mut req: Request) -> Self::Future {
+        // unknown routes are reported by controller
+        let methods = match self.route_parser.test(split_version(req.path()).1) {
+            Some(route) => route.methods(),
+            None => return Box::new(self.inner.call(req)),
+        };
+
+        let mut allowed = methods.to_vec();
+        if methods.contains(&Method::Get) {
+            allowed.push(Method::Head);
+        }
+        allowed.push(Method::Options);
+
+        match req.method().clone() {
+            Method::Options => Box::new(future::ok(
+                Response::new().with_status(StatusCode::NoContent).with_header(Allow(allowed)),
+            )),
+            Method::Head if methods.contains(&Method::Get) => {
+                req.set_method(Method::Get);
+                Box::new(self.inner.call(req).map(|response| response.with_body(Body::empty())))
+            }
+            ref method if methods.contains(method) => Box::new(self.inner.call(req)),
+            _ => Box::new(future::ok(
+                Response::new()
+                    .with_status(StatusCode::MethodNotAllowed)
+                    .with_header(Allow(allowed)),
+            )),
+        }
+    }
+}
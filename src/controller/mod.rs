@@ -2,449 +2,592 @@
 //! stuff like reading bodies, parsing params, forming a response.
 //! Basically it provides inputs to `Service` layer and converts outputs
 //! of `Service` layer to http responses
+pub mod accepted;
+pub mod compression;
+pub mod context;
+pub mod cors;
+pub mod csv;
+pub mod endpoints;
+pub mod etag;
+pub mod handlers;
+pub mod json_stream;
+pub mod margin;
+pub mod methods;
+pub mod query;
+pub mod redaction;
+pub mod request_id;
 pub mod requests;
 pub mod routes;
+pub mod signature;
 
+use std::io::Read;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::Instant;
 
 use failure::Error as FailureError;
 use failure::Fail;
+use flate2::read::{GzDecoder, ZlibDecoder};
 use futures::future;
 use futures::prelude::*;
-use hyper::header::Authorization;
+use hyper::header::ContentType;
 use hyper::header::Headers;
+use hyper::header::{ContentEncoding, Encoding};
+use hyper::mime;
 use hyper::server::Request;
-use hyper::Method;
+use hyper::Body;
+use serde::de::DeserializeOwned;
+use serde_json;
+use tokio_core::reactor::Handle;
 
-use stq_http::client::{ClientHandle as HttpClientHandle, HttpClientWithDefaultHeaders, TimeLimitedHttpClient};
 use stq_http::controller::Controller;
 use stq_http::controller::ControllerFuture;
 use stq_http::errors::ErrorMessageWrapper;
-use stq_http::request_util::parse_body;
-use stq_http::request_util::serialize_future;
-use stq_http::request_util::CorrelationToken as CorrelationTokenHeader;
-use stq_http::request_util::RequestTimeout as RequestTimeoutHeader;
-use stq_http::request_util::{Currency as CurrencyHeader, FiatCurrency as FiatCurrencyHeader};
 use stq_router::RouteParser;
-
-use self::routes::Route;
-use config::Config;
+use validator::Validate;
+
+use self::context::RequestContext;
+use self::endpoints::EndpointSwitch;
+use self::handlers::{HandlerContext, Handlers};
+use self::margin::ProcessingMargin;
+use self::query::Query;
+use self::routes::{create_route_parser, split_version, ApiVersion, Route};
+use acknowledgment::{AcknowledgmentTimers, AcknowledgmentTimersImpl};
+use audit::{AuditLog, AuditLogImpl, AuditScope};
+use billing_events::{BillingEventLog, BillingEventLogImpl};
+use config::{Config, Limits};
 use errors::Error;
-use microservice::{
-    BillingMicroserviceImpl, DeliveryMicroserviceImpl, NotificationsMicroserviceImpl, OrdersMicroserviceImpl, StoresMicroserviceImpl,
-    UsersMicroserviceImpl, WarehousesMicroserviceImpl,
-};
+use fraud::{FraudOverrides, FraudOverridesImpl};
+use microservice::{BaseHttpClient, CircuitBreakers, DependencyMonitor};
 use models::*;
+use moderation::{ModerationQueue, ModerationQueueImpl};
+use saga::{SagaExecutor, SagaStore, SagaStoreImpl};
+use scrubbing::mask_credentials;
 use sentry_integration::log_and_capture_error;
-use services::account::{AccountService, AccountServiceImpl};
-use services::delivery::{DeliveryService, DeliveryServiceImpl};
-use services::order::{OrderService, OrderServiceImpl};
-use services::store::{StoreService, StoreServiceImpl};
+use tracking_events::{TrackingEventLog, TrackingEventLogImpl};
+use vacations::{StoreVacations, StoreVacationsImpl};
+
+/// Header with locale chosen by user in the session, takes precedence over `Accept-Language`
+pub const SESSION_LOCALE_HEADER: &str = "Session-Locale";
+/// Headers carrying credentials of the caller, they are left out of recorded requests
+const UNRECORDED_HEADERS: &[&str] = &["Authorization", "Cookie"];
 
+#[derive(Clone)]
 pub struct ControllerImpl {
     pub config: Config,
-    pub http_client: HttpClientHandle,
+    pub http_client: BaseHttpClient,
+    pub handle: Arc<Handle>,
     pub route_parser: Arc<RouteParser<Route>>,
+    pub handlers: Arc<Handlers>,
+    pub saga_store: Arc<SagaStore>,
+    pub moderation_queue: Arc<ModerationQueue>,
+    pub acknowledgment_timers: Arc<AcknowledgmentTimers>,
+    pub vacations: Arc<StoreVacations>,
+    pub fraud_overrides: Arc<FraudOverrides>,
+    pub audit_log: Arc<AuditLog>,
+    pub billing_events: Arc<BillingEventLog>,
+    pub tracking_events: Arc<TrackingEventLog>,
+    pub executor: SagaExecutor,
+    pub breakers: CircuitBreakers,
+    pub monitor: DependencyMonitor,
+    pub margin: ProcessingMargin,
+    pub endpoints: EndpointSwitch,
 }
 
 impl Controller for ControllerImpl {
     fn call(&self, req: Request) -> ControllerFuture {
-        let headers = req.headers().clone();
-
-        let default_timeout = Duration::from_millis(self.config.client.http_timeout_ms);
-        let request_timeout = match headers.get::<RequestTimeoutHeader>() {
-            None => default_timeout,
-            Some(header) => header.0.parse::<u64>().map(Duration::from_millis).unwrap_or(default_timeout),
+        if !self.config.saga.record_calls {
+            return self.dispatch(req, None);
         }
-        .checked_sub(Duration::from_millis(self.config.service.processing_timeout_ms))
-        .unwrap_or(Duration::new(0, 0));
-
-        let http_client = TimeLimitedHttpClient::new(self.http_client.clone(), request_timeout);
-
-        let orders_microservice = Arc::new(OrdersMicroserviceImpl::new(
-            HttpClientWithDefaultHeaders::new(http_client.clone(), default_headers(&headers)),
-            self.config.clone(),
-        ));
-
-        let stores_microservice = Arc::new(StoresMicroserviceImpl::new(
-            HttpClientWithDefaultHeaders::new(http_client.clone(), stores_headers(&headers)),
-            self.config.clone(),
-        ));
-
-        let notifications_microservice = Arc::new(NotificationsMicroserviceImpl::new(
-            HttpClientWithDefaultHeaders::new(http_client.clone(), default_headers(&headers)),
-            self.config.clone(),
-        ));
-
-        let users_microservice = Arc::new(UsersMicroserviceImpl::new(
-            HttpClientWithDefaultHeaders::new(http_client.clone(), default_headers(&headers)),
-            self.config.clone(),
-        ));
-
-        let billing_microservice = Arc::new(BillingMicroserviceImpl::new(
-            HttpClientWithDefaultHeaders::new(http_client.clone(), default_headers(&headers)),
-            self.config.clone(),
-        ));
-
-        let warehouses_microservice = Arc::new(WarehousesMicroserviceImpl::new(
-            HttpClientWithDefaultHeaders::new(http_client.clone(), default_headers(&headers)),
-            self.config.clone(),
-        ));
-
-        let delivery_microservice = Arc::new(DeliveryMicroserviceImpl::new(
-            HttpClientWithDefaultHeaders::new(http_client.clone(), default_headers(&headers)),
-            self.config.clone(),
-        ));
-
-        let config = self.config.clone();
-
-        let account_service = AccountServiceImpl::new(
-            config.clone(),
-            stores_microservice.clone(),
-            billing_microservice.clone(),
-            delivery_microservice.clone(),
-            users_microservice.clone(),
-            notifications_microservice.clone(),
-        );
-        let store_service = StoreServiceImpl::new(
-            config.clone(),
-            orders_microservice.clone(),
-            stores_microservice.clone(),
-            notifications_microservice.clone(),
-            billing_microservice.clone(),
-            warehouses_microservice.clone(),
-            users_microservice.clone(),
-            delivery_microservice.clone(),
-        );
 
-        let order_service = OrderServiceImpl::new(
-            config.clone(),
-            orders_microservice.clone(),
-            stores_microservice.clone(),
-            notifications_microservice.clone(),
-            users_microservice.clone(),
-            billing_microservice.clone(),
-            warehouses_microservice.clone(),
-        );
+        // Request is recorded along with the saga it starts, so its body is buffered beforehand
+        let (_, route_path) = split_version(req.path());
+        let limit = body_limit(&self.config.limits, self.route_parser.test(route_path).as_ref());
+        let controller = self.clone();
+        let (method, uri, version, headers, body) = req.deconstruct();
+        Box::new(
+            body.map_err(|e| FailureError::from(e.context("Reading request body failed").context(Error::Parse)))
+                .fold(Vec::new(), move |mut bytes, chunk| {
+                    if bytes.len() + chunk.len() > limit {
+                        return Err(FailureError::from(
+                            format_err!("Request body exceeds limit of {} bytes", limit).context(Error::PayloadTooLarge),
+                        ));
+                    }
+                    bytes.extend_from_slice(&chunk);
+                    Ok(bytes)
+                })
+                .and_then(move |bytes| {
+                    let recorded = RecordedRequest {
+                        method: method.to_string(),
+                        path: uri.to_string(),
+                        headers: headers
+                            .iter()
+                            .filter(|header| !UNRECORDED_HEADERS.iter().any(|name| header.name().eq_ignore_ascii_case(name)))
+                            .map(|header| (header.name().to_string(), header.value_string()))
+                            .collect(),
+                        body: mask_credentials(&String::from_utf8_lossy(&bytes)),
+                    };
+                    let mut req = Request::new(method, uri);
+                    req.set_version(version);
+                    *req.headers_mut() = headers;
+                    req.set_body(bytes);
+                    controller.dispatch(req, Some(recorded))
+                }),
+        )
+    }
+}
 
-        let delivery_service = DeliveryServiceImpl::new(
+impl ControllerImpl {
+    /// Controller serving requests in-process with every store kept in memory, used for replay of sagas and in tests
+    pub fn in_memory(config: Config, http_client: BaseHttpClient, handle: Arc<Handle>) -> Result<Self, FailureError> {
+        Ok(Self {
+            http_client,
+            route_parser: Arc::new(create_route_parser()),
+            handlers: Arc::new(Handlers::new()),
+            saga_store: Arc::new(SagaStoreImpl::new(None, None)?),
+            moderation_queue: Arc::new(ModerationQueueImpl::new(None)?),
+            acknowledgment_timers: Arc::new(AcknowledgmentTimersImpl::new(None)?),
+            vacations: Arc::new(StoreVacationsImpl::new(None)?),
+            fraud_overrides: Arc::new(FraudOverridesImpl::new(None)?),
+            audit_log: Arc::new(AuditLogImpl::new(None, config.audit.capacity)?),
+            billing_events: Arc::new(BillingEventLogImpl::new(config.billing_events.capacity)),
+            tracking_events: Arc::new(TrackingEventLogImpl::new(
+                config
+                    .delivery_tracking
+                    .as_ref()
+                    .map(|tracking| tracking.events_capacity)
+                    .unwrap_or(0),
+            )),
+            executor: SagaExecutor::new((*handle).clone(), config.executor.clone()),
+            breakers: CircuitBreakers::new(config.circuit_breaker.clone()),
+            monitor: DependencyMonitor::new(config.dependency_monitor.clone()),
+            margin: ProcessingMargin::new(config.service.clone()),
+            endpoints: EndpointSwitch::new(&config),
+            handle,
             config,
-            orders_microservice.clone(),
-            delivery_microservice.clone(),
-            stores_microservice.clone(),
-        );
+        })
+    }
 
+    fn dispatch(&self, req: Request, recorded: Option<RecordedRequest>) -> ControllerFuture {
+        let started_at = Instant::now();
+        let method = req.method().clone();
         let path = req.path().to_string();
-
-        let fut = match (&req.method().clone(), self.route_parser.test(req.path())) {
-            (&Method::Post, Some(Route::CreateAccount)) => serialize_future(
-                parse_body::<SagaCreateProfile>(req.body())
-                    .map_err(|e| {
-                        FailureError::from(
-                            e.context("Parsing body // POST /create_account in SagaCreateProfile failed!")
-                                .context(Error::Parse),
-                        )
-                    })
-                    .and_then(move |profile| {
-                        account_service
-                            .create(profile)
-                            .map(|(_, user)| user)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error during account creation occurred.")))
-                    }),
-            ),
-            (&Method::Post, Some(Route::VerifyEmail)) => serialize_future(
-                parse_body::<VerifyRequest>(req.body())
-                    .map_err(|e| {
-                        FailureError::from(
-                            e.context("Parsing body // POST /email_verify in VerifyRequest failed!")
-                                .context(Error::Parse),
-                        )
-                    })
-                    .and_then(move |profile| {
-                        account_service
-                            .request_email_verification(profile)
-                            .map(|(_, user)| user)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error during email verification occurred.")))
-                    }),
-            ),
-            (&Method::Post, Some(Route::VerifyEmailApply)) => serialize_future(
-                parse_body::<EmailVerifyApply>(req.body())
-                    .map_err(|e| {
-                        FailureError::from(
-                            e.context("Parsing body // POST /email_verify_apply in EmailVerifyApply failed!")
-                                .context(Error::Parse),
-                        )
-                    })
-                    .and_then(move |profile| {
-                        account_service
-                            .request_email_verification_apply(profile)
-                            .map(|(_, user)| user)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error during email verification apply occurred.")))
-                    }),
-            ),
-            (&Method::Post, Some(Route::ResetPassword)) => serialize_future(
-                parse_body::<ResetRequest>(req.body())
-                    .map_err(|e| {
-                        FailureError::from(
-                            e.context("Parsing body // POST /reset_password in ResetRequest failed!")
-                                .context(Error::Parse),
-                        )
-                    })
-                    .and_then(move |profile| {
-                        account_service
-                            .request_password_reset(profile)
-                            .map(|(_, user)| user)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error during reset password occurred.")))
-                    }),
-            ),
-            (&Method::Post, Some(Route::ResetPasswordApply)) => serialize_future(
-                parse_body::<PasswordResetApply>(req.body())
-                    .map_err(|e| {
-                        FailureError::from(
-                            e.context("Parsing body // POST /reset_password_apply in PasswordResetApply failed!")
-                                .context(Error::Parse),
-                        )
-                    })
-                    .and_then(move |profile| {
-                        account_service
-                            .request_password_reset_apply(profile)
-                            .map(|(_, user)| user)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error during reset password apply occurred.")))
-                    }),
-            ),
-
-            (&Method::Post, Some(Route::CreateStore)) => serialize_future(
-                parse_body::<NewStore>(req.body())
-                    .map_err(|e| {
-                        FailureError::from(
-                            e.context("Parsing body // POST /create_store in NewStore failed!")
-                                .context(Error::Parse),
-                        )
-                    })
-                    .and_then(move |store| {
-                        store_service
-                            .create(store)
-                            .map(|(_, user)| user)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error during store creation occurred.")))
-                    }),
-            ),
-
-            (&Method::Post, Some(Route::CreateOrder)) => serialize_future(
-                parse_body::<ConvertCart>(req.body())
-                    .map_err(|e| FailureError::from(e.context("Parsing body failed, target: ConvertCart").context(Error::Parse)))
-                    .and_then(move |new_order| {
-                        order_service
-                            .create(new_order)
-                            .map(|(_, user)| user)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error during order creation occurred.")))
-                    }),
-            ),
-
-            (&Method::Post, Some(Route::BuyNow)) => serialize_future(
-                parse_body::<BuyNow>(req.body())
-                    .map_err(|e| FailureError::from(e.context("Parsing body // POST /buy_now in BuyNow failed!").context(Error::Parse)))
-                    .and_then(move |new_buy_now| {
-                        order_service
-                            .create_buy_now(new_buy_now)
-                            .map(|(_, invoice)| invoice)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error during order creation from buy now data occurred.")))
-                    }),
-            ),
-
-            (&Method::Post, Some(Route::OrdersUpdateStateByBilling)) => serialize_future(
-                parse_body::<BillingOrdersVec>(req.body())
-                    .map_err(|e| {
-                        FailureError::from(
-                            e.context("Parsing body // POST /orders/update_state in BillingOrdersVec failed!")
-                                .context(Error::Parse),
-                        )
-                    })
-                    .and_then(move |orders_info| {
-                        order_service
-                            .update_state_by_billing(orders_info)
-                            .map(|(_, _)| ())
-                            .map_err(|(_, e)| FailureError::from(e.context("Error during orders update by external billing occurred.")))
-                    }),
-            ),
-
-            (&Method::Post, Some(Route::OrdersManualSetState { order_slug })) => serialize_future(
-                parse_body::<UpdateStatePayload>(req.body())
-                    .map_err(move |e| {
-                        FailureError::from(
-                            e.context(format!(
-                                "Parsing body // POST /orders/{}/set_state in UpdateStatePayload failed!",
-                                order_slug
-                            ))
-                            .context(Error::Parse),
-                        )
-                    })
-                    .and_then(move |payload| {
-                        order_service
-                            .manual_set_state(order_slug, payload.state, payload.track_id, payload.comment, payload.committer_role)
-                            .map(|(_, order)| order)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error during orders manual update occurred.")))
-                    }),
-            ),
-
-            (&Method::Post, Some(Route::OrdersSetPaymentState { order_id })) => serialize_future({
-                parse_body::<OrderPaymentStateRequest>(req.body())
-                    .map_err(move |e| {
-                        FailureError::from(
-                            e.context("Parsing body failed, target: OrderPaymentStateRequest")
-                                .context(Error::Parse),
-                        )
-                    })
-                    .and_then(move |payload| {
-                        order_service
-                            .manual_set_payment_state(order_id, payload)
-                            .map(|_| ())
-                            .map_err(|(_, e)| FailureError::from(e.context("Error during orders manual payment state update occurred.")))
-                    })
-            }),
-
-            // POST /stores/moderate
-            (&Method::Post, Some(Route::StoreModerate)) => serialize_future(
-                parse_body::<StoreModerate>(req.body())
-                    .map_err(|e| FailureError::from(e.context("Parsing body failed, target: StoreModerate").context(Error::Parse)))
-                    .and_then(move |store_moderate| {
-                        store_service
-                            .set_store_moderation_status(store_moderate)
-                            .map(|(_, store)| store)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error during change store status occurred.")))
-                    }),
-            ),
-
-            // POST /stores/moderation
-            (&Method::Post, Some(Route::StoreModeration(store_id))) => serialize_future(
-                store_service
-                    .send_to_moderation(store_id)
-                    .map(|(_, store)| store)
-                    .map_err(|(_, e)| FailureError::from(e.context("Error sending store to moderation occurred."))),
-            ),
-
-            // POST /stores/<store_id>/deactivate
-            (&Method::Post, Some(Route::StoreDeactivate(store_id))) => serialize_future(
-                store_service
-                    .deactivate_store(store_id)
-                    .map(|(_, store)| store)
-                    .map_err(|(_, e)| FailureError::from(e.context("Error deactivating store occurred."))),
-            ),
-
-            // POST /base_products/moderate
-            (&Method::Post, Some(Route::BaseProductModerate)) => serialize_future(
-                parse_body::<BaseProductModerate>(req.body())
-                    .map_err(|e| FailureError::from(e.context("Parsing body failed, target: BaseProductModerate").context(Error::Parse)))
-                    .and_then(move |base_product_moderate| {
-                        store_service
-                            .set_moderation_status_base_product(base_product_moderate)
-                            .map(|(_, _)| ())
-                            .map_err(|(_, e)| FailureError::from(e.context("Error change base product status occurred.")))
-                    }),
-            ),
-
-            // POST /base_products/moderation
-            (&Method::Post, Some(Route::BaseProductModeration(base_product_id))) => serialize_future(
-                store_service
-                    .send_to_moderation_base_product(base_product_id)
-                    .map(|(_, _)| ())
-                    .map_err(|(_, e)| FailureError::from(e.context("Error sending base product to moderation occurred."))),
-            ),
-
-            // POST /base_products/<base_product_id>/deactivate
-            (&Method::Post, Some(Route::BaseProductDeactivate(base_product_id))) => serialize_future(
-                store_service
-                    .deactivate_base_product(base_product_id)
-                    .map(|(_, base_product)| base_product)
-                    .map_err(|(_, e)| FailureError::from(e.context("Error deactivating base product occurred."))),
-            ),
-
-            // POST /base_products/<base_product_id>/update
-            (&Method::Post, Some(Route::BaseProductUpdate(base_product_id))) => serialize_future(
-                parse_body::<UpdateBaseProduct>(req.body())
-                    .map_err(|e| FailureError::from(e.context("Parsing body failed, target: UpdateBaseProduct").context(Error::Parse)))
-                    .and_then(move |base_product_update| {
-                        store_service
-                            .update_base_product(base_product_id, base_product_update)
-                            .map(|(_, base_product)| base_product)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error updating base product occurred.")))
-                    }),
-            ),
-
-            // POST /base_products/create_with_variants
-            (&Method::Post, Some(Route::BaseProductCreateWithVariants)) => serialize_future(
-                parse_body::<NewBaseProductWithVariants>(req.body())
-                    .map_err(|e| {
-                        FailureError::from(
-                            e.context("Parsing body failed, target: NewBaseProductWithVariants")
-                                .context(Error::Parse),
-                        )
-                    })
-                    .and_then(move |payload| {
-                        store_service
-                            .create_base_product_with_variants(payload)
-                            .map(|(_, base_product)| base_product)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error creating base product with variants occurred.")))
-                    }),
-            ),
-
-            // POST /base_products/<base_product_id>/upsert-shipping
-            (&Method::Post, Some(Route::BaseProductUpsertShipping(base_product_id))) => serialize_future(
-                parse_body::<NewShipping>(req.body())
-                    .map_err(|e| FailureError::from(e.context("Parsing body failed, target: NewShipping").context(Error::Parse)))
-                    .and_then(move |payload| {
-                        delivery_service
-                            .upsert_shipping(base_product_id, payload)
-                            .map(|(_, shipping)| shipping)
-                            .map_err(|(_, e)| FailureError::from(e.context("Error update shipping for base product occurred.")))
-                    }),
-            ),
-
-            // POST /products/<product_id>/deactivate
-            (&Method::Post, Some(Route::ProductDeactivate(product_id))) => serialize_future(
-                store_service
-                    .deactivate_product(product_id)
-                    .map(|(_, product)| product)
-                    .map_err(|(_, e)| FailureError::from(e.context("Error deactivating product occurred."))),
-            ),
-
+        let (version, route_path) = split_version(req.path());
+        let route = self.route_parser.test(route_path);
+
+        let mut request = RequestContext::new(&self.config, req.headers(), self.margin.margin());
+        let saga_type = route.as_ref().and_then(Route::saga_type);
+        request.endpoints = self.endpoints.apply(&mut request.config, saga_type);
+        let budget = request.budget.clone();
+        let request_id = request.request_id.clone();
+        let body_options = BodyOptions {
+            limit: body_limit(&self.config.limits, route.as_ref()),
+            strict: self.config.service.strict_payloads,
+        };
+
+        let ctx = HandlerContext {
+            request,
+            headers: req.headers().clone(),
+            version,
+            body_options,
+            http_client: self.http_client.clone(),
+            handle: self.handle.clone(),
+            saga_store: self.saga_store.clone(),
+            moderation_queue: self.moderation_queue.clone(),
+            acknowledgment_timers: self.acknowledgment_timers.clone(),
+            vacations: self.vacations.clone(),
+            fraud_overrides: self.fraud_overrides.clone(),
+            audit_log: self.audit_log.clone(),
+            billing_events: self.billing_events.clone(),
+            tracking_events: self.tracking_events.clone(),
+            executor: self.executor.clone(),
+            breakers: self.breakers.clone(),
+            monitor: self.monitor.clone(),
+            margin: self.margin.clone(),
+            endpoints: self.endpoints.clone(),
+            audit: {
+                let audit = AuditScope::new(self.audit_log.clone(), format!("{} {}", method, path));
+                match recorded {
+                    Some(recorded) => audit.with_recording(self.saga_store.clone(), recorded),
+                    None => audit,
+                }
+            },
+        };
+
+        let fut = route.and_then(|route| self.handlers.handle(ctx, req, route)).unwrap_or_else(|| {
             // Fallback
-            (m, _) => Box::new(future::err(
+            Box::new(future::err(
                 format_err!(
                     "Request to non existing endpoint in saga coordinator microservice! {:?} {:?}",
-                    m,
+                    method,
                     path
                 )
                 .context(Error::NotFound)
                 .into(),
-            )),
-        }
-        .map_err(|err| {
-            let wrapper = ErrorMessageWrapper::<Error>::from(&err);
-            if wrapper.inner.code == 500 {
-                log_and_capture_error(&err);
-            }
-            err
+            ))
         });
 
-        Box::new(fut)
+        let margin = self.margin.clone();
+        let fut = Box::new(fut.then(move |res| {
+            margin.record(started_at.elapsed().checked_sub(budget.waited()).unwrap_or_default());
+            res
+        }));
+
+        capture_errors(fut, request_id)
+    }
+}
+
+/// Reports unexpected errors of the request to Sentry
+fn capture_errors(fut: ControllerFuture, request_id: Option<String>) -> ControllerFuture {
+    Box::new(fut.map_err(move |err| {
+        let wrapper = ErrorMessageWrapper::<Error>::from(&err);
+        if wrapper.inner.code == 500 {
+            log_and_capture_error(&err, request_id.as_ref().map(String::as_str));
+        }
+        err
+    }))
+}
+
+/// Validates payload before saga is started, so that invalid input is not sent downstream
+fn validate<T: Validate>(payload: T) -> Result<T, FailureError> {
+    payload.validate().map(|_| payload).map_err(|e| Error::Validate(e).into())
+}
+
+/// Response of saga endpoint in shape of requested api version
+#[derive(Serialize)]
+#[serde(untagged)]
+enum SagaResult<T> {
+    Flat(SagaResponse<T>),
+    Envelope(SagaEnvelope<T>),
+}
+
+fn saga_result<T>(version: ApiVersion, response: SagaResponse<T>) -> SagaResult<T> {
+    match version {
+        ApiVersion::V1 => SagaResult::Flat(response),
+        ApiVersion::V2 => SagaResult::Envelope(response.into_envelope()),
+    }
+}
+
+/// Locale of the caller. Locale chosen in the session wins over the most preferred
+/// language of `Accept-Language`.
+fn locale(request_headers: &Headers) -> Option<String> {
+    let raw_header = |name: &str| {
+        request_headers
+            .get_raw(name)
+            .and_then(|raw| raw.one())
+            .and_then(|value| ::std::str::from_utf8(value).ok())
+            .map(str::to_string)
+    };
+    raw_header(SESSION_LOCALE_HEADER)
+        .and_then(|value| parse_locale(&value))
+        .or_else(|| raw_header("Accept-Language").and_then(|value| parse_accept_language(&value)))
+}
+
+/// Version of the entity the caller has seen, given with `If-Match`
+fn if_match_version(request_headers: &Headers) -> Option<EntityVersion> {
+    request_headers
+        .get_raw("If-Match")
+        .and_then(|raw| raw.one())
+        .and_then(|value| ::std::str::from_utf8(value).ok())
+        .and_then(EntityVersion::parse)
+}
+
+/// Language tag with the highest quality, `*` is ignored
+fn parse_accept_language(value: &str) -> Option<String> {
+    let mut languages = value
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let tag = parse_locale(parts.next()?)?;
+            let quality = parts
+                .filter_map(|param| {
+                    let param = param.trim();
+                    if param.starts_with("q=") {
+                        param[2..].parse::<f32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+            Some((tag, quality))
+        })
+        .filter(|&(_, quality)| quality > 0.0)
+        .collect::<Vec<_>>();
+    // stable sort keeps order of languages with the same quality
+    languages.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(::std::cmp::Ordering::Equal));
+    languages.into_iter().next().map(|(tag, _)| tag)
+}
+
+/// Normalized language tag, e.g. `ru-RU`, or `None` if it is not a valid one
+fn parse_locale(value: &str) -> Option<String> {
+    let tag = value.trim();
+    let is_valid = !tag.is_empty()
+        && tag.len() <= 35
+        && tag
+            .split('-')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()));
+    if is_valid {
+        Some(tag.to_string())
+    } else {
+        None
+    }
+}
+
+/// Page of admin list endpoint given with `limit`, `offset` and `order` query parameters
+fn page_request(query: &Query) -> Result<PageRequest, FailureError> {
+    let limit = query.get::<usize>("limit")?.unwrap_or(DEFAULT_PAGE_LIMIT);
+    if limit == 0 || limit > MAX_PAGE_LIMIT {
+        return Err(
+            Error::Validate(validation_errors!({"limit": ["range" => format!("Limit must be from 1 to {}", MAX_PAGE_LIMIT)]})).into(),
+        );
+    }
+    Ok(PageRequest {
+        limit,
+        offset: query.get::<usize>("offset")?.unwrap_or(0),
+        order: query.get::<SortOrder>("order")?.unwrap_or_default(),
+    })
+}
+
+/// Settings of request body parsing for the route
+#[derive(Clone, Copy, Debug)]
+pub struct BodyOptions {
+    /// Maximum size of body in bytes
+    pub limit: usize,
+    /// Reject bodies with unknown fields
+    pub strict: bool,
+}
+
+/// Maximum size of request body accepted by the route
+fn body_limit(limits: &Limits, route: Option<&Route>) -> usize {
+    match route {
+        Some(Route::CreateAccount)
+        | Some(Route::VerifyEmail)
+        | Some(Route::VerifyEmailApply)
+        | Some(Route::ResetPassword)
+        | Some(Route::ResetPasswordApply) => limits.account_body_bytes,
+        Some(Route::OrdersUpdateStateByBilling)
+        | Some(Route::AdminOrdersUpdateStateReplay)
+        | Some(Route::StoreReprice(_))
+        | Some(Route::StoreCatalogImport(_)) => limits.bulk_body_bytes,
+        _ => limits.default_body_bytes,
+    }
+}
+
+/// Reads body of at most `limit` bytes and parses it as json
+fn parse_body<T: DeserializeOwned + 'static>(
+    body: Body,
+    headers: &Headers,
+    options: BodyOptions,
+) -> Box<Future<Item = T, Error = FailureError>> {
+    let strict = options.strict;
+    Box::new(read_body(body, headers, options.limit).and_then(move |bytes| deserialize::<T>(&bytes, strict)))
+}
+
+/// Parses body which may be omitted, empty body gives default payload
+fn parse_optional_body<T: DeserializeOwned + Default + 'static>(
+    body: Body,
+    headers: &Headers,
+    options: BodyOptions,
+) -> Box<Future<Item = T, Error = FailureError>> {
+    let strict = options.strict;
+    Box::new(read_body(body, headers, options.limit).and_then(move |bytes| {
+        if bytes.iter().all(u8::is_ascii_whitespace) {
+            Ok(T::default())
+        } else {
+            deserialize::<T>(&bytes, strict)
+        }
+    }))
+}
+
+/// Reads body of at most `limit` bytes. Reading stops as soon as the limit is exceeded,
+/// so large bodies are never buffered. Bodies compressed with gzip or deflate are decompressed,
+/// the limit applies to decompressed body as well.
+fn read_body(body: Body, headers: &Headers, limit: usize) -> Box<Future<Item = Vec<u8>, Error = FailureError>> {
+    let encodings = headers.get::<ContentEncoding>().map(|header| header.0.clone()).unwrap_or_default();
+    Box::new(
+        body.map_err(|e| FailureError::from(e.context("Reading request body failed").context(Error::Parse)))
+            .fold(Vec::new(), move |mut bytes, chunk| {
+                if bytes.len() + chunk.len() > limit {
+                    return Err(FailureError::from(
+                        format_err!("Request body exceeds limit of {} bytes", limit).context(Error::PayloadTooLarge),
+                    ));
+                }
+                bytes.extend_from_slice(&chunk);
+                Ok(bytes)
+            })
+            .and_then(move |bytes| decode_body(bytes, &encodings, limit)),
+    )
+}
+
+/// Rows of catalog in csv or json body, csv is expected with `Content-Type: text/csv`
+fn parse_catalog(
+    body: Body,
+    headers: &Headers,
+    options: BodyOptions,
+    max_rows: usize,
+) -> Box<Future<Item = Vec<CatalogRow>, Error = FailureError>> {
+    let is_csv = headers
+        .get::<ContentType>()
+        .map(|header| header.0.type_() == mime::TEXT && header.0.subtype() == mime::CSV)
+        .unwrap_or(false);
+
+    let rows = if is_csv {
+        Box::new(read_body(body, headers, options.limit).and_then(|bytes| {
+            csv::parse_records(&bytes)?
+                .iter()
+                .enumerate()
+                .map(|(index, record)| {
+                    CatalogRow::from_csv(record)
+                        .map_err(|e| Error::Validate(validation_errors!({"rows": ["parse" => format!("Row {}: {}", index, e)]})).into())
+                })
+                .collect::<Result<Vec<_>, FailureError>>()
+        })) as Box<Future<Item = Vec<CatalogRow>, Error = FailureError>>
+    } else {
+        parse_body::<Vec<CatalogRow>>(body, headers, options)
+    };
+
+    Box::new(rows.and_then(move |rows| match rows.len() {
+        0 => Err(Error::Validate(validation_errors!({"rows": ["length" => "Catalog has no rows"]})).into()),
+        len if len > max_rows => {
+            Err(Error::Validate(validation_errors!({"rows": ["length" => format!("Catalog has more than {} rows", max_rows)]})).into())
+        }
+        _ => Ok(rows),
+    }))
+}
+
+/// Deserializes json payload. Fields unknown to the model are logged, in strict mode the payload is rejected.
+/// Fields buffered by serde for `#[serde(flatten)]` are not seen as ignored, models with flattened fields
+/// report them through `UnknownFields`.
+pub fn deserialize<T: DeserializeOwned>(bytes: &[u8], strict: bool) -> Result<T, FailureError> {
+    let mut unknown_fields = vec![];
+    let (payload, leftovers) = collect_unknown_fields(|| {
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        serde_ignored::deserialize(&mut deserializer, |path| unknown_fields.push(path.to_string()))
+            .and_then(|payload| deserializer.end().map(|_| payload))
+    });
+    let payload = payload.map_err(|e| FailureError::from(e.context(Error::Parse)))?;
+    unknown_fields.extend(leftovers);
+
+    if !unknown_fields.is_empty() {
+        let fields = unknown_fields.join(", ");
+        if strict {
+            return Err(format_err!("Payload contains unknown fields: {}", fields)
+                .context(Error::Parse)
+                .into());
+        }
+        warn!("Payload contains unknown fields, they are ignored: {}", fields);
     }
+    Ok(payload)
 }
 
-fn default_headers(request_headers: &Headers) -> Headers {
-    let mut headers = Headers::new();
-    if let Some(auth) = request_headers.get::<Authorization<String>>() {
-        headers.set(auth.clone());
+/// Undoes content encodings of body in reverse order of their application
+fn decode_body(mut bytes: Vec<u8>, encodings: &[Encoding], limit: usize) -> Result<Vec<u8>, FailureError> {
+    for encoding in encodings.iter().rev() {
+        bytes = match *encoding {
+            Encoding::Identity => bytes,
+            Encoding::Gzip => decompress(GzDecoder::new(&bytes[..]), limit)?,
+            Encoding::Deflate => decompress(ZlibDecoder::new(&bytes[..]), limit)?,
+            ref other => return Err(format_err!("Unsupported content encoding {}", other).context(Error::Parse).into()),
+        };
     }
-    if let Some(correlation) = request_headers.get::<CorrelationTokenHeader>() {
-        headers.set(correlation.clone());
+    Ok(bytes)
+}
+
+fn decompress<R: Read>(decoder: R, limit: usize) -> Result<Vec<u8>, FailureError> {
+    let mut bytes = Vec::new();
+    decoder
+        .take(limit as u64 + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| FailureError::from(e.context("Decompressing request body failed").context(Error::Parse)))?;
+    if bytes.len() > limit {
+        return Err(format_err!("Decompressed request body exceeds limit of {} bytes", limit)
+            .context(Error::PayloadTooLarge)
+            .into());
     }
-    headers
+    Ok(bytes)
 }
 
-fn stores_headers(request_headers: &Headers) -> Headers {
-    let mut stores_headers = default_headers(request_headers);
-    stores_headers.set(CurrencyHeader("STQ".to_string()));
-    stores_headers.set(FiatCurrencyHeader("USD".to_string()));
-    stores_headers
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use futures::Future;
+    use hyper::header::{Encoding, Headers};
+    use hyper::Body;
+
+    use super::routes::Route;
+    use super::{body_limit, decode_body, parse_accept_language, parse_locale, read_body};
+    use config::Limits;
+    use errors::Error;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn decodes_gzip_and_deflate_bodies() {
+        let body = br#"{"id":1}"#;
+        assert_eq!(decode_body(gzip(body), &[Encoding::Gzip], 1024).unwrap(), body.to_vec());
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        let deflated = encoder.finish().unwrap();
+        assert_eq!(decode_body(deflated, &[Encoding::Deflate], 1024).unwrap(), body.to_vec());
+    }
+
+    #[test]
+    fn rejects_body_exceeding_limit_after_decompression() {
+        let body = vec![b' '; 4096];
+        let compressed = gzip(&body);
+        assert!(compressed.len() < 1024);
+        assert!(decode_body(compressed, &[Encoding::Gzip], 1024).is_err());
+    }
+
+    #[test]
+    fn rejects_body_exceeding_limit_as_payload_too_large() {
+        let body = vec![b' '; 2048];
+        assert_eq!(read_body(Body::from(body.clone()), &Headers::new(), 2048).wait().unwrap(), body);
+
+        let e = read_body(Body::from(body), &Headers::new(), 1024).wait().unwrap_err();
+        assert!(e.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(&Error::PayloadTooLarge) => true,
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn applies_bulk_limit_to_bulk_routes_only() {
+        let limits = Limits {
+            account_body_bytes: 1,
+            bulk_body_bytes: 2,
+            default_body_bytes: 3,
+            downstream_payload_bytes: 4,
+        };
+        assert_eq!(body_limit(&limits, Some(&Route::CreateAccount)), 1);
+        assert_eq!(body_limit(&limits, Some(&Route::OrdersUpdateStateByBilling)), 2);
+        assert_eq!(body_limit(&limits, Some(&Route::StoreModerate)), 3);
+        assert_eq!(body_limit(&limits, Some(&Route::BaseProductModerate)), 3);
+        assert_eq!(body_limit(&limits, None), 3);
+    }
+
+    #[test]
+    fn picks_most_preferred_language() {
+        assert_eq!(parse_accept_language("ru-RU, en;q=0.8").as_ref().map(String::as_str), Some("ru-RU"));
+        assert_eq!(
+            parse_accept_language("en;q=0.5, de;q=0.9, *").as_ref().map(String::as_str),
+            Some("de")
+        );
+        assert_eq!(parse_accept_language("*, en;q=0"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_locale() {
+        assert_eq!(parse_locale(" en-US ").as_ref().map(String::as_str), Some("en-US"));
+        assert_eq!(parse_locale("en&project=wallet"), None);
+        assert_eq!(parse_locale("en--US"), None);
+    }
 }
@@ -0,0 +1,135 @@
+//! Typed parameters of query string. Values are percent-decoded, invalid values and
+//! missing required parameters are reported as validation errors, so that the caller
+//! gets `400 Bad Request` naming the parameter.
+use std::str::FromStr;
+
+use failure::Error as FailureError;
+
+use errors::Error;
+
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    params: Vec<(String, String)>,
+}
+
+impl Query {
+    pub fn parse(query: Option<&str>) -> Self {
+        let params = query
+            .unwrap_or("")
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let mut parts = pair.splitn(2, '=');
+                let name = parts.next().unwrap_or("");
+                let value = parts.next().unwrap_or("");
+                (percent_decode(name), percent_decode(value))
+            })
+            .collect();
+        Self { params }
+    }
+
+    /// Value of parameter, `None` if the parameter is absent. The first value wins if the parameter is repeated.
+    pub fn get<T: FromStr>(&self, name: &'static str) -> Result<Option<T>, FailureError> {
+        match self.raw(name) {
+            None => Ok(None),
+            Some(value) => parse_value(name, value).map(Some),
+        }
+    }
+
+    pub fn require<T: FromStr>(&self, name: &'static str) -> Result<T, FailureError> {
+        self.get(name)?.ok_or_else(|| {
+            Error::Validate(validation_errors!({name: ["required" => format!("Query parameter {} is required", name)]})).into()
+        })
+    }
+
+    /// Flag is on if given without value, e.g. `?dry_run`, or with `true` or `1`, absent flag is off
+    pub fn flag(&self, name: &'static str) -> Result<bool, FailureError> {
+        match self.raw(name) {
+            None => Ok(false),
+            Some("") | Some("1") => Ok(true),
+            Some("0") => Ok(false),
+            Some(value) => parse_value(name, value),
+        }
+    }
+
+    /// Comma separated values, e.g. `?status=completed,reverted`, empty if the parameter is absent
+    pub fn list<T: FromStr>(&self, name: &'static str) -> Result<Vec<T>, FailureError> {
+        match self.raw(name) {
+            None => Ok(vec![]),
+            Some(values) => values
+                .split(',')
+                .filter(|value| !value.is_empty())
+                .map(|value| parse_value(name, value))
+                .collect(),
+        }
+    }
+
+    fn raw(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|&&(ref key, _)| key == name)
+            .map(|&(_, ref value)| value.as_str())
+    }
+}
+
+fn parse_value<T: FromStr>(name: &'static str, value: &str) -> Result<T, FailureError> {
+    value
+        .parse::<T>()
+        .map_err(|_| Error::Validate(validation_errors!({name: ["parse" => format!("Invalid value of query parameter {}", name)]})).into())
+}
+
+/// Decodes `%XX` escapes and `+` as space, malformed escapes are kept as is
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match ::std::str::from_utf8(&bytes[i + 1..i + 3])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Query;
+
+    #[test]
+    fn parses_query_params() {
+        let query = Query::parse(Some("store_id=12&fix=true"));
+        assert_eq!(query.get::<i32>("store_id").unwrap(), Some(12));
+        assert_eq!(query.get::<bool>("fix").unwrap(), Some(true));
+        assert_eq!(query.get::<u32>("tolerance").unwrap(), None);
+        assert_eq!(Query::parse(None).get::<u32>("tolerance").unwrap(), None);
+        assert!(Query::parse(Some("tolerance=-1")).get::<u32>("tolerance").is_err());
+    }
+
+    #[test]
+    fn parses_typed_params() {
+        let query = Query::parse(Some("status=completed,reverted&dry_run&fix=0&name=Tea%20shop+2"));
+        assert_eq!(query.list::<String>("status").unwrap(), vec!["completed", "reverted"]);
+        assert!(query.flag("dry_run").unwrap());
+        assert!(!query.flag("fix").unwrap());
+        assert!(!query.flag("async").unwrap());
+        assert_eq!(query.get::<String>("name").unwrap(), Some("Tea shop 2".to_string()));
+        assert!(query.require::<u32>("limit").is_err());
+        assert!(Query::parse(Some("limit=ten")).get::<u32>("limit").is_err());
+        assert!(Query::parse(Some("dry_run=maybe")).flag("dry_run").is_err());
+    }
+}
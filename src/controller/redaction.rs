@@ -0,0 +1,154 @@
+//! Redaction of successful json responses. Sagas echo full entities of microservices,
+//! e.g. user with phone and birthdate, and not every caller needs all of it. Fields allowed
+//! for the route in `[redaction.routes]` are kept, the rest of the response is removed.
+//! Responses that are not json objects or arrays are sent as is.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::future::{self, Either};
+use futures::prelude::*;
+use hyper;
+use hyper::header::ContentLength;
+use hyper::server::{Request, Response, Service};
+use serde_json::{self, Value};
+
+use stq_router::RouteParser;
+
+use super::routes::{split_version, Route};
+use config::Redaction;
+
+/// Field path split by dots, empty path allows the whole value
+type FieldPath = Vec<String>;
+
+pub struct Redactions<S> {
+    route_parser: Arc<RouteParser<Route>>,
+    /// Allowed fields by route name
+    routes: Arc<HashMap<String, Vec<FieldPath>>>,
+    inner: S,
+}
+
+impl<S> Redactions<S> {
+    pub fn new(route_parser: Arc<RouteParser<Route>>, config: Option<&Redaction>, inner: S) -> Self {
+        let routes = config
+            .map(|config| {
+                config
+                    .routes
+                    .iter()
+                    .map(|(route, fields)| (route.clone(), fields.iter().map(|field| field_path(field)).collect()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            route_parser,
+            routes: Arc::new(routes),
+            inner,
+        }
+    }
+}
+
+impl<S> Service for Redactions<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+    S::Future: 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let allowed = self
+            .route_parser
+            .test(split_version(req.path()).1)
+            .and_then(|route| self.routes.get(&route.name()).cloned());
+        let allowed = match allowed {
+            Some(allowed) => allowed,
+            None => return Box::new(self.inner.call(req)),
+        };
+
+        Box::new(self.inner.call(req).and_then(move |response| {
+            if !response.status().is_success() {
+                return Either::A(future::ok(response));
+            }
+            let headers = response.headers().clone();
+            let status = response.status();
+            Either::B(response.body().concat2().map(move |body| {
+                let body = match serde_json::from_slice::<Value>(&body) {
+                    Ok(value @ Value::Object(_)) | Ok(value @ Value::Array(_)) => redact(value, &allowed).to_string().into_bytes(),
+                    _ => body.to_vec(),
+                };
+                Response::new()
+                    .with_status(status)
+                    .with_headers(headers)
+                    .with_header(ContentLength(body.len() as u64))
+                    .with_body(body)
+            }))
+        }))
+    }
+}
+
+fn field_path(field: &str) -> FieldPath {
+    field
+        .split('.')
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_string())
+        .collect()
+}
+
+/// Keeps allowed fields of objects, every element of arrays is redacted the same way
+fn redact(value: Value, allowed: &[FieldPath]) -> Value {
+    if allowed.iter().any(|path| path.is_empty()) {
+        return value;
+    }
+    match value {
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .filter_map(|(name, value)| {
+                    let nested = allowed
+                        .iter()
+                        .filter(|path| path[0] == name)
+                        .map(|path| path[1..].to_vec())
+                        .collect::<Vec<_>>();
+                    if nested.is_empty() {
+                        None
+                    } else {
+                        Some((name, redact(value, &nested)))
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(items.into_iter().map(|item| redact(item, allowed)).collect()),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{field_path, redact};
+
+    #[test]
+    fn keeps_allowed_fields_only() {
+        let user = json!({
+            "id": 1,
+            "email": "user@example.com",
+            "phone": "+79990000000",
+            "birthdate": "1990-01-01",
+            "roles": [{"id": 1, "name": "user", "data": {"secret": true}}],
+            "store": {"id": 2, "name": "store"},
+        });
+        let allowed = vec![field_path("id"), field_path("email"), field_path("roles.name"), field_path("store")];
+        assert_eq!(
+            redact(user, &allowed),
+            json!({
+                "id": 1,
+                "email": "user@example.com",
+                "roles": [{"name": "user"}],
+                "store": {"id": 2, "name": "store"},
+            })
+        );
+
+        let users = json!([{"id": 1, "phone": "+79990000000"}, {"id": 2}]);
+        assert_eq!(redact(users, &[field_path("id")]), json!([{"id": 1}, {"id": 2}]));
+    }
+}
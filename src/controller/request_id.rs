@@ -0,0 +1,83 @@
+//! Every request gets its own id, returned in `X-Request-Id` response header,
+//! written to logs and Sentry events and passed to downstream services. Unlike
+//! saga id and correlation token it identifies a single http request. Id given
+//! by the caller in `X-Request-Id` is kept, so that the request can be traced
+//! through the gateway and every service it reaches.
+use futures::prelude::*;
+use hyper;
+use hyper::header::Headers;
+use hyper::server::{Request, Response, Service};
+use uuid::Uuid;
+
+use logging;
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+/// Ids of callers are written to logs as is, so they are limited to a safe length and alphabet
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Returns id assigned to request by `RequestId` middleware
+pub fn request_id(headers: &Headers) -> Option<String> {
+    headers
+        .get_raw(REQUEST_ID_HEADER)
+        .and_then(|raw| raw.one())
+        .and_then(|id| String::from_utf8(id.to_vec()).ok())
+}
+
+pub struct RequestId<S> {
+    inner: S,
+}
+
+impl<S> RequestId<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S> Service for RequestId<S>
+where
+    S: Service<Request = Request, Response = Response, Error = hyper::Error>,
+    S::Future: 'static,
+{
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, mut req: Request) -> Self::Future {
+        let id = request_id(req.headers())
+            .filter(|id| is_valid(id))
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        req.headers_mut().set_raw(REQUEST_ID_HEADER, id.clone());
+
+        let request = logging::scoped(Some(id.clone()), || {
+            debug!("Request started: {} {}", req.method(), req.path());
+            self.inner.call(req)
+        });
+        Box::new(logging::in_request(
+            Some(id.clone()),
+            request.map(move |mut response| {
+                debug!("Request finished with status {}", response.status());
+                response.headers_mut().set_raw(REQUEST_ID_HEADER, id);
+                response
+            }),
+        ))
+    }
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid;
+
+    #[test]
+    fn accepts_ids_safe_for_logs() {
+        assert!(is_valid("5f0c1f7e-8b0a-4c4e-9a44-3e2d3b1c0a9f"));
+        assert!(is_valid("gateway.42_a"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("id\nERROR forged line"));
+        assert!(!is_valid(&"a".repeat(129)));
+    }
+}
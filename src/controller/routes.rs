@@ -1,5 +1,9 @@
+use hyper::Method;
+
 use stq_router::RouteParser;
-use stq_types::{BaseProductId, OrderId, OrderSlug, ProductId, StoreId};
+use stq_types::{BaseProductId, OrderId, OrderSlug, ProductId, SagaId, StoreId, UserId};
+
+use models::SagaType;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Route {
@@ -16,14 +20,235 @@ pub enum Route {
     StoreModerate,
     StoreModeration(StoreId),
     StoreDeactivate(StoreId),
+    StoreActivate(StoreId),
+    StoreVerify(StoreId),
+    StoreCreatePayout(StoreId),
+    StoreReprice(StoreId),
+    StoreVacation(StoreId),
+    StoreDraft(StoreId),
+    StoreCatalogImport(StoreId),
+    CatalogImport(SagaId),
     BaseProductUpdate(BaseProductId),
     BaseProductCreateWithVariants,
     BaseProductModerate,
     BaseProductDeactivate(BaseProductId),
+    BaseProductActivate(BaseProductId),
     BaseProductUpsertShipping(BaseProductId),
+    DeliveryQuote,
+    DeliveryTrackingUpdate,
     BaseProductModeration(BaseProductId),
+    BaseProductChangeCategory(BaseProductId),
+    BaseProductVariantsBulk(BaseProductId),
     ProductDeactivate(ProductId),
+    ProductActivate(ProductId),
     OrdersSetPaymentState { order_id: OrderId },
+    OrderDispute { order_id: OrderId },
+    OrderDisputeResolve { order_id: OrderId },
+    OrderReturn { order_slug: OrderSlug },
+    OrderReturnReceive { order_slug: OrderSlug },
+    OrderCancelItem { order_slug: OrderSlug },
+    OrderCancelItems,
+    AdminJobs,
+    AdminModerationOverdue,
+    AdminModerationDeactivations,
+    AdminSagas,
+    AdminOrphanedSagas,
+    AdminSagasPurge,
+    AdminOrdersUpdateStateReplay,
+    AdminOrderDebug(OrderSlug),
+    AdminStoreTakedown(StoreId),
+    AdminSaga(SagaId),
+    AdminSagaCompensations(SagaId),
+    AdminFraudOverrides,
+    AdminFraudOverride(UserId),
+    AdminInventoryReconcile,
+    AdminAudit,
+    AdminConfig,
+    AdminDependencies,
+    AdminShadow,
+    AdminEndpoints,
+    AdminLogLevel,
+    Metrics,
+    Readyz,
+    About,
+}
+
+/// Group of routes served by the same handler
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Domain {
+    Accounts,
+    Stores,
+    Orders,
+    Delivery,
+    Admin,
+}
+
+/// Version of api requested by path prefix. Paths without prefix are served as v1,
+/// v2 changes response shapes of some routes, e.g. saga endpoints return `SagaEnvelope`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+/// Splits version prefix off the path, routes are matched against the rest of it
+pub fn split_version(path: &str) -> (ApiVersion, &str) {
+    if path.starts_with("/v1/") {
+        (ApiVersion::V1, &path[3..])
+    } else if path.starts_with("/v2/") {
+        (ApiVersion::V2, &path[3..])
+    } else {
+        (ApiVersion::V1, path)
+    }
+}
+
+impl Route {
+    /// Http methods the route is served with, besides `HEAD` and `OPTIONS`
+    pub fn methods(&self) -> &'static [Method] {
+        match *self {
+            Route::AdminJobs
+            | Route::AdminModerationOverdue
+            | Route::AdminModerationDeactivations
+            | Route::AdminSagas
+            | Route::AdminOrphanedSagas
+            | Route::AdminSaga(_)
+            | Route::AdminSagaCompensations(_)
+            | Route::AdminOrderDebug(_)
+            | Route::AdminFraudOverrides
+            | Route::AdminInventoryReconcile
+            | Route::AdminAudit
+            | Route::AdminConfig
+            | Route::AdminDependencies
+            | Route::AdminShadow
+            | Route::CatalogImport(_)
+            | Route::Metrics
+            | Route::Readyz
+            | Route::About => &[Method::Get],
+            Route::AdminFraudOverride(_) => &[Method::Put, Method::Delete],
+            Route::AdminLogLevel | Route::AdminEndpoints => &[Method::Get, Method::Put],
+            Route::StoreDraft(_) => &[Method::Put],
+            _ => &[Method::Post],
+        }
+    }
+
+    pub fn domain(&self) -> Domain {
+        match *self {
+            Route::CreateAccount | Route::VerifyEmail | Route::VerifyEmailApply | Route::ResetPassword | Route::ResetPasswordApply => {
+                Domain::Accounts
+            }
+            Route::CreateStore
+            | Route::StoreModerate
+            | Route::StoreModeration(_)
+            | Route::StoreDeactivate(_)
+            | Route::StoreActivate(_)
+            | Route::StoreVerify(_)
+            | Route::StoreCreatePayout(_)
+            | Route::StoreReprice(_)
+            | Route::StoreVacation(_)
+            | Route::StoreDraft(_)
+            | Route::StoreCatalogImport(_)
+            | Route::CatalogImport(_)
+            | Route::BaseProductUpdate(_)
+            | Route::BaseProductCreateWithVariants
+            | Route::BaseProductModerate
+            | Route::BaseProductDeactivate(_)
+            | Route::BaseProductActivate(_)
+            | Route::BaseProductModeration(_)
+            | Route::BaseProductChangeCategory(_)
+            | Route::BaseProductVariantsBulk(_)
+            | Route::ProductDeactivate(_)
+            | Route::ProductActivate(_) => Domain::Stores,
+            Route::CreateOrder
+            | Route::BuyNow
+            | Route::OrdersUpdateStateByBilling
+            | Route::OrdersManualSetState { .. }
+            | Route::OrdersSetPaymentState { .. }
+            | Route::OrderDispute { .. }
+            | Route::OrderDisputeResolve { .. }
+            | Route::OrderReturn { .. }
+            | Route::OrderReturnReceive { .. }
+            | Route::OrderCancelItem { .. }
+            | Route::OrderCancelItems => Domain::Orders,
+            Route::BaseProductUpsertShipping(_) | Route::DeliveryQuote | Route::DeliveryTrackingUpdate => Domain::Delivery,
+            Route::AdminJobs
+            | Route::AdminModerationOverdue
+            | Route::AdminModerationDeactivations
+            | Route::AdminSagas
+            | Route::AdminOrphanedSagas
+            | Route::AdminSagasPurge
+            | Route::AdminOrdersUpdateStateReplay
+            | Route::AdminSaga(_)
+            | Route::AdminSagaCompensations(_)
+            | Route::AdminOrderDebug(_)
+            | Route::AdminStoreTakedown(_)
+            | Route::AdminFraudOverrides
+            | Route::AdminFraudOverride(_)
+            | Route::AdminInventoryReconcile
+            | Route::AdminAudit
+            | Route::AdminConfig
+            | Route::AdminDependencies
+            | Route::AdminShadow
+            | Route::AdminEndpoints
+            | Route::AdminLogLevel
+            | Route::Metrics
+            | Route::Readyz
+            | Route::About => Domain::Admin,
+        }
+    }
+
+    /// Route starts saga in background and responds before it is finished
+    pub fn is_async(&self) -> bool {
+        match *self {
+            Route::StoreCatalogImport(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Type of saga started by the route
+    pub fn saga_type(&self) -> Option<SagaType> {
+        match *self {
+            Route::CreateAccount => Some(SagaType::CreateAccount),
+            Route::CreateStore => Some(SagaType::CreateStore),
+            Route::CreateOrder => Some(SagaType::CreateOrder),
+            Route::BuyNow => Some(SagaType::BuyNow),
+            Route::BaseProductUpsertShipping(_) => Some(SagaType::UpsertShipping),
+            Route::StoreVerify(_) => Some(SagaType::VerifyStore),
+            Route::StoreCreatePayout(_) => Some(SagaType::CreatePayout),
+            Route::OrderDispute { .. } => Some(SagaType::OpenDispute),
+            Route::OrderDisputeResolve { .. } => Some(SagaType::ResolveDispute),
+            Route::OrderReturn { .. } => Some(SagaType::ReturnOrder),
+            Route::OrderReturnReceive { .. } => Some(SagaType::ReceiveReturn),
+            Route::OrderCancelItem { .. } | Route::OrderCancelItems => Some(SagaType::CancelOrderItem),
+            Route::StoreReprice(_) => Some(SagaType::Reprice),
+            Route::StoreVacation(_) => Some(SagaType::StoreVacation),
+            Route::BaseProductChangeCategory(_) => Some(SagaType::ChangeCategory),
+            Route::BaseProductVariantsBulk(_) => Some(SagaType::VariantsBulkEdit),
+            Route::StoreCatalogImport(_) => Some(SagaType::CatalogImport),
+            Route::AdminStoreTakedown(_) => Some(SagaType::TakedownStore),
+            _ => None,
+        }
+    }
+
+    /// Route is polled by dashboards, its responses carry `ETag`
+    pub fn is_tagged(&self) -> bool {
+        match *self {
+            Route::AdminSaga(_) | Route::CatalogImport(_) | Route::AdminDependencies => true,
+            _ => false,
+        }
+    }
+
+    /// Snake case name of the route without its params, e.g. `admin_saga`, used as a key in config
+    pub fn name(&self) -> String {
+        let variant = format!("{:?}", self);
+        let mut name = String::new();
+        for c in variant.chars().take_while(|c| c.is_alphanumeric()) {
+            if c.is_uppercase() && !name.is_empty() {
+                name.push('_');
+            }
+            name.extend(c.to_lowercase());
+        }
+        name
+    }
 }
 
 pub fn create_route_parser() -> RouteParser<Route> {
@@ -61,6 +286,62 @@ pub fn create_route_parser() -> RouteParser<Route> {
             .map(Route::StoreDeactivate)
     });
 
+    router.add_route_with_params(r"^/stores/(\d+)/activate$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<StoreId>().ok())
+            .map(Route::StoreActivate)
+    });
+
+    router.add_route_with_params(r"^/stores/(\d+)/verify$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<StoreId>().ok())
+            .map(Route::StoreVerify)
+    });
+
+    router.add_route_with_params(r"^/stores/(\d+)/payouts$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<StoreId>().ok())
+            .map(Route::StoreCreatePayout)
+    });
+
+    router.add_route_with_params(r"^/stores/(\d+)/reprice$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<StoreId>().ok())
+            .map(Route::StoreReprice)
+    });
+
+    router.add_route_with_params(r"^/stores/(\d+)/vacation$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<StoreId>().ok())
+            .map(Route::StoreVacation)
+    });
+
+    router.add_route_with_params(r"^/stores/(\d+)/draft$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<StoreId>().ok())
+            .map(Route::StoreDraft)
+    });
+
+    router.add_route_with_params(r"^/stores/(\d+)/catalog/import$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<StoreId>().ok())
+            .map(Route::StoreCatalogImport)
+    });
+
+    router.add_route_with_params(r"^/catalog/imports/([a-fA-F0-9-]+)$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<SagaId>().ok())
+            .map(Route::CatalogImport)
+    });
+
     router.add_route(r"^/base_products/moderate$", || Route::BaseProductModerate);
 
     router.add_route_with_params(r"^/base_products/(\d+)/moderation$", |params| {
@@ -70,6 +351,20 @@ pub fn create_route_parser() -> RouteParser<Route> {
             .map(Route::BaseProductModeration)
     });
 
+    router.add_route_with_params(r"^/base_products/(\d+)/change_category$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<BaseProductId>().ok())
+            .map(Route::BaseProductChangeCategory)
+    });
+
+    router.add_route_with_params(r"^/base_products/(\d+)/variants/bulk$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<BaseProductId>().ok())
+            .map(Route::BaseProductVariantsBulk)
+    });
+
     router.add_route_with_params(r"^/base_products/(\d+)/deactivate$", |params| {
         params
             .get(0)
@@ -77,6 +372,13 @@ pub fn create_route_parser() -> RouteParser<Route> {
             .map(Route::BaseProductDeactivate)
     });
 
+    router.add_route_with_params(r"^/base_products/(\d+)/activate$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<BaseProductId>().ok())
+            .map(Route::BaseProductActivate)
+    });
+
     router.add_route_with_params(r"^/base_products/(\d+)/update$", |params| {
         params
             .get(0)
@@ -93,6 +395,9 @@ pub fn create_route_parser() -> RouteParser<Route> {
             .map(Route::BaseProductUpsertShipping)
     });
 
+    router.add_route(r"^/delivery/quote$", || Route::DeliveryQuote);
+    router.add_route(r"^/delivery/tracking_update$", || Route::DeliveryTrackingUpdate);
+
     router.add_route_with_params(r"^/products/(\d+)/deactivate$", |params| {
         params
             .get(0)
@@ -100,6 +405,13 @@ pub fn create_route_parser() -> RouteParser<Route> {
             .map(Route::ProductDeactivate)
     });
 
+    router.add_route_with_params(r"^/products/(\d+)/activate$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<ProductId>().ok())
+            .map(Route::ProductActivate)
+    });
+
     router.add_route(r"^/orders/update_state$", || Route::OrdersUpdateStateByBilling);
 
     router.add_route_with_params(r"^/orders/(\d+)/set_state$", |params| {
@@ -116,5 +428,132 @@ pub fn create_route_parser() -> RouteParser<Route> {
             .map(|order_id| Route::OrdersSetPaymentState { order_id })
     });
 
+    router.add_route_with_params(r"^/orders/([a-zA-Z0-9-]+)/dispute$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|order_id| Route::OrderDispute { order_id })
+    });
+
+    router.add_route_with_params(r"^/orders/([a-zA-Z0-9-]+)/dispute/resolve$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|order_id| Route::OrderDisputeResolve { order_id })
+    });
+
+    router.add_route_with_params(r"^/orders/(\d+)/return$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|order_slug| Route::OrderReturn { order_slug })
+    });
+
+    router.add_route_with_params(r"^/orders/(\d+)/return/receive$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|order_slug| Route::OrderReturnReceive { order_slug })
+    });
+
+    router.add_route_with_params(r"^/orders/(\d+)/cancel$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(|order_slug| Route::OrderCancelItem { order_slug })
+    });
+
+    router.add_route(r"^/orders/cancel$", || Route::OrderCancelItems);
+
+    router.add_route(r"^/admin/jobs$", || Route::AdminJobs);
+
+    router.add_route(r"^/admin/moderation/overdue$", || Route::AdminModerationOverdue);
+    router.add_route(r"^/admin/moderation/deactivations$", || Route::AdminModerationDeactivations);
+
+    router.add_route(r"^/admin/sagas$", || Route::AdminSagas);
+
+    router.add_route(r"^/admin/sagas/orphaned$", || Route::AdminOrphanedSagas);
+
+    router.add_route(r"^/admin/sagas/purge$", || Route::AdminSagasPurge);
+
+    router.add_route(r"^/admin/orders/update_state/replay$", || Route::AdminOrdersUpdateStateReplay);
+
+    router.add_route_with_params(r"^/admin/orders/(\d+)/debug$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse().ok())
+            .map(Route::AdminOrderDebug)
+    });
+
+    router.add_route_with_params(r"^/admin/stores/(\d+)/takedown$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<StoreId>().ok())
+            .map(Route::AdminStoreTakedown)
+    });
+
+    router.add_route_with_params(r"^/admin/sagas/([a-fA-F0-9-]+)$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<SagaId>().ok())
+            .map(Route::AdminSaga)
+    });
+
+    router.add_route_with_params(r"^/admin/sagas/([a-fA-F0-9-]+)/compensations$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<SagaId>().ok())
+            .map(Route::AdminSagaCompensations)
+    });
+
+    router.add_route(r"^/admin/fraud/overrides$", || Route::AdminFraudOverrides);
+
+    router.add_route_with_params(r"^/admin/fraud/overrides/(\d+)$", |params| {
+        params
+            .get(0)
+            .and_then(|string_id| string_id.parse::<UserId>().ok())
+            .map(Route::AdminFraudOverride)
+    });
+
+    router.add_route(r"^/admin/inventory/reconcile$", || Route::AdminInventoryReconcile);
+
+    router.add_route(r"^/admin/audit$", || Route::AdminAudit);
+
+    router.add_route(r"^/admin/config$", || Route::AdminConfig);
+
+    router.add_route(r"^/admin/dependencies$", || Route::AdminDependencies);
+
+    router.add_route(r"^/admin/shadow$", || Route::AdminShadow);
+
+    router.add_route(r"^/admin/endpoints$", || Route::AdminEndpoints);
+
+    router.add_route(r"^/admin/log_level$", || Route::AdminLogLevel);
+
+    router.add_route(r"^/metrics$", || Route::Metrics);
+
+    router.add_route(r"^/readyz$", || Route::Readyz);
+
+    router.add_route(r"^/about$", || Route::About);
+
     router
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_version_prefix() {
+        assert_eq!(split_version("/create_order"), (ApiVersion::V1, "/create_order"));
+        assert_eq!(split_version("/v1/create_order"), (ApiVersion::V1, "/create_order"));
+        assert_eq!(split_version("/v2/create_order"), (ApiVersion::V2, "/create_order"));
+        assert_eq!(split_version("/v3/create_order"), (ApiVersion::V1, "/v3/create_order"));
+    }
+
+    #[test]
+    fn names_routes_in_snake_case() {
+        assert_eq!(Route::CreateAccount.name(), "create_account");
+        assert_eq!(Route::AdminSaga(SagaId::new()).name(), "admin_saga");
+        assert_eq!(Route::StoreActivate(StoreId(1)).name(), "store_activate");
+    }
+}
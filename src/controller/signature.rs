@@ -0,0 +1,52 @@
+//! Signatures of webhooks called by third parties, e.g. tracking updates of couriers.
+//! Body is signed with HMAC-SHA256 using the shared secret, the signature is sent hex
+//! encoded in a header. Signatures are compared in constant time.
+use hyper::header::Headers;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+/// Header with signature of tracking update body
+pub const TRACKING_SIGNATURE_HEADER: &str = "X-Tracking-Signature";
+
+/// Hex encoded signature given in the header
+pub fn signature(headers: &Headers, name: &str) -> Option<String> {
+    headers
+        .get_raw(name)
+        .and_then(|raw| raw.one())
+        .and_then(|value| ::std::str::from_utf8(value).ok())
+        .map(|value| value.trim().to_lowercase())
+}
+
+/// Whether `signature` is the hex encoded HMAC-SHA256 of `body` with `secret`
+pub fn verify(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let expected = match sign(secret, body) {
+        Some(expected) => expected,
+        None => return false,
+    };
+    expected.len() == signature.len() && memcmp::eq(expected.as_bytes(), signature.as_bytes())
+}
+
+fn sign(secret: &[u8], body: &[u8]) -> Option<String> {
+    let key = PKey::hmac(secret).ok()?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).ok()?;
+    signer.update(body).ok()?;
+    let digest = signer.sign_to_vec().ok()?;
+    Some(digest.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_hmac_sha256() {
+        let body = b"what do ya want for nothing?";
+        let signature = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+        assert!(verify(b"Jefe", body, signature));
+        assert!(!verify(b"Jeff", body, signature));
+        assert!(!verify(b"Jefe", b"what do ya want?", signature));
+        assert!(!verify(b"Jefe", body, &signature[1..]));
+    }
+}
@@ -16,6 +16,10 @@ pub enum Error {
     HttpClient,
     #[fail(display = "Server is refusing to fullfil the reqeust")]
     Forbidden,
+    #[fail(display = "Payload too large")]
+    PayloadTooLarge,
+    #[fail(display = "Conflicting operation is in progress")]
+    Conflict,
     #[fail(display = "Unknown server error")]
     Unknown,
 }
@@ -28,6 +32,8 @@ impl Codeable for Error {
             Error::Parse => StatusCode::UnprocessableEntity,
             Error::HttpClient | Error::Unknown => StatusCode::InternalServerError,
             Error::Forbidden => StatusCode::Forbidden,
+            Error::PayloadTooLarge => StatusCode::PayloadTooLarge,
+            Error::Conflict => StatusCode::Conflict,
         }
     }
 }
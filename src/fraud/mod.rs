@@ -0,0 +1,49 @@
+//! Fraud screening of checkouts and admin overrides of its decisions
+pub mod overrides;
+
+pub use self::overrides::{FraudOverrides, FraudOverridesImpl};
+
+use std::sync::Arc;
+
+use config;
+use microservice::FraudScoringMicroservice;
+use models::FraudDecision;
+
+/// Dependencies of fraud screening step of order saga
+#[derive(Clone)]
+pub struct FraudScreener {
+    pub config: config::FraudScreening,
+    pub scoring: Arc<FraudScoringMicroservice>,
+    pub overrides: Arc<FraudOverrides>,
+}
+
+/// Decision on checkout by its score and configured thresholds
+pub fn decide(config: &config::FraudScreening, score: f64) -> FraudDecision {
+    if score >= config.block_score {
+        FraudDecision::Block
+    } else if score >= config.flag_score {
+        FraudDecision::Flag
+    } else {
+        FraudDecision::Pass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decides_by_thresholds() {
+        let config = config::FraudScreening {
+            url: "http://fraud-scoring".to_string(),
+            flag_score: 0.5,
+            block_score: 0.9,
+            fail_open: false,
+            overrides_path: None,
+        };
+        assert_eq!(decide(&config, 0.1), FraudDecision::Pass);
+        assert_eq!(decide(&config, 0.5), FraudDecision::Flag);
+        assert_eq!(decide(&config, 0.89), FraudDecision::Flag);
+        assert_eq!(decide(&config, 0.9), FraudDecision::Block);
+    }
+}
@@ -0,0 +1,132 @@
+//! Decisions admins force on checkouts of customers, they take precedence
+//! over scores of fraud scoring service.
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use failure::Error as FailureError;
+
+use stq_types::UserId;
+
+use json_file::JsonFile;
+use models::{FraudOverride, FraudOverrideInput};
+
+pub trait FraudOverrides {
+    /// Sets override of the customer, replacing the previous one
+    fn set(&self, customer_id: UserId, input: FraudOverrideInput) -> Result<FraudOverride, FailureError>;
+    /// Removes override of the customer, returns the removed one
+    fn remove(&self, customer_id: UserId) -> Result<Option<FraudOverride>, FailureError>;
+    fn get(&self, customer_id: UserId) -> Result<Option<FraudOverride>, FailureError>;
+    /// All overrides, the latest first
+    fn list(&self) -> Result<Vec<FraudOverride>, FailureError>;
+}
+
+/// Keeps overrides in memory, optionally mirroring them into json file
+pub struct FraudOverridesImpl {
+    overrides: Mutex<HashMap<UserId, FraudOverride>>,
+    file: Option<JsonFile>,
+}
+
+impl FraudOverridesImpl {
+    pub fn new(path: Option<PathBuf>) -> Result<Self, FailureError> {
+        let file = path.map(|path| JsonFile::new(path, "fraud overrides"));
+        let content = match file {
+            Some(ref file) => file.read::<Vec<FraudOverride>>()?,
+            None => None,
+        };
+        let overrides = content
+            .unwrap_or_default()
+            .into_iter()
+            .map(|item| (item.customer_id, item))
+            .collect();
+
+        Ok(Self {
+            overrides: Mutex::new(overrides),
+            file,
+        })
+    }
+
+    fn flush(&self, overrides: &HashMap<UserId, FraudOverride>) -> Result<(), FailureError> {
+        if let Some(ref file) = self.file {
+            let content = overrides.values().cloned().collect::<Vec<_>>();
+            file.write(&content)?;
+        }
+        Ok(())
+    }
+}
+
+impl FraudOverrides for FraudOverridesImpl {
+    fn set(&self, customer_id: UserId, input: FraudOverrideInput) -> Result<FraudOverride, FailureError> {
+        let mut overrides = self.overrides.lock().unwrap();
+        let item = FraudOverride {
+            customer_id,
+            decision: input.decision,
+            comment: input.comment,
+            created_at: SystemTime::now(),
+        };
+        overrides.insert(customer_id, item.clone());
+        self.flush(&overrides)?;
+        Ok(item)
+    }
+
+    fn remove(&self, customer_id: UserId) -> Result<Option<FraudOverride>, FailureError> {
+        let mut overrides = self.overrides.lock().unwrap();
+        let removed = overrides.remove(&customer_id);
+        if removed.is_some() {
+            self.flush(&overrides)?;
+        }
+        Ok(removed)
+    }
+
+    fn get(&self, customer_id: UserId) -> Result<Option<FraudOverride>, FailureError> {
+        Ok(self.overrides.lock().unwrap().get(&customer_id).cloned())
+    }
+
+    fn list(&self) -> Result<Vec<FraudOverride>, FailureError> {
+        let mut items = self.overrides.lock().unwrap().values().cloned().collect::<Vec<_>>();
+        items.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stq_types::UserId;
+
+    use super::{FraudOverrides, FraudOverridesImpl};
+    use models::{FraudOverrideDecision, FraudOverrideInput};
+
+    #[test]
+    fn replaces_override_of_customer() {
+        let overrides = FraudOverridesImpl::new(None).unwrap();
+        let customer_id = UserId(1);
+        overrides
+            .set(
+                customer_id,
+                FraudOverrideInput {
+                    decision: FraudOverrideDecision::Block,
+                    comment: None,
+                },
+            )
+            .unwrap();
+        overrides
+            .set(
+                customer_id,
+                FraudOverrideInput {
+                    decision: FraudOverrideDecision::Allow,
+                    comment: Some("verified by phone".to_string()),
+                },
+            )
+            .unwrap();
+
+        assert_eq!(overrides.list().unwrap().len(), 1);
+        assert_eq!(
+            overrides.get(customer_id).unwrap().map(|item| item.decision),
+            Some(FraudOverrideDecision::Allow)
+        );
+
+        assert!(overrides.remove(customer_id).unwrap().is_some());
+        assert!(overrides.get(customer_id).unwrap().is_none());
+    }
+}
@@ -0,0 +1,245 @@
+//! Nudges stores by email about paid orders they have not taken into processing
+//! within SLA, once per order. If escalation is enabled, moderators are told about
+//! orders still not acknowledged later on, once per order as well. Orders are looked
+//! up before acting, those which moved on meanwhile are dropped from timers. Stores
+//! on vacation are left alone until they are back.
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use failure::Error as FailureError;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use tokio_timer::Interval;
+
+use stq_static_resources::{EmailUser, OrderState};
+use stq_types::OrderIdentifier;
+
+use super::JobContext;
+use acknowledgment::AcknowledgmentTimers;
+use config;
+use microservice::{Initiator, NotificationsMicroservice, StoresMicroservice, UsersMicroservice};
+use models::*;
+
+/// Action due on order awaiting acknowledgment
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Due {
+    Nudge,
+    Escalation,
+}
+
+pub fn run(
+    ctx: JobContext,
+    order_acknowledgment: config::OrderAcknowledgment,
+    timers: Arc<AcknowledgmentTimers>,
+) -> impl Future<Item = (), Error = ()> {
+    let period = Duration::from_secs(order_acknowledgment.check_interval_s);
+    Interval::new(Instant::now() + period, period)
+        .map_err(|e| error!("Order acknowledgment timer error: {}", e))
+        .for_each(move |_| check(ctx.clone(), &order_acknowledgment, timers.clone()))
+}
+
+fn check(
+    ctx: JobContext,
+    order_acknowledgment: &config::OrderAcknowledgment,
+    timers: Arc<AcknowledgmentTimers>,
+) -> Box<Future<Item = (), Error = ()>> {
+    let now = SystemTime::now();
+    let overdue = match timers.pending() {
+        Ok(pending) => pending
+            .into_iter()
+            .filter(|pending| !ctx.vacations.is_on_vacation(pending.store_id))
+            .filter_map(|pending| due(&pending, order_acknowledgment, now).map(|due| (pending, due)))
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            error!("Could not load acknowledgment timers: {}", e);
+            return Box::new(future::ok(()));
+        }
+    };
+    if overdue.is_empty() {
+        return Box::new(future::ok(()));
+    }
+    warn!("{} paid orders are not acknowledged by their stores in time", overdue.len());
+
+    let executor = ctx.executor.clone();
+    Box::new(executor.run(
+        SagaPriority::Housekeeping,
+        iter_ok::<_, ()>(overdue).for_each(move |(pending, due)| act(&ctx, timers.clone(), pending, due)),
+    ))
+}
+
+/// Store is nudged once SLA is over, moderators are told only after the store was nudged
+fn due(pending: &PendingAcknowledgment, order_acknowledgment: &config::OrderAcknowledgment, now: SystemTime) -> Option<Due> {
+    if pending.nudged_at.is_none() {
+        return if pending.is_overdue(Duration::from_secs(order_acknowledgment.sla_s), now) {
+            Some(Due::Nudge)
+        } else {
+            None
+        };
+    }
+    match order_acknowledgment.escalate_after_s {
+        Some(escalate_after_s) if pending.escalated_at.is_none() && pending.is_overdue(Duration::from_secs(escalate_after_s), now) => {
+            Some(Due::Escalation)
+        }
+        _ => None,
+    }
+}
+
+fn act(
+    ctx: &JobContext,
+    timers: Arc<AcknowledgmentTimers>,
+    pending: PendingAcknowledgment,
+    due: Due,
+) -> impl Future<Item = (), Error = ()> {
+    let ms = ctx.microservices();
+    let stores = ms.stores.clone();
+    let users = ms.users.clone();
+    let notifications = ms.notifications.clone();
+    let cluster_url = ctx.config.cluster.url.clone();
+    let order_id = pending.order_id;
+    let order_slug = pending.order_slug;
+
+    ms.orders
+        .get_order(Some(Initiator::Superadmin), OrderIdentifier::Id(order_id))
+        .and_then(move |order| match order {
+            Some(ref order) if order.state == OrderState::Paid => Either::A(match due {
+                Due::Nudge => {
+                    Either::A(nudge_store(stores, notifications, &pending, cluster_url).and_then(move |_| timers.mark_nudged(order_id)))
+                }
+                Due::Escalation => Either::B(
+                    tell_moderators(stores, users, notifications, &pending, cluster_url).and_then(move |_| timers.mark_escalated(order_id)),
+                ),
+            }),
+            _ => {
+                info!("Order {} is no longer awaiting acknowledgment", order_slug);
+                Either::B(future::result(timers.stop(order_id)))
+            }
+        })
+        .then(move |res| {
+            if let Err(e) = res {
+                warn!("Checking acknowledgment of order {} failed: {}", order_slug, e);
+            }
+            Ok(())
+        })
+}
+
+fn hours_waiting(pending: &PendingAcknowledgment) -> u64 {
+    SystemTime::now()
+        .duration_since(pending.paid_at)
+        .map(|waiting| waiting.as_secs() / 3600)
+        .unwrap_or_default()
+}
+
+/// Stores without email are not nudged, they are not nudged later on either
+fn nudge_store(
+    stores: Arc<StoresMicroservice>,
+    notifications: Arc<NotificationsMicroservice>,
+    pending: &PendingAcknowledgment,
+    cluster_url: String,
+) -> impl Future<Item = (), Error = FailureError> {
+    let store_id = pending.store_id;
+    let order_slug = pending.order_slug;
+    let hours_waiting = hours_waiting(pending);
+
+    stores
+        .get(store_id, Visibility::Active)
+        .and_then(move |store| match store.and_then(|store| store.email) {
+            Some(store_email) => {
+                info!("Store {} is nudged about order {}", store_id, order_slug);
+                let email = OrderAcknowledgmentOverdueForStore {
+                    store_email,
+                    store_id: store_id.to_string(),
+                    order_slug: order_slug.to_string(),
+                    hours_waiting,
+                    cluster_url,
+                };
+                Either::A(notifications.order_acknowledgment_overdue_for_store(Initiator::Superadmin, email))
+            }
+            None => {
+                warn!("Store {} has no email to be nudged about order {}", store_id, order_slug);
+                Either::B(future::ok(()))
+            }
+        })
+}
+
+/// Failure to email one of moderators does not stop the others
+fn tell_moderators(
+    stores: Arc<StoresMicroservice>,
+    users: Arc<UsersMicroservice>,
+    notifications: Arc<NotificationsMicroservice>,
+    pending: &PendingAcknowledgment,
+    cluster_url: String,
+) -> impl Future<Item = (), Error = FailureError> {
+    let store_id = pending.store_id;
+    let order_slug = pending.order_slug;
+    let hours_waiting = hours_waiting(pending);
+    info!(
+        "Moderators are told about order {} not acknowledged by store {}",
+        order_slug, store_id
+    );
+
+    stores.get_moderators(Initiator::Superadmin).and_then(move |moderator_ids| {
+        iter_ok::<_, FailureError>(moderator_ids).for_each(move |moderator_id| {
+            let notifications = notifications.clone();
+            let cluster_url = cluster_url.clone();
+            users
+                .get(Some(Initiator::Superadmin), moderator_id)
+                .and_then(move |moderator| match moderator {
+                    Some(user) => {
+                        let email = OrderAcknowledgmentOverdueForModerator {
+                            user: EmailUser {
+                                email: user.email.clone(),
+                                first_name: user.first_name.unwrap_or_else(|| "user".to_string()),
+                                last_name: user.last_name.unwrap_or_else(|| "".to_string()),
+                            },
+                            store_id: store_id.to_string(),
+                            order_slug: order_slug.to_string(),
+                            hours_waiting,
+                            cluster_url,
+                        };
+                        Either::A(notifications.order_acknowledgment_overdue_for_moderator(Initiator::Superadmin, email))
+                    }
+                    None => Either::B(future::ok(())),
+                })
+                .then(|_| Ok::<_, FailureError>(()))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use stq_types::{OrderId, OrderSlug, StoreId};
+
+    use super::{due, Due};
+    use config::OrderAcknowledgment;
+    use models::PendingAcknowledgment;
+
+    #[test]
+    fn escalates_only_after_nudge() {
+        let order_acknowledgment = OrderAcknowledgment {
+            timers_path: None,
+            sla_s: 3600,
+            escalate_after_s: Some(7200),
+            check_interval_s: 600,
+        };
+        let mut pending = PendingAcknowledgment::new(OrderId::new(), OrderSlug(1), StoreId(1));
+        let now = pending.paid_at;
+        assert_eq!(due(&pending, &order_acknowledgment, now), None);
+        assert_eq!(
+            due(&pending, &order_acknowledgment, now + Duration::from_secs(8000)),
+            Some(Due::Nudge)
+        );
+
+        pending.nudged_at = Some(SystemTime::now());
+        assert_eq!(due(&pending, &order_acknowledgment, now + Duration::from_secs(4000)), None);
+        assert_eq!(
+            due(&pending, &order_acknowledgment, now + Duration::from_secs(8000)),
+            Some(Due::Escalation)
+        );
+
+        pending.escalated_at = Some(SystemTime::now());
+        assert_eq!(due(&pending, &order_acknowledgment, now + Duration::from_secs(8000)), None);
+    }
+}
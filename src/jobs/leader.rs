@@ -0,0 +1,76 @@
+//! Leader election of coordinator replicas. Low stock digest reads stores and stocks
+//! shared by all replicas, so it runs only on the leader and stores are not emailed
+//! twice. Jobs working on data kept by the replica itself, like saga log, vacations,
+//! moderation queue and acknowledgment timers, run on every replica. Leader holds
+//! a lease in Redis and renews it periodically, replica that can not renew the lease
+//! stops leading right away, before the lease expires and another replica takes it over.
+//! Redis is called with blocking io on a separate thread, not on the reactor.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+use futures_cpupool::Builder as CpuPoolBuilder;
+use tokio_timer::Interval;
+
+use config;
+use redis::LeaseLock;
+
+/// Whether the replica runs leader only jobs
+#[derive(Clone, Debug)]
+pub struct Leadership {
+    is_leader: Arc<AtomicBool>,
+}
+
+impl Leadership {
+    /// Replica runs every job, for deployments with single replica
+    pub fn single() -> Self {
+        Self {
+            is_leader: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Replica runs leader only jobs after it acquires the lease
+    pub fn elected() -> Self {
+        Self {
+            is_leader: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.is_leader.load(Ordering::SeqCst)
+    }
+
+    fn set(&self, is_leader: bool) {
+        if self.is_leader.swap(is_leader, Ordering::SeqCst) != is_leader {
+            if is_leader {
+                info!("Coordinator replica became the leader, leader only jobs are started");
+            } else {
+                warn!("Coordinator replica is no longer the leader, leader only jobs are stopped");
+            }
+        }
+    }
+}
+
+pub fn run<L: LeaseLock + 'static>(lock: L, leadership: Leadership, config: config::LeaderElection) -> impl Future<Item = (), Error = ()> {
+    let period = Duration::from_millis(config.renew_interval_ms);
+    let pool = CpuPoolBuilder::new().pool_size(1).name_prefix("leader-election-").create();
+    let lock = Arc::new(lock);
+    Interval::new(Instant::now(), period)
+        .map_err(|e| error!("Leader election timer error: {}", e))
+        .for_each(move |_| {
+            let lock = lock.clone();
+            let leadership = leadership.clone();
+            // the next renewal waits for this one, so calls to Redis never pile up
+            pool.spawn_fn(move || lock.acquire()).then(move |res| {
+                match res {
+                    Ok(is_leader) => leadership.set(is_leader),
+                    Err(e) => {
+                        warn!("Renewing leader lease failed: {}", e);
+                        leadership.set(false);
+                    }
+                }
+                Ok(())
+            })
+        })
+}
@@ -0,0 +1,208 @@
+//! Emails stores a digest of their products running out of stock at scheduled
+//! times. Products reported in a digest are not reported again until they are
+//! restocked above the threshold. Stores are checked one by one, failure to check
+//! a store does not stop the others. Reported products are kept in memory only,
+//! so a new leader replica reports every product low in stock once.
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use futures::future::{self, Either, Loop};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use tokio_timer::Delay;
+
+use stq_types::{ProductId, Quantity, StoreId};
+
+use super::schedule::Schedule;
+use super::JobContext;
+use config;
+use microservice::Initiator;
+use models::*;
+use services::inventory::stock_quantities;
+
+/// Products of stores reported since they went low in stock
+type Reported = HashMap<StoreId, HashSet<ProductId>>;
+
+pub fn run(ctx: JobContext, low_stock: config::LowStock, schedule: Schedule) -> impl Future<Item = (), Error = ()> {
+    info!("Low stock digest is sent by schedule in {} time zone", schedule.time_zone());
+    future::loop_fn(Reported::new(), move |reported| {
+        let now = Utc::now();
+        let next_run = schedule.next_run(now);
+        debug!("Next low stock digest at {}", next_run);
+        let delay = (next_run - now).to_std().unwrap_or_else(|_| Duration::from_secs(0));
+        let ctx = ctx.clone();
+        let low_stock = low_stock.clone();
+        Delay::new(Instant::now() + delay)
+            .map_err(|e| error!("Low stock timer error: {}", e))
+            .and_then(move |_| {
+                // Digest is sent by the leader replica only
+                if ctx.leadership.is_leader() {
+                    Either::A(check(ctx, low_stock, reported))
+                } else {
+                    Either::B(future::ok(reported))
+                }
+            })
+            .map(Loop::Continue)
+    })
+}
+
+fn check(ctx: JobContext, low_stock: config::LowStock, reported: Reported) -> impl Future<Item = Reported, Error = ()> {
+    ctx.microservices()
+        .stores
+        .get_store_ids(Some(Initiator::Superadmin))
+        .then(move |res| {
+            let store_ids = match res {
+                Ok(store_ids) => store_ids,
+                Err(e) => {
+                    error!("Could not get stores to check low stocks: {}", e);
+                    vec![]
+                }
+            };
+            let stores = store_ids
+                .into_iter()
+                .filter(|store_id| !ctx.vacations.is_on_vacation(*store_id))
+                .filter_map(|store_id| low_stock.threshold(store_id).map(|threshold| (store_id, threshold)))
+                .collect::<Vec<_>>();
+            iter_ok::<_, ()>(stores).fold(reported, move |mut reported, (store_id, threshold)| {
+                let previous = reported.remove(&store_id).unwrap_or_default();
+                check_store(&ctx, store_id, threshold, previous).map(move |products| {
+                    if !products.is_empty() {
+                        reported.insert(store_id, products);
+                    }
+                    reported
+                })
+            })
+        })
+}
+
+/// Sends digest of products low in stock not reported before, resolves to products reported since they went low
+fn check_store(
+    ctx: &JobContext,
+    store_id: StoreId,
+    threshold: u32,
+    reported: HashSet<ProductId>,
+) -> impl Future<Item = HashSet<ProductId>, Error = ()> {
+    let ms = ctx.microservices();
+    let warehouses = ms.warehouses.clone();
+    let notifications = ms.notifications.clone();
+    let cluster_url = ctx.config.cluster.url.clone();
+
+    let products = ms
+        .stores
+        .get_products_by_store(store_id)
+        .and_then(move |products| stock_quantities(warehouses, products))
+        .map(move |quantities| low_stock_products(quantities, threshold));
+
+    let previous = reported.clone();
+    ms.stores
+        .get(store_id, Visibility::Active)
+        .join(products)
+        .and_then(move |(store, products)| {
+            let low = products.iter().map(|product| product.product_id).collect::<HashSet<_>>();
+            let products = unreported(products, &reported);
+            match store.and_then(|store| store.email) {
+                Some(store_email) if !products.is_empty() => {
+                    info!("{} products of store {} are low in stock", products.len(), store_id);
+                    let digest = LowStockForStore {
+                        store_email,
+                        store_id: store_id.to_string(),
+                        threshold,
+                        products,
+                        cluster_url,
+                    };
+                    Either::A(notifications.low_stock_for_store(Initiator::Superadmin, digest).map(move |_| low))
+                }
+                // restocked products are forgotten, so that they are reported once they run low again
+                _ => Either::B(future::ok(low.intersection(&reported).cloned().collect())),
+            }
+        })
+        .then(move |res| match res {
+            Ok(reported) => Ok(reported),
+            Err(e) => {
+                warn!("Checking low stocks of store {} failed: {}", store_id, e);
+                Ok(previous)
+            }
+        })
+}
+
+/// Products that were not reported since they went low in stock
+fn unreported(products: Vec<LowStockProduct>, reported: &HashSet<ProductId>) -> Vec<LowStockProduct> {
+    products
+        .into_iter()
+        .filter(|product| !reported.contains(&product.product_id))
+        .collect()
+}
+
+/// Products with stock below the threshold, the scarcest first
+fn low_stock_products<P>(quantities: P, threshold: u32) -> Vec<LowStockProduct>
+where
+    P: IntoIterator<Item = (Product, Quantity)>,
+{
+    let mut products = quantities
+        .into_iter()
+        .filter(|&(_, quantity)| quantity.0 < threshold as i32)
+        .map(|(product, quantity)| LowStockProduct {
+            product_id: product.id,
+            base_product_id: product.base_product_id,
+            quantity,
+        })
+        .collect::<Vec<_>>();
+    products.sort_by_key(|product| product.quantity.0);
+    products
+}
+
+#[cfg(test)]
+mod tests {
+    use stq_static_resources::Currency;
+    use stq_types::{BaseProductId, ProductId, ProductPrice, Quantity};
+
+    use std::collections::HashSet;
+
+    use super::{low_stock_products, unreported};
+    use models::{CustomerPrice, Product};
+
+    fn product(id: i32) -> Product {
+        Product {
+            uuid: id.to_string(),
+            id: ProductId(id),
+            base_product_id: BaseProductId(id),
+            is_active: true,
+            discount: None,
+            photo_main: None,
+            additional_photos: None,
+            vendor_code: id.to_string(),
+            cashback: None,
+            currency: Currency::STQ,
+            price: ProductPrice(1.0),
+            pre_order: false,
+            pre_order_days: 0,
+            customer_price: CustomerPrice {
+                price: ProductPrice(1.0),
+                currency: Currency::STQ,
+            },
+            quantity: None,
+        }
+    }
+
+    #[test]
+    fn reports_products_below_threshold() {
+        let quantities = vec![(product(1), Quantity(3)), (product(2), Quantity(5)), (product(3), Quantity(0))];
+        let products = low_stock_products(quantities, 5);
+        assert_eq!(
+            products.iter().map(|product| product.product_id).collect::<Vec<_>>(),
+            vec![ProductId(3), ProductId(1)]
+        );
+    }
+
+    #[test]
+    fn skips_reported_products() {
+        let quantities = vec![(product(1), Quantity(3)), (product(2), Quantity(1))];
+        let reported = vec![ProductId(1)].into_iter().collect::<HashSet<_>>();
+        let products = unreported(low_stock_products(quantities, 5), &reported);
+        assert_eq!(
+            products.iter().map(|product| product.product_id).collect::<Vec<_>>(),
+            vec![ProductId(2)]
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! Background jobs running on the same reactor as http server
+pub mod acknowledgment;
+pub mod leader;
+pub mod low_stock;
+pub mod moderation;
+pub mod reaper;
+pub mod recovery;
+pub mod retention;
+pub mod saga_stats;
+pub mod schedule;
+pub mod secrets;
+pub mod statsd;
+pub mod vacation;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use failure::Error as FailureError;
+use hyper::header::Headers;
+
+use stq_http::client::{HttpClientWithDefaultHeaders, TimeLimitedHttpClient};
+use stq_http::request_util::{Currency as CurrencyHeader, FiatCurrency as FiatCurrencyHeader};
+
+use audit::{AuditLog, AuditScope};
+use config::Config;
+use controller::margin::ProcessingMargin;
+use microservice::*;
+use moderation::ModerationQueue;
+use saga::{SagaExecutor, SagaStore};
+use vacations::StoreVacations;
+
+use self::leader::Leadership;
+use self::schedule::{JobInfo, Schedule};
+
+#[derive(Clone)]
+pub struct JobContext {
+    pub config: Config,
+    pub http_client: BaseHttpClient,
+    pub saga_store: Arc<SagaStore>,
+    pub moderation_queue: Arc<ModerationQueue>,
+    pub vacations: Arc<StoreVacations>,
+    pub audit_log: Arc<AuditLog>,
+    pub leadership: Leadership,
+    pub executor: SagaExecutor,
+    pub breakers: CircuitBreakers,
+    pub monitor: DependencyMonitor,
+    pub margin: ProcessingMargin,
+}
+
+/// Microservice clients acting on behalf of saga coordinator itself
+pub struct Microservices {
+    pub users: Arc<UsersMicroservice>,
+    pub stores: Arc<StoresMicroservice>,
+    pub orders: Arc<OrdersMicroservice>,
+    pub billing: Arc<BillingMicroservice>,
+    pub warehouses: Arc<WarehousesMicroservice>,
+    pub notifications: Arc<NotificationsMicroservice>,
+    pub delivery: Arc<DeliveryMicroservice>,
+}
+
+impl JobContext {
+    pub fn microservices(&self) -> Microservices {
+        let http_client = TimeLimitedHttpClient::new(self.http_client.clone(), Duration::from_millis(self.config.client.http_timeout_ms));
+
+        let mut stores_headers = Headers::new();
+        stores_headers.set(CurrencyHeader("STQ".to_string()));
+        stores_headers.set(FiatCurrencyHeader("USD".to_string()));
+
+        let mut currency_headers = Headers::new();
+        currency_headers.set(CurrencyHeader(self.config.client.default_currency.clone()));
+        currency_headers.set(FiatCurrencyHeader(self.config.client.default_fiat_currency.clone()));
+
+        let audit = AuditScope::new(self.audit_log.clone(), "background job".to_string());
+
+        Microservices {
+            users: Arc::new(
+                UsersMicroserviceImpl::new(
+                    HttpClientWithDefaultHeaders::new(http_client.clone(), Headers::new()),
+                    self.config.clone(),
+                )
+                .with_audit(audit.clone())
+                .with_monitor(self.monitor.clone()),
+            ),
+            stores: Arc::new(
+                StoresMicroserviceImpl::new(
+                    HttpClientWithDefaultHeaders::new(http_client.clone(), stores_headers),
+                    self.config.clone(),
+                )
+                .with_audit(audit.clone())
+                .with_monitor(self.monitor.clone()),
+            ),
+            orders: Arc::new(
+                OrdersMicroserviceImpl::new(
+                    HttpClientWithDefaultHeaders::new(http_client.clone(), currency_headers.clone()),
+                    self.config.clone(),
+                )
+                .with_audit(audit.clone())
+                .with_monitor(self.monitor.clone()),
+            ),
+            billing: Arc::new(
+                BillingMicroserviceImpl::new(
+                    HttpClientWithDefaultHeaders::new(http_client.clone(), currency_headers),
+                    self.config.clone(),
+                )
+                .with_audit(audit.clone())
+                .with_monitor(self.monitor.clone()),
+            ),
+            warehouses: Arc::new(
+                WarehousesMicroserviceImpl::new(
+                    HttpClientWithDefaultHeaders::new(http_client.clone(), Headers::new()),
+                    self.config.clone(),
+                )
+                .with_audit(audit.clone())
+                .with_monitor(self.monitor.clone()),
+            ),
+            notifications: Arc::new(
+                NotificationsMicroserviceImpl::new(
+                    HttpClientWithDefaultHeaders::new(http_client.clone(), Headers::new()),
+                    self.config.clone(),
+                )
+                .with_audit(audit.clone())
+                .with_monitor(self.monitor.clone())
+                .with_breakers(self.breakers.clone()),
+            ),
+            delivery: Arc::new(
+                DeliveryMicroserviceImpl::new(HttpClientWithDefaultHeaders::new(http_client, Headers::new()), self.config.clone())
+                    .with_audit(audit.clone())
+                    .with_monitor(self.monitor.clone()),
+            ),
+        }
+    }
+}
+
+/// Schedule of reaper if it is configured to run at fixed times
+pub fn reaper_schedule(config: &Config) -> Result<Option<Schedule>, FailureError> {
+    match config.saga.reaper_schedule {
+        Some(ref schedule) => Schedule::from_config(schedule)
+            .map(Some)
+            .map_err(|e| e.context("Invalid reaper schedule").into()),
+        None => Ok(None),
+    }
+}
+
+/// Schedule of low stock digest if the digest is enabled
+pub fn low_stock_schedule(config: &Config) -> Result<Option<Schedule>, FailureError> {
+    match config.low_stock {
+        Some(ref low_stock) => Schedule::from_config(&low_stock.schedule)
+            .map(Some)
+            .map_err(|e| e.context("Invalid low stock schedule").into()),
+        None => Ok(None),
+    }
+}
+
+/// Background jobs with their next run times
+pub fn jobs_info(config: &Config) -> Result<Vec<JobInfo>, FailureError> {
+    let reaper = match reaper_schedule(config)? {
+        Some(schedule) => JobInfo {
+            name: "reaper".to_string(),
+            interval_s: None,
+            time_zone: Some(schedule.time_zone()),
+            next_run_at: Some(schedule.next_run(Utc::now())),
+        },
+        None => JobInfo {
+            name: "reaper".to_string(),
+            interval_s: Some(config.saga.reaper_interval_s),
+            time_zone: None,
+            next_run_at: None,
+        },
+    };
+    let moderation = JobInfo {
+        name: "moderation_sla".to_string(),
+        interval_s: Some(config.moderation.check_interval_s),
+        time_zone: None,
+        next_run_at: None,
+    };
+    let vacation = JobInfo {
+        name: "vacation_end".to_string(),
+        interval_s: Some(config.vacations.check_interval_s),
+        time_zone: None,
+        next_run_at: None,
+    };
+    let mut jobs = vec![reaper, moderation, vacation];
+    if let Some(schedule) = low_stock_schedule(config)? {
+        jobs.push(JobInfo {
+            name: "low_stock".to_string(),
+            interval_s: None,
+            time_zone: Some(schedule.time_zone()),
+            next_run_at: Some(schedule.next_run(Utc::now())),
+        });
+    }
+    if let Some(ref order_acknowledgment) = config.order_acknowledgment {
+        jobs.push(JobInfo {
+            name: "order_acknowledgment".to_string(),
+            interval_s: Some(order_acknowledgment.check_interval_s),
+            time_zone: None,
+            next_run_at: None,
+        });
+    }
+    jobs.push(JobInfo {
+        name: "saga_retention".to_string(),
+        interval_s: Some(config.saga.retention.purge_interval_s),
+        time_zone: None,
+        next_run_at: None,
+    });
+    if let Some(ref saga_stats) = config.saga_stats {
+        jobs.push(JobInfo {
+            name: "saga_stats_export".to_string(),
+            interval_s: Some(saga_stats.interval_s),
+            time_zone: None,
+            next_run_at: None,
+        });
+    }
+    if let Some(interval_s) = config.secrets.refresh_interval_s {
+        jobs.push(JobInfo {
+            name: "secrets_refresh".to_string(),
+            interval_s: Some(interval_s),
+            time_zone: None,
+            next_run_at: None,
+        });
+    }
+    Ok(jobs)
+}
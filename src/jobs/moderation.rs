@@ -0,0 +1,83 @@
+//! Watches items awaiting moderation. Items waiting beyond moderation SLA are
+//! logged and, if enabled, moderators are reminded about them by email at
+//! most once per SLA period.
+use std::time::{Duration, Instant, SystemTime};
+
+use futures::future;
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use tokio_timer::Interval;
+
+use super::JobContext;
+use models::{PendingModeration, SagaPriority};
+use services::store::StoreServiceImpl;
+
+pub fn run(ctx: JobContext) -> impl Future<Item = (), Error = ()> {
+    let period = Duration::from_secs(ctx.config.moderation.check_interval_s);
+    Interval::new(Instant::now() + period, period)
+        .map_err(|e| error!("Moderation SLA timer error: {}", e))
+        .for_each(move |_| check(ctx.clone()))
+}
+
+fn check(ctx: JobContext) -> Box<Future<Item = (), Error = ()>> {
+    let sla = Duration::from_secs(ctx.config.moderation.sla_s);
+    let now = SystemTime::now();
+    let overdue = match ctx.moderation_queue.pending() {
+        Ok(pending) => pending.into_iter().filter(|item| item.is_overdue(sla, now)).collect::<Vec<_>>(),
+        Err(e) => {
+            error!("Could not load moderation queue: {}", e);
+            return Box::new(future::ok(()));
+        }
+    };
+    if overdue.is_empty() {
+        return Box::new(future::ok(()));
+    }
+    warn!("{} items await moderation longer than {}s", overdue.len(), sla.as_secs());
+
+    if !ctx.config.moderation.remind_moderators {
+        return Box::new(future::ok(()));
+    }
+    let to_remind = overdue
+        .into_iter()
+        .filter(|item| item.reminded_at.map(|reminded_at| is_older(reminded_at, sla, now)).unwrap_or(true))
+        .collect::<Vec<_>>();
+    let executor = ctx.executor.clone();
+    Box::new(executor.run(
+        SagaPriority::Moderation,
+        iter_ok::<_, ()>(to_remind).for_each(move |pending| remind(&ctx, pending)),
+    ))
+}
+
+fn remind(ctx: &JobContext, pending: PendingModeration) -> impl Future<Item = (), Error = ()> {
+    let ms = ctx.microservices();
+    let service = StoreServiceImpl::new(
+        ctx.config.clone(),
+        ctx.saga_store.clone(),
+        ctx.moderation_queue.clone(),
+        ms.orders,
+        ms.stores,
+        ms.notifications,
+        ms.billing,
+        ms.warehouses,
+        ms.users,
+        ms.delivery,
+    );
+    let moderation_queue = ctx.moderation_queue.clone();
+    let item = pending.item;
+    service.remind_moderators(pending).then(move |res| {
+        match res {
+            Ok(_) => {
+                info!("Moderators were reminded about {:?}", item);
+                if let Err(e) = moderation_queue.mark_reminded(item) {
+                    error!("Could not update moderation queue with {:?}: {}", item, e);
+                }
+            }
+            Err((_, e)) => warn!("Reminding moderators about {:?} failed: {}", item, e),
+        }
+        Ok(())
+    })
+}
+
+fn is_older(time: SystemTime, age: Duration, now: SystemTime) -> bool {
+    now.duration_since(time).map(|elapsed| elapsed > age).unwrap_or(false)
+}
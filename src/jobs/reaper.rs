@@ -0,0 +1,532 @@
+//! Reaper periodically looks through saga logs for sagas whose compensation
+//! failed and retries cleanup of the resources they created. Sagas that were
+//! interrupted in progress are recovered first and then reverted. Sagas that
+//! can not be cleaned up after `reaper_max_attempts` tries are marked as
+//! orphaned and listed in admin API.
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::Utc;
+use failure::Error as FailureError;
+use futures::future::{self, Either, Loop};
+use futures::prelude::*;
+use serde_json;
+use tokio_timer::{Delay, Interval};
+
+use stq_types::{SagaId, UserId};
+
+use super::schedule::Schedule;
+use super::{recovery, JobContext, Microservices};
+use audit::AuditScope;
+use config;
+use microservice::Initiator;
+use models::*;
+use saga::{SagaLog, SagaStore};
+use services::account::AccountServiceImpl;
+use services::cancellation::OrderCancellationServiceImpl;
+use services::catalog::CatalogServiceImpl;
+use services::category::CategoryChangeServiceImpl;
+use services::delivery::DeliveryServiceImpl;
+use services::dispute::DisputeServiceImpl;
+use services::order::OrderServiceImpl;
+use services::order_return::OrderReturnServiceImpl;
+use services::payout::PayoutServiceImpl;
+use services::pricing::PricingServiceImpl;
+use services::store::StoreServiceImpl;
+use services::takedown::StoreTakedownServiceImpl;
+use services::vacation::StoreVacationServiceImpl;
+use services::variants::VariantsServiceImpl;
+use services::verification::StoreVerificationServiceImpl;
+
+pub fn run(ctx: JobContext, schedule: Option<Schedule>) -> Box<Future<Item = (), Error = ()>> {
+    match schedule {
+        Some(schedule) => {
+            info!("Reaper runs by schedule in {} time zone", schedule.time_zone());
+            Box::new(future::loop_fn(ctx, move |ctx| {
+                let now = Utc::now();
+                let next_run = schedule.next_run(now);
+                debug!("Next reaper run at {}", next_run);
+                let delay = (next_run - now).to_std().unwrap_or_else(|_| Duration::from_secs(0));
+                Delay::new(Instant::now() + delay)
+                    .map_err(|e| error!("Orphaned resources reaper timer error: {}", e))
+                    .and_then(move |_| reap(ctx.clone()).map(move |_| Loop::Continue(ctx)))
+            }))
+        }
+        None => {
+            let period = Duration::from_secs(ctx.config.saga.reaper_interval_s);
+            Box::new(
+                Interval::new(Instant::now() + period, period)
+                    .map_err(|e| error!("Orphaned resources reaper timer error: {}", e))
+                    .for_each(move |_| reap(ctx.clone())),
+            )
+        }
+    }
+}
+
+fn reap(ctx: JobContext) -> impl Future<Item = (), Error = ()> {
+    let saga_config = ctx.config.saga.clone();
+    let mut records = match ctx.saga_store.find_by_status(&[SagaStatus::RevertFailed, SagaStatus::InProgress]) {
+        Ok(records) => records
+            .into_iter()
+            .filter(|record| record.status == SagaStatus::RevertFailed || is_stale(record, &saga_config))
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            error!("Reaper could not load saga logs: {}", e);
+            vec![]
+        }
+    };
+    let taken_over = ctx
+        .saga_store
+        .take_over(Duration::from_millis(saga_config.claim_lease_ms))
+        .then(|res| match res {
+            Ok(taken_over) => {
+                if !taken_over.is_empty() {
+                    warn!(
+                        "Reaper took over {} sagas of replicas that stopped executing them",
+                        taken_over.len()
+                    );
+                }
+                Ok(taken_over)
+            }
+            Err(e) => {
+                error!("Reaper could not take over sagas of other replicas: {}", e);
+                Ok(vec![])
+            }
+        });
+
+    taken_over.and_then(move |taken_over| {
+        records.extend(taken_over);
+        if !records.is_empty() {
+            info!("Reaper found {} sagas with orphaned resources", records.len());
+        }
+
+        // sagas are reaped concurrently within limits of their priority classes
+        let reaped = records
+            .into_iter()
+            .map(|record| {
+                let ctx = ctx.clone();
+                ctx.executor
+                    .clone()
+                    .run(record.saga_type.priority(), future::lazy(move || reap_saga(&ctx, record)))
+            })
+            .collect::<Vec<_>>();
+        future::join_all(reaped).map(|_| ())
+    })
+}
+
+fn reap_saga(ctx: &JobContext, record: SagaRecord) -> Box<Future<Item = (), Error = ()>> {
+    if record.status == SagaStatus::InProgress {
+        if let Some(input) = catalog_import_input(&record) {
+            return resume_catalog_import(ctx, record, input);
+        }
+
+        // Saga was interrupted, find out which of its steps have taken effect before reverting it
+        let saga_id = record.id;
+        let saga_store = ctx.saga_store.clone();
+        warn!("Saga {} ({}) was interrupted, recovering it", saga_id, record.saga_type);
+        if record.claim.is_some() {
+            // saga is reverted instead of being resumed, so other replicas must not take it over
+            release(&*saga_store, saga_id);
+        }
+
+        let ctx = ctx.clone();
+        let fut = recovery::recover(&ctx.microservices(), saga_store.clone(), record).then(move |res| match res {
+            Ok(record) => {
+                finish(
+                    &*saga_store,
+                    saga_id,
+                    SagaStatus::RevertFailed,
+                    Some("Saga was interrupted".to_string()),
+                );
+                Either::A(compensate(&ctx, record))
+            }
+            Err(e) => {
+                error!("Recovery of saga {} failed: {}", saga_id, e);
+                finish(&*saga_store, saga_id, SagaStatus::Orphaned, Some(e.to_string()));
+                Either::B(future::ok(()))
+            }
+        });
+        return Box::new(fut);
+    }
+
+    Box::new(compensate(ctx, record))
+}
+
+/// Input of catalog import, `None` for other sagas and imports started before inputs were saved
+fn catalog_import_input(record: &SagaRecord) -> Option<CatalogImportInput> {
+    if record.saga_type != SagaType::CatalogImport {
+        return None;
+    }
+    record.input.clone().and_then(|input| {
+        serde_json::from_value(input)
+            .map_err(|e| error!("Input of catalog import {} can not be parsed: {}", record.id, e))
+            .ok()
+    })
+}
+
+/// Catalog import whose executor stopped renewing its claim is taken over and resumed instead of being reverted
+fn resume_catalog_import(ctx: &JobContext, record: SagaRecord, input: CatalogImportInput) -> Box<Future<Item = (), Error = ()>> {
+    let saga_id = record.id;
+    let saga_store = ctx.saga_store.clone();
+    let ctx = ctx.clone();
+    let claim = saga_store.claim(saga_id, Duration::from_millis(ctx.config.saga.claim_lease_ms));
+    Box::new(claim.then(move |res| match res {
+        Ok(true) => {
+            warn!("Catalog import {} was interrupted, resuming it", saga_id);
+            Either::A(resume_claimed_catalog_import(ctx, record, input))
+        }
+        Ok(false) => Either::B(future::ok(())),
+        Err(e) => {
+            error!("Reaper could not claim catalog import {}: {}", saga_id, e);
+            Either::B(future::ok(()))
+        }
+    }))
+}
+
+fn resume_claimed_catalog_import(ctx: JobContext, record: SagaRecord, input: CatalogImportInput) -> Box<Future<Item = (), Error = ()>> {
+    let saga_id = record.id;
+    let saga_store = ctx.saga_store.clone();
+    let fut = recovery::recover(&ctx.microservices(), saga_store.clone(), record).then(move |res| match res {
+        Ok(record) => {
+            let ms = ctx.microservices();
+            let mut service = CatalogServiceImpl::new(
+                ctx.config.clone(),
+                saga_store.clone(),
+                ms.stores.clone(),
+                ms.delivery.clone(),
+                ms.warehouses.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Either::A(service.resume(input).then(move |res| {
+                match res {
+                    Ok(_) => info!("Resumed catalog import {} finished", saga_id),
+                    Err((_, e)) => error!("Resumed catalog import {} failed: {}", saga_id, e),
+                }
+                Ok(())
+            }))
+        }
+        Err(e) => {
+            error!("Recovery of catalog import {} failed: {}", saga_id, e);
+            finish(&*saga_store, saga_id, SagaStatus::Orphaned, Some(e.to_string()));
+            release(&*saga_store, saga_id);
+            Either::B(future::ok(()))
+        }
+    });
+    Box::new(fut)
+}
+
+fn compensate(ctx: &JobContext, record: SagaRecord) -> impl Future<Item = (), Error = ()> {
+    let saga_id = record.id;
+    let saga_store = ctx.saga_store.clone();
+
+    let attempt = match saga_store.register_revert_attempt(saga_id) {
+        Ok(attempt) => attempt,
+        Err(e) => {
+            error!("Reaper could not register revert attempt of saga {}: {}", saga_id, e);
+            return Either::A(future::ok(()));
+        }
+    };
+    info!("Reverting saga {} ({}), attempt {}", saga_id, record.saga_type, attempt);
+
+    let max_attempts = ctx.config.saga.reaper_max_attempts;
+    let microservices = ctx.microservices();
+    let check = check_cleanup(&microservices, &record);
+
+    Either::B(revert(ctx, &microservices, record).and_then(move |_| check).then(move |res| {
+        match res {
+            Ok(_) => {
+                info!("Saga {} resources were cleaned up by reaper", saga_id);
+                finish(&*saga_store, saga_id, SagaStatus::Reverted, None);
+            }
+            Err(ref e) if attempt >= max_attempts => {
+                error!("Reaper gave up cleaning up saga {} after {} attempts: {}", saga_id, attempt, e);
+                finish(&*saga_store, saga_id, SagaStatus::Orphaned, Some(e.to_string()));
+            }
+            Err(ref e) => {
+                warn!("Reaper could not clean up saga {}: {}", saga_id, e);
+                finish(&*saga_store, saga_id, SagaStatus::RevertFailed, Some(e.to_string()));
+            }
+        }
+        Ok(())
+    }))
+}
+
+fn revert(ctx: &JobContext, ms: &Microservices, record: SagaRecord) -> Box<Future<Item = (), Error = FailureError>> {
+    let saga_store = ctx.saga_store.clone();
+    let config = ctx.config.clone();
+
+    match record.saga_type {
+        SagaType::CreateAccount => {
+            let mut service = AccountServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ms.stores.clone(),
+                ms.billing.clone(),
+                ms.delivery.clone(),
+                ms.users.clone(),
+                ms.notifications.clone(),
+                None,
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.create_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::CreateStore => {
+            let mut service = StoreServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ctx.moderation_queue.clone(),
+                ms.orders.clone(),
+                ms.stores.clone(),
+                ms.notifications.clone(),
+                ms.billing.clone(),
+                ms.warehouses.clone(),
+                ms.users.clone(),
+                ms.delivery.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.create_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::CreateOrder | SagaType::BuyNow => {
+            let mut service = OrderServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ms.orders.clone(),
+                ms.stores.clone(),
+                ms.notifications.clone(),
+                ms.users.clone(),
+                ms.billing.clone(),
+                ms.warehouses.clone(),
+                ms.delivery.clone(),
+                None,
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.create_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::UpsertShipping => {
+            let mut service = DeliveryServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ms.orders.clone(),
+                ms.delivery.clone(),
+                ms.stores.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.upsert_shipping_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::VerifyStore => {
+            let mut service = StoreVerificationServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ms.stores.clone(),
+                ms.billing.clone(),
+                ms.users.clone(),
+                ms.notifications.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.verify_store_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::TakedownStore => {
+            let audit = AuditScope::new(ctx.audit_log.clone(), "background job".to_string());
+            audit.bind_saga(record.id);
+            let mut service = StoreTakedownServiceImpl::new(
+                config,
+                saga_store.clone(),
+                audit,
+                ms.orders.clone(),
+                ms.stores.clone(),
+                ms.users.clone(),
+                ms.notifications.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.takedown_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::StoreVacation => {
+            let mut service = StoreVacationServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ctx.vacations.clone(),
+                ms.orders.clone(),
+                ms.stores.clone(),
+                ms.users.clone(),
+                ms.notifications.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.vacation_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::ChangeCategory => {
+            let mut service = CategoryChangeServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ms.orders.clone(),
+                ms.stores.clone(),
+                ms.delivery.clone(),
+                ms.users.clone(),
+                ms.notifications.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.change_category_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::VariantsBulkEdit => {
+            let mut service = VariantsServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ms.orders.clone(),
+                ms.stores.clone(),
+                ms.warehouses.clone(),
+                ms.users.clone(),
+                ms.notifications.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.bulk_edit_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::CreatePayout => {
+            let mut service = PayoutServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ms.stores.clone(),
+                ms.billing.clone(),
+                ms.notifications.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.create_payout_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::Reprice => {
+            let mut service = PricingServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ms.orders.clone(),
+                ms.stores.clone(),
+                ms.users.clone(),
+                ms.notifications.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.reprice_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::CatalogImport => {
+            let mut service = CatalogServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ms.stores.clone(),
+                ms.delivery.clone(),
+                ms.warehouses.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.import_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::OpenDispute | SagaType::ResolveDispute => {
+            let mut service = DisputeServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ms.orders.clone(),
+                ms.billing.clone(),
+                ms.warehouses.clone(),
+                ms.stores.clone(),
+                ms.users.clone(),
+                ms.notifications.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.dispute_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::ReturnOrder | SagaType::ReceiveReturn => {
+            let mut service = OrderReturnServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ms.orders.clone(),
+                ms.billing.clone(),
+                ms.warehouses.clone(),
+                ms.stores.clone(),
+                ms.users.clone(),
+                ms.notifications.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.return_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+        SagaType::CancelOrderItem => {
+            let mut service = OrderCancellationServiceImpl::new(
+                config,
+                saga_store.clone(),
+                ms.orders.clone(),
+                ms.billing.clone(),
+                ms.warehouses.clone(),
+                ms.stores.clone(),
+                ms.users.clone(),
+                ms.notifications.clone(),
+            );
+            service.log = Rc::new(SagaLog::restore(record, saga_store));
+            Box::new(service.cancel_item_revert().map(|_| ()).map_err(|(_, e)| e))
+        }
+    }
+}
+
+/// Queries downstream services to make sure the main resource created by saga is gone:
+/// store of store creation, user of account creation or invoice of order creation
+fn check_cleanup(ms: &Microservices, record: &SagaRecord) -> Box<Future<Item = (), Error = FailureError>> {
+    for entry in &record.stages {
+        match entry.stage {
+            SagaStage::CreateStore(CreateStoreOperationStage::StoreCreationComplete(store_id)) => {
+                return Box::new(ms.stores.get(store_id, Visibility::Active).and_then(move |store| match store {
+                    Some(ref store) if store.is_active => Err(format_err!("Store {} is still active", store_id)),
+                    _ => Ok(()),
+                }));
+            }
+            SagaStage::CreateProfile(CreateProfileOperationStage::AccountCreationComplete(_)) => {
+                let user_id = match created_user_id(record) {
+                    Some(user_id) => user_id,
+                    None => continue,
+                };
+                return Box::new(ms.users.get(Some(Initiator::Superadmin), user_id).and_then(move |user| match user {
+                    Some(_) => Err(format_err!("User {} still exists", user_id)),
+                    None => Ok(()),
+                }));
+            }
+            SagaStage::CreateOrder(CreateOrderOperationStage::BillingCreateInvoiceComplete(saga_id)) => {
+                return Box::new(ms.billing.get_invoice_by_saga_id(Initiator::Superadmin, saga_id).and_then(
+                    move |invoice| match invoice {
+                        Some(_) => Err(format_err!("Invoice of saga {} still exists", saga_id)),
+                        None => Ok(()),
+                    },
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    Box::new(future::ok(()))
+}
+
+fn created_user_id(record: &SagaRecord) -> Option<UserId> {
+    record
+        .find_result(|stage| match stage {
+            SagaStage::CreateProfile(CreateProfileOperationStage::AccountCreationComplete(_)) => true,
+            _ => false,
+        })
+        .and_then(|user| serde_json::from_value::<User>(user.clone()).ok())
+        .map(|user| user.id)
+}
+
+fn is_stale(record: &SagaRecord, config: &config::Saga) -> bool {
+    if record.status != SagaStatus::InProgress {
+        return false;
+    }
+    match record.claim {
+        // saga executed in background is stale once its executor stops renewing the claim
+        Some(ref claim) => claim.is_expired(Duration::from_millis(config.claim_lease_ms), SystemTime::now()),
+        None => record
+            .updated_at
+            .elapsed()
+            .map(|elapsed| elapsed.as_secs() >= config.reaper_stale_after_s)
+            .unwrap_or(false),
+    }
+}
+
+fn release(saga_store: &SagaStore, saga_id: SagaId) {
+    if let Err(e) = saga_store.release(saga_id) {
+        error!("Reaper could not release claim of saga {}: {}", saga_id, e);
+    }
+}
+
+fn finish(saga_store: &SagaStore, saga_id: SagaId, status: SagaStatus, error: Option<String>) {
+    if let Err(e) = saga_store.set_status(saga_id, status, error) {
+        error!("Reaper could not set status {} of saga {}: {}", status, saga_id, e);
+    }
+}
@@ -0,0 +1,169 @@
+//! Recovery of interrupted sagas. If coordinator stopped after a downstream
+//! call had been made but before its completion was written to saga log,
+//! the step is probed in downstream service instead of being blindly
+//! re-executed or reverted. The outcome is written into saga log with
+//! `Recovered` or `NotApplied` marker.
+use std::sync::Arc;
+
+use failure::Error as FailureError;
+use futures::future;
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use serde_json::{self, Value};
+
+use super::Microservices;
+use microservice::Initiator;
+use models::*;
+use saga::SagaStore;
+
+/// Outcome of interrupted step as reported by downstream service
+pub enum StepOutcome {
+    /// Step took effect, contains its completion stage and created entity
+    Applied(SagaStage, Option<Value>),
+    NotApplied,
+    /// Downstream service can not tell
+    Unknown,
+}
+
+/// Probes every interrupted step of saga and returns its updated record
+pub fn recover(ms: &Microservices, saga_store: Arc<SagaStore>, record: SagaRecord) -> Box<Future<Item = SagaRecord, Error = FailureError>> {
+    let saga_id = record.id;
+    let probes = record
+        .pending_steps()
+        .into_iter()
+        .map(|stage| probe(ms, &stage).map(move |outcome| (stage, outcome)))
+        .collect::<Vec<_>>();
+
+    let store = saga_store.clone();
+    Box::new(
+        iter_ok::<_, FailureError>(probes)
+            .and_then(|probe| probe)
+            .for_each(move |(stage, outcome)| {
+                let entry = match outcome {
+                    StepOutcome::Applied(complete_stage, result) => {
+                        info!("Interrupted step {:?} of saga {} has taken effect", stage, saga_id);
+                        SagaLogEntry::recovered(complete_stage, result)
+                    }
+                    StepOutcome::NotApplied => {
+                        info!("Interrupted step {:?} of saga {} has not taken effect", stage, saga_id);
+                        SagaLogEntry::not_applied(stage)
+                    }
+                    StepOutcome::Unknown => {
+                        warn!("Outcome of interrupted step {:?} of saga {} is unknown", stage, saga_id);
+                        return Ok(());
+                    }
+                };
+                store.append_stage(saga_id, entry)
+            })
+            .and_then(move |_| {
+                saga_store
+                    .get(saga_id)?
+                    .ok_or_else(|| format_err!("Saga {} disappeared from saga store during recovery", saga_id))
+            }),
+    )
+}
+
+/// Asks downstream service whether the step started with `stage` has taken effect
+fn probe(ms: &Microservices, stage: &SagaStage) -> Box<Future<Item = StepOutcome, Error = FailureError>> {
+    match stage {
+        SagaStage::CreateProfile(CreateProfileOperationStage::AccountCreationStart(saga_id)) => {
+            let saga_id = *saga_id;
+            Box::new(
+                ms.users
+                    .get_user_by_saga_id(Some(Initiator::Superadmin), saga_id)
+                    .map(move |user| match user {
+                        Some(user) => StepOutcome::Applied(
+                            CreateProfileOperationStage::AccountCreationComplete(saga_id).into_saga_stage(),
+                            serde_json::to_value(user).ok(),
+                        ),
+                        None => StepOutcome::NotApplied,
+                    }),
+            )
+        }
+        SagaStage::CreateStore(CreateStoreOperationStage::StoreCreationStart(saga_id)) => Box::new(
+            ms.stores
+                .get_store_by_saga_id(Some(Initiator::Superadmin), *saga_id)
+                .map(|store| match store {
+                    Some(store) => StepOutcome::Applied(
+                        CreateStoreOperationStage::StoreCreationComplete(store.id).into_saga_stage(),
+                        serde_json::to_value(store).ok(),
+                    ),
+                    None => StepOutcome::NotApplied,
+                }),
+        ),
+        SagaStage::CreateOrder(CreateOrderOperationStage::BillingCreateInvoiceStart(saga_id)) => {
+            let saga_id = *saga_id;
+            Box::new(
+                ms.billing
+                    .get_invoice_by_saga_id(Initiator::Superadmin, saga_id)
+                    .map(move |invoice| match invoice {
+                        Some(invoice) => StepOutcome::Applied(
+                            CreateOrderOperationStage::BillingCreateInvoiceComplete(saga_id).into_saga_stage(),
+                            serde_json::to_value(invoice).ok(),
+                        ),
+                        None => StepOutcome::NotApplied,
+                    }),
+            )
+        }
+        SagaStage::CreatePayout(CreatePayoutOperationStage::PayoutCreationStart(saga_id)) => {
+            let saga_id = *saga_id;
+            Box::new(
+                ms.billing
+                    .get_payout_by_saga_id(Initiator::Superadmin, saga_id)
+                    .map(move |payout| match payout {
+                        Some(payout) => StepOutcome::Applied(
+                            CreatePayoutOperationStage::PayoutCreationComplete(saga_id).into_saga_stage(),
+                            serde_json::to_value(payout).ok(),
+                        ),
+                        None => StepOutcome::NotApplied,
+                    }),
+            )
+        }
+        SagaStage::Dispute(DisputeOperationStage::DisputeRegistrationStart(saga_id)) => {
+            let saga_id = *saga_id;
+            Box::new(
+                ms.billing
+                    .get_dispute_by_saga_id(Initiator::Superadmin, saga_id)
+                    .map(move |dispute| match dispute {
+                        Some(dispute) => StepOutcome::Applied(
+                            DisputeOperationStage::DisputeRegistrationComplete(saga_id).into_saga_stage(),
+                            serde_json::to_value(dispute).ok(),
+                        ),
+                        None => StepOutcome::NotApplied,
+                    }),
+            )
+        }
+        SagaStage::OrderReturn(OrderReturnOperationStage::ReturnCreationStart(saga_id)) => {
+            let saga_id = *saga_id;
+            Box::new(
+                ms.orders
+                    .get_return_by_saga_id(Initiator::Superadmin, saga_id)
+                    .map(move |order_return| match order_return {
+                        Some(order_return) => StepOutcome::Applied(
+                            OrderReturnOperationStage::ReturnCreationComplete(saga_id).into_saga_stage(),
+                            serde_json::to_value(order_return).ok(),
+                        ),
+                        None => StepOutcome::NotApplied,
+                    }),
+            )
+        }
+        SagaStage::CancelOrderItem(CancelOrderItemOperationStage::InvoiceAmendStart(saga_id)) => {
+            let saga_id = *saga_id;
+            Box::new(
+                ms.billing
+                    .get_invoice_amendment_by_saga_id(Initiator::Superadmin, saga_id)
+                    .map(move |amendment| match amendment {
+                        Some(amendment) => StepOutcome::Applied(
+                            CancelOrderItemOperationStage::InvoiceAmendComplete(saga_id).into_saga_stage(),
+                            serde_json::to_value(amendment).ok(),
+                        ),
+                        None => StepOutcome::NotApplied,
+                    }),
+            )
+        }
+        _ => {
+            debug!("No downstream probe for stage {:?}", stage);
+            Box::new(future::ok(StepOutcome::Unknown))
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Purges records of finished sagas from saga store once they are older than
+//! `[saga.retention]` allows, so that the store stays bounded. Sagas in progress and
+//! sagas waiting for reaper are never purged. Every replica purges its own store.
+use std::time::{Duration, Instant, SystemTime};
+
+use failure::Error as FailureError;
+use futures::prelude::*;
+use tokio_timer::Interval;
+
+use super::JobContext;
+use config;
+use models::SagaStatus;
+use saga::SagaStore;
+
+/// Numbers of purged sagas
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct PurgeReport {
+    /// Completed and reverted sagas
+    pub finished: usize,
+    pub orphaned: usize,
+}
+
+pub fn run(ctx: JobContext, retention: config::SagaRetention) -> impl Future<Item = (), Error = ()> {
+    let period = Duration::from_secs(retention.purge_interval_s);
+    Interval::new(Instant::now() + period, period)
+        .map_err(|e| error!("Saga retention timer error: {}", e))
+        .for_each(move |_| {
+            let finished_after = Duration::from_secs(retention.finished_after_s);
+            let orphaned_after = retention.orphaned_after_s.map(Duration::from_secs);
+            match purge(&*ctx.saga_store, finished_after, orphaned_after, SystemTime::now()) {
+                Ok(ref report) if *report == PurgeReport::default() => {}
+                Ok(report) => info!("Purged {} finished and {} orphaned sagas", report.finished, report.orphaned),
+                Err(e) => error!("Purging sagas failed: {}", e),
+            }
+            Ok(())
+        })
+}
+
+/// Purges finished sagas older than `finished_after`, orphaned sagas are purged only if `orphaned_after` is set
+pub fn purge(
+    saga_store: &SagaStore,
+    finished_after: Duration,
+    orphaned_after: Option<Duration>,
+    now: SystemTime,
+) -> Result<PurgeReport, FailureError> {
+    let finished = saga_store.purge(&[SagaStatus::Completed, SagaStatus::Reverted], now - finished_after)?;
+    let orphaned = match orphaned_after {
+        Some(orphaned_after) => saga_store.purge(&[SagaStatus::Orphaned], now - orphaned_after)?,
+        None => 0,
+    };
+    Ok(PurgeReport { finished, orphaned })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use stq_types::SagaId;
+
+    use super::{purge, PurgeReport};
+    use models::{SagaRecord, SagaStatus, SagaType};
+    use saga::{SagaStore, SagaStoreImpl};
+
+    #[test]
+    fn purges_only_expired_finished_sagas() {
+        let store = SagaStoreImpl::new(None, None).unwrap();
+        for status in &[
+            SagaStatus::Completed,
+            SagaStatus::Orphaned,
+            SagaStatus::RevertFailed,
+            SagaStatus::InProgress,
+        ] {
+            let mut record = SagaRecord::new(SagaId::new(), SagaType::CreateStore);
+            record.status = *status;
+            store.insert(record).unwrap();
+        }
+        let finished_after = Duration::from_secs(3600);
+
+        assert_eq!(
+            purge(&store, finished_after, None, SystemTime::now()).unwrap(),
+            PurgeReport::default()
+        );
+        let later = SystemTime::now() + Duration::from_secs(7200);
+        assert_eq!(
+            purge(&store, finished_after, None, later).unwrap(),
+            PurgeReport { finished: 1, orphaned: 0 }
+        );
+        assert_eq!(store.find_by_status(&[SagaStatus::Orphaned]).unwrap().len(), 1);
+        assert_eq!(
+            store
+                .find_by_status(&[SagaStatus::RevertFailed, SagaStatus::InProgress])
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+}
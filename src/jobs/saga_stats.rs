@@ -0,0 +1,246 @@
+//! Exports statistics of finished sagas for the analytics team, so that they do not
+//! have to dig them out of Graylog. Sagas finished during every `[saga_stats]` interval
+//! are aggregated into a row per saga type: counts by outcome, compensation rate and
+//! durations. Rows are appended to csv file or posted to http sink. Saga counts as
+//! finished when its record was last updated, so saga retried by reaper is exported
+//! again in the interval of its last attempt.
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::mem;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Utc};
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use hyper::header::{ContentType, Headers};
+use hyper::Method;
+use serde_json;
+use tokio_timer::Interval;
+
+use stq_http::client::{HttpClient, TimeLimitedHttpClient};
+
+use super::JobContext;
+use config::{SagaStatsExport, SagaStatsSink};
+use metrics::duration_ms;
+use models::{SagaRecord, SagaStatus, SagaType};
+
+const CSV_HEADER: &str = "period_start,period_end,saga_type,finished,completed,reverted,failed,compensation_rate,duration_ms_avg,duration_ms_p95,duration_ms_max";
+
+/// Statistics of sagas of a single type finished during the period
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct SagaTypeStats {
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub saga_type: SagaType,
+    pub finished: u64,
+    pub completed: u64,
+    pub reverted: u64,
+    /// Sagas whose resources are left for reaper or manual cleanup
+    pub failed: u64,
+    /// Share of finished sagas that were compensated at least once
+    pub compensation_rate: f64,
+    pub duration_ms_avg: u64,
+    pub duration_ms_p95: u64,
+    pub duration_ms_max: u64,
+}
+
+pub fn run(ctx: JobContext, export: SagaStatsExport) -> impl Future<Item = (), Error = ()> {
+    let period = Duration::from_secs(export.interval_s);
+    let mut since = SystemTime::now();
+    Interval::new(Instant::now() + period, period)
+        .map_err(|e| error!("Saga stats export timer error: {}", e))
+        .for_each(move |_| {
+            let until = SystemTime::now();
+            let period_start = mem::replace(&mut since, until);
+            let finished = [
+                SagaStatus::Completed,
+                SagaStatus::Reverted,
+                SagaStatus::RevertFailed,
+                SagaStatus::Orphaned,
+            ];
+            let stats = match ctx.saga_store.find_by_status(&finished) {
+                Ok(records) => aggregate(&records, period_start, until),
+                Err(e) => {
+                    error!("Saga stats export could not load saga logs: {}", e);
+                    return Either::A(future::ok(()));
+                }
+            };
+            if stats.is_empty() {
+                return Either::A(future::ok(()));
+            }
+
+            Either::B(write(&ctx, &export.sink, stats).then(|res| {
+                if let Err(e) = res {
+                    error!("Exporting saga stats failed: {}", e);
+                }
+                Ok(())
+            }))
+        })
+}
+
+/// Rows of saga types having sagas finished within `[since, until)`, ordered by saga type
+pub fn aggregate(records: &[SagaRecord], since: SystemTime, until: SystemTime) -> Vec<SagaTypeStats> {
+    let mut by_type = BTreeMap::new();
+    for record in records {
+        if record.status == SagaStatus::InProgress || record.updated_at < since || record.updated_at >= until {
+            continue;
+        }
+        by_type.entry(record.saga_type.to_string()).or_insert_with(Vec::new).push(record);
+    }
+
+    by_type
+        .into_iter()
+        .map(|(_, records)| {
+            let count = |status: SagaStatus| records.iter().filter(|record| record.status == status).count() as u64;
+            let compensated = records.iter().filter(|record| !record.compensations.is_empty()).count();
+            let mut durations = records
+                .iter()
+                .map(|record| duration_ms(record.updated_at.duration_since(record.created_at).unwrap_or_default()))
+                .collect::<Vec<_>>();
+            durations.sort();
+            let finished = records.len() as u64;
+
+            SagaTypeStats {
+                period_start: since.into(),
+                period_end: until.into(),
+                saga_type: records[0].saga_type,
+                finished,
+                completed: count(SagaStatus::Completed),
+                reverted: count(SagaStatus::Reverted),
+                failed: count(SagaStatus::RevertFailed) + count(SagaStatus::Orphaned),
+                compensation_rate: compensated as f64 / finished as f64,
+                duration_ms_avg: durations.iter().sum::<u64>() / finished,
+                duration_ms_p95: quantile_of(&durations, 0.95),
+                duration_ms_max: durations.last().cloned().unwrap_or_default(),
+            }
+        })
+        .collect()
+}
+
+fn write(ctx: &JobContext, sink: &SagaStatsSink, stats: Vec<SagaTypeStats>) -> Box<Future<Item = (), Error = FailureError>> {
+    match *sink {
+        SagaStatsSink::Csv { ref path } => Box::new(future::result(write_csv(Path::new(path), &stats))),
+        SagaStatsSink::Http { ref url, ref auth_token } => {
+            let body = match serde_json::to_string(&stats) {
+                Ok(body) => body,
+                Err(e) => return Box::new(future::err(e.into())),
+            };
+            let mut headers = Headers::new();
+            headers.set(ContentType::json());
+            if let Some(token) = auth_token.as_ref().and_then(|auth_token| auth_token.value()) {
+                headers.set_raw("Authorization", format!("Bearer {}", token));
+            }
+            let url = url.clone();
+            let http_client = TimeLimitedHttpClient::new(ctx.http_client.clone(), Duration::from_millis(ctx.config.client.http_timeout_ms));
+            Box::new(
+                http_client
+                    .request(Method::Post, url.clone(), Some(body), Some(headers))
+                    .map_err(|e| FailureError::from(e.context("Posting saga stats failed")))
+                    .and_then(move |response| {
+                        if response.status().is_success() {
+                            Ok(())
+                        } else {
+                            Err(format_err!("Saga stats sink {} responded with {}", url, response.status()))
+                        }
+                    }),
+            )
+        }
+    }
+}
+
+fn write_csv(path: &Path, stats: &[SagaTypeStats]) -> Result<(), FailureError> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| e.context(format!("Could not open saga stats file {}", path.display())))?;
+    let mut rows = String::new();
+    if file.metadata()?.len() == 0 {
+        rows.push_str(CSV_HEADER);
+        rows.push('\n');
+    }
+    for row in stats {
+        rows.push_str(&csv_row(row));
+        rows.push('\n');
+    }
+    file.write_all(rows.as_bytes())
+        .map_err(|e| e.context(format!("Could not write saga stats file {}", path.display())))?;
+    Ok(())
+}
+
+fn csv_row(stats: &SagaTypeStats) -> String {
+    format!(
+        "{},{},{},{},{},{},{},{:.4},{},{},{}",
+        stats.period_start.to_rfc3339(),
+        stats.period_end.to_rfc3339(),
+        stats.saga_type,
+        stats.finished,
+        stats.completed,
+        stats.reverted,
+        stats.failed,
+        stats.compensation_rate,
+        stats.duration_ms_avg,
+        stats.duration_ms_p95,
+        stats.duration_ms_max
+    )
+}
+
+/// Nearest rank quantile of sorted durations
+fn quantile_of(sorted: &[u64], quantile: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (quantile * sorted.len() as f64).ceil() as usize;
+    sorted[rank.max(1).min(sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use stq_types::SagaId;
+
+    use super::aggregate;
+    use models::{CompensationReport, SagaRecord, SagaStatus, SagaType};
+
+    fn record(saga_type: SagaType, status: SagaStatus, duration_ms: u64, finished_at: SystemTime) -> SagaRecord {
+        let mut record = SagaRecord::new(SagaId::new(), saga_type);
+        record.status = status;
+        record.created_at = finished_at - Duration::from_millis(duration_ms);
+        record.updated_at = finished_at;
+        record
+    }
+
+    #[test]
+    fn aggregates_sagas_finished_within_period() {
+        let until = SystemTime::now();
+        let since = until - Duration::from_secs(3600);
+        let finished_at = since + Duration::from_secs(60);
+
+        let mut reverted = record(SagaType::CreateOrder, SagaStatus::Reverted, 3000, finished_at);
+        reverted.compensations.push(CompensationReport::default());
+        let records = vec![
+            record(SagaType::CreateOrder, SagaStatus::Completed, 1000, finished_at),
+            record(SagaType::CreateOrder, SagaStatus::Completed, 2000, finished_at),
+            reverted,
+            record(SagaType::CreateOrder, SagaStatus::Orphaned, 6000, finished_at),
+            record(SagaType::CreateOrder, SagaStatus::Completed, 1000, since - Duration::from_secs(1)),
+            record(SagaType::CreateAccount, SagaStatus::InProgress, 1000, finished_at),
+        ];
+
+        let stats = aggregate(&records, since, until);
+        assert_eq!(stats.len(), 1);
+        let stats = &stats[0];
+        assert_eq!(stats.saga_type, SagaType::CreateOrder);
+        assert_eq!((stats.finished, stats.completed, stats.reverted, stats.failed), (4, 2, 1, 1));
+        assert_eq!(stats.compensation_rate, 0.25);
+        assert_eq!(
+            (stats.duration_ms_avg, stats.duration_ms_p95, stats.duration_ms_max),
+            (3000, 6000, 6000)
+        );
+    }
+}
@@ -0,0 +1,112 @@
+//! Daily run times of jobs in a named time zone. Times are resolved in that
+//! time zone, so jobs keep running at the same local time across DST changes
+//! regardless of time zone of the server.
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use failure::Error as FailureError;
+
+use config;
+
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    time_zone: Tz,
+    times: Vec<NaiveTime>,
+}
+
+impl Schedule {
+    pub fn from_config(config: &config::Schedule) -> Result<Self, FailureError> {
+        let time_zone = config
+            .time_zone
+            .parse::<Tz>()
+            .map_err(|e| format_err!("Invalid time zone {}: {}", config.time_zone, e))?;
+        let times = config
+            .times
+            .iter()
+            .map(|time| NaiveTime::parse_from_str(time, "%H:%M").map_err(|e| format_err!("Invalid run time {}: {}", time, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        if times.is_empty() {
+            return Err(format_err!("Schedule has no run times"));
+        }
+        Ok(Self { time_zone, times })
+    }
+
+    pub fn time_zone(&self) -> String {
+        self.time_zone.name().to_string()
+    }
+
+    /// First run time strictly after `now`
+    pub fn next_run(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        let today = now.with_timezone(&self.time_zone).date().naive_local();
+        (0..3)
+            .flat_map(|days| {
+                let date = today + Duration::days(days);
+                self.times.iter().map(move |time| date.and_time(*time))
+            })
+            .filter_map(|local| {
+                // run time skipped by DST transition is moved forward by an hour
+                self.time_zone
+                    .from_local_datetime(&local)
+                    .earliest()
+                    .or_else(|| self.time_zone.from_local_datetime(&(local + Duration::hours(1))).earliest())
+            })
+            .map(|time| time.with_timezone(&Utc))
+            .filter(|time| *time > now)
+            .min()
+            .expect("Schedule with run times always has next run")
+    }
+}
+
+/// State of a background job reported in admin API
+#[derive(Clone, Debug, Serialize)]
+pub struct JobInfo {
+    pub name: String,
+    /// Set for jobs running by interval
+    pub interval_s: Option<u64>,
+    /// Set for jobs running by schedule
+    pub time_zone: Option<String>,
+    pub next_run_at: Option<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::Schedule;
+    use config;
+
+    fn schedule(time_zone: &str, times: &[&str]) -> Schedule {
+        Schedule::from_config(&config::Schedule {
+            time_zone: time_zone.to_string(),
+            times: times.iter().map(|time| time.to_string()).collect(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn resolves_run_times_in_time_zone() {
+        let schedule = schedule("Europe/Moscow", &["03:00", "15:00"]);
+        let now = Utc.ymd(2018, 12, 10).and_hms(10, 0, 0);
+        assert_eq!(schedule.next_run(now), Utc.ymd(2018, 12, 10).and_hms(12, 0, 0));
+        let now = Utc.ymd(2018, 12, 10).and_hms(12, 0, 0);
+        assert_eq!(schedule.next_run(now), Utc.ymd(2018, 12, 11).and_hms(0, 0, 0));
+    }
+
+    #[test]
+    fn keeps_local_time_across_dst() {
+        let schedule = schedule("America/New_York", &["02:30"]);
+        // 02:30 does not exist on the day clocks move forward
+        let now = Utc.ymd(2019, 3, 10).and_hms(0, 0, 0);
+        assert_eq!(schedule.next_run(now), Utc.ymd(2019, 3, 10).and_hms(7, 30, 0));
+        let now = Utc.ymd(2019, 3, 10).and_hms(8, 0, 0);
+        assert_eq!(schedule.next_run(now), Utc.ymd(2019, 3, 11).and_hms(6, 30, 0));
+    }
+
+    #[test]
+    fn rejects_unknown_time_zone() {
+        assert!(Schedule::from_config(&config::Schedule {
+            time_zone: "Mars/Olympus".to_string(),
+            times: vec!["03:00".to_string()],
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,30 @@
+//! Refreshes secrets referenced in config, so that rotated secrets are picked up
+//! without restart. Previous values are kept if refresh fails.
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+use stq_http::client::TimeLimitedHttpClient;
+use tokio_timer::Interval;
+
+use super::JobContext;
+use secrets;
+
+pub fn run(ctx: JobContext, interval_s: u64) -> impl Future<Item = (), Error = ()> {
+    let period = Duration::from_secs(interval_s);
+    Interval::new(Instant::now() + period, period)
+        .map_err(|e| error!("Secrets refresh timer error: {}", e))
+        .for_each(move |_| refresh(&ctx))
+}
+
+fn refresh(ctx: &JobContext) -> impl Future<Item = (), Error = ()> {
+    if let Err(e) = secrets::load_files(&ctx.config) {
+        warn!("Refreshing secret files failed: {}", e);
+    }
+    let http_client = TimeLimitedHttpClient::new(ctx.http_client.clone(), Duration::from_millis(ctx.config.client.http_timeout_ms));
+    secrets::load_vault(http_client, &ctx.config).then(|res| {
+        if let Err(e) = res {
+            warn!("Refreshing secrets from Vault failed: {}", e);
+        }
+        Ok(())
+    })
+}
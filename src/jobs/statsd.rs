@@ -0,0 +1,85 @@
+//! Pushes saga and downstream call metrics to statsd agent over udp. Lines are packed into
+//! datagrams small enough to be delivered without fragmentation.
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use failure::Error as FailureError;
+use futures::future;
+use futures::prelude::*;
+use tokio_timer::Interval;
+
+use super::JobContext;
+use config::StatsD;
+use metrics;
+
+const MAX_DATAGRAM_BYTES: usize = 1432;
+
+pub fn run(ctx: JobContext, config: StatsD) -> impl Future<Item = (), Error = ()> {
+    let period = Duration::from_secs(config.interval_s);
+    let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| error!("Could not bind statsd exporter socket: {}", e));
+    future::result(socket).and_then(move |socket| {
+        Interval::new(Instant::now() + period, period)
+            .map_err(|e| error!("Statsd exporter timer error: {}", e))
+            .for_each(move |_| {
+                if let Err(e) = push(&ctx, &config, &socket) {
+                    warn!("Pushing metrics to statsd failed: {}", e);
+                }
+                Ok(())
+            })
+    })
+}
+
+fn push(ctx: &JobContext, config: &StatsD, socket: &UdpSocket) -> Result<(), FailureError> {
+    let mut lines = metrics::render_statsd(
+        &*ctx.saga_store,
+        &*ctx.moderation_queue,
+        &ctx.executor,
+        &ctx.breakers,
+        &ctx.margin,
+        &ctx.config,
+        &config.prefix,
+        config.datadog_tags,
+    )?;
+    lines.extend(metrics::render_dependencies_statsd(
+        &ctx.monitor.report(&ctx.breakers),
+        &config.prefix,
+        config.datadog_tags,
+    ));
+    for datagram in pack(&lines) {
+        socket.send_to(datagram.as_bytes(), config.address.as_str())?;
+    }
+    Ok(())
+}
+
+/// Joins lines into newline separated datagrams of up to `MAX_DATAGRAM_BYTES`
+fn pack(lines: &[String]) -> Vec<String> {
+    let mut datagrams = vec![];
+    let mut current = String::new();
+    for line in lines {
+        if !current.is_empty() && current.len() + 1 + line.len() > MAX_DATAGRAM_BYTES {
+            datagrams.push(::std::mem::replace(&mut current, String::new()));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        datagrams.push(current);
+    }
+    datagrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pack, MAX_DATAGRAM_BYTES};
+
+    #[test]
+    fn packs_lines_into_datagrams() {
+        let line = "x".repeat(600);
+        let lines = vec![line.clone(), line.clone(), line.clone()];
+        let datagrams = pack(&lines);
+        assert_eq!(datagrams, vec![format!("{}\n{}", line, line), line]);
+        assert!(datagrams.iter().all(|datagram| datagram.len() <= MAX_DATAGRAM_BYTES));
+    }
+}
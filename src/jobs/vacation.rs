@@ -0,0 +1,60 @@
+//! Brings stores back from vacation once it is over. Stores are brought back one by
+//! one, a store failing to come back is retried on the next run.
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use futures::future;
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use tokio_timer::Interval;
+
+use super::JobContext;
+use models::{SagaPriority, StoreVacation};
+use services::vacation::StoreVacationServiceImpl;
+
+pub fn run(ctx: JobContext) -> impl Future<Item = (), Error = ()> {
+    let period = Duration::from_secs(ctx.config.vacations.check_interval_s);
+    Interval::new(Instant::now() + period, period)
+        .map_err(|e| error!("Vacation timer error: {}", e))
+        .for_each(move |_| check(ctx.clone()))
+}
+
+fn check(ctx: JobContext) -> Box<Future<Item = (), Error = ()>> {
+    let over = match ctx.vacations.over(Utc::now()) {
+        Ok(over) => over,
+        Err(e) => {
+            error!("Could not load vacations: {}", e);
+            return Box::new(future::ok(()));
+        }
+    };
+    if over.is_empty() {
+        return Box::new(future::ok(()));
+    }
+
+    let executor = ctx.executor.clone();
+    Box::new(executor.run(
+        SagaPriority::Housekeeping,
+        iter_ok::<_, ()>(over).for_each(move |vacation| end(&ctx, vacation)),
+    ))
+}
+
+fn end(ctx: &JobContext, vacation: StoreVacation) -> impl Future<Item = (), Error = ()> {
+    let ms = ctx.microservices();
+    let service = StoreVacationServiceImpl::new(
+        ctx.config.clone(),
+        ctx.saga_store.clone(),
+        ctx.vacations.clone(),
+        ms.orders,
+        ms.stores,
+        ms.users,
+        ms.notifications,
+    );
+    let store_id = vacation.store_id;
+    service.end_vacation(store_id).then(move |res| {
+        match res {
+            Ok(_) => info!("Store {} is back from vacation ended at {}", store_id, vacation.ends_at),
+            Err((_, e)) => warn!("Bringing store {} back from vacation failed: {}", store_id, e),
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,134 @@
+//! Json files mirroring state kept in memory, e.g. saga log or moderation queue.
+//! Files are replaced as a whole: content is written to a temporary file next to
+//! the target, which is then renamed over it, so readers never see half of a write.
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+use failure::Error as FailureError;
+use failure::Fail;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{self, Value};
+
+#[derive(Clone, Debug)]
+pub struct JsonFile {
+    path: PathBuf,
+    /// Names the content in errors, e.g. `saga log`
+    name: &'static str,
+}
+
+impl JsonFile {
+    pub fn new(path: PathBuf, name: &'static str) -> Self {
+        Self { path, name }
+    }
+
+    /// Content of the file, `None` if the file does not exist yet
+    pub fn read<T: DeserializeOwned>(&self) -> Result<Option<T>, FailureError> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let file = File::open(&self.path).map_err(|e| e.context(format!("Could not open {} {}", self.name, self.path.display())))?;
+        let content =
+            serde_json::from_reader(file).map_err(|e| e.context(format!("Could not parse {} {}", self.name, self.path.display())))?;
+        Ok(Some(content))
+    }
+
+    /// Replaces content of the file
+    pub fn write<T: Serialize>(&self, content: &T) -> Result<(), FailureError> {
+        let tmp_path = self.path.with_extension("tmp");
+        let file = File::create(&tmp_path).map_err(|e| e.context(format!("Could not create {}", tmp_path.display())))?;
+        serde_json::to_writer(file, content).map_err(|e| e.context(format!("Could not write {} {}", self.name, tmp_path.display())))?;
+        fs::rename(&tmp_path, &self.path).map_err(|e| e.context(format!("Could not replace {} {}", self.name, self.path.display())))?;
+        Ok(())
+    }
+}
+
+/// Writes file in a background thread, so that callers on the reactor thread do not wait for serialization
+/// and disk. Writes requested while the previous one is in progress are coalesced into a single write of
+/// the latest snapshot. Dropping the writer waits for the pending write.
+pub struct JsonFileWriter {
+    requests: Mutex<Option<Sender<()>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl JsonFileWriter {
+    /// Starts writer thread, `snapshot` takes content of the file at the moment of write
+    pub fn spawn<F>(file: JsonFile, snapshot: F) -> Result<Self, FailureError>
+    where
+        F: Fn() -> Result<Value, FailureError> + Send + 'static,
+    {
+        let (requests, received) = mpsc::channel::<()>();
+        let thread = thread::Builder::new()
+            .name(format!("{} writer", file.name))
+            .spawn(move || {
+                while received.recv().is_ok() {
+                    while received.try_recv().is_ok() {}
+                    if let Err(e) = snapshot().and_then(|content| file.write(&content)) {
+                        error!("Could not write {}: {}", file.name, e);
+                    }
+                }
+            })
+            .map_err(|e| e.context("Could not start json file writer"))?;
+        Ok(Self {
+            requests: Mutex::new(Some(requests)),
+            thread: Some(thread),
+        })
+    }
+
+    /// Requests write of the latest snapshot
+    pub fn schedule(&self) {
+        if let Some(ref requests) = *self.requests.lock().unwrap() {
+            // writer thread lives as long as the sender, so sending never fails
+            let _ = requests.send(());
+        }
+    }
+}
+
+impl Drop for JsonFileWriter {
+    fn drop(&mut self) {
+        self.requests.lock().unwrap().take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::{JsonFile, JsonFileWriter};
+
+    #[test]
+    fn replaces_content() {
+        let path = env::temp_dir().join(format!("{}.json", Uuid::new_v4()));
+        let file = JsonFile::new(path.clone(), "test file");
+        assert_eq!(file.read::<Vec<u32>>().unwrap(), None);
+
+        file.write(&vec![1, 2]).unwrap();
+        file.write(&vec![3]).unwrap();
+        assert_eq!(file.read::<Vec<u32>>().unwrap(), Some(vec![3]));
+        assert!(!path.with_extension("tmp").exists());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn writes_latest_snapshot_in_background() {
+        let path = env::temp_dir().join(format!("{}.json", Uuid::new_v4()));
+        let file = JsonFile::new(path.clone(), "test file");
+        let writer = JsonFileWriter::spawn(file.clone(), || Ok(json!([1, 2]))).unwrap();
+        writer.schedule();
+        writer.schedule();
+        drop(writer);
+
+        assert_eq!(file.read::<Vec<u32>>().unwrap(), Some(vec![1, 2]));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,16 +1,26 @@
+extern crate base64;
 extern crate chrono;
+extern crate chrono_tz;
 extern crate config as config_crate;
 extern crate env_logger;
 #[macro_use]
 extern crate failure;
+extern crate flate2;
 extern crate futures;
 extern crate futures_cpupool;
 extern crate hyper;
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate log;
+extern crate openssl;
+extern crate rand;
+extern crate regex;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_ignored;
+#[macro_use]
 extern crate serde_json;
 extern crate tokio_core;
 extern crate tokio_signal;
@@ -18,6 +28,8 @@ extern crate tokio_timer;
 extern crate uuid;
 extern crate validator;
 #[macro_use]
+extern crate validator_derive;
+#[macro_use]
 extern crate sentry;
 extern crate geo;
 
@@ -31,14 +43,33 @@ extern crate stq_types;
 
 #[macro_use]
 mod macros;
+mod acknowledgment;
+mod audit;
+mod billing_events;
+mod build_info;
 pub mod config;
 mod controller;
 mod errors;
+mod fraud;
+mod jobs;
+mod json_file;
+pub mod logging;
+mod metrics;
 mod microservice;
 mod models;
+mod moderation;
+mod redis;
+#[cfg(feature = "replay")]
+pub mod replay;
+mod saga;
+pub mod scrubbing;
+pub mod secrets;
 pub mod sentry_integration;
 mod services;
+mod tracking_events;
+mod vacations;
 
+use std::path::PathBuf;
 use std::process;
 use std::sync::Arc;
 
@@ -48,8 +79,32 @@ use futures::prelude::*;
 use hyper::server::Http;
 use tokio_core::reactor::Core;
 
+use acknowledgment::{AcknowledgmentTimers, AcknowledgmentTimersImpl};
+use audit::{AuditLog, AuditLogImpl};
+use billing_events::{BillingEventLog, BillingEventLogImpl};
+use controller::accepted::Accepted;
+use controller::compression::Compressed;
+use controller::cors::Cors;
+use controller::endpoints::EndpointSwitch;
+use controller::etag::ETags;
+use controller::handlers::Handlers;
+use controller::margin::ProcessingMargin;
+use controller::methods::Methods;
+use controller::redaction::Redactions;
+use controller::request_id::RequestId;
 use controller::ControllerImpl;
 use errors::Error;
+use fraud::{FraudOverrides, FraudOverridesImpl};
+use jobs::leader::Leadership;
+use jobs::JobContext;
+use microservice::{Cassette, CassetteHttpClient, ChaosHttpClient, CircuitBreakers, DependencyMonitor, ShadowHttpClient};
+use moderation::{ModerationQueue, ModerationQueueImpl};
+use redis::RedisLeaseLock;
+use saga::encryption::SagaLogCipher;
+use saga::shared::SharedSagas;
+use saga::{SagaExecutor, SagaStore, SagaStoreImpl};
+use tracking_events::{TrackingEventLog, TrackingEventLogImpl};
+use vacations::{StoreVacations, StoreVacationsImpl};
 
 /// Starts new web service from provided `Config`
 pub fn start_server(config: config::Config) {
@@ -64,19 +119,223 @@ pub fn start_server(config: config::Config) {
 
     let client = stq_http::client::Client::new(&config.to_http_config(), &handle);
 
-    let client_handle = client.handle();
+    let cassette = config.cassette.as_ref().map(Cassette::new).map(|cassette| {
+        cassette.unwrap_or_else(|reason| {
+            eprintln!("Cassette Initialization Error: {}", reason);
+            process::exit(1);
+        })
+    });
+    let (client_handle, shadow) = ShadowHttpClient::new(
+        ChaosHttpClient::new(CassetteHttpClient::new(client.handle(), cassette), &config),
+        &config,
+    );
     let client_stream = client.stream();
     handle.spawn(client_stream.for_each(|_| Ok(())));
+    handle.spawn(shadow);
+
+    let saga_log_cipher = match config.saga.encryption {
+        Some(ref encryption) => Some(SagaLogCipher::new(encryption).unwrap_or_else(|reason| {
+            eprintln!("Saga Log Encryption Error: {}", reason);
+            process::exit(1);
+        })),
+        None => None,
+    };
+    let saga_store =
+        SagaStoreImpl::new(config.saga.log_path.clone().map(PathBuf::from), saga_log_cipher.clone()).unwrap_or_else(|reason| {
+            eprintln!("Saga Store Initialization Error: {}", reason);
+            process::exit(1);
+        });
+    let saga_store: Arc<SagaStore> = match config.saga.sharing.clone() {
+        Some(sharing) => Arc::new(saga_store.with_shared(SharedSagas::new(sharing, saga_log_cipher))),
+        None => Arc::new(saga_store),
+    };
+
+    config.saga.definitions.validate().unwrap_or_else(|reason| {
+        eprintln!("Saga Definitions Error: {}", reason);
+        process::exit(1);
+    });
+
+    let reaper_schedule = jobs::reaper_schedule(&config).unwrap_or_else(|reason| {
+        eprintln!("Reaper Schedule Error: {}", reason);
+        process::exit(1);
+    });
+
+    let low_stock_schedule = jobs::low_stock_schedule(&config).unwrap_or_else(|reason| {
+        eprintln!("Low Stock Schedule Error: {}", reason);
+        process::exit(1);
+    });
+
+    let moderation_queue: Arc<ModerationQueue> = Arc::new(
+        ModerationQueueImpl::new(config.moderation.queue_path.clone().map(PathBuf::from)).unwrap_or_else(|reason| {
+            eprintln!("Moderation Queue Initialization Error: {}", reason);
+            process::exit(1);
+        }),
+    );
+
+    let acknowledgment_timers: Arc<AcknowledgmentTimers> = Arc::new(
+        AcknowledgmentTimersImpl::new(
+            config
+                .order_acknowledgment
+                .as_ref()
+                .and_then(|order_acknowledgment| order_acknowledgment.timers_path.clone())
+                .map(PathBuf::from),
+        )
+        .unwrap_or_else(|reason| {
+            eprintln!("Acknowledgment Timers Initialization Error: {}", reason);
+            process::exit(1);
+        }),
+    );
+
+    let vacations: Arc<StoreVacations> = Arc::new(
+        StoreVacationsImpl::new(config.vacations.path.clone().map(PathBuf::from)).unwrap_or_else(|reason| {
+            eprintln!("Vacations Initialization Error: {}", reason);
+            process::exit(1);
+        }),
+    );
+
+    let fraud_overrides: Arc<FraudOverrides> = Arc::new(
+        FraudOverridesImpl::new(
+            config
+                .fraud_screening
+                .as_ref()
+                .and_then(|fraud_screening| fraud_screening.overrides_path.clone())
+                .map(PathBuf::from),
+        )
+        .unwrap_or_else(|reason| {
+            eprintln!("Fraud Overrides Initialization Error: {}", reason);
+            process::exit(1);
+        }),
+    );
+
+    let audit_log: Arc<AuditLog> = Arc::new(
+        AuditLogImpl::new(config.audit.path.clone().map(PathBuf::from), config.audit.capacity).unwrap_or_else(|reason| {
+            eprintln!("Audit Log Initialization Error: {}", reason);
+            process::exit(1);
+        }),
+    );
+
+    let billing_events: Arc<BillingEventLog> = Arc::new(BillingEventLogImpl::new(config.billing_events.capacity));
+
+    let tracking_events: Arc<TrackingEventLog> = Arc::new(TrackingEventLogImpl::new(
+        config
+            .delivery_tracking
+            .as_ref()
+            .map(|tracking| tracking.events_capacity)
+            .unwrap_or(0),
+    ));
+
+    saga::events::init(config.saga_events.as_ref()).unwrap_or_else(|reason| {
+        eprintln!("Saga Events Initialization Error: {}", reason);
+        process::exit(1);
+    });
+
+    let executor = SagaExecutor::new((*handle).clone(), config.executor.clone());
+    let breakers = CircuitBreakers::new(config.circuit_breaker.clone());
+    let monitor = DependencyMonitor::new(config.dependency_monitor.clone());
+    let margin = ProcessingMargin::new(config.service.clone());
+    let endpoints = EndpointSwitch::new(&config);
+
+    let leadership = match config.leader_election.clone() {
+        Some(leader_election) => {
+            let leadership = Leadership::elected();
+            handle.spawn(jobs::leader::run(
+                RedisLeaseLock::new(leader_election.clone()),
+                leadership.clone(),
+                leader_election,
+            ));
+            leadership
+        }
+        None => Leadership::single(),
+    };
+
+    let job_context = JobContext {
+        config: config.clone(),
+        http_client: client_handle.clone(),
+        saga_store: saga_store.clone(),
+        moderation_queue: moderation_queue.clone(),
+        vacations: vacations.clone(),
+        audit_log: audit_log.clone(),
+        leadership,
+        executor: executor.clone(),
+        breakers: breakers.clone(),
+        monitor: monitor.clone(),
+        margin: margin.clone(),
+    };
+
+    handle.spawn(jobs::reaper::run(job_context.clone(), reaper_schedule));
+
+    if let Some(statsd) = config.metrics.statsd.clone() {
+        handle.spawn(jobs::statsd::run(job_context.clone(), statsd));
+    }
+
+    handle.spawn(jobs::moderation::run(job_context.clone()));
+
+    if let (Some(low_stock), Some(schedule)) = (config.low_stock.clone(), low_stock_schedule) {
+        handle.spawn(jobs::low_stock::run(job_context.clone(), low_stock, schedule));
+    }
+
+    handle.spawn(jobs::vacation::run(job_context.clone()));
+
+    if let Some(order_acknowledgment) = config.order_acknowledgment.clone() {
+        handle.spawn(jobs::acknowledgment::run(
+            job_context.clone(),
+            order_acknowledgment,
+            acknowledgment_timers.clone(),
+        ));
+    }
+
+    handle.spawn(jobs::retention::run(job_context.clone(), config.saga.retention.clone()));
+
+    if let Some(saga_stats) = config.saga_stats.clone() {
+        handle.spawn(jobs::saga_stats::run(job_context.clone(), saga_stats));
+    }
+
+    if let Some(interval_s) = config.secrets.refresh_interval_s {
+        handle.spawn(jobs::secrets::run(job_context.clone(), interval_s));
+    }
 
     let serve = Http::new()
         .serve_addr_handle(&address, &*handle, {
+            let handle = handle.clone();
             move || {
                 // Prepare application
-                let app = Application::<Error>::new(ControllerImpl {
-                    config: config.clone(),
-                    http_client: client_handle.clone(),
-                    route_parser: Arc::new(controller::routes::create_route_parser()),
-                });
+                let route_parser = Arc::new(controller::routes::create_route_parser());
+                let app = RequestId::new(Compressed::new(Cors::new(
+                    config.cors.clone(),
+                    Methods::new(
+                        route_parser.clone(),
+                        ETags::new(
+                            route_parser.clone(),
+                            Redactions::new(
+                                route_parser.clone(),
+                                config.redaction.as_ref(),
+                                Accepted::new(
+                                    route_parser.clone(),
+                                    Application::<Error>::new(ControllerImpl {
+                                        config: config.clone(),
+                                        http_client: client_handle.clone(),
+                                        handle: handle.clone(),
+                                        route_parser,
+                                        handlers: Arc::new(Handlers::new()),
+                                        saga_store: saga_store.clone(),
+                                        moderation_queue: moderation_queue.clone(),
+                                        acknowledgment_timers: acknowledgment_timers.clone(),
+                                        vacations: vacations.clone(),
+                                        fraud_overrides: fraud_overrides.clone(),
+                                        audit_log: audit_log.clone(),
+                                        billing_events: billing_events.clone(),
+                                        tracking_events: tracking_events.clone(),
+                                        executor: executor.clone(),
+                                        breakers: breakers.clone(),
+                                        monitor: monitor.clone(),
+                                        margin: margin.clone(),
+                                        endpoints: endpoints.clone(),
+                                    }),
+                                ),
+                            ),
+                        ),
+                    ),
+                )));
 
                 Ok(app)
             }
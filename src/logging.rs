@@ -0,0 +1,178 @@
+//! Levels of log output. Levels of modules are configured in `[logging]` and are handed
+//! to the logger as `RUST_LOG` directives at startup. Global level caps output of every
+//! module and can be changed at runtime with `PUT /admin/log_level`, e.g. module of one
+//! saga type is configured with `debug` and its debug output is switched on only while
+//! global level is raised to `debug`.
+//!
+//! Messages logged while a request is processed, by services and by sagas the request
+//! started, are prefixed with id of the request, see `in_request`.
+use std::cell::RefCell;
+use std::env;
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+
+use failure::Error as FailureError;
+use futures::{Future, Poll};
+use log::{self, LevelFilter};
+
+use config;
+
+const RUST_LOG: &str = "RUST_LOG";
+
+/// `RUST_LOG` directives of the config, e.g. `info,saga_coordinator_lib::services::order=debug`
+pub fn directives(config: &config::Logging) -> Result<String, FailureError> {
+    let mut directives = vec![parse_level(&config.level)?.to_string().to_lowercase()];
+    let mut modules = config.modules.iter().collect::<Vec<_>>();
+    modules.sort();
+    for (module, level) in modules {
+        directives.push(format!("{}={}", module, parse_level(level)?.to_string().to_lowercase()));
+    }
+    Ok(directives.join(","))
+}
+
+/// Passes levels of the config to the logger, `RUST_LOG` set explicitly takes precedence
+pub fn configure_env(config: &config::Logging) -> Result<(), FailureError> {
+    let directives = directives(config)?;
+    if env::var(RUST_LOG).is_err() {
+        env::set_var(RUST_LOG, directives);
+    }
+    Ok(())
+}
+
+/// Sets global level of the config, must be called after the logger is initialized
+pub fn init(config: &config::Logging) -> Result<(), FailureError> {
+    set_level(parse_level(&config.level)?);
+    Ok(())
+}
+
+pub fn level() -> LevelFilter {
+    log::max_level()
+}
+
+pub fn set_level(level: LevelFilter) {
+    if level != log::max_level() {
+        warn!("Log level is changed from {} to {}", log::max_level(), level);
+    }
+    log::set_max_level(level);
+}
+
+pub fn parse_level(level: &str) -> Result<LevelFilter, FailureError> {
+    LevelFilter::from_str(level).map_err(|_| format_err!("Unknown log level {}", level))
+}
+
+thread_local! {
+    /// Request whose future is polled on this thread right now
+    static REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Id of the request being processed, `None` outside of requests, e.g. in jobs
+pub fn request_id() -> Option<String> {
+    REQUEST_ID.with(|request_id| request_id.borrow().clone())
+}
+
+/// Runs `future` in logging context of the request, messages logged while it is polled carry `request_id`
+pub fn in_request<F: Future>(request_id: Option<String>, future: F) -> InRequest<F> {
+    InRequest { request_id, inner: future }
+}
+
+/// Runs `f` in logging context of the request
+pub fn scoped<T, F: FnOnce() -> T>(request_id: Option<String>, f: F) -> T {
+    let _context = ContextGuard::enter(request_id);
+    f()
+}
+
+pub struct InRequest<F> {
+    request_id: Option<String>,
+    inner: F,
+}
+
+impl<F: Future> Future for InRequest<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let _context = ContextGuard::enter(self.request_id.clone());
+        self.inner.poll()
+    }
+}
+
+/// Restores context of the outer request once the inner one is polled, even if polling panics
+struct ContextGuard {
+    outer: Option<String>,
+}
+
+impl ContextGuard {
+    fn enter(request_id: Option<String>) -> Self {
+        let outer = REQUEST_ID.with(|current| mem::replace(&mut *current.borrow_mut(), request_id));
+        ContextGuard { outer }
+    }
+}
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        let outer = self.outer.take();
+        REQUEST_ID.with(|current| *current.borrow_mut() = outer);
+    }
+}
+
+/// Prefix of messages logged in context of a request, e.g. `[request 5f0c1f7e] `
+pub struct RequestPrefix;
+
+impl fmt::Display for RequestPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        REQUEST_ID.with(|request_id| match *request_id.borrow() {
+            Some(ref request_id) => write!(f, "[request {}] ", request_id),
+            None => Ok(()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use futures::future::{self, Future};
+
+    use super::{directives, in_request, request_id, RequestPrefix};
+    use config;
+
+    #[test]
+    fn builds_directives_of_modules() {
+        let mut modules = HashMap::new();
+        modules.insert("saga_coordinator_lib::services::order".to_string(), "DEBUG".to_string());
+        modules.insert("hyper".to_string(), "warn".to_string());
+        let logging = config::Logging {
+            level: "info".to_string(),
+            modules,
+        };
+        assert_eq!(
+            directives(&logging).unwrap(),
+            "info,hyper=warn,saga_coordinator_lib::services::order=debug"
+        );
+
+        let invalid = config::Logging {
+            level: "verbose".to_string(),
+            modules: HashMap::new(),
+        };
+        assert!(directives(&invalid).is_err());
+    }
+
+    #[test]
+    fn keeps_request_id_while_request_is_polled() {
+        let inner = future::lazy(|| {
+            assert_eq!(request_id(), Some("inner".to_string()));
+            Ok::<_, ()>(())
+        });
+        let outer = in_request(
+            Some("outer".to_string()),
+            future::lazy(move || {
+                assert_eq!(RequestPrefix.to_string(), "[request outer] ");
+                in_request(Some("inner".to_string()), inner).map(|_| request_id())
+            }),
+        );
+        assert_eq!(outer.wait(), Ok(Some("outer".to_string())));
+        assert_eq!(request_id(), None);
+        assert_eq!(RequestPrefix.to_string(), "");
+    }
+}
@@ -0,0 +1,18 @@
+//! Macros of the `log` crate prefixing messages with id of the request being processed,
+//! see `logging::in_request`. They shadow macros of the `log` crate in the whole crate.
+
+macro_rules! error {
+    ($($arg:tt)+) => (log!(::log::Level::Error, "{}{}", ::logging::RequestPrefix, format_args!($($arg)+)));
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => (log!(::log::Level::Warn, "{}{}", ::logging::RequestPrefix, format_args!($($arg)+)));
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => (log!(::log::Level::Info, "{}{}", ::logging::RequestPrefix, format_args!($($arg)+)));
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => (log!(::log::Level::Debug, "{}{}", ::logging::RequestPrefix, format_args!($($arg)+)));
+}
@@ -1,3 +1,5 @@
 // Macroses module
 #[macro_use]
+mod logging;
+#[macro_use]
 pub mod validation;
@@ -4,11 +4,17 @@ extern crate stq_logging;
 fn main() {
     let config = lib::config::Config::new().expect("Failed to load service configuration. Please check your 'config' folder");
 
+    lib::secrets::resolve(&config).expect("Failed to resolve secrets referenced in configuration");
+
+    lib::scrubbing::init(&config.scrubbing).expect("Invalid scrubbing patterns in configuration");
+
     // Prepare sentry integration
     let _sentry = lib::sentry_integration::init(config.sentry.as_ref());
 
     // Prepare logger
+    lib::logging::configure_env(&config.logging).expect("Invalid log levels in configuration");
     stq_logging::init(config.graylog.as_ref());
+    lib::logging::init(&config.logging).expect("Invalid log levels in configuration");
 
     lib::start_server(config);
 }
@@ -0,0 +1,315 @@
+//! Saga metrics in prometheus text exposition format or as statsd lines. Values
+//! are computed from saga store on every scrape or push, so they persist
+//! together with saga log.
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use failure::Error as FailureError;
+
+use config::{Config, MetricsLabels};
+use controller::margin::{MarginStats, ProcessingMargin};
+use microservice::CircuitBreakers;
+use models::{BreakerState, DependencyHealth, DependencyReport, PendingModeration, SagaRecord, SagaStatus};
+use moderation::ModerationQueue;
+use saga::executor::ExecutorClassStats;
+use saga::{SagaExecutor, SagaStore};
+
+const ALL_STATUSES: &[SagaStatus] = &[
+    SagaStatus::InProgress,
+    SagaStatus::Completed,
+    SagaStatus::Reverted,
+    SagaStatus::RevertFailed,
+    SagaStatus::Orphaned,
+];
+
+type Samples = BTreeMap<(String, String), u64>;
+
+struct Metric {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    labels: (&'static str, &'static str),
+    samples: Samples,
+}
+
+pub fn render(
+    saga_store: &SagaStore,
+    moderation_queue: &ModerationQueue,
+    executor: &SagaExecutor,
+    breakers: &CircuitBreakers,
+    margin: &ProcessingMargin,
+    config: &Config,
+) -> Result<String, FailureError> {
+    let records = saga_store.find_by_status(ALL_STATUSES)?;
+    let pending = moderation_queue.pending()?;
+    let mut out = String::new();
+    for metric in collect(&records, &pending, &executor.stats(), &breakers.health(), &margin.stats(), config) {
+        write_metric(&mut out, &metric);
+    }
+    Ok(out)
+}
+
+/// Metrics as statsd gauges, counters are sent as gauges too as the totals are kept in saga log.
+/// Labels are sent as datadog tags if `datadog_tags` is set and are appended to metric name otherwise.
+pub fn render_statsd(
+    saga_store: &SagaStore,
+    moderation_queue: &ModerationQueue,
+    executor: &SagaExecutor,
+    breakers: &CircuitBreakers,
+    margin: &ProcessingMargin,
+    config: &Config,
+    prefix: &str,
+    datadog_tags: bool,
+) -> Result<Vec<String>, FailureError> {
+    let records = saga_store.find_by_status(ALL_STATUSES)?;
+    let pending = moderation_queue.pending()?;
+    let mut lines = vec![];
+    for metric in collect(&records, &pending, &executor.stats(), &breakers.health(), &margin.stats(), config) {
+        for ((first, second), value) in &metric.samples {
+            let line = if datadog_tags {
+                format!(
+                    "{}{}:{}|g|#{}:{},{}:{}",
+                    prefix, metric.name, value, metric.labels.0, first, metric.labels.1, second
+                )
+            } else {
+                format!(
+                    "{}{}.{}.{}:{}|g",
+                    prefix,
+                    metric.name,
+                    statsd_segment(first),
+                    statsd_segment(second),
+                    value
+                )
+            };
+            lines.push(line);
+        }
+    }
+    Ok(lines)
+}
+
+/// Downstream call outcomes within the monitor window as statsd gauges, one set per microservice
+pub fn render_dependencies_statsd(reports: &[DependencyReport], prefix: &str, datadog_tags: bool) -> Vec<String> {
+    let mut lines = vec![];
+    for report in reports {
+        let mut values = vec![
+            ("saga_coordinator_downstream_calls", report.calls.to_string()),
+            ("saga_coordinator_downstream_error_rate", report.error_rate.to_string()),
+        ];
+        if let Some(latency) = report.p95_latency_ms {
+            values.push(("saga_coordinator_downstream_p95_latency_ms", latency.to_string()));
+        }
+        for (name, value) in values {
+            let line = if datadog_tags {
+                format!("{}{}:{}|g|#service:{}", prefix, name, value, report.service)
+            } else {
+                format!("{}{}.{}:{}|g", prefix, name, statsd_segment(&report.service), value)
+            };
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+fn collect(
+    records: &[SagaRecord],
+    pending: &[PendingModeration],
+    executor: &[ExecutorClassStats],
+    dependencies: &[DependencyHealth],
+    margin: &MarginStats,
+    config: &Config,
+) -> Vec<Metric> {
+    let labels = config.metrics.labels;
+    let step_label = |step: &str| match labels {
+        MetricsLabels::Step => step.to_string(),
+        MetricsLabels::Service => downstream_service(step).to_string(),
+    };
+
+    let mut sagas = Samples::new();
+    let mut compensations = Samples::new();
+    let mut compensated_steps = Samples::new();
+    let mut compensation_failures = Samples::new();
+    let mut warnings = Samples::new();
+
+    for record in records {
+        let saga_type = record.saga_type.to_string();
+        *sagas.entry((saga_type.clone(), record.status.to_string())).or_insert(0) += 1;
+
+        for report in &record.compensations {
+            let outcome = if report.is_success() { "succeeded" } else { "failed" };
+            *compensations.entry((saga_type.clone(), outcome.to_string())).or_insert(0) += 1;
+
+            for stage in &report.compensated {
+                *compensated_steps
+                    .entry((saga_type.clone(), step_label(stage.step().0)))
+                    .or_insert(0) += 1;
+            }
+
+            for failure in &report.failures {
+                let step = failure.stage.step().0;
+                *compensation_failures.entry((saga_type.clone(), step_label(step))).or_insert(0) += 1;
+            }
+        }
+
+        for warning in &record.warnings {
+            *warnings.entry((saga_type.clone(), step_label(&warning.step))).or_insert(0) += 1;
+        }
+    }
+
+    let sla = Duration::from_secs(config.moderation.sla_s);
+    let now = SystemTime::now();
+    let mut moderation = Samples::new();
+    for item in pending {
+        let sla_state = if item.is_overdue(sla, now) { "exceeded" } else { "within" };
+        *moderation
+            .entry((item.item.item_type().to_string(), sla_state.to_string()))
+            .or_insert(0) += 1;
+    }
+
+    let mut executor_sagas = Samples::new();
+    for class in executor {
+        executor_sagas.insert((class.priority.to_string(), "queued".to_string()), class.queued as u64);
+        executor_sagas.insert((class.priority.to_string(), "running".to_string()), class.running as u64);
+    }
+
+    let mut breakers = Samples::new();
+    for health in dependencies {
+        for state in BreakerState::all() {
+            let value = if health.state == *state { 1 } else { 0 };
+            breakers.insert((health.dependency.to_string(), state.to_string()), value);
+        }
+    }
+
+    let mut processing = Samples::new();
+    for &(quantile, overhead_ms) in &margin.overhead_ms {
+        if let Some(overhead_ms) = overhead_ms {
+            processing.insert(("overhead".to_string(), quantile.to_string()), overhead_ms);
+        }
+    }
+    let margin_key = match margin.adaptive_quantile {
+        Some(quantile) => ("adaptive_margin".to_string(), quantile.to_string()),
+        None => ("static_margin".to_string(), "none".to_string()),
+    };
+    processing.insert(margin_key, margin.margin_ms);
+
+    let step_label_name = match labels {
+        MetricsLabels::Step => "step",
+        MetricsLabels::Service => "service",
+    };
+
+    vec![
+        Metric {
+            name: "saga_coordinator_sagas",
+            kind: "gauge",
+            help: "Number of sagas in saga log by status",
+            labels: ("saga_type", "status"),
+            samples: sagas,
+        },
+        Metric {
+            name: "saga_coordinator_compensations_total",
+            kind: "counter",
+            help: "Number of saga compensation attempts by outcome",
+            labels: ("saga_type", "outcome"),
+            samples: compensations,
+        },
+        Metric {
+            name: "saga_coordinator_compensated_steps_total",
+            kind: "counter",
+            help: "Number of saga steps rolled back by compensation",
+            labels: ("saga_type", step_label_name),
+            samples: compensated_steps,
+        },
+        Metric {
+            name: "saga_coordinator_compensation_failures_total",
+            kind: "counter",
+            help: "Number of failed compensations of saga steps",
+            labels: ("saga_type", step_label_name),
+            samples: compensation_failures,
+        },
+        Metric {
+            name: "saga_coordinator_warnings_total",
+            kind: "counter",
+            help: "Number of failed soft steps of sagas",
+            labels: ("saga_type", step_label_name),
+            samples: warnings,
+        },
+        Metric {
+            name: "saga_coordinator_moderation_pending",
+            kind: "gauge",
+            help: "Number of stores and base products awaiting moderation by moderation SLA state",
+            labels: ("item_type", "sla"),
+            samples: moderation,
+        },
+        Metric {
+            name: "saga_coordinator_background_sagas",
+            kind: "gauge",
+            help: "Number of sagas executed in background by priority class and state",
+            labels: ("priority", "state"),
+            samples: executor_sagas,
+        },
+        Metric {
+            name: "saga_coordinator_circuit_breaker_state",
+            kind: "gauge",
+            help: "Current state of circuit breaker of non critical dependency, 1 for the state breaker is in",
+            labels: ("dependency", "state"),
+            samples: breakers,
+        },
+        Metric {
+            name: "saga_coordinator_processing_time_ms",
+            kind: "gauge",
+            help: "Overhead of the coordinator over the latest requests and margin subtracted from timeout of the caller",
+            labels: ("kind", "quantile"),
+            samples: processing,
+        },
+    ]
+}
+
+/// Microservice called by saga step
+fn downstream_service(step: &str) -> &str {
+    match step {
+        "account_creation" => "users",
+        "store_creation" | "store_role_set" | "store_verification" => "stores",
+        "shipping_upsert" => "delivery",
+        "fraud_screening" => "fraud_scoring",
+        "orders_notification"
+        | "store_verification_notification"
+        | "store_payout_notification"
+        | "dispute_notification"
+        | "return_notification"
+        | "cancellation_notification"
+        | "customers_notification" => "notifications",
+        // the rest of steps are prefixed with the service, e.g. `billing_role_set`
+        _ => step.split('_').next().unwrap_or(step),
+    }
+}
+
+/// Duration in whole milliseconds, as reported in metrics, events and stats
+pub fn duration_ms(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
+
+fn write_metric(out: &mut String, metric: &Metric) {
+    out.push_str(&format!(
+        "# HELP {} {}\n# TYPE {} {}\n",
+        metric.name, metric.help, metric.name, metric.kind
+    ));
+    for ((first, second), value) in &metric.samples {
+        out.push_str(&format!(
+            "{}{{{}=\"{}\",{}=\"{}\"}} {}\n",
+            metric.name, metric.labels.0, first, metric.labels.1, second, value
+        ));
+    }
+}
+
+/// Label value usable as part of statsd metric name
+fn statsd_segment(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -7,11 +7,13 @@ use stq_routes::model::Model as StqModel;
 use stq_routes::service::Service as StqService;
 use stq_types::*;
 
-use super::{ApiFuture, Initiator};
+use super::{idempotent_headers, retry_lookup, ApiFuture, DependencyMonitor, Initiator, Requester};
 
+use audit::AuditScope;
 use config;
 use errors::Error;
 use models::*;
+use services::parse_validation_errors;
 
 pub trait BillingMicroservice {
     fn delete_user_merchant(&self, initiator: Option<Initiator>, user_id: UserId) -> ApiFuture<MerchantId>;
@@ -22,13 +24,38 @@ pub trait BillingMicroservice {
     fn create_role(&self, initiator: Option<Initiator>, payload: NewRole<BillingRole>) -> ApiFuture<NewRole<BillingRole>>;
     fn create_invoice(&self, initiator: Initiator, payload: CreateInvoice) -> ApiFuture<Invoice>;
     fn revert_create_invoice(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<SagaId>;
+    fn get_invoice_by_saga_id(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<Option<Invoice>>;
+    /// Invoice the order was checked out with, `None` if the order has none
+    fn get_order_invoice(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<Option<Invoice>>;
     fn decline_order(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<()>;
     fn capture_order(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<()>;
     fn set_payment_state(&self, initiator: Option<Initiator>, order_id: OrderId, payload: OrderPaymentStateRequest) -> ApiFuture<()>;
+    fn get_store_kyc_status(&self, initiator: Initiator, store_id: StoreId) -> ApiFuture<StoreKycStatus>;
+    fn get_payout_eligible_orders(&self, initiator: Initiator, store_id: StoreId) -> ApiFuture<Vec<PayoutEligibleOrder>>;
+    fn create_payout(&self, initiator: Initiator, payload: NewPayout) -> ApiFuture<Payout>;
+    fn revert_create_payout(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<()>;
+    fn get_payout_by_saga_id(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<Option<Payout>>;
+    fn create_dispute(&self, initiator: Initiator, payload: NewDispute) -> ApiFuture<Dispute>;
+    fn revert_create_dispute(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<()>;
+    fn get_dispute_by_saga_id(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<Option<Dispute>>;
+    fn resolve_dispute(&self, initiator: Initiator, order_id: OrderId, payload: ResolveDisputePayload) -> ApiFuture<Dispute>;
+    /// Removes cancelled orders from the invoice they were checked out with
+    fn amend_invoice(&self, initiator: Initiator, payload: AmendInvoice) -> ApiFuture<InvoiceAmendment>;
+    fn revert_amend_invoice(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<()>;
+    fn get_invoice_amendment_by_saga_id(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<Option<InvoiceAmendment>>;
+    fn reserve_gift_cards(&self, initiator: Initiator, payload: ReserveGiftCards) -> ApiFuture<GiftCardReservation>;
+    fn release_gift_cards(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<()>;
+    fn calculate_taxes(&self, initiator: Initiator, payload: CalculateTaxes) -> ApiFuture<Vec<OrderTaxes>>;
+    fn get_order_taxes(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<Vec<TaxLine>>;
+    /// Generates receipt of the invoice, receipt generated before is returned if there is one.
+    /// Request is keyed by the invoice, so it is retried on transient failures
+    fn create_invoice_receipt(&self, initiator: Initiator, invoice_id: InvoiceId, payload: NewReceipt) -> ApiFuture<Receipt>;
+    /// Receipt of invoice the order was paid with, `None` if it has not been generated
+    fn get_order_receipt(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<Option<Receipt>>;
 }
 
 pub struct BillingMicroserviceImpl<T: HttpClient + Clone> {
-    http_client: T,
+    requester: Requester<T>,
     config: config::Config,
 }
 
@@ -36,105 +63,125 @@ impl<T: 'static + HttpClient + Clone> BillingMicroservice for BillingMicroservic
     fn delete_user_merchant(&self, initiator: Option<Initiator>, user_id: UserId) -> ApiFuture<MerchantId> {
         let url = format!("{}/merchants/user/{}", self.billing_url(), user_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deleting user merchant in billing microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), _>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting user merchant in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn create_user_merchant(&self, initiator: Option<Initiator>, payload: CreateUserMerchantPayload) -> ApiFuture<Merchant> {
         let url = format!("{}/merchants/user", self.billing_url());
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Creating merchant in billing microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating merchant in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn delete_store_merchant(&self, initiator: Option<Initiator>, store_id: StoreId) -> ApiFuture<MerchantId> {
         let url = format!("{}/merchants/store/{}", self.billing_url(), store_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deleting store merchant in billing microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), _>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting store merchant in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn delete_role(&self, initiator: Option<Initiator>, role_id: RoleId) -> ApiFuture<NewRole<BillingRole>> {
         let url = format!("{}/roles/by-id/{}", self.billing_url(), role_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deleting role in billing microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), _>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting role in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn create_store_merchant(&self, initiator: Option<Initiator>, payload: CreateStoreMerchantPayload) -> ApiFuture<Merchant> {
         let url = format!("{}/merchants/store", self.billing_url());
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Creating merchant in billing microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating merchant in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn create_role(&self, initiator: Option<Initiator>, payload: NewRole<BillingRole>) -> ApiFuture<NewRole<BillingRole>> {
         let url = format!("{}/{}", self.billing_url(), StqModel::Role.to_url());
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Creating role in billing microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating role in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn revert_create_invoice(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<SagaId> {
         let url = format!("{}/invoices/by-saga-id/{}", self.billing_url(), saga_id.0);
         Box::new(
-            super::request::<_, (), SagaId>(self.http_client.clone(), Method::Delete, url, None, Some(initiator.into())).map_err(|e| {
-                e.context("Reverting invoice creation in billing microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), SagaId>(Method::Delete, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Reverting invoice creation in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn get_invoice_by_saga_id(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<Option<Invoice>> {
+        let url = format!("{}/invoices/by-saga-id/{}", self.billing_url(), saga_id.0);
+        Box::new(
+            self.requester
+                .request::<(), Option<Invoice>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Getting invoice by saga id in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn get_order_invoice(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<Option<Invoice>> {
+        let url = format!("{}/orders/{}/invoice", self.billing_url(), order_id);
+        Box::new(
+            self.requester
+                .request::<(), Option<Invoice>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Getting order invoice in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn create_invoice(&self, initiator: Initiator, payload: CreateInvoice) -> ApiFuture<Invoice> {
         let url = format!("{}/invoices", self.billing_url());
         Box::new(
-            super::request::<_, CreateInvoice, Invoice>(self.http_client.clone(), Method::Post, url, Some(payload), Some(initiator.into()))
+            self.requester
+                .request::<CreateInvoice, Invoice>(Method::Post, url, Some(payload), Some(initiator.into()))
                 .map_err(|e| {
                     e.context("Creating invoice in billing microservice failed.")
                         .context(Error::HttpClient)
@@ -145,46 +192,302 @@ impl<T: 'static + HttpClient + Clone> BillingMicroservice for BillingMicroservic
     fn decline_order(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<()> {
         let url = format!("{}/orders/{}/decline", self.billing_url(), order_id);
         Box::new(
-            super::request::<_, (), ()>(self.http_client.clone(), Method::Post, url, None, Some(initiator.into())).map_err(move |e| {
-                e.context(format!("Declining order {} in billing microservice failed", order_id))
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), ()>(Method::Post, url, None, Some(initiator.into()))
+                .map_err(move |e| {
+                    e.context(format!("Declining order {} in billing microservice failed", order_id))
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
     fn capture_order(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<()> {
         let url = format!("{}/orders/{}/capture", self.billing_url(), order_id);
         Box::new(
-            super::request::<_, (), ()>(self.http_client.clone(), Method::Post, url, None, Some(initiator.into())).map_err(move |e| {
-                e.context(format!("Capturing order {} in billing microservice failed", order_id))
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), ()>(Method::Post, url, None, Some(initiator.into()))
+                .map_err(move |e| {
+                    e.context(format!("Capturing order {} in billing microservice failed", order_id))
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn set_payment_state(&self, initiator: Option<Initiator>, order_id: OrderId, payload: OrderPaymentStateRequest) -> ApiFuture<()> {
         let url = format!("{}/orders/{}/set_payment_state", self.billing_url(), order_id);
         Box::new(
-            super::request::<_, OrderPaymentStateRequest, ()>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(move |e| {
-                e.context(format!("Set payment state order {} in billing microservice failed", order_id))
+            self.requester
+                .request::<OrderPaymentStateRequest, ()>(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(move |e| {
+                    e.context(format!("Set payment state order {} in billing microservice failed", order_id))
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn get_store_kyc_status(&self, initiator: Initiator, store_id: StoreId) -> ApiFuture<StoreKycStatus> {
+        let url = format!("{}/merchants/store/{}/kyc", self.billing_url(), store_id);
+        Box::new(
+            self.requester
+                .request::<(), StoreKycStatus>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(move |e| {
+                    e.context(format!("Getting KYC status of store {} in billing microservice failed", store_id))
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn get_payout_eligible_orders(&self, initiator: Initiator, store_id: StoreId) -> ApiFuture<Vec<PayoutEligibleOrder>> {
+        let url = format!("{}/payouts/by-store-id/{}/eligible-orders", self.billing_url(), store_id);
+        Box::new(
+            self.requester
+                .request::<(), Vec<PayoutEligibleOrder>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(move |e| {
+                    e.context(format!(
+                        "Getting orders eligible for payout of store {} in billing microservice failed",
+                        store_id
+                    ))
+                    .context(Error::HttpClient)
+                    .into()
+                }),
+        )
+    }
+
+    fn create_payout(&self, initiator: Initiator, payload: NewPayout) -> ApiFuture<Payout> {
+        let url = format!("{}/payouts", self.billing_url());
+        Box::new(
+            self.requester
+                .request::<NewPayout, Payout>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Creating payout in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn revert_create_payout(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<()> {
+        let url = format!("{}/payouts/by-saga-id/{}", self.billing_url(), saga_id.0);
+        Box::new(
+            self.requester
+                .request::<(), ()>(Method::Delete, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Reverting payout creation in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn get_payout_by_saga_id(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<Option<Payout>> {
+        let url = format!("{}/payouts/by-saga-id/{}", self.billing_url(), saga_id.0);
+        Box::new(
+            self.requester
+                .request::<(), Option<Payout>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Getting payout by saga id in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn create_dispute(&self, initiator: Initiator, payload: NewDispute) -> ApiFuture<Dispute> {
+        let url = format!("{}/disputes", self.billing_url());
+        Box::new(
+            self.requester
+                .request::<NewDispute, Dispute>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Registering dispute in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn revert_create_dispute(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<()> {
+        let url = format!("{}/disputes/by-saga-id/{}", self.billing_url(), saga_id.0);
+        Box::new(
+            self.requester
+                .request::<(), ()>(Method::Delete, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Reverting dispute registration in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn get_dispute_by_saga_id(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<Option<Dispute>> {
+        let url = format!("{}/disputes/by-saga-id/{}", self.billing_url(), saga_id.0);
+        Box::new(
+            self.requester
+                .request::<(), Option<Dispute>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Getting dispute by saga id in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn resolve_dispute(&self, initiator: Initiator, order_id: OrderId, payload: ResolveDisputePayload) -> ApiFuture<Dispute> {
+        let url = format!("{}/orders/{}/dispute/resolve", self.billing_url(), order_id);
+        Box::new(
+            self.requester
+                .request::<ResolveDisputePayload, Dispute>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(move |e| {
+                    e.context(format!("Resolving dispute of order {} in billing microservice failed", order_id))
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn amend_invoice(&self, initiator: Initiator, payload: AmendInvoice) -> ApiFuture<InvoiceAmendment> {
+        let url = format!("{}/invoices/amendments", self.billing_url());
+        Box::new(
+            self.requester
+                .request::<AmendInvoice, InvoiceAmendment>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    parse_validation_errors(e.into(), &["order"])
+                        .context("Amending invoice in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn revert_amend_invoice(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<()> {
+        let url = format!("{}/invoices/amendments/by-saga-id/{}", self.billing_url(), saga_id.0);
+        Box::new(
+            self.requester
+                .request::<(), ()>(Method::Delete, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Reverting invoice amendment in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn get_invoice_amendment_by_saga_id(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<Option<InvoiceAmendment>> {
+        let url = format!("{}/invoices/amendments/by-saga-id/{}", self.billing_url(), saga_id.0);
+        Box::new(
+            self.requester
+                .request::<(), Option<InvoiceAmendment>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Getting invoice amendment by saga id in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn reserve_gift_cards(&self, initiator: Initiator, payload: ReserveGiftCards) -> ApiFuture<GiftCardReservation> {
+        let url = format!("{}/gift_cards/reservations", self.billing_url());
+        Box::new(
+            self.requester
+                .request::<ReserveGiftCards, GiftCardReservation>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    parse_validation_errors(e.into(), &["gift_card_codes"])
+                        .context("Reserving gift cards in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn release_gift_cards(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<()> {
+        let url = format!("{}/gift_cards/reservations/by-saga-id/{}", self.billing_url(), saga_id.0);
+        Box::new(
+            self.requester
+                .request::<(), ()>(Method::Delete, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Releasing gift cards reservation in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn calculate_taxes(&self, initiator: Initiator, payload: CalculateTaxes) -> ApiFuture<Vec<OrderTaxes>> {
+        let url = format!("{}/taxes/calculate", self.billing_url());
+        Box::new(
+            self.requester
+                .request::<CalculateTaxes, Vec<OrderTaxes>>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Calculating taxes in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn get_order_taxes(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<Vec<TaxLine>> {
+        let url = format!("{}/orders/{}/taxes", self.billing_url(), order_id);
+        Box::new(
+            self.requester
+                .request::<(), Vec<TaxLine>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Getting order taxes in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn create_invoice_receipt(&self, initiator: Initiator, invoice_id: InvoiceId, payload: NewReceipt) -> ApiFuture<Receipt> {
+        let url = format!("{}/invoices/{}/receipt", self.billing_url(), invoice_id);
+        let requester = self.requester.clone();
+        let key = format!("invoice-receipt-{}", invoice_id);
+        Box::new(
+            retry_lookup(&self.config.saga.lookup_retries, move || {
+                let headers = idempotent_headers(initiator, key.clone());
+                Box::new(requester.request::<NewReceipt, Receipt>(Method::Post, url.clone(), Some(payload.clone()), Some(headers)))
+                    as ApiFuture<_>
+            })
+            .map_err(|e| {
+                e.context("Creating invoice receipt in billing microservice failed.")
                     .context(Error::HttpClient)
                     .into()
             }),
         )
     }
+
+    fn get_order_receipt(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<Option<Receipt>> {
+        let url = format!("{}/orders/{}/receipt", self.billing_url(), order_id);
+        Box::new(
+            self.requester
+                .request::<(), Option<Receipt>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Getting order receipt in billing microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
 }
 
 impl<T: HttpClient + Clone> BillingMicroserviceImpl<T> {
     pub fn new(http_client: T, config: config::Config) -> Self {
-        Self { http_client, config }
+        Self {
+            requester: Requester::new(http_client, &config),
+            config,
+        }
+    }
+
+    pub fn with_audit(mut self, audit: AuditScope) -> Self {
+        self.requester.audit = Some(audit);
+        self
+    }
+
+    pub fn with_monitor(mut self, monitor: DependencyMonitor) -> Self {
+        self.requester.monitor = Some(monitor.scope("billing"));
+        self
     }
 
     fn billing_url(&self) -> String {
@@ -0,0 +1,156 @@
+//! Circuit breakers of dependencies saga steps can do without. After several calls
+//! to the dependency fail in a row, its breaker opens and further calls fail right
+//! away, so soft steps making them are skipped with a warning instead of slowing
+//! every saga down with requests bound to fail. Once `open_ms` passes, calls are
+//! let through again: the first success closes the breaker, a failure opens it anew.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use failure::Error;
+use futures::future;
+use futures::Future;
+
+use super::ApiFuture;
+use config;
+use errors::Error as ServiceError;
+use models::{BreakerState, Dependency, DependencyHealth};
+
+#[derive(Clone, Debug, Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+
+/// Breakers of every non critical dependency, shared by all requests and jobs
+#[derive(Clone)]
+pub struct CircuitBreakers {
+    config: config::CircuitBreaker,
+    breakers: Arc<Mutex<HashMap<Dependency, Breaker>>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(config: config::CircuitBreaker) -> Self {
+        Self {
+            config,
+            breakers: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn state(&self, dependency: Dependency) -> BreakerState {
+        let breakers = self.breakers.lock().unwrap();
+        let breaker = breakers.get(&dependency).cloned().unwrap_or_default();
+        state_at(&self.config, &breaker, Instant::now())
+    }
+
+    pub fn health(&self) -> Vec<DependencyHealth> {
+        let breakers = self.breakers.lock().unwrap();
+        let now = Instant::now();
+        Dependency::all()
+            .iter()
+            .map(|dependency| {
+                let breaker = breakers.get(dependency).cloned().unwrap_or_default();
+                DependencyHealth {
+                    dependency: *dependency,
+                    state: state_at(&self.config, &breaker, now),
+                    consecutive_failures: breaker.consecutive_failures,
+                }
+            })
+            .collect()
+    }
+
+    pub fn record_success(&self, dependency: Dependency) {
+        let mut breakers = self.breakers.lock().unwrap();
+        let breaker = breakers.entry(dependency).or_insert_with(Breaker::default);
+        if breaker.opened_at.is_some() {
+            info!("Circuit breaker of {} is closed, calls to it are made again", dependency);
+        }
+        *breaker = Breaker::default();
+    }
+
+    pub fn record_failure(&self, dependency: Dependency) {
+        let mut breakers = self.breakers.lock().unwrap();
+        let breaker = breakers.entry(dependency).or_insert_with(Breaker::default);
+        record_failure_at(&self.config, breaker, Instant::now());
+        if breaker.consecutive_failures == self.config.failure_threshold {
+            warn!(
+                "Circuit breaker of {} is open after {} failed calls, steps calling it are skipped",
+                dependency, breaker.consecutive_failures
+            );
+        }
+    }
+
+    /// Fails call to the dependency right away while its breaker is open, records outcome of the call otherwise
+    pub fn guard<T, F>(&self, dependency: Dependency, call: F) -> ApiFuture<T>
+    where
+        T: 'static,
+        F: Future<Item = T, Error = Error> + 'static,
+    {
+        if self.state(dependency) == BreakerState::Open {
+            return Box::new(future::err(
+                format_err!("Circuit breaker of {} is open, call is skipped", dependency)
+                    .context(ServiceError::HttpClient)
+                    .into(),
+            ));
+        }
+
+        let breakers = self.clone();
+        Box::new(call.then(move |res| {
+            match res {
+                Ok(_) => breakers.record_success(dependency),
+                Err(_) => breakers.record_failure(dependency),
+            }
+            res
+        }))
+    }
+}
+
+fn state_at(config: &config::CircuitBreaker, breaker: &Breaker, now: Instant) -> BreakerState {
+    match breaker.opened_at {
+        Some(opened_at) if now.duration_since(opened_at) < Duration::from_millis(config.open_ms) => BreakerState::Open,
+        Some(_) => BreakerState::HalfOpen,
+        None => BreakerState::Closed,
+    }
+}
+
+/// Opens the breaker once failures reach the threshold, failure of half open breaker opens it again
+fn record_failure_at(config: &config::CircuitBreaker, breaker: &mut Breaker, now: Instant) {
+    breaker.consecutive_failures += 1;
+    if breaker.consecutive_failures >= config.failure_threshold {
+        breaker.opened_at = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{record_failure_at, state_at, Breaker};
+    use config;
+    use models::BreakerState;
+
+    #[test]
+    fn opens_after_threshold_and_half_opens_after_timeout() {
+        let config = config::CircuitBreaker {
+            failure_threshold: 3,
+            open_ms: 1000,
+        };
+        let start = Instant::now();
+        let mut breaker = Breaker::default();
+
+        record_failure_at(&config, &mut breaker, start);
+        record_failure_at(&config, &mut breaker, start);
+        assert_eq!(state_at(&config, &breaker, start), BreakerState::Closed);
+
+        record_failure_at(&config, &mut breaker, start);
+        assert_eq!(state_at(&config, &breaker, start + Duration::from_millis(999)), BreakerState::Open);
+        assert_eq!(
+            state_at(&config, &breaker, start + Duration::from_millis(1000)),
+            BreakerState::HalfOpen
+        );
+
+        let probe = start + Duration::from_millis(1500);
+        record_failure_at(&config, &mut breaker, probe);
+        assert_eq!(state_at(&config, &breaker, probe), BreakerState::Open);
+    }
+}
@@ -0,0 +1,164 @@
+//! Time budget of calls to microservices made for a request. Every client of the
+//! request shares the budget, each call is limited by the time left before the
+//! deadline at the moment the call is made. Compensations and work continuing in
+//! background after the response detach from the deadline, their calls are limited
+//! by `client.http_timeout_ms` only. Budget also measures time the request spends
+//! waiting for microservices, the rest is overhead of the coordinator itself.
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::Future;
+use hyper::header::Headers;
+use hyper::server::Response;
+use hyper::Method;
+
+use stq_http::client::{Error as HttpError, HttpClient, TimeLimitedHttpClient};
+
+#[derive(Clone, Debug)]
+pub struct Budget {
+    state: Arc<Mutex<BudgetState>>,
+    /// Limit of a single call
+    call_timeout: Duration,
+}
+
+#[derive(Debug, Default)]
+struct BudgetState {
+    /// `None` once the budget is detached from the deadline
+    deadline: Option<Instant>,
+    /// Calls in flight, overlapping calls are counted as waiting once
+    in_flight: usize,
+    waiting_since: Option<Instant>,
+    waited: Duration,
+}
+
+impl Budget {
+    pub fn new(deadline: Instant, call_timeout: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(BudgetState {
+                deadline: Some(deadline),
+                ..BudgetState::default()
+            })),
+            call_timeout,
+        }
+    }
+
+    /// Budget of work which is not bound to any deadline, e.g. background jobs
+    pub fn detached(call_timeout: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(BudgetState::default())),
+            call_timeout,
+        }
+    }
+
+    /// Further calls are limited by call timeout only, for every client sharing the budget
+    pub fn detach(&self) {
+        self.state.lock().unwrap().deadline = None;
+    }
+
+    /// Time left for the next call, zero if the deadline has passed
+    pub fn time_left(&self) -> Duration {
+        time_left_at(self.state.lock().unwrap().deadline, self.call_timeout, Instant::now())
+    }
+
+    /// Time spent waiting for at least one microservice call
+    pub fn waited(&self) -> Duration {
+        let state = self.state.lock().unwrap();
+        match state.waiting_since {
+            Some(since) => state.waited + since.elapsed(),
+            None => state.waited,
+        }
+    }
+
+    fn call_started(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight == 0 {
+            state.waiting_since = Some(Instant::now());
+        }
+        state.in_flight += 1;
+    }
+
+    fn call_finished(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight = state.in_flight.saturating_sub(1);
+        if state.in_flight == 0 {
+            if let Some(since) = state.waiting_since.take() {
+                state.waited += since.elapsed();
+            }
+        }
+    }
+}
+
+fn time_left_at(deadline: Option<Instant>, call_timeout: Duration, now: Instant) -> Duration {
+    match deadline {
+        Some(deadline) if deadline > now => ::std::cmp::min(deadline - now, call_timeout),
+        Some(_) => Duration::new(0, 0),
+        None => call_timeout,
+    }
+}
+
+/// Http client limiting every call by the time left in the budget
+#[derive(Clone)]
+pub struct BudgetedHttpClient<C: HttpClient + Clone> {
+    inner: C,
+    budget: Budget,
+}
+
+impl<C: HttpClient + Clone> BudgetedHttpClient<C> {
+    pub fn new(inner: C, budget: Budget) -> Self {
+        Self { inner, budget }
+    }
+}
+
+impl<C: HttpClient + Clone> HttpClient for BudgetedHttpClient<C> {
+    fn request(
+        &self,
+        method: Method,
+        url: String,
+        body: Option<String>,
+        headers: Option<Headers>,
+    ) -> Box<Future<Item = Response, Error = HttpError> + Send> {
+        let budget = self.budget.clone();
+        budget.call_started();
+        Box::new(
+            TimeLimitedHttpClient::new(self.inner.clone(), budget.time_left())
+                .request(method, url, body, headers)
+                .then(move |res| {
+                    budget.call_finished();
+                    res
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{time_left_at, Budget};
+
+    #[test]
+    fn limits_calls_by_deadline_and_call_timeout() {
+        let now = Instant::now();
+        let call_timeout = Duration::from_millis(5000);
+        assert_eq!(
+            time_left_at(Some(now + Duration::from_millis(800)), call_timeout, now),
+            Duration::from_millis(800)
+        );
+        assert_eq!(time_left_at(Some(now + Duration::from_secs(60)), call_timeout, now), call_timeout);
+        assert_eq!(time_left_at(Some(now), call_timeout, now), Duration::new(0, 0));
+        assert_eq!(time_left_at(None, call_timeout, now), call_timeout);
+    }
+
+    #[test]
+    fn counts_overlapping_calls_once() {
+        let budget = Budget::detached(Duration::from_millis(5000));
+        budget.call_started();
+        budget.call_started();
+        budget.call_finished();
+        assert!(budget.state.lock().unwrap().waiting_since.is_some());
+        budget.call_finished();
+        let state = budget.state.lock().unwrap();
+        assert_eq!(state.in_flight, 0);
+        assert!(state.waiting_since.is_none());
+    }
+}
@@ -0,0 +1,177 @@
+//! Cassette of calls to microservices. In record mode responses of microservices are
+//! appended to json file as they come, in playback mode calls are answered from that
+//! file and microservices are not called at all. Calls recorded during an incident
+//! in development environment can then be played back by regression tests.
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use failure::Error as FailureError;
+use futures::future;
+use futures::prelude::*;
+use hyper::header::{Headers, TransferEncoding};
+use hyper::server::Response;
+use hyper::{Method, StatusCode};
+
+use stq_http::client::{Error as HttpError, HttpClient};
+
+use config::{self, CassetteMode};
+use json_file::JsonFile;
+
+/// Response of microservice to a call
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    pub url: String,
+    pub request_body: Option<String>,
+    pub status: u16,
+    pub response_body: String,
+}
+
+#[derive(Clone)]
+pub struct Cassette {
+    mode: CassetteMode,
+    file: JsonFile,
+    interactions: Arc<Mutex<Vec<Interaction>>>,
+    /// Whether interaction is played back already
+    played: Arc<Mutex<Vec<bool>>>,
+}
+
+impl Cassette {
+    /// Loads cassette for playback, cassette for recording is started anew
+    pub fn new(config: &config::Cassette) -> Result<Self, FailureError> {
+        let file = JsonFile::new(PathBuf::from(&config.path), "cassette");
+        let interactions = match config.mode {
+            CassetteMode::Record => vec![],
+            CassetteMode::Playback => file.read()?.ok_or_else(|| format_err!("Cassette {} is not found", config.path))?,
+        };
+        Ok(Self::with_interactions(config.mode, file, interactions))
+    }
+
+    fn with_interactions(mode: CassetteMode, file: JsonFile, interactions: Vec<Interaction>) -> Self {
+        let played = vec![false; interactions.len()];
+        Self {
+            mode,
+            file,
+            interactions: Arc::new(Mutex::new(interactions)),
+            played: Arc::new(Mutex::new(played)),
+        }
+    }
+
+    /// The first interaction with the method and url that is not played back yet
+    fn play(&self, method: &Method, url: &str) -> Option<Interaction> {
+        let interactions = self.interactions.lock().unwrap();
+        let mut played = self.played.lock().unwrap();
+        let method = method.to_string();
+        let index = (0..interactions.len())
+            .find(|&index| !played[index] && interactions[index].method == method && interactions[index].url == url)?;
+        played[index] = true;
+        Some(interactions[index].clone())
+    }
+
+    fn record(&self, interaction: Interaction) -> Result<(), FailureError> {
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions.push(interaction);
+        self.file.write(&*interactions)
+    }
+}
+
+/// Http client recording calls to cassette or playing them back, calls are passed through if there is no cassette
+#[derive(Clone)]
+pub struct CassetteHttpClient<C: HttpClient + Clone> {
+    inner: C,
+    cassette: Option<Cassette>,
+}
+
+impl<C: HttpClient + Clone> CassetteHttpClient<C> {
+    pub fn new(inner: C, cassette: Option<Cassette>) -> Self {
+        Self { inner, cassette }
+    }
+}
+
+impl<C: HttpClient + Clone> HttpClient for CassetteHttpClient<C> {
+    fn request(
+        &self,
+        method: Method,
+        url: String,
+        body: Option<String>,
+        headers: Option<Headers>,
+    ) -> Box<Future<Item = Response, Error = HttpError> + Send> {
+        let cassette = match self.cassette {
+            Some(ref cassette) => cassette.clone(),
+            None => return self.inner.request(method, url, body, headers),
+        };
+
+        match cassette.mode {
+            CassetteMode::Playback => Box::new(future::result(match cassette.play(&method, &url) {
+                Some(interaction) => Ok(Response::new()
+                    .with_status(StatusCode::from_u16(interaction.status))
+                    .with_body(interaction.response_body)),
+                None => Err(HttpError::Unknown(format!("Call {} {} is not found in cassette", method, url))),
+            })),
+            CassetteMode::Record => {
+                let mut interaction = Interaction {
+                    method: method.to_string(),
+                    url: url.clone(),
+                    request_body: body.clone(),
+                    status: 0,
+                    response_body: String::new(),
+                };
+                Box::new(self.inner.request(method, url, body, headers).and_then(move |response| {
+                    let status = response.status();
+                    let mut headers = response.headers().clone();
+                    // body is sent with its length instead
+                    headers.remove::<TransferEncoding>();
+                    response.body().concat2().map_err(HttpError::Network).map(move |bytes| {
+                        interaction.status = status.as_u16();
+                        interaction.response_body = String::from_utf8_lossy(&bytes).into_owned();
+                        if let Err(e) = cassette.record(interaction) {
+                            error!("Could not record call to cassette: {}", e);
+                        }
+                        Response::new().with_status(status).with_headers(headers).with_body(bytes.to_vec())
+                    })
+                }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use hyper::Method;
+
+    use super::{Cassette, Interaction};
+    use config::CassetteMode;
+    use json_file::JsonFile;
+
+    fn interaction(url: &str, response_body: &str) -> Interaction {
+        Interaction {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            request_body: None,
+            status: 200,
+            response_body: response_body.to_string(),
+        }
+    }
+
+    #[test]
+    fn plays_back_repeated_calls_in_order() {
+        let cassette = Cassette::with_interactions(
+            CassetteMode::Playback,
+            JsonFile::new(PathBuf::from("cassette.json"), "cassette"),
+            vec![
+                interaction("http://stores/stores/1", "{\"status\":\"draft\"}"),
+                interaction("http://users/users/1", "{}"),
+                interaction("http://stores/stores/1", "{\"status\":\"moderation\"}"),
+            ],
+        );
+
+        let first = cassette.play(&Method::Get, "http://stores/stores/1").unwrap();
+        let second = cassette.play(&Method::Get, "http://stores/stores/1").unwrap();
+        assert_eq!(first.response_body, "{\"status\":\"draft\"}");
+        assert_eq!(second.response_body, "{\"status\":\"moderation\"}");
+        assert!(cassette.play(&Method::Get, "http://stores/stores/1").is_none());
+        assert!(cassette.play(&Method::Post, "http://users/users/1").is_none());
+    }
+}
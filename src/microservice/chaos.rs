@@ -0,0 +1,124 @@
+//! Chaos mode of calls to microservices. Calls to microservices listed in `[chaos]`
+//! are delayed and randomly answered with `503 Service Unavailable`, so that timeout
+//! budgets, retries and compensations can be checked in staging. Latency is injected
+//! below time limited clients, so it counts towards the budget of the request.
+use std::time::{Duration, Instant};
+
+use futures::future;
+use futures::prelude::*;
+use hyper::header::{ContentType, Headers};
+use hyper::server::Response;
+use hyper::{Method, StatusCode};
+use rand;
+use tokio_timer::Delay;
+
+use stq_http::client::{Error as HttpError, HttpClient};
+
+use config::{ChaosFaults, Config};
+
+/// Http client injecting faults configured for the microservice, calls are passed through if chaos mode is off
+#[derive(Clone)]
+pub struct ChaosHttpClient<C: HttpClient + Clone> {
+    inner: C,
+    /// Url prefixes of microservices along with their faults
+    services: Vec<(String, ChaosFaults)>,
+}
+
+impl<C: HttpClient + Clone> ChaosHttpClient<C> {
+    pub fn new(inner: C, config: &Config) -> Self {
+        let services = match config.chaos {
+            Some(ref chaos) => {
+                warn!("Chaos mode is on, faults are injected into calls to {:?}", chaos.services.keys());
+                service_urls(config)
+                    .into_iter()
+                    .filter_map(|(name, url)| chaos.services.get(name).map(|faults| (url, faults.clone())))
+                    .collect()
+            }
+            None => vec![],
+        };
+        Self { inner, services }
+    }
+
+    fn faults(&self, url: &str) -> Option<&ChaosFaults> {
+        self.services
+            .iter()
+            .find(|&&(ref prefix, _)| url.starts_with(prefix.as_str()))
+            .map(|&(_, ref faults)| faults)
+    }
+}
+
+impl<C: HttpClient + Clone> HttpClient for ChaosHttpClient<C> {
+    fn request(
+        &self,
+        method: Method,
+        url: String,
+        body: Option<String>,
+        headers: Option<Headers>,
+    ) -> Box<Future<Item = Response, Error = HttpError> + Send> {
+        let faults = match self.faults(&url) {
+            Some(faults) => faults.clone(),
+            None => return self.inner.request(method, url, body, headers),
+        };
+
+        let delay = injected_latency(&faults, rand::random::<f64>());
+        let fails = rand::random::<f64>() < faults.error_rate;
+        let inner = self.inner.clone();
+        Box::new(Delay::new(Instant::now() + delay).then(move |_| {
+            if fails {
+                debug!("Chaos mode fails call {} {}", method, url);
+                Box::new(future::ok(
+                    Response::new()
+                        .with_status(StatusCode::ServiceUnavailable)
+                        .with_header(ContentType::json())
+                        .with_body(json!({"code": 503, "description": "Failure injected by chaos mode"}).to_string()),
+                )) as Box<Future<Item = Response, Error = HttpError> + Send>
+            } else {
+                inner.request(method, url, body, headers)
+            }
+        }))
+    }
+}
+
+/// Urls of microservices by their names
+pub fn service_urls(config: &Config) -> Vec<(&'static str, String)> {
+    let mut urls = vec![
+        ("users", config.users_microservice.url.clone()),
+        ("stores", config.stores_microservice.url.clone()),
+        ("orders", config.orders_microservice.url.clone()),
+        ("billing", config.billing_microservice.url.clone()),
+        ("warehouses", config.warehouses_microservice.url.clone()),
+        ("notifications", config.notifications_microservice.url.clone()),
+        ("delivery", config.delivery_microservice.url.clone()),
+    ];
+    if let Some(ref fraud_screening) = config.fraud_screening {
+        urls.push(("fraud_scoring", fraud_screening.url.clone()));
+    }
+    if let Some(ref files) = config.files {
+        urls.push(("files", files.url.clone()));
+    }
+    urls
+}
+
+/// `latency_ms` along with share of `jitter_ms` given by `random` from 0 to 1
+fn injected_latency(faults: &ChaosFaults, random: f64) -> Duration {
+    Duration::from_millis(faults.latency_ms + (faults.jitter_ms as f64 * random) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::injected_latency;
+    use config::ChaosFaults;
+
+    #[test]
+    fn adds_jitter_to_latency() {
+        let faults = ChaosFaults {
+            latency_ms: 200,
+            jitter_ms: 100,
+            error_rate: 0.0,
+        };
+        assert_eq!(injected_latency(&faults, 0.0), Duration::from_millis(200));
+        assert_eq!(injected_latency(&faults, 0.5), Duration::from_millis(250));
+    }
+}
@@ -2,26 +2,45 @@ use failure::Fail;
 use futures::Future;
 use hyper::Method;
 
+use stq_api::orders::AddressFull;
 use stq_http::client::HttpClient;
 use stq_routes::model::Model as StqModel;
 use stq_routes::service::Service as StqService;
 use stq_types::*;
 
-use super::{ApiFuture, Initiator};
+use super::{ApiFuture, DependencyMonitor, Initiator, Requester};
 
+use audit::AuditScope;
 use config;
 use errors::Error;
 use models::*;
+use services::parse_validation_errors;
 
 pub trait DeliveryMicroservice {
     fn delete_shipping_by_base_product(&self, initiator: Option<Initiator>, base_product_id: BaseProductId) -> ApiFuture<()>;
+    /// Restores shipping of base product deleted along with its deactivation
+    fn restore_shipping_by_base_product(&self, initiator: Option<Initiator>, base_product_id: BaseProductId) -> ApiFuture<()>;
     fn delete_delivery_role(&self, initiator: Option<Initiator>, role_id: RoleId) -> ApiFuture<NewRole<DeliveryRole>>;
     fn create_delivery_role(&self, initiator: Option<Initiator>, payload: NewRole<DeliveryRole>) -> ApiFuture<NewRole<DeliveryRole>>;
     fn upsert_shipping(&self, initiator: Option<Initiator>, base_product_id: BaseProductId, payload: NewShipping) -> ApiFuture<Shipping>;
+    /// Keeps only packages of base product applicable to the category, e.g. after the category was changed
+    fn recalculate_shipping(
+        &self,
+        initiator: Option<Initiator>,
+        base_product_id: BaseProductId,
+        payload: RecalculateShipping,
+    ) -> ApiFuture<Shipping>;
+    /// Checks address against country data of delivery and returns it in canonical form
+    fn normalize_address(&self, initiator: Option<Initiator>, payload: AddressFull) -> ApiFuture<AddressFull>;
+    /// Shipping methods and their prices of products delivered to the country
+    fn query_rates(&self, initiator: Option<Initiator>, payload: ShippingRatesQuery) -> ApiFuture<Vec<ProductShippingRates>>;
+    fn purchase_label(&self, initiator: Option<Initiator>, payload: NewShippingLabel) -> ApiFuture<ShippingLabel>;
+    /// Voids label of the order, delivery company refunds it
+    fn cancel_label(&self, initiator: Option<Initiator>, order_id: OrderId) -> ApiFuture<()>;
 }
 
 pub struct DeliveryMicroserviceImpl<T: 'static + HttpClient + Clone> {
-    http_client: T,
+    requester: Requester<T>,
     config: config::Config,
 }
 
@@ -29,65 +48,161 @@ impl<T: 'static + HttpClient + Clone> DeliveryMicroservice for DeliveryMicroserv
     fn delete_shipping_by_base_product(&self, initiator: Option<Initiator>, base_product_id: BaseProductId) -> ApiFuture<()> {
         let url = format!("{}/{}/{}", self.delivery_url(), StqModel::Product.to_url(), base_product_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deleting shipping by base product in delivery microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), _>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting shipping by base product in delivery microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn restore_shipping_by_base_product(&self, initiator: Option<Initiator>, base_product_id: BaseProductId) -> ApiFuture<()> {
+        let url = format!("{}/{}/{}/restore", self.delivery_url(), StqModel::Product.to_url(), base_product_id);
+        Box::new(
+            self.requester
+                .request::<(), _>(Method::Post, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Restoring shipping by base product in delivery microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn delete_delivery_role(&self, initiator: Option<Initiator>, role_id: RoleId) -> ApiFuture<NewRole<DeliveryRole>> {
         let url = format!("{}/roles/by-id/{}", self.delivery_url(), role_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deleting role in delivery microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), _>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting role in delivery microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn create_delivery_role(&self, initiator: Option<Initiator>, payload: NewRole<DeliveryRole>) -> ApiFuture<NewRole<DeliveryRole>> {
         let url = format!("{}/{}", self.delivery_url(), StqModel::Role.to_url());
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Creating role in delivery microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating role in delivery microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn upsert_shipping(&self, initiator: Option<Initiator>, base_product_id: BaseProductId, payload: NewShipping) -> ApiFuture<Shipping> {
         let url = format!("{}/products/{}", self.delivery_url(), base_product_id);
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Set shipping in delivery microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Set shipping in delivery microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn recalculate_shipping(
+        &self,
+        initiator: Option<Initiator>,
+        base_product_id: BaseProductId,
+        payload: RecalculateShipping,
+    ) -> ApiFuture<Shipping> {
+        let url = format!(
+            "{}/{}/{}/recalculate",
+            self.delivery_url(),
+            StqModel::Product.to_url(),
+            base_product_id
+        );
+        Box::new(
+            self.requester
+                .request::<RecalculateShipping, Shipping>(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Recalculating shipping of base product in delivery microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn normalize_address(&self, initiator: Option<Initiator>, payload: AddressFull) -> ApiFuture<AddressFull> {
+        let url = format!("{}/addresses/normalize", self.delivery_url());
+        Box::new(
+            self.requester
+                .request::<AddressFull, AddressFull>(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    parse_validation_errors(e.into(), ADDRESS_FIELDS)
+                        .context("Normalizing address in delivery microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn query_rates(&self, initiator: Option<Initiator>, payload: ShippingRatesQuery) -> ApiFuture<Vec<ProductShippingRates>> {
+        let url = format!("{}/rates/query", self.delivery_url());
+        Box::new(
+            self.requester
+                .request::<ShippingRatesQuery, Vec<ProductShippingRates>>(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Querying shipping rates in delivery microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn purchase_label(&self, initiator: Option<Initiator>, payload: NewShippingLabel) -> ApiFuture<ShippingLabel> {
+        let url = format!("{}/labels", self.delivery_url());
+        Box::new(
+            self.requester
+                .request::<NewShippingLabel, ShippingLabel>(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Purchasing shipping label in delivery microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn cancel_label(&self, initiator: Option<Initiator>, order_id: OrderId) -> ApiFuture<()> {
+        let url = format!("{}/labels/by-order-id/{}", self.delivery_url(), order_id);
+        Box::new(
+            self.requester
+                .request::<(), ()>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Cancelling shipping label in delivery microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 }
 
 impl<T: 'static + HttpClient + Clone> DeliveryMicroserviceImpl<T> {
     pub fn new(http_client: T, config: config::Config) -> Self {
-        Self { http_client, config }
+        Self {
+            requester: Requester::new(http_client, &config),
+            config,
+        }
+    }
+
+    pub fn with_audit(mut self, audit: AuditScope) -> Self {
+        self.requester.audit = Some(audit);
+        self
+    }
+
+    pub fn with_monitor(mut self, monitor: DependencyMonitor) -> Self {
+        self.requester.monitor = Some(monitor.scope("delivery"));
+        self
     }
 
     fn delivery_url(&self) -> String {
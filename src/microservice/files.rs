@@ -0,0 +1,85 @@
+use failure::Fail;
+use futures::Future;
+use hyper::Method;
+
+use stq_http::client::HttpClient;
+
+use super::{retry_lookup, ApiFuture, DependencyMonitor, Initiator, Requester};
+
+use audit::AuditScope;
+use config;
+use errors::Error;
+use models::*;
+
+/// Client of static files service keeping uploads of stores and products
+pub trait FilesMicroservice {
+    /// Schedules deletion of files, scheduling the same files again is a no-op
+    fn schedule_cleanup(&self, initiator: Initiator, payload: FilesCleanup) -> ApiFuture<()>;
+    /// Cancels scheduled deletion of files, files without scheduled deletion are skipped
+    fn cancel_cleanup(&self, initiator: Initiator, payload: FilesCleanupCancellation) -> ApiFuture<()>;
+}
+
+pub struct FilesMicroserviceImpl<T: 'static + HttpClient + Clone> {
+    requester: Requester<T>,
+    url: String,
+    /// Requests are safe to repeat, so they are retried like lookups
+    retries: config::LookupRetries,
+}
+
+impl<T: 'static + HttpClient + Clone> FilesMicroservice for FilesMicroserviceImpl<T> {
+    fn schedule_cleanup(&self, initiator: Initiator, payload: FilesCleanup) -> ApiFuture<()> {
+        let url = format!("{}/cleanups", self.url);
+        let requester = self.requester.clone();
+        Box::new(
+            retry_lookup(&self.retries, move || {
+                Box::new(requester.request::<FilesCleanup, ()>(Method::Post, url.clone(), Some(payload.clone()), Some(initiator.into())))
+                    as ApiFuture<_>
+            })
+            .map_err(|e| {
+                e.context("Scheduling cleanup of files in files microservice failed.")
+                    .context(Error::HttpClient)
+                    .into()
+            }),
+        )
+    }
+
+    fn cancel_cleanup(&self, initiator: Initiator, payload: FilesCleanupCancellation) -> ApiFuture<()> {
+        let url = format!("{}/cleanups/cancel", self.url);
+        let requester = self.requester.clone();
+        Box::new(
+            retry_lookup(&self.retries, move || {
+                Box::new(requester.request::<FilesCleanupCancellation, ()>(
+                    Method::Post,
+                    url.clone(),
+                    Some(payload.clone()),
+                    Some(initiator.into()),
+                )) as ApiFuture<_>
+            })
+            .map_err(|e| {
+                e.context("Cancelling cleanup of files in files microservice failed.")
+                    .context(Error::HttpClient)
+                    .into()
+            }),
+        )
+    }
+}
+
+impl<T: 'static + HttpClient + Clone> FilesMicroserviceImpl<T> {
+    pub fn new(http_client: T, config: &config::Config, url: String) -> Self {
+        Self {
+            requester: Requester::new(http_client, config),
+            url,
+            retries: config.saga.lookup_retries.clone(),
+        }
+    }
+
+    pub fn with_audit(mut self, audit: AuditScope) -> Self {
+        self.requester.audit = Some(audit);
+        self
+    }
+
+    pub fn with_monitor(mut self, monitor: DependencyMonitor) -> Self {
+        self.requester.monitor = Some(monitor.scope("files"));
+        self
+    }
+}
@@ -0,0 +1,50 @@
+use failure::Fail;
+use futures::Future;
+use hyper::Method;
+
+use stq_http::client::HttpClient;
+
+use super::{ApiFuture, DependencyMonitor, Requester};
+
+use config;
+use errors::Error;
+use models::*;
+
+/// Client of external fraud scoring service, it is not a part of the cluster
+pub trait FraudScoringMicroservice {
+    fn score(&self, payload: FraudScoreRequest) -> ApiFuture<FraudScore>;
+}
+
+pub struct FraudScoringMicroserviceImpl<T: 'static + HttpClient + Clone> {
+    requester: Requester<T>,
+    url: String,
+}
+
+impl<T: 'static + HttpClient + Clone> FraudScoringMicroservice for FraudScoringMicroserviceImpl<T> {
+    fn score(&self, payload: FraudScoreRequest) -> ApiFuture<FraudScore> {
+        let url = format!("{}/score", self.url);
+        Box::new(
+            self.requester
+                .request::<FraudScoreRequest, FraudScore>(Method::Post, url, Some(payload), None)
+                .map_err(|e| {
+                    e.context("Scoring checkout in fraud scoring service failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+}
+
+impl<T: 'static + HttpClient + Clone> FraudScoringMicroserviceImpl<T> {
+    pub fn new(http_client: T, config: &config::Config, url: String) -> Self {
+        Self {
+            requester: Requester::new(http_client, config),
+            url,
+        }
+    }
+
+    pub fn with_monitor(mut self, monitor: DependencyMonitor) -> Self {
+        self.requester.monitor = Some(monitor.scope("fraud_scoring"));
+        self
+    }
+}
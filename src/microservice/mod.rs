@@ -1,14 +1,23 @@
+use std::time::Instant;
+
 use failure::Error;
+use futures::future::Either;
 use futures::{Future, IntoFuture};
-use hyper::header::{Authorization, Headers};
+use hyper::header::{Authorization, Headers, UserAgent};
 use hyper::Method;
 use serde::de::Deserialize;
 use serde::ser::Serialize;
-use serde_json;
+use serde_json::{self, Value};
 
-use stq_http::client::HttpClient;
+use stq_http::client::{ClientHandle, HttpClient};
 use stq_types::*;
 
+use audit::AuditScope;
+use build_info;
+use config::Config;
+use models::RecordedCall;
+use scrubbing::mask_credentials;
+
 mod orders;
 pub use self::orders::*;
 
@@ -30,32 +39,192 @@ pub use self::warehouses::*;
 mod delivery;
 pub use self::delivery::*;
 
+mod fraud;
+pub use self::fraud::*;
+
+mod files;
+pub use self::files::*;
+
+mod breaker;
+pub use self::breaker::*;
+
+mod monitor;
+pub use self::monitor::*;
+
+mod cassette;
+pub use self::cassette::*;
+
+mod chaos;
+pub use self::chaos::*;
+
+mod shadow;
+pub use self::shadow::*;
+
+mod budget;
+pub use self::budget::*;
+
+mod retry;
+pub use self::retry::*;
+
+/// `Authorization` header of requests made with superadmin rights
+const SUPERADMIN_AUTHORIZATION: &str = "1";
+/// Header identifying the coordinator to microservices along with `User-Agent`
+const CALLING_SERVICE_HEADER: &str = "X-Calling-Service";
+/// Header microservices deduplicate repeated requests by
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const SERVICE_NAME: &str = "saga-coordinator";
+
 pub type ApiFuture<T> = Box<Future<Item = T, Error = Error>>;
 
+/// Client every microservice client is built on, injects faults in chaos mode and records calls to cassette
+pub type BaseHttpClient = ShadowHttpClient<ChaosHttpClient<CassetteHttpClient<ClientHandle>>>;
+
 #[derive(Clone, Copy, Debug)]
 pub enum Initiator {
     Superadmin,
     User(UserId),
 }
 
-fn request<C: HttpClient + 'static, T: Serialize, S: for<'a> Deserialize<'a> + 'static + Send>(
+/// Sends requests of a microservice client, payloads exceeding `limits.downstream_payload_bytes` are not sent.
+/// Requests are identified with `User-Agent` and `X-Calling-Service` of the coordinator.
+/// Requests made with superadmin rights are recorded to `audit`, outcome and latency of every request to `monitor`.
+/// If `audit` records calls of the saga, every request is saved along with its response for replay.
+#[derive(Clone)]
+struct Requester<C: HttpClient + Clone> {
     http_client: C,
-    method: Method,
-    url: String,
-    payload: Option<T>,
-    headers: Option<Headers>,
-) -> impl Future<Item = S, Error = Error> {
-    let body = if let Some(payload) = payload {
-        serde_json::to_string::<T>(&payload).map(Some)
-    } else {
-        Ok(None)
+    user_agent: String,
+    payload_limit: usize,
+    /// Audits calls made with superadmin rights, set with `with_audit` of the client
+    audit: Option<AuditScope>,
+    /// Samples outcome and latency of calls, set with `with_monitor` of the client
+    monitor: Option<MonitorScope>,
+}
+
+impl<C: HttpClient + Clone> Requester<C> {
+    fn new(http_client: C, config: &Config) -> Self {
+        Self {
+            http_client,
+            user_agent: user_agent(config),
+            payload_limit: config.limits.downstream_payload_bytes,
+            audit: None,
+            monitor: None,
+        }
+    }
+}
+
+impl<C: HttpClient + Clone + 'static> Requester<C> {
+    fn request<T: Serialize, S: for<'a> Deserialize<'a> + 'static + Send>(
+        &self,
+        method: Method,
+        url: String,
+        payload: Option<T>,
+        headers: Option<Headers>,
+    ) -> impl Future<Item = S, Error = Error> {
+        let body = serialize_payload(&method, &url, payload, self.payload_limit);
+
+        let http_client = self.http_client.clone();
+        let audit = self.audit.clone();
+        let is_superadmin = headers.as_ref().map(is_superadmin).unwrap_or(false);
+        let monitor = self.monitor.clone();
+
+        let mut headers = headers.unwrap_or_else(Headers::new);
+        headers.set_raw(CALLING_SERVICE_HEADER, self.user_agent.clone());
+        headers.set(UserAgent::new(self.user_agent.clone()));
+
+        body.into_future().and_then(move |serialized_body| {
+            if is_superadmin {
+                if let Some(ref audit) = audit {
+                    audit.record(&method, &url);
+                }
+            }
+            let started_at = Instant::now();
+            let record_outcome = move |is_ok: bool| {
+                if let Some(monitor) = monitor {
+                    monitor.record(is_ok, started_at.elapsed());
+                }
+            };
+
+            match audit.filter(AuditScope::records_calls) {
+                // response is recorded as is, before it is deserialized
+                Some(audit) => {
+                    let mut call = RecordedCall {
+                        method: method.to_string(),
+                        url: url.clone(),
+                        payload: serialized_body.as_ref().map(|body| mask_credentials(body)),
+                        response: None,
+                        error: None,
+                    };
+                    Either::A(
+                        http_client
+                            .request_json::<Value>(method, url, serialized_body, Some(headers))
+                            .then(move |res| {
+                                record_outcome(res.is_ok());
+                                let res = res.map_err(Error::from);
+                                match res {
+                                    Ok(ref response) => call.response = Some(response.clone()),
+                                    Err(ref e) => call.error = Some(e.to_string()),
+                                }
+                                audit.record_call(call);
+                                res.and_then(|response| serde_json::from_value::<S>(response).map_err(Error::from))
+                            }),
+                    )
+                }
+                None => Either::B(
+                    http_client
+                        .request_json::<S>(method, url, serialized_body, Some(headers))
+                        .then(move |res| {
+                            record_outcome(res.is_ok());
+                            res.map_err(Error::from)
+                        }),
+                ),
+            }
+        })
+    }
+}
+
+/// Serialized payload of request, payloads of more than `limit` bytes are rejected
+fn serialize_payload<T: Serialize>(method: &Method, url: &str, payload: Option<T>, limit: usize) -> Result<Option<String>, Error> {
+    let payload = match payload {
+        Some(payload) => payload,
+        None => return Ok(None),
     };
+    let body = serde_json::to_string::<T>(&payload)?;
+    if body.len() > limit {
+        return Err(format_err!(
+            "Payload of {} {} is {} bytes, which exceeds limit of {} bytes",
+            method,
+            url,
+            body.len(),
+            limit
+        ));
+    }
+    Ok(Some(body))
+}
 
-    body.into_future().map_err(Error::from).and_then(move |serialized_body| {
-        http_client
-            .request_json::<S>(method, url, serialized_body, headers)
-            .map_err(Error::from)
-    })
+/// Identification of the coordinator sent to microservices, e.g. `saga-coordinator/0.1.0 (build 1a2b3c4)`
+fn user_agent(config: &Config) -> String {
+    if let Some(ref user_agent) = config.client.user_agent {
+        return user_agent.clone();
+    }
+    match build_info::GIT_COMMIT {
+        Some(build) => format!("{}/{} (build {})", SERVICE_NAME, build_info::VERSION, build),
+        None => format!("{}/{}", SERVICE_NAME, build_info::VERSION),
+    }
+}
+
+/// Headers of request made by `initiator`, which microservice deduplicates by `key`, so that the request is safe to retry
+fn idempotent_headers(initiator: Initiator, key: String) -> Headers {
+    let mut headers: Headers = initiator.into();
+    headers.set_raw(IDEMPOTENCY_KEY_HEADER, key);
+    headers
+}
+
+/// Whether request with the headers is made with superadmin rights
+pub fn is_superadmin(headers: &Headers) -> bool {
+    headers
+        .get::<Authorization<String>>()
+        .map(|authorization| authorization.0 == SUPERADMIN_AUTHORIZATION)
+        .unwrap_or(false)
 }
 
 impl From<UserId> for Initiator {
@@ -68,9 +237,27 @@ impl Into<Headers> for Initiator {
     fn into(self) -> Headers {
         let mut headers = Headers::new();
         match self {
-            Initiator::Superadmin => headers.set(Authorization("1".to_string())),
+            Initiator::Superadmin => headers.set(Authorization(SUPERADMIN_AUTHORIZATION.to_string())),
             Initiator::User(id) => headers.set(Authorization(id.to_string())),
         }
         headers
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+
+    use super::serialize_payload;
+
+    #[test]
+    fn rejects_payload_exceeding_limit() {
+        let url = "http://stores/products";
+        assert_eq!(serialize_payload::<()>(&Method::Post, url, None, 4).unwrap(), None);
+        assert_eq!(
+            serialize_payload(&Method::Post, url, Some(vec![1, 2]), 5).unwrap(),
+            Some("[1,2]".to_string())
+        );
+        assert!(serialize_payload(&Method::Post, url, Some(vec![1, 2, 3]), 5).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! Health of downstream microservices as seen by the coordinator. Every call made
+//! through `request` is sampled with its outcome and latency, samples older than
+//! the window are dropped, so error rate and latency describe recent calls only.
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::CircuitBreakers;
+use config;
+use metrics::duration_ms;
+use models::{Dependency, DependencyReport};
+
+/// Microservices reported even before the first call to them
+const SERVICES: &[&str] = &[
+    "users",
+    "stores",
+    "orders",
+    "billing",
+    "warehouses",
+    "notifications",
+    "delivery",
+    "fraud_scoring",
+    "files",
+];
+
+#[derive(Clone, Copy, Debug)]
+struct Sample {
+    at: Instant,
+    success: bool,
+    latency: Duration,
+}
+
+#[derive(Clone, Debug, Default)]
+struct ServiceCalls {
+    samples: VecDeque<Sample>,
+    last_success_at: Option<SystemTime>,
+}
+
+/// Calls to microservices of all requests and jobs
+#[derive(Clone)]
+pub struct DependencyMonitor {
+    config: config::DependencyMonitor,
+    services: Arc<Mutex<HashMap<String, ServiceCalls>>>,
+}
+
+impl DependencyMonitor {
+    pub fn new(config: config::DependencyMonitor) -> Self {
+        Self {
+            config,
+            services: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Monitor of calls to a single microservice
+    pub fn scope(&self, service: &str) -> MonitorScope {
+        MonitorScope {
+            monitor: self.clone(),
+            service: service.to_string(),
+        }
+    }
+
+    pub fn record(&self, service: &str, success: bool, latency: Duration) {
+        let mut services = self.services.lock().unwrap();
+        let calls = services.entry(service.to_string()).or_insert_with(ServiceCalls::default);
+        if success {
+            calls.last_success_at = Some(SystemTime::now());
+        }
+        calls.samples.push_back(Sample {
+            at: Instant::now(),
+            success,
+            latency,
+        });
+        while calls.samples.len() > self.config.max_samples {
+            calls.samples.pop_front();
+        }
+    }
+
+    pub fn report(&self, breakers: &CircuitBreakers) -> Vec<DependencyReport> {
+        let mut services = self.services.lock().unwrap();
+        let mut names = SERVICES.iter().map(|service| service.to_string()).collect::<Vec<_>>();
+        for service in services.keys() {
+            if !names.contains(service) {
+                names.push(service.clone());
+            }
+        }
+
+        let window = Duration::from_secs(self.config.window_s);
+        let now = Instant::now();
+        names
+            .into_iter()
+            .map(|service| {
+                let calls = services.entry(service.clone()).or_insert_with(ServiceCalls::default);
+                prune(&mut calls.samples, window, now);
+                let breaker = Dependency::all()
+                    .iter()
+                    .find(|dependency| dependency.to_string() == service)
+                    .map(|dependency| breakers.state(*dependency));
+                DependencyReport {
+                    service,
+                    breaker,
+                    calls: calls.samples.len(),
+                    error_rate: error_rate(&calls.samples),
+                    p95_latency_ms: percentile_latency(&calls.samples, 95).map(duration_ms),
+                    last_success_at: calls.last_success_at,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Records calls made by microservice client
+#[derive(Clone)]
+pub struct MonitorScope {
+    monitor: DependencyMonitor,
+    service: String,
+}
+
+impl MonitorScope {
+    pub fn record(&self, success: bool, latency: Duration) {
+        self.monitor.record(&self.service, success, latency);
+    }
+}
+
+fn prune(samples: &mut VecDeque<Sample>, window: Duration, now: Instant) {
+    while samples
+        .front()
+        .map(|sample| now.duration_since(sample.at) > window)
+        .unwrap_or(false)
+    {
+        samples.pop_front();
+    }
+}
+
+fn error_rate(samples: &VecDeque<Sample>) -> f64 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let failures = samples.iter().filter(|sample| !sample.success).count();
+    failures as f64 / samples.len() as f64
+}
+
+/// Latency the given percent of calls fit in, nearest rank method
+fn percentile_latency(samples: &VecDeque<Sample>, percent: usize) -> Option<Duration> {
+    let mut latencies = samples.iter().map(|sample| sample.latency).collect::<Vec<_>>();
+    if latencies.is_empty() {
+        return None;
+    }
+    latencies.sort();
+    let rank = (latencies.len() * percent + 99) / 100;
+    latencies.get(rank.max(1) - 1).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::time::{Duration, Instant};
+
+    use super::{error_rate, percentile_latency, prune, Sample};
+
+    #[test]
+    fn reports_recent_calls_only() {
+        let now = Instant::now();
+        let mut samples = (1..=20)
+            .map(|i| Sample {
+                at: now - Duration::from_secs(30 - i),
+                success: i % 4 != 0,
+                latency: Duration::from_millis(i * 10),
+            })
+            .collect::<VecDeque<_>>();
+
+        prune(&mut samples, Duration::from_secs(20), now);
+        assert_eq!(samples.len(), 11);
+        assert_eq!(error_rate(&samples), 3.0 / 11.0);
+        assert_eq!(percentile_latency(&samples, 95), Some(Duration::from_millis(200)));
+        assert_eq!(percentile_latency(&samples, 50), Some(Duration::from_millis(150)));
+        assert_eq!(percentile_latency(&VecDeque::new(), 95), None);
+    }
+}
@@ -1,3 +1,4 @@
+use failure::Error as FailureError;
 use failure::Fail;
 use futures::Future;
 use hyper::Method;
@@ -11,10 +12,16 @@ use stq_static_resources::{
     PasswordResetForUser, Project, StoreModerationStatusForModerator, StoreModerationStatusForUser,
 };
 
-use super::{ApiFuture, Initiator};
+use super::{ApiFuture, CircuitBreakers, DependencyMonitor, Initiator, Requester};
+use audit::AuditScope;
 use config;
 use errors::Error;
-use models::{CreateEmarsysContactPayload, CreatedEmarsysContact};
+use models::{
+    CartProductsRepricedForUser, CartProductsUnavailableForUser, CreateEmarsysContactPayload, CreatedEmarsysContact, Dependency,
+    LowStockForStore, OrderAcknowledgmentOverdueForModerator, OrderAcknowledgmentOverdueForStore, OrderCreateWithReceiptForUser,
+    OrderCreateWithTaxesForUser, OrderReturnForStore, OrderReturnForUser, PayoutInitiatedForStore, ShippingLabelForStore,
+    StoreDeactivatedForUser, StoreVerifiedForUser,
+};
 
 pub trait NotificationsMicroservice {
     fn apply_email_verification(
@@ -22,15 +29,51 @@ pub trait NotificationsMicroservice {
         initiator: Option<Initiator>,
         payload: ApplyEmailVerificationForUser,
         project: Project,
+        locale: Option<String>,
+    ) -> ApiFuture<()>;
+    fn apply_password_reset(
+        &self,
+        initiator: Option<Initiator>,
+        payload: ApplyPasswordResetForUser,
+        project: Project,
+        locale: Option<String>,
+    ) -> ApiFuture<()>;
+    fn password_reset(
+        &self,
+        initiator: Option<Initiator>,
+        payload: PasswordResetForUser,
+        project: Project,
+        locale: Option<String>,
+    ) -> ApiFuture<()>;
+    fn email_verification(
+        &self,
+        initiator: Option<Initiator>,
+        payload: EmailVerificationForUser,
+        project: Project,
+        locale: Option<String>,
     ) -> ApiFuture<()>;
-    fn apply_password_reset(&self, initiator: Option<Initiator>, payload: ApplyPasswordResetForUser, project: Project) -> ApiFuture<()>;
-    fn password_reset(&self, initiator: Option<Initiator>, payload: PasswordResetForUser, project: Project) -> ApiFuture<()>;
-    fn email_verification(&self, initiator: Option<Initiator>, payload: EmailVerificationForUser, project: Project) -> ApiFuture<()>;
     fn order_create_for_user(&self, initiator: Initiator, payload: OrderCreateForUser) -> ApiFuture<()>;
+    fn order_create_with_taxes_for_user(&self, initiator: Initiator, payload: OrderCreateWithTaxesForUser) -> ApiFuture<()>;
+    fn order_create_with_receipt_for_user(&self, initiator: Initiator, payload: OrderCreateWithReceiptForUser) -> ApiFuture<()>;
     fn order_create_for_store(&self, initiator: Initiator, payload: OrderCreateForStore) -> ApiFuture<()>;
     fn order_update_state_for_user(&self, initiator: Initiator, payload: OrderUpdateStateForUser) -> ApiFuture<()>;
     fn order_update_state_for_store(&self, initiator: Initiator, payload: OrderUpdateStateForStore) -> ApiFuture<()>;
     fn store_moderation_status_for_user(&self, initiator: Initiator, payload: StoreModerationStatusForUser) -> ApiFuture<()>;
+    fn store_verified_for_user(&self, initiator: Initiator, payload: StoreVerifiedForUser) -> ApiFuture<()>;
+    fn store_deactivated_for_user(&self, initiator: Initiator, payload: StoreDeactivatedForUser) -> ApiFuture<()>;
+    fn payout_initiated_for_store(&self, initiator: Initiator, payload: PayoutInitiatedForStore) -> ApiFuture<()>;
+    fn shipping_label_for_store(&self, initiator: Initiator, payload: ShippingLabelForStore) -> ApiFuture<()>;
+    fn low_stock_for_store(&self, initiator: Initiator, payload: LowStockForStore) -> ApiFuture<()>;
+    fn order_acknowledgment_overdue_for_store(&self, initiator: Initiator, payload: OrderAcknowledgmentOverdueForStore) -> ApiFuture<()>;
+    fn order_acknowledgment_overdue_for_moderator(
+        &self,
+        initiator: Initiator,
+        payload: OrderAcknowledgmentOverdueForModerator,
+    ) -> ApiFuture<()>;
+    fn order_return_for_user(&self, initiator: Initiator, payload: OrderReturnForUser) -> ApiFuture<()>;
+    fn order_return_for_store(&self, initiator: Initiator, payload: OrderReturnForStore) -> ApiFuture<()>;
+    fn cart_products_repriced_for_user(&self, initiator: Initiator, payload: CartProductsRepricedForUser) -> ApiFuture<()>;
+    fn cart_products_unavailable_for_user(&self, initiator: Initiator, payload: CartProductsUnavailableForUser) -> ApiFuture<()>;
     fn base_product_moderation_status_for_user(&self, initiator: Initiator, payload: BaseProductModerationStatusForUser) -> ApiFuture<()>;
     fn store_moderation_status_for_moderator(&self, initiator: Initiator, payload: StoreModerationStatusForModerator) -> ApiFuture<()>;
     fn base_product_moderation_status_for_moderator(
@@ -42,8 +85,9 @@ pub trait NotificationsMicroservice {
 }
 
 pub struct NotificationsMicroserviceImpl<T: 'static + HttpClient + Clone> {
-    http_client: T,
+    requester: Requester<T>,
     config: config::Config,
+    breakers: Option<CircuitBreakers>,
 }
 
 impl<T: 'static + HttpClient + Clone> NotificationsMicroservice for NotificationsMicroserviceImpl<T> {
@@ -52,144 +96,141 @@ impl<T: 'static + HttpClient + Clone> NotificationsMicroservice for Notification
         initiator: Option<Initiator>,
         payload: ApplyEmailVerificationForUser,
         project: Project,
+        locale: Option<String>,
     ) -> ApiFuture<()> {
         let url = format!(
-            "{}/{}/apply-email-verification?project={}",
+            "{}/{}/apply-email-verification?project={}{}",
             self.notifications_url(),
             StqModel::User.to_url(),
-            project
+            project,
+            locale_query(locale)
         );
-        Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| e.context("Sending notification failed.").context(Error::HttpClient).into()),
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| e.context("Sending notification failed.").context(Error::HttpClient).into()),
         )
     }
 
-    fn apply_password_reset(&self, initiator: Option<Initiator>, payload: ApplyPasswordResetForUser, project: Project) -> ApiFuture<()> {
+    fn apply_password_reset(
+        &self,
+        initiator: Option<Initiator>,
+        payload: ApplyPasswordResetForUser,
+        project: Project,
+        locale: Option<String>,
+    ) -> ApiFuture<()> {
         let url = format!(
-            "{}/{}/apply-password-reset?project={}",
+            "{}/{}/apply-password-reset?project={}{}",
             self.notifications_url(),
             StqModel::User.to_url(),
-            project
+            project,
+            locale_query(locale)
         );
-        Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| e.context("Sending notification failed.").context(Error::HttpClient).into()),
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| e.context("Sending notification failed.").context(Error::HttpClient).into()),
         )
     }
 
-    fn password_reset(&self, initiator: Option<Initiator>, payload: PasswordResetForUser, project: Project) -> ApiFuture<()> {
+    fn password_reset(
+        &self,
+        initiator: Option<Initiator>,
+        payload: PasswordResetForUser,
+        project: Project,
+        locale: Option<String>,
+    ) -> ApiFuture<()> {
         let url = format!(
-            "{}/{}/password-reset?project={}",
+            "{}/{}/password-reset?project={}{}",
             self.notifications_url(),
             StqModel::User.to_url(),
-            project
+            project,
+            locale_query(locale)
         );
-        Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| e.context("Sending notification failed.").context(Error::HttpClient).into()),
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| e.context("Sending notification failed.").context(Error::HttpClient).into()),
         )
     }
 
-    fn email_verification(&self, initiator: Option<Initiator>, payload: EmailVerificationForUser, project: Project) -> ApiFuture<()> {
+    fn email_verification(
+        &self,
+        initiator: Option<Initiator>,
+        payload: EmailVerificationForUser,
+        project: Project,
+        locale: Option<String>,
+    ) -> ApiFuture<()> {
         let url = format!(
-            "{}/{}/email-verification?project={}",
+            "{}/{}/email-verification?project={}{}",
             self.notifications_url(),
             StqModel::User.to_url(),
-            project
+            project,
+            locale_query(locale)
         );
-        Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Sending email to notifications microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Sending email to notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn order_update_state_for_store(&self, initiator: Initiator, payload: OrderUpdateStateForStore) -> ApiFuture<()> {
         let url = format!("{}/stores/order-update-state", self.notifications_url());
-        Box::new(
-            super::request::<_, OrderUpdateStateForStore, ()>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                Some(initiator.into()),
-            )
-            .map_err(|e| {
-                e.context("Sending order update for store in notifications microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<OrderUpdateStateForStore, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending order update for store in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn order_update_state_for_user(&self, initiator: Initiator, payload: OrderUpdateStateForUser) -> ApiFuture<()> {
         let url = format!("{}/users/order-update-state", self.notifications_url());
-        Box::new(
-            super::request::<_, OrderUpdateStateForUser, ()>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                Some(initiator.into()),
-            )
-            .map_err(|e| {
-                e.context("Sending order update for user in notifications microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<OrderUpdateStateForUser, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending order update for user in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn order_create_for_store(&self, initiator: Initiator, payload: OrderCreateForStore) -> ApiFuture<()> {
         let url = format!("{}/stores/order-create", self.notifications_url());
-        Box::new(
-            super::request::<_, OrderCreateForStore, ()>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                Some(initiator.into()),
-            )
-            .map_err(|e| {
-                e.context("Sending order create for store in notifications microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<OrderCreateForStore, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending order create for store in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn order_create_for_user(&self, initiator: Initiator, payload: OrderCreateForUser) -> ApiFuture<()> {
         let url = format!("{}/users/order-create", self.notifications_url());
-        Box::new(
-            super::request::<_, OrderCreateForUser, ()>(self.http_client.clone(), Method::Post, url, Some(payload), Some(initiator.into()))
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<OrderCreateForUser, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
                 .map_err(|e| {
                     e.context("Sending order create for user in notifications microservice failed.")
                         .context(Error::HttpClient)
@@ -198,57 +239,231 @@ impl<T: 'static + HttpClient + Clone> NotificationsMicroservice for Notification
         )
     }
 
+    fn order_create_with_taxes_for_user(&self, initiator: Initiator, payload: OrderCreateWithTaxesForUser) -> ApiFuture<()> {
+        let url = format!("{}/users/order-create", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<OrderCreateWithTaxesForUser, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending order create with taxes for user in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn order_create_with_receipt_for_user(&self, initiator: Initiator, payload: OrderCreateWithReceiptForUser) -> ApiFuture<()> {
+        let url = format!("{}/users/order-create", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<OrderCreateWithReceiptForUser, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending order create with receipt for user in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
     fn store_moderation_status_for_user(&self, initiator: Initiator, payload: StoreModerationStatusForUser) -> ApiFuture<()> {
         let url = format!("{}/users/stores/update-moderation-status", self.notifications_url());
-        Box::new(
-            super::request::<_, StoreModerationStatusForUser, ()>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                Some(initiator.into()),
-            )
-            .map_err(|e| {
-                e.context("Sending change store moderation status for user in notifications microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<StoreModerationStatusForUser, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending change store moderation status for user in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn store_verified_for_user(&self, initiator: Initiator, payload: StoreVerifiedForUser) -> ApiFuture<()> {
+        let url = format!("{}/users/stores/verified", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<StoreVerifiedForUser, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending store verification to user in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn store_deactivated_for_user(&self, initiator: Initiator, payload: StoreDeactivatedForUser) -> ApiFuture<()> {
+        let url = format!("{}/users/stores/deactivated", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<StoreDeactivatedForUser, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending store deactivation to user in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn payout_initiated_for_store(&self, initiator: Initiator, payload: PayoutInitiatedForStore) -> ApiFuture<()> {
+        let url = format!("{}/stores/payout-initiated", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<PayoutInitiatedForStore, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending payout initiation to store in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn shipping_label_for_store(&self, initiator: Initiator, payload: ShippingLabelForStore) -> ApiFuture<()> {
+        let url = format!("{}/stores/order-shipping-label", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<ShippingLabelForStore, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending shipping label to store in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn low_stock_for_store(&self, initiator: Initiator, payload: LowStockForStore) -> ApiFuture<()> {
+        let url = format!("{}/stores/low-stock", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<LowStockForStore, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending low stock digest to store in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn order_acknowledgment_overdue_for_store(&self, initiator: Initiator, payload: OrderAcknowledgmentOverdueForStore) -> ApiFuture<()> {
+        let url = format!("{}/stores/order-acknowledgment-overdue", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<OrderAcknowledgmentOverdueForStore, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending overdue order acknowledgment to store in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn order_acknowledgment_overdue_for_moderator(
+        &self,
+        initiator: Initiator,
+        payload: OrderAcknowledgmentOverdueForModerator,
+    ) -> ApiFuture<()> {
+        let url = format!("{}/moderators/order-acknowledgment-overdue", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<OrderAcknowledgmentOverdueForModerator, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending overdue order acknowledgment to moderator in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn order_return_for_user(&self, initiator: Initiator, payload: OrderReturnForUser) -> ApiFuture<()> {
+        let url = format!("{}/users/order-return", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<OrderReturnForUser, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending order return to user in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn order_return_for_store(&self, initiator: Initiator, payload: OrderReturnForStore) -> ApiFuture<()> {
+        let url = format!("{}/stores/order-return", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<OrderReturnForStore, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending order return to store in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn cart_products_repriced_for_user(&self, initiator: Initiator, payload: CartProductsRepricedForUser) -> ApiFuture<()> {
+        let url = format!("{}/users/cart-products-repriced", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<CartProductsRepricedForUser, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending repriced cart products to user in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn cart_products_unavailable_for_user(&self, initiator: Initiator, payload: CartProductsUnavailableForUser) -> ApiFuture<()> {
+        let url = format!("{}/users/cart-products-unavailable", self.notifications_url());
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<CartProductsUnavailableForUser, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending unavailable cart products to user in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn base_product_moderation_status_for_user(&self, initiator: Initiator, payload: BaseProductModerationStatusForUser) -> ApiFuture<()> {
         let url = format!("{}/users/base_products/update-moderation-status", self.notifications_url());
-        Box::new(
-            super::request::<_, BaseProductModerationStatusForUser, ()>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                Some(initiator.into()),
-            )
-            .map_err(|e| {
-                e.context("Sending change base product moderation status for user in notifications microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<BaseProductModerationStatusForUser, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending change base product moderation status for user in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn store_moderation_status_for_moderator(&self, initiator: Initiator, payload: StoreModerationStatusForModerator) -> ApiFuture<()> {
         let url = format!("{}/moderators/stores/update-moderation-status", self.notifications_url());
-        Box::new(
-            super::request::<_, StoreModerationStatusForModerator, ()>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                Some(initiator.into()),
-            )
-            .map_err(|e| {
-                e.context("Sending change store moderation status for moderator in notifications microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<StoreModerationStatusForModerator, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending change store moderation status for moderator in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
     fn base_product_moderation_status_for_moderator(
@@ -257,43 +472,71 @@ impl<T: 'static + HttpClient + Clone> NotificationsMicroservice for Notification
         payload: BaseProductModerationStatusForModerator,
     ) -> ApiFuture<()> {
         let url = format!("{}/moderators/base_products/update-moderation-status", self.notifications_url());
-        Box::new(
-            super::request::<_, BaseProductModerationStatusForModerator, ()>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                Some(initiator.into()),
-            )
-            .map_err(|e| {
-                e.context("Sending change base product moderation status for moderator in notifications microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+        self.guarded(
+            Dependency::Notifications,
+            self.requester
+                .request::<BaseProductModerationStatusForModerator, ()>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Sending change base product moderation status for moderator in notifications microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn emarsys_create_contact(&self, payload: CreateEmarsysContactPayload) -> ApiFuture<CreatedEmarsysContact> {
         let url = format!("{}/emarsys/contact", self.notifications_url());
-        Box::new(
-            super::request::<_, CreateEmarsysContactPayload, CreatedEmarsysContact>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                None,
-            )
-            .map_err(|e| e.context("Creating contact in emarsys failed.").context(Error::HttpClient).into()),
+        self.guarded(
+            Dependency::Emarsys,
+            self.requester
+                .request::<CreateEmarsysContactPayload, CreatedEmarsysContact>(Method::Post, url, Some(payload), None)
+                .map_err(|e| e.context("Creating contact in emarsys failed.").context(Error::HttpClient).into()),
         )
     }
 }
 
 impl<T: 'static + HttpClient + Clone> NotificationsMicroserviceImpl<T> {
     pub fn new(http_client: T, config: config::Config) -> Self {
-        Self { http_client, config }
+        Self {
+            requester: Requester::new(http_client, &config),
+            config,
+            breakers: None,
+        }
+    }
+
+    pub fn with_audit(mut self, audit: AuditScope) -> Self {
+        self.requester.audit = Some(audit);
+        self
+    }
+
+    pub fn with_monitor(mut self, monitor: DependencyMonitor) -> Self {
+        self.requester.monitor = Some(monitor.scope("notifications"));
+        self
+    }
+
+    /// Skips calls while circuit breaker of the dependency is open
+    pub fn with_breakers(mut self, breakers: CircuitBreakers) -> Self {
+        self.breakers = Some(breakers);
+        self
+    }
+
+    fn guarded<S, F>(&self, dependency: Dependency, call: F) -> ApiFuture<S>
+    where
+        S: 'static,
+        F: Future<Item = S, Error = FailureError> + 'static,
+    {
+        match self.breakers {
+            Some(ref breakers) => breakers.guard(dependency, call),
+            None => Box::new(call),
+        }
     }
 
     fn notifications_url(&self) -> String {
         self.config.service_url(StqService::Notifications)
     }
 }
+
+/// Locale of the recipient as query parameter of notification url
+fn locale_query(locale: Option<String>) -> String {
+    locale.map(|locale| format!("&locale={}", locale)).unwrap_or_default()
+}
@@ -8,8 +8,9 @@ use stq_routes::model::Model as StqModel;
 use stq_routes::service::Service as StqService;
 use stq_types::*;
 
-use super::{ApiFuture, Initiator};
+use super::{ApiFuture, DependencyMonitor, Initiator, Requester};
 
+use audit::AuditScope;
 use config;
 use errors::Error;
 use models::*;
@@ -29,15 +30,27 @@ pub trait OrdersMicroservice {
     fn create_role(&self, initiator: Option<Initiator>, role: RoleEntry<NewOrdersRole>) -> ApiFuture<RoleEntry<NewOrdersRole>>;
     fn delete_role(&self, initiator: Option<Initiator>, role_id: RoleEntryId) -> ApiFuture<RoleEntry<NewOrdersRole>>;
     fn delete_products_from_all_carts(&self, initiator: Option<Initiator>, payload: DeleteProductsFromCartsPayload) -> ApiFuture<()>;
+    /// Customers having any of the products in their carts
+    fn find_carts_with_products(
+        &self,
+        initiator: Option<Initiator>,
+        payload: FindCartsWithProductsPayload,
+    ) -> ApiFuture<Vec<CustomerCartProducts>>;
     fn delete_delivery_method_from_all_carts(
         &self,
         initiator: Option<Initiator>,
         payload: DeleteDeliveryMethodFromCartsPayload,
     ) -> ApiFuture<()>;
+    fn create_return(&self, initiator: Initiator, payload: NewOrderReturn) -> ApiFuture<OrderReturn>;
+    fn revert_create_return(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<()>;
+    fn get_return_by_saga_id(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<Option<OrderReturn>>;
+    fn get_order_return(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<Option<OrderReturn>>;
+    /// Marks items of the return as received by store
+    fn receive_return(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<OrderReturn>;
 }
 
 pub struct OrdersMicroserviceImpl<T: 'static + HttpClient + Clone> {
-    http_client: T,
+    requester: Requester<T>,
     config: config::Config,
 }
 
@@ -45,18 +58,35 @@ impl<T: 'static + HttpClient + Clone> OrdersMicroservice for OrdersMicroserviceI
     fn delete_products_from_all_carts(&self, initiator: Option<Initiator>, payload: DeleteProductsFromCartsPayload) -> ApiFuture<()> {
         let url = format!("{}/{}/delete-products-from-all-carts", self.orders_url(), StqModel::Cart.to_url());
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Deleting products from cart in orders microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting products from cart in orders microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn find_carts_with_products(
+        &self,
+        initiator: Option<Initiator>,
+        payload: FindCartsWithProductsPayload,
+    ) -> ApiFuture<Vec<CustomerCartProducts>> {
+        let url = format!("{}/{}/by-products", self.orders_url(), StqModel::Cart.to_url());
+        Box::new(
+            self.requester
+                .request::<FindCartsWithProductsPayload, Vec<CustomerCartProducts>>(
+                    Method::Post,
+                    url,
+                    Some(payload),
+                    initiator.map(Into::into),
+                )
+                .map_err(|e| {
+                    e.context("Finding carts with products in orders microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
@@ -71,61 +101,53 @@ impl<T: 'static + HttpClient + Clone> OrdersMicroservice for OrdersMicroserviceI
             StqModel::Cart.to_url()
         );
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Deleting delivery method from cart in orders microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting delivery method from cart in orders microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn delete_role(&self, initiator: Option<Initiator>, role_id: RoleEntryId) -> ApiFuture<RoleEntry<NewOrdersRole>> {
         let url = format!("{}/roles/by-id/{}", self.orders_url(), role_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deleting role in orders microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), _>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting role in orders microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn create_role(&self, initiator: Option<Initiator>, payload: RoleEntry<NewOrdersRole>) -> ApiFuture<RoleEntry<NewOrdersRole>> {
         let url = format!("{}/{}", self.orders_url(), StqModel::Role.to_url());
         Box::new(
-            super::request::<_, RoleEntry<NewOrdersRole>, RoleEntry<NewOrdersRole>>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Creating role in orders microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<RoleEntry<NewOrdersRole>, RoleEntry<NewOrdersRole>>(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating role in orders microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn convert_cart(&self, payload: ConvertCartPayload) -> ApiFuture<Vec<Order>> {
         let url = format!("{}/{}/create_from_cart", self.orders_url(), StqModel::Order.to_url());
         Box::new(
-            super::request::<_, ConvertCartPayload, Vec<Order>>(self.http_client.clone(), Method::Post, url, Some(payload), None).map_err(
-                |e| {
+            self.requester
+                .request::<ConvertCartPayload, Vec<Order>>(Method::Post, url, Some(payload), None)
+                .map_err(|e| {
                     parse_validation_errors(e.into(), &["order"])
                         .context("Converting cart in orders microservice failed.")
                         .context(Error::HttpClient)
                         .into()
-                },
-            ),
+                }),
         )
     }
 
@@ -136,16 +158,18 @@ impl<T: 'static + HttpClient + Clone> OrdersMicroservice for OrdersMicroserviceI
             StqModel::Order.to_url(),
             order_identifier_route(&order_id),
         );
+        let requester = self.requester.clone();
 
         Box::new(
-            super::request::<_, (), Option<Order>>(self.http_client.clone(), Method::Get, url, None, initiator.map(Into::into)).map_err(
-                move |e| {
-                    parse_validation_errors(e.into(), &["order"])
-                        .context(format!("Getting order with id {:?} in orders microservice failed.", order_id))
-                        .context(Error::HttpClient)
-                        .into()
-                },
-            ),
+            super::retry_lookup(&self.config.saga.lookup_retries, move || {
+                Box::new(requester.request::<(), Option<Order>>(Method::Get, url.clone(), None, initiator.map(Into::into))) as ApiFuture<_>
+            })
+            .map_err(move |e| {
+                parse_validation_errors(e.into(), &["order"])
+                    .context(format!("Getting order with id {:?} in orders microservice failed.", order_id))
+                    .context(Error::HttpClient)
+                    .into()
+            }),
         )
     }
 
@@ -163,22 +187,17 @@ impl<T: 'static + HttpClient + Clone> OrdersMicroservice for OrdersMicroserviceI
         );
         let order_state = payload.state;
         Box::new(
-            super::request::<_, UpdateStatePayload, Option<Order>>(
-                self.http_client.clone(),
-                Method::Put,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(move |e| {
-                parse_validation_errors(e.into(), &["order"])
-                    .context(format!(
-                        "Setting order with id {:?} state {} in orders microservice failed.",
-                        order_id, order_state
-                    ))
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<UpdateStatePayload, Option<Order>>(Method::Put, url, Some(payload), initiator.map(Into::into))
+                .map_err(move |e| {
+                    parse_validation_errors(e.into(), &["order"])
+                        .context(format!(
+                            "Setting order with id {:?} state {} in orders microservice failed.",
+                            order_id, order_state
+                        ))
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
@@ -186,19 +205,14 @@ impl<T: 'static + HttpClient + Clone> OrdersMicroservice for OrdersMicroserviceI
         let url = format!("{}/{}/create_buy_now", self.orders_url(), StqModel::Order.to_url(),);
 
         Box::new(
-            super::request::<_, BuyNowPayload, Vec<Order>>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(BuyNowPayload { conversion_id, buy_now }),
-                None,
-            )
-            .map_err(|e| {
-                parse_validation_errors(e.into(), &["order"])
-                    .context("Create order from buy now data in orders microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<BuyNowPayload, Vec<Order>>(Method::Post, url, Some(BuyNowPayload { conversion_id, buy_now }), None)
+                .map_err(|e| {
+                    parse_validation_errors(e.into(), &["order"])
+                        .context("Create order from buy now data in orders microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
@@ -206,7 +220,8 @@ impl<T: 'static + HttpClient + Clone> OrdersMicroservice for OrdersMicroserviceI
         let url = format!("{}/{}/create_buy_now/revert", self.orders_url(), StqModel::Order.to_url(),);
         let headers = initiator.into();
         Box::new(
-            super::request::<_, ConvertCartRevert, CartHash>(self.http_client.clone(), Method::Post, url, Some(payload), Some(headers))
+            self.requester
+                .request::<ConvertCartRevert, CartHash>(Method::Post, url, Some(payload), Some(headers))
                 .map_err(|e| {
                     e.context("Revert convert cart in orders microservice failed.")
                         .context(Error::HttpClient)
@@ -214,11 +229,94 @@ impl<T: 'static + HttpClient + Clone> OrdersMicroservice for OrdersMicroserviceI
                 }),
         )
     }
+
+    fn create_return(&self, initiator: Initiator, payload: NewOrderReturn) -> ApiFuture<OrderReturn> {
+        let url = format!("{}/returns", self.orders_url());
+        Box::new(
+            self.requester
+                .request::<NewOrderReturn, OrderReturn>(Method::Post, url, Some(payload), Some(initiator.into()))
+                .map_err(|e| {
+                    parse_validation_errors(e.into(), &["order"])
+                        .context("Creating order return in orders microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn revert_create_return(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<()> {
+        let url = format!("{}/returns/by-saga-id/{}", self.orders_url(), saga_id.0);
+        Box::new(
+            self.requester
+                .request::<(), ()>(Method::Delete, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Reverting order return creation in orders microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn get_return_by_saga_id(&self, initiator: Initiator, saga_id: SagaId) -> ApiFuture<Option<OrderReturn>> {
+        let url = format!("{}/returns/by-saga-id/{}", self.orders_url(), saga_id.0);
+        Box::new(
+            self.requester
+                .request::<(), Option<OrderReturn>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Getting order return by saga id in orders microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn get_order_return(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<Option<OrderReturn>> {
+        let url = format!("{}/returns/by-order-id/{}", self.orders_url(), order_id);
+        Box::new(
+            self.requester
+                .request::<(), Option<OrderReturn>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(move |e| {
+                    e.context(format!("Getting return of order {} in orders microservice failed.", order_id))
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn receive_return(&self, initiator: Initiator, order_id: OrderId) -> ApiFuture<OrderReturn> {
+        let url = format!("{}/returns/by-order-id/{}/receive", self.orders_url(), order_id);
+        Box::new(
+            self.requester
+                .request::<(), OrderReturn>(Method::Post, url, None, Some(initiator.into()))
+                .map_err(move |e| {
+                    parse_validation_errors(e.into(), &["order"])
+                        .context(format!(
+                            "Confirming receipt of return of order {} in orders microservice failed.",
+                            order_id
+                        ))
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
 }
 
 impl<T: 'static + HttpClient + Clone> OrdersMicroserviceImpl<T> {
     pub fn new(http_client: T, config: config::Config) -> Self {
-        Self { http_client, config }
+        Self {
+            requester: Requester::new(http_client, &config),
+            config,
+        }
+    }
+
+    pub fn with_audit(mut self, audit: AuditScope) -> Self {
+        self.requester.audit = Some(audit);
+        self
+    }
+
+    pub fn with_monitor(mut self, monitor: DependencyMonitor) -> Self {
+        self.requester.monitor = Some(monitor.scope("orders"));
+        self
     }
 
     fn orders_url(&self) -> String {
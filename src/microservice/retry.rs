@@ -0,0 +1,86 @@
+//! Retries of lookups inside sagas. Lookups only read data, so they are safe to
+//! repeat: a transient failure of e.g. getting the order is retried a few times
+//! with backoff before the step and with it the saga fails. Responses of
+//! microservices with client errors, like validation errors, are never retried.
+//! Errors of clients are wrapped in context, so responses are looked up in their causes.
+use std::time::{Duration, Instant};
+
+use failure::Error;
+use futures::future::{self, Either, Loop};
+use futures::Future;
+use hyper::StatusCode;
+use tokio_timer::Delay;
+
+use stq_http::client::Error as HttpError;
+
+use super::ApiFuture;
+use config;
+
+/// Makes lookup with `make_lookup` and repeats it on transient failures, `config.attempts` times at most
+pub fn retry_lookup<T, F>(config: &config::LookupRetries, make_lookup: F) -> ApiFuture<T>
+where
+    T: 'static,
+    F: Fn() -> ApiFuture<T> + 'static,
+{
+    let attempts = config.attempts.max(1);
+    let backoff = Duration::from_millis(config.backoff_ms);
+    Box::new(future::loop_fn(1u32, move |attempt| {
+        make_lookup().then(move |res| match res {
+            Ok(item) => Either::A(future::ok(Loop::Break(item))),
+            Err(ref e) if attempt < attempts && is_transient(e) => {
+                debug!("Lookup attempt {} of {} failed, retrying: {}", attempt, attempts, e);
+                Either::B(
+                    Delay::new(Instant::now() + backoff * attempt)
+                        .map_err(|e| format_err!("Lookup retry timer failed: {}", e))
+                        .map(move |_| Loop::Continue(attempt + 1)),
+                )
+            }
+            Err(e) => Either::A(future::err(e)),
+        })
+    }))
+}
+
+/// Network errors, timeouts and server errors of microservices
+fn is_transient(e: &Error) -> bool {
+    match e.downcast_ref::<HttpError>() {
+        Some(HttpError::Api(status, _)) => status.is_server_error(),
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// Microservice responded that the entity does not exist
+pub fn is_not_found(e: &Error) -> bool {
+    e.iter_chain().any(|cause| match cause.downcast_ref::<HttpError>() {
+        Some(HttpError::Api(StatusCode::NotFound, _)) => true,
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use failure::Error;
+    use hyper::StatusCode;
+
+    use stq_http::client::Error as HttpError;
+
+    use super::{is_not_found, is_transient};
+
+    #[test]
+    fn retries_server_errors_only() {
+        assert!(is_transient(&Error::from(HttpError::Api(StatusCode::ServiceUnavailable, None))));
+        assert!(!is_transient(&Error::from(HttpError::Api(StatusCode::NotFound, None))));
+        assert!(!is_transient(&format_err!("Payload exceeds limit")));
+    }
+
+    #[test]
+    fn finds_not_found_in_context() {
+        let e = Error::from(HttpError::Api(StatusCode::NotFound, None));
+        assert!(is_not_found(&e.context("Deleting role failed").into()));
+        assert!(!is_not_found(
+            &Error::from(HttpError::Api(StatusCode::BadRequest, None))
+                .context("Deleting role failed")
+                .into()
+        ));
+    }
+}
@@ -0,0 +1,338 @@
+//! Shadow mode of calls to microservices, used while a microservice is migrated to its
+//! new version, e.g. billing v2. Calls to microservices listed in `[shadow]` are mirrored
+//! to the secondary url once the primary one has responded. Sagas always get the primary
+//! response, secondary responses are compared with it in background and mismatches are
+//! logged and listed in `GET /admin/shadow`. Failures and timeouts of the secondary
+//! microservice never reach sagas either.
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use futures::future;
+use futures::prelude::*;
+use futures::sync::mpsc;
+use hyper::header::Headers;
+use hyper::server::Response;
+use hyper::Method;
+use serde_json::{self, Value};
+
+use stq_http::client::{Error as HttpError, HttpClient, TimeLimitedHttpClient};
+
+use super::service_urls;
+use config::{Config, ShadowTarget};
+
+/// Mirrored calls compared at the same time
+const CONCURRENCY: usize = 8;
+/// Mismatches kept per microservice
+const RECENT_MISMATCHES: usize = 20;
+/// Differing fields kept per mismatch
+const MAX_DIFFS: usize = 20;
+
+#[derive(Clone, Debug)]
+struct ShadowService {
+    name: String,
+    /// Url prefix of the primary microservice
+    primary_url: String,
+    target: ShadowTarget,
+    methods: Vec<Method>,
+}
+
+struct ShadowCall {
+    service: String,
+    method: Method,
+    url: String,
+    body: Option<String>,
+    headers: Option<Headers>,
+    ignored_fields: Vec<String>,
+    primary_status: u16,
+    primary_body: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ShadowMismatch {
+    pub at: SystemTime,
+    pub method: String,
+    pub url: String,
+    pub primary_status: u16,
+    pub secondary_status: u16,
+    /// Paths of differing fields, e.g. `$.items[0].price`
+    pub diffs: Vec<String>,
+}
+
+/// Outcomes of calls mirrored to the secondary microservice
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ShadowReport {
+    pub matched: u64,
+    pub mismatched: u64,
+    /// Calls the secondary microservice did not answer
+    pub failed: u64,
+    pub recent_mismatches: VecDeque<ShadowMismatch>,
+}
+
+/// Reports of shadow mode by name of the primary microservice
+#[derive(Clone, Default)]
+pub struct ShadowReports(Arc<Mutex<HashMap<String, ShadowReport>>>);
+
+impl ShadowReports {
+    pub fn get(&self) -> HashMap<String, ShadowReport> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn record(&self, service: &str, outcome: Result<(), ShadowMismatch>) {
+        let mut reports = self.0.lock().unwrap();
+        let report = reports.entry(service.to_string()).or_insert_with(ShadowReport::default);
+        match outcome {
+            Ok(()) => report.matched += 1,
+            Err(mismatch) => {
+                report.mismatched += 1;
+                report.recent_mismatches.push_back(mismatch);
+                while report.recent_mismatches.len() > RECENT_MISMATCHES {
+                    report.recent_mismatches.pop_front();
+                }
+            }
+        }
+    }
+
+    fn record_failure(&self, service: &str) {
+        let mut reports = self.0.lock().unwrap();
+        reports.entry(service.to_string()).or_insert_with(ShadowReport::default).failed += 1;
+    }
+}
+
+/// Http client mirroring calls configured for the microservice, calls are passed through if shadow mode is off
+#[derive(Clone)]
+pub struct ShadowHttpClient<C: HttpClient + Clone> {
+    inner: C,
+    services: Vec<ShadowService>,
+    sender: Option<mpsc::UnboundedSender<ShadowCall>>,
+    reports: ShadowReports,
+}
+
+impl<C: HttpClient + Clone + 'static> ShadowHttpClient<C> {
+    /// Client along with the future comparing mirrored calls, the future must be spawned for shadow mode to work
+    pub fn new(inner: C, config: &Config) -> (Self, Box<Future<Item = (), Error = ()>>) {
+        let reports = ShadowReports::default();
+        let shadow = match config.shadow {
+            Some(ref shadow) if !shadow.services.is_empty() => shadow,
+            _ => {
+                let client = Self {
+                    inner,
+                    services: vec![],
+                    sender: None,
+                    reports,
+                };
+                return (client, Box::new(future::ok(())));
+            }
+        };
+
+        warn!("Shadow mode is on, calls to {:?} are mirrored", shadow.services.keys());
+        let services = service_urls(config)
+            .into_iter()
+            .filter_map(|(name, url)| {
+                shadow.services.get(name).map(|target| ShadowService {
+                    name: name.to_string(),
+                    primary_url: url,
+                    target: target.clone(),
+                    methods: target
+                        .methods
+                        .as_ref()
+                        .map(|methods| methods.iter().filter_map(|method| method.parse().ok()).collect())
+                        .unwrap_or_else(|| vec![Method::Get]),
+                })
+            })
+            .collect();
+
+        let (sender, receiver) = mpsc::unbounded();
+        let secondary = TimeLimitedHttpClient::new(inner.clone(), Duration::from_millis(config.client.http_timeout_ms));
+        let worker = {
+            let reports = reports.clone();
+            receiver
+                .map(move |call: ShadowCall| {
+                    let reports = reports.clone();
+                    secondary
+                        .request(call.method.clone(), call.url.clone(), call.body.clone(), call.headers.clone())
+                        .and_then(|response| {
+                            let status = response.status().as_u16();
+                            response
+                                .body()
+                                .concat2()
+                                .map(move |body| (status, body.to_vec()))
+                                .map_err(HttpError::Network)
+                        })
+                        .then(move |res| {
+                            match res {
+                                Ok((status, body)) => {
+                                    let outcome = compare(&call, status, &body);
+                                    if let Err(ref mismatch) = outcome {
+                                        warn!(
+                                            "Shadow call {} {} mismatched, status {} vs {}, fields {:?}",
+                                            mismatch.method,
+                                            mismatch.url,
+                                            mismatch.primary_status,
+                                            mismatch.secondary_status,
+                                            mismatch.diffs
+                                        );
+                                    }
+                                    reports.record(&call.service, outcome);
+                                }
+                                Err(e) => {
+                                    warn!("Shadow call {} {} failed: {}", call.method, call.url, e);
+                                    reports.record_failure(&call.service);
+                                }
+                            }
+                            Ok::<_, ()>(())
+                        })
+                })
+                .buffer_unordered(CONCURRENCY)
+                .for_each(|_| Ok(()))
+        };
+
+        let client = Self {
+            inner,
+            services,
+            sender: Some(sender),
+            reports,
+        };
+        (client, Box::new(worker))
+    }
+}
+
+impl<C: HttpClient + Clone> ShadowHttpClient<C> {
+    pub fn reports(&self) -> ShadowReports {
+        self.reports.clone()
+    }
+
+    fn service(&self, method: &Method, url: &str) -> Option<&ShadowService> {
+        self.services
+            .iter()
+            .find(|service| url.starts_with(service.primary_url.as_str()) && service.methods.contains(method))
+    }
+}
+
+impl<C: HttpClient + Clone> HttpClient for ShadowHttpClient<C> {
+    fn request(
+        &self,
+        method: Method,
+        url: String,
+        body: Option<String>,
+        headers: Option<Headers>,
+    ) -> Box<Future<Item = Response, Error = HttpError> + Send> {
+        let (service, sender) = match (self.service(&method, &url), self.sender.as_ref()) {
+            (Some(service), Some(sender)) => (service.clone(), sender.clone()),
+            _ => return self.inner.request(method, url, body, headers),
+        };
+
+        let call = ShadowCall {
+            service: service.name.clone(),
+            method: method.clone(),
+            url: format!("{}{}", service.target.url, &url[service.primary_url.len()..]),
+            body: body.clone(),
+            headers: headers.clone(),
+            ignored_fields: service.target.ignored_fields.clone(),
+            primary_status: 0,
+            primary_body: vec![],
+        };
+        Box::new(self.inner.request(method, url, body, headers).and_then(move |response| {
+            let status = response.status();
+            let response_headers = response.headers().clone();
+            response.body().concat2().map_err(HttpError::Network).map(move |body| {
+                let body = body.to_vec();
+                let call = ShadowCall {
+                    primary_status: status.as_u16(),
+                    primary_body: body.clone(),
+                    ..call
+                };
+                if sender.unbounded_send(call).is_err() {
+                    debug!("Shadow mode is stopped, call is not mirrored");
+                }
+                Response::new().with_status(status).with_headers(response_headers).with_body(body)
+            })
+        }))
+    }
+}
+
+fn compare(call: &ShadowCall, secondary_status: u16, secondary_body: &[u8]) -> Result<(), ShadowMismatch> {
+    let mut diffs = vec![];
+    match (
+        serde_json::from_slice::<Value>(&call.primary_body),
+        serde_json::from_slice::<Value>(secondary_body),
+    ) {
+        (Ok(primary), Ok(secondary)) => diff("$", &primary, &secondary, &call.ignored_fields, &mut diffs),
+        _ => {
+            if call.primary_body[..] != secondary_body[..] {
+                diffs.push("$".to_string());
+            }
+        }
+    }
+    if call.primary_status == secondary_status && diffs.is_empty() {
+        return Ok(());
+    }
+    diffs.truncate(MAX_DIFFS);
+    Err(ShadowMismatch {
+        at: SystemTime::now(),
+        method: call.method.to_string(),
+        url: call.url.clone(),
+        primary_status: call.primary_status,
+        secondary_status,
+        diffs,
+    })
+}
+
+/// Collects paths of fields differing between primary and secondary values, ignored fields are skipped at any depth
+fn diff(path: &str, primary: &Value, secondary: &Value, ignored_fields: &[String], diffs: &mut Vec<String>) {
+    match (primary, secondary) {
+        (&Value::Object(ref primary), &Value::Object(ref secondary)) => {
+            let mut names = primary.keys().chain(secondary.keys()).collect::<Vec<_>>();
+            names.sort();
+            names.dedup();
+            for name in names {
+                if ignored_fields.contains(name) {
+                    continue;
+                }
+                let path = format!("{}.{}", path, name);
+                match (primary.get(name), secondary.get(name)) {
+                    (Some(primary), Some(secondary)) => diff(&path, primary, secondary, ignored_fields, diffs),
+                    _ => diffs.push(path),
+                }
+            }
+        }
+        (&Value::Array(ref primary), &Value::Array(ref secondary)) if primary.len() == secondary.len() => {
+            for (i, (primary, secondary)) in primary.iter().zip(secondary).enumerate() {
+                diff(&format!("{}[{}]", path, i), primary, secondary, ignored_fields, diffs);
+            }
+        }
+        (primary, secondary) => {
+            if primary != secondary {
+                diffs.push(path.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::diff;
+
+    #[test]
+    fn lists_differing_fields() {
+        let primary = json!({
+            "id": 1,
+            "created_at": "2018-01-01T00:00:00Z",
+            "items": [{"price": 10.0, "quantity": 1}],
+            "state": "paid",
+        });
+        let secondary = json!({
+            "id": 2,
+            "created_at": "2018-01-02T00:00:00Z",
+            "items": [{"price": 12.0, "quantity": 1}],
+            "fee": 1.0,
+        });
+        let mut diffs = vec![];
+        diff("$", &primary, &secondary, &["id".to_string(), "created_at".to_string()], &mut diffs);
+        assert_eq!(diffs, vec!["$.fee", "$.items[0].price", "$.state"]);
+
+        let mut diffs = vec![];
+        diff("$", &json!([1, 2]), &json!([1]), &[], &mut diffs);
+        assert_eq!(diffs, vec!["$"]);
+    }
+}
@@ -1,14 +1,16 @@
 use failure::Fail;
 use futures::Future;
 use hyper::Method;
+use serde_json;
 
 use stq_http::client::HttpClient;
 use stq_routes::model::Model as StqModel;
 use stq_routes::service::Service as StqService;
 use stq_types::*;
 
-use super::{ApiFuture, Initiator};
+use super::{ApiFuture, DependencyMonitor, Initiator, Requester};
 
+use audit::AuditScope;
 use config;
 use errors::Error;
 use models::*;
@@ -21,18 +23,54 @@ pub trait StoresMicroservice {
     fn create_store(&self, initiator: Option<Initiator>, payload: NewStore) -> ApiFuture<Store>;
     fn use_coupon(&self, initiator: Initiator, coupon: CouponId, user: UserId) -> ApiFuture<UsedCoupon>;
     fn get(&self, store: StoreId, visibility: Visibility) -> ApiFuture<Option<Store>>;
+    fn get_store_by_saga_id(&self, initiator: Option<Initiator>, saga_id: SagaId) -> ApiFuture<Option<Store>>;
+    /// Stores of the user, including inactive ones
+    fn search_stores(&self, initiator: Option<Initiator>, payload: SearchStoresPayload) -> ApiFuture<Vec<Store>>;
     fn get_base_product(&self, base_product_id: BaseProductId, visibility: Visibility) -> ApiFuture<Option<BaseProduct>>;
     fn get_products_by_base_product(&self, base_product_id: BaseProductId) -> ApiFuture<Vec<Product>>;
     fn get_products_by_store(&self, store_id: StoreId) -> ApiFuture<Vec<Product>>;
+    /// Ids of all active stores
+    fn get_store_ids(&self, initiator: Option<Initiator>) -> ApiFuture<Vec<StoreId>>;
     fn set_store_moderation_status(&self, payload: StoreModerate) -> ApiFuture<Store>;
     fn send_to_moderation(&self, store_id: StoreId) -> ApiFuture<Store>;
     fn set_moderation_status_base_product(&self, payload: BaseProductModerate) -> ApiFuture<BaseProduct>;
     fn send_to_moderation_base_product(&self, base_product_id: BaseProductId) -> ApiFuture<BaseProduct>;
     fn get_moderators(&self, initiator: Initiator) -> ApiFuture<Vec<UserId>>;
-    fn deactivate_base_product(&self, initiator: Option<Initiator>, base_product_id: BaseProductId) -> ApiFuture<BaseProduct>;
-    fn deactivate_store(&self, initiator: Option<Initiator>, store_id: StoreId) -> ApiFuture<Store>;
+    fn deactivate_base_product(
+        &self,
+        initiator: Option<Initiator>,
+        base_product_id: BaseProductId,
+        payload: Deactivation,
+    ) -> ApiFuture<BaseProduct>;
+    fn deactivate_store(&self, initiator: Option<Initiator>, store_id: StoreId, payload: Deactivation) -> ApiFuture<Store>;
+    fn set_store_verification(&self, initiator: Option<Initiator>, store_id: StoreId, payload: StoreVerification) -> ApiFuture<Store>;
+    /// Replaces public fields of the store, e.g. name and contacts
+    fn update_store_public_fields(&self, initiator: Option<Initiator>, store_id: StoreId, payload: StorePublicFields) -> ApiFuture<Store>;
+    /// Hides products of the store from search or brings them back, the store itself stays active
+    fn set_search_visibility(&self, initiator: Option<Initiator>, store_id: StoreId, payload: SearchVisibility) -> ApiFuture<()>;
+    /// Saves draft of store changes, draft is passed through as is
+    fn update_store_draft(&self, initiator: Option<Initiator>, store_id: StoreId, draft: serde_json::Value)
+        -> ApiFuture<serde_json::Value>;
     fn deactivate_store_by_saga_id(&self, initiator: Option<Initiator>, saga_id: SagaId) -> ApiFuture<Store>;
-    fn deactivate_product(&self, initiator: Option<Initiator>, product_id: ProductId) -> ApiFuture<Product>;
+    fn deactivate_product(&self, initiator: Option<Initiator>, product_id: ProductId, payload: Deactivation) -> ApiFuture<Product>;
+    fn activate_store(&self, initiator: Option<Initiator>, store_id: StoreId) -> ApiFuture<Store>;
+    fn activate_base_product(&self, initiator: Option<Initiator>, base_product_id: BaseProductId) -> ApiFuture<BaseProduct>;
+    fn activate_product(&self, initiator: Option<Initiator>, product_id: ProductId) -> ApiFuture<Product>;
+    fn set_product_quantity(&self, initiator: Option<Initiator>, product_id: ProductId, quantity: Quantity) -> ApiFuture<Product>;
+    fn update_product_price(&self, initiator: Option<Initiator>, product_id: ProductId, payload: UpdateProductPrice) -> ApiFuture<Product>;
+    /// Restores price the product had before it was updated by the saga
+    fn revert_product_price(&self, initiator: Option<Initiator>, product_id: ProductId, saga_id: SagaId) -> ApiFuture<()>;
+    /// Adds variant to existing base product, `base_product_id` of the payload is set
+    fn create_product(&self, initiator: Option<Initiator>, payload: CreateProductWithAttributes) -> ApiFuture<Product>;
+    fn update_product(&self, initiator: Option<Initiator>, product_id: ProductId, payload: UpdateProductWithSaga) -> ApiFuture<Product>;
+    /// Restores fields the product had before it was updated by the saga
+    fn revert_product_update(&self, initiator: Option<Initiator>, product_id: ProductId, saga_id: SagaId) -> ApiFuture<()>;
+    /// Removes products from wishlists of every user
+    fn delete_products_from_all_wishlists(
+        &self,
+        initiator: Option<Initiator>,
+        payload: DeleteProductsFromWishlistsPayload,
+    ) -> ApiFuture<()>;
     fn update_base_product(
         &self,
         initiator: Option<Initiator>,
@@ -47,7 +85,7 @@ pub trait StoresMicroservice {
 }
 
 pub struct StoresMicroserviceImpl<T: 'static + HttpClient + Clone> {
-    http_client: T,
+    requester: Requester<T>,
     config: config::Config,
 }
 
@@ -59,122 +97,350 @@ impl<T: 'static + HttpClient + Clone> StoresMicroservice for StoresMicroserviceI
     ) -> ApiFuture<BaseProduct> {
         let url = format!("{}/{}/with_variants", self.stores_url(), StqModel::BaseProduct.to_url());
         Box::new(
-            super::request::<_, NewBaseProductWithVariants, _>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Create base product with variants in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<NewBaseProductWithVariants, _>(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Create base product with variants in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
-    fn deactivate_product(&self, initiator: Option<Initiator>, product_id: ProductId) -> ApiFuture<Product> {
+    fn deactivate_product(&self, initiator: Option<Initiator>, product_id: ProductId, payload: Deactivation) -> ApiFuture<Product> {
         let url = format!("{}/{}/{}", self.stores_url(), StqModel::Product.to_url(), product_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deactivate product in stores microservice failed.")
+            self.requester
+                .request::<Deactivation, _>(Method::Delete, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deactivate product in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn set_product_quantity(&self, initiator: Option<Initiator>, product_id: ProductId, quantity: Quantity) -> ApiFuture<Product> {
+        let url = format!("{}/{}/{}/quantity", self.stores_url(), StqModel::Product.to_url(), product_id);
+        Box::new(
+            self.requester
+                .request::<ProductQuantity, Product>(Method::Put, url, Some(ProductQuantity { quantity }), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Setting product quantity in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn update_product_price(&self, initiator: Option<Initiator>, product_id: ProductId, payload: UpdateProductPrice) -> ApiFuture<Product> {
+        let url = format!("{}/{}/{}/price", self.stores_url(), StqModel::Product.to_url(), product_id);
+        Box::new(
+            self.requester
+                .request::<UpdateProductPrice, Product>(Method::Put, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    parse_validation_errors(e.into(), &["price"])
+                        .context("Updating product price in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn create_product(&self, initiator: Option<Initiator>, payload: CreateProductWithAttributes) -> ApiFuture<Product> {
+        let url = format!("{}/{}", self.stores_url(), StqModel::Product.to_url());
+        Box::new(
+            self.requester
+                .request::<CreateProductWithAttributes, Product>(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating product in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn update_product(&self, initiator: Option<Initiator>, product_id: ProductId, payload: UpdateProductWithSaga) -> ApiFuture<Product> {
+        let url = format!("{}/{}/{}", self.stores_url(), StqModel::Product.to_url(), product_id);
+        Box::new(
+            self.requester
+                .request::<UpdateProductWithSaga, Product>(Method::Put, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Updating product in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn revert_product_update(&self, initiator: Option<Initiator>, product_id: ProductId, saga_id: SagaId) -> ApiFuture<()> {
+        let url = format!(
+            "{}/{}/{}/by-saga-id/{}",
+            self.stores_url(),
+            StqModel::Product.to_url(),
+            product_id,
+            saga_id.0
+        );
+        Box::new(
+            self.requester
+                .request::<(), ()>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Reverting product update in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn revert_product_price(&self, initiator: Option<Initiator>, product_id: ProductId, saga_id: SagaId) -> ApiFuture<()> {
+        let url = format!(
+            "{}/{}/{}/price/by-saga-id/{}",
+            self.stores_url(),
+            StqModel::Product.to_url(),
+            product_id,
+            saga_id.0
+        );
+        Box::new(
+            self.requester
+                .request::<(), ()>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Reverting product price in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn delete_products_from_all_wishlists(
+        &self,
+        initiator: Option<Initiator>,
+        payload: DeleteProductsFromWishlistsPayload,
+    ) -> ApiFuture<()> {
+        let url = format!("{}/wishlists/delete-products-from-all-wishlists", self.stores_url());
+        Box::new(
+            self.requester
+                .request::<DeleteProductsFromWishlistsPayload, ()>(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting products from wishlists in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn deactivate_store(&self, initiator: Option<Initiator>, store_id: StoreId, payload: Deactivation) -> ApiFuture<Store> {
+        let url = format!("{}/{}/{}", self.stores_url(), StqModel::Store.to_url(), store_id);
+        Box::new(
+            self.requester
+                .request::<Deactivation, _>(Method::Delete, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deactivate store in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn set_store_verification(&self, initiator: Option<Initiator>, store_id: StoreId, payload: StoreVerification) -> ApiFuture<Store> {
+        let url = format!("{}/{}/{}/verification", self.stores_url(), StqModel::Store.to_url(), store_id);
+        Box::new(
+            self.requester
+                .request::<StoreVerification, Store>(Method::Put, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    parse_validation_errors(e.into(), &["store"])
+                        .context("Setting store verification in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn update_store_public_fields(&self, initiator: Option<Initiator>, store_id: StoreId, payload: StorePublicFields) -> ApiFuture<Store> {
+        let url = format!("{}/{}/{}", self.stores_url(), StqModel::Store.to_url(), store_id);
+        Box::new(
+            self.requester
+                .request::<StorePublicFields, Store>(Method::Put, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    parse_validation_errors(
+                        e.into(),
+                        &["name", "short_description", "long_description", "phone", "email", "store"],
+                    )
+                    .context("Updating public fields of store in stores microservice failed.")
                     .context(Error::HttpClient)
                     .into()
-            }),
+                }),
         )
     }
 
-    fn deactivate_store(&self, initiator: Option<Initiator>, store_id: StoreId) -> ApiFuture<Store> {
-        let url = format!("{}/{}/{}", self.stores_url(), StqModel::Store.to_url(), store_id);
+    fn set_search_visibility(&self, initiator: Option<Initiator>, store_id: StoreId, payload: SearchVisibility) -> ApiFuture<()> {
+        let url = format!("{}/{}/{}/search_visibility", self.stores_url(), StqModel::Store.to_url(), store_id);
+        Box::new(
+            self.requester
+                .request::<SearchVisibility, ()>(Method::Put, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    parse_validation_errors(e.into(), &["store"])
+                        .context("Setting search visibility of store products in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn update_store_draft(
+        &self,
+        initiator: Option<Initiator>,
+        store_id: StoreId,
+        draft: serde_json::Value,
+    ) -> ApiFuture<serde_json::Value> {
+        let url = format!("{}/{}/{}/draft", self.stores_url(), StqModel::Store.to_url(), store_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deactivate store in stores microservice failed.")
+            self.requester
+                .request::<serde_json::Value, serde_json::Value>(Method::Put, url, Some(draft), initiator.map(Into::into))
+                .map_err(|e| {
+                    parse_validation_errors(
+                        e.into(),
+                        &[
+                            "name",
+                            "short_description",
+                            "long_description",
+                            "slug",
+                            "phone",
+                            "email",
+                            "default_language",
+                            "store",
+                        ],
+                    )
+                    .context("Updating store draft in stores microservice failed.")
                     .context(Error::HttpClient)
                     .into()
-            }),
+                }),
         )
     }
 
     fn deactivate_store_by_saga_id(&self, initiator: Option<Initiator>, saga_id: SagaId) -> ApiFuture<Store> {
         let url = format!("{}/{}/by_saga_id/{}", self.stores_url(), StqModel::Store.to_url(), saga_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deactivate store by saga ID in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), _>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deactivate store by saga ID in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
-    fn deactivate_base_product(&self, initiator: Option<Initiator>, base_product_id: BaseProductId) -> ApiFuture<BaseProduct> {
+    fn deactivate_base_product(
+        &self,
+        initiator: Option<Initiator>,
+        base_product_id: BaseProductId,
+        payload: Deactivation,
+    ) -> ApiFuture<BaseProduct> {
         let url = format!("{}/{}/{}", self.stores_url(), StqModel::BaseProduct.to_url(), base_product_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deactivate base product in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<Deactivation, _>(Method::Delete, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deactivate base product in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn activate_store(&self, initiator: Option<Initiator>, store_id: StoreId) -> ApiFuture<Store> {
+        let url = format!("{}/{}/{}/activate", self.stores_url(), StqModel::Store.to_url(), store_id);
+        Box::new(
+            self.requester
+                .request::<(), _>(Method::Post, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Activate store in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn activate_base_product(&self, initiator: Option<Initiator>, base_product_id: BaseProductId) -> ApiFuture<BaseProduct> {
+        let url = format!(
+            "{}/{}/{}/activate",
+            self.stores_url(),
+            StqModel::BaseProduct.to_url(),
+            base_product_id
+        );
+        Box::new(
+            self.requester
+                .request::<(), _>(Method::Post, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Activate base product in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn activate_product(&self, initiator: Option<Initiator>, product_id: ProductId) -> ApiFuture<Product> {
+        let url = format!("{}/{}/{}/activate", self.stores_url(), StqModel::Product.to_url(), product_id);
+        Box::new(
+            self.requester
+                .request::<(), _>(Method::Post, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Activate product in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn delete_stores_role(&self, initiator: Option<Initiator>, role_id: RoleId) -> ApiFuture<NewRole<StoresRole>> {
         let url = format!("{}/roles/by-id/{}", self.stores_url(), role_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deleting role in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), _>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting role in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn create_stores_role(&self, initiator: Option<Initiator>, payload: NewRole<StoresRole>) -> ApiFuture<NewRole<StoresRole>> {
         let url = format!("{}/{}", self.stores_url(), StqModel::Role.to_url());
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Creating role in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating role in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn delete_store(&self, initiator: Option<Initiator>, store_id: StoreId) -> ApiFuture<Store> {
         let url = format!("{}/{}/{}", self.stores_url(), StqModel::Store.to_url(), store_id);
         Box::new(
-            super::request::<_, NewStore, Store>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(
-                |e| {
+            self.requester
+                .request::<NewStore, Store>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
                     e.context("Deleting store in stores microservice failed.")
                         .context(Error::HttpClient)
                         .into()
-                },
-            ),
+                }),
         )
     }
 
     fn create_store(&self, initiator: Option<Initiator>, payload: NewStore) -> ApiFuture<Store> {
         let url = format!("{}/{}", self.stores_url(), StqModel::Store.to_url());
         Box::new(
-            super::request::<_, NewStore, Store>(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Creating store in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<NewStore, Store>(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating store in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
@@ -186,8 +452,12 @@ impl<T: 'static + HttpClient + Clone> StoresMicroservice for StoresMicroserviceI
             store,
             visibility
         );
+        let requester = self.requester.clone();
         Box::new(
-            super::request::<_, (), Option<Store>>(self.http_client.clone(), Method::Get, url, None, None).map_err(|e| {
+            super::retry_lookup(&self.config.saga.lookup_retries, move || {
+                Box::new(requester.request::<(), Option<Store>>(Method::Get, url.clone(), None, None)) as ApiFuture<_>
+            })
+            .map_err(|e| {
                 e.context("Getting store in stores microservice failed.")
                     .context(Error::HttpClient)
                     .into()
@@ -195,6 +465,32 @@ impl<T: 'static + HttpClient + Clone> StoresMicroservice for StoresMicroserviceI
         )
     }
 
+    fn get_store_by_saga_id(&self, initiator: Option<Initiator>, saga_id: SagaId) -> ApiFuture<Option<Store>> {
+        let url = format!("{}/{}/by_saga_id/{}", self.stores_url(), StqModel::Store.to_url(), saga_id);
+        Box::new(
+            self.requester
+                .request::<(), Option<Store>>(Method::Get, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Getting store by saga id in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn search_stores(&self, initiator: Option<Initiator>, payload: SearchStoresPayload) -> ApiFuture<Vec<Store>> {
+        let url = format!("{}/{}/search", self.stores_url(), StqModel::Store.to_url());
+        Box::new(
+            self.requester
+                .request::<SearchStoresPayload, Vec<Store>>(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Searching stores in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
     fn get_base_product(&self, base_product_id: BaseProductId, visibility: Visibility) -> ApiFuture<Option<BaseProduct>> {
         let url = format!(
             "{}/{}/{}?visibility={}",
@@ -204,11 +500,13 @@ impl<T: 'static + HttpClient + Clone> StoresMicroservice for StoresMicroserviceI
             visibility
         );
         Box::new(
-            super::request::<_, (), Option<BaseProduct>>(self.http_client.clone(), Method::Get, url, None, None).map_err(|e| {
-                e.context("Getting base product in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), Option<BaseProduct>>(Method::Get, url, None, None)
+                .map_err(|e| {
+                    e.context("Getting base product in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
@@ -220,33 +518,52 @@ impl<T: 'static + HttpClient + Clone> StoresMicroservice for StoresMicroserviceI
             base_product_id
         );
         Box::new(
-            super::request::<_, (), Vec<Product>>(self.http_client.clone(), Method::Get, url, None, None).map_err(|e| {
-                e.context("Getting products by base product in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), Vec<Product>>(Method::Get, url, None, None)
+                .map_err(|e| {
+                    e.context("Getting products by base product in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn get_products_by_store(&self, store_id: StoreId) -> ApiFuture<Vec<Product>> {
         let url = format!("{}/{}/by_store/{}", self.stores_url(), StqModel::Product.to_url(), store_id);
         Box::new(
-            super::request::<_, (), Vec<Product>>(self.http_client.clone(), Method::Get, url, None, None).map_err(|e| {
-                e.context("Getting products by store in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), Vec<Product>>(Method::Get, url, None, None)
+                .map_err(|e| {
+                    e.context("Getting products by store in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn get_store_ids(&self, initiator: Option<Initiator>) -> ApiFuture<Vec<StoreId>> {
+        let url = format!("{}/{}/ids", self.stores_url(), StqModel::Store.to_url());
+        Box::new(
+            self.requester
+                .request::<(), Vec<StoreId>>(Method::Get, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Getting store ids in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn use_coupon(&self, initiator: Initiator, coupon_id: CouponId, user: UserId) -> ApiFuture<UsedCoupon> {
         let url = format!("{}/{}/{}/users/{}", self.stores_url(), StqModel::Coupon.to_url(), coupon_id, user);
         Box::new(
-            super::request::<_, (), UsedCoupon>(self.http_client.clone(), Method::Post, url, None, Some(initiator.into())).map_err(|e| {
-                e.context("Commit coupon for user in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), UsedCoupon>(Method::Post, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Commit coupon for user in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
@@ -254,33 +571,34 @@ impl<T: 'static + HttpClient + Clone> StoresMicroservice for StoresMicroserviceI
         let url = format!("{}/{}/moderate", self.stores_url(), StqModel::Store.to_url());
 
         Box::new(
-            super::request::<_, StoreModerate, Store>(self.http_client.clone(), Method::Post, url, Some(payload), None).map_err(|e| {
-                parse_validation_errors(e.into(), &["store"])
-                    .context("Set new status for store in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<StoreModerate, Store>(Method::Post, url, Some(payload), None)
+                .map_err(|e| {
+                    parse_validation_errors(e.into(), &["store"])
+                        .context("Set new status for store in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn send_to_moderation(&self, store_id: StoreId) -> ApiFuture<Store> {
         let url = format!("{}/{}/{}/moderation", self.stores_url(), StqModel::Store.to_url(), store_id);
 
-        Box::new(
-            super::request::<_, (), Store>(self.http_client.clone(), Method::Post, url, None, None).map_err(|e| {
-                parse_validation_errors(e.into(), &["store"])
-                    .context("Send store to moderation to moderation in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
-        )
+        Box::new(self.requester.request::<(), Store>(Method::Post, url, None, None).map_err(|e| {
+            parse_validation_errors(e.into(), &["store"])
+                .context("Send store to moderation to moderation in stores microservice failed.")
+                .context(Error::HttpClient)
+                .into()
+        }))
     }
 
     fn set_moderation_status_base_product(&self, payload: BaseProductModerate) -> ApiFuture<BaseProduct> {
         let url = format!("{}/{}/moderate", self.stores_url(), StqModel::BaseProduct.to_url());
 
         Box::new(
-            super::request::<_, BaseProductModerate, BaseProduct>(self.http_client.clone(), Method::Post, url, Some(payload), None)
+            self.requester
+                .request::<BaseProductModerate, BaseProduct>(Method::Post, url, Some(payload), None)
                 .map_err(|e| {
                     parse_validation_errors(e.into(), &["base_product"])
                         .context("Set new status for base_product in stores microservice failed.")
@@ -299,12 +617,14 @@ impl<T: 'static + HttpClient + Clone> StoresMicroservice for StoresMicroserviceI
         );
 
         Box::new(
-            super::request::<_, (), BaseProduct>(self.http_client.clone(), Method::Post, url, None, None).map_err(|e| {
-                parse_validation_errors(e.into(), &["base_product"])
-                    .context("Send base_product to moderation in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), BaseProduct>(Method::Post, url, None, None)
+                .map_err(|e| {
+                    parse_validation_errors(e.into(), &["base_product"])
+                        .context("Send base_product to moderation in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
@@ -317,11 +637,13 @@ impl<T: 'static + HttpClient + Clone> StoresMicroservice for StoresMicroserviceI
         );
 
         Box::new(
-            super::request::<_, (), Vec<UserId>>(self.http_client.clone(), Method::Get, url, None, Some(initiator.into())).map_err(|e| {
-                e.context("Get moderators in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), Vec<UserId>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Get moderators in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
@@ -334,25 +656,33 @@ impl<T: 'static + HttpClient + Clone> StoresMicroservice for StoresMicroserviceI
         let url = format!("{}/{}/{}", self.stores_url(), StqModel::BaseProduct.to_url(), base_product_id);
 
         Box::new(
-            super::request::<_, UpdateBaseProduct, BaseProduct>(
-                self.http_client.clone(),
-                Method::Put,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Update base product in stores microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<UpdateBaseProduct, BaseProduct>(Method::Put, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Update base product in stores microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 }
 
 impl<T: 'static + HttpClient + Clone> StoresMicroserviceImpl<T> {
     pub fn new(http_client: T, config: config::Config) -> Self {
-        Self { http_client, config }
+        Self {
+            requester: Requester::new(http_client, &config),
+            config,
+        }
+    }
+
+    pub fn with_audit(mut self, audit: AuditScope) -> Self {
+        self.requester.audit = Some(audit);
+        self
+    }
+
+    pub fn with_monitor(mut self, monitor: DependencyMonitor) -> Self {
+        self.requester.monitor = Some(monitor.scope("stores"));
+        self
     }
 
     fn stores_url(&self) -> String {
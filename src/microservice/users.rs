@@ -8,8 +8,9 @@ use stq_routes::service::Service as StqService;
 use stq_types::enums::UsersRole;
 use stq_types::*;
 
-use super::{ApiFuture, Initiator};
+use super::{ApiFuture, DependencyMonitor, Initiator, Requester};
 
+use audit::AuditScope;
 use config;
 use errors::Error;
 use models::*;
@@ -19,6 +20,7 @@ pub trait UsersMicroservice {
     fn apply_password_reset_token(&self, initiator: Option<Initiator>, payload: PasswordResetApply) -> ApiFuture<ResetApplyToken>;
     fn create_password_reset_token(&self, initiator: Option<Initiator>, payload: ResetRequest) -> ApiFuture<String>;
     fn get_by_email(&self, initiator: Option<Initiator>, email: &str) -> ApiFuture<Option<User>>;
+    fn get_user_by_saga_id(&self, initiator: Option<Initiator>, saga_id: SagaId) -> ApiFuture<Option<User>>;
     fn delete_role(&self, initiator: Option<Initiator>, role_id: RoleId) -> ApiFuture<NewRole<UsersRole>>;
     fn delete_user(&self, initiator: Option<Initiator>, saga_id: SagaId) -> ApiFuture<User>;
     fn create_email_verify_token(&self, initiator: Option<Initiator>, payload: VerifyRequest) -> ApiFuture<String>;
@@ -29,7 +31,7 @@ pub trait UsersMicroservice {
 }
 
 pub struct UsersMicroserviceImpl<T: 'static + HttpClient + Clone> {
-    http_client: T,
+    requester: Requester<T>,
     config: config::Config,
 }
 
@@ -42,158 +44,178 @@ impl<T: 'static + HttpClient + Clone> UsersMicroservice for UsersMicroserviceImp
             payload.token
         );
         Box::new(
-            super::request(self.http_client.clone(), Method::Put, url, Some(payload), initiator.map(Into::into)).map_err(|e| {
-                e.context("Applying email verification token in users microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Put, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Applying email verification token in users microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn apply_password_reset_token(&self, initiator: Option<Initiator>, payload: PasswordResetApply) -> ApiFuture<ResetApplyToken> {
         let url = format!("{}/{}/password_reset_token", self.users_url(), StqModel::User.to_url());
         Box::new(
-            super::request(self.http_client.clone(), Method::Put, url, Some(payload), initiator.map(Into::into)).map_err(|e| {
-                e.context("Applying password reset token in users microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Put, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Applying password reset token in users microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn create_password_reset_token(&self, initiator: Option<Initiator>, payload: ResetRequest) -> ApiFuture<String> {
         let url = format!("{}/{}/password_reset_token", self.users_url(), StqModel::User.to_url());
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Creating password reset token in users microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating password reset token in users microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn get_by_email(&self, initiator: Option<Initiator>, email: &str) -> ApiFuture<Option<User>> {
         let url = format!("{}/{}/by_email?email={}", self.users_url(), StqModel::User.to_url(), email);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Get, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Receiving user from users microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), _>(Method::Get, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Receiving user from users microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn get_user_by_saga_id(&self, initiator: Option<Initiator>, saga_id: SagaId) -> ApiFuture<Option<User>> {
+        let url = format!("{}/user_by_saga_id/{}", self.users_url(), saga_id);
+        Box::new(
+            self.requester
+                .request::<(), _>(Method::Get, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Receiving user by saga id from users microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn delete_role(&self, initiator: Option<Initiator>, role_id: RoleId) -> ApiFuture<NewRole<UsersRole>> {
         let url = format!("{}/roles/by-id/{}", self.users_url(), role_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deleting role in users microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), _>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting role in users microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn delete_user(&self, initiator: Option<Initiator>, saga_id: SagaId) -> ApiFuture<User> {
         let url = format!("{}/user_by_saga_id/{}", self.users_url(), saga_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deleting user in users microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), _>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting user in users microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn create_email_verify_token(&self, initiator: Option<Initiator>, payload: VerifyRequest) -> ApiFuture<String> {
         let url = format!("{}/{}/email_verify_token", self.users_url(), StqModel::User.to_url());
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Creating email verify token in users microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating email verify token in users microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn create_role(&self, initiator: Option<Initiator>, payload: NewRole<UsersRole>) -> ApiFuture<NewRole<UsersRole>> {
         let url = format!("{}/{}", self.users_url(), StqModel::Role.to_url());
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Creating role in users microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating role in users microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn create_user(&self, initiator: Option<Initiator>, payload: SagaCreateProfile) -> ApiFuture<User> {
         let url = format!("{}/{}", self.users_url(), StqModel::User.to_url());
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Creating user in users microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating user in users microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn get(&self, initiator: Option<Initiator>, user_id: UserId) -> ApiFuture<Option<User>> {
         let url = format!("{}/{}/{}", self.users_url(), StqModel::User.to_url(), user_id);
+        let requester = self.requester.clone();
         Box::new(
-            super::request::<_, (), Option<User>>(self.http_client.clone(), Method::Get, url, None, initiator.map(Into::into)).map_err(
-                |e| {
-                    e.context("Getting user in users microservice failed.")
-                        .context(Error::HttpClient)
-                        .into()
-                },
-            ),
+            super::retry_lookup(&self.config.saga.lookup_retries, move || {
+                Box::new(requester.request::<(), Option<User>>(Method::Get, url.clone(), None, initiator.map(Into::into))) as ApiFuture<_>
+            })
+            .map_err(|e| {
+                e.context("Getting user in users microservice failed.")
+                    .context(Error::HttpClient)
+                    .into()
+            }),
         )
     }
 
     fn update_user(&self, initiator: Option<Initiator>, user_id: UserId, payload: UpdateUser) -> ApiFuture<User> {
         let url = format!("{}/{}/{}", self.users_url(), StqModel::User.to_url(), user_id);
         Box::new(
-            super::request(self.http_client.clone(), Method::Put, url, Some(payload), initiator.map(Into::into)).map_err(|e| {
-                e.context("Updating user in users microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Put, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Updating user in users microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 }
 
 impl<T: 'static + HttpClient + Clone> UsersMicroserviceImpl<T> {
     pub fn new(http_client: T, config: config::Config) -> Self {
-        Self { http_client, config }
+        Self {
+            requester: Requester::new(http_client, &config),
+            config,
+        }
+    }
+
+    pub fn with_audit(mut self, audit: AuditScope) -> Self {
+        self.requester.audit = Some(audit);
+        self
+    }
+
+    pub fn with_monitor(mut self, monitor: DependencyMonitor) -> Self {
+        self.requester.monitor = Some(monitor.scope("users"));
+        self
     }
 
     fn users_url(&self) -> String {
@@ -8,8 +8,9 @@ use stq_routes::model::Model as StqModel;
 use stq_routes::service::Service as StqService;
 use stq_types::*;
 
-use super::{ApiFuture, Initiator};
+use super::{ApiFuture, DependencyMonitor, Initiator, Requester};
 
+use audit::AuditScope;
 use config;
 use errors::Error;
 use models::*;
@@ -29,11 +30,15 @@ pub trait WarehousesMicroservice {
         product_id: ProductId,
         quantity: Quantity,
     ) -> ApiFuture<Stock>;
+    /// Changes stock of the product in its warehouse atomically, adjustments are idempotent by saga and kind
+    fn adjust_stock(&self, initiator: Initiator, product_id: ProductId, adjustment: StockAdjustment) -> ApiFuture<Stock>;
+    /// Adjustments of stock of the product applied by the saga
+    fn find_stock_adjustments(&self, initiator: Initiator, product_id: ProductId, saga_id: SagaId) -> ApiFuture<Vec<StockAdjustment>>;
     fn find_by_store_id(&self, initiator: Option<Initiator>, store_id: StoreId) -> ApiFuture<Vec<Warehouse>>;
 }
 
 pub struct WarehousesMicroserviceImpl<T: 'static + HttpClient + Clone> {
-    http_client: T,
+    requester: Requester<T>,
     config: config::Config,
 }
 
@@ -41,11 +46,13 @@ impl<T: 'static + HttpClient + Clone> WarehousesMicroservice for WarehousesMicro
     fn delete_warehouse_role(&self, initiator: Option<Initiator>, role_id: RoleEntryId) -> ApiFuture<RoleEntry<NewWarehouseRole>> {
         let url = format!("{}/roles/by-id/{}", self.warehouses_url(), role_id);
         Box::new(
-            super::request::<_, (), _>(self.http_client.clone(), Method::Delete, url, None, initiator.map(Into::into)).map_err(|e| {
-                e.context("Deleting role in warehouses microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), _>(Method::Delete, url, None, initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Deleting role in warehouses microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
@@ -56,18 +63,13 @@ impl<T: 'static + HttpClient + Clone> WarehousesMicroservice for WarehousesMicro
     ) -> ApiFuture<RoleEntry<NewWarehouseRole>> {
         let url = format!("{}/{}", self.warehouses_url(), StqModel::Role.to_url());
         Box::new(
-            super::request(
-                self.http_client.clone(),
-                Method::Post,
-                url,
-                Some(payload),
-                initiator.map(Into::into),
-            )
-            .map_err(|e| {
-                e.context("Creating role in warehouses microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request(Method::Post, url, Some(payload), initiator.map(Into::into))
+                .map_err(|e| {
+                    e.context("Creating role in warehouses microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
     fn set_product_in_warehouse(
@@ -85,36 +87,65 @@ impl<T: 'static + HttpClient + Clone> WarehousesMicroservice for WarehousesMicro
         );
 
         Box::new(
-            super::request::<_, StockSetPayload, Stock>(
-                self.http_client.clone(),
-                Method::Put,
-                url,
-                Some(StockSetPayload { quantity }),
-                Some(initiator.into()),
-            )
-            .map_err(|e| {
-                e.context("Setting product quantity in warehouses microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<StockSetPayload, Stock>(Method::Put, url, Some(StockSetPayload { quantity }), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Setting product quantity in warehouses microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn adjust_stock(&self, initiator: Initiator, product_id: ProductId, adjustment: StockAdjustment) -> ApiFuture<Stock> {
+        let url = format!("{}/stocks/by-product-id/{}/adjustments", self.warehouses_url(), product_id);
+        Box::new(
+            self.requester
+                .request::<StockAdjustment, Stock>(Method::Post, url, Some(adjustment), Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Adjusting product stock in warehouses microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
+        )
+    }
+
+    fn find_stock_adjustments(&self, initiator: Initiator, product_id: ProductId, saga_id: SagaId) -> ApiFuture<Vec<StockAdjustment>> {
+        let url = format!(
+            "{}/stocks/by-product-id/{}/adjustments/by-saga-id/{}",
+            self.warehouses_url(),
+            product_id,
+            saga_id.0
+        );
+        Box::new(
+            self.requester
+                .request::<(), Vec<StockAdjustment>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Getting stock adjustments by saga id in warehouses microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn find_by_product_id(&self, initiator: Initiator, product_id: ProductId) -> ApiFuture<Vec<Stock>> {
         let url = format!("{}/stocks/by-product-id/{}", self.warehouses_url(), product_id);
         Box::new(
-            super::request::<_, (), Vec<Stock>>(self.http_client.clone(), Method::Get, url, None, Some(initiator.into())).map_err(|e| {
-                e.context("Find stocks in warehouses microservice failed.")
-                    .context(Error::HttpClient)
-                    .into()
-            }),
+            self.requester
+                .request::<(), Vec<Stock>>(Method::Get, url, None, Some(initiator.into()))
+                .map_err(|e| {
+                    e.context("Find stocks in warehouses microservice failed.")
+                        .context(Error::HttpClient)
+                        .into()
+                }),
         )
     }
 
     fn find_by_store_id(&self, initiator: Option<Initiator>, store_id: StoreId) -> ApiFuture<Vec<Warehouse>> {
         let url = format!("{}/warehouses/by-store/{}", self.warehouses_url(), store_id);
         Box::new(
-            super::request::<_, (), Vec<Warehouse>>(self.http_client.clone(), Method::Get, url, None, initiator.map(Initiator::into))
+            self.requester
+                .request::<(), Vec<Warehouse>>(Method::Get, url, None, initiator.map(Initiator::into))
                 .map_err(|e| {
                     e.context("Find warehouses in warehouses microservice failed.")
                         .context(Error::HttpClient)
@@ -126,7 +157,20 @@ impl<T: 'static + HttpClient + Clone> WarehousesMicroservice for WarehousesMicro
 
 impl<T: 'static + HttpClient + Clone> WarehousesMicroserviceImpl<T> {
     pub fn new(http_client: T, config: config::Config) -> Self {
-        Self { http_client, config }
+        Self {
+            requester: Requester::new(http_client, &config),
+            config,
+        }
+    }
+
+    pub fn with_audit(mut self, audit: AuditScope) -> Self {
+        self.requester.audit = Some(audit);
+        self
+    }
+
+    pub fn with_monitor(mut self, monitor: DependencyMonitor) -> Self {
+        self.requester.monitor = Some(monitor.scope("warehouses"));
+        self
     }
 
     fn warehouses_url(&self) -> String {
@@ -0,0 +1,13 @@
+use chrono::{DateTime, Utc};
+
+/// Build and versions of the running coordinator
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct About {
+    pub version: String,
+    pub git_commit: Option<String>,
+    pub built_at: Option<DateTime<Utc>>,
+    /// Optional saga steps enabled in config
+    pub features: Vec<String>,
+    /// Schema version saga log is persisted with
+    pub saga_log_schema_version: u64,
+}
@@ -0,0 +1,57 @@
+use std::time::{Duration, SystemTime};
+
+use stq_static_resources::EmailUser;
+use stq_types::{OrderId, OrderSlug, StoreId};
+
+/// Paid order awaiting acknowledgment by its store
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingAcknowledgment {
+    pub order_id: OrderId,
+    pub order_slug: OrderSlug,
+    pub store_id: StoreId,
+    pub paid_at: SystemTime,
+    /// Time the store was nudged about the order
+    pub nudged_at: Option<SystemTime>,
+    /// Time moderators were told about the order
+    pub escalated_at: Option<SystemTime>,
+}
+
+impl PendingAcknowledgment {
+    pub fn new(order_id: OrderId, order_slug: OrderSlug, store_id: StoreId) -> Self {
+        Self {
+            order_id,
+            order_slug,
+            store_id,
+            paid_at: SystemTime::now(),
+            nudged_at: None,
+            escalated_at: None,
+        }
+    }
+
+    /// Whether order awaits acknowledgment longer than `sla`
+    pub fn is_overdue(&self, sla: Duration, now: SystemTime) -> bool {
+        now.duration_since(self.paid_at).map(|waiting| waiting > sla).unwrap_or(false)
+    }
+}
+
+/// Email nudging store to take paid order into processing
+#[derive(Serialize)]
+pub struct OrderAcknowledgmentOverdueForStore {
+    pub store_email: String,
+    pub store_id: String,
+    pub order_slug: String,
+    /// Hours since the order was paid
+    pub hours_waiting: u64,
+    pub cluster_url: String,
+}
+
+/// Email telling moderator about paid order its store has not acknowledged
+#[derive(Serialize)]
+pub struct OrderAcknowledgmentOverdueForModerator {
+    pub user: EmailUser,
+    pub store_id: String,
+    pub order_slug: String,
+    /// Hours since the order was paid
+    pub hours_waiting: u64,
+    pub cluster_url: String,
+}
@@ -0,0 +1,39 @@
+use std::time::SystemTime;
+
+use stq_types::SagaId;
+
+use super::LegalHold;
+
+/// Call to microservice made with superadmin rights, or legal hold put on store or released
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub method: String,
+    pub url: String,
+    /// Saga the call was made in, `None` for calls outside of sagas
+    pub saga_id: Option<SagaId>,
+    /// Request or job the call was made for
+    pub reason: String,
+    pub recorded_at: SystemTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub legal_hold: Option<LegalHold>,
+}
+
+impl AuditEntry {
+    pub fn legal_hold(saga_id: SagaId, reason: String, legal_hold: LegalHold) -> Self {
+        Self {
+            method: "LEGAL_HOLD".to_string(),
+            url: format!("/stores/{}", legal_hold.store_id),
+            saga_id: Some(saga_id),
+            reason,
+            recorded_at: SystemTime::now(),
+            legal_hold: Some(legal_hold),
+        }
+    }
+}
+
+/// Filter of audit entries requested by admin
+#[derive(Clone, Debug, Default)]
+pub struct AuditQuery {
+    pub saga_id: Option<SagaId>,
+    pub limit: Option<usize>,
+}
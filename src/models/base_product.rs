@@ -1,6 +1,8 @@
 use stq_static_resources::Currency;
 use stq_types::{CategoryId, Quantity, StoreId};
 
+use super::EntityVersion;
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct UpdateBaseProduct {
     pub name: Option<serde_json::Value>,
@@ -15,6 +17,34 @@ pub struct UpdateBaseProduct {
     pub width_cm: Option<i32>,
     pub height_cm: Option<i32>,
     pub weight_g: Option<i32>,
+    /// Version of the base product the change is made against, also given with `If-Match`
+    #[serde(default, skip_serializing)]
+    pub version: Option<EntityVersion>,
+}
+
+impl UpdateBaseProduct {
+    /// Names of fields changed by the update
+    pub fn edited_fields(&self) -> Vec<String> {
+        let fields = [
+            ("name", self.name.is_some()),
+            ("short_description", self.short_description.is_some()),
+            ("long_description", self.long_description.is_some()),
+            ("seo_title", self.seo_title.is_some()),
+            ("seo_description", self.seo_description.is_some()),
+            ("currency", self.currency.is_some()),
+            ("category_id", self.category_id.is_some()),
+            ("slug", self.slug.is_some()),
+            ("length_cm", self.length_cm.is_some()),
+            ("width_cm", self.width_cm.is_some()),
+            ("height_cm", self.height_cm.is_some()),
+            ("weight_g", self.weight_g.is_some()),
+        ];
+        fields
+            .iter()
+            .filter(|&&(_, is_edited)| is_edited)
+            .map(|&(field, _)| field.to_string())
+            .collect()
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -64,3 +94,29 @@ pub struct ProdAttrValue {
     pub value: String,
     pub meta_field: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_payload_round_trip;
+
+    #[test]
+    fn base_product_payloads_round_trip() {
+        assert_payload_round_trip::<NewBaseProductWithVariants>(json!({
+            "uuid": "a2b3c4d5-0000-4000-8000-000000000001",
+            "name": [{"lang": "en", "text": "Phone"}],
+            "store_id": 1,
+            "short_description": [{"lang": "en", "text": "Phone"}],
+            "currency": "stq",
+            "category_id": 12,
+            "variants": [{
+                "product": {"photo_main": "https://example.com/photo.png", "vendor_code": "A-1", "price": 10.5},
+                "attributes": [{"attr_id": 1, "value": "red"}],
+                "quantity": 5
+            }],
+            "selected_attributes": [1],
+            "weight_g": 500
+        }));
+        assert_payload_round_trip::<UpdateBaseProduct>(json!({"name": [{"lang": "en", "text": "Phone"}], "category_id": 12}));
+    }
+}
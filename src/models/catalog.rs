@@ -0,0 +1,300 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde_json::{self, Value};
+
+use stq_static_resources::Currency;
+use stq_types::{BaseProductId, Quantity, SagaId, StoreId};
+
+use super::{
+    CreateProductWithAttributes, NewBaseProductWithVariants, NewProduct, NewShipping, SagaRecord, SagaStage, SagaStatus, SagaType,
+};
+
+/// Language of translations given in csv catalogs without `lang` column
+pub const DEFAULT_CATALOG_LANG: &str = "en";
+
+/// Base product of imported catalog, it is created in the store catalog is imported to
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CatalogBaseProduct {
+    /// Generated if not set
+    #[serde(default)]
+    pub uuid: Option<String>,
+    pub name: Vec<Value>,
+    pub short_description: Vec<Value>,
+    #[serde(default)]
+    pub long_description: Option<Vec<Value>>,
+    #[serde(default)]
+    pub seo_title: Option<Vec<Value>>,
+    #[serde(default)]
+    pub seo_description: Option<Vec<Value>>,
+    pub currency: Currency,
+    pub category_id: i32,
+    #[serde(default)]
+    pub slug: Option<String>,
+    pub variants: Vec<CreateProductWithAttributes>,
+    #[serde(default)]
+    pub selected_attributes: Vec<i32>,
+    #[serde(default)]
+    pub length_cm: Option<i32>,
+    #[serde(default)]
+    pub width_cm: Option<i32>,
+    #[serde(default)]
+    pub height_cm: Option<i32>,
+    #[serde(default)]
+    pub weight_g: Option<i32>,
+}
+
+impl CatalogBaseProduct {
+    pub fn into_new_base_product(self, store_id: StoreId, uuid: String) -> NewBaseProductWithVariants {
+        NewBaseProductWithVariants {
+            uuid,
+            name: self.name,
+            store_id,
+            short_description: self.short_description,
+            long_description: self.long_description,
+            seo_title: self.seo_title,
+            seo_description: self.seo_description,
+            currency: self.currency,
+            category_id: self.category_id,
+            slug: self.slug,
+            variants: self.variants,
+            selected_attributes: self.selected_attributes,
+            length_cm: self.length_cm,
+            width_cm: self.width_cm,
+            height_cm: self.height_cm,
+            weight_g: self.weight_g,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CatalogRow {
+    pub base_product: CatalogBaseProduct,
+    /// Shipping of base product is left unset if not given
+    #[serde(default)]
+    pub shipping: Option<NewShipping>,
+}
+
+impl CatalogRow {
+    /// Row of csv catalog, keyed by column names. Every csv row is a base product with a single variant,
+    /// shipping can not be set in csv catalogs.
+    pub fn from_csv(record: &HashMap<String, String>) -> Result<Self, String> {
+        let lang = optional(record, "lang").unwrap_or(DEFAULT_CATALOG_LANG);
+        let translation = |text: &str| vec![json!({"lang": lang, "text": text})];
+
+        let currency = serde_json::from_value::<Currency>(Value::String(required(record, "currency")?.to_string()))
+            .map_err(|_| "Invalid value of column currency".to_string())?;
+
+        let product = NewProduct {
+            uuid: None,
+            base_product_id: None,
+            discount: parse_optional(record, "discount")?,
+            photo_main: optional(record, "photo_main").map(str::to_string),
+            additional_photos: None,
+            vendor_code: required(record, "vendor_code")?.to_string(),
+            cashback: parse_optional(record, "cashback")?,
+            price: parse_required(record, "price")?,
+            pre_order: None,
+            pre_order_days: None,
+        };
+
+        Ok(Self {
+            base_product: CatalogBaseProduct {
+                uuid: optional(record, "uuid").map(str::to_string),
+                name: translation(required(record, "name")?),
+                short_description: translation(required(record, "short_description")?),
+                long_description: optional(record, "long_description").map(&translation),
+                seo_title: None,
+                seo_description: None,
+                currency,
+                category_id: parse_required(record, "category_id")?,
+                slug: optional(record, "slug").map(str::to_string),
+                variants: vec![CreateProductWithAttributes {
+                    product,
+                    attributes: vec![],
+                    quantity: parse_optional::<i32>(record, "quantity")?.map(Quantity),
+                }],
+                selected_attributes: vec![],
+                length_cm: parse_optional(record, "length_cm")?,
+                width_cm: parse_optional(record, "width_cm")?,
+                height_cm: parse_optional(record, "height_cm")?,
+                weight_g: parse_optional(record, "weight_g")?,
+            },
+            shipping: None,
+        })
+    }
+}
+
+/// Value of csv column, empty values are treated as absent
+fn optional<'a>(record: &'a HashMap<String, String>, column: &str) -> Option<&'a str> {
+    record.get(column).map(|value| value.trim()).filter(|value| !value.is_empty())
+}
+
+fn required<'a>(record: &'a HashMap<String, String>, column: &str) -> Result<&'a str, String> {
+    optional(record, column).ok_or_else(|| format!("Column {} is required", column))
+}
+
+fn parse_optional<T: FromStr>(record: &HashMap<String, String>, column: &str) -> Result<Option<T>, String> {
+    match optional(record, column) {
+        Some(value) => value
+            .parse::<T>()
+            .map(Some)
+            .map_err(|_| format!("Invalid value of column {}", column)),
+        None => Ok(None),
+    }
+}
+
+fn parse_required<T: FromStr>(record: &HashMap<String, String>, column: &str) -> Result<T, String> {
+    parse_optional(record, column)?.ok_or_else(|| format!("Column {} is required", column))
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CatalogRowStatus {
+    Imported,
+    /// Nothing of the row is left in stores, delivery and warehouses
+    Failed,
+}
+
+/// Outcome of import of a single catalog row, rows are numbered from zero
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CatalogRowResult {
+    pub row: usize,
+    pub status: CatalogRowStatus,
+    pub base_product_id: Option<BaseProductId>,
+    pub error: Option<String>,
+}
+
+/// Response to accepted import, the import itself runs in background
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CatalogImportAccepted {
+    pub saga_id: SagaId,
+    pub rows: usize,
+    /// Path of import report, it lists rows imported so far
+    pub report_url: String,
+}
+
+/// Input of catalog import saga, kept in saga store to resume the import after restart or on another replica
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CatalogImportInput {
+    pub store_id: StoreId,
+    pub rows: Vec<CatalogRow>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CatalogImportReport {
+    pub saga_id: SagaId,
+    pub status: SagaStatus,
+    pub rows: Vec<CatalogRowResult>,
+    pub error: Option<String>,
+}
+
+impl CatalogImportReport {
+    /// Report of catalog import saga, `None` if the saga is of another type
+    pub fn from_record(record: &SagaRecord) -> Option<Self> {
+        if record.saga_type != SagaType::CatalogImport {
+            return None;
+        }
+
+        let rows = record
+            .stages
+            .iter()
+            .filter(|entry| match entry.stage {
+                SagaStage::CatalogImport(CatalogImportOperationStage::RowImported(_)) => true,
+                _ => false,
+            })
+            .filter_map(|entry| entry.result.clone())
+            .filter_map(|result| serde_json::from_value::<CatalogRowResult>(result).ok())
+            .collect();
+
+        Some(Self {
+            saga_id: record.id,
+            status: record.status,
+            rows,
+            error: record.last_error.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CatalogImportOperationStage {
+    /// Keeps uuid of base product, base product is looked up by it in `BaseProductCreationComplete`
+    BaseProductCreationStart(String),
+    BaseProductCreationComplete(String, BaseProductId),
+    ShippingUpsertStart(BaseProductId),
+    ShippingUpsertComplete(BaseProductId),
+    StockSetStart(StoreId, BaseProductId),
+    StockSetComplete(BaseProductId),
+    /// Leftovers of failed row were removed right away, they are not reverted along with the saga
+    BaseProductCleanupComplete(BaseProductId),
+    /// Carries result of the row, progress of import is reported from these
+    RowImported(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_payload_round_trip;
+
+    fn record(columns: &[(&str, &str)]) -> HashMap<String, String> {
+        columns
+            .iter()
+            .map(|&(column, value)| (column.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn converts_csv_record_into_single_variant_base_product() {
+        let row = CatalogRow::from_csv(&record(&[
+            ("name", "Mug"),
+            ("short_description", "Ceramic mug"),
+            ("currency", "stq"),
+            ("category_id", "12"),
+            ("vendor_code", "MUG-1"),
+            ("price", "10.5"),
+            ("quantity", "3"),
+            ("slug", ""),
+        ]))
+        .unwrap();
+
+        assert_eq!(row.base_product.name, vec![json!({"lang": "en", "text": "Mug"})]);
+        assert_eq!(row.base_product.category_id, 12);
+        assert_eq!(row.base_product.slug, None);
+        assert_eq!(row.base_product.variants.len(), 1);
+        assert_eq!(row.base_product.variants[0].product.price, 10.5);
+        assert_eq!(row.base_product.variants[0].quantity.map(|quantity| quantity.0), Some(3));
+    }
+
+    #[test]
+    fn rejects_csv_record_with_invalid_columns() {
+        let columns = [
+            ("name", "Mug"),
+            ("short_description", "Ceramic mug"),
+            ("currency", "stq"),
+            ("category_id", "twelve"),
+            ("vendor_code", "MUG-1"),
+        ];
+        assert_eq!(
+            CatalogRow::from_csv(&record(&columns)).unwrap_err(),
+            "Invalid value of column category_id".to_string()
+        );
+        assert_eq!(
+            CatalogRow::from_csv(&record(&columns[..2])).unwrap_err(),
+            "Column currency is required".to_string()
+        );
+    }
+
+    #[test]
+    fn catalog_row_round_trip() {
+        assert_payload_round_trip::<CatalogRow>(json!({
+            "base_product": {
+                "name": [{"lang": "en", "text": "Phone"}],
+                "short_description": [{"lang": "en", "text": "Phone"}],
+                "currency": "stq",
+                "category_id": 12,
+                "slug": "phone",
+                "variants": [{"product": {"vendor_code": "A-1", "price": 10.5}, "attributes": [], "quantity": 5}]
+            }
+        }));
+    }
+}
@@ -0,0 +1,38 @@
+use stq_types::{BaseProductId, CategoryId};
+
+use super::EntityVersion;
+
+/// Payload of category change of base product
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChangeCategoryInput {
+    pub category_id: CategoryId,
+    /// Version of the base product the change is made against, also given with `If-Match`
+    #[serde(default, skip_serializing)]
+    pub version: Option<EntityVersion>,
+}
+
+/// Payload recalculating packages of base product applicable to its category
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RecalculateShipping {
+    pub category_id: CategoryId,
+}
+
+/// Start stages keep the category the base product had before the change, to be restored by compensation.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeCategoryOperationStage {
+    CategoryUpdateStart(BaseProductId, CategoryId),
+    CategoryUpdateComplete(BaseProductId),
+    ShippingRecalculationStart(BaseProductId, CategoryId),
+    ShippingRecalculationComplete(BaseProductId),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_payload_round_trip;
+
+    #[test]
+    fn change_category_round_trip() {
+        assert_payload_round_trip::<ChangeCategoryInput>(json!({"category_id": 3}));
+    }
+}
@@ -3,19 +3,26 @@ use std::fmt;
 use std::time::SystemTime;
 
 use uuid::Uuid;
+use validator::Validate;
 
 use stq_api::orders::{AddressFull, CouponInfo, DeliveryInfo, Order, ProductInfo};
-use stq_static_resources::{CommitterRole, Currency, CurrencyType, OrderState};
+use stq_static_resources::{CommitterRole, Currency, CurrencyType, EmailUser, OrderState};
 use stq_types::*;
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+use super::unknown_fields::UnknownFields;
+use super::validation::{validate_phone, validate_prices};
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Validate)]
 pub struct ConvertCart {
     pub customer_id: UserId,
     #[serde(flatten)]
     pub address: AddressFull,
     pub receiver_name: String,
+    #[validate(custom = "validate_phone")]
     pub receiver_phone: String,
+    #[validate(email)]
     pub receiver_email: String,
+    #[validate(custom = "validate_prices")]
     pub prices: CartProductWithPriceHash,
     pub currency: Currency,
     pub coupons: HashMap<CouponId, CouponInfo>,
@@ -23,19 +30,27 @@ pub struct ConvertCart {
     pub product_info: HashMap<ProductId, ProductInfo>,
     pub uuid: Uuid,
     pub currency_type: Option<CurrencyType>,
+    /// Gift cards and store credit codes applied to the whole checkout
+    #[serde(default)]
+    pub gift_card_codes: Vec<String>,
+    /// Fields unknown to both the checkout and the address, must stay after `address`
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Validate)]
 pub struct BuyNow {
     pub product_id: ProductId,
     pub customer_id: UserId,
     pub store_id: StoreId,
     pub address: AddressFull,
     pub receiver_name: String,
+    #[validate(email)]
     pub receiver_email: String,
     pub price: ProductSellerPrice,
     pub quantity: Quantity,
     pub currency: Currency,
+    #[validate(custom = "validate_phone")]
     pub receiver_phone: String,
     pub pre_order: bool,
     pub pre_order_days: i32,
@@ -72,6 +87,12 @@ pub struct CreateInvoice {
     pub customer_id: UserId,
     pub saga_id: SagaId,
     pub currency: Currency,
+    /// Gift cards reserved for the invoice, their total is discounted from invoice amount
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gift_cards: Option<GiftCardReservation>,
+    /// Tax lines per order, only set when tax calculation is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taxes: Option<Vec<OrderTaxes>>,
 }
 
 impl fmt::Display for CreateInvoice {
@@ -86,7 +107,49 @@ impl fmt::Display for CreateInvoice {
 
 pub type CartProductWithPriceHash = HashMap<ProductId, ProductSellerPrice>;
 
-pub type CreateOrderOperationLog = Vec<CreateOrderOperationStage>;
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReserveGiftCards {
+    pub codes: Vec<String>,
+    pub customer_id: UserId,
+    pub currency: Currency,
+    pub saga_id: SagaId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReservedGiftCard {
+    pub code: String,
+    pub amount: ProductPrice,
+}
+
+/// Gift cards held by billing for a checkout until the invoice is paid or the reservation is released
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GiftCardReservation {
+    pub saga_id: SagaId,
+    pub cards: Vec<ReservedGiftCard>,
+    pub total: ProductPrice,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CalculateTaxes {
+    pub customer_id: UserId,
+    pub currency: Currency,
+    pub address: AddressFull,
+    pub orders: Vec<Order>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaxLine {
+    pub name: String,
+    /// Fraction of taxable amount, e.g. `0.2` for 20%
+    pub rate: f64,
+    pub amount: ProductPrice,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderTaxes {
+    pub order_id: OrderId,
+    pub lines: Vec<TaxLine>,
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BillingOrders {
@@ -104,12 +167,18 @@ pub struct OrdersCartItemInfo {
 
 pub type CartHash = BTreeMap<i32, OrdersCartItemInfo>;
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CreateOrderOperationStage {
     OrdersConvertCartStart(ConversionId),
     OrdersConvertCartComplete(ConversionId),
     BillingCreateInvoiceStart(SagaId),
     BillingCreateInvoiceComplete(SagaId),
+    BillingReserveGiftCardsStart(SagaId),
+    BillingReserveGiftCardsComplete(SagaId),
+    BillingCalculateTaxesStart(SagaId),
+    BillingCalculateTaxesComplete(SagaId),
+    FraudScreeningStart(SagaId),
+    FraudScreeningComplete(SagaId),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -132,6 +201,39 @@ impl fmt::Display for BillingOrderInfo {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BillingOrdersVec(pub Vec<BillingOrderInfo>);
+
+/// Order states received from billing in a single call, identified with `X-Request-Id` of the call
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BillingEvent {
+    pub id: String,
+    pub received_at: SystemTime,
+    pub orders: Vec<BillingOrderInfo>,
+}
+
+/// Either id of recently received billing event or order states to apply
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BillingReplayInput {
+    pub event_id: Option<String>,
+    pub orders: Option<Vec<BillingOrderInfo>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BillingReplayReport {
+    pub event_id: Option<String>,
+    pub received: usize,
+    /// Orders whose state was changed, the rest already were in the received state
+    pub updated: Vec<OrderId>,
+}
+
+/// Outcome of order states streamed from billing. States are applied batch by batch in order of the array,
+/// so if a later element fails to parse or to apply, the first `applied` elements stay applied.
+/// The rest may be sent once again, orders already in the received state are left as is
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BillingStreamReport {
+    pub applied: usize,
+    /// Failure that stopped the stream after some elements had been applied
+    pub error: Option<String>,
+}
 impl fmt::Display for BillingOrdersVec {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let comma_separated = self.0.iter().fold("".to_string(), |acc, i| format!("{}, {}", acc, i));
@@ -139,6 +241,14 @@ impl fmt::Display for BillingOrdersVec {
     }
 }
 
+/// Order state set with `POST /orders/<slug>/set_state`, committer role is derived from the caller
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SetStateInput {
+    pub state: OrderState,
+    pub track_id: Option<String>,
+    pub comment: Option<String>,
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct UpdateStatePayload {
     pub state: OrderState,
@@ -178,6 +288,26 @@ pub struct Invoice {
     pub amount_captured: ProductPrice,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiptFormat {
+    Pdf,
+    Html,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NewReceipt {
+    pub format: ReceiptFormat,
+}
+
+/// Receipt of invoice generated by billing, receipt of the same invoice and format is generated once
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Receipt {
+    pub invoice_id: InvoiceId,
+    pub format: ReceiptFormat,
+    pub url: String,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Transaction {
     pub id: String,
@@ -212,6 +342,28 @@ pub struct DeleteProductsFromCartsPayload {
     pub product_ids: Vec<ProductId>,
 }
 
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DeleteProductsFromWishlistsPayload {
+    pub product_ids: Vec<ProductId>,
+}
+
+/// Result of saga along with numbers of products it cleaned up in carts of customers
+#[derive(Clone, Debug, Serialize)]
+pub struct CartsCleanupResult<T> {
+    #[serde(flatten)]
+    pub result: T,
+    pub removed_from_carts: usize,
+    pub cleared_delivery_methods: usize,
+}
+
+/// Products removed from cart of the user since they are no longer available
+#[derive(Serialize)]
+pub struct CartProductsUnavailableForUser {
+    pub user: EmailUser,
+    pub product_ids: Vec<ProductId>,
+    pub cluster_url: String,
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct DeleteDeliveryMethodFromCartsPayload {
     pub product_ids: Vec<ProductId>,
@@ -253,7 +405,7 @@ pub struct OrderPaymentStateRequest {
     pub state: PaymentState,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Eq, PartialEq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum PaymentState {
     /// Order created and maybe paid by customer
@@ -271,3 +423,21 @@ pub enum PaymentState {
     /// Need money payment to seller
     PaymentToSellerNeeded,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::{assert_model_round_trip, assert_payload_round_trip};
+
+    #[test]
+    fn order_payloads_round_trip() {
+        assert_model_round_trip(&UpdateStatePayload {
+            state: OrderState::Sent,
+            track_id: Some("TR1".to_string()),
+            comment: None,
+            committer_role: CommitterRole::Seller,
+        });
+        assert_payload_round_trip::<SetStateInput>(json!({"state": "sent", "track_id": "TR1"}));
+        assert_payload_round_trip::<BillingReplayInput>(json!({"event_id": "a1"}));
+    }
+}
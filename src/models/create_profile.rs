@@ -3,10 +3,13 @@ use std::time::SystemTime;
 
 use chrono::NaiveDate;
 use uuid::Uuid;
+use validator::{self, Validate, ValidationError, ValidationErrors};
 
 use stq_static_resources::{Device, Gender, Project, Provider};
 use stq_types::{Alpha3, EmarsysId, MerchantId, RoleId, SagaId, UserId};
 
+use super::validation::validate_phone;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct User {
     pub id: UserId,
@@ -90,6 +93,26 @@ pub struct SagaCreateProfile {
     pub project: Option<Project>,
 }
 
+impl Validate for SagaCreateProfile {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if !validator::validate_email(&self.identity.email) {
+            errors.add("email", ValidationError::new("email"));
+        }
+        if let Some(phone) = self.user.as_ref().and_then(|user| user.phone.as_ref()) {
+            if let Err(e) = validate_phone(phone) {
+                errors.add("phone", e);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
 impl fmt::Display for SagaCreateProfile {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "SagaCreateProfile - user: {:#?}, identity: {})", self.user, self.identity)
@@ -146,9 +169,7 @@ pub struct PasswordResetApply {
     pub project: Option<Project>,
 }
 
-pub type CreateProfileOperationLog = Vec<CreateProfileOperationStage>;
-
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CreateProfileOperationStage {
     AccountCreationStart(SagaId),
     AccountCreationComplete(SagaId),
@@ -163,3 +184,31 @@ pub enum CreateProfileOperationStage {
     BillingCreateMerchantStart(UserId),
     BillingCreateMerchantComplete(UserId),
 }
+
+#[cfg(test)]
+mod tests {
+    use stq_static_resources::Provider;
+    use stq_types::SagaId;
+
+    use super::*;
+    use models::round_trip::{assert_model_round_trip, assert_payload_round_trip};
+
+    #[test]
+    fn account_payloads_round_trip() {
+        assert_model_round_trip(&SagaCreateProfile {
+            user: None,
+            identity: NewIdentity {
+                email: "user@example.com".to_string(),
+                password: Some("secret".to_string()),
+                provider: Provider::Email,
+                saga_id: SagaId::new(),
+            },
+            device: None,
+            project: None,
+        });
+        assert_payload_round_trip::<ResetRequest>(json!({"email": "user@example.com", "uuid": "a2b3c4d5-0000-4000-8000-000000000001"}));
+        assert_payload_round_trip::<VerifyRequest>(json!({"email": "user@example.com"}));
+        assert_payload_round_trip::<EmailVerifyApply>(json!({"token": "token"}));
+        assert_payload_round_trip::<PasswordResetApply>(json!({"token": "token", "password": "secret"}));
+    }
+}
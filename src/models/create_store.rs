@@ -2,10 +2,13 @@ use std::time::SystemTime;
 
 use serde_json;
 use uuid::Uuid;
+use validator::Validate;
 
 use stq_static_resources::ModerationStatus;
 use stq_types::{RoleEntryId, RoleId, SagaId, StoreId, UserId};
 
+use super::validation::{validate_phone, validate_slug};
+
 /// Payload for querying stores
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Store {
@@ -44,16 +47,19 @@ pub struct Store {
     pub place_id: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Validate)]
 pub struct NewStore {
     pub name: serde_json::Value,
     pub user_id: UserId,
     pub short_description: serde_json::Value,
     pub long_description: Option<serde_json::Value>,
+    #[validate(custom = "validate_slug")]
     pub slug: String,
     pub cover: Option<String>,
     pub logo: Option<String>,
+    #[validate(custom = "validate_phone")]
     pub phone: Option<String>,
+    #[validate(email)]
     pub email: Option<String>,
     pub address: Option<String>,
     pub facebook_url: Option<String>,
@@ -75,15 +81,61 @@ pub struct NewStore {
     pub uuid: Uuid,
 }
 
+impl NewStore {
+    /// Existing active store of the same user with the same slug or name in any language
+    pub fn is_likely_duplicate_of(&self, store: &Store) -> bool {
+        store.is_active
+            && store.user_id == self.user_id
+            && (normalize_name(&store.slug) == normalize_name(&self.slug) || have_common_name(&store.name, &self.name))
+    }
+}
+
+/// Payload for searching stores of the user
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchStoresPayload {
+    pub user_id: UserId,
+}
+
+/// Lowercase name without punctuation and whitespace, so that `My Store` and `my-store` are the same
+fn normalize_name(name: &str) -> String {
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
+
+/// Names are translations, e.g. `[{"lang": "en", "text": "Store"}]`
+fn have_common_name(a: &serde_json::Value, b: &serde_json::Value) -> bool {
+    let names = |value: &serde_json::Value| {
+        value
+            .as_array()
+            .map(|translations| {
+                translations
+                    .iter()
+                    .filter_map(|translation| translation.get("text").and_then(|text| text.as_str()))
+                    .map(normalize_name)
+                    .filter(|name| !name.is_empty())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+    let b = names(b);
+    names(a).iter().any(|name| b.contains(name))
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CreateStoreMerchantPayload {
     pub id: StoreId,
     pub country_code: Option<String>,
 }
 
-pub type CreateStoreOperationLog = Vec<CreateStoreOperationStage>;
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoreDeactivatedForUser {
+    pub store_email: String,
+    pub store_id: String,
+    pub reason: Option<String>,
+    pub appeal_url: Option<String>,
+    pub cluster_url: String,
+}
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CreateStoreOperationStage {
     StoreCreationStart(SagaId),
     StoreCreationComplete(StoreId),
@@ -98,3 +150,25 @@ pub enum CreateStoreOperationStage {
     BillingCreateMerchantStart(StoreId),
     BillingCreateMerchantComplete(StoreId),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_normalized_names() {
+        assert_eq!(normalize_name("My Store-2"), normalize_name("my_store 2"));
+        assert!(have_common_name(
+            &json!([{"lang": "en", "text": "Tea Shop"}, {"lang": "ru", "text": "Чайная"}]),
+            &json!([{"lang": "en", "text": "tea-shop!"}])
+        ));
+        assert!(!have_common_name(
+            &json!([{"lang": "en", "text": "Tea Shop"}]),
+            &json!([{"lang": "en", "text": "Coffee Shop"}])
+        ));
+        assert!(!have_common_name(
+            &json!([{"lang": "en", "text": "--"}]),
+            &json!([{"lang": "en", "text": ""}])
+        ));
+    }
+}
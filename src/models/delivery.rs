@@ -1,5 +1,22 @@
+use std::collections::HashMap;
+
 use stq_static_resources::Currency;
 use stq_types::*;
+use validator::Validate;
+
+/// Fields of `AddressFull` delivery reports validation errors for
+pub const ADDRESS_FIELDS: &[&str] = &[
+    "country",
+    "country_code",
+    "administrative_area_level_1",
+    "administrative_area_level_2",
+    "locality",
+    "political",
+    "postal_code",
+    "route",
+    "street_number",
+    "address",
+];
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct NewShipping {
@@ -84,3 +101,231 @@ pub struct Country {
     pub is_selected: bool,
     pub children: Vec<Country>,
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct DeliveryQuoteInput {
+    pub delivery_to: Alpha3,
+    #[validate(length(min = "1"))]
+    pub items: Vec<DeliveryQuoteItem>,
+}
+
+/// Cart item to quote shipping for
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeliveryQuoteItem {
+    pub product_id: ProductId,
+    pub base_product_id: BaseProductId,
+    pub store_id: StoreId,
+    pub quantity: Quantity,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShippingRatesQuery {
+    pub delivery_to: Alpha3,
+    pub items: Vec<ShippingRatesQueryItem>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShippingRatesQueryItem {
+    pub base_product_id: BaseProductId,
+    pub quantity: Quantity,
+}
+
+/// Shipping method available for the product, price is for the queried quantity
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ShippingRate {
+    pub company_package_id: CompanyPackageId,
+    pub name: String,
+    pub price: ProductPrice,
+    pub currency: Currency,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProductShippingRates {
+    pub base_product_id: BaseProductId,
+    pub rates: Vec<ShippingRate>,
+    pub pickup: Option<Pickups>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProductDeliveryQuote {
+    pub product_id: ProductId,
+    pub base_product_id: BaseProductId,
+    pub quantity: Quantity,
+    /// Empty if product can not be delivered to the destination
+    pub rates: Vec<ShippingRate>,
+    pub pickup: Option<Pickups>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoreDeliveryQuote {
+    pub store_id: StoreId,
+    pub products: Vec<ProductDeliveryQuote>,
+}
+
+/// Shipping methods of cart items grouped by store
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeliveryQuote {
+    pub delivery_to: Alpha3,
+    pub stores: Vec<StoreDeliveryQuote>,
+}
+
+impl DeliveryQuote {
+    /// Matches rates from delivery with cart items, stores keep order they first appear in the cart
+    pub fn new(input: DeliveryQuoteInput, rates: Vec<ProductShippingRates>) -> Self {
+        let rates = rates
+            .into_iter()
+            .map(|product_rates| (product_rates.base_product_id, product_rates))
+            .collect::<HashMap<_, _>>();
+
+        let mut stores: Vec<StoreDeliveryQuote> = vec![];
+        for item in input.items {
+            let (product_rates, pickup) = match rates.get(&item.base_product_id) {
+                Some(product_rates) => (product_rates.rates.clone(), product_rates.pickup.clone()),
+                None => (vec![], None),
+            };
+            let product = ProductDeliveryQuote {
+                product_id: item.product_id,
+                base_product_id: item.base_product_id,
+                quantity: item.quantity,
+                rates: product_rates,
+                pickup,
+            };
+            match stores.iter().position(|store| store.store_id == item.store_id) {
+                Some(index) => stores[index].products.push(product),
+                None => stores.push(StoreDeliveryQuote {
+                    store_id: item.store_id,
+                    products: vec![product],
+                }),
+            }
+        }
+
+        Self {
+            delivery_to: input.delivery_to,
+            stores,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NewShippingLabel {
+    pub order_id: OrderId,
+    pub order_slug: OrderSlug,
+    pub store_id: StoreId,
+}
+
+/// Shipping label purchased from delivery company for the order
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShippingLabel {
+    pub order_id: OrderId,
+    pub track_id: String,
+    pub label_url: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ShippingLabelForStore {
+    pub store_email: String,
+    pub store_id: String,
+    pub order_slug: String,
+    pub track_id: String,
+    pub label_url: String,
+    pub cluster_url: String,
+}
+
+/// Tracking event of the order sent by courier, the same event may be delivered more than once
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TrackingUpdate {
+    pub event_id: String,
+    pub order_slug: OrderSlug,
+    pub track_id: String,
+    pub status: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackingOutcome {
+    /// Order state is changed
+    Applied,
+    /// Status does not change state of the order, or the order is not sent
+    Ignored,
+    /// Event is already handled
+    Duplicate,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TrackingUpdateResult {
+    pub event_id: String,
+    pub outcome: TrackingOutcome,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum UpsertShippingOperationStage {
+    ShippingUpsertStart(BaseProductId),
+    ShippingUpsertComplete(BaseProductId),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_payload_round_trip;
+
+    fn item(product_id: i32, base_product_id: i32, store_id: i32) -> DeliveryQuoteItem {
+        DeliveryQuoteItem {
+            product_id: ProductId(product_id),
+            base_product_id: BaseProductId(base_product_id),
+            store_id: StoreId(store_id),
+            quantity: Quantity(1),
+        }
+    }
+
+    #[test]
+    fn groups_quote_by_store() {
+        let input = DeliveryQuoteInput {
+            delivery_to: Alpha3("RUS".to_string()),
+            items: vec![item(1, 10, 2), item(2, 20, 1), item(3, 30, 2)],
+        };
+        let rates = vec![ProductShippingRates {
+            base_product_id: BaseProductId(10),
+            rates: vec![ShippingRate {
+                company_package_id: CompanyPackageId(1),
+                name: "Courier".to_string(),
+                price: ProductPrice(5.0),
+                currency: Currency::STQ,
+            }],
+            pickup: None,
+        }];
+
+        let quote = DeliveryQuote::new(input, rates);
+        let stores = quote.stores.iter().map(|store| store.store_id).collect::<Vec<_>>();
+        assert_eq!(stores, vec![StoreId(2), StoreId(1)]);
+        assert_eq!(quote.stores[0].products.len(), 2);
+        assert_eq!(quote.stores[0].products[0].rates.len(), 1);
+        // products delivery has no rates for are still quoted
+        assert!(quote.stores[0].products[1].rates.is_empty());
+        assert!(quote.stores[1].products[0].rates.is_empty());
+    }
+
+    #[test]
+    fn delivery_payloads_round_trip() {
+        assert_payload_round_trip::<NewShipping>(json!({
+            "items": [{
+                "base_product_id": 1,
+                "store_id": 1,
+                "company_package_id": 1,
+                "price": 5.0,
+                "measurements": {"volume_cubic_cm": 1000, "weight_g": 500},
+                "delivery_from": "RUS",
+                "deliveries_to": ["RUS"],
+                "shipping": "Local",
+                "currency": "stq"
+            }],
+            "pickup": {"base_product_id": 1, "store_id": 1, "pickup": true}
+        }));
+        assert_payload_round_trip::<DeliveryQuoteInput>(json!({
+            "delivery_to": "RUS",
+            "items": [{"product_id": 1, "base_product_id": 2, "store_id": 3, "quantity": 1}]
+        }));
+        assert_payload_round_trip::<TrackingUpdate>(
+            json!({"event_id": "e1", "order_slug": 1001, "track_id": "TR1", "status": "delivered"}),
+        );
+    }
+}
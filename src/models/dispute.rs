@@ -0,0 +1,87 @@
+use stq_static_resources::OrderState;
+use stq_types::{OrderId, SagaId};
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct DisputeInput {
+    #[validate(length(min = "1", max = "2000"))]
+    pub reason: String,
+}
+
+/// Party the disputed money goes to
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeResolution {
+    RefundCustomer,
+    PaySeller,
+}
+
+impl DisputeResolution {
+    /// State of the order once dispute is resolved
+    pub fn order_state(self) -> OrderState {
+        match self {
+            DisputeResolution::RefundCustomer => OrderState::Cancelled,
+            DisputeResolution::PaySeller => OrderState::Complete,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DisputeResolveInput {
+    pub resolution: DisputeResolution,
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NewDispute {
+    pub order_id: OrderId,
+    pub saga_id: SagaId,
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ResolveDisputePayload {
+    pub resolution: DisputeResolution,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Dispute {
+    pub id: Uuid,
+    pub order_id: OrderId,
+    pub reason: String,
+    pub resolution: Option<DisputeResolution>,
+}
+
+/// Stages of both opening and resolving a dispute.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisputeOperationStage {
+    DisputeRegistrationStart(SagaId),
+    DisputeRegistrationComplete(SagaId),
+    /// Keeps state of the order before the dispute, so it can be restored
+    OrderFreezeStart(OrderId, OrderState),
+    OrderFreezeComplete(OrderId),
+    StockReturnStart(OrderId),
+    StockReturnComplete(OrderId),
+    OrderUnfreezeStart(OrderId),
+    OrderUnfreezeComplete(OrderId),
+    DisputeResolutionStart(OrderId),
+    DisputeResolutionComplete(OrderId),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_model_round_trip;
+
+    #[test]
+    fn dispute_payloads_round_trip() {
+        assert_model_round_trip(&DisputeInput {
+            reason: "Item is broken".to_string(),
+        });
+        assert_model_round_trip(&DisputeResolveInput {
+            resolution: DisputeResolution::RefundCustomer,
+            comment: None,
+        });
+    }
+}
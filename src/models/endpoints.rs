@@ -0,0 +1,25 @@
+/// Share of traffic of the microservice sent to its secondary deployment, from 0 to 100
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EndpointSwitchInput {
+    pub service: String,
+    pub secondary_percent: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct EndpointState {
+    pub service: String,
+    pub primary_url: String,
+    pub secondary_url: String,
+    pub secondary_percent: u8,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_payload_round_trip;
+
+    #[test]
+    fn endpoint_switch_round_trip() {
+        assert_payload_round_trip::<EndpointSwitchInput>(json!({"service": "stores", "secondary_percent": 10}));
+    }
+}
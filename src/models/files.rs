@@ -0,0 +1,34 @@
+use super::{Product, Store};
+
+/// Files no longer referenced by active stores and products, static files service deletes them after `delay_s`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FilesCleanup {
+    pub urls: Vec<String>,
+    pub delay_s: u64,
+}
+
+/// Files which must be kept after all, e.g. because their store was activated again
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FilesCleanupCancellation {
+    pub urls: Vec<String>,
+}
+
+/// Logo and cover of store
+pub fn store_files(store: &Store) -> Vec<String> {
+    store.logo.iter().chain(store.cover.iter()).cloned().collect()
+}
+
+/// Main and additional photos of products
+pub fn product_files(products: &[Product]) -> Vec<String> {
+    products
+        .iter()
+        .flat_map(|product| {
+            product
+                .photo_main
+                .iter()
+                .chain(product.additional_photos.iter().flat_map(|photos| photos.iter()))
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
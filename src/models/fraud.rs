@@ -0,0 +1,82 @@
+use std::time::SystemTime;
+
+use stq_api::orders::Order;
+use stq_static_resources::Currency;
+use stq_types::{SagaId, UserId};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FraudScoreRequest {
+    pub saga_id: SagaId,
+    pub customer_id: UserId,
+    pub currency: Currency,
+    pub orders: Vec<Order>,
+}
+
+/// Risk of checkout estimated by scoring service, from 0 to 1
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FraudScore {
+    pub score: f64,
+    #[serde(default)]
+    pub reasons: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FraudDecision {
+    Pass,
+    /// Checkout proceeds, but is marked for review in saga log
+    Flag,
+    Block,
+}
+
+/// Outcome of fraud screening of a checkout, recorded in saga log
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FraudCheck {
+    /// `None` if scoring service failed and screening is configured to fail open
+    pub score: Option<FraudScore>,
+    pub decision: FraudDecision,
+    /// Decision was taken by admin override instead of the score
+    pub overridden: bool,
+}
+
+/// Decision admins force on checkouts of the customer regardless of their score
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FraudOverrideDecision {
+    Allow,
+    Block,
+}
+
+impl From<FraudOverrideDecision> for FraudDecision {
+    fn from(decision: FraudOverrideDecision) -> Self {
+        match decision {
+            FraudOverrideDecision::Allow => FraudDecision::Pass,
+            FraudOverrideDecision::Block => FraudDecision::Block,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FraudOverrideInput {
+    pub decision: FraudOverrideDecision,
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FraudOverride {
+    pub customer_id: UserId,
+    pub decision: FraudOverrideDecision,
+    pub comment: Option<String>,
+    pub created_at: SystemTime,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_payload_round_trip;
+
+    #[test]
+    fn fraud_override_round_trip() {
+        assert_payload_round_trip::<FraudOverrideInput>(json!({"decision": "block", "comment": "Chargebacks"}));
+    }
+}
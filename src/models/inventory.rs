@@ -0,0 +1,105 @@
+use stq_types::{BaseProductId, ProductId, Quantity, StoreId};
+
+use super::{Page, PageRequest};
+
+/// Parameters of inventory reconciliation of a store
+#[derive(Clone, Debug)]
+pub struct ReconcileInventory {
+    pub store_id: StoreId,
+    /// Sets listed quantities to stocks for discrepancies within `tolerance`
+    pub fix: bool,
+    pub tolerance: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProductQuantity {
+    pub quantity: Quantity,
+}
+
+/// Product whose quantity in stores listing differs from its stocks in warehouses
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InventoryDiscrepancy {
+    pub product_id: ProductId,
+    pub base_product_id: BaseProductId,
+    /// `None` if stores do not list quantity of the product
+    pub listed_quantity: Option<Quantity>,
+    /// Sum of stocks of the product in all warehouses of the store
+    pub stock_quantity: Quantity,
+    pub fixed: bool,
+}
+
+impl InventoryDiscrepancy {
+    /// Difference of quantities, product without listed quantity is counted as listed with zero
+    pub fn difference(&self) -> u32 {
+        let listed = self.listed_quantity.map(|quantity| quantity.0).unwrap_or(0);
+        (listed - self.stock_quantity.0).abs() as u32
+    }
+
+    pub fn is_within(&self, tolerance: u32) -> bool {
+        self.difference() <= tolerance
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InventoryReport {
+    pub store_id: StoreId,
+    pub products_checked: usize,
+    pub discrepancies: Vec<InventoryDiscrepancy>,
+}
+
+impl InventoryReport {
+    /// Report with a page of discrepancies sorted by their difference
+    pub fn paginate(self, page: &PageRequest) -> InventoryReportPage {
+        InventoryReportPage {
+            store_id: self.store_id,
+            products_checked: self.products_checked,
+            discrepancies: page.paginate_by(self.discrepancies, InventoryDiscrepancy::difference),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct InventoryReportPage {
+    pub store_id: StoreId,
+    pub products_checked: usize,
+    pub discrepancies: Page<InventoryDiscrepancy>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LowStockProduct {
+    pub product_id: ProductId,
+    pub base_product_id: BaseProductId,
+    pub quantity: Quantity,
+}
+
+/// Digest of products of the store running out of stock
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LowStockForStore {
+    pub store_email: String,
+    pub store_id: String,
+    pub threshold: u32,
+    pub products: Vec<LowStockProduct>,
+    pub cluster_url: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_difference_of_quantities() {
+        let mut discrepancy = InventoryDiscrepancy {
+            product_id: ProductId(1),
+            base_product_id: BaseProductId(1),
+            listed_quantity: Some(Quantity(3)),
+            stock_quantity: Quantity(5),
+            fixed: false,
+        };
+        assert_eq!(discrepancy.difference(), 2);
+        assert!(discrepancy.is_within(2));
+        assert!(!discrepancy.is_within(1));
+
+        discrepancy.listed_quantity = None;
+        assert_eq!(discrepancy.difference(), 5);
+    }
+}
@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+
+/// Global log level set by admin, e.g. `debug`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogLevelInput {
+    pub level: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogLevels {
+    /// Global level capping output of every module
+    pub level: String,
+    /// Levels of modules configured at startup
+    pub modules: HashMap<String, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_payload_round_trip;
+
+    #[test]
+    fn log_level_round_trip() {
+        assert_payload_round_trip::<LogLevelInput>(json!({"level": "debug"}));
+    }
+}
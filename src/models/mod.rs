@@ -1,21 +1,302 @@
+pub mod about;
+pub mod acknowledgment;
+pub mod audit;
 pub mod base_product;
+pub mod catalog;
+pub mod category_change;
 pub mod create_order;
 pub mod create_profile;
 pub mod create_store;
 pub mod delivery;
+pub mod dispute;
+pub mod endpoints;
+pub mod files;
+pub mod fraud;
+pub mod inventory;
+pub mod log_level;
 pub mod moderate;
 pub mod notifications;
+pub mod order_cancellation;
+pub mod order_return;
+pub mod page;
+pub mod payout;
+pub mod pricing;
+pub mod readiness;
 pub mod roles;
+pub mod saga;
+pub mod support;
+pub mod takedown;
+pub mod unknown_fields;
+pub mod vacation;
+pub mod validation;
+pub mod variants;
+pub mod verification;
 pub mod visibility;
 pub mod warehouses;
 
+pub use self::about::*;
+pub use self::acknowledgment::*;
+pub use self::audit::*;
 pub use self::base_product::*;
+pub use self::catalog::*;
+pub use self::category_change::*;
 pub use self::create_order::*;
 pub use self::create_profile::*;
 pub use self::create_store::*;
 pub use self::delivery::*;
+pub use self::dispute::*;
+pub use self::endpoints::*;
+pub use self::files::*;
+pub use self::fraud::*;
+pub use self::inventory::*;
+pub use self::log_level::*;
 pub use self::moderate::*;
 pub use self::notifications::*;
+pub use self::order_cancellation::*;
+pub use self::order_return::*;
+pub use self::page::*;
+pub use self::payout::*;
+pub use self::pricing::*;
+pub use self::readiness::*;
 pub use self::roles::*;
+pub use self::saga::*;
+pub use self::support::*;
+pub use self::takedown::*;
+pub use self::unknown_fields::*;
+pub use self::vacation::*;
+pub use self::variants::*;
+pub use self::verification::*;
 pub use self::visibility::*;
 pub use self::warehouses::*;
+
+/// Round trips of models through json for tests of model modules
+#[cfg(test)]
+pub mod round_trip {
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use serde_json::{self, Value};
+
+    /// Deserializes payload and serializes the model back, fields of the payload dropped or changed by the model
+    /// make it fail. Optional fields left out of the payload may be serialized
+    pub fn assert_payload_round_trip<T: DeserializeOwned + Serialize>(payload: Value) {
+        let model = serde_json::from_value::<T>(payload.clone()).unwrap();
+        let serialized = serde_json::to_value(&model).unwrap();
+        assert!(contains(&serialized, &payload), "{} is serialized as {}", payload, serialized);
+    }
+
+    pub fn assert_model_round_trip<T: DeserializeOwned + Serialize>(model: &T) {
+        let value = serde_json::to_value(model).unwrap();
+        assert_payload_round_trip::<T>(value);
+    }
+
+    /// Whether `actual` has every field of `expected` with the same value, at any depth
+    fn contains(actual: &Value, expected: &Value) -> bool {
+        match (actual, expected) {
+            (Value::Object(actual), Value::Object(expected)) => expected
+                .iter()
+                .all(|(key, value)| actual.get(key).map(|actual| contains(actual, value)).unwrap_or(false)),
+            (Value::Array(actual), Value::Array(expected)) => {
+                actual.len() == expected.len() && actual.iter().zip(expected).all(|(actual, expected)| contains(actual, expected))
+            }
+            _ => actual == expected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use chrono::{TimeZone, Utc};
+    use serde_json;
+    use stq_types::{BaseProductId, OrderId, OrderSlug, ProductId, Quantity, RoleId, SagaId, StoreId, UserId};
+
+    use super::round_trip::{assert_model_round_trip, assert_payload_round_trip};
+    use super::*;
+    use controller::deserialize;
+
+    #[test]
+    fn new_store_round_trip() {
+        assert_payload_round_trip::<NewStore>(json!({
+            "name": [{"lang": "en", "text": "Store"}],
+            "user_id": 1,
+            "short_description": [{"lang": "en", "text": "Short"}],
+            "long_description": null,
+            "slug": "store",
+            "cover": null,
+            "logo": null,
+            "phone": "+79001234567",
+            "email": "store@example.com",
+            "address": null,
+            "facebook_url": null,
+            "twitter_url": null,
+            "instagram_url": null,
+            "default_language": "en",
+            "slogan": null,
+            "country": "Russia",
+            "country_code": "RUS",
+            "administrative_area_level_1": null,
+            "administrative_area_level_2": null,
+            "locality": null,
+            "political": null,
+            "postal_code": null,
+            "route": null,
+            "saga_id": null,
+            "street_number": null,
+            "place_id": null,
+            "uuid": "a2b3c4d5-0000-4000-8000-000000000001"
+        }));
+    }
+
+    #[test]
+    fn order_payment_state_request_round_trip() {
+        assert_payload_round_trip::<OrderPaymentStateRequest>(json!({"state": "paidtoseller"}));
+    }
+
+    #[test]
+    fn new_role_round_trip() {
+        assert_model_round_trip(&NewRole::new(
+            RoleId::new(),
+            UserId(1),
+            "store_manager".to_string(),
+            Some(StoreId(1)),
+        ));
+    }
+
+    #[test]
+    fn saga_record_round_trip() {
+        let saga_id = SagaId::new();
+        let mut record = SagaRecord::new(saga_id, SagaType::CreateStore);
+        record.stages.push(SagaLogEntry::new(
+            CreateStoreOperationStage::StoreCreationStart(saga_id).into_saga_stage(),
+            None,
+        ));
+        record.stages.push(SagaLogEntry::recovered(
+            CreateStoreOperationStage::StoreCreationComplete(StoreId(1)).into_saga_stage(),
+            Some(json!({"id": 1})),
+        ));
+        record
+            .warnings
+            .push(SagaWarning::new("delivery_role_set", "Delivery is unavailable".to_string()));
+        record.compensations.push(CompensationReport::default());
+        assert_model_round_trip(&record);
+    }
+
+    #[test]
+    fn rejects_unknown_fields_of_payloads() {
+        let payload = br#"{"reason": "Court order 1", "urgent": true}"#;
+        assert_eq!(deserialize::<StoreTakedown>(payload, false).unwrap().reason, "Court order 1");
+        assert!(deserialize::<StoreTakedown>(payload, true).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_fields_of_payloads_with_flattened_fields() {
+        let cart = json!({
+            "customer_id": 1,
+            "country": "Russia",
+            "receiver_name": "Receiver",
+            "receiver_phone": "+79001234567",
+            "receiver_email": "receiver@example.com",
+            "prices": {},
+            "currency": "stq",
+            "coupons": {},
+            "delivery_info": {},
+            "product_info": {},
+            "uuid": "a2b3c4d5-0000-4000-8000-000000000001",
+            "currency_type": null
+        });
+        let payload = serde_json::to_vec(&cart).unwrap();
+        let parsed = deserialize::<ConvertCart>(&payload, true).unwrap();
+        assert!(parsed.unknown.is_empty());
+
+        let mut with_unknown = cart.clone();
+        with_unknown["reciever_name"] = json!("Receiver");
+        let payload = serde_json::to_vec(&with_unknown).unwrap();
+        assert_eq!(
+            deserialize::<ConvertCart>(&payload, false).unwrap().unknown.0,
+            vec!["reciever_name".to_string()]
+        );
+        assert!(deserialize::<ConvertCart>(&payload, true).is_err());
+        // leftovers are not passed on to orders microservice
+        let forwarded = serde_json::to_value(deserialize::<ConvertCart>(&payload, false).unwrap()).unwrap();
+        assert!(forwarded.get("reciever_name").is_none());
+
+        let edit = br#"{"edit": [{"product_id": 1, "price": 11.0, "prise": 12.0}]}"#;
+        assert!(deserialize::<VariantsBulkEdit>(edit, false).is_ok());
+        assert!(deserialize::<VariantsBulkEdit>(edit, true).is_err());
+    }
+
+    #[test]
+    fn stored_models_round_trip() {
+        assert_model_round_trip(&PendingAcknowledgment::new(OrderId::new(), OrderSlug(1), StoreId(1)));
+        assert_model_round_trip(&PendingModeration::new(ModerationItem::Store(StoreId(1)), StoreId(1)));
+        assert_model_round_trip(&DeactivationRecord::new(
+            DeactivatedItem::Product(ProductId(1)),
+            Deactivation::default(),
+            Some(UserId(1)),
+        ));
+        assert_model_round_trip(&StoreVacation::new(StoreId(1), Utc.ymd(2018, 1, 1).and_hms(0, 0, 0)));
+        assert_model_round_trip(&AuditEntry {
+            method: "POST".to_string(),
+            url: "/stores/1".to_string(),
+            saga_id: Some(SagaId::new()),
+            reason: "Takedown".to_string(),
+            recorded_at: UNIX_EPOCH + Duration::from_secs(1),
+            legal_hold: None,
+        });
+    }
+
+    #[test]
+    fn downstream_models_round_trip() {
+        assert_model_round_trip(&FilesCleanup {
+            urls: vec!["https://example.com/photo.png".to_string()],
+            delay_s: 60,
+        });
+        assert_model_round_trip(&InventoryReport {
+            store_id: StoreId(1),
+            products_checked: 1,
+            discrepancies: vec![InventoryDiscrepancy {
+                product_id: ProductId(1),
+                base_product_id: BaseProductId(1),
+                listed_quantity: None,
+                stock_quantity: Quantity(2),
+                fixed: false,
+            }],
+        });
+        assert_model_round_trip(&StoreKycStatus {
+            store_id: StoreId(1),
+            status: KycStatus::Pending,
+        });
+        assert_model_round_trip(&CreateEmarsysContactPayload {
+            user_id: UserId(1),
+            email: "user@example.com".to_string(),
+            first_name: None,
+            last_name: None,
+            country: None,
+        });
+        assert_model_round_trip(&DependencyReport {
+            service: "stores".to_string(),
+            breaker: Some(BreakerState::HalfOpen),
+            calls: 10,
+            error_rate: 0.5,
+            p95_latency_ms: Some(120),
+            last_success_at: None,
+        });
+        assert_model_round_trip(&StockAdjustment {
+            saga_id: SagaId::new(),
+            kind: StockAdjustmentKind::ReturnReverted,
+            quantity: Quantity(2),
+        });
+    }
+
+    #[test]
+    fn parses_entity_version_from_if_match() {
+        assert_eq!(EntityVersion::parse("\"1538000000123\""), Some(EntityVersion(1538000000123)));
+        assert_eq!(EntityVersion::parse("W/\"42\""), Some(EntityVersion(42)));
+        assert_eq!(EntityVersion::parse("*"), None);
+        assert_eq!(
+            EntityVersion::of(UNIX_EPOCH + Duration::from_millis(1538000000123)),
+            EntityVersion(1538000000123)
+        );
+    }
+}
@@ -1,18 +1,43 @@
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use stq_static_resources::{Currency, ModerationStatus, Translation};
-use stq_types::{BaseProductId, CategoryId, ProductId, ProductPrice, StoreId};
+use stq_types::{BaseProductId, CategoryId, ProductId, ProductPrice, Quantity, StoreId, UserId};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct StoreModerate {
     pub store_id: StoreId,
     pub status: ModerationStatus,
+    /// Version of the store the moderator has seen, also given with `If-Match`
+    #[serde(default, skip_serializing)]
+    pub version: Option<EntityVersion>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BaseProductModerate {
     pub base_product_id: BaseProductId,
     pub status: ModerationStatus,
+    /// Version of the base product the moderator has seen, also given with `If-Match`
+    #[serde(default, skip_serializing)]
+    pub version: Option<EntityVersion>,
+}
+
+/// Version of store or base product, milliseconds of its `updated_at`.
+/// Changes made against a stale version are rejected with `409 Conflict`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntityVersion(pub u64);
+
+impl EntityVersion {
+    pub fn of(updated_at: SystemTime) -> Self {
+        let since_epoch = updated_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        EntityVersion(since_epoch.as_secs() * 1000 + u64::from(since_epoch.subsec_millis()))
+    }
+
+    /// Version given with `If-Match`, quotes and weak prefix are ignored
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let value = if value.starts_with("W/") { &value[2..] } else { value };
+        value.trim_matches('"').parse().ok().map(EntityVersion)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -57,6 +82,9 @@ pub struct Product {
     pub pre_order: bool,
     pub pre_order_days: i32,
     pub customer_price: CustomerPrice,
+    /// Quantity shown in store listing, kept in sync with warehouses stocks
+    #[serde(default)]
+    pub quantity: Option<Quantity>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -64,3 +92,109 @@ pub struct CustomerPrice {
     pub price: ProductPrice,
     pub currency: Currency,
 }
+
+/// Store or base product sent to moderation
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", content = "id", rename_all = "snake_case")]
+pub enum ModerationItem {
+    Store(StoreId),
+    BaseProduct(BaseProductId),
+}
+
+impl ModerationItem {
+    pub fn item_type(&self) -> &'static str {
+        match self {
+            ModerationItem::Store(_) => "store",
+            ModerationItem::BaseProduct(_) => "base_product",
+        }
+    }
+}
+
+/// Item awaiting decision of moderators
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingModeration {
+    pub item: ModerationItem,
+    pub store_id: StoreId,
+    pub entered_at: SystemTime,
+    /// Last time moderators were reminded about the item
+    pub reminded_at: Option<SystemTime>,
+}
+
+impl PendingModeration {
+    pub fn new(item: ModerationItem, store_id: StoreId) -> Self {
+        Self {
+            item,
+            store_id,
+            entered_at: SystemTime::now(),
+            reminded_at: None,
+        }
+    }
+
+    /// Whether item waits for moderation longer than `sla`
+    pub fn is_overdue(&self, sla: Duration, now: SystemTime) -> bool {
+        now.duration_since(self.entered_at).map(|waiting| waiting > sla).unwrap_or(false)
+    }
+}
+
+/// Payload of deactivation of store, base product or product, it may be omitted
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Deactivation {
+    /// Reason told to store manager
+    pub reason: Option<String>,
+    /// Note of the moderator, kept in moderation trail only
+    pub note: Option<String>,
+}
+
+/// Payload of activation of store, base product or product, it may be omitted
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Activation {
+    /// Zero stocks are put into warehouse of the store for activated products having no stocks
+    #[serde(default)]
+    pub stock_placeholders: bool,
+}
+
+/// Store, base product or product deactivated
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", content = "id", rename_all = "snake_case")]
+pub enum DeactivatedItem {
+    Store(StoreId),
+    BaseProduct(BaseProductId),
+    Product(ProductId),
+}
+
+/// Deactivation recorded in moderation trail
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeactivationRecord {
+    pub item: DeactivatedItem,
+    pub reason: Option<String>,
+    pub note: Option<String>,
+    /// `None` if deactivated by superadmin
+    pub actor: Option<UserId>,
+    pub recorded_at: SystemTime,
+}
+
+impl DeactivationRecord {
+    pub fn new(item: DeactivatedItem, deactivation: Deactivation, actor: Option<UserId>) -> Self {
+        Self {
+            item,
+            reason: deactivation.reason,
+            note: deactivation.note,
+            actor,
+            recorded_at: SystemTime::now(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_payload_round_trip;
+
+    #[test]
+    fn moderation_payloads_round_trip() {
+        assert_payload_round_trip::<StoreModerate>(json!({"store_id": 1, "status": "published"}));
+        assert_payload_round_trip::<BaseProductModerate>(json!({"base_product_id": 1, "status": "published"}));
+        assert_payload_round_trip::<Deactivation>(json!({"reason": "Counterfeit"}));
+        assert_payload_round_trip::<Activation>(json!({"stock_placeholders": true}));
+    }
+}
@@ -1,5 +1,8 @@
+use stq_static_resources::OrderCreateForUser;
 use stq_types::{Alpha3, EmarsysId, UserId};
 
+use super::TaxLine;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateEmarsysContactPayload {
     pub user_id: UserId,
@@ -14,3 +17,22 @@ pub struct CreatedEmarsysContact {
     pub user_id: UserId,
     pub emarsys_id: EmarsysId,
 }
+
+/// Order created email with tax lines of the order, sent when tax calculation is enabled
+#[derive(Serialize)]
+pub struct OrderCreateWithTaxesForUser {
+    #[serde(flatten)]
+    pub email: OrderCreateForUser,
+    pub taxes: Vec<TaxLine>,
+}
+
+/// Order created email linking receipt of the invoice, sent when receipts are requested
+#[derive(Serialize)]
+pub struct OrderCreateWithReceiptForUser {
+    #[serde(flatten)]
+    pub email: OrderCreateForUser,
+    /// Tax lines of the order, set when tax calculation is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taxes: Option<Vec<TaxLine>>,
+    pub receipt_url: String,
+}
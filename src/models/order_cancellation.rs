@@ -0,0 +1,87 @@
+use stq_static_resources::OrderState;
+use stq_types::{OrderId, OrderSlug, ProductPrice, SagaId};
+use uuid::Uuid;
+use validator::Validate;
+
+/// Cancellation of a single order of checkout, the rest of orders paid with the same invoice stay as they are
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct OrderItemCancelInput {
+    #[validate(length(max = "2000"))]
+    pub comment: Option<String>,
+}
+
+/// Cancellation of several orders of checkout, billing takes all of them out of the invoice at once
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct OrderItemsCancelInput {
+    #[validate(length(min = "1"))]
+    pub order_slugs: Vec<OrderSlug>,
+    #[validate(length(max = "2000"))]
+    pub comment: Option<String>,
+}
+
+/// Orders removed from the invoice they were checked out with
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AmendInvoice {
+    pub saga_id: SagaId,
+    pub order_ids: Vec<OrderId>,
+}
+
+/// Change of invoice made by billing, the amount is either not charged or refunded to customer
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InvoiceAmendment {
+    pub id: Uuid,
+    pub saga_id: SagaId,
+    pub order_ids: Vec<OrderId>,
+    pub amount: ProductPrice,
+}
+
+/// Orders can be cancelled until they are sent
+pub fn is_cancellable(state: OrderState) -> bool {
+    match state {
+        OrderState::New | OrderState::PaymentAwaited | OrderState::TransactionPending | OrderState::Paid | OrderState::InProcessing => true,
+        _ => false,
+    }
+}
+
+/// Stock is taken from warehouse once order is paid
+pub fn holds_stock(state: OrderState) -> bool {
+    state == OrderState::Paid || state == OrderState::InProcessing
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CancelOrderItemOperationStage {
+    /// Keeps state of the order before cancellation, so it can be restored
+    OrderCancelStart(OrderId, OrderState),
+    OrderCancelComplete(OrderId),
+    StockReturnStart(OrderId),
+    StockReturnComplete(OrderId),
+    InvoiceAmendStart(SagaId),
+    InvoiceAmendComplete(SagaId),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_model_round_trip;
+
+    #[test]
+    fn returns_stock_only_of_paid_orders() {
+        assert!(is_cancellable(OrderState::New));
+        assert!(!holds_stock(OrderState::New));
+        assert!(is_cancellable(OrderState::InProcessing));
+        assert!(holds_stock(OrderState::InProcessing));
+        assert!(!is_cancellable(OrderState::Sent));
+        assert!(!is_cancellable(OrderState::Cancelled));
+    }
+
+    #[test]
+    fn cancellation_payloads_round_trip() {
+        assert_model_round_trip(&OrderItemCancelInput {
+            comment: Some("Out of stock".to_string()),
+        });
+        assert_model_round_trip(&OrderItemsCancelInput {
+            order_slugs: vec![OrderSlug(1), OrderSlug(2)],
+            comment: None,
+        });
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use stq_static_resources::{EmailUser, OrderState};
+use stq_types::{OrderId, Quantity, SagaId};
+use uuid::Uuid;
+use validator::Validate;
+
+use super::PaymentState;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct OrderReturnInput {
+    #[validate(length(min = "1", max = "2000"))]
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnState {
+    /// Customer is sending the items back
+    Requested,
+    /// Store confirmed receipt of the items, they are back in stock
+    Received,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NewOrderReturn {
+    pub order_id: OrderId,
+    pub saga_id: SagaId,
+    pub reason: String,
+    pub quantity: Quantity,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OrderReturn {
+    pub id: Uuid,
+    pub order_id: OrderId,
+    pub reason: String,
+    pub quantity: Quantity,
+    pub state: ReturnState,
+}
+
+/// Orders can be returned once delivered, for `window` since their last state change
+pub fn is_returnable(state: OrderState, updated_at: DateTime<Utc>, now: DateTime<Utc>, window: Duration) -> bool {
+    match state {
+        OrderState::Delivered | OrderState::Received | OrderState::Complete => now
+            .signed_duration_since(updated_at)
+            .to_std()
+            .map(|elapsed| elapsed <= window)
+            .unwrap_or(true),
+        _ => false,
+    }
+}
+
+/// Payment of order can be refunded while the money is not paid to seller
+pub fn is_refundable(payment_state: PaymentState) -> bool {
+    payment_state == PaymentState::Captured || payment_state == PaymentState::PaymentToSellerNeeded
+}
+
+/// Email telling customer about the return of the order
+#[derive(Serialize)]
+pub struct OrderReturnForUser {
+    pub user: EmailUser,
+    pub order_slug: String,
+    pub return_state: ReturnState,
+    pub cluster_url: String,
+}
+
+/// Email telling store about the return of the order
+#[derive(Serialize)]
+pub struct OrderReturnForStore {
+    pub store_email: String,
+    pub store_id: String,
+    pub order_slug: String,
+    pub return_state: ReturnState,
+    pub cluster_url: String,
+}
+
+/// Stages of both requesting a return and confirming receipt of the returned items.
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderReturnOperationStage {
+    ReturnCreationStart(SagaId),
+    ReturnCreationComplete(SagaId),
+    /// Keeps payment state of the order before the return, so it can be restored
+    RefundRequestStart(OrderId, PaymentState),
+    RefundRequestComplete(OrderId),
+    StockReturnStart(OrderId),
+    StockReturnComplete(OrderId),
+    ReturnReceiptStart(OrderId),
+    ReturnReceiptComplete(OrderId),
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration as ChronoDuration;
+
+    use super::*;
+    use models::round_trip::assert_payload_round_trip;
+
+    #[test]
+    fn returns_delivered_orders_within_window() {
+        let window = Duration::from_secs(14 * 24 * 3600);
+        let now = Utc::now();
+        let delivered_at = now - ChronoDuration::days(3);
+        assert!(is_returnable(OrderState::Delivered, delivered_at, now, window));
+        assert!(is_returnable(OrderState::Complete, delivered_at, now, window));
+        assert!(!is_returnable(OrderState::Sent, delivered_at, now, window));
+        assert!(!is_returnable(OrderState::Delivered, now - ChronoDuration::days(15), now, window));
+    }
+
+    #[test]
+    fn order_return_round_trip() {
+        assert_payload_round_trip::<OrderReturnInput>(json!({"reason": "Item is broken"}));
+    }
+}
@@ -0,0 +1,112 @@
+//! Conventions of admin list endpoints: `limit` and `offset` select the page,
+//! `order` sorts items by the key of the endpoint, usually time they were recorded.
+//! Every list endpoint responds with the same envelope.
+use std::str::FromStr;
+
+pub const DEFAULT_PAGE_LIMIT: usize = 50;
+pub const MAX_PAGE_LIMIT: usize = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+impl FromStr for SortOrder {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_ref() {
+            "asc" => Ok(SortOrder::Asc),
+            "desc" => Ok(SortOrder::Desc),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The latest items come first unless asked otherwise
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder::Desc
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageRequest {
+    pub limit: usize,
+    pub offset: usize,
+    pub order: SortOrder,
+}
+
+impl Default for PageRequest {
+    fn default() -> Self {
+        Self {
+            limit: DEFAULT_PAGE_LIMIT,
+            offset: 0,
+            order: SortOrder::default(),
+        }
+    }
+}
+
+impl PageRequest {
+    /// Sorts items by `key` in requested order and takes the page
+    pub fn paginate_by<T, K, F>(&self, mut items: Vec<T>, key: F) -> Page<T>
+    where
+        K: Ord,
+        F: FnMut(&T) -> K,
+    {
+        items.sort_by_key(key);
+        if self.order == SortOrder::Desc {
+            items.reverse();
+        }
+        let total = items.len();
+        let items = items.into_iter().skip(self.offset).take(self.limit).collect::<Vec<_>>();
+        let next_offset = Some(self.offset + items.len()).filter(|next| *next < total);
+        Page {
+            items,
+            total,
+            limit: self.limit,
+            offset: self.offset,
+            next_offset,
+        }
+    }
+}
+
+/// Envelope of admin list endpoints
+#[derive(Clone, Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Number of items matching filters of the request on all pages
+    pub total: usize,
+    pub limit: usize,
+    pub offset: usize,
+    /// Offset of the next page, `None` on the last page
+    pub next_offset: Option<usize>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PageRequest, SortOrder};
+
+    #[test]
+    fn takes_page_in_requested_order() {
+        let request = PageRequest {
+            limit: 2,
+            offset: 1,
+            order: SortOrder::Desc,
+        };
+        let page = request.paginate_by(vec![3, 1, 5, 4, 2], |item| *item);
+        assert_eq!(page.items, vec![4, 3]);
+        assert_eq!(page.total, 5);
+        assert_eq!(page.next_offset, Some(3));
+
+        let last = PageRequest {
+            offset: 3,
+            order: SortOrder::Asc,
+            ..request
+        }
+        .paginate_by(vec![3, 1, 5, 4, 2], |item| *item);
+        assert_eq!(last.items, vec![4, 5]);
+        assert_eq!(last.next_offset, None);
+    }
+}
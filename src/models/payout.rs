@@ -0,0 +1,67 @@
+use stq_static_resources::Currency;
+use stq_types::{OrderId, ProductPrice, SagaId, StoreId};
+use uuid::Uuid;
+use validator::Validate;
+
+use super::PaymentState;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct PayoutInput {
+    #[validate(length(min = "1"))]
+    pub order_ids: Vec<OrderId>,
+}
+
+/// Order that billing considers eligible for payout to seller
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PayoutEligibleOrder {
+    pub order_id: OrderId,
+    pub payment_state: PaymentState,
+    pub amount: ProductPrice,
+    pub currency: Currency,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NewPayout {
+    pub store_id: StoreId,
+    pub order_ids: Vec<OrderId>,
+    pub saga_id: SagaId,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Payout {
+    pub id: Uuid,
+    pub store_id: StoreId,
+    pub order_ids: Vec<OrderId>,
+    pub amount: ProductPrice,
+    pub currency: Currency,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PayoutInitiatedForStore {
+    pub store_email: String,
+    pub store_id: String,
+    pub orders_count: usize,
+    pub cluster_url: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CreatePayoutOperationStage {
+    PayoutCreationStart(SagaId),
+    PayoutCreationComplete(SagaId),
+    /// Keeps payment state of the order before it was marked paid to seller, so it can be restored
+    OrderPaidToSellerStart(OrderId, PaymentState),
+    OrderPaidToSellerComplete(OrderId),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_model_round_trip;
+
+    #[test]
+    fn payout_round_trip() {
+        assert_model_round_trip(&PayoutInput {
+            order_ids: vec![OrderId::new()],
+        });
+    }
+}
@@ -0,0 +1,100 @@
+use stq_static_resources::EmailUser;
+use stq_types::{BaseProductId, ProductId, ProductPrice, SagaId, StoreId, UserId};
+use validator::Validate;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct RepriceInput {
+    #[validate(length(min = "1"))]
+    pub prices: Vec<NewProductPrice>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NewProductPrice {
+    pub product_id: ProductId,
+    pub price: ProductPrice,
+}
+
+/// Price set by saga, stores keep previous price so that it can be restored by saga id
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UpdateProductPrice {
+    pub price: ProductPrice,
+    pub saga_id: SagaId,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RepriceStatus {
+    Repriced,
+    Failed,
+}
+
+/// Outcome of price change of a single product, failed changes do not fail the saga
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ProductRepriceResult {
+    pub product_id: ProductId,
+    pub base_product_id: Option<BaseProductId>,
+    pub price: ProductPrice,
+    pub status: RepriceStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RepriceResult {
+    pub store_id: StoreId,
+    pub products: Vec<ProductRepriceResult>,
+}
+
+impl RepriceResult {
+    pub fn repriced(&self) -> Vec<&ProductRepriceResult> {
+        self.products
+            .iter()
+            .filter(|product| product.status == RepriceStatus::Repriced)
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FindCartsWithProductsPayload {
+    pub product_ids: Vec<ProductId>,
+}
+
+/// Products of the list found in cart of the customer
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CustomerCartProducts {
+    pub customer_id: UserId,
+    pub product_ids: Vec<ProductId>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RepricedProduct {
+    pub product_id: ProductId,
+    pub base_product_id: Option<BaseProductId>,
+    pub price: ProductPrice,
+}
+
+/// Products removed from cart of the user since their prices changed
+#[derive(Serialize)]
+pub struct CartProductsRepricedForUser {
+    pub user: EmailUser,
+    pub products: Vec<RepricedProduct>,
+    pub cluster_url: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RepriceOperationStage {
+    ProductPriceUpdateStart(ProductId),
+    ProductPriceUpdateComplete(ProductId),
+    CartsCleanupStart(SagaId),
+    CartsCleanupComplete(SagaId),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_payload_round_trip;
+
+    #[test]
+    fn reprice_round_trip() {
+        assert_payload_round_trip::<RepriceInput>(json!({"prices": [{"product_id": 1, "price": 12.5}]}));
+    }
+}
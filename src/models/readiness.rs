@@ -0,0 +1,93 @@
+use std::fmt;
+use std::time::SystemTime;
+
+/// Dependency saga steps can do without, calls to it are guarded by circuit breaker
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Dependency {
+    /// Emails and other notifications of users and stores
+    Notifications,
+    /// Contacts of marketing campaigns, created through notifications microservice
+    Emarsys,
+}
+
+impl Dependency {
+    pub fn all() -> &'static [Dependency] {
+        &[Dependency::Notifications, Dependency::Emarsys]
+    }
+}
+
+impl fmt::Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Dependency::Notifications => "notifications",
+            Dependency::Emarsys => "emarsys",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Calls are made as usual
+    Closed,
+    /// Calls fail right away, soft steps making them are skipped
+    Open,
+    /// Calls are let through again to find out whether dependency is back
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn all() -> &'static [BreakerState] {
+        &[BreakerState::Closed, BreakerState::Open, BreakerState::HalfOpen]
+    }
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DependencyHealth {
+    pub dependency: Dependency,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessStatus {
+    Ready,
+    /// Coordinator serves requests, but steps calling some of the dependencies are skipped
+    Degraded,
+}
+
+/// Response of readiness probe. Coordinator stays ready while only non critical dependencies are down,
+/// so that replicas are not taken out of service because of them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Readiness {
+    pub status: ReadinessStatus,
+    pub dependencies: Vec<DependencyHealth>,
+}
+
+/// Recent calls of the coordinator to downstream microservice
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DependencyReport {
+    pub service: String,
+    /// `None` for microservices calls to which are not guarded by circuit breaker
+    pub breaker: Option<BreakerState>,
+    /// Calls made within the window
+    pub calls: usize,
+    /// Share of failed calls within the window
+    pub error_rate: f64,
+    pub p95_latency_ms: Option<u64>,
+    pub last_success_at: Option<SystemTime>,
+}
@@ -0,0 +1,948 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use serde_json;
+
+use stq_types::{BaseProductId, ProductId, SagaId, StoreId, UserId};
+
+use super::{
+    CancelOrderItemOperationStage, CatalogImportOperationStage, ChangeCategoryOperationStage, CreateOrderOperationStage,
+    CreatePayoutOperationStage, CreateProfileOperationStage, CreateStoreOperationStage, DisputeOperationStage, OrderReturnOperationStage,
+    RepriceOperationStage, StoreVacationOperationStage, TakedownStoreOperationStage, UpsertShippingOperationStage,
+    VariantsBulkEditOperationStage, VerifyStoreOperationStage,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SagaType {
+    CreateAccount,
+    CreateStore,
+    CreateOrder,
+    BuyNow,
+    UpsertShipping,
+    VerifyStore,
+    CreatePayout,
+    OpenDispute,
+    ResolveDispute,
+    Reprice,
+    CatalogImport,
+    TakedownStore,
+    StoreVacation,
+    ChangeCategory,
+    VariantsBulkEdit,
+    ReturnOrder,
+    ReceiveReturn,
+    CancelOrderItem,
+}
+
+impl fmt::Display for SagaType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            SagaType::CreateAccount => "create_account",
+            SagaType::CreateStore => "create_store",
+            SagaType::CreateOrder => "create_order",
+            SagaType::BuyNow => "buy_now",
+            SagaType::UpsertShipping => "upsert_shipping",
+            SagaType::VerifyStore => "verify_store",
+            SagaType::CreatePayout => "create_payout",
+            SagaType::OpenDispute => "open_dispute",
+            SagaType::ResolveDispute => "resolve_dispute",
+            SagaType::Reprice => "reprice",
+            SagaType::CatalogImport => "catalog_import",
+            SagaType::TakedownStore => "takedown_store",
+            SagaType::StoreVacation => "store_vacation",
+            SagaType::ChangeCategory => "change_category",
+            SagaType::VariantsBulkEdit => "variants_bulk_edit",
+            SagaType::ReturnOrder => "return_order",
+            SagaType::ReceiveReturn => "receive_return",
+            SagaType::CancelOrderItem => "cancel_order_item",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for SagaType {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+    }
+}
+
+impl SagaType {
+    /// Class the saga is executed with in background, e.g. when reaper reverts it
+    pub fn priority(&self) -> SagaPriority {
+        match self {
+            SagaType::CreateOrder
+            | SagaType::BuyNow
+            | SagaType::OpenDispute
+            | SagaType::ResolveDispute
+            | SagaType::ReturnOrder
+            | SagaType::ReceiveReturn
+            | SagaType::CancelOrderItem => SagaPriority::Checkout,
+            SagaType::CreateStore | SagaType::VerifyStore | SagaType::TakedownStore => SagaPriority::Moderation,
+            SagaType::CreateAccount
+            | SagaType::UpsertShipping
+            | SagaType::CreatePayout
+            | SagaType::Reprice
+            | SagaType::CatalogImport
+            | SagaType::StoreVacation
+            | SagaType::ChangeCategory
+            | SagaType::VariantsBulkEdit => SagaPriority::Housekeeping,
+        }
+    }
+}
+
+/// Priority class of background saga execution, from the most urgent one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SagaPriority {
+    /// Sagas customers wait for
+    Checkout,
+    /// Sagas of stores and base products under moderation
+    Moderation,
+    /// Bulk admin batches and cleanups
+    Housekeeping,
+}
+
+impl SagaPriority {
+    pub fn all() -> &'static [SagaPriority] {
+        &[SagaPriority::Checkout, SagaPriority::Moderation, SagaPriority::Housekeeping]
+    }
+}
+
+impl fmt::Display for SagaPriority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            SagaPriority::Checkout => "checkout",
+            SagaPriority::Moderation => "moderation",
+            SagaPriority::Housekeeping => "housekeeping",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SagaStatus {
+    /// Saga is being executed right now
+    InProgress,
+    /// Happy path finished
+    Completed,
+    /// Happy path failed and every created resource was cleaned up
+    Reverted,
+    /// Happy path failed and some of the created resources are still alive
+    RevertFailed,
+    /// Reaper gave up cleaning up resources, manual intervention is needed
+    Orphaned,
+}
+
+impl fmt::Display for SagaStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            SagaStatus::InProgress => "in_progress",
+            SagaStatus::Completed => "completed",
+            SagaStatus::Reverted => "reverted",
+            SagaStatus::RevertFailed => "revert_failed",
+            SagaStatus::Orphaned => "orphaned",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for SagaStatus {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+    }
+}
+
+impl SagaStatus {
+    pub const ALL: &'static [SagaStatus] = &[
+        SagaStatus::InProgress,
+        SagaStatus::Completed,
+        SagaStatus::Reverted,
+        SagaStatus::RevertFailed,
+        SagaStatus::Orphaned,
+    ];
+}
+
+/// Operation stage of any saga, as it is kept in saga store
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "saga", content = "stage", rename_all = "snake_case")]
+pub enum SagaStage {
+    CreateProfile(CreateProfileOperationStage),
+    CreateStore(CreateStoreOperationStage),
+    CreateOrder(CreateOrderOperationStage),
+    UpsertShipping(UpsertShippingOperationStage),
+    VerifyStore(VerifyStoreOperationStage),
+    CreatePayout(CreatePayoutOperationStage),
+    Dispute(DisputeOperationStage),
+    Reprice(RepriceOperationStage),
+    CatalogImport(CatalogImportOperationStage),
+    TakedownStore(TakedownStoreOperationStage),
+    StoreVacation(StoreVacationOperationStage),
+    ChangeCategory(ChangeCategoryOperationStage),
+    VariantsBulkEdit(VariantsBulkEditOperationStage),
+    OrderReturn(OrderReturnOperationStage),
+    CancelOrderItem(CancelOrderItemOperationStage),
+}
+
+impl SagaStage {
+    pub fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            SagaStage::CreateProfile(stage) => stage.step(),
+            SagaStage::CreateStore(stage) => stage.step(),
+            SagaStage::CreateOrder(stage) => stage.step(),
+            SagaStage::UpsertShipping(stage) => stage.step(),
+            SagaStage::VerifyStore(stage) => stage.step(),
+            SagaStage::CreatePayout(stage) => stage.step(),
+            SagaStage::Dispute(stage) => stage.step(),
+            SagaStage::Reprice(stage) => stage.step(),
+            SagaStage::CatalogImport(stage) => stage.step(),
+            SagaStage::TakedownStore(stage) => stage.step(),
+            SagaStage::StoreVacation(stage) => stage.step(),
+            SagaStage::ChangeCategory(stage) => stage.step(),
+            SagaStage::VariantsBulkEdit(stage) => stage.step(),
+            SagaStage::OrderReturn(stage) => stage.step(),
+            SagaStage::CancelOrderItem(stage) => stage.step(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepPhase {
+    /// Written before calling downstream service
+    Start,
+    /// Written after downstream service responded successfully
+    Complete,
+}
+
+/// Conversion between operation stages of particular saga and `SagaStage`.
+/// Stages are persisted in saga logs, changing existing variants requires a migration in `saga::schema`
+pub trait OperationStage: Clone + fmt::Debug + Sized {
+    fn into_saga_stage(self) -> SagaStage;
+    fn from_saga_stage(stage: SagaStage) -> Option<Self>;
+    /// Name of saga step this stage belongs to and its phase
+    fn step(&self) -> (&'static str, StepPhase);
+}
+
+impl OperationStage for CreateProfileOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::CreateProfile(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::CreateProfile(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            CreateProfileOperationStage::AccountCreationStart(_) => ("account_creation", StepPhase::Start),
+            CreateProfileOperationStage::AccountCreationComplete(_) => ("account_creation", StepPhase::Complete),
+            CreateProfileOperationStage::UsersRoleSetStart(_) => ("users_role_set", StepPhase::Start),
+            CreateProfileOperationStage::UsersRoleSetComplete(_) => ("users_role_set", StepPhase::Complete),
+            CreateProfileOperationStage::StoreRoleSetStart(_) => ("store_role_set", StepPhase::Start),
+            CreateProfileOperationStage::StoreRoleSetComplete(_) => ("store_role_set", StepPhase::Complete),
+            CreateProfileOperationStage::BillingRoleSetStart(_) => ("billing_role_set", StepPhase::Start),
+            CreateProfileOperationStage::BillingRoleSetComplete(_) => ("billing_role_set", StepPhase::Complete),
+            CreateProfileOperationStage::DeliveryRoleSetStart(_) => ("delivery_role_set", StepPhase::Start),
+            CreateProfileOperationStage::DeliveryRoleSetComplete(_) => ("delivery_role_set", StepPhase::Complete),
+            CreateProfileOperationStage::BillingCreateMerchantStart(_) => ("billing_create_merchant", StepPhase::Start),
+            CreateProfileOperationStage::BillingCreateMerchantComplete(_) => ("billing_create_merchant", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for CreateStoreOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::CreateStore(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::CreateStore(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            CreateStoreOperationStage::StoreCreationStart(_) => ("store_creation", StepPhase::Start),
+            CreateStoreOperationStage::StoreCreationComplete(_) => ("store_creation", StepPhase::Complete),
+            CreateStoreOperationStage::WarehousesRoleSetStart(_) => ("warehouses_role_set", StepPhase::Start),
+            CreateStoreOperationStage::WarehousesRoleSetComplete(_) => ("warehouses_role_set", StepPhase::Complete),
+            CreateStoreOperationStage::OrdersRoleSetStart(_) => ("orders_role_set", StepPhase::Start),
+            CreateStoreOperationStage::OrdersRoleSetComplete(_) => ("orders_role_set", StepPhase::Complete),
+            CreateStoreOperationStage::BillingRoleSetStart(_) => ("billing_role_set", StepPhase::Start),
+            CreateStoreOperationStage::BillingRoleSetComplete(_) => ("billing_role_set", StepPhase::Complete),
+            CreateStoreOperationStage::DeliveryRoleSetStart(_) => ("delivery_role_set", StepPhase::Start),
+            CreateStoreOperationStage::DeliveryRoleSetComplete(_) => ("delivery_role_set", StepPhase::Complete),
+            CreateStoreOperationStage::BillingCreateMerchantStart(_) => ("billing_create_merchant", StepPhase::Start),
+            CreateStoreOperationStage::BillingCreateMerchantComplete(_) => ("billing_create_merchant", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for CreateOrderOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::CreateOrder(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::CreateOrder(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            CreateOrderOperationStage::OrdersConvertCartStart(_) => ("orders_convert_cart", StepPhase::Start),
+            CreateOrderOperationStage::OrdersConvertCartComplete(_) => ("orders_convert_cart", StepPhase::Complete),
+            CreateOrderOperationStage::BillingCreateInvoiceStart(_) => ("billing_create_invoice", StepPhase::Start),
+            CreateOrderOperationStage::BillingCreateInvoiceComplete(_) => ("billing_create_invoice", StepPhase::Complete),
+            CreateOrderOperationStage::BillingReserveGiftCardsStart(_) => ("billing_reserve_gift_cards", StepPhase::Start),
+            CreateOrderOperationStage::BillingReserveGiftCardsComplete(_) => ("billing_reserve_gift_cards", StepPhase::Complete),
+            CreateOrderOperationStage::BillingCalculateTaxesStart(_) => ("billing_calculate_taxes", StepPhase::Start),
+            CreateOrderOperationStage::BillingCalculateTaxesComplete(_) => ("billing_calculate_taxes", StepPhase::Complete),
+            CreateOrderOperationStage::FraudScreeningStart(_) => ("fraud_screening", StepPhase::Start),
+            CreateOrderOperationStage::FraudScreeningComplete(_) => ("fraud_screening", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for UpsertShippingOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::UpsertShipping(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::UpsertShipping(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            UpsertShippingOperationStage::ShippingUpsertStart(_) => ("shipping_upsert", StepPhase::Start),
+            UpsertShippingOperationStage::ShippingUpsertComplete(_) => ("shipping_upsert", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for VerifyStoreOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::VerifyStore(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::VerifyStore(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            VerifyStoreOperationStage::StoreVerificationStart(_) => ("store_verification", StepPhase::Start),
+            VerifyStoreOperationStage::StoreVerificationComplete(_) => ("store_verification", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for CreatePayoutOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::CreatePayout(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::CreatePayout(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            CreatePayoutOperationStage::PayoutCreationStart(_) => ("billing_create_payout", StepPhase::Start),
+            CreatePayoutOperationStage::PayoutCreationComplete(_) => ("billing_create_payout", StepPhase::Complete),
+            CreatePayoutOperationStage::OrderPaidToSellerStart(_, _) => ("billing_payment_state_set", StepPhase::Start),
+            CreatePayoutOperationStage::OrderPaidToSellerComplete(_) => ("billing_payment_state_set", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for DisputeOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::Dispute(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::Dispute(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            DisputeOperationStage::DisputeRegistrationStart(_) => ("billing_dispute_registration", StepPhase::Start),
+            DisputeOperationStage::DisputeRegistrationComplete(_) => ("billing_dispute_registration", StepPhase::Complete),
+            DisputeOperationStage::OrderFreezeStart(_, _) => ("orders_freeze", StepPhase::Start),
+            DisputeOperationStage::OrderFreezeComplete(_) => ("orders_freeze", StepPhase::Complete),
+            DisputeOperationStage::StockReturnStart(_) => ("warehouses_stock_return", StepPhase::Start),
+            DisputeOperationStage::StockReturnComplete(_) => ("warehouses_stock_return", StepPhase::Complete),
+            DisputeOperationStage::OrderUnfreezeStart(_) => ("orders_unfreeze", StepPhase::Start),
+            DisputeOperationStage::OrderUnfreezeComplete(_) => ("orders_unfreeze", StepPhase::Complete),
+            DisputeOperationStage::DisputeResolutionStart(_) => ("billing_dispute_resolution", StepPhase::Start),
+            DisputeOperationStage::DisputeResolutionComplete(_) => ("billing_dispute_resolution", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for RepriceOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::Reprice(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::Reprice(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            RepriceOperationStage::ProductPriceUpdateStart(_) => ("stores_product_price_update", StepPhase::Start),
+            RepriceOperationStage::ProductPriceUpdateComplete(_) => ("stores_product_price_update", StepPhase::Complete),
+            RepriceOperationStage::CartsCleanupStart(_) => ("orders_carts_cleanup", StepPhase::Start),
+            RepriceOperationStage::CartsCleanupComplete(_) => ("orders_carts_cleanup", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for CatalogImportOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::CatalogImport(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::CatalogImport(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            CatalogImportOperationStage::BaseProductCreationStart(_) => ("stores_base_product_creation", StepPhase::Start),
+            CatalogImportOperationStage::BaseProductCreationComplete(_, _) => ("stores_base_product_creation", StepPhase::Complete),
+            CatalogImportOperationStage::ShippingUpsertStart(_) => ("delivery_shipping_upsert", StepPhase::Start),
+            CatalogImportOperationStage::ShippingUpsertComplete(_) => ("delivery_shipping_upsert", StepPhase::Complete),
+            CatalogImportOperationStage::StockSetStart(_, _) => ("warehouses_stock_set", StepPhase::Start),
+            CatalogImportOperationStage::StockSetComplete(_) => ("warehouses_stock_set", StepPhase::Complete),
+            CatalogImportOperationStage::BaseProductCleanupComplete(_) => ("stores_base_product_cleanup", StepPhase::Complete),
+            CatalogImportOperationStage::RowImported(_) => ("catalog_row_report", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for TakedownStoreOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::TakedownStore(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::TakedownStore(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            TakedownStoreOperationStage::StoreDeactivationStart(_) => ("stores_store_deactivation", StepPhase::Start),
+            TakedownStoreOperationStage::StoreDeactivationComplete(_) => ("stores_store_deactivation", StepPhase::Complete),
+            TakedownStoreOperationStage::StoreAnonymizationStart(_, _) => ("stores_store_anonymization", StepPhase::Start),
+            TakedownStoreOperationStage::StoreAnonymizationComplete(_) => ("stores_store_anonymization", StepPhase::Complete),
+            TakedownStoreOperationStage::LegalHoldStart(_, _) => ("audit_legal_hold", StepPhase::Start),
+            TakedownStoreOperationStage::LegalHoldComplete(_) => ("audit_legal_hold", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for StoreVacationOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::StoreVacation(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::StoreVacation(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            StoreVacationOperationStage::ProductsHidingStart(_) => ("stores_products_hiding", StepPhase::Start),
+            StoreVacationOperationStage::ProductsHidingComplete(_) => ("stores_products_hiding", StepPhase::Complete),
+            StoreVacationOperationStage::VacationRecordStart(_) => ("vacation_record", StepPhase::Start),
+            StoreVacationOperationStage::VacationRecordComplete(_) => ("vacation_record", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for ChangeCategoryOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::ChangeCategory(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::ChangeCategory(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            ChangeCategoryOperationStage::CategoryUpdateStart(_, _) => ("stores_category_update", StepPhase::Start),
+            ChangeCategoryOperationStage::CategoryUpdateComplete(_) => ("stores_category_update", StepPhase::Complete),
+            ChangeCategoryOperationStage::ShippingRecalculationStart(_, _) => ("delivery_shipping_recalculation", StepPhase::Start),
+            ChangeCategoryOperationStage::ShippingRecalculationComplete(_) => ("delivery_shipping_recalculation", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for VariantsBulkEditOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::VariantsBulkEdit(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::VariantsBulkEdit(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            VariantsBulkEditOperationStage::VariantCreationStart(_) => ("stores_variant_creation", StepPhase::Start),
+            VariantsBulkEditOperationStage::VariantCreationComplete(_, _) => ("stores_variant_creation", StepPhase::Complete),
+            VariantsBulkEditOperationStage::StockSetStart(_, _) => ("warehouses_stock_set", StepPhase::Start),
+            VariantsBulkEditOperationStage::StockSetComplete(_) => ("warehouses_stock_set", StepPhase::Complete),
+            VariantsBulkEditOperationStage::VariantUpdateStart(_) => ("stores_variant_update", StepPhase::Start),
+            VariantsBulkEditOperationStage::VariantUpdateComplete(_) => ("stores_variant_update", StepPhase::Complete),
+            VariantsBulkEditOperationStage::VariantDeactivationStart(_) => ("stores_variant_deactivation", StepPhase::Start),
+            VariantsBulkEditOperationStage::VariantDeactivationComplete(_) => ("stores_variant_deactivation", StepPhase::Complete),
+            VariantsBulkEditOperationStage::VariantCleanupComplete(_) => ("stores_variant_cleanup", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for OrderReturnOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::OrderReturn(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::OrderReturn(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            OrderReturnOperationStage::ReturnCreationStart(_) => ("orders_return_creation", StepPhase::Start),
+            OrderReturnOperationStage::ReturnCreationComplete(_) => ("orders_return_creation", StepPhase::Complete),
+            OrderReturnOperationStage::RefundRequestStart(_, _) => ("billing_payment_state_set", StepPhase::Start),
+            OrderReturnOperationStage::RefundRequestComplete(_) => ("billing_payment_state_set", StepPhase::Complete),
+            OrderReturnOperationStage::StockReturnStart(_) => ("warehouses_stock_return", StepPhase::Start),
+            OrderReturnOperationStage::StockReturnComplete(_) => ("warehouses_stock_return", StepPhase::Complete),
+            OrderReturnOperationStage::ReturnReceiptStart(_) => ("orders_return_receipt", StepPhase::Start),
+            OrderReturnOperationStage::ReturnReceiptComplete(_) => ("orders_return_receipt", StepPhase::Complete),
+        }
+    }
+}
+
+impl OperationStage for CancelOrderItemOperationStage {
+    fn into_saga_stage(self) -> SagaStage {
+        SagaStage::CancelOrderItem(self)
+    }
+
+    fn from_saga_stage(stage: SagaStage) -> Option<Self> {
+        match stage {
+            SagaStage::CancelOrderItem(stage) => Some(stage),
+            _ => None,
+        }
+    }
+
+    fn step(&self) -> (&'static str, StepPhase) {
+        match self {
+            CancelOrderItemOperationStage::OrderCancelStart(_, _) => ("orders_cancel", StepPhase::Start),
+            CancelOrderItemOperationStage::OrderCancelComplete(_) => ("orders_cancel", StepPhase::Complete),
+            CancelOrderItemOperationStage::StockReturnStart(_) => ("warehouses_stock_return", StepPhase::Start),
+            CancelOrderItemOperationStage::StockReturnComplete(_) => ("warehouses_stock_return", StepPhase::Complete),
+            CancelOrderItemOperationStage::InvoiceAmendStart(_) => ("billing_invoice_amend", StepPhase::Start),
+            CancelOrderItemOperationStage::InvoiceAmendComplete(_) => ("billing_invoice_amend", StepPhase::Complete),
+        }
+    }
+}
+
+/// Idempotency marker of saga log entry, tells how the entry got into the log
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepMarker {
+    /// Written by saga itself while executing the step
+    Executed,
+    /// Completion of interrupted step, confirmed by querying downstream service during recovery
+    Recovered,
+    /// Downstream service reported that interrupted step has not taken effect
+    NotApplied,
+}
+
+impl Default for StepMarker {
+    fn default() -> Self {
+        StepMarker::Executed
+    }
+}
+
+/// Stage of saga together with the result it produced
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SagaLogEntry {
+    pub stage: SagaStage,
+    /// Snapshot of the entity returned by downstream service on this stage
+    pub result: Option<serde_json::Value>,
+    #[serde(default)]
+    pub marker: StepMarker,
+    pub recorded_at: SystemTime,
+}
+
+impl SagaLogEntry {
+    pub fn new(stage: SagaStage, result: Option<serde_json::Value>) -> Self {
+        Self {
+            stage,
+            result,
+            marker: StepMarker::Executed,
+            recorded_at: SystemTime::now(),
+        }
+    }
+
+    pub fn recovered(stage: SagaStage, result: Option<serde_json::Value>) -> Self {
+        Self {
+            marker: StepMarker::Recovered,
+            ..Self::new(stage, result)
+        }
+    }
+
+    pub fn not_applied(stage: SagaStage) -> Self {
+        Self {
+            marker: StepMarker::NotApplied,
+            ..Self::new(stage, None)
+        }
+    }
+}
+
+/// Failure of soft step, which does not fail the saga
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SagaWarning {
+    pub step: String,
+    pub message: String,
+    pub recorded_at: SystemTime,
+}
+
+impl SagaWarning {
+    pub fn new(step: &str, message: String) -> Self {
+        Self {
+            step: step.to_string(),
+            message,
+            recorded_at: SystemTime::now(),
+        }
+    }
+}
+
+/// Step performed with superadmin rights instead of rights of the caller
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SagaEscalation {
+    pub step: String,
+    pub reason: String,
+    /// Caller the step was performed on behalf of, `None` for anonymous callers
+    pub caller: Option<UserId>,
+    pub recorded_at: SystemTime,
+}
+
+impl SagaEscalation {
+    pub fn new(step: &str, reason: &str, caller: Option<UserId>) -> Self {
+        Self {
+            step: step.to_string(),
+            reason: reason.to_string(),
+            caller,
+            recorded_at: SystemTime::now(),
+        }
+    }
+}
+
+/// Claim of saga by the process executing it in background. Process renews the claim
+/// while the saga runs, saga with expired claim is resumed by reaper, e.g. after restart.
+/// Claims are handed over between replicas only if they are shared, see `saga::shared`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SagaClaim {
+    pub owner: String,
+    pub heartbeat_at: SystemTime,
+}
+
+impl SagaClaim {
+    pub fn new(owner: &str) -> Self {
+        Self {
+            owner: owner.to_string(),
+            heartbeat_at: SystemTime::now(),
+        }
+    }
+
+    pub fn is_expired(&self, lease: Duration, now: SystemTime) -> bool {
+        now.duration_since(self.heartbeat_at)
+            .map(|elapsed| elapsed >= lease)
+            .unwrap_or(false)
+    }
+}
+
+/// Entity changed by saga, sagas changing the same entity must not run concurrently
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "type", content = "id", rename_all = "snake_case")]
+pub enum LockedEntity {
+    Store(StoreId),
+    BaseProduct(BaseProductId),
+    Product(ProductId),
+}
+
+impl fmt::Display for LockedEntity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LockedEntity::Store(id) => write!(f, "store {}", id),
+            LockedEntity::BaseProduct(id) => write!(f, "base product {}", id),
+            LockedEntity::Product(id) => write!(f, "product {}", id),
+        }
+    }
+}
+
+/// Advisory lock of entity held by saga
+#[derive(Clone, Debug)]
+pub struct EntityLock {
+    pub saga_id: SagaId,
+    pub acquired_at: SystemTime,
+}
+
+impl EntityLock {
+    pub fn new(saga_id: SagaId) -> Self {
+        Self {
+            saga_id,
+            acquired_at: SystemTime::now(),
+        }
+    }
+
+    pub fn is_expired(&self, lease: Duration, now: SystemTime) -> bool {
+        now.duration_since(self.acquired_at)
+            .map(|elapsed| elapsed >= lease)
+            .unwrap_or(false)
+    }
+}
+
+/// Response of saga endpoints, carries warnings of failed soft steps along with the result
+#[derive(Clone, Debug, Serialize)]
+pub struct SagaResponse<T> {
+    #[serde(skip)]
+    pub saga_id: SagaId,
+    #[serde(flatten)]
+    pub result: T,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<SagaWarning>,
+}
+
+impl<T> SagaResponse<T> {
+    pub fn into_envelope(self) -> SagaEnvelope<T> {
+        SagaEnvelope {
+            saga_id: self.saga_id,
+            result: self.result,
+            warnings: self.warnings,
+        }
+    }
+}
+
+/// Structured response of saga endpoints exposed by v2 of api
+#[derive(Clone, Debug, Serialize)]
+pub struct SagaEnvelope<T> {
+    pub saga_id: SagaId,
+    pub result: T,
+    pub warnings: Vec<SagaWarning>,
+}
+
+/// Compensation of a single step which could not be carried out
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompensationFailure {
+    pub stage: SagaStage,
+    pub error: String,
+}
+
+/// Outcome of a single compensation attempt of saga. Every step is compensated
+/// regardless of failures of the others, so the report lists all of them.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompensationReport {
+    pub compensated: Vec<SagaStage>,
+    pub failures: Vec<CompensationFailure>,
+    pub finished_at: SystemTime,
+}
+
+impl Default for CompensationReport {
+    fn default() -> Self {
+        Self {
+            compensated: vec![],
+            failures: vec![],
+            finished_at: SystemTime::now(),
+        }
+    }
+}
+
+impl CompensationReport {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl fmt::Display for CompensationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} compensations failed",
+            self.failures.len(),
+            self.failures.len() + self.compensated.len()
+        )?;
+        for (i, failure) in self.failures.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { "; " };
+            write!(f, "{}{:?}: {}", separator, failure.stage, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Http request received by the coordinator
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path with query
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+/// Call to microservice along with its outcome
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordedCall {
+    pub method: String,
+    pub url: String,
+    pub payload: Option<String>,
+    /// Response of successful call
+    pub response: Option<serde_json::Value>,
+    /// Error of failed call
+    pub error: Option<String>,
+}
+
+/// Persisted operation log of a single saga execution
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SagaRecord {
+    pub id: SagaId,
+    pub saga_type: SagaType,
+    pub status: SagaStatus,
+    pub stages: Vec<SagaLogEntry>,
+    /// Reports of every compensation attempt, the last one is the most recent
+    #[serde(default)]
+    pub compensations: Vec<CompensationReport>,
+    #[serde(default)]
+    pub warnings: Vec<SagaWarning>,
+    #[serde(default)]
+    pub escalations: Vec<SagaEscalation>,
+    /// Set for sagas executed in background, `None` once they are finished
+    #[serde(default)]
+    pub claim: Option<SagaClaim>,
+    /// Input of saga executed in background, reaper of this or another replica resumes the saga with it
+    #[serde(default)]
+    pub input: Option<serde_json::Value>,
+    /// Request the saga was started with, recorded for replay if `saga.record_calls` is set
+    #[serde(default)]
+    pub request: Option<RecordedRequest>,
+    /// Calls to microservices made by the saga, recorded for replay if `saga.record_calls` is set
+    #[serde(default)]
+    pub calls: Vec<RecordedCall>,
+    /// Urls of microservices switched between deployments the saga was started with, by name of the microservice
+    #[serde(default)]
+    pub endpoints: BTreeMap<String, String>,
+    pub revert_attempts: u32,
+    pub last_error: Option<String>,
+    pub created_at: SystemTime,
+    pub updated_at: SystemTime,
+}
+
+impl SagaRecord {
+    pub fn new(id: SagaId, saga_type: SagaType) -> Self {
+        let now = SystemTime::now();
+        Self {
+            id,
+            saga_type,
+            status: SagaStatus::InProgress,
+            stages: vec![],
+            compensations: vec![],
+            warnings: vec![],
+            escalations: vec![],
+            claim: None,
+            input: None,
+            request: None,
+            calls: vec![],
+            endpoints: BTreeMap::new(),
+            revert_attempts: 0,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    /// Returns start stages of steps which were interrupted before completion
+    pub fn pending_steps(&self) -> Vec<SagaStage> {
+        let resolved = self
+            .stages
+            .iter()
+            .filter(|entry| entry.marker == StepMarker::NotApplied || entry.stage.step().1 == StepPhase::Complete)
+            .map(|entry| entry.stage.step().0)
+            .collect::<Vec<_>>();
+
+        self.stages
+            .iter()
+            .filter(|entry| entry.marker != StepMarker::NotApplied && entry.stage.step().1 == StepPhase::Start)
+            .filter(|entry| !resolved.contains(&entry.stage.step().0))
+            .map(|entry| entry.stage.clone())
+            .collect()
+    }
+
+    /// Returns snapshot of the first result recorded for the stage matching predicate
+    pub fn find_result<F>(&self, predicate: F) -> Option<&serde_json::Value>
+    where
+        F: Fn(&SagaStage) -> bool,
+    {
+        self.stages
+            .iter()
+            .filter(|entry| predicate(&entry.stage))
+            .filter_map(|entry| entry.result.as_ref())
+            .next()
+    }
+}
@@ -0,0 +1,20 @@
+use stq_api::orders::Order;
+use stq_api::warehouses::Stock;
+use stq_types::PaymentState;
+
+use super::{Invoice, SagaRecord};
+
+/// Everything the coordinator can gather about a single order, attached to support tickets
+#[derive(Debug, Serialize)]
+pub struct OrderDebugBundle {
+    pub order: Order,
+    pub payment_state: PaymentState,
+    /// Invoices created by sagas of the order
+    pub invoices: Vec<Invoice>,
+    /// Stocks of the ordered product in warehouses
+    pub stocks: Vec<Stock>,
+    /// Sagas whose logs mention the order, the latest first
+    pub sagas: Vec<SagaRecord>,
+    /// Parts of the bundle that could not be gathered, e.g. while billing is down
+    pub errors: Vec<String>,
+}
@@ -0,0 +1,142 @@
+use std::hash::{Hash, Hasher};
+
+use serde_json;
+use validator::Validate;
+
+use stq_types::StoreId;
+
+use super::Store;
+
+/// Payload of legal takedown of store
+#[derive(Serialize, Deserialize, Clone, Debug, Validate)]
+pub struct StoreTakedown {
+    /// Legal ground of the takedown, e.g. court order number, kept in audit trail
+    #[validate(length(min = "1", message = "Reason must not be empty"))]
+    pub reason: String,
+}
+
+/// Public fields of store anonymized by takedown, fields left out are not changed in stores
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StorePublicFields {
+    pub name: serde_json::Value,
+    pub short_description: serde_json::Value,
+    pub long_description: Option<serde_json::Value>,
+    pub slogan: Option<String>,
+    pub cover: Option<String>,
+    pub logo: Option<String>,
+    pub phone: Option<String>,
+    pub email: Option<String>,
+    pub address: Option<String>,
+    pub facebook_url: Option<String>,
+    pub twitter_url: Option<String>,
+    pub instagram_url: Option<String>,
+}
+
+impl StorePublicFields {
+    pub fn of(store: &Store) -> Self {
+        Self {
+            name: store.name.clone(),
+            short_description: store.short_description.clone(),
+            long_description: store.long_description.clone(),
+            slogan: store.slogan.clone(),
+            cover: store.cover.clone(),
+            logo: store.logo.clone(),
+            phone: store.phone.clone(),
+            email: store.email.clone(),
+            address: store.address.clone(),
+            facebook_url: store.facebook_url.clone(),
+            twitter_url: store.twitter_url.clone(),
+            instagram_url: store.instagram_url.clone(),
+        }
+    }
+
+    /// Fields replacing public fields of the store, fields the store has not filled in are left out
+    pub fn anonymized(store: &Store) -> Self {
+        let blank = |field: &Option<String>| field.as_ref().map(|_| String::new());
+        Self {
+            name: json!([{"lang": store.default_language, "text": format!("Store {}", store.id)}]),
+            short_description: json!([{"lang": store.default_language, "text": ""}]),
+            long_description: store
+                .long_description
+                .as_ref()
+                .map(|_| json!([{"lang": store.default_language, "text": ""}])),
+            slogan: blank(&store.slogan),
+            cover: blank(&store.cover),
+            logo: blank(&store.logo),
+            phone: blank(&store.phone),
+            email: blank(&store.email),
+            address: blank(&store.address),
+            facebook_url: blank(&store.facebook_url),
+            twitter_url: blank(&store.twitter_url),
+            instagram_url: blank(&store.instagram_url),
+        }
+    }
+}
+
+/// Translations are json values, which are not hashable, so fields are hashed as they are persisted
+impl Hash for StorePublicFields {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        serde_json::to_string(self).unwrap_or_default().hash(state);
+    }
+}
+
+/// Marker of legal hold put on store by takedown, released if the takedown is reverted
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LegalHold {
+    pub store_id: StoreId,
+    pub reason: String,
+    pub released: bool,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TakedownStoreOperationStage {
+    StoreDeactivationStart(StoreId),
+    StoreDeactivationComplete(StoreId),
+    /// Original public fields of the store are kept to be restored by compensation
+    StoreAnonymizationStart(StoreId, StorePublicFields),
+    StoreAnonymizationComplete(StoreId),
+    LegalHoldStart(StoreId, String),
+    LegalHoldComplete(StoreId),
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::{StorePublicFields, StoreTakedown};
+    use models::round_trip::assert_payload_round_trip;
+    use models::Store;
+
+    #[test]
+    fn anonymizes_only_filled_in_fields() {
+        let store: Store = serde_json::from_value(json!({
+            "id": 7,
+            "user_id": 1,
+            "is_active": true,
+            "name": [{"lang": "en", "text": "Tea Shop"}],
+            "short_description": [{"lang": "en", "text": "Best tea in town"}],
+            "slug": "tea-shop",
+            "phone": "+79990001122",
+            "facebook_url": "https://facebook.com/teashop",
+            "created_at": {"secs_since_epoch": 0, "nanos_since_epoch": 0},
+            "updated_at": {"secs_since_epoch": 0, "nanos_since_epoch": 0},
+            "default_language": "en",
+            "rating": 5.0,
+            "status": "published",
+        }))
+        .unwrap();
+
+        let fields = StorePublicFields::anonymized(&store);
+        assert_eq!(fields.name, json!([{"lang": "en", "text": "Store 7"}]));
+        assert_eq!(fields.phone, Some(String::new()));
+        assert_eq!(fields.facebook_url, Some(String::new()));
+        assert_eq!(fields.email, None);
+        assert_eq!(fields.long_description, None);
+        assert_eq!(StorePublicFields::of(&store).phone, store.phone);
+    }
+
+    #[test]
+    fn takedown_round_trip() {
+        assert_payload_round_trip::<StoreTakedown>(json!({"reason": "Court order 1"}));
+    }
+}
@@ -0,0 +1,68 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+
+use serde::de::{Deserialize, Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+thread_local! {
+    static COLLECTED: RefCell<Option<Vec<String>>> = RefCell::new(None);
+}
+
+/// Fields of payload left over by its `#[serde(flatten)]` fields. Serde buffers them for flattened fields,
+/// so they are not reported as unknown like fields of other payloads. Declared after flattened fields of the payload,
+/// they are collected by `collect_unknown_fields` and payload having them is rejected in strict mode.
+/// Leftovers are not serialized, so that they are not passed on to microservices.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnknownFields(pub Vec<String>);
+
+impl UnknownFields {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Runs deserialization collecting names of fields left over by flattened fields of payloads on the way
+pub fn collect_unknown_fields<T, F: FnOnce() -> T>(deserialize: F) -> (T, Vec<String>) {
+    let outer = COLLECTED.with(|collected| mem::replace(&mut *collected.borrow_mut(), Some(vec![])));
+    let res = deserialize();
+    let fields = COLLECTED.with(|collected| mem::replace(&mut *collected.borrow_mut(), outer).unwrap_or_default());
+    (res, fields)
+}
+
+impl<'de> Deserialize<'de> for UnknownFields {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct FieldsVisitor;
+
+        impl<'de> Visitor<'de> for FieldsVisitor {
+            type Value = Vec<String>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("fields left over by flattened fields")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut fields = vec![];
+                while let Some(field) = map.next_key::<String>()? {
+                    map.next_value::<IgnoredAny>()?;
+                    fields.push(field);
+                }
+                Ok(fields)
+            }
+        }
+
+        let fields = deserializer.deserialize_map(FieldsVisitor)?;
+        COLLECTED.with(|collected| {
+            if let Some(ref mut collected) = *collected.borrow_mut() {
+                collected.extend(fields.iter().cloned());
+            }
+        });
+        Ok(UnknownFields(fields))
+    }
+}
+
+impl Serialize for UnknownFields {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_map(Some(0))?.end()
+    }
+}
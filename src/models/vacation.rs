@@ -0,0 +1,57 @@
+use chrono::{DateTime, Utc};
+
+use stq_types::StoreId;
+
+/// Payload of vacation mode of store
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoreVacationInput {
+    /// Products of the store are back in search once vacation ends
+    pub ends_at: DateTime<Utc>,
+}
+
+/// Store on vacation, its products are hidden from search until `ends_at`
+#[derive(Serialize, Deserialize, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct StoreVacation {
+    pub store_id: StoreId,
+    pub starts_at: DateTime<Utc>,
+    pub ends_at: DateTime<Utc>,
+}
+
+impl StoreVacation {
+    pub fn new(store_id: StoreId, ends_at: DateTime<Utc>) -> Self {
+        Self {
+            store_id,
+            starts_at: Utc::now(),
+            ends_at,
+        }
+    }
+
+    pub fn is_over(&self, now: DateTime<Utc>) -> bool {
+        self.ends_at <= now
+    }
+}
+
+/// Payload hiding products of store from search or bringing them back
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SearchVisibility {
+    pub hidden: bool,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StoreVacationOperationStage {
+    ProductsHidingStart(StoreId),
+    ProductsHidingComplete(StoreId),
+    VacationRecordStart(StoreVacation),
+    VacationRecordComplete(StoreId),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use models::round_trip::assert_payload_round_trip;
+
+    #[test]
+    fn vacation_round_trip() {
+        assert_payload_round_trip::<StoreVacationInput>(json!({"ends_at": "2018-01-01T00:00:00Z"}));
+    }
+}
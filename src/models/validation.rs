@@ -0,0 +1,70 @@
+//! Custom validators of inbound payloads, used with `#[derive(Validate)]`
+use std::borrow::Cow;
+
+use validator::ValidationError;
+
+use super::CartProductWithPriceHash;
+
+/// Phone number in international format: optional `+` followed by 7 to 15 digits
+pub fn validate_phone(phone: &str) -> Result<(), ValidationError> {
+    let digits = phone.trim_left_matches('+');
+    let is_valid = phone.len() - digits.len() <= 1 && digits.len() >= 7 && digits.len() <= 15 && digits.chars().all(|c| c.is_ascii_digit());
+    if is_valid {
+        Ok(())
+    } else {
+        Err(error("phone", "Incorrect phone format"))
+    }
+}
+
+/// Lowercase latin letters and digits separated by single hyphens
+pub fn validate_slug(slug: &str) -> Result<(), ValidationError> {
+    let is_valid = !slug.is_empty()
+        && slug
+            .split('-')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit()));
+    if is_valid {
+        Ok(())
+    } else {
+        Err(error(
+            "slug",
+            "Slug may contain only lowercase latin letters, digits and single hyphens",
+        ))
+    }
+}
+
+pub fn validate_prices(prices: &CartProductWithPriceHash) -> Result<(), ValidationError> {
+    if prices.is_empty() {
+        Err(error("prices", "Cart has no products with prices"))
+    } else {
+        Ok(())
+    }
+}
+
+fn error(code: &'static str, message: &'static str) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::from(message));
+    error
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_phone() {
+        assert!(validate_phone("+79001234567").is_ok());
+        assert!(validate_phone("79001234567").is_ok());
+        assert!(validate_phone("++79001234567").is_err());
+        assert!(validate_phone("+7 900 123").is_err());
+        assert!(validate_phone("123").is_err());
+    }
+
+    #[test]
+    fn validates_slug() {
+        assert!(validate_slug("my-store-1").is_ok());
+        assert!(validate_slug("My-Store").is_err());
+        assert!(validate_slug("my--store").is_err());
+        assert!(validate_slug("-store").is_err());
+        assert!(validate_slug("").is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use stq_types::{ProductId, SagaId, StoreId};
+
+use super::{CreateProductWithAttributes, ProdAttrValue};
+
+/// Changes of variants of base product made in one request
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct VariantsBulkEdit {
+    #[serde(default)]
+    pub add: Vec<CreateProductWithAttributes>,
+    #[serde(default)]
+    pub edit: Vec<VariantEdit>,
+    #[serde(default)]
+    pub remove: Vec<ProductId>,
+}
+
+impl VariantsBulkEdit {
+    pub fn is_empty(&self) -> bool {
+        self.add.is_empty() && self.edit.is_empty() && self.remove.is_empty()
+    }
+
+    /// Edited or removed variants which are not among `existing` variants of the base product
+    pub fn unknown_product_ids(&self, existing: &[ProductId]) -> Vec<ProductId> {
+        self.edit
+            .iter()
+            .map(|variant| variant.product_id)
+            .chain(self.remove.iter().cloned())
+            .filter(|product_id| !existing.contains(product_id))
+            .collect()
+    }
+
+    /// Variants both edited and removed, or given several times
+    pub fn repeated_product_ids(&self) -> Vec<ProductId> {
+        let mut seen = vec![];
+        let mut repeated = vec![];
+        for product_id in self
+            .edit
+            .iter()
+            .map(|variant| variant.product_id)
+            .chain(self.remove.iter().cloned())
+        {
+            if seen.contains(&product_id) {
+                repeated.push(product_id);
+            } else {
+                seen.push(product_id);
+            }
+        }
+        repeated
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VariantEdit {
+    pub product_id: ProductId,
+    #[serde(flatten)]
+    pub product: UpdateProduct,
+    /// Fields unknown to the variant, must stay after `product`
+    #[serde(flatten)]
+    pub unknown: UnknownFields,
+}
+
+/// Fields of variant left unset are not changed
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct UpdateProduct {
+    pub discount: Option<f64>,
+    pub photo_main: Option<String>,
+    pub additional_photos: Option<Vec<String>>,
+    pub vendor_code: Option<String>,
+    pub cashback: Option<f64>,
+    pub price: Option<f64>,
+    pub pre_order: Option<bool>,
+    pub pre_order_days: Option<i32>,
+    pub attributes: Option<Vec<ProdAttrValue>>,
+}
+
+/// Update of variant made by saga, stores keeps previous fields of the variant to restore them on revert
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UpdateProductWithSaga {
+    #[serde(flatten)]
+    pub product: UpdateProduct,
+    pub saga_id: SagaId,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VariantAction {
+    Add,
+    Edit,
+    Remove,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum VariantStatus {
+    Done,
+    /// Nothing of the variant change is left in stores and warehouses
+    Failed,
+}
+
+/// Outcome of a single variant change, `index` is the position of the change in its list of the request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VariantResult {
+    pub action: VariantAction,
+    pub index: usize,
+    pub product_id: Option<ProductId>,
+    pub status: VariantStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VariantsBulkEditOperationStage {
+    /// Keeps uuid of variant, variant is looked up by it in `VariantCreationComplete`
+    VariantCreationStart(String),
+    VariantCreationComplete(String, ProductId),
+    StockSetStart(StoreId, ProductId),
+    StockSetComplete(ProductId),
+    VariantUpdateStart(ProductId),
+    VariantUpdateComplete(ProductId),
+    VariantDeactivationStart(ProductId),
+    VariantDeactivationComplete(ProductId),
+    /// Leftovers of failed variant were removed right away, they are not reverted along with the saga
+    VariantCleanupComplete(ProductId),
+}
+
+#[cfg(test)]
+mod tests {
+    use stq_types::ProductId;
+
+    use super::*;
+    use models::round_trip::assert_payload_round_trip;
+
+    fn edit(product_id: i32) -> VariantEdit {
+        VariantEdit {
+            product_id: ProductId(product_id),
+            product: UpdateProduct::default(),
+            unknown: UnknownFields::default(),
+        }
+    }
+
+    #[test]
+    fn checks_changed_variants_against_existing() {
+        let input = VariantsBulkEdit {
+            add: vec![],
+            edit: vec![edit(1), edit(2)],
+            remove: vec![ProductId(2), ProductId(5)],
+        };
+        assert!(!input.is_empty());
+        assert_eq!(input.unknown_product_ids(&[ProductId(1), ProductId(2)]), vec![ProductId(5)]);
+        assert_eq!(input.repeated_product_ids(), vec![ProductId(2)]);
+    }
+
+    #[test]
+    fn bulk_edit_round_trip() {
+        assert_payload_round_trip::<VariantsBulkEdit>(json!({"edit": [{"product_id": 1, "price": 11.0}], "remove": [2]}));
+    }
+}
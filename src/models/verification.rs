@@ -0,0 +1,35 @@
+use stq_types::StoreId;
+
+/// KYC status of store merchant as reported by billing
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KycStatus {
+    NotStarted,
+    Pending,
+    Verified,
+    Rejected,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoreKycStatus {
+    pub store_id: StoreId,
+    pub status: KycStatus,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoreVerification {
+    pub verified: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoreVerifiedForUser {
+    pub store_email: String,
+    pub store_id: String,
+    pub cluster_url: String,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VerifyStoreOperationStage {
+    StoreVerificationStart(StoreId),
+    StoreVerificationComplete(StoreId),
+}
@@ -1,6 +1,6 @@
 use geo::Point as GeoPoint;
 
-use stq_types::{Alpha3, StoreId, WarehouseId, WarehouseSlug};
+use stq_types::{Alpha3, Quantity, SagaId, StoreId, WarehouseId, WarehouseSlug};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Warehouse {
@@ -21,3 +21,22 @@ pub struct Warehouse {
     pub address: Option<String>,
     pub place_id: Option<String>,
 }
+
+/// Change of stock of a product applied by warehouses atomically, stock never goes below zero.
+/// Warehouses apply adjustment of the same saga and kind once, so retried steps and compensations
+/// do not change stock twice.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StockAdjustment {
+    pub saga_id: SagaId,
+    pub kind: StockAdjustmentKind,
+    pub quantity: Quantity,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StockAdjustmentKind {
+    /// Returned items are put back to stock
+    Return,
+    /// Compensation of `Return`, items are taken from stock again
+    ReturnReverted,
+}
@@ -0,0 +1,5 @@
+//! Tracking of stores and base products awaiting moderation and their auto-approval
+pub mod queue;
+pub mod rules;
+
+pub use self::queue::{ModerationQueue, ModerationQueueImpl};
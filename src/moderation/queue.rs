@@ -0,0 +1,260 @@
+//! Queue of items sent to moderation. It records when every item entered
+//! moderation, so items waiting beyond moderation SLA can be reported and
+//! moderators reminded about them. Fields of base products edited since
+//! their last moderation are kept too, for auto-approval of minor edits.
+//! Deactivations of stores, base products and products along with their reasons
+//! form moderation trail, the latest `TRAIL_CAPACITY` of them are kept.
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use failure::Error as FailureError;
+
+use stq_types::{BaseProductId, StoreId};
+
+use json_file::JsonFile;
+use models::{DeactivationRecord, ModerationItem, PendingModeration};
+
+pub const TRAIL_CAPACITY: usize = 1000;
+
+pub trait ModerationQueue {
+    /// Records that item entered moderation, time of an earlier entry is kept
+    fn enter(&self, item: ModerationItem, store_id: StoreId) -> Result<(), FailureError>;
+    /// Removes item once moderators decided on it
+    fn leave(&self, item: ModerationItem) -> Result<(), FailureError>;
+    /// Records that moderators were reminded about item
+    fn mark_reminded(&self, item: ModerationItem) -> Result<(), FailureError>;
+    /// Items awaiting moderation, the longest waiting first
+    fn pending(&self) -> Result<Vec<PendingModeration>, FailureError>;
+    /// Adds fields of base product changed by an edit, they are forgotten once moderators decide on it
+    fn record_edit(&self, base_product_id: BaseProductId, fields: Vec<String>) -> Result<(), FailureError>;
+    /// Fields of base product edited since its last moderation, `None` if there were no edits
+    fn edited_fields(&self, base_product_id: BaseProductId) -> Result<Option<Vec<String>>, FailureError>;
+    /// Adds deactivation to moderation trail
+    fn record_deactivation(&self, record: DeactivationRecord) -> Result<(), FailureError>;
+    /// Deactivations in moderation trail, the latest first
+    fn deactivations(&self, limit: Option<usize>) -> Result<Vec<DeactivationRecord>, FailureError>;
+}
+
+#[derive(Default)]
+struct State {
+    pending: HashMap<ModerationItem, PendingModeration>,
+    edits: HashMap<BaseProductId, BTreeSet<String>>,
+    deactivations: VecDeque<DeactivationRecord>,
+}
+
+/// Content of moderation queue json file
+#[derive(Serialize, Deserialize)]
+struct QueueFile {
+    pending: Vec<PendingModeration>,
+    #[serde(default)]
+    edits: Vec<BaseProductEdits>,
+    #[serde(default)]
+    deactivations: Vec<DeactivationRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BaseProductEdits {
+    base_product_id: BaseProductId,
+    fields: BTreeSet<String>,
+}
+
+/// Keeps moderation queue in memory, optionally mirroring it into json file
+pub struct ModerationQueueImpl {
+    state: Mutex<State>,
+    file: Option<JsonFile>,
+}
+
+impl ModerationQueueImpl {
+    pub fn new(path: Option<PathBuf>) -> Result<Self, FailureError> {
+        let file = path.map(|path| JsonFile::new(path, "moderation queue"));
+        let content = match file {
+            Some(ref file) => file.read::<QueueFile>()?,
+            None => None,
+        };
+        let state = match content {
+            Some(content) => State {
+                pending: content.pending.into_iter().map(|pending| (pending.item, pending)).collect(),
+                edits: content
+                    .edits
+                    .into_iter()
+                    .map(|edits| (edits.base_product_id, edits.fields))
+                    .collect(),
+                deactivations: content.deactivations.into_iter().collect(),
+            },
+            None => State::default(),
+        };
+
+        Ok(Self {
+            state: Mutex::new(state),
+            file,
+        })
+    }
+
+    fn flush(&self, state: &State) -> Result<(), FailureError> {
+        if let Some(ref file) = self.file {
+            let content = QueueFile {
+                pending: state.pending.values().cloned().collect(),
+                edits: state
+                    .edits
+                    .iter()
+                    .map(|(base_product_id, fields)| BaseProductEdits {
+                        base_product_id: *base_product_id,
+                        fields: fields.clone(),
+                    })
+                    .collect(),
+                deactivations: state.deactivations.iter().cloned().collect(),
+            };
+            file.write(&content)?;
+        }
+        Ok(())
+    }
+}
+
+impl ModerationQueue for ModerationQueueImpl {
+    fn enter(&self, item: ModerationItem, store_id: StoreId) -> Result<(), FailureError> {
+        let mut state = self.state.lock().unwrap();
+        if state.pending.contains_key(&item) {
+            return Ok(());
+        }
+        state.pending.insert(item, PendingModeration::new(item, store_id));
+        self.flush(&state)
+    }
+
+    fn leave(&self, item: ModerationItem) -> Result<(), FailureError> {
+        let mut state = self.state.lock().unwrap();
+        let removed = state.pending.remove(&item).is_some();
+        let edits_removed = match item {
+            ModerationItem::BaseProduct(base_product_id) => state.edits.remove(&base_product_id).is_some(),
+            ModerationItem::Store(_) => false,
+        };
+        if !removed && !edits_removed {
+            return Ok(());
+        }
+        self.flush(&state)
+    }
+
+    fn mark_reminded(&self, item: ModerationItem) -> Result<(), FailureError> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(pending) = state.pending.get_mut(&item) {
+            pending.reminded_at = Some(SystemTime::now());
+        }
+        self.flush(&state)
+    }
+
+    fn pending(&self) -> Result<Vec<PendingModeration>, FailureError> {
+        let mut items = self.state.lock().unwrap().pending.values().cloned().collect::<Vec<_>>();
+        items.sort_by_key(|pending| pending.entered_at);
+        Ok(items)
+    }
+
+    fn record_edit(&self, base_product_id: BaseProductId, fields: Vec<String>) -> Result<(), FailureError> {
+        let mut state = self.state.lock().unwrap();
+        state.edits.entry(base_product_id).or_insert_with(BTreeSet::new).extend(fields);
+        self.flush(&state)
+    }
+
+    fn edited_fields(&self, base_product_id: BaseProductId) -> Result<Option<Vec<String>>, FailureError> {
+        let state = self.state.lock().unwrap();
+        Ok(state.edits.get(&base_product_id).map(|fields| fields.iter().cloned().collect()))
+    }
+
+    fn record_deactivation(&self, record: DeactivationRecord) -> Result<(), FailureError> {
+        let mut state = self.state.lock().unwrap();
+        state.deactivations.push_back(record);
+        while state.deactivations.len() > TRAIL_CAPACITY {
+            state.deactivations.pop_front();
+        }
+        self.flush(&state)
+    }
+
+    fn deactivations(&self, limit: Option<usize>) -> Result<Vec<DeactivationRecord>, FailureError> {
+        let state = self.state.lock().unwrap();
+        let latest = state.deactivations.iter().rev().cloned();
+        Ok(match limit {
+            Some(limit) => latest.take(limit).collect(),
+            None => latest.collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use stq_types::{BaseProductId, ProductId, StoreId};
+
+    use super::{ModerationQueue, ModerationQueueImpl};
+    use models::{DeactivatedItem, Deactivation, DeactivationRecord, ModerationItem};
+
+    #[test]
+    fn keeps_time_of_first_entry() {
+        let queue = ModerationQueueImpl::new(None).unwrap();
+        let item = ModerationItem::BaseProduct(BaseProductId(1));
+        queue.enter(item, StoreId(1)).unwrap();
+        let entered_at = queue.pending().unwrap()[0].entered_at;
+        queue.enter(item, StoreId(1)).unwrap();
+        queue.enter(ModerationItem::Store(StoreId(1)), StoreId(1)).unwrap();
+
+        let pending = queue.pending().unwrap();
+        assert_eq!(pending.len(), 2);
+        let base_product = pending.iter().find(|pending| pending.item == item).unwrap();
+        assert_eq!(base_product.entered_at, entered_at);
+
+        queue.leave(item).unwrap();
+        assert_eq!(queue.pending().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn forgets_edits_after_moderation() {
+        let queue = ModerationQueueImpl::new(None).unwrap();
+        let base_product_id = BaseProductId(1);
+        queue.record_edit(base_product_id, vec!["seo_title".to_string()]).unwrap();
+        queue
+            .record_edit(base_product_id, vec!["name".to_string(), "seo_title".to_string()])
+            .unwrap();
+        assert_eq!(
+            queue.edited_fields(base_product_id).unwrap(),
+            Some(vec!["name".to_string(), "seo_title".to_string()])
+        );
+
+        queue.leave(ModerationItem::BaseProduct(base_product_id)).unwrap();
+        assert_eq!(queue.edited_fields(base_product_id).unwrap(), None);
+    }
+
+    #[test]
+    fn reports_items_beyond_sla() {
+        let queue = ModerationQueueImpl::new(None).unwrap();
+        queue.enter(ModerationItem::Store(StoreId(1)), StoreId(1)).unwrap();
+        let pending = &queue.pending().unwrap()[0];
+        let sla = Duration::from_secs(3600);
+        assert!(!pending.is_overdue(sla, SystemTime::now()));
+        assert!(pending.is_overdue(sla, SystemTime::now() + Duration::from_secs(3601)));
+    }
+
+    #[test]
+    fn lists_latest_deactivations_first() {
+        let queue = ModerationQueueImpl::new(None).unwrap();
+        let deactivation = Deactivation {
+            reason: Some("Counterfeit goods".to_string()),
+            note: None,
+        };
+        queue
+            .record_deactivation(DeactivationRecord::new(DeactivatedItem::Store(StoreId(1)), deactivation, None))
+            .unwrap();
+        queue
+            .record_deactivation(DeactivationRecord::new(
+                DeactivatedItem::Product(ProductId(2)),
+                Deactivation::default(),
+                None,
+            ))
+            .unwrap();
+
+        let deactivations = queue.deactivations(None).unwrap();
+        assert_eq!(deactivations.len(), 2);
+        assert_eq!(deactivations[0].item, DeactivatedItem::Product(ProductId(2)));
+        assert_eq!(deactivations[1].reason, Some("Counterfeit goods".to_string()));
+        assert_eq!(queue.deactivations(Some(1)).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,68 @@
+//! Auto-approval of base products sent to moderation by configured rules
+use stq_types::{CategoryId, StoreId};
+
+use config::AutoApprovalRule;
+
+/// First rule approving base product of the store and category, `edited_fields` are fields edited since its last moderation
+pub fn matching_rule<'a>(
+    rules: &'a [AutoApprovalRule],
+    store_id: StoreId,
+    category_id: CategoryId,
+    edited_fields: Option<&[String]>,
+) -> Option<&'a AutoApprovalRule> {
+    rules.iter().find(|rule| matches(rule, store_id, category_id, edited_fields))
+}
+
+fn matches(rule: &AutoApprovalRule, store_id: StoreId, category_id: CategoryId, edited_fields: Option<&[String]>) -> bool {
+    if rule.store_ids.is_empty() && rule.category_ids.is_empty() && rule.minor_edit_fields.is_empty() {
+        return false;
+    }
+
+    let store_matches = rule.store_ids.is_empty() || rule.store_ids.contains(&store_id);
+    let category_matches = rule.category_ids.is_empty() || rule.category_ids.contains(&category_id);
+    let edits_match = rule.minor_edit_fields.is_empty()
+        || edited_fields
+            .map(|fields| fields.iter().all(|field| rule.minor_edit_fields.contains(field)))
+            .unwrap_or(false);
+
+    store_matches && category_matches && edits_match
+}
+
+#[cfg(test)]
+mod tests {
+    use stq_types::{CategoryId, StoreId};
+
+    use super::matching_rule;
+    use config::AutoApprovalRule;
+
+    fn rule(store_ids: Vec<StoreId>, category_ids: Vec<CategoryId>, minor_edit_fields: &[&str]) -> AutoApprovalRule {
+        AutoApprovalRule {
+            name: "rule".to_string(),
+            store_ids,
+            category_ids,
+            minor_edit_fields: minor_edit_fields.iter().map(|field| field.to_string()).collect(),
+        }
+    }
+
+    fn approves(rule: AutoApprovalRule, edited_fields: Option<&[&str]>) -> bool {
+        let edited_fields = edited_fields.map(|fields| fields.iter().map(|field| field.to_string()).collect::<Vec<_>>());
+        matching_rule(&[rule], StoreId(10), CategoryId(5), edited_fields.as_ref().map(Vec::as_slice)).is_some()
+    }
+
+    #[test]
+    fn matches_every_criterion_of_rule() {
+        assert!(approves(rule(vec![StoreId(10)], vec![], &[]), None));
+        assert!(!approves(rule(vec![StoreId(10)], vec![CategoryId(6)], &[]), None));
+        assert!(approves(
+            rule(vec![], vec![CategoryId(5)], &["seo_title"]),
+            Some(&["seo_title"][..])
+        ));
+        assert!(!approves(rule(vec![], vec![], &["seo_title"]), None));
+    }
+
+    #[test]
+    fn rejects_major_edits_and_empty_rules() {
+        assert!(!approves(rule(vec![], vec![], &["seo_title"]), Some(&["seo_title", "name"][..])));
+        assert!(!approves(rule(vec![], vec![], &[]), None));
+    }
+}
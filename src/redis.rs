@@ -0,0 +1,296 @@
+//! Minimal Redis client for state shared by coordinator replicas: leader lease, saga
+//! claims and entity locks. Every command is sent over a new connection with blocking
+//! io, calls are bounded by a short io timeout.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use uuid::Uuid;
+
+use config;
+use metrics::duration_ms;
+
+/// Sets the lease if it is free, extends it if it is held by the caller
+const ACQUIRE_SCRIPT: &str = "if redis.call('get', KEYS[1]) == ARGV[1] then redis.call('pexpire', KEYS[1], ARGV[2]) return 1 end \
+                              if redis.call('set', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then return 1 end return 0";
+/// Deletes the lease if it is held by the caller
+const RELEASE_SCRIPT: &str = "if redis.call('get', KEYS[1]) == ARGV[1] then return redis.call('del', KEYS[1]) end return 0";
+const IO_TIMEOUT_MS: u64 = 1000;
+
+/// Reply of Redis, error replies are returned as errors
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    Status(String),
+    Integer(i64),
+    /// `None` if the value is missing
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    pub fn into_string(self) -> Option<String> {
+        match self {
+            Reply::Status(value) | Reply::Bulk(Some(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Strings of array reply, other replies have none
+    pub fn into_strings(self) -> Vec<String> {
+        match self {
+            Reply::Array(items) => items.into_iter().filter_map(Reply::into_string).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+pub trait LeaseLock: Send + Sync {
+    /// Acquires the lease or extends it, `false` if it is held by another replica
+    fn acquire(&self) -> Result<bool, FailureError>;
+}
+
+/// Lease kept in Redis under a key, expires unless its holder extends it
+pub struct RedisLeaseLock {
+    redis_address: String,
+    key: String,
+    /// Identifies the holder of the lease
+    token: String,
+    lease: Duration,
+}
+
+impl RedisLeaseLock {
+    /// Lease of the leader replica under the configured key
+    pub fn new(config: config::LeaderElection) -> Self {
+        Self::keyed(
+            config.redis_address,
+            config.key,
+            Uuid::new_v4().to_string(),
+            Duration::from_millis(config.lease_ms),
+        )
+    }
+
+    /// Lease under `key` held by `token`
+    pub fn keyed(redis_address: String, key: String, token: String, lease: Duration) -> Self {
+        Self {
+            redis_address,
+            key,
+            token,
+            lease,
+        }
+    }
+
+    /// Releases the lease, `false` if it is not held by the caller
+    pub fn release(&self) -> Result<bool, FailureError> {
+        let reply = command(&self.redis_address, &["EVAL", RELEASE_SCRIPT, "1", &self.key, &self.token])?;
+        Ok(reply == Reply::Integer(1))
+    }
+
+    /// Token of the current holder, `None` if the lease is free
+    pub fn holder(&self) -> Result<Option<String>, FailureError> {
+        command(&self.redis_address, &["GET", &self.key]).map(Reply::into_string)
+    }
+}
+
+impl LeaseLock for RedisLeaseLock {
+    fn acquire(&self) -> Result<bool, FailureError> {
+        let lease_ms = duration_ms(self.lease).to_string();
+        let reply = command(
+            &self.redis_address,
+            &["EVAL", ACQUIRE_SCRIPT, "1", &self.key, &self.token, &lease_ms],
+        )?;
+        Ok(reply == Reply::Integer(1))
+    }
+}
+
+/// Sends command to Redis and reads its reply
+pub fn command(address: &str, args: &[&str]) -> Result<Reply, FailureError> {
+    let timeout = Duration::from_millis(IO_TIMEOUT_MS);
+    let socket_address = address
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format_err!("Redis address {} is not resolved", address))?;
+    let mut stream = TcpStream::connect_timeout(&socket_address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.write_all(&encode_command(args))?;
+    read_reply(&mut BufReader::new(stream))
+}
+
+/// Encodes command as RESP array of bulk strings
+fn encode_command(args: &[&str]) -> Vec<u8> {
+    let mut command = format!("*{}\r\n", args.len());
+    for arg in args {
+        command.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    command.into_bytes()
+}
+
+fn read_reply<R: BufRead>(reader: &mut R) -> Result<Reply, FailureError> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let line = line.trim_right();
+    let mut chars = line.chars();
+    let kind = chars.next();
+    let rest = chars.as_str();
+    match kind {
+        Some('+') => Ok(Reply::Status(rest.to_string())),
+        Some('-') => Err(format_err!("Redis replied with error: {}", rest)),
+        Some(':') => Ok(Reply::Integer(rest.parse()?)),
+        Some('$') => {
+            let len = rest.parse::<i64>()?;
+            if len < 0 {
+                return Ok(Reply::Bulk(None));
+            }
+            // value is followed by CRLF
+            let mut value = vec![0; len as usize + 2];
+            reader.read_exact(&mut value)?;
+            value.truncate(len as usize);
+            Ok(Reply::Bulk(Some(String::from_utf8(value)?)))
+        }
+        Some('*') => {
+            let len = rest.parse::<i64>()?;
+            let mut items = vec![];
+            for _ in 0..len {
+                items.push(read_reply(reader)?);
+            }
+            Ok(Reply::Array(items))
+        }
+        _ => Err(format_err!("Unexpected reply of Redis: {:?}", line)),
+    }
+}
+
+/// In-process stand-in for Redis serving commands sent by the coordinator
+#[cfg(test)]
+pub mod fake {
+    use std::collections::{BTreeSet, HashMap};
+    use std::io::{BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{read_reply, Reply, ACQUIRE_SCRIPT};
+
+    #[derive(Default)]
+    struct State {
+        /// Values with their expiration times
+        values: HashMap<String, (String, Option<Instant>)>,
+        sets: HashMap<String, BTreeSet<String>>,
+    }
+
+    /// Starts the server on a random port and returns its address
+    pub fn start() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let mut state = State::default();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let args = read_reply(&mut BufReader::new(&stream)).unwrap().into_strings();
+                let reply = state.execute(&args);
+                stream.write_all(encode_reply(&reply).as_bytes()).unwrap();
+            }
+        });
+        address
+    }
+
+    impl State {
+        fn get(&mut self, key: &str) -> Option<String> {
+            let now = Instant::now();
+            let is_expired = self
+                .values
+                .get(key)
+                .and_then(|&(_, expires_at)| expires_at)
+                .map(|expires_at| expires_at <= now)
+                .unwrap_or(false);
+            if is_expired {
+                self.values.remove(key);
+            }
+            self.values.get(key).map(|&(ref value, _)| value.clone())
+        }
+
+        fn execute(&mut self, args: &[String]) -> Reply {
+            match args[0].as_str() {
+                "GET" => Reply::Bulk(self.get(&args[1])),
+                "SET" => {
+                    self.values.insert(args[1].clone(), (args[2].clone(), None));
+                    Reply::Status("OK".to_string())
+                }
+                "DEL" => Reply::Integer(self.values.remove(&args[1]).map(|_| 1).unwrap_or(0)),
+                "SADD" => Reply::Integer(
+                    self.sets
+                        .entry(args[1].clone())
+                        .or_insert_with(BTreeSet::new)
+                        .insert(args[2].clone()) as i64,
+                ),
+                "SREM" => Reply::Integer(self.sets.get_mut(&args[1]).map(|set| set.remove(&args[2]) as i64).unwrap_or(0)),
+                "SMEMBERS" => Reply::Array(
+                    self.sets
+                        .get(&args[1])
+                        .map(|set| set.iter().map(|member| Reply::Bulk(Some(member.clone()))).collect())
+                        .unwrap_or_default(),
+                ),
+                // lease scripts are executed by hand
+                "EVAL" => {
+                    let (key, token) = (&args[3], &args[4]);
+                    let holder = self.get(key);
+                    let is_held = holder.as_ref() == Some(token);
+                    if args[1] == ACQUIRE_SCRIPT {
+                        let is_acquired = is_held || holder.is_none();
+                        if is_acquired {
+                            let lease = Duration::from_millis(args[5].parse().unwrap());
+                            self.values.insert(key.clone(), (token.clone(), Some(Instant::now() + lease)));
+                        }
+                        Reply::Integer(is_acquired as i64)
+                    } else {
+                        if is_held {
+                            self.values.remove(key);
+                        }
+                        Reply::Integer(is_held as i64)
+                    }
+                }
+                command => panic!("Command {} is not supported by fake Redis", command),
+            }
+        }
+    }
+
+    fn encode_reply(reply: &Reply) -> String {
+        match reply {
+            Reply::Status(status) => format!("+{}\r\n", status),
+            Reply::Integer(value) => format!(":{}\r\n", value),
+            Reply::Bulk(Some(value)) => format!("${}\r\n{}\r\n", value.len(), value),
+            Reply::Bulk(None) => "$-1\r\n".to_string(),
+            Reply::Array(items) => items
+                .iter()
+                .fold(format!("*{}\r\n", items.len()), |encoded, item| encoded + &encode_reply(item)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_command, read_reply, Reply};
+
+    #[test]
+    fn encodes_command_as_bulk_strings() {
+        assert_eq!(
+            String::from_utf8(encode_command(&["GET", "saga:leader"])).unwrap(),
+            "*2\r\n$3\r\nGET\r\n$11\r\nsaga:leader\r\n"
+        );
+    }
+
+    #[test]
+    fn reads_replies() {
+        let mut reply = "*3\r\n$5\r\nfirst\r\n$-1\r\n:1\r\n".as_bytes();
+        assert_eq!(
+            read_reply(&mut reply).unwrap(),
+            Reply::Array(vec![Reply::Bulk(Some("first".to_string())), Reply::Bulk(None), Reply::Integer(1)])
+        );
+
+        let mut reply = "$12\r\nline\r\nbreaks\r\n".as_bytes();
+        assert_eq!(read_reply(&mut reply).unwrap().into_string(), Some("line\r\nbreaks".to_string()));
+
+        let mut reply = "-ERR unknown command\r\n".as_bytes();
+        assert!(read_reply(&mut reply).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+//! Replay of recorded sagas for debugging. Request the saga was started with is sent
+//! to a coordinator running in-process, whose microservices are replaced with a mock
+//! server answering with the recorded responses. Sagas are recorded only if
+//! `saga.record_calls` is set. Calls the replayed saga makes that were not recorded
+//! and recorded calls it does not make are reported, so are calls with payloads
+//! different from the recorded ones. Credentials of the caller are not recorded,
+//! `Authorization` of the replayed request is given along with the saga instead.
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use failure::Error as FailureError;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use hyper;
+use hyper::header::{Authorization, ContentType};
+use hyper::server::{Http, Request, Response, Service};
+use hyper::StatusCode;
+use serde_json;
+use tokio_core::reactor::Core;
+
+use stq_http::controller::Controller;
+use stq_http::errors::ErrorMessageWrapper;
+use stq_types::SagaId;
+
+use config::Config;
+use controller::ControllerImpl;
+use errors::Error;
+use microservice::{CassetteHttpClient, ChaosHttpClient, ShadowHttpClient};
+use models::{RecordedCall, SagaStatus, SagaType};
+use saga::encryption::SagaLogCipher;
+use saga::{SagaStore, SagaStoreImpl};
+
+/// Outcome of replay compared to the recorded saga
+#[derive(Clone, Debug, Serialize)]
+pub struct ReplayReport {
+    pub saga_id: SagaId,
+    pub saga_type: SagaType,
+    pub recorded_status: SagaStatus,
+    /// `None` if replay did not start a saga
+    pub replayed_status: Option<SagaStatus>,
+    pub response_status: u16,
+    pub response: String,
+    /// Calls made by the replayed saga that were not recorded, e.g. `GET /users/1`
+    pub unexpected_calls: Vec<String>,
+    /// Recorded calls the replayed saga did not make
+    pub missing_calls: Vec<String>,
+    /// Calls made with payloads different from the recorded ones
+    pub payload_mismatches: Vec<String>,
+}
+
+/// Replays saga recorded in saga log at `saga_log_path` on behalf of the caller with `authorization`, anonymously if it is `None`
+pub fn replay(
+    mut config: Config,
+    saga_log_path: PathBuf,
+    saga_id: SagaId,
+    authorization: Option<String>,
+) -> Result<ReplayReport, FailureError> {
+    let cipher = match config.saga.encryption {
+        Some(ref encryption) => Some(SagaLogCipher::new(encryption)?),
+        None => None,
+    };
+    let record = SagaStoreImpl::new(Some(saga_log_path), cipher)?
+        .get(saga_id)?
+        .ok_or_else(|| format_err!("Saga {} is not found in saga log", saga_id))?;
+    let request = record.request.clone().ok_or_else(|| {
+        format_err!(
+            "Request of saga {} is not recorded, it was executed without saga.record_calls",
+            saga_id
+        )
+    })?;
+
+    let mut core = Core::new()?;
+    let handle = Arc::new(core.handle());
+
+    let calls = Rc::new(RefCell::new(MockCalls::new(record.calls.clone())));
+    let serve = Http::new()
+        .serve_addr_handle(&"127.0.0.1:0".parse()?, &*handle, {
+            let calls = calls.clone();
+            move || Ok(MockService { calls: calls.clone() })
+        })
+        .map_err(|e| format_err!("Could not start mock microservices: {}", e))?;
+    let mock_url = format!("http://{}", serve.incoming_ref().local_addr());
+    handle.spawn(
+        serve
+            .for_each({
+                let handle = handle.clone();
+                move |conn| {
+                    handle.spawn(conn.map(|_| ()).map_err(|e| warn!("Mock microservice error: {}", e)));
+                    Ok(())
+                }
+            })
+            .map_err(|_| ()),
+    );
+
+    for (_, microservice) in config.microservices_mut() {
+        microservice.url = format!("{}{}", mock_url, mock_prefix(&microservice.url));
+        microservice.secondary_url = None;
+    }
+    if let Some(ref mut fraud_screening) = config.fraud_screening {
+        fraud_screening.url = format!("{}{}", mock_url, mock_prefix(&fraud_screening.url));
+    }
+    config.saga.log_path = None;
+    config.saga.record_calls = false;
+    config.chaos = None;
+    config.shadow = None;
+
+    let client = ::stq_http::client::Client::new(&config.to_http_config(), &handle);
+    let (http_client, _) = ShadowHttpClient::new(
+        ChaosHttpClient::new(CassetteHttpClient::new(client.handle(), None), &config),
+        &config,
+    );
+    handle.spawn(client.stream().for_each(|_| Ok(())));
+
+    let controller = ControllerImpl::in_memory(config, http_client, handle.clone())?;
+    let saga_store = controller.saga_store.clone();
+
+    let mut req = Request::new(
+        request.method.parse()?,
+        request
+            .path
+            .parse()
+            .map_err(|e| format_err!("Invalid recorded path {}: {}", request.path, e))?,
+    );
+    for (name, value) in request.headers {
+        req.headers_mut().append_raw(name, value);
+    }
+    if let Some(authorization) = authorization {
+        req.headers_mut().set(Authorization(authorization));
+    }
+    req.set_body(request.body);
+
+    let (response_status, response) = core.run(controller.call(req).then(|res| match res {
+        Ok(response) => {
+            let status = response.status().as_u16();
+            Either::A(
+                response
+                    .body()
+                    .concat2()
+                    .map(move |body| (status, String::from_utf8_lossy(&body).into_owned()))
+                    .map_err(FailureError::from),
+            )
+        }
+        Err(e) => {
+            let wrapper = ErrorMessageWrapper::<Error>::from(&e);
+            Either::B(future::ok((wrapper.inner.code, e.to_string())))
+        }
+    }))?;
+
+    let replayed_status = saga_store
+        .find_by_status(&[
+            SagaStatus::InProgress,
+            SagaStatus::Completed,
+            SagaStatus::Reverted,
+            SagaStatus::RevertFailed,
+            SagaStatus::Orphaned,
+        ])?
+        .into_iter()
+        .next()
+        .map(|record| record.status);
+
+    let calls = calls.borrow();
+    Ok(ReplayReport {
+        saga_id,
+        saga_type: record.saga_type.clone(),
+        recorded_status: record.status,
+        replayed_status,
+        response_status,
+        response,
+        unexpected_calls: calls.unexpected.clone(),
+        missing_calls: calls.missing(),
+        payload_mismatches: calls.payload_mismatches.clone(),
+    })
+}
+
+/// Path the mock serves microservice at, e.g. `/users:8000` for `http://users:8000`.
+/// Host is kept in the path, so that calls to microservices sharing a path do not collide.
+fn mock_prefix(url: &str) -> String {
+    let url = url.trim_right_matches('/');
+    let without_scheme = url.find("://").map(|index| &url[index + 3..]).unwrap_or(url);
+    format!("/{}", without_scheme)
+}
+
+/// Path the mock receives the call at, e.g. `/users:8000/users/1` for `http://users:8000/users/1`
+fn mock_path(url: &str) -> String {
+    let without_scheme = url.find("://").map(|index| &url[index + 3..]).unwrap_or(url);
+    format!("/{}", without_scheme)
+}
+
+struct MockCall {
+    path: String,
+    call: RecordedCall,
+    replayed: bool,
+}
+
+/// Recorded calls along with discrepancies found during replay
+struct MockCalls {
+    calls: Vec<MockCall>,
+    unexpected: Vec<String>,
+    payload_mismatches: Vec<String>,
+}
+
+impl MockCalls {
+    fn new(calls: Vec<RecordedCall>) -> Self {
+        Self {
+            calls: calls
+                .into_iter()
+                .map(|call| MockCall {
+                    path: mock_path(&call.url),
+                    call,
+                    replayed: false,
+                })
+                .collect(),
+            unexpected: vec![],
+            payload_mismatches: vec![],
+        }
+    }
+
+    /// The first recorded call matching method and path that is not replayed yet
+    fn take(&mut self, method: &str, path: &str, payload: Option<String>) -> Option<RecordedCall> {
+        let call = match self
+            .calls
+            .iter_mut()
+            .find(|call| !call.replayed && call.call.method == method && call.path == path)
+        {
+            Some(call) => call,
+            None => {
+                self.unexpected.push(format!("{} {}", method, path));
+                return None;
+            }
+        };
+        call.replayed = true;
+        if !same_payload(call.call.payload.as_ref(), payload.as_ref()) {
+            self.payload_mismatches.push(format!("{} {}", method, call.call.url));
+        }
+        Some(call.call.clone())
+    }
+
+    fn missing(&self) -> Vec<String> {
+        self.calls
+            .iter()
+            .filter(|call| !call.replayed)
+            .map(|call| format!("{} {}", call.call.method, call.call.url))
+            .collect()
+    }
+}
+
+/// Payloads are compared as json, so that order of fields does not matter
+fn same_payload(recorded: Option<&String>, replayed: Option<&String>) -> bool {
+    let parse = |payload: Option<&String>| payload.and_then(|payload| serde_json::from_str::<serde_json::Value>(payload).ok());
+    match (recorded, replayed) {
+        (None, None) => true,
+        (Some(recorded), Some(replayed)) => recorded == replayed || parse(Some(recorded)) == parse(Some(replayed)),
+        _ => false,
+    }
+}
+
+struct MockService {
+    calls: Rc<RefCell<MockCalls>>,
+}
+
+impl Service for MockService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let calls = self.calls.clone();
+        let method = req.method().to_string();
+        let path = req.uri().to_string();
+        Box::new(req.body().concat2().map(move |body| {
+            let payload = if body.is_empty() {
+                None
+            } else {
+                Some(String::from_utf8_lossy(&body).into_owned())
+            };
+            match calls.borrow_mut().take(&method, &path, payload) {
+                Some(RecordedCall {
+                    response: Some(response), ..
+                }) => Response::new().with_header(ContentType::json()).with_body(response.to_string()),
+                Some(RecordedCall { error, .. }) => Response::new()
+                    .with_status(StatusCode::InternalServerError)
+                    .with_body(error.unwrap_or_default()),
+                None => Response::new()
+                    .with_status(StatusCode::NotFound)
+                    .with_body(format!("Call {} {} is not recorded", method, path)),
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mock_path, mock_prefix, same_payload};
+
+    #[test]
+    fn serves_microservices_under_their_hosts() {
+        assert_eq!(mock_prefix("http://users:8000/"), "/users:8000");
+        assert_eq!(
+            format!("{}/users/1?fields=email", mock_prefix("http://users:8000")),
+            mock_path("http://users:8000/users/1?fields=email")
+        );
+    }
+
+    #[test]
+    fn compares_payloads_as_json() {
+        let recorded = r#"{"id":1,"name":"store"}"#.to_string();
+        let replayed = r#"{"name":"store","id":1}"#.to_string();
+        assert!(same_payload(Some(&recorded), Some(&replayed)));
+        assert!(!same_payload(Some(&recorded), None));
+        assert!(same_payload(None, None));
+    }
+}
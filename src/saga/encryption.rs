@@ -0,0 +1,240 @@
+//! Encryption of personal data in persisted saga log. Inputs of sagas, recorded requests
+//! and calls, and results of stages carry emails, addresses and orders of users, such
+//! fields are encrypted with AES-256-GCM right before saga log is written and decrypted
+//! right after it is read, records in memory are never encrypted. Encrypted field is
+//! replaced with an envelope naming the key, so logs written before key rotation are
+//! still read. Saga id is authenticated along with the field, so that encrypted fields
+//! can not be moved between records.
+use std::collections::HashMap;
+
+use base64;
+use failure::Error as FailureError;
+use openssl::rand::rand_bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use serde_json::{self, Map, Value};
+
+use config;
+use secrets::Secret;
+
+/// Key of the envelope object encrypted field is replaced with
+const ENVELOPE: &str = "$encrypted";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// Fields of saga record that are encrypted as a whole
+const ENCRYPTED_FIELDS: &[&str] = &["input", "request", "calls"];
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Envelope {
+    key_id: String,
+    nonce: String,
+    /// Ciphertext along with authentication tag
+    data: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct SagaLogCipher {
+    active_key: String,
+    keys: HashMap<String, Secret>,
+}
+
+impl SagaLogCipher {
+    pub fn new(config: &config::SagaLogEncryption) -> Result<Self, FailureError> {
+        if !config.keys.contains_key(&config.active_key) {
+            return Err(format_err!("Active saga log key {} is not among saga log keys", config.active_key));
+        }
+        Ok(Self {
+            active_key: config.active_key.clone(),
+            keys: config.keys.clone(),
+        })
+    }
+
+    /// Encrypts personal data of serialized saga record with the active key
+    pub fn encrypt_record(&self, mut record: Value) -> Result<Value, FailureError> {
+        let saga_id = saga_id(&record)?;
+        let key = self.key(&self.active_key)?;
+        for field in ENCRYPTED_FIELDS {
+            if let Some(value) = record.get_mut(*field) {
+                if !value.is_null() {
+                    *value = self.seal(&key, &saga_id, value)?;
+                }
+            }
+        }
+        if let Some(Value::Array(stages)) = record.get_mut("stages") {
+            for stage in stages.iter_mut() {
+                if let Some(result) = stage.get_mut("result") {
+                    if !result.is_null() {
+                        *result = self.seal(&key, &saga_id, result)?;
+                    }
+                }
+            }
+        }
+        Ok(record)
+    }
+
+    /// Decrypts every encrypted field of serialized saga record with the key it was encrypted with
+    pub fn decrypt_record(&self, mut record: Value) -> Result<Value, FailureError> {
+        let saga_id = saga_id(&record)?;
+        self.open_all(&saga_id, &mut record)?;
+        Ok(record)
+    }
+
+    fn open_all(&self, saga_id: &str, value: &mut Value) -> Result<(), FailureError> {
+        if let Some(envelope) = envelope(value) {
+            *value = self.open(saga_id, envelope?)?;
+            return Ok(());
+        }
+        match *value {
+            Value::Array(ref mut items) => items.iter_mut().map(|item| self.open_all(saga_id, item)).collect(),
+            Value::Object(ref mut fields) => fields.values_mut().map(|field| self.open_all(saga_id, field)).collect(),
+            _ => Ok(()),
+        }
+    }
+
+    fn seal(&self, key: &[u8], saga_id: &str, value: &Value) -> Result<Value, FailureError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand_bytes(&mut nonce)?;
+        let mut tag = [0u8; TAG_LEN];
+        let plaintext = serde_json::to_vec(value)?;
+        let mut data = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            key,
+            Some(&nonce[..]),
+            saga_id.as_bytes(),
+            &plaintext,
+            &mut tag,
+        )?;
+        data.extend_from_slice(&tag);
+
+        let envelope = Envelope {
+            key_id: self.active_key.clone(),
+            nonce: base64::encode(&nonce),
+            data: base64::encode(&data),
+        };
+        let mut sealed = Map::new();
+        sealed.insert(ENVELOPE.to_string(), serde_json::to_value(envelope)?);
+        Ok(Value::Object(sealed))
+    }
+
+    fn open(&self, saga_id: &str, envelope: Envelope) -> Result<Value, FailureError> {
+        let key = self.key(&envelope.key_id)?;
+        let nonce = base64::decode(&envelope.nonce)?;
+        let data = base64::decode(&envelope.data)?;
+        if data.len() < TAG_LEN {
+            return Err(format_err!("Encrypted field of saga {} is truncated", saga_id));
+        }
+        let (ciphertext, tag) = data.split_at(data.len() - TAG_LEN);
+        let plaintext = decrypt_aead(Cipher::aes_256_gcm(), &key, Some(&nonce[..]), saga_id.as_bytes(), ciphertext, tag).map_err(|_| {
+            format_err!(
+                "Encrypted field of saga {} could not be decrypted with key {}",
+                saga_id,
+                envelope.key_id
+            )
+        })?;
+        Ok(serde_json::from_slice(&plaintext)?)
+    }
+
+    fn key(&self, key_id: &str) -> Result<Vec<u8>, FailureError> {
+        let value = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| format_err!("Saga log key {} is not configured", key_id))?
+            .value()
+            .ok_or_else(|| format_err!("Saga log key {} is not resolved", key_id))?;
+        let key = base64::decode(value.trim()).map_err(|e| format_err!("Saga log key {} is not valid base64: {}", key_id, e))?;
+        if key.len() != KEY_LEN {
+            return Err(format_err!("Saga log key {} must be {} bytes long", key_id, KEY_LEN));
+        }
+        Ok(key)
+    }
+}
+
+/// Decrypts records of saga log as it was read, saga log having encrypted fields can not be read without cipher
+pub fn decrypt_log(log: Value, cipher: Option<&SagaLogCipher>) -> Result<Value, FailureError> {
+    let decrypt = |record: Value| match cipher {
+        Some(cipher) => cipher.decrypt_record(record),
+        None if is_encrypted(&record) => Err(format_err!("Saga log is encrypted, but saga.encryption is not configured")),
+        None => Ok(record),
+    };
+    match log {
+        Value::Array(records) => records.into_iter().map(decrypt).collect::<Result<Vec<_>, _>>().map(Value::Array),
+        Value::Object(mut log) => {
+            if let Some(Value::Array(records)) = log.remove("records") {
+                let records = records.into_iter().map(decrypt).collect::<Result<Vec<_>, _>>()?;
+                log.insert("records".to_string(), Value::Array(records));
+            }
+            Ok(Value::Object(log))
+        }
+        log => Ok(log),
+    }
+}
+
+fn is_encrypted(value: &Value) -> bool {
+    match *value {
+        Value::Object(ref fields) => fields.contains_key(ENVELOPE) || fields.values().any(is_encrypted),
+        Value::Array(ref items) => items.iter().any(is_encrypted),
+        _ => false,
+    }
+}
+
+fn envelope(value: &Value) -> Option<Result<Envelope, FailureError>> {
+    match *value {
+        Value::Object(ref fields) if fields.len() == 1 => fields
+            .get(ENVELOPE)
+            .map(|envelope| serde_json::from_value(envelope.clone()).map_err(FailureError::from)),
+        _ => None,
+    }
+}
+
+fn saga_id(record: &Value) -> Result<String, FailureError> {
+    record
+        .get("id")
+        .and_then(Value::as_str)
+        .map(|id| id.to_string())
+        .ok_or_else(|| format_err!("Saga record has no id"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use base64;
+
+    use super::{decrypt_log, SagaLogCipher};
+    use config::SagaLogEncryption;
+    use secrets::Secret;
+
+    fn cipher(active_key: &str) -> SagaLogCipher {
+        let mut keys = HashMap::new();
+        keys.insert("old".to_string(), Secret::new(base64::encode(&[1u8; 32])));
+        keys.insert("new".to_string(), Secret::new(base64::encode(&[2u8; 32])));
+        SagaLogCipher::new(&SagaLogEncryption {
+            active_key: active_key.to_string(),
+            keys,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn decrypts_records_encrypted_before_rotation() {
+        let record = json!({
+            "id": "00000000-0000-0000-0000-000000000001",
+            "input": {"email": "user@example.com"},
+            "request": null,
+            "stages": [{"stage": "AccountCreationStart", "result": {"phone": "+79990000000"}}],
+        });
+        let encrypted = cipher("old").encrypt_record(record.clone()).unwrap();
+        assert!(!encrypted.to_string().contains("user@example.com"));
+        assert!(!encrypted.to_string().contains("+79990000000"));
+        assert_eq!(encrypted["stages"][0]["stage"], "AccountCreationStart");
+
+        let log = json!({"schema_version": 2, "records": [encrypted.clone()]});
+        assert_eq!(decrypt_log(log.clone(), Some(&cipher("new"))).unwrap()["records"][0], record);
+        assert!(decrypt_log(log, None).is_err());
+
+        let mut moved = encrypted;
+        moved["id"] = json!("00000000-0000-0000-0000-000000000002");
+        assert!(cipher("new").decrypt_record(moved).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Lifecycle events of sagas: start, finish and compensation. Every event is sent to
+//! Graylog as GELF message with structured fields, so that sagas can be queried by
+//! type, duration or outcome instead of grepping free text log lines. Events are sent
+//! over UDP and are lost if Graylog is unavailable, saga execution never waits for them.
+use std::env;
+use std::net::UdpSocket;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::Error as FailureError;
+use failure::Fail;
+use serde_json::{self, Value};
+
+use stq_types::SagaId;
+
+use config;
+use models::{SagaStatus, SagaType};
+
+const DEFAULT_HOST: &str = "saga-coordinator";
+/// Informational syslog level
+const GELF_LEVEL: u8 = 6;
+
+lazy_static! {
+    static ref EMITTER: RwLock<Option<GelfEmitter>> = RwLock::new(None);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SagaEventKind {
+    Started,
+    Finished,
+    Compensated,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SagaEvent {
+    pub event: SagaEventKind,
+    pub saga_id: SagaId,
+    pub saga_type: Option<SagaType>,
+    /// Time since saga start
+    pub duration_ms: u64,
+    /// Saga steps completed so far
+    pub stages_completed: usize,
+    /// Whether completed steps were rolled back, for compensation events whether all of them were
+    pub compensated: bool,
+    pub status: Option<SagaStatus>,
+}
+
+impl SagaEvent {
+    fn short_message(&self) -> String {
+        let saga_type = self
+            .saga_type
+            .map(|saga_type| saga_type.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        match self.event {
+            SagaEventKind::Started => format!("Saga {} of type {} started", self.saga_id, saga_type),
+            SagaEventKind::Finished => format!("Saga {} of type {} finished", self.saga_id, saga_type),
+            SagaEventKind::Compensated => format!("Saga {} of type {} compensated", self.saga_id, saga_type),
+        }
+    }
+}
+
+struct GelfEmitter {
+    socket: UdpSocket,
+    address: String,
+    host: String,
+}
+
+/// Starts sending saga events to Graylog, events are only logged at debug level if it is not configured
+pub fn init(config: Option<&config::SagaEvents>) -> Result<(), FailureError> {
+    let emitter = match config {
+        Some(config) => Some(GelfEmitter {
+            socket: UdpSocket::bind("0.0.0.0:0").map_err(|e| e.context("Could not bind socket for saga events"))?,
+            address: config.address.clone(),
+            host: config
+                .host
+                .clone()
+                .or_else(|| env::var("HOSTNAME").ok())
+                .unwrap_or_else(|| DEFAULT_HOST.to_string()),
+        }),
+        None => None,
+    };
+    *EMITTER.write().unwrap() = emitter;
+    Ok(())
+}
+
+pub fn emit(event: SagaEvent) {
+    debug!("{}", event.short_message());
+    if let Some(ref emitter) = *EMITTER.read().unwrap() {
+        let message = gelf_message(&event, &emitter.host, SystemTime::now());
+        let res = serde_json::to_vec(&message).map_err(FailureError::from).and_then(|datagram| {
+            emitter
+                .socket
+                .send_to(&datagram, emitter.address.as_str())
+                .map_err(FailureError::from)
+        });
+        if let Err(e) = res {
+            warn!("Could not send event of saga {} to Graylog: {}", event.saga_id, e);
+        }
+    }
+}
+
+/// GELF message of the event, fields of the event become additional fields prefixed with `_`
+fn gelf_message(event: &SagaEvent, host: &str, now: SystemTime) -> Value {
+    let timestamp = now.duration_since(UNIX_EPOCH).map(seconds).unwrap_or(0.0);
+    let mut message = json!({
+        "version": "1.1",
+        "host": host,
+        "short_message": event.short_message(),
+        "timestamp": timestamp,
+        "level": GELF_LEVEL,
+    });
+    if let (Some(object), Ok(Value::Object(fields))) = (message.as_object_mut(), serde_json::to_value(event)) {
+        for (name, value) in fields {
+            if !value.is_null() {
+                object.insert(format!("_{}", name), value);
+            }
+        }
+    }
+    message
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_millis()) / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use stq_types::SagaId;
+
+    use super::{gelf_message, SagaEvent, SagaEventKind};
+    use models::{SagaStatus, SagaType};
+
+    #[test]
+    fn sends_event_fields_as_additional_fields() {
+        let saga_id = SagaId::new();
+        let event = SagaEvent {
+            event: SagaEventKind::Finished,
+            saga_id,
+            saga_type: Some(SagaType::CreateOrder),
+            duration_ms: 1250,
+            stages_completed: 4,
+            compensated: true,
+            status: Some(SagaStatus::Reverted),
+        };
+
+        let message = gelf_message(&event, "coordinator-1", UNIX_EPOCH + Duration::from_millis(1_500));
+        assert_eq!(message["version"], "1.1");
+        assert_eq!(message["host"], "coordinator-1");
+        assert_eq!(message["timestamp"], 1.5);
+        assert_eq!(message["_event"], "finished");
+        assert_eq!(message["_saga_id"], json!(saga_id));
+        assert_eq!(message["_saga_type"], json!(SagaType::CreateOrder));
+        assert_eq!(message["_duration_ms"], 1250);
+        assert_eq!(message["_stages_completed"], 4);
+        assert_eq!(message["_compensated"], true);
+        assert_eq!(message["_status"], "reverted");
+    }
+}
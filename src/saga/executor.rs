@@ -0,0 +1,220 @@
+//! Execution of background sagas by priority classes. Every class has its own queue
+//! and concurrency limit. When several classes have sagas waiting, free slots are
+//! given to them in proportion to their weights, so bulk admin batches do not delay
+//! checkouts. The executor lives on the reactor thread, like saga logs do.
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use futures::prelude::*;
+use futures::sync::oneshot;
+use tokio_core::reactor::Handle;
+
+use config;
+use logging;
+use models::SagaPriority;
+
+type Task = Box<Future<Item = (), Error = ()>>;
+
+/// Sagas of the class waiting for a slot and running right now
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExecutorClassStats {
+    pub priority: SagaPriority,
+    pub queued: usize,
+    pub running: usize,
+}
+
+#[derive(Clone)]
+pub struct SagaExecutor {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    handle: Handle,
+    config: config::Executor,
+    state: RefCell<State>,
+}
+
+#[derive(Default)]
+struct State {
+    queues: HashMap<SagaPriority, VecDeque<Task>>,
+    running: HashMap<SagaPriority, usize>,
+    /// Credits of smooth weighted round robin between classes
+    credits: HashMap<SagaPriority, i64>,
+}
+
+impl SagaExecutor {
+    pub fn new(handle: Handle, config: config::Executor) -> Self {
+        Self {
+            inner: Rc::new(Inner {
+                handle,
+                config,
+                state: RefCell::new(State::default()),
+            }),
+        }
+    }
+
+    /// Queues saga, it is executed once its class gets a free slot. Saga keeps logging context
+    /// of the request it is queued by.
+    pub fn spawn<F>(&self, priority: SagaPriority, saga: F)
+    where
+        F: Future<Item = (), Error = ()> + 'static,
+    {
+        self.inner
+            .state
+            .borrow_mut()
+            .queues
+            .entry(priority)
+            .or_insert_with(VecDeque::new)
+            .push_back(Box::new(logging::in_request(logging::request_id(), saga)));
+        self.dispatch();
+    }
+
+    /// Queues saga and resolves once it is executed
+    pub fn run<F>(&self, priority: SagaPriority, saga: F) -> impl Future<Item = (), Error = ()>
+    where
+        F: Future<Item = (), Error = ()> + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.spawn(
+            priority,
+            saga.then(move |res| {
+                let _ = tx.send(res);
+                Ok(())
+            }),
+        );
+        rx.then(|res| match res {
+            Ok(res) => res,
+            // executor is dropped along with the reactor
+            Err(_) => Err(()),
+        })
+    }
+
+    pub fn stats(&self) -> Vec<ExecutorClassStats> {
+        let state = self.inner.state.borrow();
+        SagaPriority::all()
+            .iter()
+            .map(|priority| ExecutorClassStats {
+                priority: *priority,
+                queued: state.queues.get(priority).map(VecDeque::len).unwrap_or(0),
+                running: state.running.get(priority).cloned().unwrap_or(0),
+            })
+            .collect()
+    }
+
+    fn dispatch(&self) {
+        loop {
+            let next = {
+                let mut state = self.inner.state.borrow_mut();
+                next_class(&state, &self.inner.config).and_then(|priority| {
+                    take_slot(&mut state, &self.inner.config, priority);
+                    state
+                        .queues
+                        .get_mut(&priority)
+                        .and_then(VecDeque::pop_front)
+                        .map(|task| (priority, task))
+                })
+            };
+
+            match next {
+                Some((priority, task)) => {
+                    let executor = self.clone();
+                    self.inner.handle.spawn(task.then(move |_| {
+                        executor.finish(priority);
+                        Ok(())
+                    }));
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn finish(&self, priority: SagaPriority) {
+        if let Some(running) = self.inner.state.borrow_mut().running.get_mut(&priority) {
+            *running -= 1;
+        }
+        self.dispatch();
+    }
+}
+
+/// Class the next free slot goes to, the one with the most credits among classes with sagas waiting
+/// and running less than their concurrency. Ties go to the more urgent class.
+fn next_class(state: &State, config: &config::Executor) -> Option<SagaPriority> {
+    eligible(state, config)
+        .into_iter()
+        .map(|priority| {
+            (
+                priority,
+                state.credits.get(&priority).cloned().unwrap_or(0) + weight(config, priority),
+            )
+        })
+        .fold(None, |best: Option<(SagaPriority, i64)>, (priority, credits)| match best {
+            Some((_, best_credits)) if best_credits >= credits => best,
+            _ => Some((priority, credits)),
+        })
+        .map(|(priority, _)| priority)
+}
+
+/// Every eligible class earns its weight, the chosen one pays for the slot with the total weight
+fn take_slot(state: &mut State, config: &config::Executor, priority: SagaPriority) {
+    let eligible = eligible(state, config);
+    let total = eligible.iter().map(|priority| weight(config, *priority)).sum::<i64>();
+    for other in eligible {
+        *state.credits.entry(other).or_insert(0) += weight(config, other);
+    }
+    *state.credits.entry(priority).or_insert(0) -= total;
+    *state.running.entry(priority).or_insert(0) += 1;
+}
+
+fn eligible(state: &State, config: &config::Executor) -> Vec<SagaPriority> {
+    SagaPriority::all()
+        .iter()
+        .cloned()
+        .filter(|priority| state.queues.get(priority).map(|queue| !queue.is_empty()).unwrap_or(false))
+        .filter(|priority| state.running.get(priority).cloned().unwrap_or(0) < config.class(*priority).concurrency)
+        .collect()
+}
+
+fn weight(config: &config::Executor, priority: SagaPriority) -> i64 {
+    i64::from(config.class(priority).weight)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use futures::future;
+
+    use super::{next_class, take_slot, State, Task};
+    use config;
+    use models::SagaPriority;
+
+    #[test]
+    fn shares_slots_by_weights() {
+        let mut config = config::Executor::default();
+        config.checkout.weight = 3;
+        config.checkout.concurrency = 100;
+        config.moderation.weight = 1;
+        config.moderation.concurrency = 100;
+        config.housekeeping.concurrency = 0;
+
+        let mut state = State::default();
+        for priority in SagaPriority::all() {
+            let queue = (0..10).map(|_| Box::new(future::ok::<(), ()>(())) as Task).collect::<VecDeque<_>>();
+            state.queues.insert(*priority, queue);
+        }
+
+        let mut picked = vec![];
+        for _ in 0..8 {
+            let priority = next_class(&state, &config).unwrap();
+            take_slot(&mut state, &config, priority);
+            state.queues.get_mut(&priority).unwrap().pop_front();
+            picked.push(priority);
+        }
+
+        let checkouts = picked.iter().filter(|priority| **priority == SagaPriority::Checkout).count();
+        assert_eq!(checkouts, 6);
+        assert!(!picked.contains(&SagaPriority::Housekeeping));
+        assert_eq!(picked[0], SagaPriority::Checkout);
+    }
+}
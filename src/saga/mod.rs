@@ -0,0 +1,611 @@
+//! Saga operation log. Every saga execution keeps its stages in memory
+//! (they are used for reverting) and mirrors them into `SagaStore`,
+//! so that failed compensations can be found and retried later.
+//!
+//! The log is owned by a single saga execution running on the reactor thread,
+//! so it is shared with `Rc` and appended to without locking.
+pub mod encryption;
+pub mod events;
+pub mod executor;
+pub mod schema;
+pub mod shared;
+pub mod steps;
+pub mod store;
+
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use failure::Error as FailureError;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use serde::Serialize;
+use serde_json::{self, Value};
+use tokio_timer::{Interval, Timeout};
+
+use stq_types::{SagaId, UserId};
+
+pub use self::executor::SagaExecutor;
+pub use self::steps::{run_steps, StepFuture};
+pub use self::store::{SagaStore, SagaStoreImpl};
+
+use self::events::{SagaEvent, SagaEventKind};
+use config;
+use errors::Error;
+use metrics::duration_ms;
+use microservice::{is_not_found, Budget};
+use models::{
+    CompensationFailure, CompensationReport, LockedEntity, OperationStage, SagaEscalation, SagaLogEntry, SagaRecord, SagaResponse,
+    SagaStatus, SagaType, SagaWarning, StepMarker, StepPhase,
+};
+use sentry_integration::{capture_compensation_failure, capture_compensation_started, capture_saga_panic};
+use services::parse_validation_errors;
+
+pub struct SagaLog<S> {
+    saga_id: SagaId,
+    saga_type: Cell<Option<SagaType>>,
+    stages: RefCell<Vec<S>>,
+    warnings: RefCell<Vec<SagaWarning>>,
+    started_at: Cell<SystemTime>,
+    store: Arc<SagaStore>,
+    /// Budget of calls of the saga, detached from the deadline once compensation starts
+    budget: RefCell<Option<Budget>>,
+    /// Entities locked by the saga, they are unlocked once the log is dropped, i.e. after compensations
+    locks: Rc<RefCell<Vec<LockedEntity>>>,
+    /// Urls of microservices switched between deployments, recorded once the saga starts
+    endpoints: RefCell<BTreeMap<String, String>>,
+}
+
+impl<S: OperationStage> SagaLog<S> {
+    pub fn new(store: Arc<SagaStore>) -> Self {
+        Self {
+            saga_id: SagaId::new(),
+            saga_type: Cell::new(None),
+            stages: RefCell::new(vec![]),
+            warnings: RefCell::new(vec![]),
+            started_at: Cell::new(SystemTime::now()),
+            store,
+            budget: RefCell::new(None),
+            locks: Rc::new(RefCell::new(vec![])),
+            endpoints: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Restores log of already started saga from its persisted record
+    pub fn restore(record: SagaRecord, store: Arc<SagaStore>) -> Self {
+        // steps which did not take effect downstream must not be reverted
+        let not_applied = record
+            .stages
+            .iter()
+            .filter(|entry| entry.marker == StepMarker::NotApplied)
+            .map(|entry| entry.stage.clone())
+            .collect::<Vec<_>>();
+        let stages = record
+            .stages
+            .into_iter()
+            .filter(|entry| !not_applied.contains(&entry.stage))
+            .filter_map(|entry| S::from_saga_stage(entry.stage))
+            .collect();
+        Self {
+            saga_id: record.id,
+            saga_type: Cell::new(Some(record.saga_type)),
+            stages: RefCell::new(stages),
+            warnings: RefCell::new(record.warnings),
+            endpoints: RefCell::new(record.endpoints),
+            started_at: Cell::new(record.created_at),
+            store,
+            budget: RefCell::new(None),
+            locks: Rc::new(RefCell::new(vec![])),
+        }
+    }
+
+    pub fn saga_id(&self) -> SagaId {
+        self.saga_id
+    }
+
+    /// Shares time budget with microservice clients of the saga
+    pub fn bind_budget(&self, budget: Budget) {
+        *self.budget.borrow_mut() = Some(budget);
+    }
+
+    /// Records urls of microservices the saga is served by, for debugging of blue/green switches
+    pub fn bind_endpoints(&self, endpoints: BTreeMap<String, String>) {
+        *self.endpoints.borrow_mut() = endpoints;
+    }
+
+    pub fn start(&self, saga_type: SagaType) {
+        self.saga_type.set(Some(saga_type));
+        self.started_at.set(SystemTime::now());
+        let mut record = SagaRecord::new(self.saga_id, saga_type);
+        record.endpoints = self.endpoints.borrow().clone();
+        if let Err(e) = self.store.insert(record) {
+            error!("Could not persist start of saga {}: {}", self.saga_id, e);
+        }
+        events::emit(self.event(SagaEventKind::Started, false, None));
+    }
+
+    pub fn push(&self, stage: S) {
+        self.append(stage, None);
+    }
+
+    /// Pushes stage along with the entity it produced in downstream service
+    pub fn push_with_result<T: Serialize>(&self, stage: S, result: &T) {
+        let result = serde_json::to_value(result)
+            .map_err(|e| error!("Could not serialize result of stage {:?} of saga {}: {}", stage, self.saga_id, e))
+            .ok();
+        self.append(stage, result);
+    }
+
+    fn append(&self, stage: S, result: Option<Value>) {
+        self.stages.borrow_mut().push(stage.clone());
+        let entry = SagaLogEntry::new(stage.into_saga_stage(), result);
+        if let Err(e) = self.store.append_stage(self.saga_id, entry) {
+            error!("Could not persist stage of saga {}: {}", self.saga_id, e);
+        }
+    }
+
+    pub fn stages(&self) -> Vec<S> {
+        self.stages.borrow().clone()
+    }
+
+    /// Records failure of soft step, it does not fail the saga
+    pub fn warn(&self, step: &str, error: &FailureError) {
+        warn!("Soft step {} of saga {} failed: {}", step, self.saga_id, error);
+        let warning = SagaWarning::new(step, error.to_string());
+        self.warnings.borrow_mut().push(warning.clone());
+        if let Err(e) = self.store.add_warning(self.saga_id, warning) {
+            error!("Could not persist warning of saga {}: {}", self.saga_id, e);
+        }
+    }
+
+    /// Records that step is performed with superadmin rights instead of rights of the caller
+    pub fn escalate(&self, step: &str, reason: &str, caller: Option<UserId>) {
+        info!("Step {} of saga {} is escalated to superadmin: {}", step, self.saga_id, reason);
+        if let Err(e) = self.store.add_escalation(self.saga_id, SagaEscalation::new(step, reason, caller)) {
+            error!("Could not persist escalation of saga {}: {}", self.saga_id, e);
+        }
+    }
+
+    /// Saves input of saga executed in background, so that the saga can be resumed after restart or on another replica
+    pub fn set_input<T: Serialize>(&self, input: &T) {
+        let res = serde_json::to_value(input)
+            .map_err(FailureError::from)
+            .and_then(|input| self.store.set_input(self.saga_id, input));
+        if let Err(e) = res {
+            error!("Could not persist input of saga {}: {}", self.saga_id, e);
+        }
+    }
+
+    /// Claims saga for the process or renews the claim, `false` if another process executes the saga
+    pub fn claim(&self, lease: Duration) -> Box<Future<Item = bool, Error = ()>> {
+        let saga_id = self.saga_id;
+        Box::new(self.store.claim(saga_id, lease).or_else(move |e| {
+            error!("Could not claim saga {}: {}", saga_id, e);
+            Ok(false)
+        }))
+    }
+
+    pub fn release(&self) {
+        if let Err(e) = self.store.release(self.saga_id) {
+            error!("Could not release claim of saga {}: {}", self.saga_id, e);
+        }
+    }
+
+    /// Locks entity for the rest of the saga, fails with `Error::Conflict` if another saga changes it
+    pub fn lock(&self, entity: LockedEntity, lease: Duration) -> Box<Future<Item = (), Error = FailureError>> {
+        if self.locks.borrow().contains(&entity) {
+            return Box::new(future::ok(()));
+        }
+        let saga_id = self.saga_id;
+        let store = self.store.clone();
+        let locks = self.locks.clone();
+        Box::new(self.store.lock_entity(entity, saga_id, lease).and_then(move |is_locked| {
+            if !is_locked {
+                return Err(FailureError::from(
+                    format_err!("Another saga is changing {}", entity).context(Error::Conflict),
+                ));
+            }
+            // log was dropped while the lock was being acquired, nothing unlocks it later
+            if Rc::strong_count(&locks) == 1 {
+                return store.unlock_entity(entity, saga_id);
+            }
+            locks.borrow_mut().push(entity);
+            Ok(())
+        }))
+    }
+
+    pub fn warnings(&self) -> Vec<SagaWarning> {
+        self.warnings.borrow().clone()
+    }
+
+    /// Wraps result of completed saga into response carrying its warnings
+    pub fn response<T>(&self, result: T) -> SagaResponse<T> {
+        SagaResponse {
+            saga_id: self.saga_id,
+            result,
+            warnings: self.warnings(),
+        }
+    }
+
+    /// Runs `revert` for every logged step in reverse order of completion, carrying on after failures.
+    /// `revert` gets the start stage of step along with the phase the step reached: steps which were
+    /// started but never completed may not have taken effect downstream, so there may be nothing to revert
+    /// and `404 Not Found` from the downstream service counts as success for them.
+    /// Report of the attempt is saved to saga store, the error lists every failed compensation.
+    /// Start of compensation and every failed step are reported to Sentry.
+    pub fn compensate<F>(&self, mut revert: F) -> Box<Future<Item = (), Error = FailureError>>
+    where
+        S: 'static,
+        F: FnMut(S, StepPhase) -> Box<Future<Item = (), Error = FailureError>> + 'static,
+    {
+        // compensations must run even if the deadline of happy path has passed
+        if let Some(ref budget) = *self.budget.borrow() {
+            budget.detach();
+        }
+        let saga_id = self.saga_id;
+        let saga_type = self.saga_type.get();
+        let store = self.store.clone();
+        let started_at = self.started_at.get();
+        let event = self.event(SagaEventKind::Compensated, false, None);
+        let steps = compensation_order(&self.stages());
+        if let Some((stage, _)) = steps.first() {
+            capture_compensation_started(saga_id, saga_type, stage.step().0);
+        }
+
+        Box::new(
+            iter_ok::<_, FailureError>(steps)
+                .fold(CompensationReport::default(), move |mut report, (stage, phase)| {
+                    AssertUnwindSafe(revert(stage.clone(), phase)).catch_unwind().then(move |res| {
+                        let res = res.unwrap_or_else(|payload| Err(format_err!("Compensation panicked: {}", panic_message(&*payload))));
+                        match res {
+                            Ok(()) => report.compensated.push(stage.into_saga_stage()),
+                            Err(ref err) if phase == StepPhase::Start && is_not_found(err) => {
+                                debug!("Interrupted stage {:?} of saga {} has not taken effect: {}", stage, saga_id, err);
+                                report.compensated.push(stage.into_saga_stage());
+                            }
+                            Err(err) => {
+                                error!("Compensation of stage {:?} of saga {} failed: {}", stage, saga_id, err);
+                                capture_compensation_failure(saga_id, saga_type, stage.step().0, &err);
+                                report.failures.push(CompensationFailure {
+                                    stage: stage.into_saga_stage(),
+                                    error: err.to_string(),
+                                });
+                            }
+                        }
+                        Ok::<_, FailureError>(report)
+                    })
+                })
+                .and_then(move |mut report| {
+                    report.finished_at = SystemTime::now();
+                    let result = if report.is_success() {
+                        Ok(())
+                    } else {
+                        Err(format_err!("{}", report))
+                    };
+                    events::emit(SagaEvent {
+                        compensated: report.is_success(),
+                        duration_ms: duration_ms(SystemTime::now().duration_since(started_at).unwrap_or_default()),
+                        ..event
+                    });
+                    if let Err(e) = store.add_compensation_report(saga_id, report) {
+                        error!("Could not persist compensation report of saga {}: {}", saga_id, e);
+                    }
+                    result
+                }),
+        )
+    }
+
+    pub fn finish(&self, status: SagaStatus, error: Option<String>) {
+        if let Err(e) = self.store.set_status(self.saga_id, status, error) {
+            error!("Could not persist status {} of saga {}: {}", status, self.saga_id, e);
+        }
+        let compensated = status == SagaStatus::Reverted || status == SagaStatus::RevertFailed;
+        events::emit(self.event(SagaEventKind::Finished, compensated, Some(status)));
+    }
+
+    /// Lifecycle event of the saga as of now
+    fn event(&self, kind: SagaEventKind, compensated: bool, status: Option<SagaStatus>) -> SagaEvent {
+        SagaEvent {
+            event: kind,
+            saga_id: self.saga_id,
+            saga_type: self.saga_type.get(),
+            duration_ms: duration_ms(SystemTime::now().duration_since(self.started_at.get()).unwrap_or_default()),
+            stages_completed: self
+                .stages
+                .borrow()
+                .iter()
+                .filter(|stage| stage.step().1 == StepPhase::Complete)
+                .count(),
+            compensated,
+            status,
+        }
+    }
+}
+
+impl<S> Drop for SagaLog<S> {
+    fn drop(&mut self) {
+        for entity in self.locks.borrow_mut().drain(..) {
+            if let Err(e) = self.store.unlock_entity(entity, self.saga_id) {
+                error!("Could not unlock {} locked by saga {}: {}", entity, self.saga_id, e);
+            }
+        }
+    }
+}
+
+/// Runs soft step of saga, its failure is recorded as a warning instead of failing the saga
+pub fn soft_step<S, X, T, F>(log: Rc<SagaLog<S>>, step: &'static str, fut: F) -> impl Future<Item = X, Error = (X, FailureError)>
+where
+    S: OperationStage,
+    F: Future<Item = (X, T), Error = (X, FailureError)>,
+{
+    fut.then(move |res| match res {
+        Ok((s, _)) => Ok(s),
+        Err((s, e)) => {
+            log.warn(step, &e);
+            Ok(s)
+        }
+    })
+}
+
+/// Service executing saga, `run` finishes the saga in its log and compensates it on failure
+pub trait SagaService: Clone + Sized {
+    type Stage: OperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>>;
+
+    /// Compensates steps of the failed saga
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>>;
+}
+
+/// Runs happy path of saga within `deadline`, its panics fail the saga. Saga is finished as completed once
+/// happy path succeeds, otherwise completed steps are compensated and saga is finished as reverted or as failed to revert.
+/// Errors of microservices are turned into validation errors of `fields` for the caller.
+pub fn run<S, T, F, R>(
+    service: S,
+    saga_type: SagaType,
+    deadline: Duration,
+    happy_path: F,
+    fields: &'static [&'static str],
+) -> impl Future<Item = (S, SagaResponse<T>), Error = (S, FailureError)>
+where
+    S: SagaService,
+    F: FnOnce(S) -> R,
+    R: Future<Item = (S, T), Error = (S, FailureError)>,
+{
+    let saga_id = service.log().saga_id();
+    let happy = isolate_panics(service.clone(), saga_id, saga_type, {
+        let service = service.clone();
+        move || happy_path(service)
+    });
+
+    with_deadline(service, deadline, happy)
+        .map(|(s, result)| {
+            s.log().finish(SagaStatus::Completed, None);
+            let response = s.log().response(result);
+            (s, response)
+        })
+        .or_else(move |(s, e)| {
+            s.revert().then(move |res| {
+                let s = match res {
+                    Ok(s) => {
+                        s.log().finish(SagaStatus::Reverted, Some(e.to_string()));
+                        s
+                    }
+                    Err((s, revert_e)) => {
+                        s.log().finish(SagaStatus::RevertFailed, Some(revert_e.to_string()));
+                        s
+                    }
+                };
+                Err::<(S, SagaResponse<T>), _>((s, parse_validation_errors(e, fields)))
+            })
+        })
+}
+
+/// Turns panic inside happy path of saga into saga error, so that completed steps get compensated
+/// instead of the panic tearing down connection task. Panic is reported to Sentry along with saga context.
+/// Happy path is built lazily, so panics during its construction are caught as well.
+pub fn isolate_panics<S, T, F, R>(
+    service: S,
+    saga_id: SagaId,
+    saga_type: SagaType,
+    happy_path: F,
+) -> impl Future<Item = (S, T), Error = (S, FailureError)>
+where
+    F: FnOnce() -> R,
+    R: Future<Item = (S, T), Error = (S, FailureError)>,
+{
+    AssertUnwindSafe(future::lazy(happy_path))
+        .catch_unwind()
+        .then(move |res| match res {
+            Ok(res) => res,
+            Err(payload) => {
+                let message = panic_message(&*payload);
+                capture_saga_panic(saga_id, saga_type, &message);
+                Err((
+                    service,
+                    FailureError::from(format_err!("Saga {} panicked: {}", saga_id, message).context(Error::Unknown)),
+                ))
+            }
+        })
+}
+
+fn panic_message(payload: &(Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// Fails happy path of saga if it does not finish before `deadline`, so that completed steps get compensated.
+/// Happy path owns the service, so `service` is handed back along with the error on expiry.
+pub fn with_deadline<S, T, F>(service: S, deadline: Duration, happy_path: F) -> impl Future<Item = (S, T), Error = (S, FailureError)>
+where
+    F: Future<Item = (S, T), Error = (S, FailureError)>,
+{
+    Timeout::new(happy_path, deadline).then(move |res| match res {
+        Ok(res) => Ok(res),
+        Err(ref e) if e.is_elapsed() => Err((
+            service,
+            FailureError::from(format_err!("Saga deadline of {:?} expired", deadline).context(Error::HttpClient)),
+        )),
+        Err(e) => match e.into_inner() {
+            Some(e) => Err(e),
+            None => Err((
+                service,
+                FailureError::from(format_err!("Saga deadline timer failed").context(Error::Unknown)),
+            )),
+        },
+    })
+}
+
+/// Renews claim of saga executed in background while `fut` runs, so that reaper does not take it over
+pub fn with_heartbeat<S, F>(log: Rc<SagaLog<S>>, config: &config::Saga, fut: F) -> impl Future<Item = F::Item, Error = F::Error>
+where
+    S: OperationStage + 'static,
+    F: Future,
+{
+    let saga_id = log.saga_id();
+    let interval = Duration::from_millis(config.heartbeat_interval_ms);
+    let lease = Duration::from_millis(config.claim_lease_ms);
+    let heartbeat = Interval::new(Instant::now() + interval, interval)
+        .map_err(move |e| error!("Heartbeat timer of saga {} failed: {}", saga_id, e))
+        .for_each(move |_| {
+            // the next renewal waits for this one, so renewals never pile up
+            log.claim(lease).map(move |claimed| {
+                if !claimed {
+                    warn!("Saga {} was taken over by reaper of this or another replica", saga_id);
+                }
+            })
+        });
+
+    fut.select2(heartbeat).then(|res| match res {
+        Ok(Either::A((item, _))) => Either::A(future::ok(item)),
+        Err(Either::A((e, _))) => Either::A(future::err(e)),
+        // heartbeat timer failed, saga runs on without it
+        Ok(Either::B((_, fut))) | Err(Either::B((_, fut))) => Either::B(fut),
+    })
+}
+
+/// Returns start stages of saga steps along with the phase they reached, in the order they have
+/// to be compensated in: reverse order of their completion. Steps which were started but never
+/// completed were the last ones to be executed, so they are compensated first.
+pub fn compensation_order<S: OperationStage>(stages: &[S]) -> Vec<(S, StepPhase)> {
+    let mut steps = stages
+        .iter()
+        .enumerate()
+        .filter(|(_, stage)| stage.step().1 == StepPhase::Start)
+        .map(|(start_index, stage)| {
+            let name = stage.step().0;
+            let completed_at = stages
+                .iter()
+                .enumerate()
+                .skip(start_index + 1)
+                .find(|(_, other)| other.step() == (name, StepPhase::Complete))
+                .map(|(index, _)| index);
+            (completed_at, start_index, stage.clone())
+        })
+        .collect::<Vec<_>>();
+
+    steps.sort_by(
+        |(a_completed, a_started, _), (b_completed, b_started, _)| match (a_completed, b_completed) {
+            (None, None) => b_started.cmp(a_started),
+            (None, Some(_)) => Ordering::Less,
+            (Some(_), None) => Ordering::Greater,
+            (Some(a), Some(b)) => b.cmp(a),
+        },
+    );
+
+    steps
+        .into_iter()
+        .map(|(completed_at, _, stage)| match completed_at {
+            Some(_) => (stage, StepPhase::Complete),
+            None => (stage, StepPhase::Start),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use stq_types::{RoleId, SagaId, UserId};
+
+    use super::compensation_order;
+    use models::CreateProfileOperationStage::*;
+    use models::StepPhase::{Complete, Start};
+
+    #[test]
+    fn compensates_in_reverse_order_of_completion() {
+        let saga_id = SagaId::new();
+        let users_role = RoleId::new();
+        let store_role = RoleId::new();
+        let user_id = UserId(1);
+        let log = vec![
+            AccountCreationStart(saga_id),
+            AccountCreationComplete(saga_id),
+            UsersRoleSetStart(users_role),
+            UsersRoleSetComplete(users_role),
+            StoreRoleSetStart(store_role),
+            StoreRoleSetComplete(store_role),
+            BillingCreateMerchantStart(user_id),
+            BillingCreateMerchantComplete(user_id),
+        ];
+
+        assert_eq!(
+            compensation_order(&log),
+            vec![
+                (BillingCreateMerchantStart(user_id), Complete),
+                (StoreRoleSetStart(store_role), Complete),
+                (UsersRoleSetStart(users_role), Complete),
+                (AccountCreationStart(saga_id), Complete),
+            ]
+        );
+    }
+
+    #[test]
+    fn compensates_interrupted_step_first() {
+        let saga_id = SagaId::new();
+        let users_role = RoleId::new();
+        let store_role = RoleId::new();
+        let log = vec![
+            AccountCreationStart(saga_id),
+            AccountCreationComplete(saga_id),
+            UsersRoleSetStart(users_role),
+            UsersRoleSetComplete(users_role),
+            StoreRoleSetStart(store_role),
+        ];
+
+        assert_eq!(
+            compensation_order(&log),
+            vec![
+                (StoreRoleSetStart(store_role), Start),
+                (UsersRoleSetStart(users_role), Complete),
+                (AccountCreationStart(saga_id), Complete),
+            ]
+        );
+    }
+
+    #[test]
+    fn orders_by_completion_rather_than_start() {
+        let saga_id = SagaId::new();
+        let users_role = RoleId::new();
+        let log = vec![
+            AccountCreationStart(saga_id),
+            UsersRoleSetStart(users_role),
+            UsersRoleSetComplete(users_role),
+            AccountCreationComplete(saga_id),
+        ];
+
+        assert_eq!(
+            compensation_order(&log),
+            vec![(AccountCreationStart(saga_id), Complete), (UsersRoleSetStart(users_role), Complete)]
+        );
+    }
+}
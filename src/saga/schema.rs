@@ -0,0 +1,189 @@
+//! Versioning of persisted saga logs. Any change of serialized form of saga
+//! records or operation stages (renaming or changing payload of a stage, etc.)
+//! must bump `SCHEMA_VERSION` and add a migration from the previous version,
+//! so that logs written by older builds can still be reverted and recovered.
+//! Adding a brand new stage variant does not require a migration.
+use failure::Error as FailureError;
+use serde_json::{self, Map, Value};
+
+use models::SagaRecord;
+
+/// Current version of persisted saga log schema
+pub const SCHEMA_VERSION: u64 = 2;
+
+type Migration = fn(Value) -> Result<Value, FailureError>;
+
+/// Migrations of a single saga record, `MIGRATIONS[i]` upgrades record of version `i + 1` to version `i + 2`
+const MIGRATIONS: &[Migration] = &[wrap_stages_into_entries];
+
+/// Persisted saga log, records are serialized as is or with personal data encrypted
+#[derive(Serialize)]
+pub struct SagaLogFile<T> {
+    pub schema_version: u64,
+    pub records: Vec<T>,
+}
+
+impl<T> SagaLogFile<T> {
+    pub fn new(records: Vec<T>) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            records,
+        }
+    }
+}
+
+/// Parses persisted saga log of any known version, migrating records to the current one
+pub fn load(log: Value) -> Result<Vec<SagaRecord>, FailureError> {
+    let (version, records) = match log {
+        // logs written before versioning was introduced
+        Value::Array(records) => (1, records),
+        Value::Object(mut log) => {
+            let version = log
+                .get("schema_version")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| format_err!("Saga log has no schema version"))?;
+            match log.remove("records") {
+                Some(Value::Array(records)) => (version, records),
+                _ => return Err(format_err!("Saga log has no records")),
+            }
+        }
+        _ => return Err(format_err!("Saga log has unexpected format")),
+    };
+
+    if version == 0 || version > SCHEMA_VERSION {
+        return Err(format_err!(
+            "Saga log schema version {} is not supported, current version is {}",
+            version,
+            SCHEMA_VERSION
+        ));
+    }
+
+    if version < SCHEMA_VERSION {
+        info!("Migrating saga log from schema version {} to {}", version, SCHEMA_VERSION);
+    }
+
+    records
+        .into_iter()
+        .map(|record| {
+            let record = migrate(record, version)?;
+            serde_json::from_value::<SagaRecord>(record).map_err(FailureError::from)
+        })
+        .collect()
+}
+
+fn migrate(record: Value, version: u64) -> Result<Value, FailureError> {
+    MIGRATIONS
+        .iter()
+        .skip(version as usize - 1)
+        .fold(Ok(record), |record, migration| record.and_then(migration))
+}
+
+/// v1 -> v2: stages became entries carrying results of downstream calls
+fn wrap_stages_into_entries(mut record: Value) -> Result<Value, FailureError> {
+    let recorded_at = record
+        .get("updated_at")
+        .cloned()
+        .ok_or_else(|| format_err!("Saga record has no updated_at field"))?;
+
+    if let Some(Value::Array(stages)) = record.get_mut("stages") {
+        for stage in stages.iter_mut() {
+            if stage.get("stage").is_none() {
+                let mut entry = Map::new();
+                entry.insert("stage".to_string(), stage.take());
+                entry.insert("result".to_string(), Value::Null);
+                entry.insert("recorded_at".to_string(), recorded_at.clone());
+                *stage = Value::Object(entry);
+            }
+        }
+    }
+
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use serde_json::{self, Value};
+
+    use stq_types::{RoleId, SagaId, StoreId};
+
+    use super::{load, SagaLogFile};
+    use models::*;
+
+    fn fixture(content: &str) -> Value {
+        serde_json::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn loads_unversioned_log() {
+        let records = load(fixture(include_str!("fixtures/saga_log_v1.json"))).unwrap();
+        assert_eq!(records.len(), 1);
+
+        let record = &records[0];
+        let saga_id = "2b1c7e0a-52a5-4c8e-9a57-0c6f3b6ec1d1".parse::<SagaId>().unwrap();
+        let role_id: RoleId = serde_json::from_value(json!("8e0f9c1b-3f5e-4f0a-bb0c-2f4b2f4f9a10")).unwrap();
+        assert_eq!(record.id, saga_id);
+        assert_eq!(record.saga_type, SagaType::CreateAccount);
+        assert_eq!(record.status, SagaStatus::RevertFailed);
+        assert_eq!(record.revert_attempts, 2);
+        assert_eq!(
+            record.stages.iter().map(|entry| entry.stage.clone()).collect::<Vec<_>>(),
+            vec![
+                CreateProfileOperationStage::AccountCreationStart(saga_id).into_saga_stage(),
+                CreateProfileOperationStage::AccountCreationComplete(saga_id).into_saga_stage(),
+                CreateProfileOperationStage::UsersRoleSetStart(role_id).into_saga_stage(),
+            ]
+        );
+        // stages of v1 carry no results, they are dated by the last update of the record
+        for entry in &record.stages {
+            assert!(entry.result.is_none());
+            assert_eq!(entry.marker, StepMarker::Executed);
+            assert_eq!(entry.recorded_at, UNIX_EPOCH + Duration::from_secs(1_540_000_060));
+        }
+        assert_eq!(
+            record.pending_steps(),
+            vec![CreateProfileOperationStage::UsersRoleSetStart(role_id).into_saga_stage()]
+        );
+    }
+
+    #[test]
+    fn loads_v2_log() {
+        let records = load(fixture(include_str!("fixtures/saga_log_v2.json"))).unwrap();
+        assert_eq!(records.len(), 1);
+
+        let record = &records[0];
+        assert_eq!(record.saga_type, SagaType::CreateStore);
+        assert_eq!(record.stages.len(), 2);
+        assert_eq!(
+            record.stages[1].stage,
+            CreateStoreOperationStage::StoreCreationComplete(StoreId(42)).into_saga_stage()
+        );
+        assert_eq!(record.stages[1].result, Some(json!({"id": 42, "name": "Shop"})));
+        assert!(record.warnings.is_empty());
+        assert!(record.claim.is_none());
+    }
+
+    #[test]
+    fn loads_log_it_writes() {
+        let mut record = SagaRecord::new(SagaId::new(), SagaType::CreateAccount);
+        record.stages.push(SagaLogEntry::recovered(
+            CreateProfileOperationStage::AccountCreationComplete(record.id).into_saga_stage(),
+            Some(json!({"id": 1})),
+        ));
+        let log = serde_json::to_value(SagaLogFile::new(vec![&record])).unwrap();
+
+        let records = load(log).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, record.id);
+        assert_eq!(records[0].stages[0].stage, record.stages[0].stage);
+        assert_eq!(records[0].stages[0].marker, StepMarker::Recovered);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        assert!(load(json!({"schema_version": 0, "records": []})).is_err());
+        assert!(load(json!({"schema_version": super::SCHEMA_VERSION + 1, "records": []})).is_err());
+        assert!(load(json!({"records": []})).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! Claims of background sagas and entity locks shared by replicas in Redis. Replica
+//! executing saga in background holds a lease on it and publishes saga record once it claims
+//! the saga and whenever the record changes, heartbeats only extend the lease. Reaper of any
+//! replica takes over saga whose lease expired and resumes it from the record. Stages appended
+//! right before the replica stopped may not be published, recovery finds out whether they took
+//! effect. Entity is locked with a lease held by the saga, so that conflicting sagas are refused
+//! on any replica and a lock outlives a stopped replica only until the lease expires.
+//! Redis is called with blocking io on a separate thread, not on the reactor. The thread calls
+//! Redis one command at a time, so that commands of the replica are applied in order.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use futures::prelude::*;
+use futures::sync::oneshot;
+use futures_cpupool::{Builder as CpuPoolBuilder, CpuPool};
+use serde_json::{self, Value};
+
+use stq_types::SagaId;
+
+use config;
+use models::{LockedEntity, SagaClaim, SagaRecord};
+use redis::{self, LeaseLock, RedisLeaseLock, Reply};
+use saga::encryption::SagaLogCipher;
+
+#[derive(Clone)]
+pub struct SharedSagas {
+    config: config::SagaSharing,
+    /// Personal data is published as is if not set
+    cipher: Option<SagaLogCipher>,
+    pool: CpuPool,
+    /// Latest records of sagas waiting for publication, changes made meanwhile are published at once
+    pending: Arc<Mutex<HashMap<SagaId, SagaRecord>>>,
+}
+
+impl SharedSagas {
+    pub fn new(config: config::SagaSharing, cipher: Option<SagaLogCipher>) -> Self {
+        let pool = CpuPoolBuilder::new().pool_size(1).name_prefix("saga-sharing-").create();
+        Self {
+            config,
+            cipher,
+            pool,
+            pending: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Claims saga for `owner` and publishes saga record, `false` if another replica holds the claim
+    pub fn claim(&self, record: SagaRecord, owner: &str, lease: Duration) -> Box<Future<Item = bool, Error = FailureError>> {
+        let owner = owner.to_string();
+        self.spawn(move |shared| {
+            if !shared.claim_lock(record.id, &owner, lease).acquire()? {
+                return Ok(false);
+            }
+            shared.publish_now(&record)?;
+            shared.command(&["SADD", &shared.claimed_key(), &record.id.to_string()])?;
+            Ok(true)
+        })
+    }
+
+    /// Extends claim of `owner` without publishing the record, `false` if another replica took the saga over
+    pub fn renew(&self, saga_id: SagaId, owner: &str, lease: Duration) -> Box<Future<Item = bool, Error = FailureError>> {
+        let owner = owner.to_string();
+        self.spawn(move |shared| shared.claim_lock(saga_id, &owner, lease).acquire())
+    }
+
+    /// Publishes changed record of saga claimed by `owner` in background, failure is logged.
+    /// Record of saga taken over by another replica is left as it is
+    pub fn publish(&self, record: SagaRecord, owner: &str) {
+        let saga_id = record.id;
+        // publication queued before sends the latest record
+        if self.pending.lock().unwrap().insert(saga_id, record).is_some() {
+            return;
+        }
+        let owner = owner.to_string();
+        self.queue(move |shared| {
+            let record = match shared.pending.lock().unwrap().remove(&saga_id) {
+                Some(record) => record,
+                None => return,
+            };
+            let res = shared
+                .claim_lock(saga_id, &owner, Duration::from_secs(0))
+                .holder()
+                .and_then(|holder| match holder {
+                    Some(ref holder) if *holder == owner => shared.publish_now(&record),
+                    _ => Ok(()),
+                });
+            if let Err(e) = res {
+                error!("Could not publish record of saga {}: {}", saga_id, e);
+            }
+        })
+    }
+
+    /// Releases claim of `owner` and withdraws saga record in background, failure is logged.
+    /// Claims of other replicas are left intact
+    pub fn release(&self, saga_id: SagaId, owner: &str) {
+        let owner = owner.to_string();
+        self.queue(move |shared| {
+            if let Err(e) = shared.release_now(saga_id, &owner) {
+                error!("Could not release shared claim of saga {}: {}", saga_id, e);
+            }
+        })
+    }
+
+    /// Claims sagas whose claims have expired for `owner` and returns their records,
+    /// sagas for which `is_known` is true are left to the replica keeping them
+    pub fn take_over<F>(&self, owner: &str, lease: Duration, is_known: F) -> Box<Future<Item = Vec<SagaRecord>, Error = FailureError>>
+    where
+        F: Fn(SagaId) -> bool + Send + 'static,
+    {
+        let owner = owner.to_string();
+        self.spawn(move |shared| shared.take_over_now(&owner, lease, is_known))
+    }
+
+    /// Locks entity for the saga or extends its lock, `false` if another saga holds the lock.
+    /// Lock of saga for which `is_finished` is true is taken over
+    pub fn lock_entity<F>(
+        &self,
+        entity: LockedEntity,
+        saga_id: SagaId,
+        lease: Duration,
+        is_finished: F,
+    ) -> Box<Future<Item = bool, Error = FailureError>>
+    where
+        F: Fn(SagaId) -> bool + Send + 'static,
+    {
+        self.spawn(move |shared| shared.lock_entity_now(entity, saga_id, lease, is_finished))
+    }
+
+    /// Releases lock of the saga, locks of other sagas are left intact. Lock is released in background,
+    /// failure is logged
+    pub fn unlock_entity(&self, entity: LockedEntity, saga_id: SagaId) {
+        self.queue(move |shared| {
+            if let Err(e) = shared.entity_lock(entity, saga_id, Duration::from_secs(0)).release() {
+                error!("Could not unlock {} locked by saga {}: {}", entity, saga_id, e);
+            }
+        })
+    }
+
+    /// Waits for commands queued before
+    #[cfg(test)]
+    pub fn settle(&self) {
+        self.spawn(|_| Ok(())).wait().unwrap()
+    }
+
+    fn take_over_now<F>(&self, owner: &str, lease: Duration, is_known: F) -> Result<Vec<SagaRecord>, FailureError>
+    where
+        F: Fn(SagaId) -> bool,
+    {
+        let saga_ids = self.command(&["SMEMBERS", &self.claimed_key()])?.into_strings();
+        let mut records = vec![];
+        for saga_id in saga_ids.into_iter().filter_map(|saga_id| saga_id.parse::<SagaId>().ok()) {
+            if is_known(saga_id) || !self.claim_lock(saga_id, owner, lease).acquire()? {
+                continue;
+            }
+            match self.load(saga_id) {
+                Ok(Some(mut record)) => {
+                    record.claim = Some(SagaClaim::new(owner));
+                    records.push(record);
+                }
+                // saga was finished by its replica right before the claim expired
+                Ok(None) => self.release_now(saga_id, owner)?,
+                Err(e) => error!("Published record of saga {} can not be loaded: {}", saga_id, e),
+            }
+        }
+        Ok(records)
+    }
+
+    fn release_now(&self, saga_id: SagaId, owner: &str) -> Result<(), FailureError> {
+        if self.claim_lock(saga_id, owner, Duration::from_secs(0)).release()? {
+            self.command(&["DEL", &self.record_key(saga_id)])?;
+            self.command(&["SREM", &self.claimed_key(), &saga_id.to_string()])?;
+        }
+        Ok(())
+    }
+
+    fn publish_now(&self, record: &SagaRecord) -> Result<(), FailureError> {
+        let mut published = serde_json::to_value(record)?;
+        if let Some(ref cipher) = self.cipher {
+            published = cipher.encrypt_record(published)?;
+        }
+        self.command(&["SET", &self.record_key(record.id), &published.to_string()])
+            .map(|_| ())
+    }
+
+    fn lock_entity_now<F>(&self, entity: LockedEntity, saga_id: SagaId, lease: Duration, is_finished: F) -> Result<bool, FailureError>
+    where
+        F: Fn(SagaId) -> bool,
+    {
+        let lock = self.entity_lock(entity, saga_id, lease);
+        if lock.acquire()? {
+            return Ok(true);
+        }
+        match lock.holder()?.and_then(|holder| holder.parse::<SagaId>().ok()) {
+            Some(holder) if is_finished(holder) => {
+                self.entity_lock(entity, holder, lease).release()?;
+                lock.acquire()
+            }
+            Some(_) => Ok(false),
+            // lock was released meanwhile
+            None => lock.acquire(),
+        }
+    }
+
+    /// Runs `f` on the sharing thread, it runs to completion even if the returned future is dropped
+    fn spawn<T, F>(&self, f: F) -> Box<Future<Item = T, Error = FailureError>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&SharedSagas) -> Result<T, FailureError> + Send + 'static,
+    {
+        let (sender, receiver) = oneshot::channel();
+        self.queue(move |shared| {
+            // caller may not wait for the result
+            let _ = sender.send(f(shared));
+        });
+        Box::new(receiver.then(|res| match res {
+            Ok(res) => res,
+            Err(_) => Err(format_err!("Saga sharing thread stopped")),
+        }))
+    }
+
+    /// Runs `f` on the sharing thread without waiting for it
+    fn queue<F>(&self, f: F)
+    where
+        F: FnOnce(&SharedSagas) + Send + 'static,
+    {
+        let shared = self.clone();
+        self.pool
+            .spawn_fn(move || {
+                f(&shared);
+                Ok::<_, ()>(())
+            })
+            .forget();
+    }
+
+    fn load(&self, saga_id: SagaId) -> Result<Option<SagaRecord>, FailureError> {
+        let published = match self.command(&["GET", &self.record_key(saga_id)])?.into_string() {
+            Some(published) => serde_json::from_str::<Value>(&published)?,
+            None => return Ok(None),
+        };
+        let record = match self.cipher {
+            Some(ref cipher) => cipher.decrypt_record(published)?,
+            None => published,
+        };
+        Ok(Some(serde_json::from_value(record)?))
+    }
+
+    fn claim_lock(&self, saga_id: SagaId, owner: &str, lease: Duration) -> RedisLeaseLock {
+        RedisLeaseLock::keyed(
+            self.config.redis_address.clone(),
+            format!("{}:claim:{}", self.config.key_prefix, saga_id),
+            owner.to_string(),
+            lease,
+        )
+    }
+
+    fn entity_lock(&self, entity: LockedEntity, saga_id: SagaId, lease: Duration) -> RedisLeaseLock {
+        let entity = match entity {
+            LockedEntity::Store(id) => format!("store:{}", id),
+            LockedEntity::BaseProduct(id) => format!("base_product:{}", id),
+            LockedEntity::Product(id) => format!("product:{}", id),
+        };
+        RedisLeaseLock::keyed(
+            self.config.redis_address.clone(),
+            format!("{}:lock:{}", self.config.key_prefix, entity),
+            saga_id.to_string(),
+            lease,
+        )
+    }
+
+    fn record_key(&self, saga_id: SagaId) -> String {
+        format!("{}:record:{}", self.config.key_prefix, saga_id)
+    }
+
+    /// Set of sagas with published records
+    fn claimed_key(&self) -> String {
+        format!("{}:claimed", self.config.key_prefix)
+    }
+
+    fn command(&self, args: &[&str]) -> Result<Reply, FailureError> {
+        redis::command(&self.config.redis_address, args)
+    }
+}
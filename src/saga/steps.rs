@@ -0,0 +1,160 @@
+//! Execution of saga steps according to `config::SagaDefinition`
+use std::rc::Rc;
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use futures::future::join_all;
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use tokio_timer::timeout::Error as TimeoutError;
+use tokio_timer::Timeout;
+
+use config::SagaDefinition;
+use errors::Error;
+use models::OperationStage;
+use saga::SagaLog;
+
+pub type StepFuture<S> = Box<Future<Item = (S, ()), Error = (S, FailureError)>>;
+
+/// Runs groups of steps one after another, steps of the same group are run concurrently.
+/// `run_step` executes a single step by its name, failures of soft steps are recorded in `log` as warnings.
+/// Steps with declared timeout fail once it expires.
+pub fn run_steps<S, St, F>(
+    service: S,
+    log: Rc<SagaLog<St>>,
+    definition: SagaDefinition,
+    run_step: F,
+) -> Box<Future<Item = S, Error = (S, FailureError)>>
+where
+    S: Clone + 'static,
+    St: OperationStage + 'static,
+    F: Fn(S, &str) -> StepFuture<S> + 'static,
+{
+    let groups = definition.groups();
+    Box::new(iter_ok::<_, (S, FailureError)>(groups).fold(service, move |s, group| {
+        let steps = group
+            .into_iter()
+            .map(|step| {
+                let soft = definition.is_soft(&step);
+                let log = log.clone();
+                let fut = match definition.timeout(&step) {
+                    Some(timeout) => with_timeout(s.clone(), &step, timeout, run_step(s.clone(), &step)),
+                    None => run_step(s.clone(), &step),
+                };
+                fut.then(move |res| match res {
+                    Ok(_) => Ok::<_, FailureError>(None),
+                    Err((_, e)) => {
+                        if soft {
+                            log.warn(&step, &e);
+                            Ok(None)
+                        } else {
+                            Ok(Some((step, e)))
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        join_all(steps).then(
+            move |res| match res.map(|failures| failures.into_iter().filter_map(|failure| failure).next()) {
+                Ok(None) => Ok(s),
+                Ok(Some((step, e))) => Err((s, FailureError::from(e.context(format!("Step {} of saga failed", step))))),
+                Err(e) => Err((s, e)),
+            },
+        )
+    }))
+}
+
+/// Fails step which does not finish within its timeout, so that a single slow step
+/// does not take the whole deadline of saga
+fn with_timeout<S: 'static>(service: S, step: &str, timeout: Duration, fut: StepFuture<S>) -> StepFuture<S> {
+    let step = step.to_string();
+    Box::new(Timeout::new(fut, timeout).then(move |res| match res {
+        Ok(res) => Ok(res),
+        Err(e) => match step_error(&step, timeout, e) {
+            Ok(e) => Err(e),
+            Err(e) => Err((service, e)),
+        },
+    }))
+}
+
+/// Fails call of step with fixed place in saga, like steps of order sagas, once timeout declared for the step expires.
+/// Call is run as is if the step has no timeout.
+pub fn with_step_timeout<T, F>(step: &str, timeout: Option<Duration>, fut: F) -> Box<Future<Item = T, Error = FailureError>>
+where
+    T: 'static,
+    F: Future<Item = T, Error = FailureError> + 'static,
+{
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Box::new(fut),
+    };
+    let step = step.to_string();
+    Box::new(Timeout::new(fut, timeout).map_err(move |e| match step_error(&step, timeout, e) {
+        Ok(e) => e,
+        Err(e) => e,
+    }))
+}
+
+/// Error of the step itself, or error of the timeout if step did not finish in time
+fn step_error<E>(step: &str, timeout: Duration, e: TimeoutError<E>) -> Result<E, FailureError> {
+    if e.is_elapsed() {
+        return Err(format_err!("Step {} timed out after {:?}", step, timeout)
+            .context(Error::HttpClient)
+            .into());
+    }
+    match e.into_inner() {
+        Some(e) => Ok(e),
+        None => Err(format_err!("Timer of step {} failed", step).context(Error::Unknown).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use failure::Error as FailureError;
+    use futures::future;
+    use futures::prelude::*;
+    use tokio_core::reactor::Core;
+
+    use super::with_step_timeout;
+
+    #[test]
+    fn compensates_saga_once_step_times_out() {
+        let compensated = Rc::new(RefCell::new(vec![]));
+        let completed = Rc::new(RefCell::new(vec![]));
+
+        let saga = with_step_timeout("billing_reserve_gift_cards", Some(Duration::from_secs(1)), future::ok(()))
+            .and_then({
+                let completed = completed.clone();
+                move |_| {
+                    completed.borrow_mut().push("billing_reserve_gift_cards");
+                    // billing never responds
+                    with_step_timeout(
+                        "billing_create_invoice",
+                        Some(Duration::from_millis(10)),
+                        future::empty::<(), FailureError>(),
+                    )
+                }
+            })
+            .or_else({
+                let completed = completed.clone();
+                let compensated = compensated.clone();
+                move |e| {
+                    for step in completed.borrow().iter().rev() {
+                        compensated.borrow_mut().push(*step);
+                    }
+                    Err::<(), _>(e)
+                }
+            });
+
+        let e = Core::new().unwrap().run(saga).unwrap_err();
+        assert!(e
+            .iter_chain()
+            .any(|cause| cause.to_string().contains("Step billing_create_invoice timed out")));
+        assert_eq!(*compensated.borrow(), vec!["billing_reserve_gift_cards"]);
+    }
+}
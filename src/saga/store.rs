@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::future;
+use futures::prelude::*;
+use serde_json::{self, Value};
+use uuid::Uuid;
+
+use stq_types::SagaId;
+
+use errors::Error;
+use json_file::{JsonFile, JsonFileWriter};
+use models::{
+    CompensationReport, EntityLock, LockedEntity, RecordedCall, RecordedRequest, SagaClaim, SagaEscalation, SagaLogEntry, SagaRecord,
+    SagaStatus, SagaWarning,
+};
+use saga::encryption::{self, SagaLogCipher};
+use saga::schema::{self, SagaLogFile};
+use saga::shared::SharedSagas;
+
+/// Storage of saga operation logs
+pub trait SagaStore {
+    /// Saves record of a newly started saga
+    fn insert(&self, record: SagaRecord) -> Result<(), FailureError>;
+    /// Appends stage to the operation log of saga
+    fn append_stage(&self, saga_id: SagaId, entry: SagaLogEntry) -> Result<(), FailureError>;
+    /// Sets saga status, `error` describes the reason of failure if any
+    fn set_status(&self, saga_id: SagaId, status: SagaStatus, error: Option<String>) -> Result<(), FailureError>;
+    /// Saves warning of failed soft step
+    fn add_warning(&self, saga_id: SagaId, warning: SagaWarning) -> Result<(), FailureError>;
+    /// Saves escalation of step to superadmin rights
+    fn add_escalation(&self, saga_id: SagaId, escalation: SagaEscalation) -> Result<(), FailureError>;
+    /// Saves report of compensation attempt
+    fn add_compensation_report(&self, saga_id: SagaId, report: CompensationReport) -> Result<(), FailureError>;
+    /// Saves input of saga executed in background
+    fn set_input(&self, saga_id: SagaId, input: Value) -> Result<(), FailureError>;
+    /// Saves request the saga was started with
+    fn record_request(&self, saga_id: SagaId, request: RecordedRequest) -> Result<(), FailureError>;
+    /// Appends call to microservice made by the saga
+    fn record_call(&self, saga_id: SagaId, call: RecordedCall) -> Result<(), FailureError>;
+    /// Claims saga for the process or renews its claim, `false` if saga is claimed by another process
+    /// whose claim has not expired yet
+    fn claim(&self, saga_id: SagaId, lease: Duration) -> Box<Future<Item = bool, Error = FailureError>>;
+    /// Releases claim of the process, claims of other processes are left intact. Shared claim is released in background
+    fn release(&self, saga_id: SagaId) -> Result<(), FailureError>;
+    /// Claims sagas of other replicas whose claims have expired and adds their records to the store.
+    /// Returns records of the sagas, none if claims are not shared by replicas
+    fn take_over(&self, lease: Duration) -> Box<Future<Item = Vec<SagaRecord>, Error = FailureError>>;
+    /// Locks entity for the saga, `false` if another saga holds the lock. Locks of sagas which are not
+    /// in progress any more and locks held longer than `lease` are taken over
+    fn lock_entity(&self, entity: LockedEntity, saga_id: SagaId, lease: Duration) -> Box<Future<Item = bool, Error = FailureError>>;
+    /// Releases lock of the saga, locks of other sagas are left intact. Shared lock is released in background
+    fn unlock_entity(&self, entity: LockedEntity, saga_id: SagaId) -> Result<(), FailureError>;
+    /// Increments counter of revert attempts and returns its new value
+    fn register_revert_attempt(&self, saga_id: SagaId) -> Result<u32, FailureError>;
+    fn get(&self, saga_id: SagaId) -> Result<Option<SagaRecord>, FailureError>;
+    fn find_by_status(&self, statuses: &[SagaStatus]) -> Result<Vec<SagaRecord>, FailureError>;
+    /// Removes records of sagas in any of `statuses` that have not changed since `updated_before`,
+    /// claimed records are kept. Returns number of removed records
+    fn purge(&self, statuses: &[SagaStatus], updated_before: SystemTime) -> Result<usize, FailureError>;
+}
+
+/// Keeps saga records in memory, optionally mirroring them into json file.
+/// The file is written in background after every change.
+pub struct SagaStoreImpl {
+    records: Arc<Mutex<HashMap<SagaId, SagaRecord>>>,
+    /// Locks are advisory and kept in memory of the replica if claims and locks are not shared
+    locks: Mutex<HashMap<LockedEntity, EntityLock>>,
+    writer: Option<Arc<JsonFileWriter>>,
+    /// Claims are kept in saga log of the replica if not set, they hand sagas over from
+    /// a stopped process to its restart then, not to other replicas. Locks conflict only
+    /// with sagas of the replica then
+    shared: Option<SharedSagas>,
+    /// Identifies the process in saga claims
+    owner: String,
+}
+
+impl SagaStoreImpl {
+    /// Personal data is written to the file as is if `cipher` is not set
+    pub fn new(path: Option<PathBuf>, cipher: Option<SagaLogCipher>) -> Result<Self, FailureError> {
+        let file = path.map(|path| JsonFile::new(path, "saga log"));
+        let records = match file {
+            Some(ref file) => match file.read::<Value>()? {
+                Some(log) => {
+                    let log = encryption::decrypt_log(log, cipher.as_ref()).map_err(|e| e.context("Could not decrypt saga log"))?;
+                    let records = schema::load(log).map_err(|e| e.context("Could not load saga log"))?;
+                    records.into_iter().map(|record| (record.id, record)).collect()
+                }
+                None => HashMap::new(),
+            },
+            None => HashMap::new(),
+        };
+        let records = Arc::new(Mutex::new(records));
+
+        let writer = match file {
+            Some(file) => {
+                let records = records.clone();
+                Some(Arc::new(JsonFileWriter::spawn(file, move || snapshot(&records, cipher.as_ref()))?))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            records,
+            locks: Mutex::new(HashMap::new()),
+            writer,
+            shared: None,
+            owner: Uuid::new_v4().to_string(),
+        })
+    }
+
+    pub fn with_shared(mut self, shared: SharedSagas) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    fn update<F>(&self, saga_id: SagaId, f: F) -> Result<SagaRecord, FailureError>
+    where
+        F: FnOnce(&mut SagaRecord),
+    {
+        let updated = update_record(&self.records, saga_id, f)?;
+        self.flush();
+        if let Some(ref shared) = self.shared {
+            // replica taking the saga over resumes it from the published record
+            if updated.claim.as_ref().map(|claim| claim.owner == self.owner).unwrap_or(false) {
+                shared.publish(updated.clone(), &self.owner);
+            }
+        }
+        Ok(updated)
+    }
+
+    fn flush(&self) {
+        flush(&self.writer);
+    }
+}
+
+fn update_record<F>(records: &Mutex<HashMap<SagaId, SagaRecord>>, saga_id: SagaId, f: F) -> Result<SagaRecord, FailureError>
+where
+    F: FnOnce(&mut SagaRecord),
+{
+    let mut records = records.lock().unwrap();
+    let record = records.get_mut(&saga_id).ok_or_else(|| not_found(saga_id))?;
+    f(record);
+    record.updated_at = SystemTime::now();
+    Ok(record.clone())
+}
+
+fn flush(writer: &Option<Arc<JsonFileWriter>>) {
+    if let Some(ref writer) = *writer {
+        writer.schedule();
+    }
+}
+
+fn not_found(saga_id: SagaId) -> FailureError {
+    format_err!("Saga {} is not found in saga store", saga_id)
+        .context(Error::NotFound)
+        .into()
+}
+
+/// Content of saga log file, records are taken under lock and serialized after it is released
+fn snapshot(records: &Mutex<HashMap<SagaId, SagaRecord>>, cipher: Option<&SagaLogCipher>) -> Result<Value, FailureError> {
+    let records = records.lock().unwrap().values().cloned().collect::<Vec<_>>();
+    let records = records
+        .into_iter()
+        .map(|record| {
+            let record = serde_json::to_value(record)?;
+            match cipher {
+                Some(cipher) => cipher.encrypt_record(record),
+                None => Ok(record),
+            }
+        })
+        .collect::<Result<Vec<_>, FailureError>>()
+        .map_err(|e| e.context("Could not encrypt saga log"))?;
+    Ok(serde_json::to_value(SagaLogFile::new(records))?)
+}
+
+impl SagaStore for SagaStoreImpl {
+    fn insert(&self, record: SagaRecord) -> Result<(), FailureError> {
+        self.records.lock().unwrap().insert(record.id, record);
+        self.flush();
+        Ok(())
+    }
+
+    fn append_stage(&self, saga_id: SagaId, entry: SagaLogEntry) -> Result<(), FailureError> {
+        self.update(saga_id, |record| record.stages.push(entry)).map(|_| ())
+    }
+
+    fn set_status(&self, saga_id: SagaId, status: SagaStatus, error: Option<String>) -> Result<(), FailureError> {
+        self.update(saga_id, |record| {
+            record.status = status;
+            record.last_error = error;
+        })
+        .map(|_| ())
+    }
+
+    fn add_warning(&self, saga_id: SagaId, warning: SagaWarning) -> Result<(), FailureError> {
+        self.update(saga_id, |record| record.warnings.push(warning)).map(|_| ())
+    }
+
+    fn add_escalation(&self, saga_id: SagaId, escalation: SagaEscalation) -> Result<(), FailureError> {
+        self.update(saga_id, |record| record.escalations.push(escalation)).map(|_| ())
+    }
+
+    fn add_compensation_report(&self, saga_id: SagaId, report: CompensationReport) -> Result<(), FailureError> {
+        self.update(saga_id, |record| record.compensations.push(report)).map(|_| ())
+    }
+
+    fn set_input(&self, saga_id: SagaId, input: Value) -> Result<(), FailureError> {
+        self.update(saga_id, |record| record.input = Some(input)).map(|_| ())
+    }
+
+    fn record_request(&self, saga_id: SagaId, request: RecordedRequest) -> Result<(), FailureError> {
+        self.update(saga_id, |record| record.request = Some(request)).map(|_| ())
+    }
+
+    fn record_call(&self, saga_id: SagaId, call: RecordedCall) -> Result<(), FailureError> {
+        self.update(saga_id, |record| record.calls.push(call)).map(|_| ())
+    }
+
+    fn claim(&self, saga_id: SagaId, lease: Duration) -> Box<Future<Item = bool, Error = FailureError>> {
+        let owner = self.owner.clone();
+        if let Some(ref shared) = self.shared {
+            let record = match self.get(saga_id) {
+                Ok(Some(record)) => record,
+                Ok(None) => return Box::new(future::err(not_found(saga_id))),
+                Err(e) => return Box::new(future::err(e)),
+            };
+            // record is published when the saga is claimed and whenever it changes, heartbeat only extends the lease
+            let is_held = record.claim.as_ref().map(|claim| claim.owner == owner).unwrap_or(false);
+            let claimed = if is_held {
+                shared.renew(saga_id, &owner, lease)
+            } else {
+                shared.claim(record, &owner, lease)
+            };
+            let records = self.records.clone();
+            let writer = self.writer.clone();
+            return Box::new(claimed.and_then(move |claimed| {
+                if claimed {
+                    update_record(&records, saga_id, |record| record.claim = Some(SagaClaim::new(&owner)))?;
+                    flush(&writer);
+                }
+                Ok(claimed)
+            }));
+        }
+        let now = SystemTime::now();
+        let mut claimed = false;
+        let res = self.update(saga_id, |record| {
+            let is_free = match record.claim {
+                Some(ref claim) => claim.owner == owner || claim.is_expired(lease, now),
+                None => true,
+            };
+            if is_free {
+                record.claim = Some(SagaClaim::new(&owner));
+                claimed = true;
+            }
+        });
+        Box::new(future::result(res.map(|_| claimed)))
+    }
+
+    fn release(&self, saga_id: SagaId) -> Result<(), FailureError> {
+        let owner = self.owner.clone();
+        if let Some(ref shared) = self.shared {
+            shared.release(saga_id, &owner);
+        }
+        self.update(saga_id, |record| {
+            if record.claim.as_ref().map(|claim| claim.owner == owner).unwrap_or(false) {
+                record.claim = None;
+            }
+        })
+        .map(|_| ())
+    }
+
+    fn take_over(&self, lease: Duration) -> Box<Future<Item = Vec<SagaRecord>, Error = FailureError>> {
+        let shared = match self.shared {
+            Some(ref shared) => shared,
+            None => return Box::new(future::ok(vec![])),
+        };
+        let known = self.records.clone();
+        let records = self.records.clone();
+        let writer = self.writer.clone();
+        Box::new(
+            shared
+                .take_over(&self.owner, lease, move |saga_id| known.lock().unwrap().contains_key(&saga_id))
+                .map(move |taken_over| {
+                    if !taken_over.is_empty() {
+                        records
+                            .lock()
+                            .unwrap()
+                            .extend(taken_over.iter().map(|record| (record.id, record.clone())));
+                        flush(&writer);
+                    }
+                    taken_over
+                }),
+        )
+    }
+
+    fn lock_entity(&self, entity: LockedEntity, saga_id: SagaId, lease: Duration) -> Box<Future<Item = bool, Error = FailureError>> {
+        if let Some(ref shared) = self.shared {
+            let records = self.records.clone();
+            // sagas of other replicas are unknown, their locks are held until they are released or expire
+            return shared.lock_entity(entity, saga_id, lease, move |holder| {
+                records
+                    .lock()
+                    .unwrap()
+                    .get(&holder)
+                    .map(|record| record.status != SagaStatus::InProgress)
+                    .unwrap_or(false)
+            });
+        }
+        let records = self.records.lock().unwrap();
+        let mut locks = self.locks.lock().unwrap();
+        let is_free = match locks.get(&entity) {
+            Some(lock) => {
+                let holder_finished = records
+                    .get(&lock.saga_id)
+                    .map(|record| record.status != SagaStatus::InProgress)
+                    .unwrap_or(false);
+                lock.saga_id == saga_id || holder_finished || lock.is_expired(lease, SystemTime::now())
+            }
+            None => true,
+        };
+        if is_free {
+            locks.insert(entity, EntityLock::new(saga_id));
+        }
+        Box::new(future::ok(is_free))
+    }
+
+    fn unlock_entity(&self, entity: LockedEntity, saga_id: SagaId) -> Result<(), FailureError> {
+        if let Some(ref shared) = self.shared {
+            shared.unlock_entity(entity, saga_id);
+            return Ok(());
+        }
+        let mut locks = self.locks.lock().unwrap();
+        if locks.get(&entity).map(|lock| lock.saga_id == saga_id).unwrap_or(false) {
+            locks.remove(&entity);
+        }
+        Ok(())
+    }
+
+    fn register_revert_attempt(&self, saga_id: SagaId) -> Result<u32, FailureError> {
+        self.update(saga_id, |record| record.revert_attempts += 1)
+            .map(|record| record.revert_attempts)
+    }
+
+    fn get(&self, saga_id: SagaId) -> Result<Option<SagaRecord>, FailureError> {
+        Ok(self.records.lock().unwrap().get(&saga_id).cloned())
+    }
+
+    fn find_by_status(&self, statuses: &[SagaStatus]) -> Result<Vec<SagaRecord>, FailureError> {
+        let mut records = self
+            .records
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|record| statuses.contains(&record.status))
+            .cloned()
+            .collect::<Vec<_>>();
+        records.sort_by_key(|record| record.created_at);
+        Ok(records)
+    }
+
+    fn purge(&self, statuses: &[SagaStatus], updated_before: SystemTime) -> Result<usize, FailureError> {
+        let purged = {
+            let mut records = self.records.lock().unwrap();
+            let count = records.len();
+            records.retain(|_, record| !statuses.contains(&record.status) || record.updated_at >= updated_before || record.claim.is_some());
+            count - records.len()
+        };
+        if purged > 0 {
+            self.flush();
+        }
+        Ok(purged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, SystemTime};
+
+    use futures::prelude::*;
+
+    use stq_types::{SagaId, StoreId};
+
+    use super::{SagaStore, SagaStoreImpl};
+    use config::SagaSharing;
+    use models::{LockedEntity, SagaClaim, SagaRecord, SagaStatus, SagaType};
+    use redis::fake as fake_redis;
+    use saga::shared::SharedSagas;
+
+    fn shared_store(sharing: &SagaSharing) -> SagaStoreImpl {
+        SagaStoreImpl::new(None, None)
+            .unwrap()
+            .with_shared(SharedSagas::new(sharing.clone(), None))
+    }
+
+    #[test]
+    fn takes_over_only_expired_claims() {
+        let store = SagaStoreImpl::new(None, None).unwrap();
+        let lease = Duration::from_secs(60);
+        let saga_id = SagaId::new();
+        let mut record = SagaRecord::new(saga_id, SagaType::CatalogImport);
+        record.claim = Some(SagaClaim::new("another replica"));
+        store.insert(record.clone()).unwrap();
+
+        assert!(!store.claim(saga_id, lease).wait().unwrap());
+
+        record.claim = Some(SagaClaim {
+            owner: "another replica".to_string(),
+            heartbeat_at: SystemTime::now() - lease,
+        });
+        store.insert(record).unwrap();
+
+        assert!(store.claim(saga_id, lease).wait().unwrap());
+        assert!(store.claim(saga_id, lease).wait().unwrap());
+        store.release(saga_id).unwrap();
+        assert!(store.get(saga_id).unwrap().unwrap().claim.is_none());
+    }
+
+    #[test]
+    fn hands_sagas_over_between_replicas() {
+        let sharing = SagaSharing {
+            redis_address: fake_redis::start(),
+            key_prefix: "test".to_string(),
+        };
+        let (stopped, live, another) = (shared_store(&sharing), shared_store(&sharing), shared_store(&sharing));
+        let lease = Duration::from_millis(100);
+        let saga_id = SagaId::new();
+        stopped.insert(SagaRecord::new(saga_id, SagaType::CatalogImport)).unwrap();
+
+        assert!(stopped.claim(saga_id, lease).wait().unwrap());
+        // changes of claimed saga are published, renewal only extends the lease
+        stopped.set_input(saga_id, json!({"store_id": 1})).unwrap();
+        assert!(stopped.claim(saga_id, lease).wait().unwrap());
+        stopped.shared.as_ref().unwrap().settle();
+        assert!(live.take_over(lease).wait().unwrap().is_empty());
+
+        thread::sleep(lease);
+        let taken_over = live.take_over(lease).wait().unwrap();
+        assert_eq!(taken_over.len(), 1);
+        assert_eq!(taken_over[0].input, Some(json!({"store_id": 1})));
+        assert!(live.get(saga_id).unwrap().is_some());
+        assert!(!stopped.claim(saga_id, lease).wait().unwrap());
+
+        live.release(saga_id).unwrap();
+        live.shared.as_ref().unwrap().settle();
+        thread::sleep(lease);
+        assert!(another.take_over(lease).wait().unwrap().is_empty());
+    }
+
+    #[test]
+    fn locks_entity_across_replicas() {
+        let sharing = SagaSharing {
+            redis_address: fake_redis::start(),
+            key_prefix: "test".to_string(),
+        };
+        let (replica, another) = (shared_store(&sharing), shared_store(&sharing));
+        let lease = Duration::from_secs(60);
+        let entity = LockedEntity::Store(StoreId(1));
+        let (first, second, third) = (SagaId::new(), SagaId::new(), SagaId::new());
+        replica.insert(SagaRecord::new(first, SagaType::CreateStore)).unwrap();
+
+        assert!(replica.lock_entity(entity, first, lease).wait().unwrap());
+        assert!(!another.lock_entity(entity, second, lease).wait().unwrap());
+
+        replica.set_status(first, SagaStatus::Completed, None).unwrap();
+        assert!(!another.lock_entity(entity, second, lease).wait().unwrap());
+        assert!(replica.lock_entity(entity, third, lease).wait().unwrap());
+
+        replica.unlock_entity(entity, third).unwrap();
+        replica.shared.as_ref().unwrap().settle();
+        assert!(another.lock_entity(entity, second, lease).wait().unwrap());
+        assert!(!replica.lock_entity(entity, first, lease).wait().unwrap());
+    }
+
+    #[test]
+    fn locks_entity_for_single_saga() {
+        let store = SagaStoreImpl::new(None, None).unwrap();
+        let lease = Duration::from_secs(60);
+        let entity = LockedEntity::Store(StoreId(1));
+        let (first, second) = (SagaId::new(), SagaId::new());
+        store.insert(SagaRecord::new(first, SagaType::CreateStore)).unwrap();
+
+        assert!(store.lock_entity(entity, first, lease).wait().unwrap());
+        assert!(store.lock_entity(entity, first, lease).wait().unwrap());
+        assert!(!store.lock_entity(entity, second, lease).wait().unwrap());
+
+        store.unlock_entity(entity, second).unwrap();
+        assert!(!store.lock_entity(entity, second, lease).wait().unwrap());
+
+        store.set_status(first, SagaStatus::Completed, None).unwrap();
+        assert!(store.lock_entity(entity, second, lease).wait().unwrap());
+        store.unlock_entity(entity, second).unwrap();
+        assert!(store.lock_entity(entity, first, lease).wait().unwrap());
+    }
+}
@@ -0,0 +1,165 @@
+//! Scrubbing of personal data, like emails, phone numbers and addresses, out of log
+//! output and Sentry events. Inputs of sagas and errors of microservices echo profiles
+//! and addresses of users, such values are wrapped in `Scrubbed` when they are logged.
+//! Sentry events are scrubbed right before they are sent, whatever produced them.
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::RwLock;
+
+use failure::Error as FailureError;
+use regex::{self, Regex};
+use sentry::protocol::Event;
+use serde_json::{self, Value};
+
+use config;
+
+const REDACTED: &str = "<redacted>";
+/// Fields of json payloads carrying credentials, e.g. password of new account or token of password reset
+const CREDENTIAL_FIELDS: &[&str] = &["password", "token"];
+
+lazy_static! {
+    static ref SCRUBBER: RwLock<Option<Scrubber>> = RwLock::new(None);
+}
+
+pub struct Scrubber {
+    /// Patterns along with their replacements
+    patterns: Vec<(Regex, String)>,
+}
+
+impl Scrubber {
+    pub fn new(config: &config::Scrubbing) -> Result<Self, FailureError> {
+        let mut patterns = vec![];
+        if !config.fields.is_empty() {
+            let fields = config.fields.iter().map(|field| regex::escape(field)).collect::<Vec<_>>().join("|");
+            // `"phone":"..."` of json and `phone: Some("...")` of debug output
+            let regex = format!(r#"(?P<key>"?\b(?:{})\b"?\s*:\s*(?:Some\()?)"(?:[^"\\]|\\.)*""#, fields);
+            patterns.push((Regex::new(&regex)?, format!("${{key}}\"{}\"", REDACTED)));
+        }
+        for pattern in &config.patterns {
+            let regex = Regex::new(&pattern.regex).map_err(|e| format_err!("Invalid scrubbing pattern {}: {}", pattern.regex, e))?;
+            patterns.push((regex, pattern.replacement.clone()));
+        }
+        Ok(Self { patterns })
+    }
+
+    pub fn scrub<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        self.patterns
+            .iter()
+            .fold(Cow::Borrowed(text), |text, &(ref regex, ref replacement)| {
+                let scrubbed = match regex.replace_all(&text, replacement.as_str()) {
+                    Cow::Borrowed(_) => None,
+                    Cow::Owned(scrubbed) => Some(scrubbed),
+                };
+                scrubbed.map(Cow::Owned).unwrap_or(text)
+            })
+    }
+}
+
+/// Scrubbing is off until it is initialized
+pub fn init(config: &config::Scrubbing) -> Result<(), FailureError> {
+    let scrubber = if config.enabled { Some(Scrubber::new(config)?) } else { None };
+    *SCRUBBER.write().unwrap() = scrubber;
+    Ok(())
+}
+
+pub fn scrub(text: &str) -> String {
+    match *SCRUBBER.read().unwrap() {
+        Some(ref scrubber) => scrubber.scrub(text).into_owned(),
+        None => text.to_string(),
+    }
+}
+
+/// Scrubs message, exceptions, breadcrumbs and extra values of Sentry event
+pub fn scrub_event(mut event: Event<'static>) -> Option<Event<'static>> {
+    event.message = event.message.map(|message| scrub(&message));
+    for exception in &mut event.exception.values {
+        exception.value = exception.value.take().map(|value| scrub(&value));
+    }
+    for breadcrumb in &mut event.breadcrumbs.values {
+        breadcrumb.message = breadcrumb.message.take().map(|message| scrub(&message));
+    }
+    for value in event.extra.values_mut() {
+        if let Value::String(ref mut text) = *value {
+            *text = scrub(text);
+        }
+    }
+    Some(event)
+}
+
+/// Payload with credentials masked, so that it can be kept in saga log. Credentials are masked whether
+/// scrubbing is enabled or not, payloads that are not json are redacted as a whole.
+pub fn mask_credentials(payload: &str) -> String {
+    if payload.trim().is_empty() {
+        return payload.to_string();
+    }
+    match serde_json::from_str::<Value>(payload) {
+        Ok(mut value) => {
+            mask_fields(&mut value);
+            value.to_string()
+        }
+        Err(_) => REDACTED.to_string(),
+    }
+}
+
+fn mask_fields(value: &mut Value) {
+    match *value {
+        Value::Object(ref mut fields) => {
+            for (name, field) in fields.iter_mut() {
+                if CREDENTIAL_FIELDS.contains(&name.as_str()) && !field.is_null() {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    mask_fields(field);
+                }
+            }
+        }
+        Value::Array(ref mut items) => items.iter_mut().for_each(mask_fields),
+        _ => {}
+    }
+}
+
+/// Value formatted with personal data scrubbed, formatting is lazy, so it costs nothing if the log level is off
+pub struct Scrubbed<T>(pub T);
+
+impl<T: fmt::Display> fmt::Display for Scrubbed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&scrub(&self.0.to_string()))
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Scrubbed<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&scrub(&format!("{:?}", self.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mask_credentials, Scrubber};
+    use config;
+
+    #[test]
+    fn masks_credentials_of_payloads() {
+        assert_eq!(
+            mask_credentials(r#"{"user":{"email":"user@example.com","password":"secret"},"token":"abc"}"#),
+            r#"{"token":"<redacted>","user":{"email":"user@example.com","password":"<redacted>"}}"#
+        );
+        assert_eq!(mask_credentials("email=user@example.com&password=secret"), "<redacted>");
+    }
+
+    #[test]
+    fn scrubs_personal_data() {
+        let scrubber = Scrubber::new(&config::Scrubbing::default()).unwrap();
+        assert_eq!(
+            scrubber.scrub(r#"{"id":1,"email":"user@example.com","phone":"+7 999 000-00-00","birthdate":"1990-01-01"}"#),
+            r#"{"id":1,"email":"<email>","phone":"<redacted>","birthdate":"<redacted>"}"#
+        );
+        assert_eq!(
+            scrubber.scrub(r#"NewUser { phone: Some("79990000000"), first_name: Some("John") }"#),
+            r#"NewUser { phone: Some("<redacted>"), first_name: Some("John") }"#
+        );
+        assert_eq!(
+            scrubber.scrub("Call +7 (999) 000-00-00 at 2018-01-01 10:00:00"),
+            "Call <phone> at 2018-01-01 10:00:00"
+        );
+    }
+}
@@ -0,0 +1,238 @@
+//! Sensitive config values. Such values are written in config either as is, or as
+//! reference to mounted secret file or to key of Vault secret. References are resolved
+//! at startup and refreshed periodically, every clone of config sees refreshed values.
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::future;
+use futures::prelude::*;
+use hyper::header::Headers;
+use hyper::Method;
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use serde_json;
+use tokio_core::reactor::Core;
+
+use stq_http;
+use stq_http::client::{HttpClient, TimeLimitedHttpClient};
+
+use config::{Config, SagaStatsSink};
+
+const FILE_PREFIX: &str = "file:";
+const VAULT_PREFIX: &str = "vault:";
+const REDACTED: &str = "<redacted>";
+
+/// Where the value of secret comes from
+#[derive(Clone, Debug, PartialEq)]
+pub enum SecretSource {
+    /// Value written in config as is
+    Literal,
+    /// `file:<path>`, e.g. secret mounted by orchestrator
+    File(PathBuf),
+    /// `vault:<key>`, key of the secret configured in `[secrets.vault]`
+    Vault(String),
+}
+
+/// Value of sensitive config field. Never shows up in debug output or serialized config.
+#[derive(Clone)]
+pub struct Secret {
+    source: SecretSource,
+    value: Arc<RwLock<Option<String>>>,
+}
+
+impl Secret {
+    pub fn new(raw: String) -> Self {
+        let (source, value) = if raw.starts_with(FILE_PREFIX) {
+            (SecretSource::File(PathBuf::from(&raw[FILE_PREFIX.len()..])), None)
+        } else if raw.starts_with(VAULT_PREFIX) {
+            (SecretSource::Vault(raw[VAULT_PREFIX.len()..].to_string()), None)
+        } else {
+            (SecretSource::Literal, Some(raw))
+        };
+        Self {
+            source,
+            value: Arc::new(RwLock::new(value)),
+        }
+    }
+
+    pub fn source(&self) -> &SecretSource {
+        &self.source
+    }
+
+    /// Current value, `None` if reference is not resolved yet
+    pub fn value(&self) -> Option<String> {
+        self.value.read().unwrap().clone()
+    }
+
+    fn set(&self, value: String) {
+        *self.value.write().unwrap() = Some(value);
+    }
+
+    /// Reference the secret is written as in config, literal values are redacted
+    fn reference(&self) -> String {
+        match self.source {
+            SecretSource::Literal => REDACTED.to_string(),
+            SecretSource::File(ref path) => format!("{}{}", FILE_PREFIX, path.display()),
+            SecretSource::Vault(ref key) => format!("{}{}", VAULT_PREFIX, key),
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Secret({})", self.reference())
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.reference())
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Secret::new)
+    }
+}
+
+/// Secrets of the config
+fn secrets(config: &Config) -> Vec<Secret> {
+    let mut secrets = vec![];
+    if let Some(ref sentry) = config.sentry {
+        secrets.push(sentry.dsn.clone());
+    }
+    if let Some(ref encryption) = config.saga.encryption {
+        secrets.extend(encryption.keys.values().cloned());
+    }
+    if let Some(ref delivery_tracking) = config.delivery_tracking {
+        secrets.push(delivery_tracking.secret.clone());
+    }
+    if let Some(ref saga_stats) = config.saga_stats {
+        if let SagaStatsSink::Http {
+            auth_token: Some(ref auth_token),
+            ..
+        } = saga_stats.sink
+        {
+            secrets.push(auth_token.clone());
+        }
+    }
+    secrets
+}
+
+/// Reads secrets referencing files
+pub fn load_files(config: &Config) -> Result<(), FailureError> {
+    let mut secrets = secrets(config);
+    if let Some(ref vault) = config.secrets.vault {
+        secrets.push(vault.token.clone());
+    }
+    for secret in secrets {
+        if let SecretSource::File(ref path) = *secret.source() {
+            let value = fs::read_to_string(path).map_err(|e| e.context(format!("Could not read secret file {}", path.display())))?;
+            secret.set(value.trim().to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Fetches secrets referencing Vault keys
+pub fn load_vault<C: HttpClient + 'static>(http_client: C, config: &Config) -> Box<Future<Item = (), Error = FailureError>> {
+    let secrets = secrets(config)
+        .into_iter()
+        .filter(|secret| match *secret.source() {
+            SecretSource::Vault(_) => true,
+            _ => false,
+        })
+        .collect::<Vec<_>>();
+    if secrets.is_empty() {
+        return Box::new(future::ok(()));
+    }
+
+    let vault = match config.secrets.vault {
+        Some(ref vault) => vault.clone(),
+        None => return Box::new(future::err(format_err!("Secrets reference Vault, but it is not configured"))),
+    };
+    let token = match vault.token.value() {
+        Some(token) => token,
+        None => return Box::new(future::err(format_err!("Vault token is not resolved"))),
+    };
+
+    let mut headers = Headers::new();
+    headers.set_raw("X-Vault-Token", token);
+    let url = format!("{}/v1/{}", vault.url.trim_right_matches('/'), vault.path.trim_left_matches('/'));
+
+    Box::new(
+        http_client
+            .request_json::<serde_json::Value>(Method::Get, url, None, Some(headers))
+            .map_err(|e| FailureError::from(e.context("Fetching secrets from Vault failed")))
+            .and_then(move |response| {
+                for secret in secrets {
+                    if let SecretSource::Vault(ref key) = *secret.source() {
+                        match vault_value(&response, key) {
+                            Some(value) => secret.set(value),
+                            None => return Err(format_err!("Key {} is missing in Vault secret {}", key, vault.path)),
+                        }
+                    }
+                }
+                Ok(())
+            }),
+    )
+}
+
+/// Value of the key in Vault response, both kv v1 and kv v2 engines are supported
+fn vault_value(response: &serde_json::Value, key: &str) -> Option<String> {
+    let data = &response["data"];
+    let data = if data["data"].is_object() { &data["data"] } else { data };
+    data[key].as_str().map(|value| value.to_string())
+}
+
+/// Resolves every secret of the config before the service starts, fails if any of them can not be resolved
+pub fn resolve(config: &Config) -> Result<(), FailureError> {
+    load_files(config)?;
+
+    if config.secrets.vault.is_some() {
+        let mut core = Core::new()?;
+        let client = stq_http::client::Client::new(&config.to_http_config(), &core.handle());
+        let http_client = TimeLimitedHttpClient::new(client.handle(), Duration::from_millis(config.client.http_timeout_ms));
+        core.handle().spawn(client.stream().for_each(|_| Ok(())));
+        core.run(load_vault(http_client, config))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde_json;
+
+    use super::{vault_value, Secret, SecretSource};
+
+    #[test]
+    fn redacts_literal_secrets() {
+        let literal = Secret::new("https://key@sentry.io/1".to_string());
+        assert_eq!(literal.value(), Some("https://key@sentry.io/1".to_string()));
+        assert_eq!(format!("{:?}", literal), "Secret(<redacted>)");
+        assert_eq!(serde_json::to_string(&literal).unwrap(), "\"<redacted>\"");
+
+        let file = Secret::new("file:/run/secrets/sentry_dsn".to_string());
+        assert_eq!(file.source(), &SecretSource::File(PathBuf::from("/run/secrets/sentry_dsn")));
+        assert_eq!(file.value(), None);
+        assert_eq!(format!("{:?}", file), "Secret(file:/run/secrets/sentry_dsn)");
+    }
+
+    #[test]
+    fn reads_both_vault_engines() {
+        let v1 = json!({"data": {"sentry_dsn": "v1"}});
+        let v2 = json!({"data": {"data": {"sentry_dsn": "v2"}, "metadata": {"version": 3}}});
+        assert_eq!(vault_value(&v1, "sentry_dsn"), Some("v1".to_string()));
+        assert_eq!(vault_value(&v2, "sentry_dsn"), Some("v2".to_string()));
+        assert_eq!(vault_value(&v2, "missing"), None);
+    }
+}
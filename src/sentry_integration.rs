@@ -1,30 +1,110 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
 use failure::Error;
 use sentry;
 use sentry::integrations::failure::capture_error;
+use sentry::Level;
+
+use stq_types::SagaId;
+
+use build_info;
+use models::SagaType;
+use saga::schema::SCHEMA_VERSION;
+use scrubbing::{self, Scrubbed};
+use secrets::Secret;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SentryConfig {
-    pub dsn: String,
+    pub dsn: Secret,
     pub environment: String,
+    /// Release events are reported with, `saga_coordinator@<version>+<commit>` if not set
+    #[serde(default)]
+    pub release: Option<String>,
 }
 
 pub fn init(sentry_config: Option<&SentryConfig>) -> Option<sentry::internals::ClientInitGuard> {
     sentry_config.map(|config_sentry| {
         println!("initialization support with sentry");
         let result = sentry::init((
-            config_sentry.dsn.clone(),
+            config_sentry.dsn.value().unwrap_or_default(),
             sentry::ClientOptions {
-                release: sentry_crate_release!(),
+                release: release(config_sentry),
                 environment: Some(config_sentry.environment.clone().into()),
+                before_send: Some(Arc::new(Box::new(scrubbing::scrub_event))),
                 ..Default::default()
             },
         ));
         sentry::integrations::panic::register_panic_handler();
+        sentry::configure_scope(|scope| {
+            scope.set_tag("saga_schema_version", SCHEMA_VERSION);
+            if let Some(commit) = build_info::GIT_COMMIT {
+                scope.set_tag("git_commit", commit);
+            }
+        });
         result
     })
 }
 
-pub fn log_and_capture_error(error: &Error) {
-    error!("Internal server error: {:?}", error);
-    capture_error(error);
+/// Release of the build, so that regressions can be tied to deploys
+fn release(config: &SentryConfig) -> Option<Cow<'static, str>> {
+    if let Some(ref release) = config.release {
+        return Some(release.clone().into());
+    }
+    match build_info::GIT_COMMIT {
+        Some(commit) => Some(format!("{}@{}+{}", env!("CARGO_PKG_NAME"), build_info::VERSION, commit).into()),
+        None => sentry_crate_release!(),
+    }
+}
+
+pub fn log_and_capture_error(error: &Error, request_id: Option<&str>) {
+    error!(
+        "Internal server error in request {}: {:?}",
+        request_id.unwrap_or("-"),
+        Scrubbed(error)
+    );
+    sentry::with_scope(
+        |scope| {
+            if let Some(request_id) = request_id {
+                scope.set_tag("request_id", request_id);
+            }
+        },
+        || capture_error(error),
+    );
+}
+
+/// Reports panic caught inside saga execution, tagged with the saga it happened in
+pub fn capture_saga_panic(saga_id: SagaId, saga_type: SagaType, message: &str) {
+    error!("Saga {} of type {} panicked: {}", saga_id, saga_type, Scrubbed(message));
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("saga_id", saga_id);
+            scope.set_tag("saga_type", saga_type);
+        },
+        || sentry::capture_message(&format!("Saga panicked: {}", message), Level::Error),
+    );
+}
+
+/// Reports start of saga rollback, `stage` is the step compensated first
+pub fn capture_compensation_started(saga_id: SagaId, saga_type: Option<SagaType>, stage: &str) {
+    sentry::with_scope(
+        |scope| compensation_scope(scope, saga_id, saga_type, stage),
+        || sentry::capture_message("Saga is rolling back", Level::Warning),
+    );
+}
+
+/// Reports failed compensation of saga step, its resources are left for reaper
+pub fn capture_compensation_failure(saga_id: SagaId, saga_type: Option<SagaType>, stage: &str, error: &Error) {
+    sentry::with_scope(
+        |scope| compensation_scope(scope, saga_id, saga_type, stage),
+        || sentry::capture_message(&format!("Saga compensation failed: {}", error), Level::Error),
+    );
+}
+
+fn compensation_scope(scope: &mut sentry::Scope, saga_id: SagaId, saga_type: Option<SagaType>, stage: &str) {
+    scope.set_tag("saga_id", saga_id);
+    if let Some(saga_type) = saga_type {
+        scope.set_tag("saga_type", saga_type);
+    }
+    scope.set_tag("stage", stage);
 }
@@ -1,25 +1,26 @@
-use std::sync::{Arc, Mutex};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
 
 use failure::Error as FailureError;
-use futures;
 use futures::future;
 use futures::prelude::*;
-use futures::stream::iter_ok;
-use hyper::header::Authorization;
-use hyper::Headers;
 
 use stq_static_resources::*;
 use stq_types::{BillingRole, DeliveryRole, RoleId, SagaId, StoresRole, UserId, UsersRole};
 
+use super::initiator::InitiatorPolicy;
 use super::parse_validation_errors;
 use config;
 use errors::Error;
 use microservice::*;
 use models::*;
+use saga::{self, run_steps, soft_step, SagaLog, SagaService, SagaStore, StepFuture};
+use scrubbing::Scrubbed;
 use services::types::ServiceFuture;
 
 pub trait AccountService {
-    fn create(self, input: SagaCreateProfile) -> ServiceFuture<Box<AccountService>, User>;
+    fn create(self, input: SagaCreateProfile) -> ServiceFuture<Box<AccountService>, SagaResponse<User>>;
     fn request_password_reset(self, input: ResetRequest) -> ServiceFuture<Box<AccountService>, ()>;
     fn request_password_reset_apply(self, input: PasswordResetApply) -> ServiceFuture<Box<AccountService>, String>;
     fn request_email_verification(self, input: VerifyRequest) -> ServiceFuture<Box<AccountService>, ()>;
@@ -27,6 +28,7 @@ pub trait AccountService {
 }
 
 /// Account service, responsible for Creating user
+#[derive(Clone)]
 pub struct AccountServiceImpl {
     pub stores_microservice: Arc<StoresMicroservice>,
     pub billing_microservice: Arc<BillingMicroservice>,
@@ -34,19 +36,24 @@ pub struct AccountServiceImpl {
     pub users_microservice: Arc<UsersMicroservice>,
     pub notifications_microservice: Arc<NotificationsMicroservice>,
     pub config: config::Config,
-    pub log: Arc<Mutex<CreateProfileOperationLog>>,
+    pub log: Rc<SagaLog<CreateProfileOperationStage>>,
+    /// Locale of the caller, used for notifications and their urls
+    pub locale: Option<String>,
+    pub initiators: InitiatorPolicy,
 }
 
 impl AccountServiceImpl {
     pub fn new(
         config: config::Config,
+        saga_store: Arc<SagaStore>,
         stores_microservice: Arc<StoresMicroservice>,
         billing_microservice: Arc<BillingMicroservice>,
         delivery_microservice: Arc<DeliveryMicroservice>,
         users_microservice: Arc<UsersMicroservice>,
         notifications_microservice: Arc<NotificationsMicroservice>,
+        locale: Option<String>,
     ) -> Self {
-        let log = Arc::new(Mutex::new(CreateProfileOperationLog::new()));
+        let log = Rc::new(SagaLog::new(saga_store));
         Self {
             config,
             log,
@@ -55,11 +62,19 @@ impl AccountServiceImpl {
             delivery_microservice,
             users_microservice,
             notifications_microservice,
+            locale,
+            initiators: InitiatorPolicy::default(),
         }
     }
 
+    /// Performs steps of sagas on behalf of the caller
+    pub fn with_caller(mut self, caller: Option<Initiator>) -> Self {
+        self.initiators = InitiatorPolicy::new(caller);
+        self
+    }
+
     fn create_user(self, input: SagaCreateProfile, saga_id_arg: SagaId) -> ServiceFuture<Self, User> {
-        debug!("Creating user, input: {}, saga id: {}", input, saga_id_arg);
+        debug!("Creating user, input: {}, saga id: {}", Scrubbed(&input), saga_id_arg);
         // Create account
         let new_ident = NewIdentity {
             provider: input.identity.provider,
@@ -90,17 +105,16 @@ impl AccountServiceImpl {
         };
 
         let log = self.log.clone();
-        log.lock()
-            .unwrap()
-            .push(CreateProfileOperationStage::AccountCreationStart(saga_id_arg));
+        let initiator = self
+            .initiators
+            .superadmin(&log, "account_creation", "accounts are created only by users administrators");
+        log.push(CreateProfileOperationStage::AccountCreationStart(saga_id_arg));
 
         let res = self
             .users_microservice
-            .create_user(Some(Initiator::Superadmin), create_profile)
+            .create_user(Some(initiator), create_profile)
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateProfileOperationStage::AccountCreationComplete(saga_id_arg));
+                log.push_with_result(CreateProfileOperationStage::AccountCreationComplete(saga_id_arg), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -119,17 +133,16 @@ impl AccountServiceImpl {
         let new_role_id = RoleId::new();
         let role = NewRole::<UsersRole>::new(new_role_id, user_id, UsersRole::User, None);
 
-        log.lock()
-            .unwrap()
-            .push(CreateProfileOperationStage::UsersRoleSetStart(new_role_id));
+        let initiator = self
+            .initiators
+            .superadmin(&log, "users_role_set", "roles are granted only by users administrators");
+        log.push(CreateProfileOperationStage::UsersRoleSetStart(new_role_id));
 
         let res = self
             .users_microservice
-            .create_role(Some(Initiator::Superadmin), role)
+            .create_role(Some(initiator), role)
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateProfileOperationStage::UsersRoleSetComplete(new_role_id));
+                log.push_with_result(CreateProfileOperationStage::UsersRoleSetComplete(new_role_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -148,17 +161,16 @@ impl AccountServiceImpl {
         let new_role_id = RoleId::new();
         let role = NewRole::<StoresRole>::new(new_role_id, user_id, StoresRole::User, None);
 
-        log.lock()
-            .unwrap()
-            .push(CreateProfileOperationStage::StoreRoleSetStart(new_role_id));
+        let initiator = self
+            .initiators
+            .superadmin(&log, "store_role_set", "roles are granted only by stores administrators");
+        log.push(CreateProfileOperationStage::StoreRoleSetStart(new_role_id));
 
         let res = self
             .stores_microservice
-            .create_stores_role(Some(Initiator::Superadmin), role)
+            .create_stores_role(Some(initiator), role)
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateProfileOperationStage::StoreRoleSetComplete(new_role_id));
+                log.push_with_result(CreateProfileOperationStage::StoreRoleSetComplete(new_role_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -177,17 +189,16 @@ impl AccountServiceImpl {
         let new_role_id = RoleId::new();
         let role = NewRole::<BillingRole>::new(new_role_id, user_id, BillingRole::User, None);
 
-        log.lock()
-            .unwrap()
-            .push(CreateProfileOperationStage::BillingRoleSetStart(new_role_id));
+        let initiator = self
+            .initiators
+            .superadmin(&log, "billing_role_set", "roles are granted only by billing administrators");
+        log.push(CreateProfileOperationStage::BillingRoleSetStart(new_role_id));
 
         let res = self
             .billing_microservice
-            .create_role(Some(Initiator::Superadmin), role)
+            .create_role(Some(initiator), role)
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateProfileOperationStage::BillingRoleSetComplete(new_role_id));
+                log.push_with_result(CreateProfileOperationStage::BillingRoleSetComplete(new_role_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -206,17 +217,16 @@ impl AccountServiceImpl {
         let new_role_id = RoleId::new();
         let role = NewRole::<DeliveryRole>::new(new_role_id, user_id, DeliveryRole::User, None);
 
-        log.lock()
-            .unwrap()
-            .push(CreateProfileOperationStage::DeliveryRoleSetStart(new_role_id));
+        let initiator = self
+            .initiators
+            .superadmin(&log, "delivery_role_set", "roles are granted only by delivery administrators");
+        log.push(CreateProfileOperationStage::DeliveryRoleSetStart(new_role_id));
 
         let res = self
             .delivery_microservice
-            .create_delivery_role(Some(Initiator::Superadmin), role)
+            .create_delivery_role(Some(initiator), role)
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateProfileOperationStage::DeliveryRoleSetComplete(new_role_id));
+                log.push_with_result(CreateProfileOperationStage::DeliveryRoleSetComplete(new_role_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -233,17 +243,18 @@ impl AccountServiceImpl {
 
         // Create user role
         let log = self.log.clone();
-        log.lock()
-            .unwrap()
-            .push(CreateProfileOperationStage::BillingCreateMerchantStart(user_id));
+        let initiator = self.initiators.superadmin(
+            &log,
+            "billing_create_merchant",
+            "merchants are created only by billing administrators",
+        );
+        log.push(CreateProfileOperationStage::BillingCreateMerchantStart(user_id));
 
         let res = self
             .billing_microservice
-            .create_user_merchant(Some(Initiator::Superadmin), payload)
+            .create_user_merchant(Some(initiator), payload)
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateProfileOperationStage::BillingCreateMerchantComplete(user_id));
+                log.push_with_result(CreateProfileOperationStage::BillingCreateMerchantComplete(user_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -257,28 +268,11 @@ impl AccountServiceImpl {
     fn notify_user(self, user: User, device: Option<Device>, project: Option<Project>) -> ServiceFuture<Self, ()> {
         debug!("Notifiing user in notificatins microservice");
         let project_ = project.unwrap_or_else(|| Project::MarketPlace);
-        let verify_email_path = match project_ {
-            Project::MarketPlace => {
-                let config::DevicesUrls { web, ios, android } = self.config.notification_urls.verify_email.marketplace.clone();
-                device
-                    .map(|device| match device {
-                        Device::WEB => web.clone(),
-                        Device::IOS => ios,
-                        Device::Android => android,
-                    })
-                    .unwrap_or_else(|| web)
-            }
-            Project::Wallet => {
-                let config::DevicesUrls { web, ios, android } = self.config.notification_urls.verify_email.wallet.clone();
-                device
-                    .map(|device| match device {
-                        Device::WEB => web.clone(),
-                        Device::IOS => ios,
-                        Device::Android => android,
-                    })
-                    .unwrap_or_else(|| web)
-            }
-        };
+        let verify_email_path = self.config.notification_urls.verify_email.device_url(
+            project_.clone(),
+            device.clone(),
+            self.locale.as_ref().map(String::as_str),
+        );
 
         let verify = VerifyRequest {
             email: user.email.clone(),
@@ -287,6 +281,7 @@ impl AccountServiceImpl {
         };
         let user_id = user.id;
         let notifications_microservice = self.notifications_microservice.clone();
+        let locale = self.locale.clone();
         let res = self
             .users_microservice
             .create_email_verify_token(Some(user_id.into()), verify)
@@ -301,7 +296,7 @@ impl AccountServiceImpl {
                     verify_email_path,
                     token,
                 };
-                notifications_microservice.email_verification(Some(Initiator::Superadmin), email, project_)
+                notifications_microservice.email_verification(Some(Initiator::Superadmin), email, project_, locale)
             })
             .then(|res| match res {
                 Ok(_) => Ok((self, ())),
@@ -348,39 +343,59 @@ impl AccountServiceImpl {
         Box::new(res)
     }
 
+    // Executes step of account creation configured in `saga.definitions.create_account`,
+    // every step is listed in `config::CREATE_ACCOUNT_STEPS`
+    fn run_step(self, step: &str, user_id: UserId) -> StepFuture<Self> {
+        match step {
+            "users_role_set" => Box::new(self.create_user_role(user_id).map(|(s, _)| (s, ()))),
+            "store_role_set" => Box::new(self.create_store_role(user_id).map(|(s, _)| (s, ()))),
+            "billing_role_set" => Box::new(self.create_billing_role(user_id).map(|(s, _)| (s, ()))),
+            "delivery_role_set" => Box::new(self.create_delivery_role(user_id).map(|(s, _)| (s, ()))),
+            "billing_create_merchant" => Box::new(self.create_merchant(user_id).map(|(s, _)| (s, ()))),
+            _ => Box::new(future::err((self, format_err!("Unknown step {} of account creation saga", step)))),
+        }
+    }
+
     // Contains happy path for account creation
     fn create_happy(self, input: SagaCreateProfile) -> ServiceFuture<Self, User> {
-        let saga_id = SagaId::new();
+        let saga_id = self.log.saga_id();
+        self.log.start(SagaType::CreateAccount);
         let provider = input.identity.provider.clone();
         let device = input.device.clone();
         let project = input.project.clone();
 
         Box::new(
             self.create_user(input, saga_id)
-                .and_then(|(s, user)| s.create_user_role(user.id).map(|(s, _)| (s, user)))
-                .and_then(|(s, user)| s.create_store_role(user.id).map(|(s, _)| (s, user)))
-                .and_then(|(s, user)| s.create_billing_role(user.id).map(|(s, _)| (s, user)))
-                .and_then(|(s, user)| s.create_delivery_role(user.id).map(|(s, _)| (s, user)))
-                .and_then(|(s, user)| s.create_merchant(user.id).map(|(s, _)| (s, user)))
+                .and_then(|(s, user)| {
+                    let definition = s.config.saga.definitions.create_account.clone();
+                    let user_id = user.id;
+                    let log = s.log.clone();
+                    run_steps(s, log, definition, move |s, step| s.run_step(step, user_id)).map(|s| (s, user))
+                })
                 .and_then(move |(s, user)| {
                     // only if provider is email it needs to be verified
                     match provider {
-                        Provider::Email => Box::new(s.notify_user(user.clone(), device, project).then(|res| match res {
-                            Ok((s, _)) => Ok((s, user)),
-                            Err((s, _)) => Ok((s, user)),
-                        })) as ServiceFuture<Self, User>,
+                        Provider::Email => Box::new(
+                            soft_step(
+                                s.log.clone(),
+                                "email_verification_notification",
+                                s.notify_user(user.clone(), device, project),
+                            )
+                            .map(|s| (s, user)),
+                        ) as ServiceFuture<Self, User>,
                         Provider::Facebook | Provider::Google if project.unwrap_or_default() == Project::MarketPlace => Box::new(
-                            s.create_emarsys_contact(CreateEmarsysContactPayload {
-                                user_id: user.id,
-                                email: user.email.clone(),
-                                first_name: user.first_name.clone(),
-                                last_name: user.last_name.clone(),
-                                country: user.country.clone(),
-                            })
-                            .then(|res| match res {
-                                Ok((s, _)) => Ok((s, user)),
-                                Err((s, _)) => Ok((s, user)),
-                            }),
+                            soft_step(
+                                s.log.clone(),
+                                "emarsys_contact_creation",
+                                s.create_emarsys_contact(CreateEmarsysContactPayload {
+                                    user_id: user.id,
+                                    email: user.email.clone(),
+                                    first_name: user.first_name.clone(),
+                                    last_name: user.last_name.clone(),
+                                    country: user.country.clone(),
+                                }),
+                            )
+                            .map(|s| (s, user)),
                         )
                             as ServiceFuture<Self, User>,
                         _ => Box::new(future::ok((s, user))) as ServiceFuture<Self, User>,
@@ -390,135 +405,122 @@ impl AccountServiceImpl {
     }
 
     // Contains reversal of account creation
-    fn create_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
-        let log = self.log.lock().unwrap().clone();
-
+    pub fn create_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
         let stores_microservice = self.stores_microservice.clone();
         let billing_microservice = self.billing_microservice.clone();
         let delivery_microservice = self.delivery_microservice.clone();
         let users_microservice = self.users_microservice.clone();
 
-        let fut = iter_ok::<_, ()>(log).for_each(move |e| {
-            match e {
-                CreateProfileOperationStage::AccountCreationStart(saga_id) => {
-                    debug!("Reverting user, saga_id: {}", saga_id);
-                    Box::new(
-                        users_microservice
-                            .delete_user(Some(Initiator::Superadmin), saga_id)
-                            .then(|_| Ok(())),
-                    ) as Box<Future<Item = (), Error = ()>>
-                }
-
-                CreateProfileOperationStage::UsersRoleSetStart(role_id) => {
-                    debug!("Reverting users role, role_id: {}", role_id);
-                    let mut headers = Headers::new();
-                    headers.set(Authorization("1".to_string())); // only super admin delete user role
+        let compensation = self.log.compensate(move |e, _| match e {
+            CreateProfileOperationStage::AccountCreationStart(saga_id) => {
+                debug!("Reverting user, saga_id: {}", saga_id);
+                let users_microservice = users_microservice.clone();
+                Box::new(
+                    users_microservice
+                        .get_user_by_saga_id(Some(Initiator::Superadmin), saga_id)
+                        .and_then(move |user| match user {
+                            Some(_) => Box::new(users_microservice.delete_user(Some(Initiator::Superadmin), saga_id).map(|_| ()))
+                                as Box<Future<Item = (), Error = FailureError>>,
+                            None => {
+                                debug!("User with saga_id {} was not created, nothing to revert", saga_id);
+                                Box::new(future::ok(()))
+                            }
+                        }),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
 
-                    Box::new(
-                        users_microservice
-                            .delete_role(Some(Initiator::Superadmin), role_id)
-                            .then(|_| Ok(())),
-                    ) as Box<Future<Item = (), Error = ()>>
-                }
+            CreateProfileOperationStage::UsersRoleSetStart(role_id) => {
+                debug!("Reverting users role, role_id: {}", role_id);
+                Box::new(users_microservice.delete_role(Some(Initiator::Superadmin), role_id).map(|_| ()))
+                    as Box<Future<Item = (), Error = FailureError>>
+            }
 
-                CreateProfileOperationStage::StoreRoleSetStart(role_id) => {
-                    debug!("Reverting stores users role, role_id: {}", role_id);
+            CreateProfileOperationStage::StoreRoleSetStart(role_id) => {
+                debug!("Reverting stores users role, role_id: {}", role_id);
 
-                    Box::new(
-                        stores_microservice
-                            .delete_stores_role(Some(Initiator::Superadmin), role_id)
-                            .then(|_| Ok(())),
-                    ) as Box<Future<Item = (), Error = ()>>
-                }
-
-                CreateProfileOperationStage::BillingRoleSetStart(role_id) => {
-                    debug!("Reverting billing role, role_id: {}", role_id);
+                Box::new(
+                    stores_microservice
+                        .delete_stores_role(Some(Initiator::Superadmin), role_id)
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
 
-                    Box::new(
-                        billing_microservice
-                            .delete_role(Some(Initiator::Superadmin), role_id)
-                            .then(|_| Ok(())),
-                    ) as Box<Future<Item = (), Error = ()>>
-                }
+            CreateProfileOperationStage::BillingRoleSetStart(role_id) => {
+                debug!("Reverting billing role, role_id: {}", role_id);
 
-                CreateProfileOperationStage::DeliveryRoleSetStart(role_id) => {
-                    debug!("Reverting delivery role, role_id: {}", role_id);
-                    Box::new(
-                        delivery_microservice
-                            .delete_delivery_role(Some(Initiator::Superadmin), role_id)
-                            .then(|_| Ok(())),
-                    ) as Box<Future<Item = (), Error = ()>>
-                }
+                Box::new(billing_microservice.delete_role(Some(Initiator::Superadmin), role_id).map(|_| ()))
+                    as Box<Future<Item = (), Error = FailureError>>
+            }
 
-                CreateProfileOperationStage::BillingCreateMerchantStart(user_id) => {
-                    debug!("Reverting merchant, user_id: {}", user_id);
-                    Box::new(
-                        billing_microservice
-                            .delete_user_merchant(Some(Initiator::Superadmin), user_id)
-                            .then(|_| Ok(())),
-                    ) as Box<Future<Item = (), Error = ()>>
-                }
+            CreateProfileOperationStage::DeliveryRoleSetStart(role_id) => {
+                debug!("Reverting delivery role, role_id: {}", role_id);
+                Box::new(
+                    delivery_microservice
+                        .delete_delivery_role(Some(Initiator::Superadmin), role_id)
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
 
-                _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = ()>>,
+            CreateProfileOperationStage::BillingCreateMerchantStart(user_id) => {
+                debug!("Reverting merchant, user_id: {}", user_id);
+                Box::new(
+                    billing_microservice
+                        .delete_user_merchant(Some(Initiator::Superadmin), user_id)
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
             }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
         });
 
-        fut.then(|res| match res {
-            Ok(_) => Ok((self, ())),
-            Err(_) => Err((self, format_err!("Order service create_revert error occurred."))),
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((self, format_err!("Account service create_revert error occurred: {}", e))),
         })
     }
 }
 
+impl SagaService for AccountServiceImpl {
+    type Stage = CreateProfileOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.create_revert().map(|(s, _)| s))
+    }
+}
+
 impl AccountService for AccountServiceImpl {
-    fn create(self, input: SagaCreateProfile) -> ServiceFuture<Box<AccountService>, User> {
+    fn create(self, input: SagaCreateProfile) -> ServiceFuture<Box<AccountService>, SagaResponse<User>> {
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::CreateAccount,
+            deadline,
+            move |s| s.create_happy(input),
+            &["email", "password"],
+        );
+
         Box::new(
-            self.create_happy(input.clone())
-                .map(|(s, user)| (Box::new(s) as Box<AccountService>, user))
-                .or_else(move |(s, e)| {
-                    s.create_revert().then(move |res| {
-                        let s = match res {
-                            Ok((s, _)) => s,
-                            Err((s, _)) => s,
-                        };
-                        futures::future::err((Box::new(s) as Box<AccountService>, e))
-                    })
-                })
-                .map_err(|(s, e): (Box<AccountService>, FailureError)| (s, parse_validation_errors(e, &["email", "password"]))),
+            res.map(|(s, response)| (Box::new(s) as Box<AccountService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<AccountService>, e)),
         )
     }
 
     fn request_password_reset(self, input: ResetRequest) -> ServiceFuture<Box<AccountService>, ()> {
         let project_ = input.project.clone().unwrap_or_else(|| Project::MarketPlace);
-        let reset_password_path = match project_ {
-            Project::MarketPlace => {
-                let config::DevicesUrls { web, ios, android } = self.config.notification_urls.reset_password.marketplace.clone();
-                input
-                    .device
-                    .clone()
-                    .map(|device| match device {
-                        Device::WEB => web.clone(),
-                        Device::IOS => ios,
-                        Device::Android => android,
-                    })
-                    .unwrap_or_else(|| web)
-            }
-            Project::Wallet => {
-                let config::DevicesUrls { web, ios, android } = self.config.notification_urls.reset_password.wallet.clone();
-                input
-                    .device
-                    .clone()
-                    .map(|device| match device {
-                        Device::WEB => web.clone(),
-                        Device::IOS => ios,
-                        Device::Android => android,
-                    })
-                    .unwrap_or_else(|| web)
-            }
-        };
+        let reset_password_path = self.config.notification_urls.reset_password.device_url(
+            project_.clone(),
+            input.device.clone(),
+            self.locale.as_ref().map(String::as_str),
+        );
 
         let users_microservice = self.users_microservice.clone();
         let notifications_microservice = self.notifications_microservice.clone();
+        let locale = self.locale.clone();
         let res = self
             .users_microservice
             .get_by_email(Some(Initiator::Superadmin), &input.email)
@@ -545,7 +547,7 @@ impl AccountService for AccountServiceImpl {
                                     reset_password_path,
                                     token,
                                 };
-                                notifications_microservice.password_reset(Some(Initiator::Superadmin), email, project_)
+                                notifications_microservice.password_reset(Some(Initiator::Superadmin), email, project_, locale)
                             }),
                     )
                 } else {
@@ -568,6 +570,7 @@ impl AccountService for AccountServiceImpl {
         let project_ = input.project.clone().unwrap_or_else(|| Project::MarketPlace);
         let users_microservice = self.users_microservice.clone();
         let notifications_microservice = self.notifications_microservice.clone();
+        let locale = self.locale.clone();
         let res = self
             .users_microservice
             .apply_password_reset_token(Some(Initiator::Superadmin), input)
@@ -586,7 +589,7 @@ impl AccountService for AccountServiceImpl {
                     let email = ApplyPasswordResetForUser { user, cluster_url };
                     Box::new(
                         notifications_microservice
-                            .apply_password_reset(Some(Initiator::Superadmin), email, project_)
+                            .apply_password_reset(Some(Initiator::Superadmin), email, project_, locale)
                             .map(|_| token),
                     )
                 } else {
@@ -604,35 +607,15 @@ impl AccountService for AccountServiceImpl {
 
     fn request_email_verification(self, input: VerifyRequest) -> ServiceFuture<Box<AccountService>, ()> {
         let project_ = input.project.clone().unwrap_or_else(|| Project::MarketPlace);
-        let verify_email_path = match project_ {
-            Project::MarketPlace => {
-                let config::DevicesUrls { web, ios, android } = self.config.notification_urls.verify_email.marketplace.clone();
-                input
-                    .device
-                    .clone()
-                    .map(|device| match device {
-                        Device::WEB => web.clone(),
-                        Device::IOS => ios,
-                        Device::Android => android,
-                    })
-                    .unwrap_or_else(|| web)
-            }
-            Project::Wallet => {
-                let config::DevicesUrls { web, ios, android } = self.config.notification_urls.verify_email.wallet.clone();
-                input
-                    .device
-                    .clone()
-                    .map(|device| match device {
-                        Device::WEB => web.clone(),
-                        Device::IOS => ios,
-                        Device::Android => android,
-                    })
-                    .unwrap_or_else(|| web)
-            }
-        };
+        let verify_email_path = self.config.notification_urls.verify_email.device_url(
+            project_.clone(),
+            input.device.clone(),
+            self.locale.as_ref().map(String::as_str),
+        );
 
         let users_microservice = self.users_microservice.clone();
         let notifications_microservice = self.notifications_microservice.clone();
+        let locale = self.locale.clone();
         let res = self
             .users_microservice
             .get_by_email(Some(Initiator::Superadmin), &input.email)
@@ -658,7 +641,7 @@ impl AccountService for AccountServiceImpl {
                                     verify_email_path,
                                     token,
                                 };
-                                notifications_microservice.email_verification(Some(Initiator::Superadmin), email, project_)
+                                notifications_microservice.email_verification(Some(Initiator::Superadmin), email, project_, locale)
                             }),
                     )
                 } else {
@@ -677,6 +660,7 @@ impl AccountService for AccountServiceImpl {
 
     fn request_email_verification_apply(self, input: EmailVerifyApply) -> ServiceFuture<Box<AccountService>, EmailVerifyApplyToken> {
         let notifications_microservice = self.notifications_microservice.clone();
+        let locale = self.locale.clone();
         let users_microservice = self.users_microservice.clone();
         let project_ = input.project.clone().unwrap_or_else(|| Project::MarketPlace);
         Box::new(
@@ -692,7 +676,7 @@ impl AccountService for AccountServiceImpl {
                     let email = ApplyEmailVerificationForUser { user: email_user };
 
                     notifications_microservice
-                        .apply_email_verification(Some(Initiator::Superadmin), email, project_)
+                        .apply_email_verification(Some(Initiator::Superadmin), email, project_, locale)
                         .then(|res| match res {
                             Ok(_) => Ok((user, email_apply_token)),
                             Err(err) => {
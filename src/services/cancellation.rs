@@ -0,0 +1,542 @@
+//! Cancellation of some orders of multi-product checkout. Orders are cancelled and
+//! their stock returned first, then billing takes all of them out of the invoice at
+//! once. If billing can not amend the invoice, the orders and their stock are brought back.
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use futures::future::{self, join_all, Either};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+
+use stq_api::orders::Order;
+use stq_static_resources::{CommitterRole, EmailUser, OrderState, OrderUpdateStateForStore, OrderUpdateStateForUser};
+use stq_types::{InvoiceId, OrderId, OrderIdentifier, OrderSlug, SagaId};
+
+use super::initiator::InitiatorPolicy;
+use config;
+use errors::Error;
+use microservice::*;
+use models::*;
+use saga::{self, soft_step, SagaLog, SagaService, SagaStore};
+use scrubbing::Scrubbed;
+use services::stock::revert_stock_return;
+use services::types::ServiceFuture;
+
+pub trait OrderCancellationService {
+    /// Cancels the order and takes it out of the invoice, other orders of the checkout are left as they are
+    fn cancel_item(
+        self,
+        order_slug: OrderSlug,
+        input: OrderItemCancelInput,
+    ) -> ServiceFuture<Box<OrderCancellationService>, SagaResponse<Order>>;
+    /// Cancels orders of the same checkout and takes all of them out of the invoice with a single amendment
+    fn cancel_items(self, input: OrderItemsCancelInput) -> ServiceFuture<Box<OrderCancellationService>, SagaResponse<Vec<Order>>>;
+}
+
+#[derive(Clone)]
+pub struct OrderCancellationServiceImpl {
+    pub orders_microservice: Arc<OrdersMicroservice>,
+    pub billing_microservice: Arc<BillingMicroservice>,
+    pub warehouses_microservice: Arc<WarehousesMicroservice>,
+    pub stores_microservice: Arc<StoresMicroservice>,
+    pub users_microservice: Arc<UsersMicroservice>,
+    pub notifications_microservice: Arc<NotificationsMicroservice>,
+    pub config: config::Config,
+    pub log: Rc<SagaLog<CancelOrderItemOperationStage>>,
+    pub initiators: InitiatorPolicy,
+}
+
+impl OrderCancellationServiceImpl {
+    pub fn new(
+        config: config::Config,
+        saga_store: Arc<SagaStore>,
+        orders_microservice: Arc<OrdersMicroservice>,
+        billing_microservice: Arc<BillingMicroservice>,
+        warehouses_microservice: Arc<WarehousesMicroservice>,
+        stores_microservice: Arc<StoresMicroservice>,
+        users_microservice: Arc<UsersMicroservice>,
+        notifications_microservice: Arc<NotificationsMicroservice>,
+    ) -> Self {
+        let log = Rc::new(SagaLog::new(saga_store));
+        Self {
+            config,
+            orders_microservice,
+            billing_microservice,
+            warehouses_microservice,
+            stores_microservice,
+            users_microservice,
+            notifications_microservice,
+            log,
+            initiators: InitiatorPolicy::default(),
+        }
+    }
+
+    /// Performs steps of sagas on behalf of the caller
+    pub fn with_caller(mut self, caller: Option<Initiator>) -> Self {
+        self.initiators = InitiatorPolicy::new(caller);
+        self
+    }
+
+    fn cancel_item_happy(
+        self,
+        order_slug: OrderSlug,
+        input: OrderItemCancelInput,
+    ) -> impl Future<Item = (Self, Order), Error = (Self, FailureError)> {
+        self.log.start(SagaType::CancelOrderItem);
+        self.log.set_input(&input);
+        let OrderItemCancelInput { comment } = input;
+
+        // exactly one order is cancelled for one slug
+        self.cancel_orders(vec![order_slug], comment)
+            .map(|(s, mut orders)| (s, orders.remove(0)))
+    }
+
+    fn cancel_items_happy(self, input: OrderItemsCancelInput) -> impl Future<Item = (Self, Vec<Order>), Error = (Self, FailureError)> {
+        self.log.start(SagaType::CancelOrderItem);
+        self.log.set_input(&input);
+        let OrderItemsCancelInput { order_slugs, comment } = input;
+
+        self.cancel_orders(order_slugs, comment)
+    }
+
+    /// All orders are checked before any of them is cancelled, they must belong to the same invoice and the caller
+    /// must be allowed to cancel each of them. Orders are cancelled and their stock is returned one by one,
+    /// then billing takes them out of the invoice with a single amendment.
+    fn cancel_orders(
+        self,
+        order_slugs: Vec<OrderSlug>,
+        comment: Option<String>,
+    ) -> impl Future<Item = (Self, Vec<Order>), Error = (Self, FailureError)> {
+        iter_ok::<_, (Self, FailureError)>(order_slugs)
+            .fold((self, vec![]), |(s, mut orders), order_slug| {
+                s.get_order(order_slug).and_then(move |(s, order)| {
+                    if orders.iter().any(|&(ref known, _): &(Order, CommitterRole)| known.id == order.id) {
+                        Either::A(future::ok((s, orders)))
+                    } else if is_cancellable(order.state) {
+                        Either::B(s.check_permission(order).map(move |(s, order)| {
+                            orders.push(order);
+                            (s, orders)
+                        }))
+                    } else {
+                        debug!("Order {} in state {} can not be cancelled", order_slug, order.state);
+                        Either::A(future::err((
+                            s,
+                            Error::Validate(validation_errors!({"order": ["order_state" => "Order in this state can not be cancelled"]}))
+                                .into(),
+                        )))
+                    }
+                })
+            })
+            .and_then(|(s, orders)| s.check_invoice(orders))
+            .and_then(move |(s, orders)| {
+                iter_ok::<_, (Self, FailureError)>(orders).fold((s, vec![]), move |(s, mut cancelled), (order, committer_role)| {
+                    let previous_state = order.state;
+                    s.cancel_order(order, comment.clone(), committer_role)
+                        .and_then(move |(s, order)| {
+                            if holds_stock(previous_state) {
+                                Either::A(s.return_stock(order))
+                            } else {
+                                Either::B(future::ok((s, order)))
+                            }
+                        })
+                        .map(move |(s, order)| {
+                            cancelled.push(order);
+                            (s, cancelled)
+                        })
+                })
+            })
+            .and_then(|(s, orders)| {
+                let order_ids = orders.iter().map(|order| order.id).collect();
+                s.amend_invoice(order_ids).map(move |(s, _)| (s, orders))
+            })
+            .and_then(|(s, orders)| {
+                iter_ok::<_, (Self, FailureError)>(orders).fold((s, vec![]), |(s, mut notified), order| {
+                    soft_step(s.log.clone(), "cancellation_notification", s.notify_parties(order.clone())).map(move |s| {
+                        notified.push(order);
+                        (s, notified)
+                    })
+                })
+            })
+    }
+
+    /// Derives role the caller commits cancellation of the order with, it must be allowed to cancel orders
+    fn check_permission(self, order: Order) -> impl Future<Item = (Self, (Order, CommitterRole)), Error = (Self, FailureError)> {
+        let permissions = self.config.order_state_permissions.clone();
+        let order_slug = order.slug;
+        self.initiators
+            .order_committer_role(&self.stores_microservice, &order)
+            .and_then(move |committer_role| {
+                if permissions.allows(committer_role, OrderState::Cancelled) {
+                    Ok(committer_role)
+                } else {
+                    Err(
+                        format_err!("Committer {} is not allowed to cancel order {}", committer_role, order_slug)
+                            .context(Error::Forbidden)
+                            .into(),
+                    )
+                }
+            })
+            .then(|res| match res {
+                Ok(committer_role) => Ok((self, (order, committer_role))),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    /// Order is looked up on behalf of the caller, so orders checks that the caller may see it
+    fn get_order(self, order_slug: OrderSlug) -> impl Future<Item = (Self, Order), Error = (Self, FailureError)> {
+        self.orders_microservice
+            .get_order(None, OrderIdentifier::Slug(order_slug))
+            .and_then(move |order| {
+                order
+                    .ok_or_else(|| format_err!("Order {} not found", order_slug).context(Error::NotFound).into())
+                    .into_future()
+            })
+            .then(|res| match res {
+                Ok(order) => Ok((self, order)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    /// Single amendment takes orders out of one invoice only, so orders of different invoices are refused
+    fn check_invoice(
+        self,
+        orders: Vec<(Order, CommitterRole)>,
+    ) -> impl Future<Item = (Self, Vec<(Order, CommitterRole)>), Error = (Self, FailureError)> {
+        let lookups = orders
+            .iter()
+            .map(|&(ref order, _)| {
+                let order_id = order.id;
+                self.billing_microservice
+                    .get_order_invoice(Initiator::Superadmin, order_id)
+                    .map(move |invoice| (order_id, invoice.map(|invoice| invoice.invoice_id)))
+            })
+            .collect::<Vec<_>>();
+
+        join_all(lookups)
+            .and_then(|invoices| check_same_invoice(&invoices))
+            .then(|res| match res {
+                Ok(()) => Ok((self, orders)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn cancel_order(
+        self,
+        order: Order,
+        comment: Option<String>,
+        committer_role: CommitterRole,
+    ) -> impl Future<Item = (Self, Order), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let order_id = order.id;
+        let initiator = match self.initiators.caller("orders_cancel") {
+            Ok(initiator) => initiator,
+            Err(e) => return Either::A(future::err((self, e))),
+        };
+        log.push(CancelOrderItemOperationStage::OrderCancelStart(order_id, order.state));
+
+        let payload = UpdateStatePayload {
+            state: OrderState::Cancelled,
+            track_id: None,
+            comment,
+            committer_role,
+        };
+        Either::B(
+            self.orders_microservice
+                .set_order_state(Some(initiator), OrderIdentifier::Id(order_id), payload)
+                .and_then(move |order| {
+                    order
+                        .ok_or_else(|| format_err!("Order {} not found", order_id).context(Error::NotFound).into())
+                        .into_future()
+                })
+                .and_then(move |order| {
+                    log.push_with_result(CancelOrderItemOperationStage::OrderCancelComplete(order_id), &order);
+                    Ok(order)
+                })
+                .then(|res| match res {
+                    Ok(order) => Ok((self, order)),
+                    Err(e) => Err((self, e)),
+                }),
+        )
+    }
+
+    fn return_stock(self, order: Order) -> impl Future<Item = (Self, Order), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let order_id = order.id;
+        let initiator = self.initiators.superadmin(
+            &log,
+            "warehouses_stock_return",
+            "stocks are adjusted only by warehouses administrators",
+        );
+        log.push(CancelOrderItemOperationStage::StockReturnStart(order_id));
+
+        let adjustment = StockAdjustment {
+            saga_id: self.log.saga_id(),
+            kind: StockAdjustmentKind::Return,
+            quantity: order.quantity,
+        };
+        self.warehouses_microservice
+            .adjust_stock(initiator, order.product, adjustment)
+            .then(move |res| match res {
+                Ok(_) => {
+                    log.push(CancelOrderItemOperationStage::StockReturnComplete(order_id));
+                    Ok((self, order))
+                }
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn amend_invoice(self, order_ids: Vec<OrderId>) -> impl Future<Item = (Self, InvoiceAmendment), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let saga_id = self.log.saga_id();
+        let initiator = self
+            .initiators
+            .superadmin(&log, "billing_invoice_amend", "invoices are amended only by billing administrators");
+        log.push(CancelOrderItemOperationStage::InvoiceAmendStart(saga_id));
+
+        let payload = AmendInvoice { saga_id, order_ids };
+        self.billing_microservice
+            .amend_invoice(initiator, payload)
+            .and_then(move |amendment| {
+                log.push_with_result(CancelOrderItemOperationStage::InvoiceAmendComplete(saga_id), &amendment);
+                Ok(amendment)
+            })
+            .then(|res| match res {
+                Ok(amendment) => Ok((self, amendment)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    /// Tells both customer and store that the order is cancelled
+    fn notify_parties(self, order: Order) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let cluster_url = self.config.cluster.url.clone();
+        let notifications_microservice = self.notifications_microservice.clone();
+        let order_slug = order.slug.to_string();
+        let order_state = order.state.to_string();
+
+        let to_customer = {
+            let notifications_microservice = notifications_microservice.clone();
+            let cluster_url = cluster_url.clone();
+            let order_slug = order_slug.clone();
+            let order_state = order_state.clone();
+            let customer_id = order.customer;
+            self.users_microservice
+                .get(Some(Initiator::Superadmin), customer_id)
+                .and_then(move |user| {
+                    user.ok_or_else(|| format_err!("Customer {} not found", customer_id).context(Error::NotFound).into())
+                        .into_future()
+                })
+                .and_then(move |user| {
+                    let email = OrderUpdateStateForUser {
+                        user: EmailUser {
+                            email: user.email.clone(),
+                            first_name: user.first_name.unwrap_or_else(|| "user".to_string()),
+                            last_name: user.last_name.unwrap_or_else(|| "".to_string()),
+                        },
+                        order_slug,
+                        order_state,
+                        cluster_url,
+                    };
+                    notifications_microservice.order_update_state_for_user(Initiator::Superadmin, email)
+                })
+        };
+
+        let store_id = order.store;
+        let to_store =
+            self.stores_microservice
+                .get(store_id, Visibility::Active)
+                .and_then(move |store| match store.and_then(|store| store.email) {
+                    Some(store_email) => {
+                        let email = OrderUpdateStateForStore {
+                            store_email,
+                            store_id: store_id.to_string(),
+                            order_slug,
+                            order_state,
+                            cluster_url,
+                        };
+                        Either::A(notifications_microservice.order_update_state_for_store(Initiator::Superadmin, email))
+                    }
+                    None => Either::B(future::ok(())),
+                });
+
+        // both notifications are sent even if the first one fails
+        to_customer
+            .then(|customer_res| to_store.then(|store_res| customer_res.and(store_res)))
+            .then(|res| match res {
+                Ok(_) => Ok((self, ())),
+                Err(e) => Err((
+                    self,
+                    FailureError::from(e.context("Notifying parties of order cancellation failed.")),
+                )),
+            })
+    }
+
+    // Contains reversal of order item cancellation
+    pub fn cancel_item_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let billing_microservice = self.billing_microservice.clone();
+        let orders_microservice = self.orders_microservice.clone();
+        let warehouses_microservice = self.warehouses_microservice.clone();
+        let saga_id = self.log.saga_id();
+
+        let compensation = self.log.compensate(move |e, phase| match e {
+            CancelOrderItemOperationStage::InvoiceAmendStart(saga_id) => revert_amendment(billing_microservice.clone(), saga_id),
+
+            CancelOrderItemOperationStage::StockReturnStart(order_id) => {
+                debug!("Reverting stock return of cancelled order {}", order_id);
+                revert_stock_return(
+                    orders_microservice.clone(),
+                    warehouses_microservice.clone(),
+                    saga_id,
+                    order_id,
+                    phase,
+                )
+            }
+
+            CancelOrderItemOperationStage::OrderCancelStart(order_id, previous_state) => {
+                debug!("Reverting cancellation of order {} to state {}", order_id, previous_state);
+                let payload = UpdateStatePayload {
+                    state: previous_state,
+                    track_id: None,
+                    comment: Some("Order cancellation saga was reverted".to_string()),
+                    committer_role: CommitterRole::System,
+                };
+                Box::new(
+                    orders_microservice
+                        .set_order_state(Some(Initiator::Superadmin), OrderIdentifier::Id(order_id), payload)
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
+        });
+
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((
+                self,
+                format_err!("Order cancellation service cancel_item_revert error occurred: {}", e),
+            )),
+        })
+    }
+}
+
+impl SagaService for OrderCancellationServiceImpl {
+    type Stage = CancelOrderItemOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.cancel_item_revert().map(|(s, _)| s))
+    }
+}
+
+impl OrderCancellationService for OrderCancellationServiceImpl {
+    fn cancel_item(
+        self,
+        order_slug: OrderSlug,
+        input: OrderItemCancelInput,
+    ) -> ServiceFuture<Box<OrderCancellationService>, SagaResponse<Order>> {
+        debug!("Cancel order {}, input: {:?}", order_slug, Scrubbed(&input));
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::CancelOrderItem,
+            deadline,
+            move |s| s.cancel_item_happy(order_slug, input),
+            &["order", "comment"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<OrderCancellationService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<OrderCancellationService>, e)),
+        )
+    }
+
+    fn cancel_items(self, input: OrderItemsCancelInput) -> ServiceFuture<Box<OrderCancellationService>, SagaResponse<Vec<Order>>> {
+        debug!("Cancel orders, input: {:?}", Scrubbed(&input));
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::CancelOrderItem,
+            deadline,
+            move |s| s.cancel_items_happy(input),
+            &["order", "comment"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<OrderCancellationService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<OrderCancellationService>, e)),
+        )
+    }
+}
+
+fn check_same_invoice(invoices: &[(OrderId, Option<InvoiceId>)]) -> Result<(), FailureError> {
+    let mut shared: Option<&InvoiceId> = None;
+    for &(order_id, ref invoice_id) in invoices {
+        let invoice_id = match invoice_id {
+            Some(invoice_id) => invoice_id,
+            None => {
+                return Err(format_err!("Invoice of order {} not found", order_id)
+                    .context(Error::NotFound)
+                    .into())
+            }
+        };
+        match shared {
+            Some(shared) if shared != invoice_id => {
+                debug!(
+                    "Order {} belongs to invoice {}, other orders to invoice {}",
+                    order_id, invoice_id, shared
+                );
+                return Err(Error::Validate(
+                    validation_errors!({"order": ["invoice" => "Orders of different invoices can not be cancelled together"]}),
+                )
+                .into());
+            }
+            _ => shared = Some(invoice_id),
+        }
+    }
+    Ok(())
+}
+
+fn revert_amendment(billing_microservice: Arc<BillingMicroservice>, saga_id: SagaId) -> Box<Future<Item = (), Error = FailureError>> {
+    debug!("Reverting invoice amendment, saga_id: {}", saga_id);
+    Box::new(
+        billing_microservice
+            .get_invoice_amendment_by_saga_id(Initiator::Superadmin, saga_id)
+            .and_then(move |amendment| match amendment {
+                Some(_) => Either::A(billing_microservice.revert_amend_invoice(Initiator::Superadmin, saga_id)),
+                None => {
+                    debug!("Invoice was not amended by saga {}, nothing to revert", saga_id);
+                    Either::B(future::ok(()))
+                }
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use stq_types::{InvoiceId, OrderId};
+
+    use super::check_same_invoice;
+    use errors::Error;
+
+    #[test]
+    fn refuses_orders_of_different_invoices() {
+        let invoice_id = InvoiceId::new();
+        let (first, second) = (OrderId::new(), OrderId::new());
+        assert!(check_same_invoice(&[(first, Some(invoice_id.clone())), (second, Some(invoice_id.clone()))]).is_ok());
+
+        let e = check_same_invoice(&[(first, Some(invoice_id)), (second, Some(InvoiceId::new()))]).unwrap_err();
+        assert!(e.iter_chain().any(|cause| match cause.downcast_ref::<Error>() {
+            Some(&Error::Validate(_)) => true,
+            _ => false,
+        }));
+
+        assert!(check_same_invoice(&[(first, None)]).is_err());
+    }
+}
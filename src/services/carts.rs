@@ -0,0 +1,187 @@
+//! Cleanup of carts of customers after stores, base products or their shipping change.
+//! Products are collected through the saga and orders microservice receives at most
+//! one call of every kind however many stores and base products the saga changes.
+//! Customers whose carts lost products are notified if the deployment enables it.
+//! Deactivated products are removed from wishlists the same way.
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use failure::Error as FailureError;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+
+use stq_static_resources::EmailUser;
+use stq_types::ProductId;
+
+use microservice::{Initiator, NotificationsMicroservice, OrdersMicroservice, StoresMicroservice, UsersMicroservice};
+use models::*;
+
+#[derive(Clone, Default)]
+pub struct CartsCleanup {
+    /// Products to be removed from carts
+    products: Rc<RefCell<HashSet<ProductId>>>,
+    /// Products whose delivery methods are no longer valid
+    delivery_methods: Rc<RefCell<HashSet<ProductId>>>,
+    /// Products to be removed from wishlists
+    wishlists: Rc<RefCell<HashSet<ProductId>>>,
+    notifier: Option<CustomersNotifier>,
+}
+
+/// Notifies customers about products removed from their carts
+#[derive(Clone)]
+pub struct CustomersNotifier {
+    pub users_microservice: Arc<UsersMicroservice>,
+    pub notifications_microservice: Arc<NotificationsMicroservice>,
+    pub cluster_url: String,
+}
+
+impl CartsCleanup {
+    pub fn with_notifier(mut self, notifier: CustomersNotifier) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    pub fn remove_products<I: IntoIterator<Item = ProductId>>(&self, product_ids: I) {
+        self.products.borrow_mut().extend(product_ids);
+    }
+
+    pub fn clear_delivery_methods<I: IntoIterator<Item = ProductId>>(&self, product_ids: I) {
+        self.delivery_methods.borrow_mut().extend(product_ids);
+    }
+
+    pub fn remove_from_wishlists<I: IntoIterator<Item = ProductId>>(&self, product_ids: I) {
+        self.wishlists.borrow_mut().extend(product_ids);
+    }
+
+    /// Removes products collected so far from wishlists with a single call, returns their number
+    pub fn flush_wishlists(&self, stores_microservice: &Arc<StoresMicroservice>) -> impl Future<Item = usize, Error = FailureError> {
+        let product_ids = take(&self.wishlists);
+        let removed = product_ids.len();
+        if product_ids.is_empty() {
+            Either::A(future::ok(0))
+        } else {
+            Either::B(
+                stores_microservice
+                    .delete_products_from_all_wishlists(Some(Initiator::Superadmin), DeleteProductsFromWishlistsPayload { product_ids })
+                    .map(move |_| removed),
+            )
+        }
+    }
+
+    /// Cleans up carts with everything collected so far, numbers of affected products are added to the result
+    pub fn flush<T>(
+        &self,
+        orders_microservice: &Arc<OrdersMicroservice>,
+        result: T,
+    ) -> impl Future<Item = CartsCleanupResult<T>, Error = FailureError> {
+        let delivery_methods = take(&self.delivery_methods);
+        let products = take(&self.products);
+        let cleared_delivery_methods = delivery_methods.len();
+        let removed_from_carts = products.len();
+
+        let clear_delivery_methods = if delivery_methods.is_empty() {
+            Either::A(future::ok(()))
+        } else {
+            Either::B(orders_microservice.delete_delivery_method_from_all_carts(
+                Some(Initiator::Superadmin),
+                DeleteDeliveryMethodFromCartsPayload {
+                    product_ids: delivery_methods,
+                },
+            ))
+        };
+
+        // customers have to be found before products are removed from their carts
+        let notifier = self.notifier.clone();
+        let customers = match notifier {
+            Some(_) if !products.is_empty() => Either::A(find_customers(orders_microservice, products.clone())),
+            _ => Either::B(future::ok(vec![])),
+        };
+
+        let orders_microservice = orders_microservice.clone();
+        let remove_products = move || {
+            if products.is_empty() {
+                Either::A(future::ok(()))
+            } else {
+                Either::B(orders_microservice.delete_products_from_all_carts(
+                    Some(Initiator::Superadmin),
+                    DeleteProductsFromCartsPayload { product_ids: products },
+                ))
+            }
+        };
+
+        clear_delivery_methods
+            .and_then(|_| customers)
+            .and_then(move |customers| remove_products().map(|_| customers))
+            .and_then(move |customers| match notifier {
+                Some(notifier) => Either::A(notifier.notify(customers)),
+                None => Either::B(future::ok(())),
+            })
+            .map(move |_| CartsCleanupResult {
+                result,
+                removed_from_carts,
+                cleared_delivery_methods,
+            })
+    }
+}
+
+impl CustomersNotifier {
+    /// Customers not notified are logged, notifications do not fail the saga
+    fn notify(self, customers: Vec<CustomerCartProducts>) -> impl Future<Item = (), Error = FailureError> {
+        let users_microservice = self.users_microservice;
+        let notifications_microservice = self.notifications_microservice;
+        let cluster_url = self.cluster_url;
+
+        iter_ok::<_, FailureError>(customers).for_each(move |customer| {
+            let customer_id = customer.customer_id;
+            let product_ids = customer.product_ids;
+            let notifications_microservice = notifications_microservice.clone();
+            let cluster_url = cluster_url.clone();
+
+            users_microservice
+                .get(Some(Initiator::Superadmin), customer_id)
+                .and_then(move |user| {
+                    user.ok_or_else(|| format_err!("User {} is not found in users microservice", customer_id))
+                        .into_future()
+                })
+                .and_then(move |user| {
+                    let email = CartProductsUnavailableForUser {
+                        user: EmailUser {
+                            email: user.email.clone(),
+                            first_name: user.first_name.unwrap_or_else(|| "user".to_string()),
+                            last_name: user.last_name.unwrap_or_else(|| "".to_string()),
+                        },
+                        product_ids,
+                        cluster_url,
+                    };
+                    notifications_microservice.cart_products_unavailable_for_user(Initiator::Superadmin, email)
+                })
+                .then(move |res| {
+                    if let Err(e) = res {
+                        warn!("Customer {} was not notified about unavailable cart products: {}", customer_id, e);
+                    }
+                    Ok(())
+                })
+        })
+    }
+}
+
+/// Customers having any of the products in their carts, nobody is notified if the lookup fails
+fn find_customers(
+    orders_microservice: &Arc<OrdersMicroservice>,
+    product_ids: Vec<ProductId>,
+) -> impl Future<Item = Vec<CustomerCartProducts>, Error = FailureError> {
+    orders_microservice
+        .find_carts_with_products(Some(Initiator::Superadmin), FindCartsWithProductsPayload { product_ids })
+        .or_else(|e| {
+            warn!("Customers having unavailable products in their carts were not found: {}", e);
+            Ok::<_, FailureError>(vec![])
+        })
+}
+
+/// Collected products, the collection is emptied
+fn take(product_ids: &RefCell<HashSet<ProductId>>) -> Vec<ProductId> {
+    product_ids.borrow_mut().drain().collect()
+}
@@ -0,0 +1,448 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use uuid::Uuid;
+
+use stq_types::*;
+
+use super::parse_validation_errors;
+use super::store::{fill_uids, set_variants_stock, store_warehouse_id};
+use config;
+use microservice::*;
+use models::*;
+use saga::{self, compensation_order, with_heartbeat, SagaLog, SagaService, SagaStore};
+use services::types::ServiceFuture;
+
+pub trait CatalogService {
+    /// Imports catalog rows into the store one by one. Rows that fail to import are cleaned up
+    /// and reported in the result instead of failing the saga.
+    fn import(self, store_id: StoreId, rows: Vec<CatalogRow>) -> ServiceFuture<Box<CatalogService>, SagaResponse<Vec<CatalogRowResult>>>;
+}
+
+#[derive(Clone)]
+pub struct CatalogServiceImpl {
+    pub stores_microservice: Arc<StoresMicroservice>,
+    pub delivery_microservice: Arc<DeliveryMicroservice>,
+    pub warehouses_microservice: Arc<WarehousesMicroservice>,
+    pub config: config::Config,
+    pub log: Rc<SagaLog<CatalogImportOperationStage>>,
+}
+
+impl CatalogServiceImpl {
+    pub fn new(
+        config: config::Config,
+        saga_store: Arc<SagaStore>,
+        stores_microservice: Arc<StoresMicroservice>,
+        delivery_microservice: Arc<DeliveryMicroservice>,
+        warehouses_microservice: Arc<WarehousesMicroservice>,
+    ) -> Self {
+        let log = Rc::new(SagaLog::new(saga_store));
+        Self {
+            config,
+            stores_microservice,
+            delivery_microservice,
+            warehouses_microservice,
+            log,
+        }
+    }
+
+    /// Imports rows along with their indices in catalog
+    fn import_happy(
+        self,
+        store_id: StoreId,
+        rows: Vec<(usize, CatalogRow)>,
+    ) -> impl Future<Item = (Self, Vec<CatalogRowResult>), Error = (Self, FailureError)> {
+        let needs_stock = rows
+            .iter()
+            .any(|(_, row)| row.base_product.variants.iter().any(|variant| variant.quantity.is_some()));
+
+        self.warehouse_id(store_id, needs_stock).and_then(move |(s, warehouse_id)| {
+            iter_ok::<_, (Self, FailureError)>(rows).fold((s, vec![]), move |(s, mut results), (index, row)| {
+                s.import_row(store_id, warehouse_id, index, row).map(move |(s, result)| {
+                    results.push(result);
+                    (s, results)
+                })
+            })
+        })
+    }
+
+    /// Warehouse is looked up once for the whole catalog, catalog with stock can not be imported into store without warehouse
+    fn warehouse_id(
+        self,
+        store_id: StoreId,
+        needs_stock: bool,
+    ) -> impl Future<Item = (Self, Option<WarehouseId>), Error = (Self, FailureError)> {
+        let warehouse_id = if needs_stock {
+            Either::A(store_warehouse_id(self.warehouses_microservice.clone(), store_id).map(Some))
+        } else {
+            Either::B(future::ok(None))
+        };
+
+        warehouse_id.then(|res| match res {
+            Ok(warehouse_id) => Ok((self, warehouse_id)),
+            Err(e) => Err((self, e)),
+        })
+    }
+
+    fn import_row(
+        self,
+        store_id: StoreId,
+        warehouse_id: Option<WarehouseId>,
+        index: usize,
+        row: CatalogRow,
+    ) -> impl Future<Item = (Self, CatalogRowResult), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let first_stage = log.stages().len();
+        let uuid = row.base_product.uuid.clone().unwrap_or_else(|| Uuid::new_v4().to_string());
+        let CatalogRow { base_product, shipping } = row;
+
+        self.create_row(store_id, warehouse_id, base_product.into_new_base_product(store_id, uuid), shipping)
+            .then(move |res| match res {
+                Ok(base_product_id) => {
+                    let result = CatalogRowResult {
+                        row: index,
+                        status: CatalogRowStatus::Imported,
+                        base_product_id: Some(base_product_id),
+                        error: None,
+                    };
+                    log.push_with_result(CatalogImportOperationStage::RowImported(index), &result);
+                    Either::A(future::ok((self, result)))
+                }
+                Err((base_product_id, e)) => {
+                    warn!("Catalog row {} of saga {} was not imported: {}", index, log.saga_id(), e);
+                    let row_stages = log.stages().split_off(first_stage);
+                    Either::B(self.cleanup_row(base_product_id, row_stages).map(move |s| {
+                        let result = CatalogRowResult {
+                            row: index,
+                            status: CatalogRowStatus::Failed,
+                            base_product_id: None,
+                            error: Some(e.to_string()),
+                        };
+                        s.log.push_with_result(CatalogImportOperationStage::RowImported(index), &result);
+                        (s, result)
+                    }))
+                }
+            })
+    }
+
+    /// Creates base product of the row along with its shipping and stock.
+    /// Error carries id of base product if it was created before the failure.
+    fn create_row(
+        &self,
+        store_id: StoreId,
+        warehouse_id: Option<WarehouseId>,
+        payload: NewBaseProductWithVariants,
+        shipping: Option<NewShipping>,
+    ) -> impl Future<Item = BaseProductId, Error = (Option<BaseProductId>, FailureError)> {
+        let log = self.log.clone();
+        let stores_microservice = self.stores_microservice.clone();
+        let delivery_microservice = self.delivery_microservice.clone();
+        let warehouses_microservice = self.warehouses_microservice.clone();
+
+        fill_uids(payload).map_err(|e| (None, e)).into_future().and_then(move |payload| {
+            let uuid = payload.uuid.clone();
+            log.push(CatalogImportOperationStage::BaseProductCreationStart(uuid.clone()));
+            stores_microservice
+                .create_base_product_with_variants(None, payload.clone())
+                .map(move |base_product| {
+                    log.push_with_result(
+                        CatalogImportOperationStage::BaseProductCreationComplete(uuid, base_product.id),
+                        &base_product,
+                    );
+                    (log, base_product.id, payload)
+                })
+                .map_err(|e| (None, parse_validation_errors(e, &["base_product"])))
+                .and_then(move |(log, base_product_id, payload)| {
+                    let shipping = match shipping {
+                        Some(shipping) => {
+                            let log = log.clone();
+                            log.push(CatalogImportOperationStage::ShippingUpsertStart(base_product_id));
+                            Either::A(
+                                delivery_microservice
+                                    .upsert_shipping(None, base_product_id, shipping)
+                                    .map(move |shipping| {
+                                        log.push_with_result(
+                                            CatalogImportOperationStage::ShippingUpsertComplete(base_product_id),
+                                            &shipping,
+                                        );
+                                    }),
+                            )
+                        }
+                        None => Either::B(future::ok(())),
+                    };
+
+                    shipping
+                        .and_then(move |_| {
+                            let has_stock = payload.variants.iter().any(|variant| variant.quantity.is_some());
+                            match warehouse_id {
+                                Some(warehouse_id) if has_stock => {
+                                    log.push(CatalogImportOperationStage::StockSetStart(store_id, base_product_id));
+                                    Either::A(
+                                        set_variants_stock(
+                                            stores_microservice,
+                                            warehouses_microservice,
+                                            warehouse_id,
+                                            base_product_id,
+                                            payload,
+                                        )
+                                        .map(move |_| log.push(CatalogImportOperationStage::StockSetComplete(base_product_id))),
+                                    )
+                                }
+                                _ => Either::B(future::ok(())),
+                            }
+                        })
+                        .map(move |_| base_product_id)
+                        .map_err(move |e| (Some(base_product_id), e))
+                })
+        })
+    }
+
+    /// Reverts steps of failed row right away, so that the rest of catalog is imported without it.
+    /// Cleaned up base product is skipped if the whole saga gets reverted later.
+    fn cleanup_row(
+        self,
+        base_product_id: Option<BaseProductId>,
+        row_stages: Vec<CatalogImportOperationStage>,
+    ) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let base_product_id = match base_product_id {
+            Some(base_product_id) => base_product_id,
+            None => return Either::A(future::ok(self)),
+        };
+
+        let base_product_ids = created_base_product_ids(&row_stages);
+        let stores_microservice = self.stores_microservice.clone();
+        let delivery_microservice = self.delivery_microservice.clone();
+        let warehouses_microservice = self.warehouses_microservice.clone();
+
+        Either::B(
+            iter_ok::<_, FailureError>(compensation_order(&row_stages))
+                .for_each(move |(stage, _)| {
+                    revert_stage(
+                        stores_microservice.clone(),
+                        delivery_microservice.clone(),
+                        warehouses_microservice.clone(),
+                        &base_product_ids,
+                        stage,
+                    )
+                })
+                .then(move |res| {
+                    match res {
+                        Ok(_) => self
+                            .log
+                            .push(CatalogImportOperationStage::BaseProductCleanupComplete(base_product_id)),
+                        // leftovers of the row are cleaned up along with the rest of catalog if the saga is reverted
+                        Err(e) => error!(
+                            "Cleanup of base product {} of saga {} failed: {}",
+                            base_product_id,
+                            self.log.saga_id(),
+                            e
+                        ),
+                    }
+                    Ok::<_, (Self, FailureError)>(self)
+                }),
+        )
+    }
+
+    // Contains reversal of imported rows: stock is reset, shipping is deleted and base products are deactivated.
+    // Rows already cleaned up after their failure are skipped.
+    pub fn import_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let stores_microservice = self.stores_microservice.clone();
+        let delivery_microservice = self.delivery_microservice.clone();
+        let warehouses_microservice = self.warehouses_microservice.clone();
+        let saga_id = self.log.saga_id();
+
+        let stages = self.log.stages();
+        let base_product_ids = created_base_product_ids(&stages);
+        let cleaned_up = stages
+            .iter()
+            .filter_map(|stage| match stage {
+                CatalogImportOperationStage::BaseProductCleanupComplete(base_product_id) => Some(*base_product_id),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        let compensation = self.log.compensate(move |stage, _| {
+            let base_product_id = match &stage {
+                CatalogImportOperationStage::BaseProductCreationStart(uuid) => base_product_ids.get(uuid).cloned(),
+                CatalogImportOperationStage::ShippingUpsertStart(base_product_id)
+                | CatalogImportOperationStage::StockSetStart(_, base_product_id) => Some(*base_product_id),
+                _ => None,
+            };
+            if base_product_id.map(|id| cleaned_up.contains(&id)).unwrap_or(false) {
+                return Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>;
+            }
+
+            debug!("Reverting stage {:?} of catalog import, saga_id: {}", stage, saga_id);
+            revert_stage(
+                stores_microservice.clone(),
+                delivery_microservice.clone(),
+                warehouses_microservice.clone(),
+                &base_product_ids,
+                stage,
+            )
+        });
+
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((self, format_err!("Catalog service import_revert error occurred: {}", e))),
+        })
+    }
+}
+
+/// Ids of created base products by their uuids
+fn created_base_product_ids(stages: &[CatalogImportOperationStage]) -> HashMap<String, BaseProductId> {
+    stages
+        .iter()
+        .filter_map(|stage| match stage {
+            CatalogImportOperationStage::BaseProductCreationComplete(uuid, base_product_id) => Some((uuid.clone(), *base_product_id)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn revert_stage(
+    stores_microservice: Arc<StoresMicroservice>,
+    delivery_microservice: Arc<DeliveryMicroservice>,
+    warehouses_microservice: Arc<WarehousesMicroservice>,
+    base_product_ids: &HashMap<String, BaseProductId>,
+    stage: CatalogImportOperationStage,
+) -> Box<Future<Item = (), Error = FailureError>> {
+    match stage {
+        CatalogImportOperationStage::BaseProductCreationStart(uuid) => match base_product_ids.get(&uuid) {
+            Some(base_product_id) => Box::new(
+                stores_microservice
+                    .deactivate_base_product(Some(Initiator::Superadmin), *base_product_id, Deactivation::default())
+                    .map(|_| ()),
+            ),
+            None => {
+                debug!("Base product {} was not created, nothing to deactivate", uuid);
+                Box::new(future::ok(()))
+            }
+        },
+
+        CatalogImportOperationStage::ShippingUpsertStart(base_product_id) => {
+            Box::new(delivery_microservice.delete_shipping_by_base_product(Some(Initiator::Superadmin), base_product_id))
+        }
+
+        CatalogImportOperationStage::StockSetStart(store_id, base_product_id) => Box::new(
+            store_warehouse_id(warehouses_microservice.clone(), store_id)
+                .join(stores_microservice.get_products_by_base_product(base_product_id))
+                .and_then(move |(warehouse_id, products)| {
+                    iter_ok::<_, FailureError>(products).for_each(move |product| {
+                        warehouses_microservice
+                            .set_product_in_warehouse(Initiator::Superadmin, warehouse_id, product.id, Quantity(0))
+                            .map(|_| ())
+                    })
+                }),
+        ),
+
+        _ => Box::new(future::ok(())),
+    }
+}
+
+impl CatalogServiceImpl {
+    /// Continues import whose claim has expired, e.g. as its replica stopped during the import. Rows imported so far are skipped,
+    /// the row interrupted in the middle is cleaned up and imported again.
+    pub fn resume(self, input: CatalogImportInput) -> ServiceFuture<Box<CatalogService>, SagaResponse<Vec<CatalogRowResult>>> {
+        let stages = self.log.stages();
+        let is_row_imported = |stage: &CatalogImportOperationStage| match stage {
+            CatalogImportOperationStage::RowImported(_) => true,
+            _ => false,
+        };
+        let imported = stages
+            .iter()
+            .filter_map(|stage| match stage {
+                CatalogImportOperationStage::RowImported(index) => Some(*index),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+        let interrupted = stages
+            .iter()
+            .rposition(is_row_imported)
+            .map(|last| stages[last + 1..].to_vec())
+            .unwrap_or_else(|| stages.clone());
+        let interrupted_base_product_id = created_base_product_ids(&interrupted).values().next().cloned();
+
+        let CatalogImportInput { store_id, rows } = input;
+        let rows = rows
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| !imported.contains(index))
+            .collect::<Vec<_>>();
+        info!(
+            "Resuming catalog import {} into store {}, {} rows left",
+            self.log.saga_id(),
+            store_id,
+            rows.len()
+        );
+
+        self.execute(move |s| {
+            s.cleanup_row(interrupted_base_product_id, interrupted)
+                .and_then(move |s| s.import_happy(store_id, rows))
+        })
+    }
+
+    /// Runs import on behalf of the process holding claim of the saga, claim is released once the import is finished
+    fn execute<F, R>(self, happy_path: F) -> ServiceFuture<Box<CatalogService>, SagaResponse<Vec<CatalogRowResult>>>
+    where
+        F: FnOnce(Self) -> R + 'static,
+        R: Future<Item = (Self, Vec<CatalogRowResult>), Error = (Self, FailureError)> + 'static,
+    {
+        let deadline = Duration::from_millis(self.config.catalog_import.deadline_ms);
+        let log = self.log.clone();
+        let saga_config = self.config.saga.clone();
+
+        let res = saga::run(
+            self,
+            SagaType::CatalogImport,
+            deadline,
+            move |s| with_heartbeat(log, &saga_config, happy_path(s)),
+            &[],
+        );
+
+        Box::new(
+            res.map(|(s, response)| {
+                s.log.release();
+                (Box::new(s) as Box<CatalogService>, response)
+            })
+            .map_err(|(s, e)| {
+                s.log.release();
+                (Box::new(s) as Box<CatalogService>, e)
+            }),
+        )
+    }
+}
+
+impl SagaService for CatalogServiceImpl {
+    type Stage = CatalogImportOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.import_revert().map(|(s, _)| s))
+    }
+}
+
+impl CatalogService for CatalogServiceImpl {
+    fn import(self, store_id: StoreId, rows: Vec<CatalogRow>) -> ServiceFuture<Box<CatalogService>, SagaResponse<Vec<CatalogRowResult>>> {
+        debug!("Import catalog of {} rows into store {}", rows.len(), store_id);
+        // started right away, so that report of the import is available as soon as it is accepted
+        self.log.start(SagaType::CatalogImport);
+        self.log.set_input(&CatalogImportInput {
+            store_id,
+            rows: rows.clone(),
+        });
+        let claim = self.log.claim(Duration::from_millis(self.config.saga.claim_lease_ms));
+
+        let rows = rows.into_iter().enumerate().collect::<Vec<_>>();
+        Box::new(claim.then(move |_| self.execute(move |s| s.import_happy(store_id, rows))))
+    }
+}
@@ -0,0 +1,293 @@
+//! Category change of base product. Packages of the base product are recalculated
+//! by delivery for the new category, so that shipping selection keeps working, and
+//! variants of the base product are removed from carts, as delivery methods chosen
+//! for them may no longer be available.
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::future::{self, Either};
+use futures::prelude::*;
+
+use stq_types::*;
+
+use config;
+use errors::Error;
+use microservice::*;
+use models::*;
+use saga::{self, SagaLog, SagaService, SagaStore};
+use services::carts::{CartsCleanup, CustomersNotifier};
+use services::store::check_version;
+use services::types::ServiceFuture;
+
+pub trait CategoryChangeService {
+    /// Moves base product into category of the input
+    fn change_category(
+        self,
+        base_product_id: BaseProductId,
+        input: ChangeCategoryInput,
+    ) -> ServiceFuture<Box<CategoryChangeService>, SagaResponse<CartsCleanupResult<BaseProduct>>>;
+}
+
+#[derive(Clone)]
+pub struct CategoryChangeServiceImpl {
+    pub orders_microservice: Arc<OrdersMicroservice>,
+    pub stores_microservice: Arc<StoresMicroservice>,
+    pub delivery_microservice: Arc<DeliveryMicroservice>,
+    pub config: config::Config,
+    pub log: Rc<SagaLog<ChangeCategoryOperationStage>>,
+    pub carts_cleanup: CartsCleanup,
+}
+
+impl CategoryChangeServiceImpl {
+    pub fn new(
+        config: config::Config,
+        saga_store: Arc<SagaStore>,
+        orders_microservice: Arc<OrdersMicroservice>,
+        stores_microservice: Arc<StoresMicroservice>,
+        delivery_microservice: Arc<DeliveryMicroservice>,
+        users_microservice: Arc<UsersMicroservice>,
+        notifications_microservice: Arc<NotificationsMicroservice>,
+    ) -> Self {
+        let log = Rc::new(SagaLog::new(saga_store));
+        let carts_cleanup = if config.features.unavailable_cart_products_notifications {
+            CartsCleanup::default().with_notifier(CustomersNotifier {
+                users_microservice,
+                notifications_microservice,
+                cluster_url: config.cluster.url.clone(),
+            })
+        } else {
+            CartsCleanup::default()
+        };
+        Self {
+            config,
+            orders_microservice,
+            stores_microservice,
+            delivery_microservice,
+            log,
+            carts_cleanup,
+        }
+    }
+
+    fn change_category_happy(
+        self,
+        base_product_id: BaseProductId,
+        input: ChangeCategoryInput,
+    ) -> impl Future<Item = (Self, CartsCleanupResult<BaseProduct>), Error = (Self, FailureError)> {
+        self.log.start(SagaType::ChangeCategory);
+        self.log.set_input(&input);
+        let category_id = input.category_id;
+
+        self.lock_base_product(base_product_id)
+            .and_then(move |s| s.get_base_product(base_product_id, input.version))
+            .and_then(move |(s, old_category_id)| {
+                if old_category_id == category_id {
+                    let e =
+                        Error::Validate(validation_errors!({"category_id": ["category_id" => "Base product is already in the category"]}));
+                    return Either::A(future::err((s, e.into())));
+                }
+                Either::B(
+                    s.update_category(base_product_id, old_category_id, category_id)
+                        .and_then(move |(s, base_product)| {
+                            s.recalculate_shipping(base_product_id, old_category_id, category_id)
+                                .map(move |s| (s, base_product))
+                        }),
+                )
+            })
+            .and_then(move |(s, base_product)| s.cleanup_carts(base_product))
+    }
+
+    fn lock_base_product(self, base_product_id: BaseProductId) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let lease = Duration::from_secs(self.config.saga.reaper_stale_after_s);
+        self.log
+            .lock(LockedEntity::BaseProduct(base_product_id), lease)
+            .then(|res| match res {
+                Ok(()) => Ok(self),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    /// Category the base product is in before the change
+    fn get_base_product(
+        self,
+        base_product_id: BaseProductId,
+        expected_version: Option<EntityVersion>,
+    ) -> impl Future<Item = (Self, CategoryId), Error = (Self, FailureError)> {
+        self.stores_microservice
+            .get_base_product(base_product_id, Visibility::Active)
+            .and_then(move |base_product| match base_product {
+                Some(base_product) => check_version(
+                    expected_version,
+                    base_product.updated_at,
+                    LockedEntity::BaseProduct(base_product_id),
+                )
+                .map(|_| base_product.category_id),
+                None => Err(format_err!("Base product {} not found", base_product_id)
+                    .context(Error::NotFound)
+                    .into()),
+            })
+            .then(|res| match res {
+                Ok(category_id) => Ok((self, category_id)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    /// Category is changed on behalf of the caller, so stores checks that the caller manages the store
+    fn update_category(
+        self,
+        base_product_id: BaseProductId,
+        old_category_id: CategoryId,
+        category_id: CategoryId,
+    ) -> impl Future<Item = (Self, BaseProduct), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        log.push(ChangeCategoryOperationStage::CategoryUpdateStart(base_product_id, old_category_id));
+
+        let payload = UpdateBaseProduct {
+            category_id: Some(category_id),
+            ..Default::default()
+        };
+        self.stores_microservice
+            .update_base_product(None, base_product_id, payload)
+            .then(move |res| match res {
+                Ok(base_product) => {
+                    log.push(ChangeCategoryOperationStage::CategoryUpdateComplete(base_product_id));
+                    Ok((self, base_product))
+                }
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn recalculate_shipping(
+        self,
+        base_product_id: BaseProductId,
+        old_category_id: CategoryId,
+        category_id: CategoryId,
+    ) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        log.push(ChangeCategoryOperationStage::ShippingRecalculationStart(
+            base_product_id,
+            old_category_id,
+        ));
+
+        self.delivery_microservice
+            .recalculate_shipping(None, base_product_id, RecalculateShipping { category_id })
+            .then(move |res| match res {
+                Ok(_) => {
+                    log.push(ChangeCategoryOperationStage::ShippingRecalculationComplete(base_product_id));
+                    Ok(self)
+                }
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn cleanup_carts(
+        self,
+        base_product: BaseProduct,
+    ) -> impl Future<Item = (Self, CartsCleanupResult<BaseProduct>), Error = (Self, FailureError)> {
+        self.stores_microservice
+            .get_products_by_base_product(base_product.id)
+            .then(|res| match res {
+                Ok(products) => {
+                    self.carts_cleanup.remove_products(products.into_iter().map(|p| p.id));
+                    Ok(self)
+                }
+                Err(e) => Err((self, e)),
+            })
+            .and_then(|s| {
+                s.carts_cleanup.flush(&s.orders_microservice, base_product).then(|res| match res {
+                    Ok(result) => Ok((s, result)),
+                    Err(e) => Err((s, e)),
+                })
+            })
+    }
+
+    // Contains reversal of category change
+    pub fn change_category_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let stores_microservice = self.stores_microservice.clone();
+        let delivery_microservice = self.delivery_microservice.clone();
+
+        let compensation = self.log.compensate(move |e, _| match e {
+            ChangeCategoryOperationStage::CategoryUpdateStart(base_product_id, old_category_id) => {
+                debug!(
+                    "Reverting category of base product, base_product_id: {}, category_id: {}",
+                    base_product_id, old_category_id
+                );
+                let payload = UpdateBaseProduct {
+                    category_id: Some(old_category_id),
+                    ..Default::default()
+                };
+                Box::new(
+                    stores_microservice
+                        .update_base_product(Some(Initiator::Superadmin), base_product_id, payload)
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            ChangeCategoryOperationStage::ShippingRecalculationStart(base_product_id, old_category_id) => {
+                debug!(
+                    "Recalculating shipping of base product for previous category, base_product_id: {}, category_id: {}",
+                    base_product_id, old_category_id
+                );
+                Box::new(
+                    delivery_microservice
+                        .recalculate_shipping(
+                            Some(Initiator::Superadmin),
+                            base_product_id,
+                            RecalculateShipping {
+                                category_id: old_category_id,
+                            },
+                        )
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
+        });
+
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((
+                self,
+                format_err!("Category change service change_category_revert error occurred: {}", e),
+            )),
+        })
+    }
+}
+
+impl SagaService for CategoryChangeServiceImpl {
+    type Stage = ChangeCategoryOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.change_category_revert().map(|(s, _)| s))
+    }
+}
+
+impl CategoryChangeService for CategoryChangeServiceImpl {
+    fn change_category(
+        self,
+        base_product_id: BaseProductId,
+        input: ChangeCategoryInput,
+    ) -> ServiceFuture<Box<CategoryChangeService>, SagaResponse<CartsCleanupResult<BaseProduct>>> {
+        debug!("Change category of base product {}, input: {:?}", base_product_id, input);
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::ChangeCategory,
+            deadline,
+            move |s| s.change_category_happy(base_product_id, input),
+            &["category_id"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<CategoryChangeService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<CategoryChangeService>, e)),
+        )
+    }
+}
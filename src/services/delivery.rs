@@ -1,4 +1,6 @@
+use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
 
 use failure::Error as FailureError;
 use futures::future;
@@ -6,77 +8,169 @@ use futures::prelude::*;
 
 use stq_types::*;
 
-use super::parse_validation_errors;
+use super::carts::CartsCleanup;
 use config;
 use microservice::*;
 use models::*;
+use saga::{self, SagaLog, SagaService, SagaStore};
+use scrubbing::Scrubbed;
 use services::types::ServiceFuture;
 
 pub trait DeliveryService {
     fn upsert_shipping(self, base_product_id: BaseProductId, payload: NewShipping) -> ServiceFuture<Box<DeliveryService>, Shipping>;
+    /// Shipping methods of cart items in a single call, no saga is started
+    fn quote(self, input: DeliveryQuoteInput) -> ServiceFuture<Box<DeliveryService>, DeliveryQuote>;
 }
 
+#[derive(Clone)]
 pub struct DeliveryServiceImpl {
     pub orders_microservice: Arc<OrdersMicroservice>,
     pub delivery_microservice: Arc<DeliveryMicroservice>,
     pub stores_microservice: Arc<StoresMicroservice>,
     pub config: config::Config,
+    pub log: Rc<SagaLog<UpsertShippingOperationStage>>,
+    pub carts_cleanup: CartsCleanup,
 }
 
 impl DeliveryServiceImpl {
     pub fn new(
         config: config::Config,
+        saga_store: Arc<SagaStore>,
         orders_microservice: Arc<OrdersMicroservice>,
         delivery_microservice: Arc<DeliveryMicroservice>,
         stores_microservice: Arc<StoresMicroservice>,
     ) -> Self {
+        let log = Rc::new(SagaLog::new(saga_store));
         Self {
             config,
             orders_microservice,
             delivery_microservice,
             stores_microservice,
+            log,
+            carts_cleanup: CartsCleanup::default(),
         }
     }
 
+    fn upsert_shipping_happy(
+        self,
+        base_product_id: BaseProductId,
+        payload: NewShipping,
+    ) -> impl Future<Item = (Self, Shipping), Error = (Self, FailureError)> {
+        self.log.start(SagaType::UpsertShipping);
+        let log = self.log.clone();
+        log.push(UpsertShippingOperationStage::ShippingUpsertStart(base_product_id));
+
+        self.delivery_microservice
+            .upsert_shipping(None, base_product_id, payload)
+            .and_then(move |res| {
+                log.push_with_result(UpsertShippingOperationStage::ShippingUpsertComplete(base_product_id), &res);
+                Ok(res)
+            })
+            .then(|res| match res {
+                Ok(shipping) => Ok((self, shipping)),
+                Err(e) => Err((self, e)),
+            })
+            .and_then(move |(s, shipping)| {
+                s.remove_products_from_cart_after_shipping_change(base_product_id)
+                    .map(|(s, _)| (s, shipping))
+            })
+    }
+
+    // Contains reversal of shipping upsert. Previous shipping is not known to saga coordinator,
+    // so shipping of base product is dropped the same way it is done on base product currency change
+    pub fn upsert_shipping_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let delivery_microservice = self.delivery_microservice.clone();
+
+        let compensation = self.log.compensate(move |e, _| match e {
+            UpsertShippingOperationStage::ShippingUpsertStart(base_product_id) => {
+                debug!("Reverting shipping, base_product_id: {}", base_product_id);
+                Box::new(delivery_microservice.delete_shipping_by_base_product(Some(Initiator::Superadmin), base_product_id))
+                    as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
+        });
+
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((self, format_err!("Delivery service upsert_shipping_revert error occurred: {}", e))),
+        })
+    }
+
     fn remove_products_from_cart_after_shipping_change(
         self,
         base_product_id: BaseProductId,
     ) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
-        let stores_microservice = self.stores_microservice.clone();
         let orders_microservice = self.orders_microservice.clone();
-        let fut = stores_microservice
+        let carts_cleanup = self.carts_cleanup.clone();
+        let fut = self
+            .stores_microservice
             .get_products_by_base_product(base_product_id)
-            .map(|products| DeleteDeliveryMethodFromCartsPayload {
-                product_ids: products.into_iter().map(|p| p.id).collect(),
-            })
-            .and_then(move |payload| orders_microservice.delete_delivery_method_from_all_carts(Some(Initiator::Superadmin), payload));
-
-        let res = Box::new(fut);
+            .and_then(move |products| {
+                carts_cleanup.clear_delivery_methods(products.into_iter().map(|p| p.id));
+                carts_cleanup.flush(&orders_microservice, ())
+            });
 
-        res.then(|res| match res {
+        fut.then(|res| match res {
             Ok(_) => Ok((self, ())),
             Err(err) => Err((self, err)),
         })
     }
 }
 
+impl SagaService for DeliveryServiceImpl {
+    type Stage = UpsertShippingOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.upsert_shipping_revert().map(|(s, _)| s))
+    }
+}
+
 impl DeliveryService for DeliveryServiceImpl {
     fn upsert_shipping(self, base_product_id: BaseProductId, payload: NewShipping) -> ServiceFuture<Box<DeliveryService>, Shipping> {
-        debug!("Update shipping, input: {:?} for base product: {:?}", payload, base_product_id);
+        debug!(
+            "Update shipping, input: {:?} for base product: {:?}",
+            Scrubbed(&payload),
+            base_product_id
+        );
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
 
-        let res = self
-            .delivery_microservice
-            .upsert_shipping(None, base_product_id, payload)
-            .then(|res| match res {
-                Ok(shipping) => Ok((self, shipping)),
-                Err(e) => Err((self, e)),
-            })
-            .and_then(move |(s, shipping)| {
-                s.remove_products_from_cart_after_shipping_change(base_product_id)
-                    .map(|(s, _)| (s, shipping))
-            })
-            .map(|(s, shipping)| (Box::new(s) as Box<DeliveryService>, shipping))
-            .or_else(|(s, e)| future::err((Box::new(s) as Box<DeliveryService>, parse_validation_errors(e, &["shipping"]))));
+        let res = saga::run(
+            self,
+            SagaType::UpsertShipping,
+            deadline,
+            move |s| s.upsert_shipping_happy(base_product_id, payload),
+            &["shipping"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<DeliveryService>, response.result))
+                .map_err(|(s, e)| (Box::new(s) as Box<DeliveryService>, e)),
+        )
+    }
+
+    fn quote(self, input: DeliveryQuoteInput) -> ServiceFuture<Box<DeliveryService>, DeliveryQuote> {
+        debug!("Quote delivery, input: {:?}", Scrubbed(&input));
+        let query = ShippingRatesQuery {
+            delivery_to: input.delivery_to.clone(),
+            items: input
+                .items
+                .iter()
+                .map(|item| ShippingRatesQueryItem {
+                    base_product_id: item.base_product_id,
+                    quantity: item.quantity,
+                })
+                .collect(),
+        };
+
+        let res = self.delivery_microservice.query_rates(None, query).then(move |res| match res {
+            Ok(rates) => Ok((Box::new(self) as Box<DeliveryService>, DeliveryQuote::new(input, rates))),
+            Err(e) => Err((Box::new(self) as Box<DeliveryService>, e)),
+        });
 
         Box::new(res)
     }
@@ -0,0 +1,507 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use futures::future::{self, Either};
+use futures::prelude::*;
+
+use stq_api::orders::Order;
+use stq_static_resources::{CommitterRole, EmailUser, OrderState, OrderUpdateStateForStore, OrderUpdateStateForUser};
+use stq_types::{OrderId, OrderIdentifier, SagaId};
+
+use super::initiator::InitiatorPolicy;
+use config;
+use errors::Error;
+use microservice::*;
+use models::*;
+use saga::{self, soft_step, SagaLog, SagaService, SagaStore};
+use scrubbing::Scrubbed;
+use services::types::ServiceFuture;
+
+pub trait DisputeService {
+    /// Registers dispute of the order in billing and freezes the order until the dispute is resolved
+    fn open_dispute(self, order_id: OrderId, input: DisputeInput) -> ServiceFuture<Box<DisputeService>, SagaResponse<Dispute>>;
+    /// Resolves dispute in favour of customer or seller, returns stock to warehouse on refund
+    fn resolve_dispute(self, order_id: OrderId, input: DisputeResolveInput) -> ServiceFuture<Box<DisputeService>, SagaResponse<Order>>;
+}
+
+#[derive(Clone)]
+pub struct DisputeServiceImpl {
+    pub orders_microservice: Arc<OrdersMicroservice>,
+    pub billing_microservice: Arc<BillingMicroservice>,
+    pub warehouses_microservice: Arc<WarehousesMicroservice>,
+    pub stores_microservice: Arc<StoresMicroservice>,
+    pub users_microservice: Arc<UsersMicroservice>,
+    pub notifications_microservice: Arc<NotificationsMicroservice>,
+    pub config: config::Config,
+    pub log: Rc<SagaLog<DisputeOperationStage>>,
+    pub initiators: InitiatorPolicy,
+}
+
+impl DisputeServiceImpl {
+    pub fn new(
+        config: config::Config,
+        saga_store: Arc<SagaStore>,
+        orders_microservice: Arc<OrdersMicroservice>,
+        billing_microservice: Arc<BillingMicroservice>,
+        warehouses_microservice: Arc<WarehousesMicroservice>,
+        stores_microservice: Arc<StoresMicroservice>,
+        users_microservice: Arc<UsersMicroservice>,
+        notifications_microservice: Arc<NotificationsMicroservice>,
+    ) -> Self {
+        let log = Rc::new(SagaLog::new(saga_store));
+        Self {
+            config,
+            orders_microservice,
+            billing_microservice,
+            warehouses_microservice,
+            stores_microservice,
+            users_microservice,
+            notifications_microservice,
+            log,
+            initiators: InitiatorPolicy::default(),
+        }
+    }
+
+    /// Performs steps of sagas on behalf of the caller
+    pub fn with_caller(mut self, caller: Option<Initiator>) -> Self {
+        self.initiators = InitiatorPolicy::new(caller);
+        self
+    }
+
+    fn open_dispute_happy(
+        self,
+        order_id: OrderId,
+        input: DisputeInput,
+    ) -> impl Future<Item = (Self, Dispute), Error = (Self, FailureError)> {
+        self.log.start(SagaType::OpenDispute);
+        let DisputeInput { reason } = input;
+
+        self.get_order(order_id)
+            .and_then(move |(s, order)| match order.state {
+                OrderState::Paid
+                | OrderState::InProcessing
+                | OrderState::Sent
+                | OrderState::Delivered
+                | OrderState::Received
+                | OrderState::Complete => Ok((s, order)),
+                state => {
+                    debug!("Order {} in state {} can not be disputed", order_id, state);
+                    Err((
+                        s,
+                        Error::Validate(validation_errors!({"order": ["order_state" => "Order in this state can not be disputed"]})).into(),
+                    ))
+                }
+            })
+            .and_then(|(s, order)| s.committer_role(order))
+            .and_then(move |(s, (order, committer_role))| {
+                s.register_dispute(order_id, reason.clone()).and_then(move |(s, dispute)| {
+                    s.freeze_order(order, reason, committer_role).and_then(move |(s, order)| {
+                        soft_step(s.log.clone(), "dispute_notification", s.notify_parties(order)).map(move |s| (s, dispute))
+                    })
+                })
+            })
+    }
+
+    fn resolve_dispute_happy(
+        self,
+        order_id: OrderId,
+        input: DisputeResolveInput,
+    ) -> impl Future<Item = (Self, Order), Error = (Self, FailureError)> {
+        self.log.start(SagaType::ResolveDispute);
+        let DisputeResolveInput { resolution, comment } = input;
+
+        self.get_order(order_id)
+            .and_then(move |(s, order)| {
+                if order.state == OrderState::Dispute {
+                    Ok((s, order))
+                } else {
+                    Err((
+                        s,
+                        Error::Validate(validation_errors!({"order": ["order_state" => "Order is not disputed"]})).into(),
+                    ))
+                }
+            })
+            .and_then(|(s, order)| s.committer_role(order))
+            .and_then(move |(s, (order, committer_role))| {
+                let stock = match resolution {
+                    DisputeResolution::RefundCustomer => Either::A(s.return_stock(order)),
+                    DisputeResolution::PaySeller => Either::B(future::ok((s, ()))),
+                };
+                stock.map(move |(s, _)| (s, committer_role))
+            })
+            .and_then(move |(s, committer_role)| {
+                s.unfreeze_order(order_id, resolution.order_state(), comment, committer_role)
+                    .and_then(move |(s, order)| s.resolve_in_billing(order_id, resolution).map(|(s, _)| (s, order)))
+                    .and_then(|(s, order)| {
+                        soft_step(s.log.clone(), "dispute_notification", s.notify_parties(order.clone())).map(move |s| (s, order))
+                    })
+            })
+    }
+
+    fn get_order(self, order_id: OrderId) -> impl Future<Item = (Self, Order), Error = (Self, FailureError)> {
+        self.orders_microservice
+            .get_order(None, OrderIdentifier::Id(order_id))
+            .and_then(move |order| {
+                order
+                    .ok_or_else(|| format_err!("Order {} not found", order_id).context(Error::NotFound).into())
+                    .into_future()
+            })
+            .then(|res| match res {
+                Ok(order) => Ok((self, order)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    /// Role the caller commits state of the order with
+    fn committer_role(self, order: Order) -> impl Future<Item = (Self, (Order, CommitterRole)), Error = (Self, FailureError)> {
+        self.initiators
+            .order_committer_role(&self.stores_microservice, &order)
+            .then(|res| match res {
+                Ok(committer_role) => Ok((self, (order, committer_role))),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn register_dispute(self, order_id: OrderId, reason: String) -> impl Future<Item = (Self, Dispute), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let saga_id = self.log.saga_id();
+        let initiator = self.initiators.superadmin(
+            &log,
+            "billing_dispute_registration",
+            "disputes are registered only by billing administrators",
+        );
+        log.push(DisputeOperationStage::DisputeRegistrationStart(saga_id));
+
+        let payload = NewDispute { order_id, saga_id, reason };
+        self.billing_microservice
+            .create_dispute(initiator, payload)
+            .and_then(move |dispute| {
+                log.push_with_result(DisputeOperationStage::DisputeRegistrationComplete(saga_id), &dispute);
+                Ok(dispute)
+            })
+            .then(|res| match res {
+                Ok(dispute) => Ok((self, dispute)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn freeze_order(
+        self,
+        order: Order,
+        reason: String,
+        committer_role: CommitterRole,
+    ) -> impl Future<Item = (Self, Order), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let order_id = order.id;
+        let initiator = match self.initiators.caller("orders_freeze") {
+            Ok(initiator) => initiator,
+            Err(e) => return Either::A(future::err((self, e))),
+        };
+        log.push(DisputeOperationStage::OrderFreezeStart(order_id, order.state));
+
+        let payload = UpdateStatePayload {
+            state: OrderState::Dispute,
+            track_id: None,
+            comment: Some(reason),
+            committer_role,
+        };
+        Either::B(
+            self.set_order_state(Some(initiator), order_id, payload)
+                .and_then(move |order| {
+                    log.push_with_result(DisputeOperationStage::OrderFreezeComplete(order_id), &order);
+                    Ok(order)
+                })
+                .then(|res| match res {
+                    Ok(order) => Ok((self, order)),
+                    Err(e) => Err((self, e)),
+                }),
+        )
+    }
+
+    fn unfreeze_order(
+        self,
+        order_id: OrderId,
+        state: OrderState,
+        comment: Option<String>,
+        committer_role: CommitterRole,
+    ) -> impl Future<Item = (Self, Order), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let initiator = match self.initiators.caller("orders_unfreeze") {
+            Ok(initiator) => initiator,
+            Err(e) => return Either::A(future::err((self, e))),
+        };
+        log.push(DisputeOperationStage::OrderUnfreezeStart(order_id));
+
+        let payload = UpdateStatePayload {
+            state,
+            track_id: None,
+            comment,
+            committer_role,
+        };
+        Either::B(
+            self.set_order_state(Some(initiator), order_id, payload)
+                .and_then(move |order| {
+                    log.push_with_result(DisputeOperationStage::OrderUnfreezeComplete(order_id), &order);
+                    Ok(order)
+                })
+                .then(|res| match res {
+                    Ok(order) => Ok((self, order)),
+                    Err(e) => Err((self, e)),
+                }),
+        )
+    }
+
+    fn set_order_state(
+        &self,
+        initiator: Option<Initiator>,
+        order_id: OrderId,
+        payload: UpdateStatePayload,
+    ) -> impl Future<Item = Order, Error = FailureError> {
+        self.orders_microservice
+            .set_order_state(initiator, OrderIdentifier::Id(order_id), payload)
+            .and_then(move |order| {
+                order
+                    .ok_or_else(|| format_err!("Order {} not found", order_id).context(Error::NotFound).into())
+                    .into_future()
+            })
+    }
+
+    fn return_stock(self, order: Order) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let order_id = order.id;
+        let initiator = self.initiators.superadmin(
+            &log,
+            "warehouses_stock_return",
+            "stocks are adjusted only by warehouses administrators",
+        );
+        log.push(DisputeOperationStage::StockReturnStart(order_id));
+
+        let adjustment = StockAdjustment {
+            saga_id: self.log.saga_id(),
+            kind: StockAdjustmentKind::Return,
+            quantity: order.quantity,
+        };
+        self.warehouses_microservice
+            .adjust_stock(initiator, order.product, adjustment)
+            .and_then(move |_| {
+                log.push(DisputeOperationStage::StockReturnComplete(order_id));
+                Ok(())
+            })
+            .then(|res| match res {
+                Ok(_) => Ok((self, ())),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn resolve_in_billing(
+        self,
+        order_id: OrderId,
+        resolution: DisputeResolution,
+    ) -> impl Future<Item = (Self, Dispute), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let initiator = self.initiators.superadmin(
+            &log,
+            "billing_dispute_resolution",
+            "disputes are resolved only by billing administrators",
+        );
+        log.push(DisputeOperationStage::DisputeResolutionStart(order_id));
+
+        self.billing_microservice
+            .resolve_dispute(initiator, order_id, ResolveDisputePayload { resolution })
+            .and_then(move |dispute| {
+                log.push_with_result(DisputeOperationStage::DisputeResolutionComplete(order_id), &dispute);
+                Ok(dispute)
+            })
+            .then(|res| match res {
+                Ok(dispute) => Ok((self, dispute)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    /// Tells both customer and store about the new state of disputed order
+    fn notify_parties(self, order: Order) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let cluster_url = self.config.cluster.url.clone();
+        let notifications_microservice = self.notifications_microservice.clone();
+        let order_slug = order.slug.to_string();
+        let order_state = order.state.to_string();
+
+        let to_customer = {
+            let notifications_microservice = notifications_microservice.clone();
+            let cluster_url = cluster_url.clone();
+            let order_slug = order_slug.clone();
+            let order_state = order_state.clone();
+            let customer_id = order.customer;
+            self.users_microservice
+                .get(Some(Initiator::Superadmin), customer_id)
+                .and_then(move |user| {
+                    user.ok_or_else(|| format_err!("Customer {} not found", customer_id).context(Error::NotFound).into())
+                        .into_future()
+                })
+                .and_then(move |user| {
+                    let email = OrderUpdateStateForUser {
+                        user: EmailUser {
+                            email: user.email.clone(),
+                            first_name: user.first_name.unwrap_or_else(|| "user".to_string()),
+                            last_name: user.last_name.unwrap_or_else(|| "".to_string()),
+                        },
+                        order_slug,
+                        order_state,
+                        cluster_url,
+                    };
+                    notifications_microservice.order_update_state_for_user(Initiator::Superadmin, email)
+                })
+        };
+
+        let store_id = order.store;
+        let to_store =
+            self.stores_microservice
+                .get(store_id, Visibility::Active)
+                .and_then(move |store| match store.and_then(|store| store.email) {
+                    Some(store_email) => {
+                        let email = OrderUpdateStateForStore {
+                            store_email,
+                            store_id: store_id.to_string(),
+                            order_slug,
+                            order_state,
+                            cluster_url,
+                        };
+                        Either::A(notifications_microservice.order_update_state_for_store(Initiator::Superadmin, email))
+                    }
+                    None => Either::B(future::ok(())),
+                });
+
+        // both notifications are sent even if the first one fails
+        to_customer
+            .then(|customer_res| to_store.then(|store_res| customer_res.and(store_res)))
+            .then(|res| match res {
+                Ok(_) => Ok((self, ())),
+                Err(e) => Err((self, FailureError::from(e.context("Notifying parties of dispute failed.")))),
+            })
+    }
+
+    // Contains reversal of both opening and resolving dispute. Resolution in billing is the last step
+    // and is never compensated, everything before it is rolled back to the disputed state
+    pub fn dispute_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let billing_microservice = self.billing_microservice.clone();
+        let orders_microservice = self.orders_microservice.clone();
+        let warehouses_microservice = self.warehouses_microservice.clone();
+        let saga_id = self.log.saga_id();
+
+        let compensation = self.log.compensate(move |e, phase| match e {
+            DisputeOperationStage::DisputeRegistrationStart(saga_id) => revert_registration(billing_microservice.clone(), saga_id),
+
+            DisputeOperationStage::OrderFreezeStart(order_id, previous_state) => {
+                debug!("Reverting dispute freeze of order {} to state {}", order_id, previous_state);
+                restore_order_state(orders_microservice.clone(), order_id, previous_state)
+            }
+
+            DisputeOperationStage::StockReturnStart(order_id) => {
+                debug!("Reverting stock return of order {}", order_id);
+                revert_stock_return(
+                    orders_microservice.clone(),
+                    warehouses_microservice.clone(),
+                    saga_id,
+                    order_id,
+                    phase,
+                )
+            }
+
+            DisputeOperationStage::OrderUnfreezeStart(order_id) => {
+                debug!("Reverting dispute resolution of order {}", order_id);
+                restore_order_state(orders_microservice.clone(), order_id, OrderState::Dispute)
+            }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
+        });
+
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((self, format_err!("Dispute service dispute_revert error occurred: {}", e))),
+        })
+    }
+}
+
+impl SagaService for DisputeServiceImpl {
+    type Stage = DisputeOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.dispute_revert().map(|(s, _)| s))
+    }
+}
+
+impl DisputeService for DisputeServiceImpl {
+    fn open_dispute(self, order_id: OrderId, input: DisputeInput) -> ServiceFuture<Box<DisputeService>, SagaResponse<Dispute>> {
+        debug!("Open dispute of order {}, input: {:?}", order_id, Scrubbed(&input));
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::OpenDispute,
+            deadline,
+            move |s| s.open_dispute_happy(order_id, input),
+            &["order", "reason"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<DisputeService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<DisputeService>, e)),
+        )
+    }
+
+    fn resolve_dispute(self, order_id: OrderId, input: DisputeResolveInput) -> ServiceFuture<Box<DisputeService>, SagaResponse<Order>> {
+        debug!("Resolve dispute of order {}, input: {:?}", order_id, Scrubbed(&input));
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::ResolveDispute,
+            deadline,
+            move |s| s.resolve_dispute_happy(order_id, input),
+            &["order", "reason"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<DisputeService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<DisputeService>, e)),
+        )
+    }
+}
+
+fn revert_registration(billing_microservice: Arc<BillingMicroservice>, saga_id: SagaId) -> Box<Future<Item = (), Error = FailureError>> {
+    debug!("Reverting dispute registration, saga_id: {}", saga_id);
+    Box::new(
+        billing_microservice
+            .get_dispute_by_saga_id(Initiator::Superadmin, saga_id)
+            .and_then(move |dispute| match dispute {
+                Some(_) => Either::A(billing_microservice.revert_create_dispute(Initiator::Superadmin, saga_id)),
+                None => {
+                    debug!("Dispute with saga_id {} was not registered, nothing to revert", saga_id);
+                    Either::B(future::ok(()))
+                }
+            }),
+    )
+}
+
+fn restore_order_state(
+    orders_microservice: Arc<OrdersMicroservice>,
+    order_id: OrderId,
+    state: OrderState,
+) -> Box<Future<Item = (), Error = FailureError>> {
+    let payload = UpdateStatePayload {
+        state,
+        track_id: None,
+        comment: Some("Dispute saga was reverted".to_string()),
+        committer_role: CommitterRole::Customer,
+    };
+    Box::new(
+        orders_microservice
+            .set_order_state(Some(Initiator::Superadmin), OrderIdentifier::Id(order_id), payload)
+            .map(|_| ()),
+    )
+}
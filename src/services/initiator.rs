@@ -0,0 +1,184 @@
+//! Initiators saga steps call microservices with. Steps act with rights of the caller,
+//! unless they declare that downstream service authorizes them only for superadmin.
+//! Every escalation to superadmin is recorded in saga log. Role the caller commits order
+//! changes with is derived from the caller, so that it can not be claimed by the request.
+use std::sync::Arc;
+
+use failure::Error as FailureError;
+use futures::prelude::*;
+
+use stq_api::orders::Order;
+use stq_static_resources::CommitterRole;
+use stq_types::UserId;
+
+use errors::Error;
+use microservice::{Initiator, StoresMicroservice};
+use models::{OperationStage, Visibility};
+use saga::SagaLog;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InitiatorPolicy {
+    caller: Option<Initiator>,
+}
+
+impl InitiatorPolicy {
+    pub fn new(caller: Option<Initiator>) -> Self {
+        Self { caller }
+    }
+
+    /// Id of the caller, `None` for anonymous caller or superadmin
+    pub fn caller_id(&self) -> Option<UserId> {
+        self.caller.and_then(user_id)
+    }
+
+    pub fn is_superadmin(&self) -> bool {
+        match self.caller {
+            Some(Initiator::Superadmin) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the caller acts on behalf of `owner`, superadmin acts on behalf of anyone
+    pub fn acts_for(&self, owner: UserId) -> bool {
+        self.is_superadmin() || self.caller_id() == Some(owner)
+    }
+
+    /// Role the caller commits changes of order bought by `customer` from store managed by `store_owner` with:
+    /// superadmin commits as system, manager of the store as seller and the buyer as customer.
+    /// Anyone else may not change the order
+    pub fn committer_role(&self, store_owner: Option<UserId>, customer: UserId) -> Result<CommitterRole, FailureError> {
+        match self.caller {
+            Some(Initiator::Superadmin) => Ok(CommitterRole::System),
+            Some(Initiator::User(caller)) if Some(caller) == store_owner => Ok(CommitterRole::Seller),
+            Some(Initiator::User(caller)) if caller == customer => Ok(CommitterRole::Customer),
+            Some(Initiator::User(caller)) => Err(format_err!("Caller {} neither sold nor bought the order", caller)
+                .context(Error::Forbidden)
+                .into()),
+            None => Err(format_err!("Order may not be changed by anonymous caller")
+                .context(Error::Forbidden)
+                .into()),
+        }
+    }
+
+    /// Committer role of the caller for the order, its store is looked up only for users
+    pub fn order_committer_role(
+        &self,
+        stores_microservice: &Arc<StoresMicroservice>,
+        order: &Order,
+    ) -> Box<Future<Item = CommitterRole, Error = FailureError>> {
+        let policy = *self;
+        let customer = order.customer;
+        if policy.caller_id().is_none() {
+            return Box::new(policy.committer_role(None, customer).into_future());
+        }
+        Box::new(
+            stores_microservice
+                .get(order.store, Visibility::Active)
+                .and_then(move |store| policy.committer_role(store.map(|store| store.user_id), customer)),
+        )
+    }
+
+    /// Initiator of step downstream service authorizes for the caller, anonymous caller may not perform the step
+    pub fn caller(&self, step: &str) -> Result<Initiator, FailureError> {
+        self.caller.ok_or_else(|| {
+            format_err!("Step {} may not be performed by anonymous caller", step)
+                .context(Error::Forbidden)
+                .into()
+        })
+    }
+
+    /// Initiator of step downstream service authorizes only for superadmin, for the given reason.
+    /// Escalation is recorded in saga log unless the caller is superadmin already.
+    pub fn superadmin<S: OperationStage>(&self, log: &SagaLog<S>, step: &str, reason: &'static str) -> Initiator {
+        match self.caller {
+            Some(Initiator::Superadmin) => {}
+            caller => log.escalate(step, reason, caller.and_then(user_id)),
+        }
+        Initiator::Superadmin
+    }
+}
+
+fn user_id(initiator: Initiator) -> Option<UserId> {
+    match initiator {
+        Initiator::User(user_id) => Some(user_id),
+        Initiator::Superadmin => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use stq_static_resources::CommitterRole;
+    use stq_types::UserId;
+
+    use super::InitiatorPolicy;
+    use microservice::Initiator;
+    use models::{CreateStoreOperationStage, SagaType};
+    use saga::{SagaLog, SagaStore, SagaStoreImpl};
+
+    #[test]
+    fn escalates_only_declared_steps() {
+        let store = Arc::new(SagaStoreImpl::new(None, None).unwrap());
+        let log = SagaLog::<CreateStoreOperationStage>::new(store.clone());
+        log.start(SagaType::CreateStore);
+        let caller = UserId(42);
+        let policy = InitiatorPolicy::new(Some(Initiator::User(caller)));
+
+        match policy.caller("orders_role_set").unwrap() {
+            Initiator::User(user_id) => assert_eq!(user_id, caller),
+            Initiator::Superadmin => panic!("step is escalated"),
+        }
+        match policy.superadmin(&log, "billing_create_merchant", "merchants") {
+            Initiator::Superadmin => {}
+            Initiator::User(_) => panic!("step is not escalated"),
+        }
+
+        let escalations = store.get(log.saga_id()).unwrap().unwrap().escalations;
+        assert_eq!(escalations.len(), 1);
+        assert_eq!(escalations[0].step, "billing_create_merchant");
+        assert_eq!(escalations[0].caller, Some(caller));
+    }
+
+    #[test]
+    fn acts_for_owner_only_unless_superadmin() {
+        let owner = UserId(42);
+        assert!(InitiatorPolicy::new(Some(Initiator::User(owner))).acts_for(owner));
+        assert!(InitiatorPolicy::new(Some(Initiator::Superadmin)).acts_for(owner));
+        assert!(!InitiatorPolicy::new(Some(Initiator::User(UserId(43)))).acts_for(owner));
+        assert!(!InitiatorPolicy::new(None).acts_for(owner));
+    }
+
+    #[test]
+    fn forbids_caller_steps_of_anonymous_caller() {
+        let store = Arc::new(SagaStoreImpl::new(None, None).unwrap());
+        let log = SagaLog::<CreateStoreOperationStage>::new(store.clone());
+        log.start(SagaType::CreateStore);
+        let policy = InitiatorPolicy::new(None);
+
+        assert!(policy.caller("stores_store_deactivation").is_err());
+        assert!(store.get(log.saga_id()).unwrap().unwrap().escalations.is_empty());
+    }
+
+    #[test]
+    fn derives_committer_role_from_caller() {
+        let (owner, customer) = (UserId(42), UserId(43));
+        let role = |caller| InitiatorPolicy::new(caller).committer_role(Some(owner), customer).unwrap();
+        assert_eq!(role(Some(Initiator::Superadmin)), CommitterRole::System);
+        assert_eq!(role(Some(Initiator::User(owner))), CommitterRole::Seller);
+        assert_eq!(role(Some(Initiator::User(customer))), CommitterRole::Customer);
+    }
+
+    #[test]
+    fn forbids_order_changes_of_anonymous_caller_and_strangers() {
+        let (owner, customer) = (UserId(42), UserId(43));
+        assert!(InitiatorPolicy::new(None).committer_role(Some(owner), customer).is_err());
+        assert!(InitiatorPolicy::new(Some(Initiator::User(UserId(44))))
+            .committer_role(Some(owner), customer)
+            .is_err());
+        // store of the order is gone, its manager is not known
+        assert!(InitiatorPolicy::new(Some(Initiator::User(owner)))
+            .committer_role(None, customer)
+            .is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use failure::Error as FailureError;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+
+use stq_types::*;
+
+use config;
+use microservice::*;
+use models::*;
+use scrubbing::Scrubbed;
+use services::types::ServiceFuture;
+
+/// Maximum number of concurrent requests to warehouses looking up stocks of products
+pub const STOCK_LOOKUP_CONCURRENCY: usize = 8;
+
+pub trait InventoryService {
+    /// Compares quantities of store products listed in stores with their stocks in warehouses, no saga is started
+    fn reconcile(self, input: ReconcileInventory) -> ServiceFuture<Box<InventoryService>, InventoryReport>;
+}
+
+#[derive(Clone)]
+pub struct InventoryServiceImpl {
+    pub stores_microservice: Arc<StoresMicroservice>,
+    pub warehouses_microservice: Arc<WarehousesMicroservice>,
+    pub config: config::Config,
+}
+
+impl InventoryServiceImpl {
+    pub fn new(
+        config: config::Config,
+        stores_microservice: Arc<StoresMicroservice>,
+        warehouses_microservice: Arc<WarehousesMicroservice>,
+    ) -> Self {
+        Self {
+            config,
+            stores_microservice,
+            warehouses_microservice,
+        }
+    }
+
+    fn find_discrepancies(&self, products: Vec<Product>) -> impl Future<Item = Vec<InventoryDiscrepancy>, Error = FailureError> {
+        stock_quantities(self.warehouses_microservice.clone(), products).map(|quantities| {
+            quantities
+                .into_iter()
+                .map(|(product, stock_quantity)| InventoryDiscrepancy {
+                    product_id: product.id,
+                    base_product_id: product.base_product_id,
+                    listed_quantity: product.quantity,
+                    stock_quantity,
+                    fixed: false,
+                })
+                .filter(|discrepancy| discrepancy.difference() > 0)
+                .collect()
+        })
+    }
+
+    fn fix_discrepancy(
+        &self,
+        discrepancy: InventoryDiscrepancy,
+        tolerance: u32,
+    ) -> impl Future<Item = InventoryDiscrepancy, Error = FailureError> {
+        if !discrepancy.is_within(tolerance) {
+            return Either::A(future::ok(discrepancy));
+        }
+
+        Either::B(
+            self.stores_microservice
+                .set_product_quantity(None, discrepancy.product_id, discrepancy.stock_quantity)
+                .map(move |_| InventoryDiscrepancy {
+                    fixed: true,
+                    ..discrepancy
+                }),
+        )
+    }
+
+    fn reconcile_products(&self, input: ReconcileInventory) -> impl Future<Item = InventoryReport, Error = FailureError> {
+        let s = self.clone();
+        self.stores_microservice
+            .get_products_by_store(input.store_id)
+            .and_then(move |products| {
+                let products_checked = products.len();
+                s.find_discrepancies(products).and_then(move |discrepancies| {
+                    let fixes = discrepancies
+                        .into_iter()
+                        .map(|discrepancy| {
+                            if input.fix {
+                                Either::A(s.fix_discrepancy(discrepancy, input.tolerance))
+                            } else {
+                                Either::B(future::ok(discrepancy))
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    future::join_all(fixes).map(move |mut discrepancies| {
+                        discrepancies.sort_by_key(|discrepancy| discrepancy.product_id.0);
+                        InventoryReport {
+                            store_id: input.store_id,
+                            products_checked,
+                            discrepancies,
+                        }
+                    })
+                })
+            })
+    }
+}
+
+/// Products along with sum of their stocks in all warehouses, in no particular order
+pub fn stock_quantities(
+    warehouses_microservice: Arc<WarehousesMicroservice>,
+    products: Vec<Product>,
+) -> impl Future<Item = Vec<(Product, Quantity)>, Error = FailureError> {
+    iter_ok::<_, FailureError>(products)
+        .map(move |product| {
+            warehouses_microservice
+                .find_by_product_id(Initiator::Superadmin, product.id)
+                .map(move |stocks| {
+                    let quantity = Quantity(stocks.iter().map(|stock| stock.quantity.0).sum());
+                    (product, quantity)
+                })
+        })
+        .buffer_unordered(STOCK_LOOKUP_CONCURRENCY)
+        .collect()
+}
+
+impl InventoryService for InventoryServiceImpl {
+    fn reconcile(self, input: ReconcileInventory) -> ServiceFuture<Box<InventoryService>, InventoryReport> {
+        debug!("Reconcile inventory, input: {:?}", Scrubbed(&input));
+        let res = self.reconcile_products(input).then(move |res| match res {
+            Ok(report) => Ok((Box::new(self) as Box<InventoryService>, report)),
+            Err(e) => Err((Box::new(self) as Box<InventoryService>, e)),
+        });
+
+        Box::new(res)
+    }
+}
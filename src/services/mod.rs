@@ -1,8 +1,24 @@
 pub mod account;
+pub mod cancellation;
+pub mod carts;
+pub mod catalog;
+pub mod category;
 pub mod delivery;
+pub mod dispute;
+pub mod initiator;
+pub mod inventory;
 pub mod order;
+pub mod order_return;
+pub mod payout;
+pub mod pricing;
+pub mod stock;
 pub mod store;
+pub mod support;
+pub mod takedown;
 pub mod types;
+pub mod vacation;
+pub mod variants;
+pub mod verification;
 
 use std::collections::HashMap;
 
@@ -1,5 +1,8 @@
+use std::cell::Cell;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
 
 use failure::Error as FailureError;
 use failure::Fail;
@@ -7,38 +10,58 @@ use futures::future::{self, join_all, Either};
 use futures::prelude::*;
 use futures::stream::iter_ok;
 
-use stq_api::orders::Order;
+use stq_api::orders::{AddressFull, Order};
 use stq_static_resources::{
-    CommitterRole, EmailUser, OrderCreateForStore, OrderCreateForUser, OrderState, OrderUpdateStateForStore, OrderUpdateStateForUser,
+    CommitterRole, Currency, EmailUser, OrderCreateForStore, OrderCreateForUser, OrderState, OrderUpdateStateForStore,
+    OrderUpdateStateForUser,
 };
-use stq_types::{ConversionId, CouponId, OrderId, OrderIdentifier, OrderSlug, Quantity, SagaId, StoreId, UserId};
+use stq_types::{ConversionId, CouponId, OrderId, OrderIdentifier, OrderSlug, Quantity, StoreId, UserId};
 
-use super::parse_validation_errors;
+use super::initiator::InitiatorPolicy;
+use acknowledgment::AcknowledgmentTimers;
 use config;
 use errors::Error;
+use fraud::{self, FraudScreener};
 use microservice::{
-    BillingMicroservice, Initiator, NotificationsMicroservice, OrdersMicroservice, StoresMicroservice, UsersMicroservice,
-    WarehousesMicroservice,
+    BillingMicroservice, DeliveryMicroservice, Initiator, NotificationsMicroservice, OrdersMicroservice, StoresMicroservice,
+    UsersMicroservice, WarehousesMicroservice,
 };
 use models::*;
+use saga::steps::with_step_timeout;
+use saga::{self, soft_step, SagaLog, SagaService, SagaStore};
+use scrubbing::Scrubbed;
 use services::types::ServiceFuture;
 
+/// Number of orders updated at once when states from billing are streamed
+const BILLING_ORDERS_BATCH_SIZE: usize = 100;
+
 pub trait OrderService {
-    fn create(self, input: ConvertCart) -> ServiceFuture<Box<OrderService>, Invoice>;
-    fn create_buy_now(self, input: BuyNow) -> ServiceFuture<Box<OrderService>, Invoice>;
-    fn update_state_by_billing(self, orders_info: BillingOrdersVec) -> ServiceFuture<Box<OrderService>, ()>;
+    fn create(self, input: ConvertCart) -> ServiceFuture<Box<OrderService>, SagaResponse<Invoice>>;
+    fn create_buy_now(self, input: BuyNow) -> ServiceFuture<Box<OrderService>, SagaResponse<Invoice>>;
+    /// Updates orders in batches as their states arrive from billing. Failure before anything is applied fails the call,
+    /// failure after some batches are applied is reported along with the number of applied elements
+    fn update_state_by_billing_stream(
+        self,
+        orders_info: Box<Stream<Item = BillingOrderInfo, Error = FailureError>>,
+    ) -> ServiceFuture<Box<OrderService>, BillingStreamReport>;
+    /// Applies order states received from billing once again, orders already in the received state are left as is
+    fn replay_update_state_by_billing(self, orders_info: BillingOrdersVec) -> ServiceFuture<Box<OrderService>, Vec<OrderId>>;
+    /// Committers may set only states allowed for their role in `order_state_permissions`, others are forbidden.
+    /// Role of the committer is derived from the caller and the store of the order
     fn manual_set_state(
         self,
         order_slug: OrderSlug,
         order_state: OrderState,
         track_id: Option<String>,
         comment: Option<String>,
-        committer_role: CommitterRole,
     ) -> ServiceFuture<Box<OrderService>, Option<Order>>;
     fn manual_set_payment_state(self, order_id: OrderId, payload: OrderPaymentStateRequest) -> ServiceFuture<Box<OrderService>, ()>;
+    /// Moves sent order to the state its tracking status maps to in `delivery_tracking`, other updates are ignored
+    fn apply_tracking_update(self, update: TrackingUpdate) -> ServiceFuture<Box<OrderService>, TrackingOutcome>;
 }
 
 /// Orders services, responsible for Creating orders
+#[derive(Clone)]
 pub struct OrderServiceImpl {
     pub orders_microservice: Arc<OrdersMicroservice>,
     pub stores_microservice: Arc<StoresMicroservice>,
@@ -46,21 +69,30 @@ pub struct OrderServiceImpl {
     pub users_microservice: Arc<UsersMicroservice>,
     pub billing_microservice: Arc<BillingMicroservice>,
     pub warehouses_microservice: Arc<WarehousesMicroservice>,
+    pub delivery_microservice: Arc<DeliveryMicroservice>,
+    /// Checkouts are not screened for fraud if not set
+    pub fraud_screener: Option<FraudScreener>,
+    /// Paid orders are not timed for acknowledgment by their stores if not set
+    pub acknowledgment_timers: Option<Arc<AcknowledgmentTimers>>,
     pub config: config::Config,
-    pub log: Arc<Mutex<CreateOrderOperationLog>>,
+    pub log: Rc<SagaLog<CreateOrderOperationStage>>,
+    pub initiators: InitiatorPolicy,
 }
 
 impl OrderServiceImpl {
     pub fn new(
         config: config::Config,
+        saga_store: Arc<SagaStore>,
         orders_microservice: Arc<OrdersMicroservice>,
         stores_microservice: Arc<StoresMicroservice>,
         notifications_microservice: Arc<NotificationsMicroservice>,
         users_microservice: Arc<UsersMicroservice>,
         billing_microservice: Arc<BillingMicroservice>,
         warehouses_microservice: Arc<WarehousesMicroservice>,
+        delivery_microservice: Arc<DeliveryMicroservice>,
+        fraud_screener: Option<FraudScreener>,
     ) -> Self {
-        let log = Arc::new(Mutex::new(CreateOrderOperationLog::new()));
+        let log = Rc::new(SagaLog::new(saga_store));
         Self {
             config,
             log,
@@ -70,25 +102,62 @@ impl OrderServiceImpl {
             users_microservice,
             billing_microservice,
             warehouses_microservice,
+            delivery_microservice,
+            fraud_screener,
+            acknowledgment_timers: None,
+            initiators: InitiatorPolicy::default(),
+        }
+    }
+
+    /// Performs steps of sagas on behalf of the caller
+    pub fn with_caller(mut self, caller: Option<Initiator>) -> Self {
+        self.initiators = InitiatorPolicy::new(caller);
+        self
+    }
+
+    pub fn with_acknowledgment_timers(mut self, acknowledgment_timers: Arc<AcknowledgmentTimers>) -> Self {
+        self.acknowledgment_timers = Some(acknowledgment_timers);
+        self
+    }
+
+    /// Fails call of step once its timeout in `saga.definitions.orders` expires
+    fn with_step_timeout<T, F>(&self, step: &str, fut: F) -> Box<Future<Item = T, Error = FailureError>>
+    where
+        T: 'static,
+        F: Future<Item = T, Error = FailureError> + 'static,
+    {
+        with_step_timeout(step, self.config.saga.definitions.orders.timeout(step), fut)
+    }
+
+    /// Starts acknowledgment timers of paid orders and stops them once orders move on
+    fn track_acknowledgment(&self, orders: &[Option<Order>]) {
+        let timers = match self.acknowledgment_timers {
+            Some(ref timers) => timers,
+            None => return,
+        };
+        for order in orders.iter().filter_map(Option::as_ref) {
+            let res = match order.state {
+                OrderState::Paid => timers.start(PendingAcknowledgment::new(order.id, order.slug, order.store)),
+                _ => timers.stop(order.id),
+            };
+            if let Err(e) = res {
+                error!("Could not update acknowledgment timer of order {}: {}", order.slug, e);
+            }
         }
     }
 
     fn convert_cart(self, input: ConvertCart) -> impl Future<Item = (Self, Vec<Order>), Error = (Self, FailureError)> {
         // Create Order
-        debug!("Converting cart, input: {:?}", input);
+        debug!("Converting cart, input: {:?}", Scrubbed(&input));
         let convert_cart: ConvertCartWithConversionId = input.into();
         let conversion_id = convert_cart.conversion_id;
         let log = self.log.clone();
-        log.lock()
-            .unwrap()
-            .push(CreateOrderOperationStage::OrdersConvertCartStart(conversion_id));
+        log.push(CreateOrderOperationStage::OrdersConvertCartStart(conversion_id));
 
         self.orders_microservice
             .convert_cart(convert_cart.into())
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateOrderOperationStage::OrdersConvertCartComplete(conversion_id));
+                log.push_with_result(CreateOrderOperationStage::OrdersConvertCartComplete(conversion_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -133,20 +202,16 @@ impl OrderServiceImpl {
 
     fn buy_now(self, input: BuyNow) -> impl Future<Item = (Self, Vec<Order>), Error = (Self, FailureError)> {
         // Create Order
-        debug!("Create order from buy_now input: {:?}", input);
+        debug!("Create order from buy_now input: {:?}", Scrubbed(&input));
         let conversion_id = ConversionId::new();
 
         let log = self.log.clone();
-        log.lock()
-            .unwrap()
-            .push(CreateOrderOperationStage::OrdersConvertCartStart(conversion_id));
+        log.push(CreateOrderOperationStage::OrdersConvertCartStart(conversion_id));
 
         self.orders_microservice
             .create_buy_now(input, Some(conversion_id))
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateOrderOperationStage::OrdersConvertCartComplete(conversion_id));
+                log.push_with_result(CreateOrderOperationStage::OrdersConvertCartComplete(conversion_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -155,22 +220,217 @@ impl OrderServiceImpl {
             })
     }
 
+    fn label_sender(&self) -> LabelSender {
+        LabelSender {
+            enabled: self.config.features.delivery_labels,
+            cluster_url: self.config.cluster.url.clone(),
+            delivery_microservice: self.delivery_microservice.clone(),
+            stores_microservice: self.stores_microservice.clone(),
+            notifications_microservice: self.notifications_microservice.clone(),
+        }
+    }
+
+    /// Replaces delivery address with the one normalized by delivery, undeliverable addresses fail the saga
+    /// before anything is changed downstream
+    fn normalize_address(self, address: AddressFull) -> impl Future<Item = (Self, AddressFull), Error = (Self, FailureError)> {
+        if !self.config.features.address_normalization {
+            return Either::A(future::ok((self, address)));
+        }
+
+        debug!("Normalizing delivery address: {:?}", Scrubbed(&address));
+        Either::B(
+            self.delivery_microservice
+                .normalize_address(Some(Initiator::Superadmin), address)
+                .then(|res| match res {
+                    Ok(address) => Ok((self, address)),
+                    Err(e) => Err((self, e)),
+                }),
+        )
+    }
+
+    /// Holds gift cards of the checkout in billing, nothing is reserved if checkout has no gift cards
+    fn reserve_gift_cards(
+        self,
+        input: &ConvertCart,
+    ) -> impl Future<Item = (Self, Option<GiftCardReservation>), Error = (Self, FailureError)> {
+        if input.gift_card_codes.is_empty() {
+            return Either::A(future::ok((self, None)));
+        }
+
+        debug!("Reserving gift cards, codes: {:?}", input.gift_card_codes);
+        let log = self.log.clone();
+        let saga_id = self.log.saga_id();
+        let initiator = self.initiators.superadmin(
+            &log,
+            "billing_reserve_gift_cards",
+            "gift cards are reserved only by billing administrators",
+        );
+        log.push(CreateOrderOperationStage::BillingReserveGiftCardsStart(saga_id));
+
+        let payload = ReserveGiftCards {
+            codes: input.gift_card_codes.clone(),
+            customer_id: input.customer_id,
+            currency: input.currency,
+            saga_id,
+        };
+
+        let reserve = self.billing_microservice.reserve_gift_cards(initiator, payload);
+        Either::B(
+            self.with_step_timeout("billing_reserve_gift_cards", reserve)
+                .and_then(move |res| {
+                    log.push_with_result(CreateOrderOperationStage::BillingReserveGiftCardsComplete(saga_id), &res);
+                    Ok(res)
+                })
+                .then(|res| match res {
+                    Ok(reservation) => Ok((self, Some(reservation))),
+                    Err(e) => Err((self, e)),
+                }),
+        )
+    }
+
+    /// Computes tax lines of converted orders in billing, skipped unless tax calculation is enabled
+    fn calculate_taxes(
+        self,
+        input: &ConvertCart,
+        orders: &[Order],
+    ) -> impl Future<Item = (Self, Option<Vec<OrderTaxes>>), Error = (Self, FailureError)> {
+        if !self.config.features.tax_calculation {
+            return Either::A(future::ok((self, None)));
+        }
+
+        let log = self.log.clone();
+        let saga_id = self.log.saga_id();
+        let initiator = self.initiators.superadmin(
+            &log,
+            "billing_calculate_taxes",
+            "taxes are calculated only by billing administrators",
+        );
+        log.push(CreateOrderOperationStage::BillingCalculateTaxesStart(saga_id));
+
+        let payload = CalculateTaxes {
+            customer_id: input.customer_id,
+            currency: input.currency,
+            address: input.address.clone(),
+            orders: orders.to_vec(),
+        };
+
+        let calculate = self.billing_microservice.calculate_taxes(initiator, payload);
+        Either::B(
+            self.with_step_timeout("billing_calculate_taxes", calculate)
+                .and_then(move |res| {
+                    log.push_with_result(CreateOrderOperationStage::BillingCalculateTaxesComplete(saga_id), &res);
+                    Ok(res)
+                })
+                .then(|res| match res {
+                    Ok(taxes) => Ok((self, Some(taxes))),
+                    Err(e) => Err((self, e)),
+                }),
+        )
+    }
+
+    /// Scores checkout in fraud scoring service, blocked checkouts fail the saga before invoice is created.
+    /// Admin override of the customer takes precedence over the score.
+    fn screen_fraud(
+        self,
+        customer_id: UserId,
+        currency: Currency,
+        orders: &[Order],
+    ) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let screener = match self.fraud_screener.clone() {
+            Some(screener) => screener,
+            None => return Either::A(future::ok((self, ()))),
+        };
+
+        let log = self.log.clone();
+        let saga_id = self.log.saga_id();
+        log.push(CreateOrderOperationStage::FraudScreeningStart(saga_id));
+
+        let payload = FraudScoreRequest {
+            saga_id,
+            customer_id,
+            currency,
+            orders: orders.to_vec(),
+        };
+        let FraudScreener {
+            config,
+            scoring,
+            overrides,
+        } = screener;
+
+        let fut = overrides
+            .get(customer_id)
+            .into_future()
+            .and_then(move |customer_override| {
+                scoring.score(payload).then(move |res| {
+                    let score = match res {
+                        Ok(score) => Some(score),
+                        Err(ref e) if config.fail_open => {
+                            warn!("Fraud scoring of checkout failed, checkout proceeds unscored: {}", e);
+                            None
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    let (decision, overridden) = match customer_override {
+                        Some(customer_override) => (customer_override.decision.into(), true),
+                        None => (
+                            score
+                                .as_ref()
+                                .map(|score| fraud::decide(&config, score.score))
+                                .unwrap_or(FraudDecision::Pass),
+                            false,
+                        ),
+                    };
+                    Ok(FraudCheck {
+                        score,
+                        decision,
+                        overridden,
+                    })
+                })
+            })
+            .and_then(move |check| {
+                log.push_with_result(CreateOrderOperationStage::FraudScreeningComplete(saga_id), &check);
+                match check.decision {
+                    FraudDecision::Pass => Ok(()),
+                    FraudDecision::Flag => {
+                        warn!(
+                            "Checkout of customer {} is flagged by fraud screening, saga_id: {}",
+                            customer_id, saga_id
+                        );
+                        Ok(())
+                    }
+                    FraudDecision::Block => {
+                        debug!(
+                            "Checkout of customer {} is blocked by fraud screening, saga_id: {}",
+                            customer_id, saga_id
+                        );
+                        Err(Error::Validate(validation_errors!({"fraud": ["blocked" => "Checkout was blocked by fraud screening"]})).into())
+                    }
+                }
+            });
+
+        Either::B(fut.then(|res| match res {
+            Ok(_) => Ok((self, ())),
+            Err(e) => Err((self, e)),
+        }))
+    }
+
     fn create_invoice(self, input: &CreateInvoice) -> impl Future<Item = (Self, Invoice), Error = (Self, FailureError)> {
         // Create invoice
-        debug!("Creating invoice, input: {}", input);
+        debug!("Creating invoice, input: {}", Scrubbed(&input));
         let log = self.log.clone();
 
         let saga_id = input.saga_id;
-        log.lock()
-            .unwrap()
-            .push(CreateOrderOperationStage::BillingCreateInvoiceStart(saga_id));
+        let initiator = self.initiators.superadmin(
+            &log,
+            "billing_create_invoice",
+            "invoices are created only by billing administrators",
+        );
+        log.push(CreateOrderOperationStage::BillingCreateInvoiceStart(saga_id));
 
-        self.billing_microservice
-            .create_invoice(Initiator::Superadmin, input.clone())
+        let create = self.billing_microservice.create_invoice(initiator, input.clone());
+        self.with_step_timeout("billing_create_invoice", create)
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateOrderOperationStage::BillingCreateInvoiceComplete(saga_id));
+                log.push_with_result(CreateOrderOperationStage::BillingCreateInvoiceComplete(saga_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -179,11 +439,55 @@ impl OrderServiceImpl {
             })
     }
 
-    fn notify_user_create_order(&self, user_id: UserId, order_slug: OrderSlug) -> impl Future<Item = (), Error = FailureError> {
+    /// Requests receipt of the invoice in billing if receipts are configured, the receipt is linked in order created email
+    /// once the order is paid. Billing client retries the request keyed by the invoice on transient failures.
+    fn request_receipt(self, invoice: &Invoice) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let format = match self.config.receipts {
+            Some(ref receipts) => receipts.format,
+            None => return Either::A(future::ok(self)),
+        };
+        let log = self.log.clone();
+        let fut = self
+            .billing_microservice
+            .create_invoice_receipt(Initiator::Superadmin, invoice.invoice_id, NewReceipt { format })
+            .then(|res| match res {
+                Ok(receipt) => Ok((self, receipt)),
+                Err(e) => Err((self, e)),
+            });
+        Either::B(soft_step(log, "billing_invoice_receipt", fut))
+    }
+
+    /// Url of receipt of the order if receipts are configured, email is sent without it if there is none
+    fn receipt_url(&self, order_id: OrderId) -> impl Future<Item = Option<String>, Error = FailureError> {
+        if self.config.receipts.is_none() {
+            return Either::A(future::ok(None));
+        }
+        Either::B(
+            self.billing_microservice
+                .get_order_receipt(Initiator::Superadmin, order_id)
+                .then(move |res| match res {
+                    Ok(receipt) => Ok(receipt.map(|receipt| receipt.url)),
+                    Err(e) => {
+                        warn!("Order created email of order {} is sent without receipt: {}", order_id, e);
+                        Ok(None)
+                    }
+                }),
+        )
+    }
+
+    fn notify_user_create_order(
+        &self,
+        user_id: UserId,
+        order_id: OrderId,
+        order_slug: OrderSlug,
+    ) -> impl Future<Item = (), Error = FailureError> {
         let cluster_url = self.config.cluster.url.clone();
         let notifications_microservice = self.notifications_microservice.clone();
-        self.users_microservice
-            .get(Some(user_id.into()), user_id)
+        let billing_microservice = self.billing_microservice.clone();
+        let tax_calculation = self.config.features.tax_calculation;
+        let receipt_url = self.receipt_url(order_id);
+        let user = self.users_microservice.get(Some(user_id.into()), user_id);
+        self.with_step_timeout("users_get_user", user)
             .and_then(move |user| {
                 user.ok_or_else(|| {
                     error!(
@@ -207,7 +511,24 @@ impl OrderServiceImpl {
                     order_slug: order_slug.to_string(),
                     cluster_url,
                 };
-                notifications_microservice.order_create_for_user(Initiator::Superadmin, email)
+                let taxes = if tax_calculation {
+                    Either::A(billing_microservice.get_order_taxes(Initiator::Superadmin, order_id).map(Some))
+                } else {
+                    Either::B(future::ok(None))
+                };
+                taxes
+                    .join(receipt_url)
+                    .and_then(move |(taxes, receipt_url)| match (taxes, receipt_url) {
+                        (taxes, Some(receipt_url)) => {
+                            let email = OrderCreateWithReceiptForUser { email, taxes, receipt_url };
+                            notifications_microservice.order_create_with_receipt_for_user(Initiator::Superadmin, email)
+                        }
+                        (Some(taxes), None) => {
+                            let email = OrderCreateWithTaxesForUser { email, taxes };
+                            notifications_microservice.order_create_with_taxes_for_user(Initiator::Superadmin, email)
+                        }
+                        (None, None) => notifications_microservice.order_create_for_user(Initiator::Superadmin, email),
+                    })
             })
     }
 
@@ -252,8 +573,8 @@ impl OrderServiceImpl {
     ) -> impl Future<Item = (), Error = FailureError> {
         let cluster_url = self.config.cluster.url.clone();
         let notifications_microservice = self.notifications_microservice.clone();
-        self.users_microservice
-            .get(Some(user_id.into()), user_id)
+        let user = self.users_microservice.get(Some(user_id.into()), user_id);
+        self.with_step_timeout("users_get_user", user)
             .and_then(move |user| {
                 user.ok_or_else(|| {
                     error!(
@@ -329,9 +650,8 @@ impl OrderServiceImpl {
                     OrderState::New | OrderState::PaymentAwaited | OrderState::TransactionPending | OrderState::AmountExpired => {
                         Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>
                     }
-                    OrderState::Paid => {
-                        Box::new(self.notify_user_create_order(order.customer, order.slug)) as Box<Future<Item = (), Error = FailureError>>
-                    }
+                    OrderState::Paid => Box::new(self.notify_user_create_order(order.customer, order.id, order.slug))
+                        as Box<Future<Item = (), Error = FailureError>>,
                     OrderState::InProcessing
                     | OrderState::Cancelled
                     | OrderState::Sent
@@ -358,13 +678,18 @@ impl OrderServiceImpl {
                         as Box<Future<Item = (), Error = FailureError>>,
                 };
 
-                let res = send_to_client.then(|_| send_to_store).then(|_| Ok(()));
+                // both notifications are sent even if the first one fails
+                let res =
+                    send_to_client.then(|client_res| send_to_store.then(|store_res| Ok::<_, FailureError>(client_res.and(store_res))));
                 orders_futures.push(res);
             }
         }
 
         join_all(orders_futures)
-            .map_err(|e: FailureError| e.context("Notifying on update orders error.".to_string()).into())
+            .and_then(|results| match results.into_iter().filter_map(Result::err).next() {
+                Some(e) => Err(FailureError::from(e.context("Notifying on update orders error."))),
+                None => Ok(()),
+            })
             .then(|res| match res {
                 Ok(_) => Ok((self, ())),
                 Err(e) => Err((self, e)),
@@ -373,47 +698,73 @@ impl OrderServiceImpl {
 
     // Contains happy path for Order creation
     fn create_happy(self, input: ConvertCart) -> impl Future<Item = (Self, Invoice), Error = (Self, FailureError)> {
-        self.convert_cart(input.clone()).and_then(move |(s, orders)| {
-            let create_invoice = CreateInvoice {
-                customer_id: input.customer_id,
-                orders: orders.clone(),
-                currency: input.currency,
-                saga_id: SagaId::new(),
-            };
-            s.create_invoice(&create_invoice).and_then(move |(s, invoice)| {
-                s.commit_coupons(orders.clone()).and_then(move |(s, _)| {
-                    s.notify(&orders.into_iter().map(Some).collect::<Vec<Option<Order>>>())
-                        .then(|res| match res {
-                            Ok((s, _)) => Ok((s, invoice)),
-                            Err((s, _)) => Ok((s, invoice)),
+        self.log.start(SagaType::CreateOrder);
+        self.normalize_address(input.address.clone())
+            .and_then(move |(s, address)| {
+                let input = ConvertCart { address, ..input };
+                s.reserve_gift_cards(&input).map(move |(s, gift_cards)| (s, (input, gift_cards)))
+            })
+            .and_then(move |(s, (input, gift_cards))| {
+                s.convert_cart(input.clone()).and_then(move |(s, orders)| {
+                    s.calculate_taxes(&input, &orders).and_then(move |(s, taxes)| {
+                        s.screen_fraud(input.customer_id, input.currency, &orders).and_then(move |(s, _)| {
+                            let create_invoice = CreateInvoice {
+                                customer_id: input.customer_id,
+                                orders: orders.clone(),
+                                currency: input.currency,
+                                saga_id: s.log.saga_id(),
+                                gift_cards,
+                                taxes,
+                            };
+                            s.create_invoice(&create_invoice)
+                                .and_then(move |(s, invoice)| s.request_receipt(&invoice).map(move |s| (s, invoice)))
+                                .and_then(move |(s, invoice)| {
+                                    s.commit_coupons(orders.clone()).and_then(move |(s, _)| {
+                                        let orders = orders.into_iter().map(Some).collect::<Vec<Option<Order>>>();
+                                        soft_step(s.log.clone(), "orders_notification", s.notify(&orders)).map(|s| (s, invoice))
+                                    })
+                                })
                         })
+                    })
                 })
             })
-        })
     }
 
     fn create_from_buy_now(self, input: BuyNow) -> impl Future<Item = (Self, Invoice), Error = (Self, FailureError)> {
-        self.buy_now(input.clone()).and_then(move |(s, orders)| {
-            let create_invoice = CreateInvoice {
-                customer_id: input.customer_id,
-                orders: orders.clone(),
-                currency: input.currency,
-                saga_id: SagaId::new(),
-            };
-            s.create_invoice(&create_invoice).and_then(move |(s, invoice)| {
-                s.notify(&orders.into_iter().map(Some).collect::<Vec<Option<Order>>>())
-                    .then(|res| match res {
-                        Ok((s, _)) => Ok((s, invoice)),
-                        Err((s, _)) => Ok((s, invoice)),
-                    })
+        self.log.start(SagaType::BuyNow);
+        self.normalize_address(input.address.clone())
+            .and_then(move |(s, address)| {
+                let input = BuyNow { address, ..input };
+                s.buy_now(input.clone()).map(move |(s, orders)| (s, (input, orders)))
+            })
+            .and_then(move |(s, (input, orders))| {
+                s.screen_fraud(input.customer_id, input.currency, &orders).and_then(move |(s, _)| {
+                    let create_invoice = CreateInvoice {
+                        customer_id: input.customer_id,
+                        orders: orders.clone(),
+                        currency: input.currency,
+                        saga_id: s.log.saga_id(),
+                        gift_cards: None,
+                        taxes: None,
+                    };
+                    s.create_invoice(&create_invoice)
+                        .and_then(move |(s, invoice)| s.request_receipt(&invoice).map(move |s| (s, invoice)))
+                        .and_then(move |(s, invoice)| {
+                            let orders = orders.into_iter().map(Some).collect::<Vec<Option<Order>>>();
+                            soft_step(s.log.clone(), "orders_notification", s.notify(&orders)).map(|s| (s, invoice))
+                        })
+                })
             })
-        })
     }
 
-    // Contains happy path for Order creation
-    fn update_orders_happy(self, orders_info: BillingOrdersVec) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+    // Contains happy path for update of order states by billing, yields orders whose state was changed
+    fn update_orders_happy(
+        self,
+        orders_info: BillingOrdersVec,
+    ) -> impl Future<Item = (Self, Vec<Option<Order>>), Error = (Self, FailureError)> {
         self.update_orders(orders_info)
             .and_then(move |(s, orders)| {
+                s.track_acknowledgment(&orders);
                 s.update_warehouse(&orders).then(|res| match res {
                     Ok((s, _)) => Ok((s, orders)),
                     Err((s, _)) => Ok((s, orders)),
@@ -421,8 +772,8 @@ impl OrderServiceImpl {
             })
             .and_then(move |(s, orders)| {
                 s.notify(&orders).then(|res| match res {
-                    Ok((s, _)) => Ok((s, ())),
-                    Err((s, _)) => Ok((s, ())),
+                    Ok((s, _)) => Ok((s, orders)),
+                    Err((s, _)) => Ok((s, orders)),
                 })
             })
     }
@@ -430,14 +781,16 @@ impl OrderServiceImpl {
     // Contains happy path for Order set state
     fn set_state_happy(
         self,
+        initiator: Option<Initiator>,
         order_slug: OrderSlug,
         order_state: OrderState,
         track_id: Option<String>,
         comment: Option<String>,
         committer_role: CommitterRole,
     ) -> impl Future<Item = (Self, Option<Order>), Error = (Self, FailureError)> {
-        self.set_state(order_slug, order_state, track_id, comment, committer_role)
+        self.set_state(initiator, order_slug, order_state, track_id, comment, committer_role)
             .and_then(move |(s, order)| {
+                s.track_acknowledgment(&[order.clone()]);
                 s.notify(&[order.clone()]).then(|res| match res {
                     Ok((s, _)) => Ok((s, order)),
                     Err((s, _)) => Ok((s, order)),
@@ -508,6 +861,7 @@ impl OrderServiceImpl {
 
     fn set_state(
         self,
+        initiator: Option<Initiator>,
         order_slug: OrderSlug,
         new_order_state: OrderState,
         track_id: Option<String>,
@@ -516,8 +870,9 @@ impl OrderServiceImpl {
     ) -> impl Future<Item = (Self, Option<Order>), Error = (Self, FailureError)> {
         let orders_microservice = self.orders_microservice.clone();
         let billing_microservice = self.billing_microservice.clone();
+        let label_sender = self.label_sender();
         self.orders_microservice
-            .get_order(None, OrderIdentifier::Slug(order_slug))
+            .get_order(initiator, OrderIdentifier::Slug(order_slug))
             .and_then(move |order| {
                 order
                     .ok_or(
@@ -530,6 +885,7 @@ impl OrderServiceImpl {
             .and_then(move |order| {
                 let old_order_state = order.state;
                 let order_id = order.id;
+                let store_id = order.store;
                 if old_order_state == new_order_state {
                     // if this status already set, do not update
                     info!(
@@ -561,16 +917,34 @@ impl OrderServiceImpl {
                             }
                         }
                         .and_then(move |_| {
-                            orders_microservice.set_order_state(
-                                None,
-                                OrderIdentifier::Slug(order_slug),
-                                UpdateStatePayload {
-                                    state: new_order_state,
-                                    comment,
-                                    track_id,
-                                    committer_role,
-                                },
-                            )
+                            let label_needed = new_order_state == OrderState::Sent && track_id.is_none();
+                            label_sender
+                                .purchase(label_needed, order_id, order_slug, store_id)
+                                .and_then(move |(label_sender, label)| {
+                                    let track_id = label.as_ref().map(|label| label.track_id.clone()).or(track_id);
+                                    orders_microservice
+                                        .set_order_state(
+                                            initiator,
+                                            OrderIdentifier::Slug(order_slug),
+                                            UpdateStatePayload {
+                                                state: new_order_state,
+                                                comment,
+                                                track_id,
+                                                committer_role,
+                                            },
+                                        )
+                                        .then(move |res| match (res, label) {
+                                            (Ok(order), Some(label)) => Either::A(Either::A(
+                                                label_sender
+                                                    .send_to_store(store_id, order_slug, label)
+                                                    .then(move |_| Ok::<_, FailureError>(order)),
+                                            )),
+                                            (Err(e), Some(_)) => Either::A(Either::B(
+                                                label_sender.cancel(order_id).then(move |_| Err::<Option<Order>, _>(e)),
+                                            )),
+                                            (res, None) => Either::B(res.into_future()),
+                                        })
+                                })
                         }),
                     )
                 }
@@ -652,71 +1026,126 @@ impl OrderServiceImpl {
     }
 
     // Contains reversal of Order creation
-    fn create_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
-        let log = self.log.lock().unwrap().clone();
+    pub fn create_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
         let orders_microservice = self.orders_microservice.clone();
         let billing_microservice = self.billing_microservice.clone();
-        let fut = iter_ok::<_, ()>(log).for_each(move |e| match e {
+        let compensation = self.log.compensate(move |e, _| match e {
             CreateOrderOperationStage::OrdersConvertCartStart(conversion_id) => {
                 debug!("Reverting cart convertion, conversion_id: {}", conversion_id);
                 let result = orders_microservice
                     .revert_convert_cart(Initiator::Superadmin, ConvertCartRevert { conversion_id })
-                    .then(|_| Ok(()));
+                    .map(|_| ());
 
-                Box::new(result) as Box<Future<Item = (), Error = ()>>
+                Box::new(result) as Box<Future<Item = (), Error = FailureError>>
             }
 
             CreateOrderOperationStage::BillingCreateInvoiceStart(saga_id) => {
                 debug!("Reverting create invoice, saga_id: {}", saga_id);
+                let billing_microservice = billing_microservice.clone();
                 let result = billing_microservice
-                    .revert_create_invoice(Initiator::Superadmin, saga_id)
-                    .then(|_| Ok(()));
+                    .get_invoice_by_saga_id(Initiator::Superadmin, saga_id)
+                    .and_then(move |invoice| match invoice {
+                        Some(_) => Box::new(
+                            billing_microservice
+                                .revert_create_invoice(Initiator::Superadmin, saga_id)
+                                .map(|_| ()),
+                        ) as Box<Future<Item = (), Error = FailureError>>,
+                        None => {
+                            debug!("Invoice with saga_id {} was not created, nothing to revert", saga_id);
+                            Box::new(future::ok(()))
+                        }
+                    });
+
+                Box::new(result) as Box<Future<Item = (), Error = FailureError>>
+            }
 
-                Box::new(result) as Box<Future<Item = (), Error = ()>>
+            CreateOrderOperationStage::BillingReserveGiftCardsStart(saga_id) => {
+                debug!("Releasing gift cards reservation, saga_id: {}", saga_id);
+                Box::new(billing_microservice.release_gift_cards(Initiator::Superadmin, saga_id))
+                    as Box<Future<Item = (), Error = FailureError>>
             }
 
-            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = ()>>,
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
         });
 
-        fut.then(|res| match res {
-            Ok(_) => Ok((self, ())),
-            Err(_) => Err((self, format_err!("Order service create_revert error occurred."))),
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((self, format_err!("Order service create_revert error occurred: {}", e))),
         })
     }
 }
 
+impl SagaService for OrderServiceImpl {
+    type Stage = CreateOrderOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.create_revert().map(|(s, _)| s))
+    }
+}
+
 impl OrderService for OrderServiceImpl {
-    fn create(self, input: ConvertCart) -> ServiceFuture<Box<OrderService>, Invoice> {
+    fn create(self, input: ConvertCart) -> ServiceFuture<Box<OrderService>, SagaResponse<Invoice>> {
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(self, SagaType::CreateOrder, deadline, move |s| s.create_happy(input), &["phone"]);
+
         Box::new(
-            self.create_happy(input.clone())
-                .map(|(s, order)| (Box::new(s) as Box<OrderService>, order))
-                .or_else(move |(s, e)| {
-                    s.create_revert().then(move |res| {
-                        let s = match res {
-                            Ok((s, _)) => s,
-                            Err((s, _)) => s,
-                        };
-                        future::err((Box::new(s) as Box<OrderService>, e))
-                    })
-                })
-                .map_err(|(s, e): (Box<OrderService>, FailureError)| (s, parse_validation_errors(e, &["phone"]))),
+            res.map(|(s, response)| (Box::new(s) as Box<OrderService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<OrderService>, e)),
         )
     }
 
-    fn create_buy_now(self, input: BuyNow) -> ServiceFuture<Box<OrderService>, Invoice> {
+    fn create_buy_now(self, input: BuyNow) -> ServiceFuture<Box<OrderService>, SagaResponse<Invoice>> {
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(self, SagaType::BuyNow, deadline, move |s| s.create_from_buy_now(input), &["phone"]);
+
         Box::new(
-            self.create_from_buy_now(input)
-                .map(|(s, order)| (Box::new(s) as Box<OrderService>, order))
-                .or_else(|(s, e)| future::err((Box::new(s) as Box<OrderService>, e)))
-                .map_err(|(s, e): (Box<OrderService>, FailureError)| (s, parse_validation_errors(e, &["phone"]))),
+            res.map(|(s, response)| (Box::new(s) as Box<OrderService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<OrderService>, e)),
         )
     }
 
-    fn update_state_by_billing(self, orders_info: BillingOrdersVec) -> ServiceFuture<Box<OrderService>, ()> {
-        info!("Updating orders status: {} by billing microservices", orders_info);
+    fn update_state_by_billing_stream(
+        self,
+        orders_info: Box<Stream<Item = BillingOrderInfo, Error = FailureError>>,
+    ) -> ServiceFuture<Box<OrderService>, BillingStreamReport> {
+        let service = self.clone();
+        let applied = Rc::new(Cell::new(0));
+        let processed = applied.clone();
+        Box::new(
+            orders_info
+                .chunks(BILLING_ORDERS_BATCH_SIZE)
+                .fold(self, move |s, batch| {
+                    let processed = processed.clone();
+                    let batch_len = batch.len();
+                    s.update_orders_happy(BillingOrdersVec(batch))
+                        .map(move |(s, _)| {
+                            processed.set(processed.get() + batch_len);
+                            info!("Processed {} order states from billing", processed.get());
+                            s
+                        })
+                        .map_err(|(_, e)| e)
+                })
+                .then(move |res| match billing_stream_report(applied.get(), res.map(|_| ())) {
+                    Ok(report) => Ok((Box::new(service) as Box<OrderService>, report)),
+                    Err(e) => Err((Box::new(service) as Box<OrderService>, e)),
+                }),
+        )
+    }
+
+    fn replay_update_state_by_billing(self, orders_info: BillingOrdersVec) -> ServiceFuture<Box<OrderService>, Vec<OrderId>> {
+        info!("Replaying orders status: {} received from billing", orders_info);
         Box::new(
             self.update_orders_happy(orders_info)
-                .map(|(s, _)| (Box::new(s) as Box<OrderService>, ()))
+                .map(|(s, orders)| {
+                    let updated = orders.into_iter().filter_map(|order| order.map(|order| order.id)).collect();
+                    (Box::new(s) as Box<OrderService>, updated)
+                })
                 .or_else(|(s, e)| future::err((Box::new(s) as Box<OrderService>, e))),
         )
     }
@@ -727,14 +1156,41 @@ impl OrderService for OrderServiceImpl {
         order_state: OrderState,
         track_id: Option<String>,
         comment: Option<String>,
-        committer_role: CommitterRole,
     ) -> ServiceFuture<Box<OrderService>, Option<Order>> {
         info!(
-            "set order {} status '{}' with track {:?}, comment {:?}, committer: {}",
-            order_slug, order_state, track_id, comment, committer_role
+            "set order {} status '{}' with track {:?}, comment {:?}",
+            order_slug, order_state, track_id, comment
         );
+        let stores_microservice = self.stores_microservice.clone();
+        let initiators = self.initiators;
+        let permissions = self.config.order_state_permissions.clone();
+        let committer_role = self
+            .orders_microservice
+            .get_order(None, OrderIdentifier::Slug(order_slug))
+            .and_then(move |order| {
+                order
+                    .ok_or_else(|| format_err!("Order {} not found", order_slug).context(Error::NotFound).into())
+                    .into_future()
+            })
+            .and_then(move |order| initiators.order_committer_role(&stores_microservice, &order))
+            .and_then(move |committer_role| {
+                if permissions.allows(committer_role, order_state) {
+                    Ok(committer_role)
+                } else {
+                    Err(
+                        format_err!("Committer {} is not allowed to set order state {}", committer_role, order_state)
+                            .context(Error::Forbidden)
+                            .into(),
+                    )
+                }
+            });
         Box::new(
-            self.set_state_happy(order_slug, order_state, track_id, comment, committer_role)
+            committer_role
+                .then(|res| match res {
+                    Ok(committer_role) => Ok((self, committer_role)),
+                    Err(e) => Err((self, e)),
+                })
+                .and_then(move |(s, committer_role)| s.set_state_happy(None, order_slug, order_state, track_id, comment, committer_role))
                 .map(|(s, o)| (Box::new(s) as Box<OrderService>, o))
                 .or_else(|(s, e)| future::err((Box::new(s) as Box<OrderService>, e))),
         )
@@ -748,4 +1204,169 @@ impl OrderService for OrderServiceImpl {
                 .or_else(|(s, e)| future::err((Box::new(s) as Box<OrderService>, e))),
         )
     }
+
+    fn apply_tracking_update(self, update: TrackingUpdate) -> ServiceFuture<Box<OrderService>, TrackingOutcome> {
+        info!(
+            "tracking update {} of order {}: status '{}', track {}",
+            update.event_id, update.order_slug, update.status, update.track_id
+        );
+        let order_state = match self
+            .config
+            .delivery_tracking
+            .as_ref()
+            .and_then(|tracking| tracking.order_state(&update.status))
+        {
+            Some(order_state) => order_state,
+            None => return Box::new(future::ok((Box::new(self) as Box<OrderService>, TrackingOutcome::Ignored))),
+        };
+        let order_slug = update.order_slug;
+        let track_id = update.track_id;
+        Box::new(
+            self.orders_microservice
+                .get_order(Some(Initiator::Superadmin), OrderIdentifier::Slug(order_slug))
+                .then(move |res| match res {
+                    Ok(order) => Ok((self, order)),
+                    Err(e) => Err((self, e)),
+                })
+                .and_then(move |(s, order)| match order {
+                    None => Either::A(future::err((
+                        s,
+                        format_err!("Order is not found in orders microservice! slug: {}", order_slug)
+                            .context(Error::NotFound)
+                            .into(),
+                    ))),
+                    Some(ref order) if order.state != OrderState::Sent => {
+                        info!("order {} is in state {}, tracking update is ignored", order_slug, order.state);
+                        Either::A(future::ok((s, TrackingOutcome::Ignored)))
+                    }
+                    Some(_) => Either::B(
+                        s.set_state_happy(
+                            Some(Initiator::Superadmin),
+                            order_slug,
+                            order_state,
+                            Some(track_id),
+                            Some("Delivery reported by courier".to_string()),
+                            CommitterRole::System,
+                        )
+                        .map(|(s, _)| (s, TrackingOutcome::Applied)),
+                    ),
+                })
+                .map(|(s, outcome)| (Box::new(s) as Box<OrderService>, outcome))
+                .or_else(|(s, e)| future::err((Box::new(s) as Box<OrderService>, e))),
+        )
+    }
+}
+
+/// Purchases shipping labels for sent orders and emails them to stores
+struct LabelSender {
+    enabled: bool,
+    cluster_url: String,
+    delivery_microservice: Arc<DeliveryMicroservice>,
+    stores_microservice: Arc<StoresMicroservice>,
+    notifications_microservice: Arc<NotificationsMicroservice>,
+}
+
+impl LabelSender {
+    fn purchase(
+        self,
+        label_needed: bool,
+        order_id: OrderId,
+        order_slug: OrderSlug,
+        store_id: StoreId,
+    ) -> impl Future<Item = (Self, Option<ShippingLabel>), Error = FailureError> {
+        if !self.enabled || !label_needed {
+            return Either::A(future::ok((self, None)));
+        }
+
+        debug!("Purchasing shipping label for order {}", order_slug);
+        let payload = NewShippingLabel {
+            order_id,
+            order_slug,
+            store_id,
+        };
+        Either::B(
+            self.delivery_microservice
+                .purchase_label(Some(Initiator::Superadmin), payload)
+                .map(move |label| (self, Some(label))),
+        )
+    }
+
+    /// Compensates label purchase if order state could not be set, failure is only logged
+    fn cancel(self, order_id: OrderId) -> impl Future<Item = (), Error = ()> {
+        debug!("Cancelling shipping label of order {}", order_id);
+        self.delivery_microservice
+            .cancel_label(Some(Initiator::Superadmin), order_id)
+            .map_err(move |e| error!("Cancelling shipping label of order {} failed: {}", order_id, e))
+    }
+
+    /// Order is already sent when label is emailed, so failure is only logged
+    fn send_to_store(self, store_id: StoreId, order_slug: OrderSlug, label: ShippingLabel) -> impl Future<Item = (), Error = ()> {
+        let LabelSender {
+            cluster_url,
+            stores_microservice,
+            notifications_microservice,
+            ..
+        } = self;
+
+        stores_microservice
+            .get(store_id, Visibility::Active)
+            .and_then(move |store| match store.and_then(|store| store.email) {
+                Some(store_email) => {
+                    let email = ShippingLabelForStore {
+                        store_email,
+                        store_id: store_id.to_string(),
+                        order_slug: order_slug.to_string(),
+                        track_id: label.track_id,
+                        label_url: label.label_url,
+                        cluster_url,
+                    };
+                    Either::A(notifications_microservice.shipping_label_for_store(Initiator::Superadmin, email))
+                }
+                None => {
+                    warn!(
+                        "Shipping label of order {} is not emailed, store {} has no email",
+                        order_slug, store_id
+                    );
+                    Either::B(future::ok(()))
+                }
+            })
+            .map_err(move |e| error!("Emailing shipping label of order {} to store failed: {}", order_slug, e))
+    }
+}
+
+/// Fails the stream only if none of its elements were applied, so that the call can be retried as a whole
+fn billing_stream_report(applied: usize, res: Result<(), FailureError>) -> Result<BillingStreamReport, FailureError> {
+    match res {
+        Ok(()) => Ok(BillingStreamReport { applied, error: None }),
+        Err(e) if applied == 0 => Err(e),
+        Err(e) => {
+            warn!("Order states from billing failed after {} of them were applied: {}", applied, e);
+            Ok(BillingStreamReport {
+                applied,
+                error: Some(e.to_string()),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::billing_stream_report;
+    use models::BillingStreamReport;
+
+    #[test]
+    fn reports_elements_applied_before_failure() {
+        assert!(billing_stream_report(0, Err(format_err!("Parsing body failed"))).is_err());
+        assert_eq!(
+            billing_stream_report(200, Err(format_err!("Parsing body failed"))).unwrap(),
+            BillingStreamReport {
+                applied: 200,
+                error: Some("Parsing body failed".to_string()),
+            }
+        );
+        assert_eq!(
+            billing_stream_report(250, Ok(())).unwrap(),
+            BillingStreamReport { applied: 250, error: None }
+        );
+    }
 }
@@ -0,0 +1,461 @@
+//! Returns of delivered orders. Customer requests a return within `returns.window_s`,
+//! the payment is marked for refund right away, while the items go back to stock only
+//! once the store confirms it received them.
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use failure::Error as FailureError;
+use futures::future::{self, Either};
+use futures::prelude::*;
+
+use stq_api::orders::Order;
+use stq_static_resources::EmailUser;
+use stq_types::{OrderId, OrderIdentifier, OrderSlug, SagaId};
+
+use super::initiator::InitiatorPolicy;
+use config;
+use errors::Error;
+use microservice::*;
+use models::*;
+use saga::{self, soft_step, SagaLog, SagaService, SagaStore};
+use scrubbing::Scrubbed;
+use services::stock::revert_stock_return;
+use services::types::ServiceFuture;
+
+pub trait OrderReturnService {
+    /// Registers return of delivered order in orders and marks its payment for refund in billing
+    fn request_return(
+        self,
+        order_slug: OrderSlug,
+        input: OrderReturnInput,
+    ) -> ServiceFuture<Box<OrderReturnService>, SagaResponse<OrderReturn>>;
+    /// Confirms store received the returned items and puts them back to stock
+    fn receive_return(self, order_slug: OrderSlug) -> ServiceFuture<Box<OrderReturnService>, SagaResponse<OrderReturn>>;
+}
+
+#[derive(Clone)]
+pub struct OrderReturnServiceImpl {
+    pub orders_microservice: Arc<OrdersMicroservice>,
+    pub billing_microservice: Arc<BillingMicroservice>,
+    pub warehouses_microservice: Arc<WarehousesMicroservice>,
+    pub stores_microservice: Arc<StoresMicroservice>,
+    pub users_microservice: Arc<UsersMicroservice>,
+    pub notifications_microservice: Arc<NotificationsMicroservice>,
+    pub config: config::Config,
+    pub log: Rc<SagaLog<OrderReturnOperationStage>>,
+    pub initiators: InitiatorPolicy,
+}
+
+impl OrderReturnServiceImpl {
+    pub fn new(
+        config: config::Config,
+        saga_store: Arc<SagaStore>,
+        orders_microservice: Arc<OrdersMicroservice>,
+        billing_microservice: Arc<BillingMicroservice>,
+        warehouses_microservice: Arc<WarehousesMicroservice>,
+        stores_microservice: Arc<StoresMicroservice>,
+        users_microservice: Arc<UsersMicroservice>,
+        notifications_microservice: Arc<NotificationsMicroservice>,
+    ) -> Self {
+        let log = Rc::new(SagaLog::new(saga_store));
+        Self {
+            config,
+            orders_microservice,
+            billing_microservice,
+            warehouses_microservice,
+            stores_microservice,
+            users_microservice,
+            notifications_microservice,
+            log,
+            initiators: InitiatorPolicy::default(),
+        }
+    }
+
+    /// Performs steps of sagas on behalf of the caller
+    pub fn with_caller(mut self, caller: Option<Initiator>) -> Self {
+        self.initiators = InitiatorPolicy::new(caller);
+        self
+    }
+
+    fn request_return_happy(
+        self,
+        order_slug: OrderSlug,
+        input: OrderReturnInput,
+    ) -> impl Future<Item = (Self, OrderReturn), Error = (Self, FailureError)> {
+        self.log.start(SagaType::ReturnOrder);
+        self.log.set_input(&input);
+        let window = Duration::from_secs(self.config.returns.window_s);
+
+        self.get_order(order_slug)
+            .and_then(move |(s, order)| {
+                if is_returnable(order.state, order.updated_at, Utc::now(), window) {
+                    Ok((s, order))
+                } else {
+                    debug!("Order {} in state {} can not be returned", order_slug, order.state);
+                    Err((
+                        s,
+                        Error::Validate(validation_errors!({"order": ["order_state" => "Order can not be returned"]})).into(),
+                    ))
+                }
+            })
+            .and_then(|(s, order)| s.get_payment_state(order))
+            .and_then(move |(s, (order, payment_state))| {
+                s.create_return(&order, input.reason).and_then(move |(s, order_return)| {
+                    s.request_refund(order.id, payment_state).and_then(move |s| {
+                        soft_step(
+                            s.log.clone(),
+                            "return_notification",
+                            s.notify_parties(order, ReturnState::Requested),
+                        )
+                        .map(move |s| (s, order_return))
+                    })
+                })
+            })
+    }
+
+    fn receive_return_happy(self, order_slug: OrderSlug) -> impl Future<Item = (Self, OrderReturn), Error = (Self, FailureError)> {
+        self.log.start(SagaType::ReceiveReturn);
+
+        self.get_order(order_slug)
+            .and_then(|(s, order)| s.get_return(order))
+            .and_then(|(s, (order, order_return))| {
+                if order_return.state == ReturnState::Requested {
+                    Ok((s, order))
+                } else {
+                    Err((
+                        s,
+                        Error::Validate(validation_errors!({"order": ["return_state" => "Return items are already received"]})).into(),
+                    ))
+                }
+            })
+            .and_then(|(s, order)| s.return_stock(order))
+            .and_then(|(s, order)| {
+                s.confirm_receipt(order.id).and_then(move |(s, order_return)| {
+                    soft_step(s.log.clone(), "return_notification", s.notify_parties(order, ReturnState::Received))
+                        .map(move |s| (s, order_return))
+                })
+            })
+    }
+
+    /// Order is looked up on behalf of the caller, so orders checks that the caller may see it
+    fn get_order(self, order_slug: OrderSlug) -> impl Future<Item = (Self, Order), Error = (Self, FailureError)> {
+        self.orders_microservice
+            .get_order(None, OrderIdentifier::Slug(order_slug))
+            .and_then(move |order| {
+                order
+                    .ok_or_else(|| format_err!("Order {} not found", order_slug).context(Error::NotFound).into())
+                    .into_future()
+            })
+            .then(|res| match res {
+                Ok(order) => Ok((self, order)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    /// Orders billing does not hold the money of any more are not refundable
+    fn get_payment_state(self, order: Order) -> impl Future<Item = (Self, (Order, PaymentState)), Error = (Self, FailureError)> {
+        let order_id = order.id;
+        self.billing_microservice
+            .get_payout_eligible_orders(Initiator::Superadmin, order.store)
+            .and_then(move |eligible| {
+                match eligible
+                    .into_iter()
+                    .find(|eligible| eligible.order_id == order_id && is_refundable(eligible.payment_state))
+                {
+                    Some(eligible) => Ok((order, eligible.payment_state)),
+                    None => {
+                        debug!("Payment of order {} can not be refunded", order_id);
+                        Err(
+                            Error::Validate(validation_errors!({"order": ["payment_state" => "Payment of order can not be refunded"]}))
+                                .into(),
+                        )
+                    }
+                }
+            })
+            .then(|res| match res {
+                Ok(res) => Ok((self, res)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn get_return(self, order: Order) -> impl Future<Item = (Self, (Order, OrderReturn)), Error = (Self, FailureError)> {
+        let order_id = order.id;
+        self.orders_microservice
+            .get_order_return(Initiator::Superadmin, order_id)
+            .and_then(move |order_return| match order_return {
+                Some(order_return) => Ok((order, order_return)),
+                None => Err(format_err!("Return of order {} not found", order_id)
+                    .context(Error::NotFound)
+                    .into()),
+            })
+            .then(|res| match res {
+                Ok(res) => Ok((self, res)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn create_return(self, order: &Order, reason: String) -> impl Future<Item = (Self, OrderReturn), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let saga_id = self.log.saga_id();
+        let initiator = self
+            .initiators
+            .superadmin(&log, "orders_return_creation", "returns are created only by orders administrators");
+        log.push(OrderReturnOperationStage::ReturnCreationStart(saga_id));
+
+        let payload = NewOrderReturn {
+            order_id: order.id,
+            saga_id,
+            reason,
+            quantity: order.quantity,
+        };
+        self.orders_microservice
+            .create_return(initiator, payload)
+            .and_then(move |order_return| {
+                log.push_with_result(OrderReturnOperationStage::ReturnCreationComplete(saga_id), &order_return);
+                Ok(order_return)
+            })
+            .then(|res| match res {
+                Ok(order_return) => Ok((self, order_return)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn request_refund(self, order_id: OrderId, previous_state: PaymentState) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let initiator = self.initiators.superadmin(
+            &log,
+            "billing_payment_state_set",
+            "payment states are set only by billing administrators",
+        );
+        log.push(OrderReturnOperationStage::RefundRequestStart(order_id, previous_state));
+
+        let payload = OrderPaymentStateRequest {
+            state: PaymentState::RefundNeeded,
+        };
+        self.billing_microservice
+            .set_payment_state(Some(initiator), order_id, payload)
+            .then(move |res| match res {
+                Ok(_) => {
+                    log.push(OrderReturnOperationStage::RefundRequestComplete(order_id));
+                    Ok(self)
+                }
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn return_stock(self, order: Order) -> impl Future<Item = (Self, Order), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let order_id = order.id;
+        let initiator = self.initiators.superadmin(
+            &log,
+            "warehouses_stock_return",
+            "stocks are adjusted only by warehouses administrators",
+        );
+        log.push(OrderReturnOperationStage::StockReturnStart(order_id));
+
+        let adjustment = StockAdjustment {
+            saga_id: self.log.saga_id(),
+            kind: StockAdjustmentKind::Return,
+            quantity: order.quantity,
+        };
+        self.warehouses_microservice
+            .adjust_stock(initiator, order.product, adjustment)
+            .then(move |res| match res {
+                Ok(_) => {
+                    log.push(OrderReturnOperationStage::StockReturnComplete(order_id));
+                    Ok((self, order))
+                }
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn confirm_receipt(self, order_id: OrderId) -> impl Future<Item = (Self, OrderReturn), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let initiator = self
+            .initiators
+            .superadmin(&log, "orders_return_receipt", "returns are received only by orders administrators");
+        log.push(OrderReturnOperationStage::ReturnReceiptStart(order_id));
+
+        self.orders_microservice
+            .receive_return(initiator, order_id)
+            .and_then(move |order_return| {
+                log.push_with_result(OrderReturnOperationStage::ReturnReceiptComplete(order_id), &order_return);
+                Ok(order_return)
+            })
+            .then(|res| match res {
+                Ok(order_return) => Ok((self, order_return)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    /// Tells both customer and store about the return of the order
+    fn notify_parties(self, order: Order, return_state: ReturnState) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let cluster_url = self.config.cluster.url.clone();
+        let notifications_microservice = self.notifications_microservice.clone();
+        let order_slug = order.slug.to_string();
+
+        let to_customer = {
+            let notifications_microservice = notifications_microservice.clone();
+            let cluster_url = cluster_url.clone();
+            let order_slug = order_slug.clone();
+            let customer_id = order.customer;
+            self.users_microservice
+                .get(Some(Initiator::Superadmin), customer_id)
+                .and_then(move |user| {
+                    user.ok_or_else(|| format_err!("Customer {} not found", customer_id).context(Error::NotFound).into())
+                        .into_future()
+                })
+                .and_then(move |user| {
+                    let email = OrderReturnForUser {
+                        user: EmailUser {
+                            email: user.email.clone(),
+                            first_name: user.first_name.unwrap_or_else(|| "user".to_string()),
+                            last_name: user.last_name.unwrap_or_else(|| "".to_string()),
+                        },
+                        order_slug,
+                        return_state,
+                        cluster_url,
+                    };
+                    notifications_microservice.order_return_for_user(Initiator::Superadmin, email)
+                })
+        };
+
+        let store_id = order.store;
+        let to_store =
+            self.stores_microservice
+                .get(store_id, Visibility::Active)
+                .and_then(move |store| match store.and_then(|store| store.email) {
+                    Some(store_email) => {
+                        let email = OrderReturnForStore {
+                            store_email,
+                            store_id: store_id.to_string(),
+                            order_slug,
+                            return_state,
+                            cluster_url,
+                        };
+                        Either::A(notifications_microservice.order_return_for_store(Initiator::Superadmin, email))
+                    }
+                    None => Either::B(future::ok(())),
+                });
+
+        // both notifications are sent even if the first one fails
+        to_customer
+            .then(|customer_res| to_store.then(|store_res| customer_res.and(store_res)))
+            .then(|res| match res {
+                Ok(_) => Ok((self, ())),
+                Err(e) => Err((self, FailureError::from(e.context("Notifying parties of order return failed.")))),
+            })
+    }
+
+    // Contains reversal of both requesting a return and confirming its receipt. Receipt in orders
+    // is the last step and is never compensated
+    pub fn return_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let billing_microservice = self.billing_microservice.clone();
+        let orders_microservice = self.orders_microservice.clone();
+        let warehouses_microservice = self.warehouses_microservice.clone();
+        let saga_id = self.log.saga_id();
+
+        let compensation = self.log.compensate(move |e, phase| match e {
+            OrderReturnOperationStage::ReturnCreationStart(saga_id) => revert_return_creation(orders_microservice.clone(), saga_id),
+
+            OrderReturnOperationStage::RefundRequestStart(order_id, previous_state) => {
+                debug!(
+                    "Reverting refund request of order {} to payment state {:?}",
+                    order_id, previous_state
+                );
+                let payload = OrderPaymentStateRequest { state: previous_state };
+                Box::new(billing_microservice.set_payment_state(Some(Initiator::Superadmin), order_id, payload))
+                    as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            OrderReturnOperationStage::StockReturnStart(order_id) => {
+                debug!("Reverting stock return of order {}", order_id);
+                revert_stock_return(
+                    orders_microservice.clone(),
+                    warehouses_microservice.clone(),
+                    saga_id,
+                    order_id,
+                    phase,
+                )
+            }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
+        });
+
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((self, format_err!("Order return service return_revert error occurred: {}", e))),
+        })
+    }
+}
+
+impl SagaService for OrderReturnServiceImpl {
+    type Stage = OrderReturnOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.return_revert().map(|(s, _)| s))
+    }
+}
+
+impl OrderReturnService for OrderReturnServiceImpl {
+    fn request_return(
+        self,
+        order_slug: OrderSlug,
+        input: OrderReturnInput,
+    ) -> ServiceFuture<Box<OrderReturnService>, SagaResponse<OrderReturn>> {
+        debug!("Request return of order {}, input: {:?}", order_slug, Scrubbed(&input));
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::ReturnOrder,
+            deadline,
+            move |s| s.request_return_happy(order_slug, input),
+            &["order", "reason"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<OrderReturnService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<OrderReturnService>, e)),
+        )
+    }
+
+    fn receive_return(self, order_slug: OrderSlug) -> ServiceFuture<Box<OrderReturnService>, SagaResponse<OrderReturn>> {
+        debug!("Confirm receipt of return of order {}", order_slug);
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::ReceiveReturn,
+            deadline,
+            move |s| s.receive_return_happy(order_slug),
+            &["order", "reason"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<OrderReturnService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<OrderReturnService>, e)),
+        )
+    }
+}
+
+fn revert_return_creation(orders_microservice: Arc<OrdersMicroservice>, saga_id: SagaId) -> Box<Future<Item = (), Error = FailureError>> {
+    debug!("Reverting order return creation, saga_id: {}", saga_id);
+    Box::new(
+        orders_microservice
+            .get_return_by_saga_id(Initiator::Superadmin, saga_id)
+            .and_then(move |order_return| match order_return {
+                Some(_) => Either::A(orders_microservice.revert_create_return(Initiator::Superadmin, saga_id)),
+                None => {
+                    debug!("Return with saga_id {} was not created, nothing to revert", saga_id);
+                    Either::B(future::ok(()))
+                }
+            }),
+    )
+}
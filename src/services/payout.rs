@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+
+use stq_types::{OrderId, StoreId};
+
+use super::initiator::InitiatorPolicy;
+use config;
+use errors::Error;
+use microservice::*;
+use models::*;
+use saga::{self, soft_step, SagaLog, SagaService, SagaStore};
+use services::types::ServiceFuture;
+
+pub trait PayoutService {
+    /// Pays captured orders of the store out to seller
+    fn create_payout(self, store_id: StoreId, input: PayoutInput) -> ServiceFuture<Box<PayoutService>, SagaResponse<Payout>>;
+}
+
+#[derive(Clone)]
+pub struct PayoutServiceImpl {
+    pub stores_microservice: Arc<StoresMicroservice>,
+    pub billing_microservice: Arc<BillingMicroservice>,
+    pub notifications_microservice: Arc<NotificationsMicroservice>,
+    pub config: config::Config,
+    pub log: Rc<SagaLog<CreatePayoutOperationStage>>,
+    pub initiators: InitiatorPolicy,
+}
+
+impl PayoutServiceImpl {
+    pub fn new(
+        config: config::Config,
+        saga_store: Arc<SagaStore>,
+        stores_microservice: Arc<StoresMicroservice>,
+        billing_microservice: Arc<BillingMicroservice>,
+        notifications_microservice: Arc<NotificationsMicroservice>,
+    ) -> Self {
+        let log = Rc::new(SagaLog::new(saga_store));
+        Self {
+            config,
+            stores_microservice,
+            billing_microservice,
+            notifications_microservice,
+            log,
+            initiators: InitiatorPolicy::default(),
+        }
+    }
+
+    /// Performs steps of sagas on behalf of the caller, the caller must manage the store or be superadmin
+    pub fn with_caller(mut self, caller: Option<Initiator>) -> Self {
+        self.initiators = InitiatorPolicy::new(caller);
+        self
+    }
+
+    fn create_payout_happy(
+        self,
+        store_id: StoreId,
+        input: PayoutInput,
+    ) -> impl Future<Item = (Self, Payout), Error = (Self, FailureError)> {
+        self.log.start(SagaType::CreatePayout);
+
+        self.eligible_orders(store_id, input).and_then(move |(s, (store, orders))| {
+            let order_ids = orders.iter().map(|order| order.order_id).collect();
+            s.create_billing_payout(store_id, order_ids).and_then(move |(s, payout)| {
+                let orders_count = orders.len();
+                s.set_paid_to_seller(orders).and_then(move |s| {
+                    soft_step(s.log.clone(), "store_payout_notification", s.notify_store(store, orders_count)).map(move |s| (s, payout))
+                })
+            })
+        })
+    }
+
+    /// Orders of the input that billing allows to pay out, fails if any of them is not eligible
+    /// or if the caller does not manage the store
+    fn eligible_orders(
+        self,
+        store_id: StoreId,
+        input: PayoutInput,
+    ) -> impl Future<Item = (Self, (Store, Vec<PayoutEligibleOrder>)), Error = (Self, FailureError)> {
+        let billing_microservice = self.billing_microservice.clone();
+        let initiators = self.initiators;
+
+        let fut = self
+            .stores_microservice
+            .get(store_id, Visibility::Active)
+            .and_then(move |store| {
+                store
+                    .ok_or_else(|| format_err!("Store {} not found", store_id).context(Error::NotFound).into())
+                    .into_future()
+            })
+            .and_then(move |store| {
+                if initiators.acts_for(store.user_id) {
+                    Ok(store)
+                } else {
+                    Err(
+                        format_err!("Caller {:?} does not manage store {}", initiators.caller_id(), store_id)
+                            .context(Error::Forbidden)
+                            .into(),
+                    )
+                }
+            })
+            .and_then(move |store| {
+                billing_microservice
+                    .get_payout_eligible_orders(Initiator::Superadmin, store_id)
+                    .map(|orders| (store, orders))
+            })
+            .and_then(move |(store, orders)| {
+                let mut eligible = orders
+                    .into_iter()
+                    .filter(|order| {
+                        order.payment_state == PaymentState::Captured || order.payment_state == PaymentState::PaymentToSellerNeeded
+                    })
+                    .map(|order| (order.order_id, order))
+                    .collect::<HashMap<_, _>>();
+
+                let mut orders = vec![];
+                for order_id in input.order_ids {
+                    match eligible.remove(&order_id) {
+                        Some(order) => orders.push(order),
+                        None => {
+                            debug!("Order {} of store {} is not eligible for payout", order_id, store_id);
+                            return Err(Error::Validate(
+                                validation_errors!({"order_ids": ["not_eligible" => "Order is not eligible for payout"]}),
+                            )
+                            .into());
+                        }
+                    }
+                }
+                Ok((store, orders))
+            });
+
+        fut.then(|res| match res {
+            Ok(res) => Ok((self, res)),
+            Err(e) => Err((self, e)),
+        })
+    }
+
+    fn create_billing_payout(
+        self,
+        store_id: StoreId,
+        order_ids: Vec<OrderId>,
+    ) -> impl Future<Item = (Self, Payout), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let saga_id = self.log.saga_id();
+        let initiator = self
+            .initiators
+            .superadmin(&log, "billing_create_payout", "payouts are created only by billing administrators");
+        log.push(CreatePayoutOperationStage::PayoutCreationStart(saga_id));
+
+        let payload = NewPayout {
+            store_id,
+            order_ids,
+            saga_id,
+        };
+
+        self.billing_microservice
+            .create_payout(initiator, payload)
+            .and_then(move |payout| {
+                log.push_with_result(CreatePayoutOperationStage::PayoutCreationComplete(saga_id), &payout);
+                Ok(payout)
+            })
+            .then(|res| match res {
+                Ok(payout) => Ok((self, payout)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn set_paid_to_seller(self, orders: Vec<PayoutEligibleOrder>) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        iter_ok::<_, (Self, FailureError)>(orders).fold(self, |s, order| {
+            let log = s.log.clone();
+            let order_id = order.order_id;
+            let initiator = s.initiators.superadmin(
+                &log,
+                "billing_payment_state_set",
+                "payment states are set only by billing administrators",
+            );
+            log.push(CreatePayoutOperationStage::OrderPaidToSellerStart(order_id, order.payment_state));
+
+            let payload = OrderPaymentStateRequest {
+                state: PaymentState::PaidToSeller,
+            };
+            s.billing_microservice
+                .set_payment_state(Some(initiator), order_id, payload)
+                .then(move |res| match res {
+                    Ok(_) => {
+                        log.push(CreatePayoutOperationStage::OrderPaidToSellerComplete(order_id));
+                        Ok(s)
+                    }
+                    Err(e) => Err((s, e)),
+                })
+        })
+    }
+
+    fn notify_store(self, store: Store, orders_count: usize) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let fut = match store.email {
+            Some(store_email) => {
+                let email = PayoutInitiatedForStore {
+                    store_email,
+                    store_id: store.id.to_string(),
+                    orders_count,
+                    cluster_url: self.config.cluster.url.clone(),
+                };
+                Either::A(
+                    self.notifications_microservice
+                        .payout_initiated_for_store(Initiator::Superadmin, email),
+                )
+            }
+            None => Either::B(future::ok(())),
+        };
+
+        fut.then(|res| match res {
+            Ok(_) => Ok((self, ())),
+            Err(e) => Err((self, e)),
+        })
+    }
+
+    // Contains reversal of payout creation, payment states of orders are set back to the ones they had before the saga
+    pub fn create_payout_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let billing_microservice = self.billing_microservice.clone();
+
+        let compensation = self.log.compensate(move |e, _| match e {
+            CreatePayoutOperationStage::OrderPaidToSellerStart(order_id, previous_state) => {
+                debug!("Reverting payment state of order {} to {:?}", order_id, previous_state);
+                let payload = OrderPaymentStateRequest { state: previous_state };
+                Box::new(billing_microservice.set_payment_state(Some(Initiator::Superadmin), order_id, payload))
+                    as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            CreatePayoutOperationStage::PayoutCreationStart(saga_id) => {
+                debug!("Reverting payout creation, saga_id: {}", saga_id);
+                let billing_microservice = billing_microservice.clone();
+                let result =
+                    billing_microservice
+                        .get_payout_by_saga_id(Initiator::Superadmin, saga_id)
+                        .and_then(move |payout| match payout {
+                            Some(_) => Box::new(billing_microservice.revert_create_payout(Initiator::Superadmin, saga_id))
+                                as Box<Future<Item = (), Error = FailureError>>,
+                            None => {
+                                debug!("Payout with saga_id {} was not created, nothing to revert", saga_id);
+                                Box::new(future::ok(()))
+                            }
+                        });
+
+                Box::new(result) as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
+        });
+
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((self, format_err!("Payout service create_payout_revert error occurred: {}", e))),
+        })
+    }
+}
+
+impl SagaService for PayoutServiceImpl {
+    type Stage = CreatePayoutOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.create_payout_revert().map(|(s, _)| s))
+    }
+}
+
+impl PayoutService for PayoutServiceImpl {
+    fn create_payout(self, store_id: StoreId, input: PayoutInput) -> ServiceFuture<Box<PayoutService>, SagaResponse<Payout>> {
+        debug!("Create payout for store {}, input: {:?}", store_id, input);
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::CreatePayout,
+            deadline,
+            move |s| s.create_payout_happy(store_id, input),
+            &["order_ids"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<PayoutService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<PayoutService>, e)),
+        )
+    }
+}
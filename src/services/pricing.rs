@@ -0,0 +1,308 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+
+use stq_static_resources::EmailUser;
+use stq_types::*;
+
+use config;
+use microservice::*;
+use models::*;
+use saga::{self, soft_step, SagaLog, SagaService, SagaStore};
+use services::types::ServiceFuture;
+
+pub trait PricingService {
+    /// Changes prices of products of the store. Changes rejected by stores are reported
+    /// in the result instead of failing the saga.
+    fn reprice(self, store_id: StoreId, input: RepriceInput) -> ServiceFuture<Box<PricingService>, SagaResponse<RepriceResult>>;
+}
+
+#[derive(Clone)]
+pub struct PricingServiceImpl {
+    pub orders_microservice: Arc<OrdersMicroservice>,
+    pub stores_microservice: Arc<StoresMicroservice>,
+    pub users_microservice: Arc<UsersMicroservice>,
+    pub notifications_microservice: Arc<NotificationsMicroservice>,
+    pub config: config::Config,
+    pub log: Rc<SagaLog<RepriceOperationStage>>,
+}
+
+impl PricingServiceImpl {
+    pub fn new(
+        config: config::Config,
+        saga_store: Arc<SagaStore>,
+        orders_microservice: Arc<OrdersMicroservice>,
+        stores_microservice: Arc<StoresMicroservice>,
+        users_microservice: Arc<UsersMicroservice>,
+        notifications_microservice: Arc<NotificationsMicroservice>,
+    ) -> Self {
+        let log = Rc::new(SagaLog::new(saga_store));
+        Self {
+            config,
+            orders_microservice,
+            stores_microservice,
+            users_microservice,
+            notifications_microservice,
+            log,
+        }
+    }
+
+    fn reprice_happy(
+        self,
+        store_id: StoreId,
+        input: RepriceInput,
+    ) -> impl Future<Item = (Self, RepriceResult), Error = (Self, FailureError)> {
+        self.log.start(SagaType::Reprice);
+
+        self.store_products(store_id).and_then(move |(s, products)| {
+            s.update_prices(products, input.prices).and_then(move |(s, results)| {
+                let result = RepriceResult {
+                    store_id,
+                    products: results,
+                };
+                let repriced = result
+                    .repriced()
+                    .into_iter()
+                    .map(|product| RepricedProduct {
+                        product_id: product.product_id,
+                        base_product_id: product.base_product_id,
+                        price: product.price,
+                    })
+                    .collect::<Vec<_>>();
+                if repriced.is_empty() {
+                    return Either::A(future::ok((s, result)));
+                }
+
+                let product_ids = repriced.iter().map(|product| product.product_id).collect::<Vec<_>>();
+                Either::B(s.find_customers(product_ids.clone()).and_then(move |(s, customers)| {
+                    s.cleanup_carts(product_ids).and_then(move |(s, _)| {
+                        soft_step(s.log.clone(), "customers_notification", s.notify_customers(customers, repriced))
+                            .map(move |s| (s, result))
+                    })
+                }))
+            })
+        })
+    }
+
+    fn store_products(self, store_id: StoreId) -> impl Future<Item = (Self, HashMap<ProductId, Product>), Error = (Self, FailureError)> {
+        self.stores_microservice
+            .get_products_by_store(store_id)
+            .map(|products| products.into_iter().map(|product| (product.id, product)).collect())
+            .then(|res| match res {
+                Ok(products) => Ok((self, products)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    /// Applies price changes one by one, a rejected change does not stop the rest
+    fn update_prices(
+        self,
+        products: HashMap<ProductId, Product>,
+        prices: Vec<NewProductPrice>,
+    ) -> impl Future<Item = (Self, Vec<ProductRepriceResult>), Error = (Self, FailureError)> {
+        iter_ok::<_, (Self, FailureError)>(prices).fold((self, vec![]), move |(s, mut results), new_price| {
+            let base_product_id = products.get(&new_price.product_id).map(|product| product.base_product_id);
+            s.update_price(base_product_id, new_price).map(move |(s, result)| {
+                results.push(result);
+                (s, results)
+            })
+        })
+    }
+
+    fn update_price(
+        self,
+        base_product_id: Option<BaseProductId>,
+        new_price: NewProductPrice,
+    ) -> impl Future<Item = (Self, ProductRepriceResult), Error = (Self, FailureError)> {
+        let NewProductPrice { product_id, price } = new_price;
+        let result = move |error: Option<String>| ProductRepriceResult {
+            product_id,
+            base_product_id,
+            price,
+            status: if error.is_none() {
+                RepriceStatus::Repriced
+            } else {
+                RepriceStatus::Failed
+            },
+            error,
+        };
+
+        if base_product_id.is_none() {
+            debug!("Product {} is not found in store, it is not repriced", product_id);
+            return Either::A(future::ok((self, result(Some("Product is not found in store".to_string())))));
+        }
+
+        let log = self.log.clone();
+        log.push(RepriceOperationStage::ProductPriceUpdateStart(product_id));
+        let payload = UpdateProductPrice {
+            price,
+            saga_id: self.log.saga_id(),
+        };
+
+        Either::B(
+            self.stores_microservice
+                .update_product_price(None, product_id, payload)
+                .then(move |res| match res {
+                    Ok(product) => {
+                        log.push_with_result(RepriceOperationStage::ProductPriceUpdateComplete(product_id), &product);
+                        Ok((self, result(None)))
+                    }
+                    Err(e) => {
+                        warn!("Price of product {} was not updated: {}", product_id, e);
+                        Ok((self, result(Some(e.to_string()))))
+                    }
+                }),
+        )
+    }
+
+    /// Customers to notify have to be found before products are removed from their carts.
+    /// Customers are not notified if the lookup fails, it does not fail the saga.
+    fn find_customers(
+        self,
+        product_ids: Vec<ProductId>,
+    ) -> impl Future<Item = (Self, Vec<CustomerCartProducts>), Error = (Self, FailureError)> {
+        self.orders_microservice
+            .find_carts_with_products(Some(Initiator::Superadmin), FindCartsWithProductsPayload { product_ids })
+            .then(|res| match res {
+                Ok(customers) => Ok((self, customers)),
+                Err(e) => {
+                    self.log.warn("customers_notification", &e);
+                    Ok((self, vec![]))
+                }
+            })
+    }
+
+    fn cleanup_carts(self, product_ids: Vec<ProductId>) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let saga_id = self.log.saga_id();
+        log.push(RepriceOperationStage::CartsCleanupStart(saga_id));
+
+        self.orders_microservice
+            .delete_products_from_all_carts(Some(Initiator::Superadmin), DeleteProductsFromCartsPayload { product_ids })
+            .and_then(move |_| {
+                log.push(RepriceOperationStage::CartsCleanupComplete(saga_id));
+                Ok(())
+            })
+            .then(|res| match res {
+                Ok(_) => Ok((self, ())),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn notify_customers(
+        self,
+        customers: Vec<CustomerCartProducts>,
+        repriced: Vec<RepricedProduct>,
+    ) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let users_microservice = self.users_microservice.clone();
+        let notifications_microservice = self.notifications_microservice.clone();
+        let cluster_url = self.config.cluster.url.clone();
+        let customers_count = customers.len();
+
+        iter_ok::<_, FailureError>(customers)
+            .fold(0, move |failed, customer| {
+                let customer_id = customer.customer_id;
+                let products = repriced
+                    .iter()
+                    .filter(|product| customer.product_ids.contains(&product.product_id))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let notifications_microservice = notifications_microservice.clone();
+                let cluster_url = cluster_url.clone();
+
+                users_microservice
+                    .get(Some(Initiator::Superadmin), customer_id)
+                    .and_then(move |user| {
+                        user.ok_or_else(|| format_err!("User {} is not found in users microservice", customer_id))
+                            .into_future()
+                    })
+                    .and_then(move |user| {
+                        let email = CartProductsRepricedForUser {
+                            user: EmailUser {
+                                email: user.email.clone(),
+                                first_name: user.first_name.unwrap_or_else(|| "user".to_string()),
+                                last_name: user.last_name.unwrap_or_else(|| "".to_string()),
+                            },
+                            products,
+                            cluster_url,
+                        };
+                        notifications_microservice.cart_products_repriced_for_user(Initiator::Superadmin, email)
+                    })
+                    .then(move |res| match res {
+                        Ok(_) => Ok::<_, FailureError>(failed),
+                        Err(e) => {
+                            warn!("Customer {} was not notified about repriced products: {}", customer_id, e);
+                            Ok(failed + 1)
+                        }
+                    })
+            })
+            .and_then(move |failed| match failed {
+                0 => Ok(()),
+                _ => Err(format_err!("{} of {} customers were not notified", failed, customers_count)),
+            })
+            .then(|res| match res {
+                Ok(_) => Ok((self, ())),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    // Contains reversal of price changes, stores restore prices products had before the saga.
+    // Products removed from carts are not put back.
+    pub fn reprice_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let stores_microservice = self.stores_microservice.clone();
+        let saga_id = self.log.saga_id();
+
+        let compensation = self.log.compensate(move |e, _| match e {
+            RepriceOperationStage::ProductPriceUpdateStart(product_id) => {
+                debug!("Reverting price of product {}, saga_id: {}", product_id, saga_id);
+                Box::new(stores_microservice.revert_product_price(Some(Initiator::Superadmin), product_id, saga_id))
+                    as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
+        });
+
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((self, format_err!("Pricing service reprice_revert error occurred: {}", e))),
+        })
+    }
+}
+
+impl SagaService for PricingServiceImpl {
+    type Stage = RepriceOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.reprice_revert().map(|(s, _)| s))
+    }
+}
+
+impl PricingService for PricingServiceImpl {
+    fn reprice(self, store_id: StoreId, input: RepriceInput) -> ServiceFuture<Box<PricingService>, SagaResponse<RepriceResult>> {
+        debug!("Reprice products of store {}, input: {:?}", store_id, input);
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::Reprice,
+            deadline,
+            move |s| s.reprice_happy(store_id, input),
+            &["prices"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<PricingService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<PricingService>, e)),
+        )
+    }
+}
@@ -0,0 +1,56 @@
+//! Stock steps shared by sagas returning items of orders to warehouses: cancellation, returns and disputes
+use std::sync::Arc;
+
+use failure::Error as FailureError;
+use futures::future::{self, Either};
+use futures::prelude::*;
+
+use stq_types::{OrderId, OrderIdentifier, SagaId};
+
+use microservice::*;
+use models::*;
+
+/// Takes items of the order back from stock, adjustment is keyed by saga so repeated compensation takes them once.
+/// Stock return interrupted before its response is probed in warehouses, it is reverted only if warehouses applied it.
+pub fn revert_stock_return(
+    orders_microservice: Arc<OrdersMicroservice>,
+    warehouses_microservice: Arc<WarehousesMicroservice>,
+    saga_id: SagaId,
+    order_id: OrderId,
+    phase: StepPhase,
+) -> Box<Future<Item = (), Error = FailureError>> {
+    Box::new(
+        orders_microservice
+            .get_order(Some(Initiator::Superadmin), OrderIdentifier::Id(order_id))
+            .and_then(move |order| match order {
+                Some(order) => {
+                    let product_id = order.product;
+                    let adjustment = StockAdjustment {
+                        saga_id,
+                        kind: StockAdjustmentKind::ReturnReverted,
+                        quantity: order.quantity,
+                    };
+                    let is_returned = match phase {
+                        StepPhase::Complete => Either::A(future::ok(true)),
+                        StepPhase::Start => Either::B(
+                            warehouses_microservice
+                                .find_stock_adjustments(Initiator::Superadmin, product_id, saga_id)
+                                .map(|adjustments| adjustments.iter().any(|adjustment| adjustment.kind == StockAdjustmentKind::Return)),
+                        ),
+                    };
+                    Either::A(is_returned.and_then(move |is_returned| {
+                        if !is_returned {
+                            debug!("Stock return of order {} has not taken effect, nothing to revert", order_id);
+                            return Either::A(future::ok(()));
+                        }
+                        Either::B(
+                            warehouses_microservice
+                                .adjust_stock(Initiator::Superadmin, product_id, adjustment)
+                                .map(|_| ()),
+                        )
+                    }))
+                }
+                None => Either::B(future::err(format_err!("Order {} not found", order_id))),
+            }),
+    )
+}
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use failure::Error as FailureError;
 use failure::Fail;
@@ -7,13 +9,11 @@ use futures;
 use futures::future::{self, Either};
 use futures::prelude::*;
 use futures::stream::iter_ok;
-use hyper::header::Authorization;
-use hyper::Headers;
 use uuid::Uuid;
 
 use stq_types::{
     BaseProductId, BillingRole, DeliveryRole, OrderRole, ProductId, Quantity, RoleEntryId, RoleId, SagaId, StoreId, TransactionId, UserId,
-    WarehouseRole,
+    WarehouseId, WarehouseRole,
 };
 
 use stq_static_resources::{
@@ -21,29 +21,52 @@ use stq_static_resources::{
     StoreModerationStatusForModerator, StoreModerationStatusForUser,
 };
 
+use super::carts::{CartsCleanup, CustomersNotifier};
+use super::initiator::InitiatorPolicy;
 use super::parse_validation_errors;
 use config;
 use errors::Error;
 use microservice::*;
 use models::*;
+use moderation::rules::matching_rule;
+use moderation::ModerationQueue;
+use saga::{self, run_steps, soft_step, SagaLog, SagaService, SagaStore, StepFuture};
+use scrubbing::Scrubbed;
 use services::types::ServiceFuture;
 
 pub trait StoreService {
-    fn create(self, input: NewStore) -> ServiceFuture<Box<StoreService>, Option<Store>>;
+    fn create(self, input: NewStore) -> ServiceFuture<Box<StoreService>, SagaResponse<Option<Store>>>;
     /// Set moderation status for specific store
-    fn set_store_moderation_status(self, payload: StoreModerate) -> ServiceFuture<Box<StoreService>, Store>;
+    fn set_store_moderation_status(self, payload: StoreModerate) -> ServiceFuture<Box<StoreService>, CartsCleanupResult<Store>>;
     /// Send store to moderation from store manager
     fn send_to_moderation(self, store_id: StoreId) -> ServiceFuture<Box<StoreService>, Store>;
     /// Set moderation status for base_product_id
-    fn set_moderation_status_base_product(self, payload: BaseProductModerate) -> ServiceFuture<Box<StoreService>, ()>;
+    fn set_moderation_status_base_product(
+        self,
+        payload: BaseProductModerate,
+    ) -> ServiceFuture<Box<StoreService>, CartsCleanupResult<BaseProduct>>;
     /// send base product to moderation from store manager
     fn send_to_moderation_base_product(self, base_product_id: BaseProductId) -> ServiceFuture<Box<StoreService>, ()>;
     /// Deactivate base product
-    fn deactivate_base_product(self, base_product_id: BaseProductId) -> ServiceFuture<Box<StoreService>, BaseProduct>;
-    /// Deactivate store
-    fn deactivate_store(self, store: StoreId) -> ServiceFuture<Box<StoreService>, Store>;
+    fn deactivate_base_product(
+        self,
+        base_product_id: BaseProductId,
+        payload: Deactivation,
+    ) -> ServiceFuture<Box<StoreService>, CartsCleanupResult<BaseProduct>>;
+    /// Deactivate store, store manager is told the reason
+    fn deactivate_store(self, store: StoreId, payload: Deactivation) -> ServiceFuture<Box<StoreService>, CartsCleanupResult<Store>>;
     /// Deactivate product
-    fn deactivate_product(self, product_id: ProductId) -> ServiceFuture<Box<StoreService>, Product>;
+    fn deactivate_product(
+        self,
+        product_id: ProductId,
+        payload: Deactivation,
+    ) -> ServiceFuture<Box<StoreService>, CartsCleanupResult<Product>>;
+    /// Activate store deactivated before, moderation and shipping of its base products are restored
+    fn activate_store(self, store_id: StoreId, payload: Activation) -> ServiceFuture<Box<StoreService>, Store>;
+    /// Activate base product deactivated before, moderation and shipping are restored
+    fn activate_base_product(self, base_product_id: BaseProductId, payload: Activation) -> ServiceFuture<Box<StoreService>, BaseProduct>;
+    /// Activate product deactivated before
+    fn activate_product(self, product_id: ProductId, payload: Activation) -> ServiceFuture<Box<StoreService>, Product>;
     /// Update base product
     fn update_base_product(
         self,
@@ -53,6 +76,7 @@ pub trait StoreService {
     fn create_base_product_with_variants(self, payload: NewBaseProductWithVariants) -> ServiceFuture<Box<StoreService>, BaseProduct>;
 }
 
+#[derive(Clone)]
 pub struct StoreServiceImpl {
     pub orders_microservice: Arc<OrdersMicroservice>,
     pub stores_microservice: Arc<StoresMicroservice>,
@@ -62,12 +86,19 @@ pub struct StoreServiceImpl {
     pub delivery_microservice: Arc<DeliveryMicroservice>,
     pub users_microservice: Arc<UsersMicroservice>,
     pub config: config::Config,
-    pub log: Arc<Mutex<CreateStoreOperationLog>>,
+    pub log: Rc<SagaLog<CreateStoreOperationStage>>,
+    pub moderation_queue: Arc<ModerationQueue>,
+    pub initiators: InitiatorPolicy,
+    pub carts_cleanup: CartsCleanup,
+    /// Files of deactivated stores and products are left in storage if not set
+    pub files_microservice: Option<Arc<FilesMicroservice>>,
 }
 
 impl StoreServiceImpl {
     pub fn new(
         config: config::Config,
+        saga_store: Arc<SagaStore>,
+        moderation_queue: Arc<ModerationQueue>,
         orders_microservice: Arc<OrdersMicroservice>,
         stores_microservice: Arc<StoresMicroservice>,
         notifications_microservice: Arc<NotificationsMicroservice>,
@@ -76,7 +107,16 @@ impl StoreServiceImpl {
         users_microservice: Arc<UsersMicroservice>,
         delivery_microservice: Arc<DeliveryMicroservice>,
     ) -> Self {
-        let log = Arc::new(Mutex::new(CreateStoreOperationLog::new()));
+        let log = Rc::new(SagaLog::new(saga_store));
+        let carts_cleanup = if config.features.unavailable_cart_products_notifications {
+            CartsCleanup::default().with_notifier(CustomersNotifier {
+                users_microservice: users_microservice.clone(),
+                notifications_microservice: notifications_microservice.clone(),
+                cluster_url: config.cluster.url.clone(),
+            })
+        } else {
+            CartsCleanup::default()
+        };
         Self {
             config,
             log,
@@ -87,15 +127,93 @@ impl StoreServiceImpl {
             warehouses_microservice,
             users_microservice,
             delivery_microservice,
+            moderation_queue,
+            initiators: InitiatorPolicy::default(),
+            carts_cleanup,
+            files_microservice: None,
         }
     }
 
+    /// Schedules cleanup of files of deactivated stores and products, cleanup is cancelled by activation
+    pub fn with_files_microservice(mut self, files_microservice: Arc<FilesMicroservice>) -> Self {
+        self.files_microservice = Some(files_microservice);
+        self
+    }
+
+    /// Performs steps of sagas on behalf of the caller
+    pub fn with_caller(mut self, caller: Option<Initiator>) -> Self {
+        self.initiators = InitiatorPolicy::new(caller);
+        self
+    }
+
+    /// Reminds moderators about item waiting for moderation
+    pub fn remind_moderators(self, pending: PendingModeration) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        match pending.item {
+            ModerationItem::Store(store_id) => {
+                Either::A(self.notify_moderators_store_update_moderation_status(store_id, ModerationStatus::Moderation))
+            }
+            ModerationItem::BaseProduct(base_product_id) => Either::B(self.notify_moderators_base_product_update_moderation_status(
+                pending.store_id,
+                base_product_id,
+                ModerationStatus::Moderation,
+            )),
+        }
+    }
+
+    /// Name of auto-approval rule matching base product sent to moderation
+    fn auto_approval_rule(&self, base_product: &BaseProduct) -> Option<String> {
+        match base_product.status {
+            ModerationStatus::Moderation => {}
+            _ => return None,
+        }
+        let edited_fields = self.moderation_queue.edited_fields(base_product.id).unwrap_or_else(|e| {
+            error!("Could not get edits of base product {}: {}", base_product.id, e);
+            None
+        });
+        matching_rule(
+            &self.config.moderation.auto_approval,
+            base_product.store_id,
+            base_product.category_id,
+            edited_fields.as_ref().map(Vec::as_slice),
+        )
+        .map(|rule| rule.name.clone())
+    }
+
+    /// Keeps moderation queue in sync with moderation status of the item
+    fn track_moderation(&self, item: ModerationItem, store_id: StoreId, status: ModerationStatus) {
+        let res = match status {
+            ModerationStatus::Moderation => self.moderation_queue.enter(item, store_id),
+            _ => self.moderation_queue.leave(item),
+        };
+        if let Err(e) = res {
+            error!("Could not update moderation queue with {:?}: {}", item, e);
+        }
+    }
+
+    /// Adds deactivation to moderation trail along with the caller who made it
+    fn track_deactivation(&self, item: DeactivatedItem, deactivation: Deactivation) {
+        let record = DeactivationRecord::new(item, deactivation, self.initiators.caller_id());
+        if let Err(e) = self.moderation_queue.record_deactivation(record) {
+            error!("Could not record deactivation of {:?} in moderation trail: {}", item, e);
+        }
+    }
+
+    /// Locks entity for the rest of the request, other sagas changing it get `409 Conflict` meanwhile
+    fn lock(self, entity: LockedEntity) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let lease = Duration::from_secs(self.config.saga.reaper_stale_after_s);
+        self.log.lock(entity, lease).then(|res| match res {
+            Ok(()) => Ok(self),
+            Err(e) => Err((self, e)),
+        })
+    }
+
     fn create_store(self, input: &NewStore, saga_id: SagaId) -> ServiceFuture<Self, Store> {
         // Create Store
-        debug!("Creating store, input: {:?}", input);
+        debug!("Creating store, input: {:?}", Scrubbed(&input));
 
         let log = self.log.clone();
-        log.lock().unwrap().push(CreateStoreOperationStage::StoreCreationStart(saga_id));
+        let lease = Duration::from_secs(self.config.saga.reaper_stale_after_s);
+        log.push(CreateStoreOperationStage::StoreCreationStart(saga_id));
 
         let res = self
             .stores_microservice
@@ -107,8 +225,14 @@ impl StoreServiceImpl {
                 },
             )
             .and_then(move |store| {
-                log.lock().unwrap().push(CreateStoreOperationStage::StoreCreationComplete(store.id));
-                Ok(store)
+                log.push_with_result(CreateStoreOperationStage::StoreCreationComplete(store.id), &store);
+                // the store is locked until compensations of the saga finish
+                log.lock(LockedEntity::Store(store.id), lease).then(move |res| {
+                    if let Err(e) = res {
+                        warn!("Store {} created by saga {} is not locked: {}", store.id, saga_id, e);
+                    }
+                    Ok::<_, FailureError>(store)
+                })
             })
             .then(|res| match res {
                 Ok(store) => Ok((self, store)),
@@ -130,17 +254,17 @@ impl StoreServiceImpl {
         };
         let role = RoleEntry::<NewWarehouseRole>::new(new_role_id, user_id, role_payload);
 
-        log.lock()
-            .unwrap()
-            .push(CreateStoreOperationStage::WarehousesRoleSetStart(new_role_id));
+        let initiator = match self.initiators.caller("warehouses_role_set") {
+            Ok(initiator) => initiator,
+            Err(e) => return Box::new(future::err((self, e))),
+        };
+        log.push(CreateStoreOperationStage::WarehousesRoleSetStart(new_role_id));
 
         let res = self
             .warehouses_microservice
-            .create_warehouse_role(Some(Initiator::Superadmin), role)
+            .create_warehouse_role(Some(initiator), role)
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateStoreOperationStage::WarehousesRoleSetComplete(new_role_id));
+                log.push_with_result(CreateStoreOperationStage::WarehousesRoleSetComplete(new_role_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -163,15 +287,17 @@ impl StoreServiceImpl {
         };
         let role = RoleEntry::<NewOrdersRole>::new(new_role_id, user_id, role_payload);
 
-        log.lock().unwrap().push(CreateStoreOperationStage::OrdersRoleSetStart(new_role_id));
+        let initiator = match self.initiators.caller("orders_role_set") {
+            Ok(initiator) => initiator,
+            Err(e) => return Box::new(future::err((self, e))),
+        };
+        log.push(CreateStoreOperationStage::OrdersRoleSetStart(new_role_id));
 
         let res = self
             .orders_microservice
-            .create_role(Some(Initiator::Superadmin), role.clone())
+            .create_role(Some(initiator), role.clone())
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateStoreOperationStage::OrdersRoleSetComplete(new_role_id));
+                log.push_with_result(CreateStoreOperationStage::OrdersRoleSetComplete(new_role_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -190,17 +316,17 @@ impl StoreServiceImpl {
         let new_role_id = RoleId::new();
         let role = NewRole::<BillingRole>::new(new_role_id, user_id, BillingRole::StoreManager, Some(store_id));
 
-        log.lock()
-            .unwrap()
-            .push(CreateStoreOperationStage::BillingRoleSetStart(new_role_id));
+        let initiator = match self.initiators.caller("billing_role_set") {
+            Ok(initiator) => initiator,
+            Err(e) => return Box::new(future::err((self, e))),
+        };
+        log.push(CreateStoreOperationStage::BillingRoleSetStart(new_role_id));
 
         let res = self
             .billing_microservice
-            .create_role(Some(Initiator::Superadmin), role)
+            .create_role(Some(initiator), role)
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateStoreOperationStage::BillingRoleSetComplete(new_role_id));
+                log.push_with_result(CreateStoreOperationStage::BillingRoleSetComplete(new_role_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -219,22 +345,22 @@ impl StoreServiceImpl {
         let new_role_id = RoleId::new();
         let role = NewRole::<DeliveryRole>::new(new_role_id, user_id, DeliveryRole::StoreManager, Some(store_id));
 
-        log.lock()
-            .unwrap()
-            .push(CreateStoreOperationStage::DeliveryRoleSetStart(new_role_id));
+        let initiator = match self.initiators.caller("delivery_role_set") {
+            Ok(initiator) => initiator,
+            Err(e) => return Box::new(future::err((self, e))),
+        };
+        log.push(CreateStoreOperationStage::DeliveryRoleSetStart(new_role_id));
 
         let res = self
             .delivery_microservice
-            .create_delivery_role(Some(Initiator::Superadmin), role)
+            .create_delivery_role(Some(initiator), role)
             .map_err(|e| {
                 e.context("Creating role in delivery microservice failed.")
                     .context(Error::HttpClient)
                     .into()
             })
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateStoreOperationStage::DeliveryRoleSetComplete(new_role_id));
+                log.push_with_result(CreateStoreOperationStage::DeliveryRoleSetComplete(new_role_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -254,17 +380,18 @@ impl StoreServiceImpl {
 
         // Create store role
         let log = self.log.clone();
-        log.lock()
-            .unwrap()
-            .push(CreateStoreOperationStage::BillingCreateMerchantStart(store_id));
+        let initiator = self.initiators.superadmin(
+            &log,
+            "billing_create_merchant",
+            "merchants are created only by billing administrators",
+        );
+        log.push(CreateStoreOperationStage::BillingCreateMerchantStart(store_id));
 
         let res = self
             .billing_microservice
-            .create_store_merchant(Some(Initiator::Superadmin), payload)
+            .create_store_merchant(Some(initiator), payload)
             .and_then(move |res| {
-                log.lock()
-                    .unwrap()
-                    .push(CreateStoreOperationStage::BillingCreateMerchantComplete(store_id));
+                log.push_with_result(CreateStoreOperationStage::BillingCreateMerchantComplete(store_id), &res);
                 Ok(res)
             })
             .then(|res| match res {
@@ -275,113 +402,155 @@ impl StoreServiceImpl {
         Box::new(res)
     }
 
+    // Executes step of store creation configured in `saga.definitions.create_store`,
+    // every step is listed in `config::CREATE_STORE_STEPS`
+    fn run_step(self, step: &str, store: &Store) -> StepFuture<Self> {
+        match step {
+            "warehouses_role_set" => Box::new(self.create_warehouses_role(store.user_id, store.id).map(|(s, _)| (s, ()))),
+            "orders_role_set" => Box::new(self.create_orders_role(store.user_id, store.id).map(|(s, _)| (s, ()))),
+            "billing_role_set" => Box::new(self.create_billing_role(store.user_id, store.id).map(|(s, _)| (s, ()))),
+            "delivery_role_set" => Box::new(self.create_delivery_role(store.user_id, store.id).map(|(s, _)| (s, ()))),
+            "billing_create_merchant" => Box::new(self.create_merchant(store.id, store.country_code.clone()).map(|(s, _)| (s, ()))),
+            _ => Box::new(future::err((self, format_err!("Unknown step {} of store creation saga", step)))),
+        }
+    }
+
     // Contains happy path for Store creation
     fn create_happy(self, input: &NewStore) -> ServiceFuture<Self, Store> {
-        let saga_id = SagaId::new();
+        let saga_id = self.log.saga_id();
+        self.log.start(SagaType::CreateStore);
+        let input = input.clone();
         Box::new(
-            self.create_store(&input, saga_id)
+            self.check_duplicates(&input)
+                .and_then(move |s| s.create_store(&input, saga_id))
                 .and_then(|(s, store)| {
-                    let user_id = store.user_id;
-                    let store_id = store.id;
-                    s.create_warehouses_role(user_id, store_id).map(|(s, _)| (s, store))
-                })
-                .and_then(|(s, store)| {
-                    let user_id = store.user_id;
-                    let store_id = store.id;
-                    s.create_orders_role(user_id, store_id).map(|(s, _)| (s, store))
-                })
-                .and_then(|(s, store)| {
-                    let user_id = store.user_id;
-                    let store_id = store.id;
-                    s.create_billing_role(user_id, store_id).map(|(s, _)| (s, store))
-                })
-                .and_then(|(s, store)| {
-                    let user_id = store.user_id;
-                    let store_id = store.id;
-                    s.create_delivery_role(user_id, store_id).map(|(s, _)| (s, store))
-                })
-                .and_then(|(s, store)| s.create_merchant(store.id, store.country_code.clone()).map(|(s, _)| (s, store))),
+                    let definition = s.config.saga.definitions.create_store.clone();
+                    let created = store.clone();
+                    let log = s.log.clone();
+                    run_steps(s, log, definition, move |s, step| s.run_step(step, &created)).map(|s| (s, store))
+                }),
         )
     }
 
-    // Contains reversal of Store creation
-    fn create_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
-        let log = self.log.lock().unwrap().clone();
+    /// Looks for existing stores of the user with similar name or slug, so that duplicates
+    /// do not fail on unique constraints of stores after the saga is well under way.
+    /// Failed lookup does not stop store creation.
+    fn check_duplicates(self, input: &NewStore) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let action = match self.config.duplicate_stores {
+            Some(ref duplicate_stores) => duplicate_stores.action,
+            None => return Either::A(future::ok(self)),
+        };
+        let input = input.clone();
+
+        Either::B(
+            self.stores_microservice
+                .search_stores(None, SearchStoresPayload { user_id: input.user_id })
+                .then(move |res| {
+                    let duplicate_ids = match res {
+                        Ok(stores) => stores
+                            .iter()
+                            .filter(|store| input.is_likely_duplicate_of(store))
+                            .map(|store| store.id.to_string())
+                            .collect::<Vec<_>>(),
+                        Err(e) => {
+                            self.log.warn("stores_duplicate_check", &e);
+                            return Ok(self);
+                        }
+                    };
+                    if duplicate_ids.is_empty() {
+                        return Ok(self);
+                    }
+
+                    let message = format!("Store looks like a duplicate of stores {}", duplicate_ids.join(", "));
+                    match action {
+                        config::DuplicateStoreAction::Flag => {
+                            self.log.warn("stores_duplicate_check", &format_err!("{}", message));
+                            Ok(self)
+                        }
+                        config::DuplicateStoreAction::Reject => {
+                            Err((self, Error::Validate(validation_errors!({"slug": ["duplicate" => message]})).into()))
+                        }
+                    }
+                }),
+        )
+    }
 
+    // Contains reversal of Store creation
+    pub fn create_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
         let orders_microservice = self.orders_microservice.clone();
         let stores_microservice = self.stores_microservice.clone();
         let billing_microservice = self.billing_microservice.clone();
         let warehouses_microservice = self.warehouses_microservice.clone();
         let delivery_microservice = self.delivery_microservice.clone();
-        let fut = iter_ok::<_, ()>(log).for_each(move |e| {
-            match e {
-                // TODO: probably pass saga ID on store creation and delete store by saga ID here (requires changes in saga-coordinator and stores microservices).
-                CreateStoreOperationStage::StoreCreationStart(saga_id) => {
-                    debug!("Reverting store, saga_id: {}", saga_id);
-                    Box::new(
-                        stores_microservice
-                            .deactivate_store_by_saga_id(Some(Initiator::Superadmin), saga_id)
-                            .then(|_| Ok(())),
-                    ) as Box<Future<Item = (), Error = ()>>
-                }
-
-                CreateStoreOperationStage::WarehousesRoleSetStart(role_id) => {
-                    debug!("Reverting warehouses role, user_id: {}", role_id);
-                    let mut headers = Headers::new();
-                    headers.set(Authorization("1".to_string())); // only super admin delete user role
-
-                    Box::new(
-                        warehouses_microservice
-                            .delete_warehouse_role(Some(Initiator::Superadmin), role_id)
-                            .then(|_| Ok(())),
-                    ) as Box<Future<Item = (), Error = ()>>
-                }
+        let compensation = self.log.compensate(move |e, _| match e {
+            CreateStoreOperationStage::StoreCreationStart(saga_id) => {
+                debug!("Reverting store, saga_id: {}", saga_id);
+                let stores_microservice = stores_microservice.clone();
+                Box::new(
+                    stores_microservice
+                        .get_store_by_saga_id(Some(Initiator::Superadmin), saga_id)
+                        .and_then(move |store| match store {
+                            Some(ref store) if store.is_active => Box::new(
+                                stores_microservice
+                                    .deactivate_store_by_saga_id(Some(Initiator::Superadmin), saga_id)
+                                    .map(|_| ()),
+                            )
+                                as Box<Future<Item = (), Error = FailureError>>,
+                            _ => {
+                                debug!("No active store with saga_id {}, nothing to revert", saga_id);
+                                Box::new(future::ok(()))
+                            }
+                        }),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
 
-                CreateStoreOperationStage::OrdersRoleSetStart(role_id) => {
-                    debug!("Reverting orders role, user_id: {}", role_id);
-                    Box::new(
-                        orders_microservice
-                            .delete_role(Some(Initiator::Superadmin), role_id)
-                            .then(|_| Ok(())),
-                    ) as Box<Future<Item = (), Error = ()>>
-                }
+            CreateStoreOperationStage::WarehousesRoleSetStart(role_id) => {
+                debug!("Reverting warehouses role, user_id: {}", role_id);
+                Box::new(
+                    warehouses_microservice
+                        .delete_warehouse_role(Some(Initiator::Superadmin), role_id)
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
 
-                CreateStoreOperationStage::BillingRoleSetStart(role_id) => {
-                    debug!("Reverting billing role, user_id: {}", role_id);
+            CreateStoreOperationStage::OrdersRoleSetStart(role_id) => {
+                debug!("Reverting orders role, user_id: {}", role_id);
+                Box::new(orders_microservice.delete_role(Some(Initiator::Superadmin), role_id).map(|_| ()))
+                    as Box<Future<Item = (), Error = FailureError>>
+            }
 
-                    Box::new(
-                        billing_microservice
-                            .delete_role(Some(Initiator::Superadmin), role_id)
-                            .then(|_| Ok(())),
-                    ) as Box<Future<Item = (), Error = ()>>
-                }
+            CreateStoreOperationStage::BillingRoleSetStart(role_id) => {
+                debug!("Reverting billing role, user_id: {}", role_id);
 
-                CreateStoreOperationStage::DeliveryRoleSetStart(role_id) => {
-                    debug!("Reverting delivery role, role_id: {}", role_id);
-                    Box::new(
-                        delivery_microservice
-                            .delete_delivery_role(Some(Initiator::Superadmin), role_id)
-                            .then(|_| Ok(())),
-                    ) as Box<Future<Item = (), Error = ()>>
-                }
+                Box::new(billing_microservice.delete_role(Some(Initiator::Superadmin), role_id).map(|_| ()))
+                    as Box<Future<Item = (), Error = FailureError>>
+            }
 
-                CreateStoreOperationStage::BillingCreateMerchantStart(store_id) => {
-                    debug!("Reverting merchant, store_id: {}", store_id);
+            CreateStoreOperationStage::DeliveryRoleSetStart(role_id) => {
+                debug!("Reverting delivery role, role_id: {}", role_id);
+                Box::new(
+                    delivery_microservice
+                        .delete_delivery_role(Some(Initiator::Superadmin), role_id)
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
 
-                    Box::new(
-                        billing_microservice
-                            .delete_store_merchant(Some(Initiator::Superadmin), store_id)
-                            .then(|_| Ok(())),
-                    ) as Box<Future<Item = (), Error = ()>>
-                }
+            CreateStoreOperationStage::BillingCreateMerchantStart(store_id) => {
+                debug!("Reverting merchant, store_id: {}", store_id);
 
-                _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = ()>>,
+                Box::new(
+                    billing_microservice
+                        .delete_store_merchant(Some(Initiator::Superadmin), store_id)
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
             }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
         });
 
-        fut.then(|res| match res {
-            Ok(_) => Ok((self, ())),
-            Err(_) => Err((self, format_err!("Order service create_revert error occurred."))),
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((self, format_err!("Store service create_revert error occurred: {}", e))),
         })
     }
 
@@ -528,6 +697,43 @@ impl StoreServiceImpl {
         })
     }
 
+    fn notify_manager_store_deactivation(
+        self,
+        store_id: StoreId,
+        store_manager_id: UserId,
+        reason: Option<String>,
+    ) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let cluster_url = self.config.cluster.url.clone();
+        let appeal_url = self.config.notification_urls.store_appeal(store_id);
+        let notifications_microservice = self.notifications_microservice.clone();
+
+        let fut = self
+            .users_microservice
+            .get(Some(Initiator::Superadmin), store_manager_id)
+            .and_then(move |store_manager| match store_manager {
+                Some(user) => {
+                    let email = StoreDeactivatedForUser {
+                        store_email: user.email.to_string(),
+                        store_id: store_id.to_string(),
+                        reason,
+                        appeal_url,
+                        cluster_url,
+                    };
+                    Either::A(notifications_microservice.store_deactivated_for_user(Initiator::Superadmin, email))
+                }
+                None => Either::B(future::err(format_err!(
+                    "Manager {} of store {} not found",
+                    store_manager_id,
+                    store_id
+                ))),
+            });
+
+        fut.then(|res| match res {
+            Ok(_) => Ok((self, ())),
+            Err(e) => Err((self, e)),
+        })
+    }
+
     fn notify_manager_base_product_update_moderation_status(
         self,
         store_id: StoreId,
@@ -649,24 +855,12 @@ impl StoreServiceImpl {
         initial_status: ModerationStatus,
         status: ModerationStatus,
     ) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
-        let stores_microservice = self.stores_microservice.clone();
-        let orders_microservice = self.orders_microservice.clone();
-        let res: Box<Future<Item = (), Error = FailureError>> = if is_status_change_requires_to_delete_product(initial_status, status) {
-            let fut = stores_microservice
-                .get_products_by_base_product(base_product_id)
-                .map(|products| DeleteProductsFromCartsPayload {
-                    product_ids: products.into_iter().map(|p| p.id).collect(),
-                })
-                .and_then(move |payload| orders_microservice.delete_products_from_all_carts(Some(Initiator::Superadmin), payload));
-            Box::new(fut)
+        if is_status_change_requires_to_delete_product(initial_status, status) {
+            Either::A(self.remove_products_from_cart_after_base_product_deactivation(base_product_id))
         } else {
             //do nothing
-            Box::new(Ok(()).into_future())
-        };
-        res.then(|res| match res {
-            Ok(_) => Ok((self, ())),
-            Err(err) => Err((self, err)),
-        })
+            Either::B(future::ok((self, ())))
+        }
     }
 
     fn remove_products_from_cart_after_store_status_change(
@@ -675,60 +869,200 @@ impl StoreServiceImpl {
         initial_status: ModerationStatus,
         status: ModerationStatus,
     ) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
-        let stores_microservice = self.stores_microservice.clone();
-        let orders_microservice = self.orders_microservice.clone();
-        let res: Box<Future<Item = (), Error = FailureError>> = if is_status_change_requires_to_delete_product(initial_status, status) {
-            let fut = stores_microservice
-                .get_products_by_store(store_id)
-                .map(|products| DeleteProductsFromCartsPayload {
-                    product_ids: products.into_iter().map(|p| p.id).collect(),
-                })
-                .and_then(move |payload| orders_microservice.delete_products_from_all_carts(Some(Initiator::Superadmin), payload));
-            Box::new(fut)
+        if is_status_change_requires_to_delete_product(initial_status, status) {
+            Either::A(self.remove_products_from_cart_after_store_deactivation(store_id))
         } else {
             //do nothing
-            Box::new(Ok(()).into_future())
-        };
-        res.then(|res| match res {
-            Ok(_) => Ok((self, ())),
-            Err(err) => Err((self, err)),
-        })
+            Either::B(future::ok((self, ())))
+        }
     }
 
+    /// Collects products of base product, they are removed from carts along with their delivery methods and from wishlists
+    /// by `cleanup_carts`
     fn remove_products_from_cart_after_base_product_deactivation(
         self,
         base_product_id: BaseProductId,
     ) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
-        let stores_microservice = self.stores_microservice.clone();
-        let orders_microservice = self.orders_microservice.clone();
-        stores_microservice
+        self.products_of_base_product(base_product_id).map(|(s, product_ids)| {
+            s.carts_cleanup.clear_delivery_methods(product_ids.clone());
+            s.carts_cleanup.remove_from_wishlists(product_ids.clone());
+            s.carts_cleanup.remove_products(product_ids);
+            (s, ())
+        })
+    }
+
+    /// Collects products of store, they are removed from carts along with their delivery methods and from wishlists
+    /// by `cleanup_carts`
+    fn remove_products_from_cart_after_store_deactivation(
+        self,
+        store_id: StoreId,
+    ) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        self.stores_microservice.get_products_by_store(store_id).then(|res| match res {
+            Ok(products) => {
+                let product_ids = products.into_iter().map(|p| p.id).collect::<Vec<_>>();
+                self.carts_cleanup.clear_delivery_methods(product_ids.clone());
+                self.carts_cleanup.remove_from_wishlists(product_ids.clone());
+                self.carts_cleanup.remove_products(product_ids);
+                Ok((self, ()))
+            }
+            Err(err) => Err((self, err)),
+        })
+    }
+
+    fn products_of_base_product(
+        self,
+        base_product_id: BaseProductId,
+    ) -> impl Future<Item = (Self, Vec<ProductId>), Error = (Self, FailureError)> {
+        self.stores_microservice
             .get_products_by_base_product(base_product_id)
-            .map(|products| DeleteProductsFromCartsPayload {
-                product_ids: products.into_iter().map(|p| p.id).collect(),
+            .then(|res| match res {
+                Ok(products) => Ok((self, products.into_iter().map(|p| p.id).collect())),
+                Err(err) => Err((self, err)),
+            })
+    }
+
+    /// Cleans up carts and wishlists with everything collected through the saga, numbers of affected products are added to the result
+    fn cleanup_carts<T>(self, result: T) -> impl Future<Item = (Self, CartsCleanupResult<T>), Error = (Self, FailureError)> {
+        self.cleanup_wishlists().and_then(|s| {
+            s.carts_cleanup.flush(&s.orders_microservice, result).then(|res| match res {
+                Ok(result) => Ok((s, result)),
+                Err(err) => Err((s, err)),
+            })
+        })
+    }
+
+    /// Wishlists are cleaned up in a soft step, products left in them do not fail the saga
+    fn cleanup_wishlists(self) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let fut = self.carts_cleanup.flush_wishlists(&self.stores_microservice).then(|res| match res {
+            Ok(removed) => Ok((self, removed)),
+            Err(err) => Err((self, err)),
+        });
+        soft_step(log, "wishlists_cleanup", fut)
+    }
+
+    /// Moderation interrupted by deactivation is resumed, moderators are reminded about the item in a soft step
+    fn resume_moderation(
+        self,
+        item: ModerationItem,
+        store_id: StoreId,
+        status: ModerationStatus,
+    ) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        if status != ModerationStatus::Moderation {
+            return Either::A(future::ok(self));
+        }
+        self.track_moderation(item, store_id, status);
+        let log = self.log.clone();
+        let fut = match item {
+            ModerationItem::Store(store_id) => Either::A(self.notify_moderators_store_update_moderation_status(store_id, status)),
+            ModerationItem::BaseProduct(base_product_id) => {
+                Either::B(self.notify_moderators_base_product_update_moderation_status(store_id, base_product_id, status))
+            }
+        };
+        Either::B(soft_step(log, "moderation_resumption", fut))
+    }
+
+    /// Shipping deleted along with deactivation is restored in a soft step, base products without shipping can be edited later
+    fn restore_shipping(self, base_product_ids: Vec<BaseProductId>) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let delivery_microservice = self.delivery_microservice.clone();
+        let fut = iter_ok::<_, FailureError>(base_product_ids)
+            .for_each(move |base_product_id| {
+                delivery_microservice.restore_shipping_by_base_product(Some(Initiator::Superadmin), base_product_id)
             })
-            .and_then(move |payload| orders_microservice.delete_products_from_all_carts(Some(Initiator::Superadmin), payload))
             .then(|res| match res {
                 Ok(_) => Ok((self, ())),
                 Err(err) => Err((self, err)),
-            })
+            });
+        soft_step(log, "shipping_restoration", fut)
     }
 
-    fn remove_products_from_cart_after_store_deactivation(
+    /// Puts zero stocks into warehouse of the store for products having no stocks at all, if asked to
+    fn add_stock_placeholders(
         self,
+        payload: &Activation,
         store_id: StoreId,
-    ) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
-        let stores_microservice = self.stores_microservice.clone();
-        let orders_microservice = self.orders_microservice.clone();
-        stores_microservice
-            .get_products_by_store(store_id)
-            .map(|products| DeleteProductsFromCartsPayload {
-                product_ids: products.into_iter().map(|p| p.id).collect(),
+        product_ids: Vec<ProductId>,
+    ) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        if !payload.stock_placeholders || product_ids.is_empty() {
+            return Either::A(future::ok(self));
+        }
+        let log = self.log.clone();
+        let warehouses_microservice = self.warehouses_microservice.clone();
+        let fut = store_warehouse_id(warehouses_microservice.clone(), store_id)
+            .and_then(move |warehouse_id| {
+                iter_ok::<_, FailureError>(product_ids).for_each(move |product_id| {
+                    let warehouses_microservice = warehouses_microservice.clone();
+                    warehouses_microservice
+                        .find_by_product_id(Initiator::Superadmin, product_id)
+                        .and_then(move |stocks| {
+                            if stocks.is_empty() {
+                                Either::A(
+                                    warehouses_microservice
+                                        .set_product_in_warehouse(Initiator::Superadmin, warehouse_id, product_id, Quantity(0))
+                                        .map(|_| ()),
+                                )
+                            } else {
+                                Either::B(future::ok(()))
+                            }
+                        })
+                })
             })
-            .and_then(move |payload| orders_microservice.delete_products_from_all_carts(Some(Initiator::Superadmin), payload))
             .then(|res| match res {
                 Ok(_) => Ok((self, ())),
                 Err(err) => Err((self, err)),
+            });
+        Either::B(soft_step(log, "stock_placeholders", fut))
+    }
+
+    /// Files no longer shown after deactivation are scheduled for deletion in a soft step, files client retries
+    /// scheduling on transient failures. Stores taken down for legal reasons keep their files under legal hold.
+    fn schedule_files_cleanup<F>(self, files: F) -> impl Future<Item = Self, Error = (Self, FailureError)>
+    where
+        F: Future<Item = Vec<String>, Error = FailureError> + 'static,
+    {
+        let delay_s = self.config.files.as_ref().map(|files_config| files_config.cleanup_delay_s);
+        let (files_microservice, delay_s) = match (self.files_microservice.clone(), delay_s) {
+            (Some(files_microservice), Some(delay_s)) => (files_microservice, delay_s),
+            _ => return Either::A(future::ok(self)),
+        };
+        let log = self.log.clone();
+        let fut = files
+            .and_then(move |urls| {
+                if urls.is_empty() {
+                    return Either::A(future::ok(()));
+                }
+                Either::B(files_microservice.schedule_cleanup(Initiator::Superadmin, FilesCleanup { urls, delay_s }))
             })
+            .then(|res| match res {
+                Ok(_) => Ok((self, ())),
+                Err(err) => Err((self, err)),
+            });
+        Either::B(soft_step(log, "files_cleanup", fut))
+    }
+
+    /// Files shown again after activation are kept, their scheduled deletion is cancelled in a soft step
+    fn cancel_files_cleanup<F>(self, files: F) -> impl Future<Item = Self, Error = (Self, FailureError)>
+    where
+        F: Future<Item = Vec<String>, Error = FailureError> + 'static,
+    {
+        let files_microservice = match self.files_microservice.clone() {
+            Some(files_microservice) => files_microservice,
+            None => return Either::A(future::ok(self)),
+        };
+        let log = self.log.clone();
+        let fut = files
+            .and_then(move |urls| {
+                if urls.is_empty() {
+                    return Either::A(future::ok(()));
+                }
+                Either::B(files_microservice.cancel_cleanup(Initiator::Superadmin, FilesCleanupCancellation { urls }))
+            })
+            .then(|res| match res {
+                Ok(_) => Ok((self, ())),
+                Err(err) => Err((self, err)),
+            });
+        Either::B(soft_step(log, "files_cleanup_cancellation", fut))
     }
 
     fn after_base_product_update(
@@ -737,31 +1071,26 @@ impl StoreServiceImpl {
         base_product_update: UpdateBaseProduct,
         base_product_id: BaseProductId,
     ) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
-        let stores_microservice = self.stores_microservice.clone();
-        let orders_microservice = self.orders_microservice.clone();
         let delivery_microservice = self.delivery_microservice.clone();
 
-        stores_microservice
-            .get_products_by_base_product(base_product_id)
-            .map(|products| DeleteProductsFromCartsPayload {
-                product_ids: products.into_iter().map(|p| p.id).collect(),
+        self.products_of_base_product(base_product_id)
+            .and_then(|(s, product_ids)| {
+                s.carts_cleanup.remove_products(product_ids);
+                s.cleanup_carts(())
             })
-            .and_then(move |payload| orders_microservice.delete_products_from_all_carts(Some(Initiator::Superadmin), payload))
-            .and_then(move |_| {
-                if let Some(new_currency) = base_product_update.currency {
-                    if new_currency != old_base_product.currency {
-                        return Either::A(
-                            delivery_microservice
-                                .delete_shipping_by_base_product(Some(Initiator::Superadmin), base_product_id)
-                                .then(|_| Ok(())),
-                        );
-                    }
-                }
-                Either::B(future::ok(()))
-            })
-            .then(|res| match res {
-                Ok(_) => Ok((self, ())),
-                Err(err) => Err((self, err)),
+            .and_then(move |(s, _)| {
+                let fut = match base_product_update.currency {
+                    Some(new_currency) if new_currency != old_base_product.currency => Either::A(
+                        delivery_microservice
+                            .delete_shipping_by_base_product(Some(Initiator::Superadmin), base_product_id)
+                            .then(|_| Ok(())),
+                    ),
+                    _ => Either::B(future::ok(())),
+                };
+                fut.then(|res: Result<(), FailureError>| match res {
+                    Ok(_) => Ok((s, ())),
+                    Err(err) => Err((s, err)),
+                })
             })
             .or_else(|(s, e)| future::err((s, parse_validation_errors(e, &["base_product"]))))
     }
@@ -771,50 +1100,12 @@ impl StoreServiceImpl {
         base_product_id: BaseProductId,
         payload: NewBaseProductWithVariants,
     ) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
-        let store_id = payload.store_id;
         let stores_microservice = self.stores_microservice.clone();
         let warehouses_microservice = self.warehouses_microservice.clone();
 
-        let quantity_by_uuid: HashMap<String, Quantity> = payload
-            .variants
-            .into_iter()
-            .flat_map(|p| match (p.product.uuid, p.quantity) {
-                (Some(uuid), Some(quantity)) => Some((uuid, quantity)),
-                _ => None,
-            })
-            .collect();
-
-        let warehouse_id = self
-            .warehouses_microservice
-            .clone()
-            .find_by_store_id(None, store_id)
-            .and_then(move |warehouses| {
-                if warehouses.len() > 1 {
-                    Err(format_err!("store {} has several warehouses", store_id))
-                } else {
-                    warehouses
-                        .into_iter()
-                        .next()
-                        .ok_or(format_err!("store {} has none warehouses", store_id))
-                }
-            })
-            .map(|warehouse| warehouse.id);
-
-        stores_microservice
-            .get_products_by_base_product(base_product_id)
-            .join(warehouse_id)
-            .map(|(products, warehouse_id)| products.into_iter().map(move |p| (p.id, p.uuid, warehouse_id)))
-            .map(|products| futures::stream::iter_ok(products))
-            .flatten_stream()
-            .filter_map(move |(product_id, product_uuid, warehouse_id)| {
-                quantity_by_uuid
-                    .get(&product_uuid)
-                    .map(move |quantity| (product_id, warehouse_id, *quantity))
-            })
-            .for_each(move |(product_id, warehouse_id, quantity)| {
-                warehouses_microservice
-                    .set_product_in_warehouse(Initiator::Superadmin, warehouse_id, product_id, quantity)
-                    .map(|_| ())
+        store_warehouse_id(warehouses_microservice.clone(), payload.store_id)
+            .and_then(move |warehouse_id| {
+                set_variants_stock(stores_microservice, warehouses_microservice, warehouse_id, base_product_id, payload)
             })
             .then(|res| match res {
                 Ok(_) => Ok((self, ())),
@@ -823,6 +1114,65 @@ impl StoreServiceImpl {
     }
 }
 
+/// The only warehouse of the store, stores with several warehouses are not supported yet
+pub fn store_warehouse_id(
+    warehouses_microservice: Arc<WarehousesMicroservice>,
+    store_id: StoreId,
+) -> impl Future<Item = WarehouseId, Error = FailureError> {
+    warehouses_microservice
+        .find_by_store_id(None, store_id)
+        .and_then(move |warehouses| {
+            if warehouses.len() > 1 {
+                Err(format_err!("store {} has several warehouses", store_id))
+            } else {
+                warehouses
+                    .into_iter()
+                    .next()
+                    .ok_or(format_err!("store {} has none warehouses", store_id))
+            }
+        })
+        .map(|warehouse| warehouse.id)
+}
+
+/// Puts quantities of created variants into the warehouse, variants are matched by uuid
+pub fn set_variants_stock(
+    stores_microservice: Arc<StoresMicroservice>,
+    warehouses_microservice: Arc<WarehousesMicroservice>,
+    warehouse_id: WarehouseId,
+    base_product_id: BaseProductId,
+    payload: NewBaseProductWithVariants,
+) -> impl Future<Item = (), Error = FailureError> {
+    let quantity_by_uuid: HashMap<String, Quantity> = payload
+        .variants
+        .into_iter()
+        .flat_map(|p| match (p.product.uuid, p.quantity) {
+            (Some(uuid), Some(quantity)) => Some((uuid, quantity)),
+            _ => None,
+        })
+        .collect();
+
+    stores_microservice
+        .get_products_by_base_product(base_product_id)
+        .map(|products| futures::stream::iter_ok(products.into_iter().map(|p| (p.id, p.uuid))))
+        .flatten_stream()
+        .filter_map(move |(product_id, product_uuid)| quantity_by_uuid.get(&product_uuid).map(move |quantity| (product_id, *quantity)))
+        .for_each(move |(product_id, quantity)| {
+            warehouses_microservice
+                .set_product_in_warehouse(Initiator::Superadmin, warehouse_id, product_id, quantity)
+                .map(|_| ())
+        })
+}
+
+/// Changes made against stale version of the entity are rejected, changes without version are always accepted
+pub fn check_version(expected: Option<EntityVersion>, updated_at: SystemTime, entity: LockedEntity) -> Result<(), FailureError> {
+    match expected {
+        Some(expected) if expected != EntityVersion::of(updated_at) => Err(FailureError::from(
+            format_err!("Version {} of {} is stale", expected.0, entity).context(Error::Conflict),
+        )),
+        _ => Ok(()),
+    }
+}
+
 fn is_status_change_requires_to_delete_product(initial_status: ModerationStatus, status: ModerationStatus) -> bool {
     match (initial_status, status) {
         (ModerationStatus::Published, status) if status != ModerationStatus::Published => true,
@@ -830,59 +1180,73 @@ fn is_status_change_requires_to_delete_product(initial_status: ModerationStatus,
     }
 }
 
+impl SagaService for StoreServiceImpl {
+    type Stage = CreateStoreOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.create_revert().map(|(s, _)| s))
+    }
+}
+
 impl StoreService for StoreServiceImpl {
-    fn create(self, input: NewStore) -> ServiceFuture<Box<StoreService>, Option<Store>> {
+    fn create(self, input: NewStore) -> ServiceFuture<Box<StoreService>, SagaResponse<Option<Store>>> {
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::CreateStore,
+            deadline,
+            move |s| s.create_happy(&input).map(|(s, store)| (s, Some(store))),
+            &[
+                "name",
+                "short_description",
+                "long_description",
+                "slug",
+                "phone",
+                "email",
+                "default_language",
+                "store",
+            ],
+        );
+
         Box::new(
-            self.create_happy(&input)
-                .map(|(s, store)| (Box::new(s) as Box<StoreService>, Some(store)))
-                .or_else(move |(s, e)| {
-                    s.create_revert().then(move |res| {
-                        let s = match res {
-                            Ok((s, _)) => s,
-                            Err((s, _)) => s,
-                        };
-                        futures::future::err((Box::new(s) as Box<StoreService>, e))
-                    })
-                })
-                .map_err(|(s, e): (Box<StoreService>, FailureError)| {
-                    (
-                        s,
-                        parse_validation_errors(
-                            e,
-                            &[
-                                "name",
-                                "short_description",
-                                "long_description",
-                                "slug",
-                                "phone",
-                                "email",
-                                "default_language",
-                                "store",
-                            ],
-                        ),
-                    )
-                }),
+            res.map(|(s, response)| (Box::new(s) as Box<StoreService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<StoreService>, e)),
         )
     }
 
-    fn set_store_moderation_status(self, payload: StoreModerate) -> ServiceFuture<Box<StoreService>, Store> {
+    fn set_store_moderation_status(self, payload: StoreModerate) -> ServiceFuture<Box<StoreService>, CartsCleanupResult<Store>> {
+        let store_id = payload.store_id;
+        let expected_version = payload.version;
         Box::new(
-            self.stores_microservice
-                .get(payload.store_id, Visibility::Active)
-                .then(|res| match res {
-                    Ok(Some(store)) => Ok((self, store.status)),
-                    Ok(None) => Err((
-                        self,
-                        format_err!("Store is not found in stores microservice.")
-                            .context(Error::NotFound)
-                            .into(),
-                    )),
-                    Err(err) => Err((self, err)),
+            self.lock(LockedEntity::Store(store_id))
+                .and_then(move |s| {
+                    s.stores_microservice.get(store_id, Visibility::Active).then(move |res| match res {
+                        Ok(Some(store)) => match check_version(expected_version, store.updated_at, LockedEntity::Store(store_id)) {
+                            Ok(()) => Ok((s, store.status)),
+                            Err(err) => Err((s, err)),
+                        },
+                        Ok(None) => Err((
+                            s,
+                            format_err!("Store is not found in stores microservice.")
+                                .context(Error::NotFound)
+                                .into(),
+                        )),
+                        Err(err) => Err((s, err)),
+                    })
                 })
                 .and_then(|(s, initial_status)| {
                     s.set_store_moderation_status(payload)
                         .map(move |(s, store)| (s, store, initial_status))
                 })
+                .map(|(s, store, initial_status)| {
+                    s.track_moderation(ModerationItem::Store(store.id), store.id, store.status);
+                    (s, store, initial_status)
+                })
                 .and_then(|(s, store, initial_status)| {
                     s.remove_products_from_cart_after_store_status_change(store.id, initial_status, store.status)
                         .map(|(s, _)| (s, store))
@@ -891,7 +1255,8 @@ impl StoreService for StoreServiceImpl {
                     s.notify_manager_store_update_moderation_status(store.id, store.user_id, store.status)
                         .map(|(s, _)| (s, store))
                 })
-                .map(|(s, store)| (Box::new(s) as Box<StoreService>, store))
+                .and_then(|(s, store)| s.cleanup_carts(store))
+                .map(|(s, result)| (Box::new(s) as Box<StoreService>, result))
                 .or_else(|(s, e)| future::err((Box::new(s) as Box<StoreService>, e))),
         )
     }
@@ -900,6 +1265,10 @@ impl StoreService for StoreServiceImpl {
     fn send_to_moderation(self, store_id: StoreId) -> ServiceFuture<Box<StoreService>, Store> {
         Box::new(
             self.send_to_moderation(store_id)
+                .map(|(s, store)| {
+                    s.track_moderation(ModerationItem::Store(store.id), store.id, store.status);
+                    (s, store)
+                })
                 .and_then(|(s, store)| {
                     s.notify_moderators_store_update_moderation_status(store.id, store.status)
                         .map(|(s, _)| (s, store))
@@ -910,109 +1279,320 @@ impl StoreService for StoreServiceImpl {
     }
 
     /// Set moderation status for base_product_id
-    fn set_moderation_status_base_product(self, payload: BaseProductModerate) -> ServiceFuture<Box<StoreService>, ()> {
+    fn set_moderation_status_base_product(
+        self,
+        payload: BaseProductModerate,
+    ) -> ServiceFuture<Box<StoreService>, CartsCleanupResult<BaseProduct>> {
+        let base_product_id = payload.base_product_id;
+        let expected_version = payload.version;
         Box::new(
-            self.stores_microservice
-                .get_base_product(payload.base_product_id, Visibility::Active)
-                .then(move |res| match res {
-                    Ok(Some(base_product)) => Ok((self, base_product.status)),
-                    Ok(None) => Err((
-                        self,
-                        format_err!("Base product is not found in stores microservice.")
-                            .context(Error::NotFound)
-                            .into(),
-                    )),
-                    Err(err) => Err((self, err)),
+            self.lock(LockedEntity::BaseProduct(base_product_id))
+                .and_then(move |s| {
+                    s.stores_microservice
+                        .get_base_product(base_product_id, Visibility::Active)
+                        .then(move |res| match res {
+                            Ok(Some(base_product)) => {
+                                match check_version(
+                                    expected_version,
+                                    base_product.updated_at,
+                                    LockedEntity::BaseProduct(base_product_id),
+                                ) {
+                                    Ok(()) => Ok((s, base_product.status)),
+                                    Err(err) => Err((s, err)),
+                                }
+                            }
+                            Ok(None) => Err((
+                                s,
+                                format_err!("Base product is not found in stores microservice.")
+                                    .context(Error::NotFound)
+                                    .into(),
+                            )),
+                            Err(err) => Err((s, err)),
+                        })
                 })
                 .and_then(|(s, initial_status)| {
                     s.set_moderation_status_base_product(payload)
                         .map(move |(s, base_product)| (s, initial_status, base_product))
                 })
+                .map(|(s, initial_status, base_product)| {
+                    s.track_moderation(
+                        ModerationItem::BaseProduct(base_product.id),
+                        base_product.store_id,
+                        base_product.status,
+                    );
+                    (s, initial_status, base_product)
+                })
                 .and_then(|(s, initial_status, base_product)| {
                     s.remove_products_from_cart_after_base_product_status_change(base_product.id, initial_status, base_product.status)
                         .map(|(s, _)| (s, base_product))
                 })
                 .and_then(|(s, base)| {
                     s.notify_manager_base_product_update_moderation_status(base.store_id, base.id, base.status)
-                        .map(|(s, _)| (s, ()))
+                        .map(|(s, _)| (s, base))
                 })
-                .map(|(s, _)| (Box::new(s) as Box<StoreService>, ()))
+                .and_then(|(s, base)| s.cleanup_carts(base))
+                .map(|(s, result)| (Box::new(s) as Box<StoreService>, result))
                 .or_else(|(s, e)| future::err((Box::new(s) as Box<StoreService>, e))),
         )
     }
 
-    /// Send base product to moderation from store manager
+    /// Send base product to moderation from store manager, base products matching auto-approval rules are published right away
     fn send_to_moderation_base_product(self, base_product_id: BaseProductId) -> ServiceFuture<Box<StoreService>, ()> {
         Box::new(
             self.send_to_moderation_base_product(base_product_id)
-                .and_then(|(s, base)| {
-                    s.notify_moderators_base_product_update_moderation_status(base.store_id, base.id, base.status)
-                        .map(|(s, _)| (s, ()))
+                .map(|(s, base)| {
+                    s.track_moderation(ModerationItem::BaseProduct(base.id), base.store_id, base.status);
+                    (s, base)
                 })
-                .map(|(s, _)| (Box::new(s) as Box<StoreService>, ()))
-                .or_else(|(s, e)| future::err((Box::new(s) as Box<StoreService>, e))),
+                .or_else(|(s, e)| future::err((Box::new(s) as Box<StoreService>, e)))
+                .and_then(|(s, base)| match s.auto_approval_rule(&base) {
+                    Some(rule) => {
+                        info!("Base product {} is approved by auto-approval rule {}", base.id, rule);
+                        let payload = BaseProductModerate {
+                            base_product_id: base.id,
+                            status: ModerationStatus::Published,
+                            version: None,
+                        };
+                        Either::A(StoreService::set_moderation_status_base_product(s, payload).map(|(s, _)| (s, ())))
+                    }
+                    None => Either::B(
+                        s.notify_moderators_base_product_update_moderation_status(base.store_id, base.id, base.status)
+                            .map(|(s, _)| (Box::new(s) as Box<StoreService>, ()))
+                            .or_else(|(s, e)| future::err((Box::new(s) as Box<StoreService>, e))),
+                    ),
+                }),
         )
     }
 
     /// Deactivate base product
-    fn deactivate_base_product(self, base_product_id: BaseProductId) -> ServiceFuture<Box<StoreService>, BaseProduct> {
+    fn deactivate_base_product(
+        self,
+        base_product_id: BaseProductId,
+        payload: Deactivation,
+    ) -> ServiceFuture<Box<StoreService>, CartsCleanupResult<BaseProduct>> {
         Box::new(
-            self.stores_microservice
-                .deactivate_base_product(None, base_product_id)
-                .then(move |res| match res {
-                    Ok(base_product) => Ok((self, base_product)),
-                    Err(err) => Err((self, err)),
+            self.lock(LockedEntity::BaseProduct(base_product_id))
+                .and_then(move |s| {
+                    s.stores_microservice
+                        .deactivate_base_product(None, base_product_id, payload.clone())
+                        .then(move |res| match res {
+                            Ok(base_product) => {
+                                s.track_deactivation(DeactivatedItem::BaseProduct(base_product_id), payload);
+                                Ok((s, base_product))
+                            }
+                            Err(err) => Err((s, err)),
+                        })
                 })
                 .and_then(move |(s, base_product)| {
                     s.remove_products_from_cart_after_base_product_deactivation(base_product_id)
                         .map(move |(s, _)| (s, base_product))
                 })
-                .map(|(s, base_product)| (Box::new(s) as Box<StoreService>, base_product))
+                .and_then(|(s, base_product)| s.cleanup_carts(base_product))
+                .and_then(move |(s, result)| {
+                    let files = s
+                        .stores_microservice
+                        .get_products_by_base_product(base_product_id)
+                        .map(|products| product_files(&products));
+                    s.schedule_files_cleanup(files).map(move |s| (s, result))
+                })
+                .map(|(s, result)| (Box::new(s) as Box<StoreService>, result))
                 .or_else(|(s, e)| future::err((Box::new(s) as Box<StoreService>, e))),
         )
     }
 
-    /// Deactivate store
-    fn deactivate_store(self, store_id: StoreId) -> ServiceFuture<Box<StoreService>, Store> {
+    /// Deactivate store, store manager is told the reason
+    fn deactivate_store(self, store_id: StoreId, payload: Deactivation) -> ServiceFuture<Box<StoreService>, CartsCleanupResult<Store>> {
+        let reason = payload.reason.clone();
         Box::new(
-            self.stores_microservice
-                .deactivate_store(None, store_id)
-                .then(move |res| match res {
-                    Ok(store) => Ok((self, store)),
-                    Err(err) => Err((self, err)),
+            self.lock(LockedEntity::Store(store_id))
+                .and_then(move |s| {
+                    s.stores_microservice
+                        .deactivate_store(None, store_id, payload.clone())
+                        .then(move |res| match res {
+                            Ok(store) => {
+                                s.track_deactivation(DeactivatedItem::Store(store_id), payload);
+                                Ok((s, store))
+                            }
+                            Err(err) => Err((s, err)),
+                        })
                 })
                 .and_then(move |(s, store)| {
                     s.remove_products_from_cart_after_store_deactivation(store_id)
                         .map(move |(s, _)| (s, store))
                 })
-                .map(|(s, store)| (Box::new(s) as Box<StoreService>, store))
+                .and_then(|(s, store)| s.cleanup_carts(store))
+                .and_then(move |(s, result)| {
+                    let mut files = store_files(&result.result);
+                    let files = s.stores_microservice.get_products_by_store(store_id).map(move |products| {
+                        files.extend(product_files(&products));
+                        files
+                    });
+                    s.schedule_files_cleanup(files).map(move |s| (s, result))
+                })
+                .and_then(move |(s, result)| {
+                    let (store_id, store_manager_id) = (result.result.id, result.result.user_id);
+                    soft_step(
+                        s.log.clone(),
+                        "store_deactivation_notification",
+                        s.notify_manager_store_deactivation(store_id, store_manager_id, reason),
+                    )
+                    .map(move |s| (s, result))
+                })
+                .map(|(s, result)| (Box::new(s) as Box<StoreService>, result))
                 .or_else(|(s, e)| future::err((Box::new(s) as Box<StoreService>, e))),
         )
     }
 
     /// Deactivate product
-    fn deactivate_product(self, product_id: ProductId) -> ServiceFuture<Box<StoreService>, Product> {
-        let orders_microservice = self.orders_microservice.clone();
+    fn deactivate_product(
+        self,
+        product_id: ProductId,
+        payload: Deactivation,
+    ) -> ServiceFuture<Box<StoreService>, CartsCleanupResult<Product>> {
         Box::new(
-            self.stores_microservice
-                .deactivate_product(None, product_id)
-                .then(move |res| match res {
-                    Ok(product) => Ok((self, product)),
-                    Err(err) => Err((self, err)),
+            self.lock(LockedEntity::Product(product_id))
+                .and_then(move |s| {
+                    s.stores_microservice
+                        .deactivate_product(None, product_id, payload.clone())
+                        .then(move |res| match res {
+                            Ok(product) => {
+                                s.track_deactivation(DeactivatedItem::Product(product_id), payload);
+                                Ok((s, product))
+                            }
+                            Err(err) => Err((s, err)),
+                        })
                 })
                 .and_then(move |(s, product)| {
-                    orders_microservice
-                        .delete_products_from_all_carts(
-                            Some(Initiator::Superadmin),
-                            DeleteProductsFromCartsPayload {
-                                product_ids: vec![product_id],
-                            },
-                        )
-                        .then(move |res| match res {
-                            Ok(_) => Ok((s, product)),
+                    s.carts_cleanup.clear_delivery_methods(vec![product_id]);
+                    s.carts_cleanup.remove_from_wishlists(vec![product_id]);
+                    s.carts_cleanup.remove_products(vec![product_id]);
+                    s.cleanup_carts(product)
+                })
+                .and_then(|(s, result)| {
+                    let files = future::ok(product_files(&[result.result.clone()]));
+                    s.schedule_files_cleanup(files).map(move |s| (s, result))
+                })
+                .map(|(s, result)| (Box::new(s) as Box<StoreService>, result))
+                .or_else(|(s, e)| future::err((Box::new(s) as Box<StoreService>, e))),
+        )
+    }
+
+    /// Activate store deactivated before, moderation and shipping of its base products are restored
+    fn activate_store(self, store_id: StoreId, payload: Activation) -> ServiceFuture<Box<StoreService>, Store> {
+        Box::new(
+            self.lock(LockedEntity::Store(store_id))
+                .and_then(move |s| {
+                    s.stores_microservice.activate_store(None, store_id).then(|res| match res {
+                        Ok(store) => Ok((s, store)),
+                        Err(err) => Err((s, err)),
+                    })
+                })
+                .and_then(|(s, store)| {
+                    s.stores_microservice.get_products_by_store(store.id).then(|res| match res {
+                        Ok(products) => Ok((s, store, products)),
+                        Err(err) => Err((s, err)),
+                    })
+                })
+                .and_then(|(s, store, products)| {
+                    s.resume_moderation(ModerationItem::Store(store.id), store.id, store.status)
+                        .map(|s| (s, store, products))
+                })
+                .and_then(|(s, store, products)| {
+                    let mut base_product_ids = products.iter().map(|p| p.base_product_id).collect::<Vec<_>>();
+                    base_product_ids.sort();
+                    base_product_ids.dedup();
+                    s.restore_shipping(base_product_ids).map(|s| (s, store, products))
+                })
+                .and_then(|(s, store, products)| {
+                    let mut files = store_files(&store);
+                    files.extend(product_files(&products));
+                    s.cancel_files_cleanup(future::ok(files)).map(|s| (s, store, products))
+                })
+                .and_then(move |(s, store, products)| {
+                    let product_ids = products.into_iter().map(|p| p.id).collect();
+                    s.add_stock_placeholders(&payload, store.id, product_ids).map(|s| (s, store))
+                })
+                .map(|(s, store)| (Box::new(s) as Box<StoreService>, store))
+                .or_else(|(s, e)| future::err((Box::new(s) as Box<StoreService>, e))),
+        )
+    }
+
+    /// Activate base product deactivated before, moderation and shipping are restored
+    fn activate_base_product(self, base_product_id: BaseProductId, payload: Activation) -> ServiceFuture<Box<StoreService>, BaseProduct> {
+        Box::new(
+            self.lock(LockedEntity::BaseProduct(base_product_id))
+                .and_then(move |s| {
+                    s.stores_microservice
+                        .activate_base_product(None, base_product_id)
+                        .then(|res| match res {
+                            Ok(base_product) => Ok((s, base_product)),
                             Err(err) => Err((s, err)),
                         })
                 })
+                .and_then(|(s, base_product)| {
+                    s.resume_moderation(
+                        ModerationItem::BaseProduct(base_product.id),
+                        base_product.store_id,
+                        base_product.status,
+                    )
+                    .map(|s| (s, base_product))
+                })
+                .and_then(|(s, base_product)| s.restore_shipping(vec![base_product.id]).map(|s| (s, base_product)))
+                .and_then(|(s, base_product)| {
+                    let files = s
+                        .stores_microservice
+                        .get_products_by_base_product(base_product.id)
+                        .map(|products| product_files(&products));
+                    s.cancel_files_cleanup(files).map(|s| (s, base_product))
+                })
+                .and_then(|(s, base_product)| {
+                    s.products_of_base_product(base_product.id)
+                        .map(|(s, product_ids)| (s, base_product, product_ids))
+                })
+                .and_then(move |(s, base_product, product_ids)| {
+                    s.add_stock_placeholders(&payload, base_product.store_id, product_ids)
+                        .map(|s| (s, base_product))
+                })
+                .map(|(s, base_product)| (Box::new(s) as Box<StoreService>, base_product))
+                .or_else(|(s, e)| future::err((Box::new(s) as Box<StoreService>, e))),
+        )
+    }
+
+    /// Activate product deactivated before
+    fn activate_product(self, product_id: ProductId, payload: Activation) -> ServiceFuture<Box<StoreService>, Product> {
+        Box::new(
+            self.lock(LockedEntity::Product(product_id))
+                .and_then(move |s| {
+                    s.stores_microservice.activate_product(None, product_id).then(|res| match res {
+                        Ok(product) => Ok((s, product)),
+                        Err(err) => Err((s, err)),
+                    })
+                })
+                .and_then(move |(s, product)| {
+                    if !payload.stock_placeholders {
+                        return Either::A(future::ok((s, product)));
+                    }
+                    // store of the product is needed to find its warehouse
+                    Either::B(
+                        s.stores_microservice
+                            .get_base_product(product.base_product_id, Visibility::Active)
+                            .then(move |res| match res {
+                                Ok(Some(base_product)) => Either::A(
+                                    s.add_stock_placeholders(&payload, base_product.store_id, vec![product.id])
+                                        .map(|s| (s, product)),
+                                ),
+                                Ok(None) => Either::B(future::ok((s, product))),
+                                Err(err) => {
+                                    s.log.warn("stock_placeholders", &err);
+                                    Either::B(future::ok((s, product)))
+                                }
+                            }),
+                    )
+                })
+                .and_then(|(s, product)| {
+                    let files = future::ok(product_files(&[product.clone()]));
+                    s.cancel_files_cleanup(files).map(|s| (s, product))
+                })
                 .map(|(s, product)| (Box::new(s) as Box<StoreService>, product))
                 .or_else(|(s, e)| future::err((Box::new(s) as Box<StoreService>, e))),
         )
@@ -1026,10 +1606,20 @@ impl StoreService for StoreServiceImpl {
     ) -> ServiceFuture<Box<StoreService>, BaseProduct> {
         let stores_microservice = self.stores_microservice.clone();
         let payload_clone = payload.clone();
+        let edited_fields = payload.edited_fields();
+        let expected_version = payload.version;
         Box::new(
             self.stores_microservice
                 .get_base_product(base_product_id, Visibility::Active)
                 .and_then(move |old_base_product| old_base_product.ok_or(format_err!("Could not find base product {}", base_product_id)))
+                .and_then(move |old_base_product| {
+                    check_version(
+                        expected_version,
+                        old_base_product.updated_at,
+                        LockedEntity::BaseProduct(base_product_id),
+                    )
+                    .map(|_| old_base_product)
+                })
                 .and_then(move |old_base_product| {
                     stores_microservice
                         .update_base_product(None, base_product_id, payload)
@@ -1039,6 +1629,12 @@ impl StoreService for StoreServiceImpl {
                     Ok((old_base_product, base_product)) => Ok((self, old_base_product, base_product)),
                     Err(err) => Err((self, err)),
                 })
+                .map(move |(s, old_base_product, base_product)| {
+                    if let Err(e) = s.moderation_queue.record_edit(base_product_id, edited_fields) {
+                        error!("Could not record edit of base product {}: {}", base_product_id, e);
+                    }
+                    (s, old_base_product, base_product)
+                })
                 .and_then(move |(s, old_base_product, base_product)| {
                     s.after_base_product_update(old_base_product, payload_clone, base_product_id)
                         .map(|(s, _)| (s, base_product))
@@ -1078,7 +1674,8 @@ impl StoreService for StoreServiceImpl {
     }
 }
 
-fn fill_uids(mut payload: NewBaseProductWithVariants) -> Result<NewBaseProductWithVariants, FailureError> {
+/// Generates uuids of variants which have none, they are derived from uuid of base product
+pub fn fill_uids(mut payload: NewBaseProductWithVariants) -> Result<NewBaseProductWithVariants, FailureError> {
     let parent_uuid = Uuid::parse_str(payload.uuid.as_ref())?;
     let mut transaction = TransactionId::new(parent_uuid);
     for product in payload.variants.iter_mut() {
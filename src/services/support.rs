@@ -0,0 +1,198 @@
+use std::sync::Arc;
+
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::future::{self, join_all};
+use futures::prelude::*;
+use serde_json::{self, Value};
+
+use stq_types::*;
+
+use errors::Error;
+use microservice::*;
+use models::*;
+use saga::SagaStore;
+use services::initiator::InitiatorPolicy;
+use services::types::ServiceFuture;
+
+const ALL_STATUSES: &[SagaStatus] = &[
+    SagaStatus::InProgress,
+    SagaStatus::Completed,
+    SagaStatus::Reverted,
+    SagaStatus::RevertFailed,
+    SagaStatus::Orphaned,
+];
+
+pub trait SupportService {
+    /// Gathers the order along with its invoices, stocks of its product and sagas mentioning it, no saga is started.
+    /// Only superadmin may gather the bundle
+    fn order_bundle(self, order_slug: OrderSlug) -> ServiceFuture<Box<SupportService>, OrderDebugBundle>;
+}
+
+#[derive(Clone)]
+pub struct SupportServiceImpl {
+    pub orders_microservice: Arc<OrdersMicroservice>,
+    pub billing_microservice: Arc<BillingMicroservice>,
+    pub warehouses_microservice: Arc<WarehousesMicroservice>,
+    pub saga_store: Arc<SagaStore>,
+    pub initiators: InitiatorPolicy,
+}
+
+impl SupportServiceImpl {
+    pub fn new(
+        orders_microservice: Arc<OrdersMicroservice>,
+        billing_microservice: Arc<BillingMicroservice>,
+        warehouses_microservice: Arc<WarehousesMicroservice>,
+        saga_store: Arc<SagaStore>,
+    ) -> Self {
+        Self {
+            orders_microservice,
+            billing_microservice,
+            warehouses_microservice,
+            saga_store,
+            initiators: InitiatorPolicy::default(),
+        }
+    }
+
+    /// Gathers the bundle on behalf of the caller, the caller must be superadmin
+    pub fn with_caller(mut self, caller: Option<Initiator>) -> Self {
+        self.initiators = InitiatorPolicy::new(caller);
+        self
+    }
+
+    /// Order is required, the rest of the bundle is gathered as far as microservices allow
+    fn bundle(&self, order_slug: OrderSlug) -> impl Future<Item = OrderDebugBundle, Error = FailureError> {
+        let s = self.clone();
+        self.orders_microservice
+            .get_order(Some(Initiator::Superadmin), OrderIdentifier::Slug(order_slug))
+            .and_then(move |order| {
+                order.ok_or_else(|| {
+                    format_err!("Order is not found in orders microservice! slug: {}", order_slug)
+                        .context(Error::NotFound)
+                        .into()
+                })
+            })
+            .and_then(move |order| {
+                let mut errors = vec![];
+                let sagas = match s.saga_store.find_by_status(ALL_STATUSES) {
+                    Ok(records) => {
+                        let order_id = serde_json::to_value(order.id).unwrap_or(Value::Null);
+                        let mut sagas = records
+                            .into_iter()
+                            .filter(|record| mentions_order(record, &order_id))
+                            .collect::<Vec<_>>();
+                        sagas.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+                        sagas
+                    }
+                    Err(e) => {
+                        errors.push(format!("Sagas could not be loaded: {}", e));
+                        vec![]
+                    }
+                };
+
+                let invoices = join_all(
+                    sagas
+                        .iter()
+                        .filter(|record| record.saga_type == SagaType::CreateOrder || record.saga_type == SagaType::BuyNow)
+                        .map(|record| {
+                            let saga_id = record.id;
+                            s.billing_microservice
+                                .get_invoice_by_saga_id(Initiator::Superadmin, saga_id)
+                                .then(move |res| {
+                                    Ok::<_, FailureError>(
+                                        res.map_err(|e| format!("Invoice of saga {} could not be loaded: {}", saga_id, e)),
+                                    )
+                                })
+                        })
+                        .collect::<Vec<_>>(),
+                );
+                let stocks = s
+                    .warehouses_microservice
+                    .find_by_product_id(Initiator::Superadmin, order.product)
+                    .then(|res| Ok::<_, FailureError>(res.map_err(|e| format!("Stocks could not be loaded: {}", e))));
+
+                invoices.join(stocks).map(move |(invoices, stocks)| {
+                    let invoices = invoices
+                        .into_iter()
+                        .filter_map(|invoice| match invoice {
+                            Ok(invoice) => invoice,
+                            Err(e) => {
+                                errors.push(e);
+                                None
+                            }
+                        })
+                        .collect();
+                    let stocks = stocks.unwrap_or_else(|e| {
+                        errors.push(e);
+                        vec![]
+                    });
+                    OrderDebugBundle {
+                        payment_state: order.payment_state.clone(),
+                        order,
+                        invoices,
+                        stocks,
+                        sagas,
+                        errors,
+                    }
+                })
+            })
+    }
+}
+
+impl SupportService for SupportServiceImpl {
+    fn order_bundle(self, order_slug: OrderSlug) -> ServiceFuture<Box<SupportService>, OrderDebugBundle> {
+        let s = self.clone();
+        if !self.initiators.is_superadmin() {
+            let e: FailureError = format_err!(
+                "Caller {:?} is not allowed to gather order debug bundle",
+                self.initiators.caller_id()
+            )
+            .context(Error::Forbidden)
+            .into();
+            return Box::new(future::err((Box::new(s) as Box<SupportService>, e)));
+        }
+        Box::new(self.bundle(order_slug).then(move |res| match res {
+            Ok(bundle) => future::ok((Box::new(s) as Box<SupportService>, bundle)),
+            Err(e) => future::err((Box::new(s) as Box<SupportService>, e)),
+        }))
+    }
+}
+
+/// Order is mentioned if its id is found in input of the saga, its stages or their results
+fn mentions_order(record: &SagaRecord, order_id: &Value) -> bool {
+    record.input.iter().any(|input| contains(input, order_id))
+        || record.stages.iter().any(|entry| {
+            entry.result.iter().any(|result| contains(result, order_id))
+                || serde_json::to_value(&entry.stage)
+                    .map(|stage| contains(&stage, order_id))
+                    .unwrap_or(false)
+        })
+}
+
+fn contains(value: &Value, needle: &Value) -> bool {
+    if value == needle {
+        return true;
+    }
+    match *value {
+        Value::Object(ref fields) => fields.values().any(|field| contains(field, needle)),
+        Value::Array(ref items) => items.iter().any(|item| contains(item, needle)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stq_types::SagaId;
+
+    use super::mentions_order;
+    use models::{SagaRecord, SagaType};
+
+    #[test]
+    fn finds_order_anywhere_in_saga_input() {
+        let order_id = json!("6d1bbb41-bc4c-4d7c-9e0c-5b2a3c1c1a55");
+        let mut record = SagaRecord::new(SagaId::new(), SagaType::OpenDispute);
+        assert!(!mentions_order(&record, &order_id));
+        record.input = Some(json!({"dispute": {"order_id": "6d1bbb41-bc4c-4d7c-9e0c-5b2a3c1c1a55", "reason": "Not delivered"}}));
+        assert!(mentions_order(&record, &order_id));
+    }
+}
@@ -0,0 +1,303 @@
+//! Legal takedown of store. The store is deactivated, so that stores microservice drops
+//! it and its products from search, its products are removed from carts and wishlists,
+//! its public fields are anonymized and legal hold is recorded in audit trail. Failed
+//! takedown is compensated as a whole: the store is activated, its fields restored and
+//! legal hold released. Products removed from carts are not put back, as with deactivation.
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::future::{self, Either};
+use futures::prelude::*;
+
+use stq_types::*;
+
+use super::initiator::InitiatorPolicy;
+use audit::AuditScope;
+use config;
+use errors::Error;
+use microservice::*;
+use models::*;
+use saga::{self, soft_step, SagaLog, SagaService, SagaStore};
+use services::carts::{CartsCleanup, CustomersNotifier};
+use services::types::ServiceFuture;
+
+pub trait StoreTakedownService {
+    /// Takes store down for legal reasons, see module docs
+    fn takedown_store(
+        self,
+        store_id: StoreId,
+        payload: StoreTakedown,
+    ) -> ServiceFuture<Box<StoreTakedownService>, SagaResponse<CartsCleanupResult<Store>>>;
+}
+
+#[derive(Clone)]
+pub struct StoreTakedownServiceImpl {
+    pub orders_microservice: Arc<OrdersMicroservice>,
+    pub stores_microservice: Arc<StoresMicroservice>,
+    pub config: config::Config,
+    pub log: Rc<SagaLog<TakedownStoreOperationStage>>,
+    /// Legal hold is recorded in the same audit trail as calls of the saga
+    pub audit: AuditScope,
+    pub carts_cleanup: CartsCleanup,
+    pub initiators: InitiatorPolicy,
+}
+
+impl StoreTakedownServiceImpl {
+    pub fn new(
+        config: config::Config,
+        saga_store: Arc<SagaStore>,
+        audit: AuditScope,
+        orders_microservice: Arc<OrdersMicroservice>,
+        stores_microservice: Arc<StoresMicroservice>,
+        users_microservice: Arc<UsersMicroservice>,
+        notifications_microservice: Arc<NotificationsMicroservice>,
+    ) -> Self {
+        let log = Rc::new(SagaLog::new(saga_store));
+        let carts_cleanup = if config.features.unavailable_cart_products_notifications {
+            CartsCleanup::default().with_notifier(CustomersNotifier {
+                users_microservice,
+                notifications_microservice,
+                cluster_url: config.cluster.url.clone(),
+            })
+        } else {
+            CartsCleanup::default()
+        };
+        Self {
+            config,
+            orders_microservice,
+            stores_microservice,
+            log,
+            audit,
+            carts_cleanup,
+            initiators: InitiatorPolicy::default(),
+        }
+    }
+
+    /// Performs steps of sagas on behalf of the caller
+    pub fn with_caller(mut self, caller: Option<Initiator>) -> Self {
+        self.initiators = InitiatorPolicy::new(caller);
+        self
+    }
+
+    fn takedown_happy(
+        self,
+        store_id: StoreId,
+        payload: StoreTakedown,
+    ) -> impl Future<Item = (Self, CartsCleanupResult<Store>), Error = (Self, FailureError)> {
+        self.log.start(SagaType::TakedownStore);
+        self.log.set_input(&payload);
+
+        self.lock_store(store_id)
+            .and_then(move |s| s.get_store(store_id))
+            .and_then(move |(s, original)| s.deactivate(store_id, payload.reason.clone()).map(move |s| (s, original, payload)))
+            .and_then(move |(s, original, payload)| s.cleanup_carts(store_id).map(move |(s, cleanup)| (s, original, payload, cleanup)))
+            .and_then(move |(s, original, payload, cleanup)| {
+                s.anonymize(&original).map(move |(s, store)| {
+                    let result = CartsCleanupResult {
+                        result: store,
+                        removed_from_carts: cleanup.removed_from_carts,
+                        cleared_delivery_methods: cleanup.cleared_delivery_methods,
+                    };
+                    (s, payload, result)
+                })
+            })
+            .and_then(move |(s, payload, result)| s.put_legal_hold(store_id, payload.reason).map(move |s| (s, result)))
+    }
+
+    fn lock_store(self, store_id: StoreId) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let lease = Duration::from_secs(self.config.saga.reaper_stale_after_s);
+        self.log.lock(LockedEntity::Store(store_id), lease).then(|res| match res {
+            Ok(()) => Ok(self),
+            Err(e) => Err((self, e)),
+        })
+    }
+
+    /// Store is looked up whether it is active or not, inactive store can be taken down as well
+    fn get_store(self, store_id: StoreId) -> impl Future<Item = (Self, Store), Error = (Self, FailureError)> {
+        self.stores_microservice
+            .get(store_id, Visibility::Published)
+            .and_then(move |store| store.ok_or_else(|| format_err!("Store {} not found", store_id).context(Error::NotFound).into()))
+            .then(|res| match res {
+                Ok(store) => Ok((self, store)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn deactivate(self, store_id: StoreId, reason: String) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let initiator = match self.initiators.caller("stores_store_deactivation") {
+            Ok(initiator) => initiator,
+            Err(e) => return Either::A(future::err((self, e))),
+        };
+        log.push(TakedownStoreOperationStage::StoreDeactivationStart(store_id));
+
+        let payload = Deactivation {
+            reason: None,
+            note: Some(reason),
+        };
+        Either::B(
+            self.stores_microservice
+                .deactivate_store(Some(initiator), store_id, payload)
+                .then(move |res| match res {
+                    Ok(store) => {
+                        log.push_with_result(TakedownStoreOperationStage::StoreDeactivationComplete(store_id), &store);
+                        Ok(self)
+                    }
+                    Err(e) => Err((self, e)),
+                }),
+        )
+    }
+
+    /// Products of the store are removed from carts along with their delivery methods, wishlists are cleaned up in a soft step
+    fn cleanup_carts(self, store_id: StoreId) -> impl Future<Item = (Self, CartsCleanupResult<()>), Error = (Self, FailureError)> {
+        self.stores_microservice
+            .get_products_by_store(store_id)
+            .then(|res| match res {
+                Ok(products) => {
+                    let product_ids = products.into_iter().map(|p| p.id).collect::<Vec<_>>();
+                    self.carts_cleanup.clear_delivery_methods(product_ids.clone());
+                    self.carts_cleanup.remove_from_wishlists(product_ids.clone());
+                    self.carts_cleanup.remove_products(product_ids);
+                    Ok(self)
+                }
+                Err(e) => Err((self, e)),
+            })
+            .and_then(|s| {
+                let log = s.log.clone();
+                let fut = s.carts_cleanup.flush_wishlists(&s.stores_microservice).then(|res| match res {
+                    Ok(removed) => Ok((s, removed)),
+                    Err(e) => Err((s, e)),
+                });
+                soft_step(log, "wishlists_cleanup", fut)
+            })
+            .and_then(|s| {
+                s.carts_cleanup.flush(&s.orders_microservice, ()).then(|res| match res {
+                    Ok(result) => Ok((s, result)),
+                    Err(e) => Err((s, e)),
+                })
+            })
+    }
+
+    fn anonymize(self, original: &Store) -> impl Future<Item = (Self, Store), Error = (Self, FailureError)> {
+        let store_id = original.id;
+        let log = self.log.clone();
+        let initiator = match self.initiators.caller("stores_store_anonymization") {
+            Ok(initiator) => initiator,
+            Err(e) => return Either::A(future::err((self, e))),
+        };
+        log.push(TakedownStoreOperationStage::StoreAnonymizationStart(
+            store_id,
+            StorePublicFields::of(original),
+        ));
+
+        Either::B(
+            self.stores_microservice
+                .update_store_public_fields(Some(initiator), store_id, StorePublicFields::anonymized(original))
+                .then(move |res| match res {
+                    Ok(store) => {
+                        log.push(TakedownStoreOperationStage::StoreAnonymizationComplete(store_id));
+                        Ok((self, store))
+                    }
+                    Err(e) => Err((self, e)),
+                }),
+        )
+    }
+
+    fn put_legal_hold(self, store_id: StoreId, reason: String) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        self.log.push(TakedownStoreOperationStage::LegalHoldStart(store_id, reason.clone()));
+        let legal_hold = LegalHold {
+            store_id,
+            reason,
+            released: false,
+        };
+        match self.audit.record_legal_hold(legal_hold) {
+            Ok(()) => {
+                self.log.push(TakedownStoreOperationStage::LegalHoldComplete(store_id));
+                future::ok(self)
+            }
+            Err(e) => future::err((self, e)),
+        }
+    }
+
+    // Contains reversal of store takedown
+    pub fn takedown_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let stores_microservice = self.stores_microservice.clone();
+        let audit = self.audit.clone();
+
+        let compensation = self.log.compensate(move |e, _| match e {
+            TakedownStoreOperationStage::StoreDeactivationStart(store_id) => {
+                debug!("Reverting store deactivation, store_id: {}", store_id);
+                Box::new(
+                    stores_microservice
+                        .activate_store(Some(Initiator::Superadmin), store_id)
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            TakedownStoreOperationStage::StoreAnonymizationStart(store_id, original) => {
+                debug!("Restoring public fields of store, store_id: {}", store_id);
+                Box::new(
+                    stores_microservice
+                        .update_store_public_fields(Some(Initiator::Superadmin), store_id, original)
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            TakedownStoreOperationStage::LegalHoldStart(store_id, reason) => {
+                debug!("Releasing legal hold of store, store_id: {}", store_id);
+                let legal_hold = LegalHold {
+                    store_id,
+                    reason,
+                    released: true,
+                };
+                Box::new(future::result(audit.record_legal_hold(legal_hold))) as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
+        });
+
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((self, format_err!("Store takedown service takedown_revert error occurred: {}", e))),
+        })
+    }
+}
+
+impl SagaService for StoreTakedownServiceImpl {
+    type Stage = TakedownStoreOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.takedown_revert().map(|(s, _)| s))
+    }
+}
+
+impl StoreTakedownService for StoreTakedownServiceImpl {
+    fn takedown_store(
+        self,
+        store_id: StoreId,
+        payload: StoreTakedown,
+    ) -> ServiceFuture<Box<StoreTakedownService>, SagaResponse<CartsCleanupResult<Store>>> {
+        debug!("Take down store {}, payload: {:?}", store_id, payload);
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::TakedownStore,
+            deadline,
+            move |s| s.takedown_happy(store_id, payload),
+            &["store"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<StoreTakedownService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<StoreTakedownService>, e)),
+        )
+    }
+}
@@ -0,0 +1,256 @@
+//! Vacation mode of store. Products of the store are hidden from search and removed
+//! from carts, while the store stays active and keeps its products in wishlists.
+//! Store is brought back by `jobs::vacation` once its vacation is over, products
+//! removed from carts are not put back, as with deactivation.
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::future;
+use futures::prelude::*;
+
+use stq_types::*;
+
+use config;
+use errors::Error;
+use microservice::*;
+use models::*;
+use saga::{self, SagaLog, SagaService, SagaStore};
+use services::carts::{CartsCleanup, CustomersNotifier};
+use services::types::ServiceFuture;
+use vacations::StoreVacations;
+
+pub trait StoreVacationService {
+    /// Puts store on vacation until `ends_at` of the input
+    fn start_vacation(
+        self,
+        store_id: StoreId,
+        input: StoreVacationInput,
+    ) -> ServiceFuture<Box<StoreVacationService>, SagaResponse<CartsCleanupResult<StoreVacation>>>;
+}
+
+#[derive(Clone)]
+pub struct StoreVacationServiceImpl {
+    pub orders_microservice: Arc<OrdersMicroservice>,
+    pub stores_microservice: Arc<StoresMicroservice>,
+    pub vacations: Arc<StoreVacations>,
+    pub config: config::Config,
+    pub log: Rc<SagaLog<StoreVacationOperationStage>>,
+    pub carts_cleanup: CartsCleanup,
+}
+
+impl StoreVacationServiceImpl {
+    pub fn new(
+        config: config::Config,
+        saga_store: Arc<SagaStore>,
+        vacations: Arc<StoreVacations>,
+        orders_microservice: Arc<OrdersMicroservice>,
+        stores_microservice: Arc<StoresMicroservice>,
+        users_microservice: Arc<UsersMicroservice>,
+        notifications_microservice: Arc<NotificationsMicroservice>,
+    ) -> Self {
+        let log = Rc::new(SagaLog::new(saga_store));
+        let carts_cleanup = if config.features.unavailable_cart_products_notifications {
+            CartsCleanup::default().with_notifier(CustomersNotifier {
+                users_microservice,
+                notifications_microservice,
+                cluster_url: config.cluster.url.clone(),
+            })
+        } else {
+            CartsCleanup::default()
+        };
+        Self {
+            config,
+            orders_microservice,
+            stores_microservice,
+            vacations,
+            log,
+            carts_cleanup,
+        }
+    }
+
+    /// Brings products of the store back to search once its vacation is over, no saga is started
+    pub fn end_vacation(self, store_id: StoreId) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let vacations = self.vacations.clone();
+        self.stores_microservice
+            .set_search_visibility(Some(Initiator::Superadmin), store_id, SearchVisibility { hidden: false })
+            .and_then(move |_| vacations.end(store_id))
+            .then(|res| match res {
+                Ok(()) => Ok((self, ())),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn vacation_happy(
+        self,
+        store_id: StoreId,
+        input: StoreVacationInput,
+    ) -> impl Future<Item = (Self, CartsCleanupResult<StoreVacation>), Error = (Self, FailureError)> {
+        self.log.start(SagaType::StoreVacation);
+        self.log.set_input(&input);
+
+        self.check_input(store_id, &input)
+            .and_then(move |s| s.lock_store(store_id))
+            .and_then(move |s| s.get_store(store_id))
+            .and_then(move |s| s.hide_products(store_id))
+            .and_then(move |s| s.cleanup_carts(store_id))
+            .and_then(move |(s, cleanup)| {
+                s.record_vacation(StoreVacation::new(store_id, input.ends_at))
+                    .map(move |(s, vacation)| {
+                        let result = CartsCleanupResult {
+                            result: vacation,
+                            removed_from_carts: cleanup.removed_from_carts,
+                            cleared_delivery_methods: cleanup.cleared_delivery_methods,
+                        };
+                        (s, result)
+                    })
+            })
+    }
+
+    fn check_input(self, store_id: StoreId, input: &StoreVacationInput) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        if input.ends_at <= Utc::now() {
+            let e = Error::Validate(validation_errors!({"ends_at": ["ends_at" => "Vacation must end in the future"]}));
+            return future::err((self, e.into()));
+        }
+        if self.vacations.is_on_vacation(store_id) {
+            let e = Error::Validate(validation_errors!({"store": ["vacation" => "Store is already on vacation"]}));
+            return future::err((self, e.into()));
+        }
+        future::ok(self)
+    }
+
+    fn lock_store(self, store_id: StoreId) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let lease = Duration::from_secs(self.config.saga.reaper_stale_after_s);
+        self.log.lock(LockedEntity::Store(store_id), lease).then(|res| match res {
+            Ok(()) => Ok(self),
+            Err(e) => Err((self, e)),
+        })
+    }
+
+    fn get_store(self, store_id: StoreId) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        self.stores_microservice
+            .get(store_id, Visibility::Active)
+            .and_then(move |store| match store {
+                Some(_) => Ok(()),
+                None => Err(format_err!("Store {} not found", store_id).context(Error::NotFound).into()),
+            })
+            .then(|res| match res {
+                Ok(()) => Ok(self),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    /// Products are hidden on behalf of the caller, so stores checks that the caller manages the store
+    fn hide_products(self, store_id: StoreId) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        log.push(StoreVacationOperationStage::ProductsHidingStart(store_id));
+
+        self.stores_microservice
+            .set_search_visibility(None, store_id, SearchVisibility { hidden: true })
+            .then(move |res| match res {
+                Ok(()) => {
+                    log.push(StoreVacationOperationStage::ProductsHidingComplete(store_id));
+                    Ok(self)
+                }
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn cleanup_carts(self, store_id: StoreId) -> impl Future<Item = (Self, CartsCleanupResult<()>), Error = (Self, FailureError)> {
+        self.stores_microservice
+            .get_products_by_store(store_id)
+            .then(|res| match res {
+                Ok(products) => {
+                    self.carts_cleanup.remove_products(products.into_iter().map(|p| p.id));
+                    Ok(self)
+                }
+                Err(e) => Err((self, e)),
+            })
+            .and_then(|s| {
+                s.carts_cleanup.flush(&s.orders_microservice, ()).then(|res| match res {
+                    Ok(result) => Ok((s, result)),
+                    Err(e) => Err((s, e)),
+                })
+            })
+    }
+
+    fn record_vacation(self, vacation: StoreVacation) -> impl Future<Item = (Self, StoreVacation), Error = (Self, FailureError)> {
+        let store_id = vacation.store_id;
+        self.log.push(StoreVacationOperationStage::VacationRecordStart(vacation.clone()));
+        match self.vacations.start(vacation.clone()) {
+            Ok(()) => {
+                self.log.push(StoreVacationOperationStage::VacationRecordComplete(store_id));
+                future::ok((self, vacation))
+            }
+            Err(e) => future::err((self, e)),
+        }
+    }
+
+    // Contains reversal of store vacation
+    pub fn vacation_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let stores_microservice = self.stores_microservice.clone();
+        let vacations = self.vacations.clone();
+
+        let compensation = self.log.compensate(move |e, _| match e {
+            StoreVacationOperationStage::ProductsHidingStart(store_id) => {
+                debug!("Bringing products of store back to search, store_id: {}", store_id);
+                Box::new(stores_microservice.set_search_visibility(
+                    Some(Initiator::Superadmin),
+                    store_id,
+                    SearchVisibility { hidden: false },
+                )) as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            StoreVacationOperationStage::VacationRecordStart(vacation) => {
+                debug!("Removing vacation of store, store_id: {}", vacation.store_id);
+                Box::new(future::result(vacations.end(vacation.store_id))) as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
+        });
+
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((self, format_err!("Store vacation service vacation_revert error occurred: {}", e))),
+        })
+    }
+}
+
+impl SagaService for StoreVacationServiceImpl {
+    type Stage = StoreVacationOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.vacation_revert().map(|(s, _)| s))
+    }
+}
+
+impl StoreVacationService for StoreVacationServiceImpl {
+    fn start_vacation(
+        self,
+        store_id: StoreId,
+        input: StoreVacationInput,
+    ) -> ServiceFuture<Box<StoreVacationService>, SagaResponse<CartsCleanupResult<StoreVacation>>> {
+        debug!("Put store {} on vacation, input: {:?}", store_id, input);
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::StoreVacation,
+            deadline,
+            move |s| s.vacation_happy(store_id, input),
+            &["store", "ends_at"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<StoreVacationService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<StoreVacationService>, e)),
+        )
+    }
+}
@@ -0,0 +1,469 @@
+//! Bulk edit of variants of base product. Variants are added, edited and removed one by one,
+//! a variant that fails to change is cleaned up and reported in the result instead of failing
+//! the saga. Removed variants are taken out of carts once all changes are made.
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use failure::Fail;
+use futures::future::{self, Either};
+use futures::prelude::*;
+use futures::stream::iter_ok;
+use uuid::Uuid;
+
+use stq_types::*;
+
+use super::parse_validation_errors;
+use super::store::store_warehouse_id;
+use config;
+use errors::Error;
+use microservice::*;
+use models::*;
+use saga::{self, SagaLog, SagaService, SagaStore};
+use services::carts::{CartsCleanup, CustomersNotifier};
+use services::types::ServiceFuture;
+
+pub trait VariantsService {
+    /// Adds, edits and removes variants of base product, the outcome is reported per variant
+    fn bulk_edit(
+        self,
+        base_product_id: BaseProductId,
+        input: VariantsBulkEdit,
+    ) -> ServiceFuture<Box<VariantsService>, SagaResponse<CartsCleanupResult<Vec<VariantResult>>>>;
+}
+
+#[derive(Clone)]
+pub struct VariantsServiceImpl {
+    pub orders_microservice: Arc<OrdersMicroservice>,
+    pub stores_microservice: Arc<StoresMicroservice>,
+    pub warehouses_microservice: Arc<WarehousesMicroservice>,
+    pub config: config::Config,
+    pub log: Rc<SagaLog<VariantsBulkEditOperationStage>>,
+    pub carts_cleanup: CartsCleanup,
+}
+
+impl VariantsServiceImpl {
+    pub fn new(
+        config: config::Config,
+        saga_store: Arc<SagaStore>,
+        orders_microservice: Arc<OrdersMicroservice>,
+        stores_microservice: Arc<StoresMicroservice>,
+        warehouses_microservice: Arc<WarehousesMicroservice>,
+        users_microservice: Arc<UsersMicroservice>,
+        notifications_microservice: Arc<NotificationsMicroservice>,
+    ) -> Self {
+        let log = Rc::new(SagaLog::new(saga_store));
+        let carts_cleanup = if config.features.unavailable_cart_products_notifications {
+            CartsCleanup::default().with_notifier(CustomersNotifier {
+                users_microservice,
+                notifications_microservice,
+                cluster_url: config.cluster.url.clone(),
+            })
+        } else {
+            CartsCleanup::default()
+        };
+        Self {
+            config,
+            orders_microservice,
+            stores_microservice,
+            warehouses_microservice,
+            log,
+            carts_cleanup,
+        }
+    }
+
+    fn bulk_edit_happy(
+        self,
+        base_product_id: BaseProductId,
+        input: VariantsBulkEdit,
+    ) -> impl Future<Item = (Self, CartsCleanupResult<Vec<VariantResult>>), Error = (Self, FailureError)> {
+        self.log.start(SagaType::VariantsBulkEdit);
+        self.log.set_input(&input);
+        let needs_stock = input.add.iter().any(|variant| variant.quantity.is_some());
+        let VariantsBulkEdit { add, edit, remove } = input.clone();
+
+        self.lock_base_product(base_product_id)
+            .and_then(move |s| s.get_variants(base_product_id))
+            .and_then(move |(s, store_id, existing)| s.check_input(&input, &existing).map(move |s| (s, store_id)))
+            .and_then(move |(s, store_id)| {
+                s.warehouse_id(store_id, needs_stock)
+                    .map(move |(s, warehouse_id)| (s, store_id, warehouse_id))
+            })
+            .and_then(move |(s, store_id, warehouse_id)| {
+                iter_ok::<_, (Self, FailureError)>(add.into_iter().enumerate()).fold(
+                    (s, vec![]),
+                    move |(s, mut results), (index, variant)| {
+                        s.add_variant(store_id, base_product_id, warehouse_id, index, variant)
+                            .map(move |(s, result)| {
+                                results.push(result);
+                                (s, results)
+                            })
+                    },
+                )
+            })
+            .and_then(move |(s, results)| {
+                iter_ok::<_, (Self, FailureError)>(edit.into_iter().enumerate()).fold((s, results), |(s, mut results), (index, variant)| {
+                    s.edit_variant(index, variant).map(move |(s, result)| {
+                        results.push(result);
+                        (s, results)
+                    })
+                })
+            })
+            .and_then(move |(s, results)| {
+                iter_ok::<_, (Self, FailureError)>(remove.into_iter().enumerate()).fold(
+                    (s, results),
+                    |(s, mut results), (index, product_id)| {
+                        s.remove_variant(index, product_id).map(move |(s, result)| {
+                            results.push(result);
+                            (s, results)
+                        })
+                    },
+                )
+            })
+            .and_then(|(s, results)| {
+                s.carts_cleanup.flush(&s.orders_microservice, results).then(|res| match res {
+                    Ok(result) => Ok((s, result)),
+                    Err(e) => Err((s, e)),
+                })
+            })
+    }
+
+    fn lock_base_product(self, base_product_id: BaseProductId) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let lease = Duration::from_secs(self.config.saga.reaper_stale_after_s);
+        self.log
+            .lock(LockedEntity::BaseProduct(base_product_id), lease)
+            .then(|res| match res {
+                Ok(()) => Ok(self),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    /// Store of the base product along with ids of its variants
+    fn get_variants(
+        self,
+        base_product_id: BaseProductId,
+    ) -> impl Future<Item = (Self, StoreId, Vec<ProductId>), Error = (Self, FailureError)> {
+        let stores_microservice = self.stores_microservice.clone();
+        self.stores_microservice
+            .get_base_product(base_product_id, Visibility::Active)
+            .and_then(move |base_product| match base_product {
+                Some(base_product) => Ok(base_product.store_id),
+                None => Err(format_err!("Base product {} not found", base_product_id)
+                    .context(Error::NotFound)
+                    .into()),
+            })
+            .and_then(move |store_id| {
+                stores_microservice
+                    .get_products_by_base_product(base_product_id)
+                    .map(move |products| (store_id, products.into_iter().map(|p| p.id).collect::<Vec<_>>()))
+            })
+            .then(|res| match res {
+                Ok((store_id, existing)) => Ok((self, store_id, existing)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn check_input(self, input: &VariantsBulkEdit, existing: &[ProductId]) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        if input.is_empty() {
+            let e = Error::Validate(validation_errors!({"variants": ["empty" => "No variants to add, edit or remove"]}));
+            return future::err((self, e.into()));
+        }
+        if !input.unknown_product_ids(existing).is_empty() {
+            let e = Error::Validate(validation_errors!({"variants": ["unknown" => "Variant does not belong to the base product"]}));
+            return future::err((self, e.into()));
+        }
+        if !input.repeated_product_ids().is_empty() {
+            let e = Error::Validate(validation_errors!({"variants": ["repeated" => "Variant is changed more than once"]}));
+            return future::err((self, e.into()));
+        }
+        future::ok(self)
+    }
+
+    /// Warehouse is looked up once for all added variants, variants with stock can not be added to store without warehouse
+    fn warehouse_id(
+        self,
+        store_id: StoreId,
+        needs_stock: bool,
+    ) -> impl Future<Item = (Self, Option<WarehouseId>), Error = (Self, FailureError)> {
+        let warehouse_id = if needs_stock {
+            Either::A(store_warehouse_id(self.warehouses_microservice.clone(), store_id).map(Some))
+        } else {
+            Either::B(future::ok(None))
+        };
+
+        warehouse_id.then(|res| match res {
+            Ok(warehouse_id) => Ok((self, warehouse_id)),
+            Err(e) => Err((self, e)),
+        })
+    }
+
+    fn add_variant(
+        self,
+        store_id: StoreId,
+        base_product_id: BaseProductId,
+        warehouse_id: Option<WarehouseId>,
+        index: usize,
+        mut variant: CreateProductWithAttributes,
+    ) -> impl Future<Item = (Self, VariantResult), Error = (Self, FailureError)> {
+        variant.product.uuid = Some(
+            variant
+                .product
+                .uuid
+                .take()
+                .filter(|uuid| !uuid.is_empty())
+                .unwrap_or_else(|| Uuid::new_v4().to_string()),
+        );
+        variant.product.base_product_id = Some(base_product_id.0);
+
+        self.create_variant(store_id, warehouse_id, variant).then(move |res| match res {
+            Ok((s, product_id)) => Either::A(future::ok((s, done(VariantAction::Add, index, product_id)))),
+            Err((s, product_id, e)) => {
+                warn!("Variant {} of saga {} was not added: {}", index, s.log.saga_id(), e);
+                Either::B(
+                    s.cleanup_variant(product_id)
+                        .map(move |s| (s, failed(VariantAction::Add, index, None, e))),
+                )
+            }
+        })
+    }
+
+    /// Creates variant along with its stock. Error carries id of variant if it was created before the failure.
+    fn create_variant(
+        self,
+        store_id: StoreId,
+        warehouse_id: Option<WarehouseId>,
+        variant: CreateProductWithAttributes,
+    ) -> impl Future<Item = (Self, ProductId), Error = (Self, Option<ProductId>, FailureError)> {
+        let log = self.log.clone();
+        let warehouses_microservice = self.warehouses_microservice.clone();
+        let uuid = variant.product.uuid.clone().unwrap_or_default();
+        let quantity = variant.quantity;
+        log.push(VariantsBulkEditOperationStage::VariantCreationStart(uuid.clone()));
+
+        self.stores_microservice
+            .create_product(None, variant)
+            .map_err(|e| (None, parse_validation_errors(e, &["product"])))
+            .and_then(move |product| {
+                let product_id = product.id;
+                log.push_with_result(VariantsBulkEditOperationStage::VariantCreationComplete(uuid, product_id), &product);
+                match (warehouse_id, quantity) {
+                    (Some(warehouse_id), Some(quantity)) => {
+                        log.push(VariantsBulkEditOperationStage::StockSetStart(store_id, product_id));
+                        Either::A(
+                            warehouses_microservice
+                                .set_product_in_warehouse(Initiator::Superadmin, warehouse_id, product_id, quantity)
+                                .map(move |_| {
+                                    log.push(VariantsBulkEditOperationStage::StockSetComplete(product_id));
+                                    product_id
+                                })
+                                .map_err(move |e| (Some(product_id), e)),
+                        )
+                    }
+                    _ => Either::B(future::ok(product_id)),
+                }
+            })
+            .then(|res| match res {
+                Ok(product_id) => Ok((self, product_id)),
+                Err((product_id, e)) => Err((self, product_id, e)),
+            })
+    }
+
+    /// Deactivates variant which failed to be added, so that the rest of changes are made without it.
+    /// Cleaned up variant is skipped if the whole saga gets reverted later.
+    fn cleanup_variant(self, product_id: Option<ProductId>) -> impl Future<Item = Self, Error = (Self, FailureError)> {
+        let product_id = match product_id {
+            Some(product_id) => product_id,
+            None => return Either::A(future::ok(self)),
+        };
+
+        Either::B(
+            self.stores_microservice
+                .deactivate_product(Some(Initiator::Superadmin), product_id, Deactivation::default())
+                .then(move |res| {
+                    match res {
+                        Ok(_) => self.log.push(VariantsBulkEditOperationStage::VariantCleanupComplete(product_id)),
+                        // leftovers of the variant are cleaned up along with the rest of changes if the saga is reverted
+                        Err(e) => error!("Cleanup of variant {} of saga {} failed: {}", product_id, self.log.saga_id(), e),
+                    }
+                    Ok::<_, (Self, FailureError)>(self)
+                }),
+        )
+    }
+
+    /// Variant is edited on behalf of the caller, so stores checks that the caller manages the store
+    fn edit_variant(self, index: usize, variant: VariantEdit) -> impl Future<Item = (Self, VariantResult), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        let product_id = variant.product_id;
+        let payload = UpdateProductWithSaga {
+            product: variant.product,
+            saga_id: log.saga_id(),
+        };
+        log.push(VariantsBulkEditOperationStage::VariantUpdateStart(product_id));
+
+        self.stores_microservice
+            .update_product(None, product_id, payload)
+            .then(move |res| match res {
+                Ok(product) => {
+                    log.push_with_result(VariantsBulkEditOperationStage::VariantUpdateComplete(product_id), &product);
+                    Ok((self, done(VariantAction::Edit, index, product_id)))
+                }
+                Err(e) => {
+                    warn!("Variant {} of saga {} was not edited: {}", product_id, log.saga_id(), e);
+                    Ok((self, failed(VariantAction::Edit, index, Some(product_id), e)))
+                }
+            })
+    }
+
+    fn remove_variant(
+        self,
+        index: usize,
+        product_id: ProductId,
+    ) -> impl Future<Item = (Self, VariantResult), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        log.push(VariantsBulkEditOperationStage::VariantDeactivationStart(product_id));
+
+        self.stores_microservice
+            .deactivate_product(None, product_id, Deactivation::default())
+            .then(move |res| match res {
+                Ok(_) => {
+                    log.push(VariantsBulkEditOperationStage::VariantDeactivationComplete(product_id));
+                    self.carts_cleanup.remove_products(vec![product_id]);
+                    Ok((self, done(VariantAction::Remove, index, product_id)))
+                }
+                Err(e) => {
+                    warn!("Variant {} of saga {} was not removed: {}", product_id, log.saga_id(), e);
+                    Ok((self, failed(VariantAction::Remove, index, Some(product_id), e)))
+                }
+            })
+    }
+
+    // Contains reversal of variant changes: added variants are deactivated, edited ones are restored
+    // and removed ones are activated back. Variants already cleaned up after their failure are skipped.
+    pub fn bulk_edit_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let stores_microservice = self.stores_microservice.clone();
+        let warehouses_microservice = self.warehouses_microservice.clone();
+        let saga_id = self.log.saga_id();
+
+        let stages = self.log.stages();
+        let created = stages
+            .iter()
+            .filter_map(|stage| match stage {
+                VariantsBulkEditOperationStage::VariantCreationComplete(uuid, product_id) => Some((uuid.clone(), *product_id)),
+                _ => None,
+            })
+            .collect::<HashMap<_, _>>();
+        let cleaned_up = stages
+            .iter()
+            .filter_map(|stage| match stage {
+                VariantsBulkEditOperationStage::VariantCleanupComplete(product_id) => Some(*product_id),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        let compensation = self.log.compensate(move |stage, _| match stage {
+            VariantsBulkEditOperationStage::VariantCreationStart(uuid) => match created.get(&uuid) {
+                Some(product_id) if !cleaned_up.contains(product_id) => {
+                    debug!("Deactivating added variant, product_id: {}", product_id);
+                    Box::new(
+                        stores_microservice
+                            .deactivate_product(Some(Initiator::Superadmin), *product_id, Deactivation::default())
+                            .map(|_| ()),
+                    ) as Box<Future<Item = (), Error = FailureError>>
+                }
+                _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
+            },
+
+            VariantsBulkEditOperationStage::StockSetStart(store_id, product_id) if !cleaned_up.contains(&product_id) => {
+                debug!("Resetting stock of added variant, product_id: {}", product_id);
+                let warehouses_microservice = warehouses_microservice.clone();
+                Box::new(
+                    store_warehouse_id(warehouses_microservice.clone(), store_id).and_then(move |warehouse_id| {
+                        warehouses_microservice
+                            .set_product_in_warehouse(Initiator::Superadmin, warehouse_id, product_id, Quantity(0))
+                            .map(|_| ())
+                    }),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            VariantsBulkEditOperationStage::VariantUpdateStart(product_id) => {
+                debug!("Restoring edited variant, product_id: {}, saga_id: {}", product_id, saga_id);
+                Box::new(stores_microservice.revert_product_update(Some(Initiator::Superadmin), product_id, saga_id))
+                    as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            VariantsBulkEditOperationStage::VariantDeactivationStart(product_id) => {
+                debug!("Activating removed variant, product_id: {}", product_id);
+                Box::new(
+                    stores_microservice
+                        .activate_product(Some(Initiator::Superadmin), product_id)
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
+        });
+
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((self, format_err!("Variants service bulk_edit_revert error occurred: {}", e))),
+        })
+    }
+}
+
+fn done(action: VariantAction, index: usize, product_id: ProductId) -> VariantResult {
+    VariantResult {
+        action,
+        index,
+        product_id: Some(product_id),
+        status: VariantStatus::Done,
+        error: None,
+    }
+}
+
+fn failed(action: VariantAction, index: usize, product_id: Option<ProductId>, e: FailureError) -> VariantResult {
+    VariantResult {
+        action,
+        index,
+        product_id,
+        status: VariantStatus::Failed,
+        error: Some(e.to_string()),
+    }
+}
+
+impl SagaService for VariantsServiceImpl {
+    type Stage = VariantsBulkEditOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.bulk_edit_revert().map(|(s, _)| s))
+    }
+}
+
+impl VariantsService for VariantsServiceImpl {
+    fn bulk_edit(
+        self,
+        base_product_id: BaseProductId,
+        input: VariantsBulkEdit,
+    ) -> ServiceFuture<Box<VariantsService>, SagaResponse<CartsCleanupResult<Vec<VariantResult>>>> {
+        debug!("Bulk edit variants of base product {}, input: {:?}", base_product_id, input);
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::VariantsBulkEdit,
+            deadline,
+            move |s| s.bulk_edit_happy(base_product_id, input),
+            &["variants"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<VariantsService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<VariantsService>, e)),
+        )
+    }
+}
@@ -0,0 +1,196 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::Error as FailureError;
+use futures::future;
+use futures::prelude::*;
+
+use stq_types::*;
+
+use config;
+use errors::Error;
+use microservice::*;
+use models::*;
+use saga::{self, soft_step, SagaLog, SagaService, SagaStore};
+use services::types::ServiceFuture;
+
+pub trait StoreVerificationService {
+    /// Upgrades store to verified tier once its merchant has passed KYC in billing
+    fn verify_store(self, store_id: StoreId) -> ServiceFuture<Box<StoreVerificationService>, SagaResponse<Store>>;
+}
+
+#[derive(Clone)]
+pub struct StoreVerificationServiceImpl {
+    pub stores_microservice: Arc<StoresMicroservice>,
+    pub billing_microservice: Arc<BillingMicroservice>,
+    pub users_microservice: Arc<UsersMicroservice>,
+    pub notifications_microservice: Arc<NotificationsMicroservice>,
+    pub config: config::Config,
+    pub log: Rc<SagaLog<VerifyStoreOperationStage>>,
+}
+
+impl StoreVerificationServiceImpl {
+    pub fn new(
+        config: config::Config,
+        saga_store: Arc<SagaStore>,
+        stores_microservice: Arc<StoresMicroservice>,
+        billing_microservice: Arc<BillingMicroservice>,
+        users_microservice: Arc<UsersMicroservice>,
+        notifications_microservice: Arc<NotificationsMicroservice>,
+    ) -> Self {
+        let log = Rc::new(SagaLog::new(saga_store));
+        Self {
+            config,
+            stores_microservice,
+            billing_microservice,
+            users_microservice,
+            notifications_microservice,
+            log,
+        }
+    }
+
+    fn verify_store_happy(self, store_id: StoreId) -> impl Future<Item = (Self, Store), Error = (Self, FailureError)> {
+        self.log.start(SagaType::VerifyStore);
+
+        self.check_kyc(store_id)
+            .and_then(move |(s, _)| s.set_verified(store_id))
+            .and_then(|(s, store)| {
+                let store_manager_id = store.user_id;
+                soft_step(
+                    s.log.clone(),
+                    "store_verification_notification",
+                    s.notify_manager(store_id, store_manager_id),
+                )
+                .map(move |s| (s, store))
+            })
+    }
+
+    fn check_kyc(self, store_id: StoreId) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let billing_microservice = self.billing_microservice.clone();
+
+        let fut = self
+            .stores_microservice
+            .get(store_id, Visibility::Active)
+            .and_then(move |store| match store {
+                Some(_) => Ok(()),
+                None => Err(format_err!("Store {} not found", store_id).context(Error::NotFound).into()),
+            })
+            .and_then(move |_| billing_microservice.get_store_kyc_status(Initiator::Superadmin, store_id))
+            .and_then(move |kyc| match kyc.status {
+                KycStatus::Verified => Ok(()),
+                status => {
+                    debug!("Store {} can not be verified, KYC status: {:?}", store_id, status);
+                    Err(Error::Validate(validation_errors!({"kyc": ["kyc" => "Store merchant has not passed KYC"]})).into())
+                }
+            });
+
+        fut.then(|res| match res {
+            Ok(_) => Ok((self, ())),
+            Err(e) => Err((self, e)),
+        })
+    }
+
+    fn set_verified(self, store_id: StoreId) -> impl Future<Item = (Self, Store), Error = (Self, FailureError)> {
+        let log = self.log.clone();
+        log.push(VerifyStoreOperationStage::StoreVerificationStart(store_id));
+
+        self.stores_microservice
+            .set_store_verification(None, store_id, StoreVerification { verified: true })
+            .and_then(move |store| {
+                log.push_with_result(VerifyStoreOperationStage::StoreVerificationComplete(store_id), &store);
+                Ok(store)
+            })
+            .then(|res| match res {
+                Ok(store) => Ok((self, store)),
+                Err(e) => Err((self, e)),
+            })
+    }
+
+    fn notify_manager(self, store_id: StoreId, store_manager_id: UserId) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let cluster_url = self.config.cluster.url.clone();
+        let notifications_microservice = self.notifications_microservice.clone();
+
+        let fut = self
+            .users_microservice
+            .get(Some(Initiator::Superadmin), store_manager_id)
+            .and_then(move |store_manager| match store_manager {
+                Some(user) => {
+                    let email = StoreVerifiedForUser {
+                        store_email: user.email.to_string(),
+                        store_id: store_id.to_string(),
+                        cluster_url,
+                    };
+                    future::Either::A(notifications_microservice.store_verified_for_user(Initiator::Superadmin, email))
+                }
+                None => future::Either::B(future::err(format_err!(
+                    "Manager {} of store {} not found",
+                    store_manager_id,
+                    store_id
+                ))),
+            });
+
+        fut.then(|res| match res {
+            Ok(_) => Ok((self, ())),
+            Err(e) => Err((self, e)),
+        })
+    }
+
+    // Contains reversal of store verification
+    pub fn verify_store_revert(self) -> impl Future<Item = (Self, ()), Error = (Self, FailureError)> {
+        let stores_microservice = self.stores_microservice.clone();
+
+        let compensation = self.log.compensate(move |e, _| match e {
+            VerifyStoreOperationStage::StoreVerificationStart(store_id) => {
+                debug!("Reverting store verification, store_id: {}", store_id);
+                Box::new(
+                    stores_microservice
+                        .set_store_verification(Some(Initiator::Superadmin), store_id, StoreVerification { verified: false })
+                        .map(|_| ()),
+                ) as Box<Future<Item = (), Error = FailureError>>
+            }
+
+            _ => Box::new(future::ok(())) as Box<Future<Item = (), Error = FailureError>>,
+        });
+
+        compensation.then(|res| match res {
+            Ok(()) => Ok((self, ())),
+            Err(e) => Err((
+                self,
+                format_err!("Store verification service verify_store_revert error occurred: {}", e),
+            )),
+        })
+    }
+}
+
+impl SagaService for StoreVerificationServiceImpl {
+    type Stage = VerifyStoreOperationStage;
+
+    fn log(&self) -> &Rc<SagaLog<Self::Stage>> {
+        &self.log
+    }
+
+    fn revert(self) -> Box<Future<Item = Self, Error = (Self, FailureError)>> {
+        Box::new(self.verify_store_revert().map(|(s, _)| s))
+    }
+}
+
+impl StoreVerificationService for StoreVerificationServiceImpl {
+    fn verify_store(self, store_id: StoreId) -> ServiceFuture<Box<StoreVerificationService>, SagaResponse<Store>> {
+        debug!("Verify store: {}", store_id);
+        let deadline = Duration::from_millis(self.config.saga.deadline_ms);
+
+        let res = saga::run(
+            self,
+            SagaType::VerifyStore,
+            deadline,
+            move |s| s.verify_store_happy(store_id),
+            &["store"],
+        );
+
+        Box::new(
+            res.map(|(s, response)| (Box::new(s) as Box<StoreVerificationService>, response))
+                .map_err(|(s, e)| (Box::new(s) as Box<StoreVerificationService>, e)),
+        )
+    }
+}
@@ -0,0 +1,61 @@
+//! Tracking events of couriers handled recently, so that redelivered webhooks do not
+//! change order state twice. Events are kept in memory of the replica that received them,
+//! the oldest events are dropped once capacity is reached.
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+pub trait TrackingEventLog {
+    /// Marks the event as being handled, returns false if it is already handled or in progress
+    fn begin(&self, event_id: &str) -> bool;
+    /// Forgets the event that failed, so that courier can deliver it again
+    fn forget(&self, event_id: &str);
+}
+
+pub struct TrackingEventLogImpl {
+    events: Mutex<VecDeque<String>>,
+    capacity: usize,
+}
+
+impl TrackingEventLogImpl {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: Mutex::new(VecDeque::new()),
+            capacity,
+        }
+    }
+}
+
+impl TrackingEventLog for TrackingEventLogImpl {
+    fn begin(&self, event_id: &str) -> bool {
+        let mut events = self.events.lock().unwrap();
+        if events.iter().any(|event| event == event_id) {
+            return false;
+        }
+        events.push_back(event_id.to_string());
+        while events.len() > self.capacity {
+            events.pop_front();
+        }
+        true
+    }
+
+    fn forget(&self, event_id: &str) {
+        self.events.lock().unwrap().retain(|event| event != event_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_handled_events() {
+        let events = TrackingEventLogImpl::new(2);
+        assert!(events.begin("a"));
+        assert!(!events.begin("a"));
+        events.forget("a");
+        assert!(events.begin("a"));
+        assert!(events.begin("b"));
+        assert!(events.begin("c"));
+        assert!(events.begin("a"));
+    }
+}
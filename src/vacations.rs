@@ -0,0 +1,116 @@
+//! Stores on vacation. Products of a store on vacation are hidden from search and
+//! background jobs concerning the store, e.g. low stock digest and acknowledgment
+//! of paid orders, leave it alone until `jobs::vacation` brings it back once the
+//! vacation is over. Vacations are kept in memory, optionally mirrored into json file.
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Utc};
+use failure::Error as FailureError;
+
+use stq_types::StoreId;
+
+use json_file::JsonFile;
+use models::StoreVacation;
+
+pub trait StoreVacations {
+    /// Records that store went on vacation, an earlier vacation of the store is replaced
+    fn start(&self, vacation: StoreVacation) -> Result<(), FailureError>;
+    /// Removes vacation once the store is back
+    fn end(&self, store_id: StoreId) -> Result<(), FailureError>;
+    fn is_on_vacation(&self, store_id: StoreId) -> bool;
+    /// Vacations over by `now`, the earliest ended first
+    fn over(&self, now: DateTime<Utc>) -> Result<Vec<StoreVacation>, FailureError>;
+}
+
+/// Keeps vacations in memory, optionally mirroring them into json file
+pub struct StoreVacationsImpl {
+    vacations: Mutex<Vec<StoreVacation>>,
+    file: Option<JsonFile>,
+}
+
+impl StoreVacationsImpl {
+    pub fn new(path: Option<PathBuf>) -> Result<Self, FailureError> {
+        let file = path.map(|path| JsonFile::new(path, "vacations"));
+        let vacations = match file {
+            Some(ref file) => file.read::<Vec<StoreVacation>>()?,
+            None => None,
+        };
+
+        Ok(Self {
+            vacations: Mutex::new(vacations.unwrap_or_default()),
+            file,
+        })
+    }
+
+    fn flush(&self, vacations: &[StoreVacation]) -> Result<(), FailureError> {
+        if let Some(ref file) = self.file {
+            file.write(&vacations)?;
+        }
+        Ok(())
+    }
+}
+
+impl StoreVacations for StoreVacationsImpl {
+    fn start(&self, vacation: StoreVacation) -> Result<(), FailureError> {
+        let mut vacations = self.vacations.lock().unwrap();
+        vacations.retain(|existing| existing.store_id != vacation.store_id);
+        vacations.push(vacation);
+        self.flush(&vacations)
+    }
+
+    fn end(&self, store_id: StoreId) -> Result<(), FailureError> {
+        let mut vacations = self.vacations.lock().unwrap();
+        let len = vacations.len();
+        vacations.retain(|vacation| vacation.store_id != store_id);
+        if vacations.len() == len {
+            return Ok(());
+        }
+        self.flush(&vacations)
+    }
+
+    fn is_on_vacation(&self, store_id: StoreId) -> bool {
+        self.vacations.lock().unwrap().iter().any(|vacation| vacation.store_id == store_id)
+    }
+
+    fn over(&self, now: DateTime<Utc>) -> Result<Vec<StoreVacation>, FailureError> {
+        let mut over = self
+            .vacations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|vacation| vacation.is_over(now))
+            .cloned()
+            .collect::<Vec<_>>();
+        over.sort_by_key(|vacation| vacation.ends_at);
+        Ok(over)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+
+    use stq_types::StoreId;
+
+    use super::{StoreVacations, StoreVacationsImpl};
+    use models::StoreVacation;
+
+    #[test]
+    fn lists_vacations_over() {
+        let vacations = StoreVacationsImpl::new(None).unwrap();
+        let now = Utc::now();
+        vacations.start(StoreVacation::new(StoreId(1), now + Duration::days(7))).unwrap();
+        vacations.start(StoreVacation::new(StoreId(2), now + Duration::days(1))).unwrap();
+        assert!(vacations.is_on_vacation(StoreId(1)));
+        assert!(vacations.over(now).unwrap().is_empty());
+
+        let over = vacations.over(now + Duration::days(2)).unwrap();
+        assert_eq!(over.len(), 1);
+        assert_eq!(over[0].store_id, StoreId(2));
+
+        vacations.end(StoreId(2)).unwrap();
+        assert!(!vacations.is_on_vacation(StoreId(2)));
+        assert_eq!(vacations.over(now + Duration::days(8)).unwrap().len(), 1);
+    }
+}